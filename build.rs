@@ -0,0 +1,64 @@
+//! Embeds build provenance (git commit, build date, compiler, target and
+//! enabled features) so `bitcache --version --verbose` can report it.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let commit = command_output("git", &["rev-parse", "--short=12", "HEAD"])
+        .map(|commit| {
+            let dirty = command_output("git", &["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{}-dirty", commit)
+            } else {
+                commit
+            }
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let build_date = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now)
+        .format("%Y-%m-%d")
+        .to_string();
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_string))
+        .map(|feature| feature.to_lowercase().replace('_', "-"))
+        .collect();
+    features.sort();
+    let features = if features.is_empty() {
+        "none".to_string()
+    } else {
+        features.join(", ")
+    };
+
+    println!("cargo:rustc-env=BITCACHE_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=BITCACHE_BUILD_DATE={}", build_date);
+    println!("cargo:rustc-env=BITCACHE_RUSTC_VERSION={}", rustc_version);
+    println!(
+        "cargo:rustc-env=BITCACHE_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+    println!("cargo:rustc-env=BITCACHE_FEATURES={}", features);
+}
+
+/// Run a command and return its trimmed stdout, or `None` if it failed
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
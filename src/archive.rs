@@ -0,0 +1,235 @@
+//! Carrying entries and their bitstreams in a portable archive.
+//!
+//! [`export_archive`] writes entries of a repository, with their bitstreams,
+//! to a gzip-compressed tarball, and [`import_archive`] copies them into
+//! another repository. This moves a cache onto a network the first
+//! repository can't be reached from.
+//!
+//! An archive is laid out as a [`crate::bundle`], with the [`ARCHIVE_FORMAT`]
+//! it was written in, and written and read by the same helpers. Where a
+//! bundle is applied as it is, an archive is merged as [`crate::sync`] merges
+//! entries.
+
+use crate::bundle::{self, Packing, Staging, TREE_DIR};
+use crate::checkout::{self, ClonePool};
+use crate::error;
+use crate::layout::MetadataFile;
+use crate::progress::{status, warning};
+use crate::storage;
+use crate::sync::{self, Origin, SyncOptions, Synced};
+use crate::{compute_md5, Context, MetadataEntry, Remote};
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+
+/// Version of the archive layout [`export_archive`] writes; archives of a
+/// later version are refused by [`import_archive`]
+pub const ARCHIVE_FORMAT: u32 = 1;
+
+/// How [`export_archive`] packs an archive
+const ARCHIVE: Packing = Packing {
+    kind: "archive",
+    compression: "--gzip",
+    format: ARCHIVE_FORMAT,
+};
+
+/// Which entries [`export_archive`] writes and where
+#[derive(Debug, Clone)]
+pub struct ExportArchiveOptions {
+    /// File to write the archive to, replaced if it exists
+    pub output: PathBuf,
+    /// Only export the entries for these source hashes, every variant of
+    /// each; every entry when empty
+    pub md5s: Vec<String>,
+}
+
+impl ExportArchiveOptions {
+    /// Export every entry to `output`
+    pub fn new(output: impl Into<PathBuf>) -> Self {
+        Self {
+            output: output.into(),
+            md5s: Vec::new(),
+        }
+    }
+}
+
+/// The outcome of an [`export_archive`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Archived {
+    /// The entries written, sorted by MD5 and name
+    pub entries: Vec<MetadataEntry>,
+    /// Hashes of [`ExportArchiveOptions::md5s`] the repository has no entry
+    /// for
+    pub not_found: Vec<String>,
+    /// Bytes of the bitstreams written, before compression
+    pub bytes: u64,
+}
+
+/// Which archive [`import_archive`] reads and what it does on conflicts
+#[derive(Debug, Clone)]
+pub struct ImportArchiveOptions {
+    /// Archive written by [`export_archive`]
+    pub input: PathBuf,
+    /// Replace a destination entry whose bitstream path or checksum differs
+    /// from the archive's instead of skipping it
+    pub overwrite: bool,
+}
+
+impl ImportArchiveOptions {
+    /// Import every entry of `input`, skipping conflicts
+    pub fn new(input: impl Into<PathBuf>) -> Self {
+        Self {
+            input: input.into(),
+            overwrite: false,
+        }
+    }
+}
+
+/// Write entries of a repository and their bitstreams to a `.tar.gz` archive
+///
+/// Only the bitstreams of the exported entries are fetched. A bitstream that
+/// is missing or doesn't match its recorded MD5 fails the export, so an
+/// archive never carries a damaged one. The archive is written next to
+/// `opts.output` and renamed over it once complete.
+///
+/// ```no_run
+/// use bitcache::{Context, ExportArchiveOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = ExportArchiveOptions::new("cache.tar.gz");
+/// let archived = bitcache::export_archive(&remote, &opts, &Context::default())?;
+/// println!("{} entries exported", archived.entries.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn export_archive(
+    remote: &Remote,
+    opts: &ExportArchiveOptions,
+    ctx: &Context,
+) -> io::Result<Archived> {
+    export_archive_in(None, remote, opts, ctx)
+}
+
+/// [`export_archive`], in the clone kept by `pool` if given
+pub(crate) fn export_archive_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &ExportArchiveOptions,
+    ctx: &Context,
+) -> io::Result<Archived> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    status!(
+        "Exporting {} to {}",
+        error::redact(&remote.url),
+        opts.output.display()
+    );
+    let checkout = checkout::checkout_sparse(pool, remote, ctx)?;
+    let repo_dir = checkout.dir();
+    let metadata = MetadataFile::of(repo_dir, remote).load_sparse(&checkout, remote)?;
+    let mut archived = Archived::default();
+    for md5 in &opts.md5s {
+        if metadata.variants(md5).is_empty() && !archived.not_found.contains(md5) {
+            warning!("the repository has no entry for MD5 {}", md5);
+            archived.not_found.push(md5.clone());
+        }
+    }
+    let mut entries: Vec<_> = metadata
+        .iter()
+        .filter(|entry| opts.md5s.is_empty() || opts.md5s.contains(&entry.md5))
+        .cloned()
+        .collect();
+    entries.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+
+    let mut staging = Staging::new(ctx)?;
+    for entry in &entries {
+        entry.check_path()?;
+        if !staging.has_bitstream(&entry.binary_path) {
+            checkout.include(&entry.binary_path, remote)?;
+            let stored = bundle::stored_bitstream(repo_dir, entry)?;
+            let digest = compute_md5(&stored)?;
+            if entry
+                .binary_md5
+                .as_ref()
+                .is_some_and(|recorded| *recorded != digest)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The bitstream of MD5 {} at {} does not match its recorded MD5; run verify before exporting it",
+                        entry.label(),
+                        entry.binary_path
+                    ),
+                ));
+            }
+            staging.add_bitstream(&entry.binary_path, &stored, digest)?;
+        }
+        staging.add_entry(entry.clone());
+    }
+    archived.bytes = staging.pack(&ARCHIVE, &opts.output)?;
+    archived.entries = entries;
+    Ok(archived)
+}
+
+/// Copy the entries of an archive, with their bitstreams, into a repository
+///
+/// Every bitstream is checked against the MD5 the archive records for it
+/// before anything is committed, and an archive with a damaged or missing
+/// one is refused as a whole. The entries are then merged as
+/// [`crate::sync`] merges them: one the repository has with the same
+/// bitstream at the same path is left alone, and one it has differently is
+/// skipped unless `opts.overwrite`. Everything goes into one commit.
+///
+/// ```no_run
+/// use bitcache::{Context, ImportArchiveOptions, Remote};
+///
+/// let remote = Remote::new("git@offline.example.com:fpga/bitstreams.git");
+/// let opts = ImportArchiveOptions::new("cache.tar.gz");
+/// let imported = bitcache::import_archive(&remote, &opts, &Context::default())?;
+/// println!("{} added, {} skipped", imported.added.len(), imported.skipped.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn import_archive(
+    remote: &Remote,
+    opts: &ImportArchiveOptions,
+    ctx: &Context,
+) -> io::Result<Synced> {
+    import_archive_in(None, remote, opts, ctx)
+}
+
+/// [`import_archive`], in the clone kept by `pool` if given
+pub(crate) fn import_archive_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &ImportArchiveOptions,
+    ctx: &Context,
+) -> io::Result<Synced> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let input = &opts.input;
+    status!("Unpacking {}...", input.display());
+    let scratch = ctx.temp_dir()?;
+    let (manifest, metadata) = bundle::read_head(&ARCHIVE, input, scratch.path())?;
+    bundle::unpack_bitstreams(&ARCHIVE, input, scratch.path(), &manifest, &metadata)?;
+
+    let name = input
+        .file_name()
+        .unwrap_or(input.as_os_str())
+        .to_string_lossy();
+    let origin = Origin {
+        name: &name,
+        doing: "Importing",
+        verb: "Import",
+    };
+    let sync_opts = SyncOptions {
+        overwrite: opts.overwrite,
+        ..SyncOptions::new()
+    };
+    sync::copy_entries(
+        pool,
+        &Remote::local(scratch.path().join(TREE_DIR)),
+        remote,
+        &sync_opts,
+        ctx,
+        &origin,
+    )
+}
@@ -0,0 +1,54 @@
+//! The operations a bitstream store offers, as a trait.
+//!
+//! Code that publishes and retrieves bitstreams can take a `&dyn Backend`
+//! instead of a [`Bitcache`] client, so its tests can swap in the in-memory
+//! backend from the `test-util` feature and run without git.
+
+use crate::{
+    Bitcache, DeleteOptions, Deleted, GetOptions, MetadataEntry, PublishOptions, Published,
+    Retrieved,
+};
+use std::io;
+
+/// A store of bitstreams keyed by source MD5
+///
+/// [`Bitcache`] is the real implementation; the methods behave as the ones
+/// of the same name on it.
+pub trait Backend: Send + Sync {
+    /// Publish a bitstream
+    fn publish(&self, opts: &PublishOptions) -> io::Result<Published>;
+
+    /// Retrieve the bitstream published for a source MD5, `None` on a miss
+    fn get(&self, opts: &GetOptions) -> io::Result<Option<Retrieved>>;
+
+    /// Whether there is an entry for a source MD5
+    fn exists(&self, md5: &str) -> io::Result<bool>;
+
+    /// Every entry, oldest first
+    fn list(&self) -> io::Result<Vec<MetadataEntry>>;
+
+    /// Remove the entry for a source MD5, `None` if there is none
+    fn delete(&self, opts: &DeleteOptions) -> io::Result<Option<Deleted>>;
+}
+
+impl Backend for Bitcache {
+    fn publish(&self, opts: &PublishOptions) -> io::Result<Published> {
+        Bitcache::publish(self, opts)
+    }
+
+    fn get(&self, opts: &GetOptions) -> io::Result<Option<Retrieved>> {
+        Bitcache::get(self, opts)
+    }
+
+    fn exists(&self, md5: &str) -> io::Result<bool> {
+        Bitcache::exists(self, md5)
+    }
+
+    fn list(&self) -> io::Result<Vec<MetadataEntry>> {
+        Bitcache::list(self)
+    }
+
+    fn delete(&self, opts: &DeleteOptions) -> io::Result<Option<Deleted>> {
+        Bitcache::delete(self, opts)
+    }
+}
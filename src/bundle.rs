@@ -0,0 +1,678 @@
+//! Carrying entries and their bitstreams in a single file.
+//!
+//! [`create_bundle`] writes entries of a repository, with their bitstreams,
+//! to a zstd-compressed tarball, and [`apply_bundle`] publishes them to
+//! another repository in one commit. This moves part of a cache onto a
+//! network the first repository can't be reached from. The tarball is
+//! written and read by the `tar` and `zstd` programs, as git is run for the
+//! repository.
+//!
+//! A bundle holds `manifest.json`, with the [`BUNDLE_FORMAT`] it was written
+//! in and the MD5 of every bitstream, and under `repo/` a directory laid out
+//! as a repository: the trimmed metadata file and the bitstreams at their
+//! paths. The archives of [`crate::archive`] are laid out the same way, and
+//! written and read by the helpers here.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::BitcacheError;
+use crate::fsutil::ScratchDir;
+use crate::layout::MetadataFile;
+use crate::progress::status;
+use crate::publish::TOOL_VERSION;
+use crate::storage;
+use crate::trash;
+use crate::{
+    cancel, compute_md5, fsutil, paths, Context, Metadata, MetadataEntry, Remote, METADATA_FILE,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Version of the bundle layout [`create_bundle`] writes; bundles of a later
+/// version are refused by [`apply_bundle`] and [`read_bundle`]
+pub const BUNDLE_FORMAT: u32 = 1;
+
+/// Name of the manifest at the top of a bundle
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Directory of a bundle holding the metadata and the bitstreams
+pub(crate) const TREE_DIR: &str = "repo";
+
+/// How a kind of tarball is compressed and named
+pub(crate) struct Packing {
+    /// What it is called in messages, e.g. `bundle`
+    pub(crate) kind: &'static str,
+    /// Option telling tar how to compress and decompress it
+    pub(crate) compression: &'static str,
+    /// Latest layout version written and read
+    pub(crate) format: u32,
+}
+
+/// How [`create_bundle`] packs a bundle
+const BUNDLE: Packing = Packing {
+    kind: "bundle",
+    compression: "--zstd",
+    format: BUNDLE_FORMAT,
+};
+
+/// What `manifest.json` records
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// Layout version of the tarball, [`Packing::format`] when written
+    pub(crate) format: u32,
+    /// When the tarball was written, RFC 3339
+    #[serde(default)]
+    pub(crate) created: String,
+    /// Version of bitcache that wrote it
+    #[serde(default)]
+    pub(crate) bitcache_version: String,
+    /// MD5 of each bitstream, by its path in the repository
+    #[serde(default)]
+    pub(crate) checksums: BTreeMap<String, String>,
+}
+
+/// Which entries [`create_bundle`] writes and where
+#[derive(Debug, Clone)]
+pub struct BundleOptions {
+    /// File to write the bundle to, replaced if it exists
+    pub output: PathBuf,
+    /// Only bundle the entries for these source hashes; every entry when
+    /// empty
+    pub md5s: Vec<String>,
+    /// Only bundle the entries whose bitstream is in this directory of the
+    /// repository
+    pub path_prefix: Option<String>,
+}
+
+impl BundleOptions {
+    /// Bundle every entry into `output`
+    pub fn new(output: impl Into<PathBuf>) -> Self {
+        Self {
+            output: output.into(),
+            md5s: Vec::new(),
+            path_prefix: None,
+        }
+    }
+}
+
+/// The outcome of a [`create_bundle`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Bundled {
+    /// The entries written, sorted by MD5
+    pub entries: Vec<MetadataEntry>,
+    /// Bytes of the bitstreams written, before compression
+    pub bytes: u64,
+}
+
+/// Which bundle [`apply_bundle`] reads and what it does on conflicts
+#[derive(Debug, Clone)]
+pub struct ApplyBundleOptions {
+    /// Bundle written by [`create_bundle`]
+    pub input: PathBuf,
+    /// Replace a destination entry whose bitstream path or checksum differs
+    /// from the bundle's instead of skipping it
+    pub overwrite: bool,
+}
+
+impl ApplyBundleOptions {
+    /// Apply every entry of `input`, skipping conflicts
+    pub fn new(input: impl Into<PathBuf>) -> Self {
+        Self {
+            input: input.into(),
+            overwrite: false,
+        }
+    }
+}
+
+/// An entry of a bundle [`apply_bundle`] left out
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedEntry {
+    /// The entry as the bundle has it
+    #[serde(flatten)]
+    pub entry: MetadataEntry,
+    /// Why it was left out
+    pub reason: String,
+}
+
+/// The outcome of an [`apply_bundle`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Applied {
+    /// Entries the destination didn't have, sorted by MD5
+    pub added: Vec<MetadataEntry>,
+    /// Entries that replaced a different one for the same hash, with
+    /// [`ApplyBundleOptions::overwrite`]
+    pub replaced: Vec<MetadataEntry>,
+    /// Entries the destination already had with the same bitstream
+    pub present: Vec<MetadataEntry>,
+    /// Entries left out because the destination has them differently
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// Write entries of a repository and their bitstreams to a bundle
+///
+/// A hash of [`BundleOptions::md5s`] the repository has no entry for, or a
+/// bitstream that is missing, fails the whole bundle, so a bundle never
+/// lacks what was asked for. The bundle is written next to `opts.output` and renamed over
+/// it once complete.
+///
+/// ```no_run
+/// use bitcache::{BundleOptions, Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = BundleOptions::new("cache.bundle");
+/// let bundled = bitcache::create_bundle(&remote, &opts, &Context::default())?;
+/// println!("{} entries bundled", bundled.entries.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn create_bundle(remote: &Remote, opts: &BundleOptions, ctx: &Context) -> io::Result<Bundled> {
+    create_bundle_in(None, remote, opts, ctx)
+}
+
+/// [`create_bundle`], in the clone kept by `pool` if given
+pub(crate) fn create_bundle_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &BundleOptions,
+    ctx: &Context,
+) -> io::Result<Bundled> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    status!("Bundling {} into {}", remote.url, opts.output.display());
+    let checkout = checkout::checkout_shallow(pool, remote, ctx)?;
+    let repo_dir = checkout.dir();
+    let metadata = MetadataFile::of(repo_dir, remote).load()?;
+    if let Some(md5) = opts
+        .md5s
+        .iter()
+        .find(|md5| metadata.variants(md5).is_empty())
+    {
+        return Err(BitcacheError::NotFound { md5: md5.clone() }.into());
+    }
+    let mut bundled = Bundled::default();
+    let prefix = opts
+        .path_prefix
+        .as_deref()
+        .map(|prefix| prefix.trim_matches('/'))
+        .filter(|prefix| !prefix.is_empty());
+    let mut entries: Vec<_> = metadata
+        .iter()
+        .filter(|entry| opts.md5s.is_empty() || opts.md5s.contains(&entry.md5))
+        .filter(|entry| {
+            prefix.is_none_or(|prefix| {
+                entry
+                    .binary_path
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+        })
+        .cloned()
+        .collect();
+    entries.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+
+    let mut staging = Staging::new(ctx)?;
+    for entry in &entries {
+        entry.check_path()?;
+        if !staging.has_bitstream(&entry.binary_path) {
+            let stored = stored_bitstream(repo_dir, entry)?;
+            let digest = compute_md5(&stored)?;
+            staging.add_bitstream(&entry.binary_path, &stored, digest)?;
+        }
+        staging.add_entry(entry.clone());
+    }
+    bundled.bytes = staging.pack(&BUNDLE, &opts.output)?;
+    bundled.entries = entries;
+    Ok(bundled)
+}
+
+/// The bitstream at `path` in the clone `repo_dir`, failing when it is
+/// missing
+pub(crate) fn stored_bitstream(repo_dir: &Path, entry: &MetadataEntry) -> io::Result<PathBuf> {
+    let stored = entry.stored_in(repo_dir)?;
+    if !stored.is_file() {
+        return Err(BitcacheError::MissingBinary {
+            binary_path: entry.binary_path.clone(),
+        }
+        .into());
+    }
+    Ok(stored)
+}
+
+/// A tarball being laid out in a scratch directory before it is packed
+pub(crate) struct Staging {
+    /// Directory holding the manifest and the tree
+    scratch: ScratchDir,
+    /// The entries added
+    trimmed: Metadata,
+    /// MD5 of each bitstream added, by its path
+    checksums: BTreeMap<String, String>,
+    /// Bytes of the bitstreams added
+    bytes: u64,
+}
+
+impl Staging {
+    /// An empty tarball in a scratch directory of `ctx`
+    pub(crate) fn new(ctx: &Context) -> io::Result<Self> {
+        let scratch = ctx.temp_dir()?;
+        fs::create_dir(scratch.path().join(TREE_DIR))?;
+        Ok(Self {
+            scratch,
+            trimmed: Metadata::new(),
+            checksums: BTreeMap::new(),
+            bytes: 0,
+        })
+    }
+
+    /// Whether the bitstream at `path` was added already
+    pub(crate) fn has_bitstream(&self, path: &str) -> bool {
+        self.checksums.contains_key(path)
+    }
+
+    /// Add the bitstream `stored`, of MD5 `digest`, at `path`
+    pub(crate) fn add_bitstream(
+        &mut self,
+        path: &str,
+        stored: &Path,
+        digest: String,
+    ) -> io::Result<()> {
+        status!("Adding bitstream: {}", path);
+        let dest = paths::long_path(&self.scratch.path().join(TREE_DIR).join(path));
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let (size, _) = fsutil::link_or_copy(stored, &dest)?;
+        self.bytes += size;
+        self.checksums.insert(path.to_string(), digest);
+        Ok(())
+    }
+
+    /// Add `entry` to the trimmed metadata
+    pub(crate) fn add_entry(&mut self, entry: MetadataEntry) {
+        self.trimmed.insert_entry(entry);
+    }
+
+    /// Write the manifest and pack everything into `output` as `packing`
+    /// says, returning the bytes of the bitstreams
+    ///
+    /// The tarball is written next to `output` and renamed over it once
+    /// complete.
+    pub(crate) fn pack(self, packing: &Packing, output: &Path) -> io::Result<u64> {
+        let scratch = self.scratch.path();
+        self.trimmed
+            .save_to_file(&scratch.join(TREE_DIR).join(METADATA_FILE))?;
+        let manifest = Manifest {
+            format: packing.format,
+            created: chrono::Utc::now().to_rfc3339(),
+            bitcache_version: TOOL_VERSION.to_string(),
+            checksums: self.checksums,
+        };
+        fs::write(
+            scratch.join(MANIFEST_FILE),
+            serde_json::to_vec_pretty(&manifest)?,
+        )?;
+
+        status!("Writing {}...", output.display());
+        let dir = match output.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = output.file_name().unwrap_or(output.as_os_str());
+        let temp = tempfile::Builder::new()
+            .prefix(&format!(".{}.", name.to_string_lossy()))
+            .suffix(".tmp")
+            .tempfile_in(dir)?;
+        let args: [&OsStr; 7] = [
+            packing.compression.as_ref(),
+            "-cf".as_ref(),
+            temp.path().as_os_str(),
+            "-C".as_ref(),
+            scratch.as_os_str(),
+            MANIFEST_FILE.as_ref(),
+            TREE_DIR.as_ref(),
+        ];
+        tar(&args, &[], "write", output)?;
+        temp.persist(output).map_err(|e| e.error)?;
+        Ok(self.bytes)
+    }
+}
+
+/// What [`read_bundle`] found in a bundle
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BundleContents {
+    /// [`BUNDLE_FORMAT`] the bundle was written in
+    pub format: u32,
+    /// When it was written, RFC 3339
+    pub created: String,
+    /// Version of bitcache that wrote it
+    pub bitcache_version: String,
+    /// The entries it carries, sorted by MD5
+    pub entries: Vec<MetadataEntry>,
+}
+
+/// List the entries of a bundle written by [`create_bundle`] without
+/// applying them
+///
+/// Only the manifest and the metadata are unpacked, so the bitstreams are
+/// not checked; [`apply_bundle`] checks them all before it changes anything.
+///
+/// ```no_run
+/// use bitcache::Context;
+/// use std::path::Path;
+///
+/// let contents = bitcache::read_bundle(Path::new("cache.bundle"), &Context::default())?;
+/// for entry in &contents.entries {
+///     println!("{}  {}", entry.md5, entry.binary_path);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn read_bundle(input: &Path, ctx: &Context) -> io::Result<BundleContents> {
+    let _entered = ctx.enter();
+    let scratch = ctx.temp_dir()?;
+    let (manifest, metadata) = read_head(&BUNDLE, input, scratch.path())?;
+    let mut entries: Vec<_> = metadata.entries.into_values().flatten().collect();
+    entries.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+    Ok(BundleContents {
+        format: manifest.format,
+        created: manifest.created,
+        bitcache_version: manifest.bitcache_version,
+        entries,
+    })
+}
+
+/// Unpack the manifest and the metadata of the tarball `input`, packed as
+/// `packing` says, into `dir` and read them, refusing a later format
+pub(crate) fn read_head(
+    packing: &Packing,
+    input: &Path,
+    dir: &Path,
+) -> io::Result<(Manifest, Metadata)> {
+    let metadata_member = format!("{}/{}", TREE_DIR, METADATA_FILE);
+    unpack(
+        packing,
+        input,
+        dir,
+        &[MANIFEST_FILE.to_string(), metadata_member],
+    )?;
+    let manifest: Manifest =
+        serde_json::from_slice(&fs::read(dir.join(MANIFEST_FILE))?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is not a bitcache {}: its {} can't be read: {}",
+                    input.display(),
+                    packing.kind,
+                    MANIFEST_FILE,
+                    e
+                ),
+            )
+        })?;
+    if manifest.format > packing.format {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} is in {} format {}, but this version of bitcache only reads up to {}; upgrade bitcache to read it",
+                input.display(),
+                packing.kind,
+                manifest.format,
+                packing.format
+            ),
+        ));
+    }
+    let metadata = Metadata::load_from_file(&dir.join(TREE_DIR).join(METADATA_FILE))?;
+    Ok((manifest, metadata))
+}
+
+/// Publish the entries of a bundle, with their bitstreams, to a repository
+///
+/// Every bitstream is checked against the MD5 the bundle records for it
+/// before anything is written, and a bundle with a damaged or missing one is
+/// refused as a whole. An entry the repository has with the same bitstream
+/// at the same path is left alone, and one it has differently is skipped
+/// unless `opts.overwrite`. An entry whose path holds another entry's
+/// bitstream is always skipped. Everything goes into one commit.
+///
+/// ```no_run
+/// use bitcache::{ApplyBundleOptions, Context, Remote};
+///
+/// let remote = Remote::new("git@offline.example.com:fpga/bitstreams.git");
+/// let opts = ApplyBundleOptions::new("cache.bundle");
+/// let applied = bitcache::apply_bundle(&remote, &opts, &Context::default())?;
+/// println!("{} added, {} skipped", applied.added.len(), applied.skipped.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn apply_bundle(
+    remote: &Remote,
+    opts: &ApplyBundleOptions,
+    ctx: &Context,
+) -> io::Result<Applied> {
+    apply_bundle_in(None, remote, opts, ctx)
+}
+
+/// [`apply_bundle`], in the clone kept by `pool` if given
+pub(crate) fn apply_bundle_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &ApplyBundleOptions,
+    ctx: &Context,
+) -> io::Result<Applied> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let input = &opts.input;
+    status!("Unpacking {}...", input.display());
+    let scratch = ctx.temp_dir()?;
+    let tree = scratch.path().join(TREE_DIR);
+    let (manifest, bundle) = read_head(&BUNDLE, input, scratch.path())?;
+    unpack_bitstreams(&BUNDLE, input, scratch.path(), &manifest, &bundle)?;
+
+    let mut incoming: Vec<&MetadataEntry> = bundle.iter().collect();
+    incoming.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+    let name = input
+        .file_name()
+        .unwrap_or(input.as_os_str())
+        .to_string_lossy();
+    let message = format!("Apply bundle {}", name);
+    let mut applied = Applied::default();
+    trash::change_or_create(
+        pool,
+        remote,
+        ctx,
+        &message,
+        |repo_dir, metadata, written| {
+            // A retry starts over on the new remote head
+            applied = Applied::default();
+            for &entry in &incoming {
+                let checksum = manifest.checksums[&entry.binary_path].as_str();
+                let dest = paths::long_path(&repo_dir.join(&entry.binary_path));
+                let dest_checksum = if dest.is_file() {
+                    Some(compute_md5(&dest)?)
+                } else {
+                    None
+                };
+                let existing = metadata.lookup(&entry.md5, entry.name()).cloned();
+                if let Some(existing) = &existing {
+                    if existing.binary_path == entry.binary_path
+                        && dest_checksum.as_deref() == Some(checksum)
+                    {
+                        applied.present.push(entry.clone());
+                        continue;
+                    }
+                    if !opts.overwrite {
+                        applied.skipped.push(SkippedEntry {
+                        entry: entry.clone(),
+                        reason: format!(
+                            "the destination has it with the bitstream {}; pass --overwrite to replace it",
+                            existing.binary_path
+                        ),
+                    });
+                        continue;
+                    }
+                }
+                let owner = metadata.iter().find(|other| {
+                    (&other.md5, other.name()) != (&entry.md5, entry.name())
+                        && other.binary_path == entry.binary_path
+                });
+                if let Some(owner) = owner {
+                    if dest_checksum.as_deref() != Some(checksum) {
+                        applied.skipped.push(SkippedEntry {
+                            entry: entry.clone(),
+                            reason: format!(
+                                "{} already holds the bitstream of MD5 {}",
+                                entry.binary_path, owner.md5
+                            ),
+                        });
+                        continue;
+                    }
+                }
+                if dest_checksum.as_deref() != Some(checksum) {
+                    if let Some(dir) = dest.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    fsutil::link_or_copy(&paths::long_path(&tree.join(&entry.binary_path)), &dest)?;
+                    written.push(entry.binary_path.clone());
+                }
+                metadata.insert_entry(entry.clone());
+                match existing {
+                    Some(_) => applied.replaced.push(entry.clone()),
+                    None => applied.added.push(entry.clone()),
+                }
+            }
+            let changed = !applied.added.is_empty() || !applied.replaced.is_empty();
+            Ok(changed.then_some(()))
+        },
+    )?;
+    Ok(applied)
+}
+
+/// Unpack into `dir` the bitstreams `metadata` names from the tarball
+/// `input`, whose head [`read_head`] read, and check each against its
+/// checksum in `manifest`
+///
+/// Only the bitstreams the metadata names are unpacked, so nothing else in
+/// the tarball lands anywhere.
+pub(crate) fn unpack_bitstreams(
+    packing: &Packing,
+    input: &Path,
+    dir: &Path,
+    manifest: &Manifest,
+    metadata: &Metadata,
+) -> io::Result<()> {
+    let mut bitstreams: Vec<(&str, &str)> = Vec::new();
+    for entry in metadata.iter() {
+        entry.check_path()?;
+        let path = entry.binary_path.as_str();
+        if bitstreams.iter().any(|(other, _)| *other == path) {
+            continue;
+        }
+        let Some(checksum) = manifest.checksums.get(path) else {
+            return Err(damaged(
+                input,
+                format!("it records no checksum for the bitstream {}", path),
+            ));
+        };
+        bitstreams.push((path, checksum));
+    }
+    let members: Vec<String> = bitstreams
+        .iter()
+        .map(|(path, _)| format!("{}/{}", TREE_DIR, path))
+        .collect();
+    if !members.is_empty() {
+        unpack(packing, input, dir, &members)?;
+    }
+    status!(
+        "Checking {} bitstream{}...",
+        bitstreams.len(),
+        if bitstreams.len() == 1 { "" } else { "s" }
+    );
+    let tree = dir.join(TREE_DIR);
+    for (path, checksum) in &bitstreams {
+        cancel::check()?;
+        let unpacked = paths::long_path(&tree.join(path));
+        // A symlink could point anywhere outside the tarball
+        if !fs::symlink_metadata(&unpacked).is_ok_and(|meta| meta.is_file()) {
+            return Err(damaged(
+                input,
+                format!("the bitstream {} is missing or not a file", path),
+            ));
+        }
+        if compute_md5(&unpacked)? != *checksum {
+            return Err(damaged(
+                input,
+                format!(
+                    "the bitstream {} does not match its recorded checksum",
+                    path
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The error for a tarball `input` that can't be used as it is
+fn damaged(input: &Path, problem: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is damaged: {}", input.display(), problem),
+    )
+}
+
+/// Unpack the `members` of the tarball `input`, packed as `packing` says,
+/// into `dir`
+fn unpack(packing: &Packing, input: &Path, dir: &Path, members: &[String]) -> io::Result<()> {
+    let args: [&OsStr; 8] = [
+        packing.compression.as_ref(),
+        "-xf".as_ref(),
+        input.as_os_str(),
+        "-C".as_ref(),
+        dir.as_os_str(),
+        "--no-same-owner".as_ref(),
+        "-T".as_ref(),
+        "-".as_ref(),
+    ];
+    tar(&args, members, "read", input)
+}
+
+/// Run `tar` with `args`, giving it `names` one per line on its standard
+/// input
+fn tar(args: &[&OsStr], names: &[String], action: &str, tarball: &Path) -> io::Result<()> {
+    cancel::check()?;
+    let spawned = Command::new("tar")
+        .args(args)
+        .stdin(if names.is_empty() {
+            Stdio::null()
+        } else {
+            Stdio::piped()
+        })
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match spawned {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "tar executable not found in PATH; it is needed to write and read bundles and archives",
+            ));
+        }
+        spawned => spawned?,
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        for name in names {
+            writeln!(stdin, "{}", name)?;
+        }
+    }
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "tar could not {} {}: {}",
+            action,
+            tarball.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    ))
+}
@@ -0,0 +1,87 @@
+//! Cooperative cancellation.
+//!
+//! [`request`] only sets a flag; the CLI calls it from its Ctrl-C handler.
+//! Long-running work polls the flag between phases and inside copy loops, and
+//! child git processes are killed when it is raised, so every operation
+//! unwinds through its normal error path and drops its temporary directories
+//! and partial files on the way out.
+//!
+//! Operations started through the async API also watch a flag of their own,
+//! raised when their future is dropped, so one abandoned operation can be
+//! stopped without touching the others.
+
+use crate::error::BitcacheError;
+use std::cell::RefCell;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Exit code used when the user interrupts an operation (128 + SIGINT)
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Cancellation flag of the operation running on this thread, if any
+    static TOKEN: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+}
+
+/// Ask every running operation to stop, returning whether cancellation had
+/// already been requested
+///
+/// Operations fail with the [`interrupted`] error at their next check.
+pub fn request() -> bool {
+    CANCELLED.swap(true, Ordering::SeqCst)
+}
+
+/// Whether the user or the owner of the current operation has requested
+/// cancellation
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+        || TOKEN.with(|token| {
+            token
+                .borrow()
+                .as_ref()
+                .is_some_and(|token| token.load(Ordering::SeqCst))
+        })
+}
+
+/// Run `f` on this thread, treating `token` being raised like [`request`]
+#[cfg(any(feature = "async", test))]
+pub(crate) fn with_token<T>(token: Arc<AtomicBool>, f: impl FnOnce() -> T) -> T {
+    TOKEN.with(|current| *current.borrow_mut() = Some(token));
+    let result = f();
+    TOKEN.with(|current| current.borrow_mut().take());
+    result
+}
+
+/// Fail with an `Interrupted` error if cancellation has been requested
+pub fn check() -> io::Result<()> {
+    if is_cancelled() {
+        Err(interrupted())
+    } else {
+        Ok(())
+    }
+}
+
+/// Sleep for `duration`, waking early with an `Interrupted` error if
+/// cancellation is requested meanwhile
+pub(crate) fn sleep(duration: Duration) -> io::Result<()> {
+    const POLL: Duration = Duration::from_millis(50);
+    let until = Instant::now() + duration;
+    loop {
+        check()?;
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(());
+        }
+        thread::sleep(left.min(POLL));
+    }
+}
+
+/// The error returned by operations stopped by Ctrl-C
+pub fn interrupted() -> io::Error {
+    BitcacheError::Interrupted.into()
+}
@@ -0,0 +1,532 @@
+//! The clones operations work in.
+//!
+//! A free function such as [`crate::publish`] keeps its clone in the cache
+//! directory and brings it up to date with a fetch before the next run, which
+//! is much cheaper than cloning a large repository again. A lock file next to
+//! the clone is held for the whole operation, so runs sharing a clone, in
+//! this process or another, take turns. Without [`Context::clone_cache`] the
+//! repository is cloned into a fresh temporary directory that is removed
+//! when done.
+//!
+//! A [`crate::Bitcache`] client instead keeps its clone in a [`ClonePool`]
+//! for its own lifetime, and the pool's lock serializes its operations.
+//!
+//! A local repository, see [`Storage::Local`], is not cloned at all: its
+//! directory is used in place, locked through its [`LOCK_FILE`].
+//!
+//! A run waits for a lock for at most [`Context::lock_timeout`]. The run
+//! holding one writes its process ID into the lock file, which names it in
+//! the error of a waiter that gives up, and lets a waiter give up at once on
+//! a lock whose holder has died but left it held, e.g. through a process it
+//! started, which would otherwise never be let go.
+//!
+//! Operations that only read one bitstream, such as [`crate::get`], ask for
+//! a sparse checkout: a new one-off or cached clone then holds the files at
+//! the top of the repository, and fetches a bitstream's directory only once
+//! [`Checkout::include`] names it. A cached clone left sparse is widened the
+//! next time an operation needs all of it. Pooled clones are always whole.
+
+use crate::progress::{detail, status, warning};
+use crate::storage::{Storage, LOCK_FILE};
+use crate::{cancel, error, fsutil, git, store, BitcacheError, Context, Remote};
+use fs2::FileExt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a run waiting for a cached clone checks whether it is free
+const LOCK_POLL: Duration = Duration::from_millis(100);
+
+/// The clone a client keeps between operations
+#[derive(Default)]
+pub(crate) struct ClonePool(Mutex<Option<Kept>>);
+
+struct Kept {
+    /// Removes the clone when the client is dropped
+    _temp_dir: fsutil::ScratchDir,
+    dir: PathBuf,
+}
+
+/// A clone checked out for one operation
+pub(crate) struct Checkout<'a> {
+    dir: PathBuf,
+    /// Set for a one-off clone, which is removed when the checkout is dropped
+    _temp_dir: Option<fsutil::ScratchDir>,
+    /// Set for a pooled clone, which stays locked until the checkout is dropped
+    pooled: Option<MutexGuard<'a, Option<Kept>>>,
+    /// Set for a cached clone: its lock file, locked until the checkout is
+    /// dropped
+    cached: Option<fs::File>,
+    /// Whether only some directories are checked out, see [`Self::include`]
+    sparse: bool,
+}
+
+impl Checkout<'_> {
+    /// Root of the clone's working tree
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the clone outlives this operation, in which case files must
+    /// not be hardlinked out of it
+    pub(crate) fn is_pooled(&self) -> bool {
+        self.pooled.is_some() || self.cached.is_some()
+    }
+
+    /// Make sure the file at `path`, relative to the root of the clone, is
+    /// checked out, fetching its directory into a sparse checkout
+    pub(crate) fn include(&self, path: &str, remote: &Remote) -> io::Result<()> {
+        if !self.sparse {
+            return Ok(());
+        }
+        // Files at the top are always checked out
+        let Some((dir, _)) = path.rsplit_once('/') else {
+            return Ok(());
+        };
+        self.include_dir(dir, remote)
+    }
+
+    /// Make sure the directory at `dir`, relative to the root of the clone,
+    /// is checked out with everything in it
+    pub(crate) fn include_dir(&self, dir: &str, remote: &Remote) -> io::Result<()> {
+        if !self.sparse {
+            return Ok(());
+        }
+        detail!("Checking out {}", dir);
+        git::sparse_add(&self.dir, &[dir], remote.auth.as_ref())
+    }
+}
+
+/// How many commits [`checkout_shallow`] fetches
+const SHALLOW_DEPTH: u32 = 1;
+
+/// Check out `remote`, reusing the clone in `pool` when there is one
+///
+/// `before_clone` is given the directory the clone lives in before any git
+/// operation runs, so space checks can fail without network I/O.
+pub(crate) fn checkout<'a>(
+    pool: Option<&'a ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+    before_clone: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<Checkout<'a>> {
+    checkout_at_depth(pool, remote, ctx, None, CloneKind::Whole, before_clone)
+}
+
+/// [`checkout`] for an operation that publishes to the remote's branch,
+/// which is started from the default branch if the remote has none of that
+/// name yet, see [`git::clone_or_start_branch`]
+pub(crate) fn checkout_to_publish<'a>(
+    pool: Option<&'a ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+    before_clone: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<Checkout<'a>> {
+    checkout_at_depth(
+        pool,
+        remote,
+        ctx,
+        None,
+        CloneKind::StartBranch,
+        before_clone,
+    )
+}
+
+/// [`checkout`] for an operation that only reads the branch's head
+///
+/// A new clone is shallow, so a branch with thousands of commits is as quick
+/// to read as a new one. A shallow clone kept in `pool` still serves later
+/// operations that commit and push.
+pub(crate) fn checkout_shallow<'a>(
+    pool: Option<&'a ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<Checkout<'a>> {
+    checkout_at_depth(
+        pool,
+        remote,
+        ctx,
+        Some(SHALLOW_DEPTH),
+        CloneKind::Whole,
+        |_| Ok(()),
+    )
+}
+
+/// [`checkout_shallow`] for an operation that reads the metadata and at most
+/// the files [`Checkout::include`] names, which may leave the rest out
+///
+/// A clone made for `pool` is whole, as later operations on it may need
+/// every file.
+pub(crate) fn checkout_sparse<'a>(
+    pool: Option<&'a ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<Checkout<'a>> {
+    checkout_at_depth(
+        pool,
+        remote,
+        ctx,
+        Some(SHALLOW_DEPTH),
+        CloneKind::Sparse,
+        |_| Ok(()),
+    )
+}
+
+/// How [`checkout_at_depth`] makes a new clone
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CloneKind {
+    Whole,
+    /// See [`checkout_sparse`]
+    Sparse,
+    /// See [`checkout_to_publish`]
+    StartBranch,
+}
+
+impl CloneKind {
+    /// Clone `remote` into `dir`; returns whether the clone is sparse
+    fn run(self, remote: &Remote, dir: &Path, depth: Option<u32>) -> io::Result<bool> {
+        match (self, depth) {
+            (CloneKind::Sparse, Some(depth)) => git::clone_sparse(remote, dir, depth),
+            (CloneKind::StartBranch, _) => {
+                git::clone_or_start_branch(remote, dir, depth)?;
+                Ok(false)
+            }
+            _ => {
+                git::clone_repository(remote, dir, depth)?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+fn checkout_at_depth<'a>(
+    pool: Option<&'a ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+    depth: Option<u32>,
+    clone: CloneKind,
+    before_clone: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<Checkout<'a>> {
+    if remote.storage == Storage::Local {
+        return local(remote, ctx.lock_timeout, before_clone);
+    }
+    let Some(pool) = pool else {
+        if let Some(dir) = ctx.cached_clone_dir(remote) {
+            return cached(dir, remote, clone, ctx.lock_timeout, before_clone);
+        }
+        let temp_dir = ctx.temp_dir()?;
+        before_clone(temp_dir.path())?;
+        let dir = temp_dir.path().join("repo");
+        status!("Cloning repository: {}", error::redact(&remote.url));
+        let sparse = clone.run(remote, &dir, depth)?;
+        return Ok(Checkout {
+            dir,
+            _temp_dir: Some(temp_dir),
+            pooled: None,
+            cached: None,
+            sparse,
+        });
+    };
+
+    let mut kept = pool.0.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(clone) = kept.as_ref() {
+        before_clone(clone.dir.parent().unwrap_or(&clone.dir))?;
+        status!("Updating clone of {}", error::redact(&remote.url));
+        match git::refresh(&clone.dir, remote.auth.as_ref(), None) {
+            Ok(()) => {
+                let dir = clone.dir.clone();
+                return Ok(Checkout {
+                    dir,
+                    _temp_dir: None,
+                    pooled: Some(kept),
+                    cached: None,
+                    sparse: false,
+                });
+            }
+            Err(e) if cancel::is_cancelled() => return Err(e),
+            Err(e) => {
+                warning!("discarding the kept clone: {}", e);
+                *kept = None;
+            }
+        }
+    }
+
+    let temp_dir = ctx.temp_dir()?;
+    before_clone(temp_dir.path())?;
+    let dir = temp_dir.path().join("repo");
+    status!("Cloning repository: {}", error::redact(&remote.url));
+    // A pooled clone serves every later operation, so it is never sparse
+    let clone = match clone {
+        CloneKind::Sparse => CloneKind::Whole,
+        clone => clone,
+    };
+    clone.run(remote, &dir, depth)?;
+    *kept = Some(Kept {
+        _temp_dir: temp_dir,
+        dir: dir.clone(),
+    });
+    Ok(Checkout {
+        dir,
+        _temp_dir: None,
+        pooled: Some(kept),
+        cached: None,
+        sparse: false,
+    })
+}
+
+/// Check out `remote` in the cached clone at `dir`, updating it if it exists
+/// and cloning it otherwise, once no other run holds its lock
+///
+/// The clone is shallow and so is each update, which fetches only the new
+/// head of the branch. It is sparse when `clone` asks for it, and a sparse
+/// one is widened as soon as an operation needs all of it.
+pub(crate) fn cached<'a>(
+    dir: PathBuf,
+    remote: &Remote,
+    clone: CloneKind,
+    lock_timeout: Option<Duration>,
+    before_clone: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<Checkout<'a>> {
+    let parent = dir.parent().unwrap_or(&dir);
+    fs::create_dir_all(parent)?;
+    before_clone(parent)?;
+    let lock = wait_for_lock(
+        store::clone_lock(&dir)?,
+        &dir.with_extension("lock"),
+        "the cached clone",
+        lock_timeout,
+    )?;
+
+    // Only a clone on a branch can be updated; one of a tag is cloned again.
+    // Checking for .git keeps git from falling back to a repository the cache
+    // directory happens to be inside.
+    if dir.join(".git").is_dir() && matches!(git::current_branch(&dir), Ok(Some(_))) {
+        status!("Updating cached clone of {}", error::redact(&remote.url));
+        let refreshed =
+            git::refresh(&dir, remote.auth.as_ref(), Some(SHALLOW_DEPTH)).and_then(|()| {
+                let is_sparse = git::is_sparse(&dir)?;
+                if is_sparse && clone != CloneKind::Sparse {
+                    status!("Checking out the rest of the cached clone");
+                    git::sparse_disable(&dir, remote.auth.as_ref())?;
+                    return Ok(false);
+                }
+                Ok(is_sparse)
+            });
+        match refreshed {
+            Ok(sparse) => {
+                return Ok(Checkout {
+                    dir,
+                    _temp_dir: None,
+                    pooled: None,
+                    cached: Some(lock),
+                    sparse,
+                })
+            }
+            Err(e) if cancel::is_cancelled() => return Err(e),
+            Err(e) => warning!("discarding the cached clone: {}", e),
+        }
+    }
+
+    match fs::remove_dir_all(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    status!("Cloning repository: {}", error::redact(&remote.url));
+    detail!("Caching the clone in {}", dir.display());
+    let sparse = match clone.run(remote, &dir, Some(SHALLOW_DEPTH)) {
+        Ok(sparse) => sparse,
+        Err(e) => {
+            // A half-written clone would only be discarded by the next run
+            let _ = fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+    };
+    Ok(Checkout {
+        dir,
+        _temp_dir: None,
+        pooled: None,
+        cached: Some(lock),
+        sparse,
+    })
+}
+
+/// Use the local repository of `remote` in place, once no other run holds
+/// its lock
+fn local<'a>(
+    remote: &Remote,
+    lock_timeout: Option<Duration>,
+    before_clone: impl Fn(&Path) -> io::Result<()>,
+) -> io::Result<Checkout<'a>> {
+    let dir = PathBuf::from(&remote.url);
+    before_clone(&dir)?;
+    let path = dir.join(LOCK_FILE);
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(&path)?;
+    let lock = wait_for_lock(lock, &path, "the local repository", lock_timeout)?;
+    detail!("Using local repository {}", dir.display());
+    Ok(Checkout {
+        dir,
+        _temp_dir: None,
+        pooled: None,
+        cached: Some(lock),
+        sparse: false,
+    })
+}
+
+/// Take `lock`, the file at `path`, waiting up to `timeout` for another run
+/// that holds it to finish with `what`, and record this process as its
+/// holder
+fn wait_for_lock(
+    mut lock: fs::File,
+    path: &Path,
+    what: &str,
+    timeout: Option<Duration>,
+) -> io::Result<fs::File> {
+    let contended = fs2::lock_contended_error().kind();
+    let started = Instant::now();
+    let mut waiting = false;
+    let mut dead = None;
+    loop {
+        match lock.try_lock_exclusive() {
+            Ok(()) => {
+                Holder::current().record(&mut lock)?;
+                return Ok(lock);
+            }
+            Err(e) if e.kind() != contended => return Err(e),
+            Err(_) => {}
+        }
+        let holder = Holder::read(&mut lock);
+        if !waiting {
+            waiting = true;
+            match holder {
+                Some(Holder { pid, .. }) => status!(
+                    "Waiting for another bitcache run (process {}) to finish with {}...",
+                    pid,
+                    what
+                ),
+                None => status!(
+                    "Waiting for another bitcache run to finish with {}...",
+                    what
+                ),
+            }
+        }
+        // A holder must be seen dead twice, as the one who just took the lock
+        // may not have replaced the record of the last one yet
+        match holder.filter(|holder| !holder.is_running()) {
+            Some(holder) if dead == Some(holder) => {
+                return Err(BitcacheError::StaleLock {
+                    what: what.to_string(),
+                    holder: holder.pid,
+                    path: path.to_path_buf(),
+                }
+                .into())
+            }
+            holder => dead = holder,
+        }
+        if let Some(timeout) = timeout {
+            let waited = started.elapsed();
+            if waited >= timeout {
+                return Err(BitcacheError::LockTimeout {
+                    what: what.to_string(),
+                    holder: holder.map(|holder| holder.pid),
+                    waited,
+                }
+                .into());
+            }
+        }
+        cancel::check()?;
+        thread::sleep(LOCK_POLL);
+    }
+}
+
+/// The process that holds a lock, as recorded in the lock file
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Holder {
+    pid: u32,
+    /// When the process started, in clock ticks since boot, where the
+    /// platform tells; a process started later under the same ID is another
+    started: Option<u64>,
+}
+
+impl Holder {
+    fn current() -> Self {
+        let pid = process::id();
+        Self {
+            pid,
+            started: start_time(pid),
+        }
+    }
+
+    /// Replace the record in `lock`, which this process holds
+    fn record(self, lock: &mut fs::File) -> io::Result<()> {
+        lock.set_len(0)?;
+        lock.seek(SeekFrom::Start(0))?;
+        match self.started {
+            Some(started) => writeln!(lock, "{} {}", self.pid, started),
+            None => writeln!(lock, "{}", self.pid),
+        }
+    }
+
+    /// The holder recorded in `lock`, `None` if there is no valid record,
+    /// e.g. in a lock file written by an older version
+    fn read(lock: &mut fs::File) -> Option<Self> {
+        let mut record = String::new();
+        lock.seek(SeekFrom::Start(0)).ok()?;
+        lock.read_to_string(&mut record).ok()?;
+        let mut fields = record.split_whitespace();
+        let pid = fields.next()?.parse().ok().filter(|pid| *pid > 0)?;
+        let started = match fields.next() {
+            Some(started) => Some(started.parse().ok()?),
+            None => None,
+        };
+        Some(Self { pid, started })
+    }
+
+    /// Whether the process is still running, true where that can't be told
+    fn is_running(self) -> bool {
+        process_exists(self.pid)
+            && match (self.started, start_time(self.pid)) {
+                (Some(recorded), Some(now)) => recorded == now,
+                _ => true,
+            }
+    }
+}
+
+#[cfg(unix)]
+fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return true;
+    };
+    // Signal 0 only checks whether the process exists; one that belongs to
+    // another user can't be signalled, but exists all the same
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+/// Start time of the process `pid`, from the 22nd field of its
+/// `/proc/<pid>/stat`
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The name in the second field may hold spaces and parentheses
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start_time(_pid: u32) -> Option<u64> {
+    None
+}
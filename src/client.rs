@@ -0,0 +1,446 @@
+//! A long-lived handle on one repository.
+//!
+//! The free functions take the repository and settings on every call and
+//! bring a cached clone up to date, or clone afresh, each time. A [`Bitcache`] client is configured once
+//! with a [`Builder`] and keeps its clone between operations, updating it
+//! with a fetch instead of cloning again. A client is `Send + Sync`, so one
+//! instance can be shared between threads; operations through the same
+//! client run one at a time on the shared clone.
+
+use crate::checkout::ClonePool;
+use crate::git::{Auth, Committer};
+use crate::layout::{self, MetadataLayout, MigrateOptions, Migrated};
+use crate::progress::ProgressObserver;
+use crate::storage::{self, Storage};
+use crate::{
+    archive, bundle, delete, deprecate, diff, gc, get, health, init, lock, prune, publish, stage,
+    top, transfer, trash, update, verify, Applied, ApplyBundleOptions, Archived, BundleOptions,
+    Bundled, Context, DeleteOptions, Deleted, DeprecateOptions, DiffOptions, EmptyTrashOptions,
+    EntryDiff, EntryInfo, ExportArchiveOptions, GcOptions, GcReport, GetOptions,
+    ImportArchiveOptions, ImportOptions, Imported, InitOptions, Initialized, LockFile, LockOptions,
+    LockedGetOptions, Metadata, MetadataEntry, PruneOptions, Pruned, PublishOptions, PublishPlan,
+    Published, RegisterOptions, Registered, Remote, RepoHealth, RestoreOptions, Retrieved,
+    SearchOptions, Synced, TopEntry, TopOptions, TrashedEntry, UpdateOptions, UploadOptions,
+    Uploaded, VerifyOptions, VerifyReport,
+};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A configured connection to one repository
+///
+/// ```no_run
+/// use bitcache::{Bitcache, GetOptions, PublishOptions};
+///
+/// let client = Bitcache::builder()
+///     .repo("git@example.com:fpga/bitstreams.git")
+///     .ssh_key("/home/ci/.ssh/bitcache")
+///     .retries(3)
+///     .build()?;
+///
+/// let md5 = bitcache::compute_md5("top.vhd".as_ref())?;
+/// if !client.exists(&md5)? {
+///     // ... run synthesis ...
+///     client.publish(&PublishOptions::new("top.vhd", "build/top.bit", "boards/zedboard"))?;
+/// }
+/// client.get(&GetOptions::new(md5))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Bitcache {
+    remote: Remote,
+    ctx: Context,
+    pool: ClonePool,
+}
+
+// Sharing one client between threads is the point of keeping it long-lived
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Bitcache>();
+};
+
+impl Bitcache {
+    /// Start configuring a client
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// The repository this client works on
+    pub fn remote(&self) -> &Remote {
+        &self.remote
+    }
+
+    /// The settings this client's operations run with
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    /// [`crate::init`] the repository with an empty metadata file
+    pub fn init(&self, opts: &InitOptions) -> io::Result<Initialized> {
+        init::init_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::publish`] a bitstream to the repository
+    pub fn publish(&self, opts: &PublishOptions) -> io::Result<Published> {
+        publish::publish_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::upload`] a bitstream without recording an entry for it
+    pub fn upload(&self, opts: &UploadOptions) -> io::Result<Uploaded> {
+        stage::upload_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::register`] an entry for a bitstream already uploaded
+    pub fn register(&self, opts: &RegisterOptions) -> io::Result<Registered> {
+        stage::register_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::publish_batch`] several bitstreams in one commit
+    pub fn publish_batch(&self, batch: &[PublishOptions]) -> io::Result<Vec<Published>> {
+        publish::publish_batch_in(Some(&self.pool), &self.remote, batch, &self.ctx)
+    }
+
+    /// [`crate::update`] the bitstream of an existing entry
+    pub fn update(&self, opts: &UpdateOptions) -> io::Result<Published> {
+        update::update_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// Work out what [`Bitcache::publish`] would do without changing anything
+    ///
+    /// Always uses a clone of its own, since planning leaves files behind.
+    pub fn explain(&self, opts: &PublishOptions) -> io::Result<PublishPlan> {
+        publish::explain(&self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::publish_dry_run`]: check a [`Bitcache::publish`] without
+    /// committing anything, in a clone of its own like [`Bitcache::explain`]
+    pub fn publish_dry_run(&self, opts: &PublishOptions) -> io::Result<Published> {
+        publish::publish_dry_run(&self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::get`] the bitstream published for a source MD5
+    pub fn get(&self, opts: &GetOptions) -> io::Result<Option<Retrieved>> {
+        get::get_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// Whether the repository has an entry for a source MD5
+    pub fn exists(&self, md5: &str) -> io::Result<bool> {
+        get::exists_in(Some(&self.pool), &self.remote, md5, &self.ctx)
+    }
+
+    /// [`crate::info`] on the entry for a source MD5, without retrieving
+    /// its bitstream
+    pub fn info(&self, md5: &str, variant: Option<&str>) -> io::Result<Option<EntryInfo>> {
+        get::info_in(Some(&self.pool), &self.remote, md5, variant, &self.ctx)
+    }
+
+    /// [`crate::find`] the entries published for a source hash
+    pub fn find(&self, md5: &str) -> io::Result<Vec<MetadataEntry>> {
+        get::find_in(Some(&self.pool), &self.remote, md5, &self.ctx)
+    }
+
+    /// Every entry in the repository, oldest first
+    pub fn list(&self) -> io::Result<Vec<MetadataEntry>> {
+        get::list_in(Some(&self.pool), &self.remote, &self.ctx)
+    }
+
+    /// [`crate::search`] the entries of the repository
+    pub fn search(&self, opts: &SearchOptions) -> io::Result<Vec<MetadataEntry>> {
+        get::search_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::delete`] the entry for a source hash
+    pub fn delete(&self, opts: &DeleteOptions) -> io::Result<Option<Deleted>> {
+        delete::delete_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::deprecate`] the entry for a source hash
+    pub fn deprecate(&self, opts: &DeprecateOptions) -> io::Result<Option<MetadataEntry>> {
+        deprecate::deprecate_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::list_trash`] of the repository
+    pub fn list_trash(&self) -> io::Result<Vec<TrashedEntry>> {
+        trash::list_trash_in(Some(&self.pool), &self.remote, &self.ctx)
+    }
+
+    /// [`crate::restore`] a trashed entry
+    pub fn restore(&self, opts: &RestoreOptions) -> io::Result<Option<MetadataEntry>> {
+        trash::restore_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::empty_trash`] of the repository
+    pub fn empty_trash(&self, opts: &EmptyTrashOptions) -> io::Result<Vec<TrashedEntry>> {
+        trash::empty_trash_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::diff`] two entries of the repository
+    pub fn diff(&self, opts: &DiffOptions) -> io::Result<EntryDiff> {
+        diff::diff_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::top`] the repository's entries
+    pub fn top(&self, opts: &TopOptions) -> io::Result<Vec<TopEntry>> {
+        top::top_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::prune`] the entries a retention policy no longer keeps
+    pub fn prune(&self, opts: &PruneOptions) -> io::Result<Pruned> {
+        prune::prune_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::create_bundle`] of entries of the repository
+    pub fn create_bundle(&self, opts: &BundleOptions) -> io::Result<Bundled> {
+        bundle::create_bundle_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::apply_bundle`] to the repository
+    pub fn apply_bundle(&self, opts: &ApplyBundleOptions) -> io::Result<Applied> {
+        bundle::apply_bundle_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::lock`] artifacts of the repository
+    pub fn lock(&self, opts: &LockOptions) -> io::Result<LockFile> {
+        lock::lock_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::get_locked`] the artifacts of a lock file
+    pub fn get_locked(
+        &self,
+        lock: &LockFile,
+        opts: &LockedGetOptions,
+    ) -> io::Result<Vec<Retrieved>> {
+        lock::get_locked_in(Some(&self.pool), &self.remote, lock, opts, &self.ctx)
+    }
+
+    /// [`crate::probe`] the repository
+    pub fn probe(&self) -> io::Result<RepoHealth> {
+        health::probe_in(Some(&self.pool), &self.remote, &self.ctx)
+    }
+
+    /// [`crate::export`] the repository's metadata
+    pub fn export(&self) -> io::Result<Metadata> {
+        transfer::export_in(Some(&self.pool), &self.remote, &self.ctx)
+    }
+
+    /// [`crate::import`] entries into the repository's metadata
+    pub fn import(&self, opts: &ImportOptions) -> io::Result<Imported> {
+        transfer::import_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::export_archive`] entries of the repository
+    pub fn export_archive(&self, opts: &ExportArchiveOptions) -> io::Result<Archived> {
+        archive::export_archive_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::import_archive`] entries into the repository
+    pub fn import_archive(&self, opts: &ImportArchiveOptions) -> io::Result<Synced> {
+        archive::import_archive_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::verify`] the repository's bitstreams
+    pub fn verify(&self, opts: &VerifyOptions) -> io::Result<VerifyReport> {
+        verify::verify_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::gc`] the repository's orphaned bitstreams
+    pub fn gc(&self, opts: &GcOptions) -> io::Result<GcReport> {
+        gc::gc_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+
+    /// [`crate::migrate_metadata`] to another layout
+    pub fn migrate_metadata(&self, opts: &MigrateOptions) -> io::Result<Migrated> {
+        layout::migrate_metadata_in(Some(&self.pool), &self.remote, opts, &self.ctx)
+    }
+}
+
+/// Configures a [`Bitcache`] client
+///
+/// Everything but [`Builder::repo`], or [`Builder::local`], is optional and defaults to what the CLI
+/// uses.
+#[derive(Debug, Default)]
+pub struct Builder {
+    url: Option<String>,
+    branch: Option<String>,
+    auth: Option<Auth>,
+    storage: Storage,
+    metadata_path: Option<String>,
+    metadata_layout: MetadataLayout,
+    ctx: Context,
+}
+
+impl Builder {
+    /// Git repository URL
+    pub fn repo(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self.storage = Storage::Git;
+        self
+    }
+
+    /// Directory to use as the repository in place, without git
+    pub fn local(mut self, dir: impl AsRef<Path>) -> Self {
+        self.url = Some(dir.as_ref().to_string_lossy().into_owned());
+        self.storage = Storage::Local;
+        self
+    }
+
+    /// Branch to read and publish to instead of the remote's default branch
+    pub fn branch(mut self, branch: impl Into<String>) -> Self {
+        self.branch = Some(branch.into());
+        self
+    }
+
+    /// Credentials for git operations
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Path of the metadata file in the repository instead of
+    /// [`crate::METADATA_FILE`]
+    pub fn metadata_path(mut self, path: impl Into<String>) -> Self {
+        self.metadata_path = Some(path.into());
+        self
+    }
+
+    /// Layout of the metadata created in a repository that has none
+    pub fn metadata_layout(mut self, layout: MetadataLayout) -> Self {
+        self.metadata_layout = layout;
+        self
+    }
+
+    /// Authenticate with an SSH private key
+    pub fn ssh_key(self, path: impl Into<PathBuf>) -> Self {
+        self.auth(Auth::SshKey(path.into()))
+    }
+
+    /// Authenticate over HTTPS with an access token
+    pub fn token(self, token: impl Into<String>) -> Self {
+        self.auth(Auth::Token(token.into()))
+    }
+
+    /// Parent directory for the client's clone and other temporary files
+    pub fn work_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.ctx.work_dir = Some(dir.into());
+        self
+    }
+
+    /// Root of the local caches, or `None` to run without them
+    pub fn cache_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.ctx.cache_dir = dir;
+        self
+    }
+
+    /// Size limit for the local artifact store in bytes, 0 for none
+    pub fn cache_max_size(mut self, bytes: u64) -> Self {
+        self.ctx.cache_max_size = bytes;
+        self
+    }
+
+    /// How many times a rejected push is retried before publish gives up
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.ctx.push_attempts = retries.saturating_add(1);
+        self
+    }
+
+    /// How many times a failed clone, fetch or push is retried, and the wait
+    /// before the first retry, doubled for each next
+    pub fn transfer_retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.ctx.max_retries = retries;
+        self.ctx.retry_delay = delay;
+        self
+    }
+
+    /// Cap git's transfers to and from the remote at this many bytes per
+    /// second, 0 for no limit
+    pub fn limit_rate(mut self, bytes_per_sec: u64) -> Self {
+        self.ctx.limit_rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// How long deleted entries stay in the trash, `None` to keep them until
+    /// the trash is emptied
+    pub fn trash_retention(mut self, retention: Option<Duration>) -> Self {
+        self.ctx.trash_retention = retention;
+        self
+    }
+
+    /// Kill a clone, fetch or push that runs longer than `timeout`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.ctx.timeout = Some(timeout);
+        self
+    }
+
+    /// Fail an operation that waited longer than `timeout` for another run
+    /// to finish with the cached clone or local repository
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.ctx.lock_timeout = Some(timeout);
+        self
+    }
+
+    /// How many bitstreams a batch publish copies at once, 0 for one per CPU
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.ctx.jobs = jobs;
+        self
+    }
+
+    /// Read back and hash every written file
+    pub fn paranoid(mut self, paranoid: bool) -> Self {
+        self.ctx.paranoid = paranoid;
+        self
+    }
+
+    /// Report the events of this client's operations to `observer`
+    pub fn observer(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.ctx.observer = Some(observer);
+        self
+    }
+
+    /// Report git's own progress while cloning, fetching and pushing as
+    /// detail events
+    pub fn git_progress(mut self, git_progress: bool) -> Self {
+        self.ctx.git_progress = git_progress;
+        self
+    }
+
+    /// Make the client's commits as `committer` instead of git's configured
+    /// identity
+    pub fn committer(mut self, committer: Committer) -> Self {
+        self.ctx.committer = Some(committer);
+        self
+    }
+
+    /// Leave temporary clones in place for inspection
+    pub fn keep_temp(mut self, keep_temp: bool) -> Self {
+        self.ctx.keep_temp = keep_temp;
+        self
+    }
+
+    /// Check the configuration and create the client
+    ///
+    /// Nothing is cloned until the first operation.
+    pub fn build(self) -> io::Result<Bitcache> {
+        let url = self.url.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No repository configured: call Builder::repo or Builder::local",
+            )
+        })?;
+        let mut remote = Remote {
+            branch: self.branch,
+            auth: self.auth,
+            storage: self.storage,
+            metadata_layout: self.metadata_layout,
+            ..Remote::new(url)
+        };
+        if let Some(path) = self.metadata_path {
+            remote.metadata_path = path;
+        }
+        storage::check(&remote)?;
+        Ok(Bitcache {
+            remote,
+            ctx: self.ctx,
+            pool: ClonePool::default(),
+        })
+    }
+}
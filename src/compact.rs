@@ -0,0 +1,156 @@
+//! Dropping the history of a repository's bitstreams.
+//!
+//! Deleting an entry removes its bitstream from the head of the branch, but
+//! every clone still downloads each version ever committed. [`compact`]
+//! replaces the branch's history with a single commit holding the files at
+//! its head, which are the live bitstreams and the metadata.
+
+use crate::error;
+use crate::git::{self, PushOutcome};
+use crate::layout::MetadataFile;
+use crate::progress::status;
+use crate::storage::{self, Storage};
+use crate::{cancel, paths, Context, Remote};
+use serde::Serialize;
+use std::io;
+
+/// Prefix of the tag [`compact`] leaves at the replaced head
+pub const BACKUP_TAG_PREFIX: &str = "bitcache-before-compact-";
+
+/// Whether [`compact`] rewrites the branch or only estimates
+#[derive(Debug, Clone, Default)]
+pub struct CompactOptions {
+    /// Rewrite and force-push the branch; without it the sizes are only
+    /// estimated, as the CLI does without `--yes`
+    pub confirm: bool,
+}
+
+impl CompactOptions {
+    /// Only estimate, as the CLI does without `--yes`
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Result of [`compact`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Compacted {
+    /// Branch compacted
+    pub branch: String,
+    /// Number of commits in its history before
+    pub commits: usize,
+    /// Bytes of every file version in its history before, uncompressed
+    pub before: u64,
+    /// Bytes of the files at its head, all the history keeps afterwards
+    pub after: u64,
+    /// Tag pushed at the replaced head, to recover it from; `None` when
+    /// nothing was rewritten
+    pub backup_tag: Option<String>,
+}
+
+/// Replace the history of a repository's branch with one commit holding its
+/// head
+///
+/// The repository is cloned in full to measure its history, and nothing is
+/// changed unless [`CompactOptions::confirm`]. The head the branch had is
+/// then tagged with [`BACKUP_TAG_PREFIX`] and the time, and the new commit
+/// is force-pushed with a lease on that head, so a publisher that pushed in
+/// the meantime makes the compaction fail instead of being lost. Every
+/// clone of the branch has to be made afresh, or reset, afterwards;
+/// bitcache's cached clones reset themselves. Clones still fetch the old
+/// history through the backup tag, so delete it once the compacted branch
+/// is known to be good.
+///
+/// ```no_run
+/// use bitcache::{CompactOptions, Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let estimate = bitcache::compact(&remote, &CompactOptions::new(), &Context::default())?;
+/// println!("{} -> {} bytes", estimate.before, estimate.after);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn compact(remote: &Remote, opts: &CompactOptions, ctx: &Context) -> io::Result<Compacted> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    if remote.storage == Storage::Local {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "A local repository keeps no history to compact",
+        ));
+    }
+    let scratch = ctx.temp_dir()?;
+    let repo_dir = scratch.path().join("repo");
+    status!("Cloning repository: {}", error::redact(&remote.url));
+    git::clone_repository(remote, &repo_dir, None)?;
+    let branch = git::current_branch(&repo_dir)?.ok_or_else(|| {
+        io::Error::other("Cannot compact: the clone is not on a branch, pass --branch")
+    })?;
+    // The head must be one whose metadata can be read, or there is nothing
+    // to tell live bitstreams apart by
+    let metadata = MetadataFile::of(&repo_dir, remote).load_or_new()?;
+    for entry in metadata.iter() {
+        if !paths::long_path(&repo_dir.join(&entry.binary_path)).is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Cannot compact: the bitstream {} of MD5 {} is missing and its history would be lost; run verify --fix first",
+                    entry.binary_path,
+                    entry.label()
+                ),
+            ));
+        }
+    }
+
+    // A repository nothing was published to yet has no history at all
+    if git::head_commit(&repo_dir)?.is_none() {
+        return Ok(Compacted {
+            branch,
+            commits: 0,
+            before: 0,
+            after: 0,
+            backup_tag: None,
+        });
+    }
+
+    status!("Measuring the history of {}...", branch);
+    let (before, after) = git::blob_sizes(&repo_dir)?;
+    let mut compacted = Compacted {
+        commits: git::commit_count(&repo_dir)?,
+        branch,
+        before,
+        after,
+        backup_tag: None,
+    };
+    if !opts.confirm || compacted.commits <= 1 {
+        return Ok(compacted);
+    }
+
+    cancel::check()?;
+    let head = git::head_commit(&repo_dir)?
+        .ok_or_else(|| io::Error::other("Cannot compact: the branch has no commits"))?;
+    let tag = format!(
+        "{}{}",
+        BACKUP_TAG_PREFIX,
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+    );
+    let message = format!(
+        "Compact the history of {}\n\nReplaces {} commits; the previous head is tagged {}\n",
+        compacted.branch, compacted.commits, tag
+    );
+    let root = git::root_commit(&repo_dir, &message)?;
+    status!("Tagging the current head as {}...", tag);
+    let auth = remote.auth.as_ref();
+    git::push_tag(&repo_dir, &tag, &head, auth)?;
+    status!("Replacing the history of {}...", compacted.branch);
+    match git::force_push(&repo_dir, &root, &compacted.branch, &head, auth)? {
+        PushOutcome::Pushed => {}
+        PushOutcome::Rejected(_) => {
+            return Err(io::Error::other(format!(
+                "Another publisher pushed to {} during the compaction, so nothing was rewritten; run compact again",
+                compacted.branch
+            )));
+        }
+    }
+    compacted.backup_tag = Some(tag);
+    Ok(compacted)
+}
@@ -0,0 +1,273 @@
+//! Compression of stored bitstreams.
+//!
+//! Bitstreams compress well, so [`crate::publish`] can store them gzip or
+//! zstd compressed to keep the repository small. The entry's `compression`
+//! field records how, and [`crate::get`] saves the bitstream decompressed.
+//! Both directions stream through the `gzip` and `zstd` programs, in the
+//! way git is run, so no bitstream is ever held in memory.
+
+use crate::{cancel, fsutil};
+use clap::ValueEnum;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// Highest level zstd allows without `--ultra`
+const MAX_NORMAL_ZSTD_LEVEL: u8 = 19;
+
+/// How a bitstream is stored in the repository
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, ValueEnum)]
+#[non_exhaustive]
+pub enum Compression {
+    /// As it is, the default
+    #[default]
+    None,
+    /// gzip
+    Gzip,
+    /// Zstandard
+    Zstd,
+}
+
+impl Compression {
+    /// Name stored in the `compression` field of metadata entries
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    /// Appended to the name of a bitstream stored this way
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// The levels the program accepts; only 0, which stores the bitstream
+    /// as it is, for [`Compression::None`]
+    pub fn levels(self) -> RangeInclusive<u8> {
+        match self {
+            Compression::None => 0..=0,
+            Compression::Gzip => 1..=9,
+            Compression::Zstd => 1..=22,
+        }
+    }
+
+    /// Level used unless one is given, the program's own default
+    pub fn default_level(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Gzip => 6,
+            Compression::Zstd => 3,
+        }
+    }
+
+    /// The program that compresses and decompresses, `None` for
+    /// [`Compression::None`]
+    fn program(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// Write `src` compressed at `level` to `dest`, returning the compressed
+/// size
+///
+/// The output is the same for the same input and level every time, so
+/// publishing a bitstream again is recognized as unchanged.
+pub(crate) fn compress(
+    compression: Compression,
+    src: &Path,
+    dest: &Path,
+    level: u8,
+) -> io::Result<u64> {
+    let flag = format!("-{}", level);
+    let mut args = vec!["-c", flag.as_str()];
+    match compression {
+        // gzip would otherwise store the time of compression in the header
+        Compression::Gzip => args.push("-n"),
+        _ => args.push("-q"),
+    }
+    if compression == Compression::Zstd && level > MAX_NORMAL_ZSTD_LEVEL {
+        args.push("--ultra");
+    }
+    filter(compression, &args, src, dest, "compress")
+}
+
+/// Write `src`, stored with `compression`, decompressed to `dest`,
+/// returning the decompressed size
+pub(crate) fn decompress(compression: Compression, src: &Path, dest: &Path) -> io::Result<u64> {
+    filter(compression, &["-d", "-c", "-q"], src, dest, "decompress")
+}
+
+/// Run the program of `compression` with `args` from `src` to `dest`,
+/// removing `dest` if it fails
+fn filter(
+    compression: Compression,
+    args: &[&str],
+    src: &Path,
+    dest: &Path,
+    action: &str,
+) -> io::Result<u64> {
+    let Some(program) = compression.program() else {
+        let (size, _) = fsutil::clone_or_copy(src, dest)?;
+        return Ok(size);
+    };
+    cancel::check()?;
+    let output = Command::new(program)
+        .args(args)
+        .stdin(fs::File::open(src)?)
+        .stdout(fs::File::create(dest)?)
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|child| child.wait_with_output());
+    let finished = output.and_then(|output| {
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} could not {} {}: {}",
+                program,
+                action,
+                src.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ))
+    });
+    if let Err(e) = finished {
+        let _ = fs::remove_file(dest);
+        if e.kind() == io::ErrorKind::NotFound {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} executable not found in PATH; it is needed to {} bitstreams stored with {} compression",
+                    program, action, compression
+                ),
+            ));
+        }
+        return Err(e);
+    }
+    Ok(fs::metadata(dest)?.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Something compressible that is not all one byte
+    fn bitstream() -> Vec<u8> {
+        (0..64 * 1024u32)
+            .map(|i| if i % 7 == 0 { (i % 251) as u8 } else { 0xff })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_at_every_kind_of_level() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let original = dir.path().join("top.bit");
+        fs::write(&original, bitstream())?;
+        let levels = [
+            (Compression::Gzip, 1),
+            (Compression::Gzip, 9),
+            (Compression::Zstd, 1),
+            (Compression::Zstd, MAX_NORMAL_ZSTD_LEVEL),
+            (Compression::Zstd, 22),
+        ];
+        for (compression, level) in levels {
+            assert!(compression.levels().contains(&level));
+            let name = format!("top.bit.{}{}", level, compression.extension());
+            let compressed = dir.path().join(name);
+            let restored = dir
+                .path()
+                .join(format!("top.{}.{}.bit", compression, level));
+            let size = compress(compression, &original, &compressed, level)?;
+            assert!(size < bitstream().len() as u64, "{} {}", compression, level);
+            assert_eq!(
+                decompress(compression, &compressed, &restored)?,
+                bitstream().len() as u64
+            );
+            assert_eq!(
+                fs::read(&restored)?,
+                bitstream(),
+                "{} {}",
+                compression,
+                level
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn output_is_reproducible() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let original = dir.path().join("top.bit");
+        fs::write(&original, bitstream())?;
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let first = dir.path().join(format!("first{}", compression.extension()));
+            let second = dir
+                .path()
+                .join(format!("second{}", compression.extension()));
+            let level = compression.default_level();
+            compress(compression, &original, &first, level)?;
+            if compression == Compression::Gzip {
+                // gzip would record a later time in a second run without -n
+                std::thread::sleep(std::time::Duration::from_millis(1100));
+            }
+            compress(compression, &original, &second, level)?;
+            assert_eq!(fs::read(first)?, fs::read(second)?, "{}", compression);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn garbage_fails_to_decompress_and_leaves_nothing() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let garbage = dir
+                .path()
+                .join(format!("top.bit{}", compression.extension()));
+            let restored = dir.path().join("top.bit");
+            fs::write(&garbage, b"not compressed at all")?;
+            let error =
+                decompress(compression, &garbage, &restored).expect_err("decompressed garbage");
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", compression);
+            assert!(!restored.exists());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn names_parse_back() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            assert_eq!(compression.name().parse(), Ok(compression));
+        }
+        assert!("lz4".parse::<Compression>().is_err());
+    }
+}
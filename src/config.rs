@@ -0,0 +1,571 @@
+//! Layered configuration.
+//!
+//! Every documented option resolves through the same layers, highest priority
+//! first:
+//!
+//! 1. the command-line flag
+//! 2. the `BITCACHE_<KEY>` environment variable
+//! 3. the project config file (`.bitcache.toml` in the current directory or
+//!    the nearest parent that has one)
+//! 4. the user config file (`$XDG_CONFIG_HOME/bitcache/config.toml`, falling
+//!    back to `~/.config/bitcache/config.toml`)
+//! 5. the built-in default
+//!
+//! [`OPTIONS`] is the single list of layered options. It drives both the
+//! resolution and `bitcache config show`; a test of the CLI checks that every
+//! clap argument is either listed there or in [`CLI_ONLY`].
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Name of the project config file
+pub const PROJECT_FILE_NAME: &str = ".bitcache.toml";
+
+/// An option that can be set on the command line, in the environment or in a
+/// config file
+pub struct OptionSpec {
+    /// Key in config files; also names the environment variable
+    /// (`BITCACHE_<KEY>`) and the flag (`--<key>` with dashes), if any
+    pub key: &'static str,
+    /// One-line description shown by `config show`
+    pub help: &'static str,
+}
+
+/// Options resolved through every configuration layer
+pub const OPTIONS: &[OptionSpec] = &[
+    OptionSpec {
+        key: "repo",
+        help: "Git repository URL",
+    },
+    OptionSpec {
+        key: "fallback_repos",
+        help: "Repositories get tries in order when --repo fails, as a list",
+    },
+    OptionSpec {
+        key: "ssh_key",
+        help: "Path to SSH private key for git operations",
+    },
+    OptionSpec {
+        key: "token",
+        help: "Access token for the HTTPS host of repo, sent as the password of x-access-token",
+    },
+    OptionSpec {
+        key: "branch",
+        help: "Branch to read and publish to instead of the remote's default",
+    },
+    OptionSpec {
+        key: "path",
+        help: "Default target directory for publish",
+    },
+    OptionSpec {
+        key: "compress",
+        help: "How publish stores bitstreams: none, gzip or zstd",
+    },
+    OptionSpec {
+        key: "compress_level",
+        help: "Level publish compresses at: gzip 1 to 9, zstd 1 to 22",
+    },
+    OptionSpec {
+        key: "on_collision",
+        help: "What publish does when a path holds another entry's bitstream (rename, fail, overwrite)",
+    },
+    OptionSpec {
+        key: "build_tool_version",
+        help: "Version of the tool that built the bitstreams publish and register record",
+    },
+    OptionSpec {
+        key: "hash_algo",
+        help: "Algorithm publish hashes source files with (md5, sha256, sha512)",
+    },
+    OptionSpec {
+        key: "author",
+        help: "Who publish records as publishing, instead of the git user",
+    },
+    OptionSpec {
+        key: "no_source_commit",
+        help: "Record no source commit on publish instead of looking it up",
+    },
+    OptionSpec {
+        key: "jobs",
+        help: "Bitstreams publish-batch copies at once, 0 for one per CPU",
+    },
+    OptionSpec {
+        key: "raw_units",
+        help: "Print exact byte counts and milliseconds",
+    },
+    OptionSpec {
+        key: "time",
+        help: "Timestamp display style (utc, local, relative)",
+    },
+    OptionSpec {
+        key: "json",
+        help: "Print results and errors as JSON on stdout",
+    },
+    OptionSpec {
+        key: "format",
+        help: "How get and list print entries (human, json, csv)",
+    },
+    OptionSpec {
+        key: "heartbeat",
+        help: "Seconds between progress lines in non-TTY runs",
+    },
+    OptionSpec {
+        key: "work_dir",
+        help: "Directory for temporary clones and staging files",
+    },
+    OptionSpec {
+        key: "no_follow_symlinks",
+        help: "Refuse symlinked --source and --bitstream files on publish",
+    },
+    OptionSpec {
+        key: "output_dir",
+        help: "Where get saves bitstreams when the current directory is not writable",
+    },
+    OptionSpec {
+        key: "cache_dir",
+        help: "Directory for the local artifact cache and cached clones",
+    },
+    OptionSpec {
+        key: "no_cache",
+        help: "Clone into a temporary directory instead of reusing the cached clone",
+    },
+    OptionSpec {
+        key: "cache_max_size",
+        help: "Maximum size of the local artifact cache (e.g. 10G), 0 for no limit",
+    },
+    OptionSpec {
+        key: "limit_rate",
+        help: "Cap on git transfers in bytes per second (e.g. 2M), 0 for no limit",
+    },
+    OptionSpec {
+        key: "push_retries",
+        help: "Retries of a push rejected because another run pushed first",
+    },
+    OptionSpec {
+        key: "trash_retention_days",
+        help: "Days deleted entries stay in the trash, 0 to keep them until emptied",
+    },
+    OptionSpec {
+        key: "max_retries",
+        help: "Retries of a failed clone, fetch or push",
+    },
+    OptionSpec {
+        key: "retry_delay_ms",
+        help: "Milliseconds before the first network retry, doubled for each next",
+    },
+    OptionSpec {
+        key: "timeout",
+        help: "Seconds after which a clone, fetch or push is killed, 0 for no limit",
+    },
+    OptionSpec {
+        key: "lock_timeout",
+        help: "Seconds to wait for another run to finish with a clone, 0 for no limit",
+    },
+    OptionSpec {
+        key: "no_local_cache",
+        help: "Always fetch from the repository on get",
+    },
+    OptionSpec {
+        key: "sync",
+        help: "Flush bitstreams saved by get to disk before reporting success",
+    },
+    OptionSpec {
+        key: "refuse_deprecated",
+        help: "Fail on get of a deprecated entry instead of only warning",
+    },
+    OptionSpec {
+        key: "skip_verify",
+        help: "Save bitstreams on get without checking them against their recorded MD5",
+    },
+    OptionSpec {
+        key: "paranoid",
+        help: "Re-read and hash every file bitcache writes",
+    },
+    OptionSpec {
+        key: "verbose",
+        help: "Print more detail",
+    },
+    OptionSpec {
+        key: "log",
+        help: "How much to print: error (as --quiet), info or debug (as --verbose)",
+    },
+    OptionSpec {
+        key: "backend",
+        help: "Where the repository lives: git, dir for a directory repo names, or local:<DIR> for a directory used without git",
+    },
+    OptionSpec {
+        key: "metadata_path",
+        help: "Path of the metadata file in the repository",
+    },
+    OptionSpec {
+        key: "metadata_layout",
+        help: "Layout of new metadata: single, or sharded for a file per hash",
+    },
+];
+
+/// Arguments that only make sense for a single invocation and are therefore
+/// deliberately not read from the environment or config files
+pub const CLI_ONLY: &[&str] = &[
+    "source",
+    "include_hidden",
+    "include_symlinks",
+    "quiet",
+    "bitstream",
+    "md5",
+    "filter",
+    "expression",
+    "older_than",
+    "keep_latest",
+    "orphans",
+    "reason",
+    "replacement",
+    "undo",
+    "source_name",
+    "binary_path",
+    "binary_md5",
+    "explain",
+    "dry_run",
+    "env",
+    "env_format",
+    "output",
+    "keep_temp",
+    "rename_in_repo",
+    "variant",
+    "allow_empty_source",
+    "yes",
+    "list_plugins",
+    "by",
+    "limit",
+    "prefix",
+    "source_filter",
+    "source_pattern",
+    "since",
+    "tags",
+    "filter_tags",
+    "purge_binary",
+    "hard",
+    "expired",
+    "force",
+    "fail_if_exists",
+    "source_md5_hint",
+    "fix",
+    "os",
+    "arch",
+    "prune",
+    "manifest",
+    "input",
+    "merge",
+    "replace",
+    "overwrite",
+    "from",
+    "to",
+    "from_branch",
+    "to_branch",
+    "document",
+    "manifest",
+    "locked",
+    "update",
+    "bundle",
+    "overwrite",
+    "source_commit",
+    "md5_a",
+    "md5_b",
+    "variant_a",
+    "variant_b",
+    "layout",
+    "version",
+    "help",
+];
+
+/// Where an effective option value came from
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Environment variable
+    Env(String),
+    /// Config file
+    File(PathBuf),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A parsed config file
+struct ConfigFile {
+    path: PathBuf,
+    table: toml::Table,
+}
+
+impl ConfigFile {
+    /// Load a config file, failing loudly if it exists but is malformed
+    fn load(path: PathBuf) -> io::Result<Self> {
+        let content = fs::read_to_string(&path)?;
+        let table = content.parse::<toml::Table>().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse config file {}: {}", path.display(), e),
+            )
+        })?;
+
+        for key in table.keys() {
+            if !OPTIONS.iter().any(|option| option.key == key) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown key '{}' in config file {}", key, path.display()),
+                ));
+            }
+        }
+
+        Ok(Self { path, table })
+    }
+
+    /// A list option: an array of strings, or a single string
+    fn get_list(&self, key: &str) -> Option<io::Result<Vec<String>>> {
+        let value = self.table.get(key)?;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Invalid value '{}' for {} (from {}): expected a list of strings",
+                    value,
+                    key,
+                    self.path.display()
+                ),
+            )
+        };
+        Some(match value {
+            toml::Value::String(s) => Ok(vec![s.clone()]),
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| item.as_str().map(String::from).ok_or_else(invalid))
+                .collect(),
+            _ => Err(invalid()),
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.table.get(key).map(|value| match value {
+            toml::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+    }
+}
+
+/// The environment and config file layers below the command line
+pub struct Config {
+    project: Option<ConfigFile>,
+    user: Option<ConfigFile>,
+}
+
+impl Config {
+    /// Discover and parse the project and user config files
+    pub fn load() -> io::Result<Self> {
+        let project = find_project_file()?.map(ConfigFile::load).transpose()?;
+        let user = user_file()
+            .filter(|path| path.is_file())
+            .map(ConfigFile::load)
+            .transpose()?;
+        Ok(Self { project, user })
+    }
+
+    /// The project config file, if one was found
+    pub fn project_path(&self) -> Option<&Path> {
+        self.project.as_ref().map(|file| file.path.as_path())
+    }
+
+    /// The user config file, if it exists
+    pub fn user_path(&self) -> Option<&Path> {
+        self.user.as_ref().map(|file| file.path.as_path())
+    }
+
+    /// Look up an option in the environment and config files
+    pub fn lookup(&self, key: &str) -> Option<(String, Source)> {
+        let var = env_var(key);
+        if let Ok(value) = env::var(&var) {
+            return Some((value, Source::Env(var)));
+        }
+        [&self.project, &self.user]
+            .into_iter()
+            .flatten()
+            .find_map(|file| {
+                file.get(key)
+                    .map(|value| (value, Source::File(file.path.clone())))
+            })
+    }
+
+    /// [`Config::lookup`], with the value of a secret such as `token`
+    /// replaced by `***`, for showing the configuration
+    pub fn shown(&self, key: &str) -> Option<(String, Source)> {
+        let secret = is_secret(key);
+        self.lookup(key).map(|(value, source)| {
+            let value = if secret { "***".to_string() } else { value };
+            (value, source)
+        })
+    }
+
+    /// Resolve an option, keeping the command-line value when given
+    pub fn layer<T>(&self, key: &str, cli: Option<T>) -> io::Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        if cli.is_some() {
+            return Ok(cli);
+        }
+        self.lookup(key)
+            .map(|(value, source)| {
+                value.parse::<T>().map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid value '{}' for {} (from {}): {}",
+                            value, key, source, e
+                        ),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Resolve a list option; values given on the command line replace the
+    /// lower layers rather than adding to them
+    ///
+    /// The environment variable holds the values separated by commas.
+    pub fn list(&self, key: &str, cli: Vec<String>) -> io::Result<Vec<String>> {
+        if !cli.is_empty() {
+            return Ok(cli);
+        }
+        if let Ok(value) = env::var(env_var(key)) {
+            return Ok(value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect());
+        }
+        [&self.project, &self.user]
+            .into_iter()
+            .flatten()
+            .find_map(|file| file.get_list(key))
+            .unwrap_or(Ok(Vec::new()))
+    }
+
+    /// Resolve a boolean flag; a flag given on the command line always wins
+    pub fn flag(&self, key: &str, cli: bool) -> io::Result<bool> {
+        if cli {
+            return Ok(true);
+        }
+        match self.lookup(key) {
+            None => Ok(false),
+            Some((value, source)) => match value.to_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(true),
+                "0" | "false" | "no" | "off" | "" => Ok(false),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid value '{}' for {} (from {}): expected true or false",
+                        value, key, source
+                    ),
+                )),
+            },
+        }
+    }
+
+    /// Every layered option with its effective value and origin, as printed
+    /// by `config show`
+    pub fn describe(&self) -> String {
+        let mut out = String::from("Config files:\n");
+        match &self.project {
+            Some(file) => out += &format!("  project: {}\n", file.path.display()),
+            None => out += &format!("  project: none ({} not found)\n", PROJECT_FILE_NAME),
+        }
+        match (&self.user, user_file()) {
+            (Some(file), _) => out += &format!("  user: {}\n", file.path.display()),
+            (None, Some(path)) => out += &format!("  user: none ({} not found)\n", path.display()),
+            (None, None) => out += "  user: none (home directory unknown)\n",
+        }
+        out.push('\n');
+
+        for option in OPTIONS {
+            match self.shown(option.key) {
+                Some((value, source)) => {
+                    out += &format!("{} = {}  # from {}\n", option.key, value, source)
+                }
+                None => out += &format!("{}  # not set ({})\n", option.key, option.help),
+            }
+        }
+        out
+    }
+}
+
+/// Options whose values `config show` hides
+const SECRETS: &[&str] = &["token"];
+
+/// Whether the option `key` holds a secret such as `token`
+pub fn is_secret(key: &str) -> bool {
+    SECRETS.contains(&key)
+}
+
+/// Environment variable for an option key
+pub fn env_var(key: &str) -> String {
+    format!("BITCACHE_{}", key.to_uppercase())
+}
+
+/// Command-line flag for an option key
+pub fn flag_name(key: &str) -> String {
+    format!("--{}", key.replace('_', "-"))
+}
+
+/// Error for a required option that no layer provided
+pub fn missing(key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "Missing required option {}: pass the flag, set {}, or add `{} = ...` to {} or the user config file",
+            flag_name(key),
+            env_var(key),
+            key,
+            PROJECT_FILE_NAME
+        ),
+    )
+}
+
+/// Unwrap a layered option that must be present
+pub fn require<'a, T>(value: &'a Option<T>, key: &str) -> io::Result<&'a T> {
+    value.as_ref().ok_or_else(|| missing(key))
+}
+
+/// Find `.bitcache.toml` in the current directory or its nearest parent
+fn find_project_file() -> io::Result<Option<PathBuf>> {
+    let cwd = env::current_dir()?;
+    Ok(cwd
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE_NAME))
+        .find(|path| path.is_file()))
+}
+
+/// Location of the user config file
+fn user_file() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))?;
+    Some(config_home.join("bitcache").join("config.toml"))
+}
+
+/// The user's home directory
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
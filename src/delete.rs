@@ -0,0 +1,216 @@
+//! Removing entries from the repository.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::BitcacheError;
+use crate::fsutil;
+use crate::git::PushOutcome;
+use crate::layout::MetadataFile;
+use crate::progress::{self, status, warning, Event};
+use crate::publish::push_backoff;
+use crate::storage;
+use crate::trash::{self, TrashedEntry};
+use crate::{cancel, Context, HashAlgo, MetadataEntry, Remote};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::thread;
+
+/// Which entry to delete
+#[derive(Debug, Clone)]
+pub struct DeleteOptions {
+    /// Hash of the source file, the key of the entry
+    pub md5: String,
+    /// [`MetadataEntry::name`] of the variant to delete; without it a source
+    /// with several fails with [`BitcacheError::AmbiguousVariant`]
+    pub variant: Option<String>,
+    /// Also remove the bitstream from the repository, unless another entry
+    /// still uses it; otherwise it stays behind as an orphan. Implies
+    /// [`DeleteOptions::hard`]
+    pub purge_binary: bool,
+    /// Remove the entry for good instead of moving it and its bitstream to
+    /// the trash
+    pub hard: bool,
+}
+
+impl DeleteOptions {
+    /// Move the entry for `md5` to the trash
+    pub fn new(md5: impl Into<String>) -> Self {
+        Self {
+            md5: md5.into(),
+            variant: None,
+            purge_binary: false,
+            hard: false,
+        }
+    }
+}
+
+/// A deleted entry
+#[derive(Debug, Clone, Serialize)]
+pub struct Deleted {
+    /// The entry as it was in the repository
+    #[serde(flatten)]
+    pub entry: MetadataEntry,
+    /// Whether the bitstream was removed too
+    pub purged: bool,
+    /// The entry as kept in the trash; `None` with [`DeleteOptions::hard`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trashed: Option<TrashedEntry>,
+}
+
+/// Remove the entry for a source hash from the repository
+///
+/// Returns `None` when the repository has no entry for it, or none of
+/// [`DeleteOptions::variant`]. Unless
+/// [`DeleteOptions::hard`], the entry and its bitstream move to the trash,
+/// from where [`crate::restore`] puts them back until
+/// [`Context::trash_retention`] runs out. The entry is also dropped from the
+/// local artifact store, so `get` stops serving it.
+///
+/// ```no_run
+/// use bitcache::{Context, DeleteOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let mut opts = DeleteOptions::new("d3699e851d7f4fde53ee37c037408af7");
+/// opts.purge_binary = true;
+/// if bitcache::delete(&remote, &opts, &Context::default())?.is_none() {
+///     println!("nothing to delete");
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn delete(remote: &Remote, opts: &DeleteOptions, ctx: &Context) -> io::Result<Option<Deleted>> {
+    delete_in(None, remote, opts, ctx)
+}
+
+/// [`delete`], in the clone kept by `pool` if given
+pub(crate) fn delete_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &DeleteOptions,
+    ctx: &Context,
+) -> io::Result<Option<Deleted>> {
+    let _entered = ctx.enter();
+    let md5 = &opts.md5;
+    storage::check(remote)?;
+    status!("Deleting entry for MD5: {}", md5);
+
+    let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    let mut metadata = metadata_file.load_or_new()?;
+    let Some(entry) = metadata.select(md5, opts.variant.as_deref())?.cloned() else {
+        return Ok(None);
+    };
+    let label = entry
+        .hash_algo
+        .parse::<HashAlgo>()
+        .map_or("hash", HashAlgo::label);
+    let message = format!("Delete bitstream for source {}: {}", label, entry.label());
+
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    let hard = opts.hard || opts.purge_binary;
+    let mut purged = false;
+    let mut trashed = None;
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(push_backoff(attempt));
+            progress::emit(Event::Retry {
+                attempt,
+                attempts,
+                reason: &rejection,
+            });
+            status!(
+                "Push rejected, merging with the new remote head (attempt {} of {})",
+                attempt,
+                attempts
+            );
+            storage::of(remote).reset(repo_dir, auth)?;
+            metadata = metadata_file.load()?;
+            match metadata.lookup(md5, entry.name()) {
+                None => {
+                    status!("Entry was already deleted by a concurrent run");
+                    break;
+                }
+                Some(current) if *current != entry => {
+                    return Err(io::Error::other(format!(
+                        "The entry for MD5 {} was republished while deleting it; run delete again to delete the new entry",
+                        md5
+                    )));
+                }
+                Some(_) => {}
+            }
+        }
+
+        metadata.remove_entry(md5, entry.name());
+        let mut written = Vec::new();
+        purged = false;
+        if !hard {
+            trashed = Some(trash::trash_entry(
+                repo_dir,
+                &mut metadata,
+                entry.clone(),
+                &mut written,
+            )?);
+        } else if opts.purge_binary {
+            let shared = metadata
+                .iter()
+                .any(|other| other.binary_path == entry.binary_path);
+            if shared {
+                warning!(
+                    "keeping {}, another entry uses the same bitstream",
+                    entry.binary_path
+                );
+            } else {
+                match fs::remove_file(entry.stored_in(repo_dir)?) {
+                    Ok(()) => {
+                        fsutil::remove_empty_parents(repo_dir, &entry.binary_path);
+                        written.push(entry.binary_path.clone());
+                        purged = true;
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        warning!(
+                            "{} is already missing from the repository",
+                            entry.binary_path
+                        )
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        trash::expire(repo_dir, &mut metadata, ctx, &mut written)?;
+
+        cancel::check()?;
+        status!("Updating metadata...");
+        written.extend(metadata_file.save(&metadata, ctx.paranoid)?);
+        let written: Vec<&str> = written.iter().map(String::as_str).collect();
+
+        status!("Committing and pushing changes...");
+        if !storage::of(remote).commit(repo_dir, &written, &message)? {
+            status!("No changes to commit");
+            break;
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => break,
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+
+    if let Some(store) = ctx.artifact_store(remote) {
+        if let Err(e) = store.remove(md5) {
+            warning!(
+                "could not remove the entry from the local artifact cache: {}",
+                e
+            );
+        }
+    }
+    Ok(Some(Deleted {
+        entry,
+        purged,
+        trashed,
+    }))
+}
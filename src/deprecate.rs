@@ -0,0 +1,145 @@
+//! Marking entries whose bitstreams should no longer be used.
+
+use crate::checkout::ClonePool;
+use crate::error::BitcacheError;
+use crate::progress::{status, warning};
+use crate::trash;
+use crate::{Context, MetadataEntry, Remote};
+use std::io;
+
+/// Which entry [`deprecate`] marks, and why
+#[derive(Debug, Clone)]
+pub struct DeprecateOptions {
+    /// Hash of the source file, the key of the entry
+    pub md5: String,
+    /// [`MetadataEntry::name`] of the variant to mark; without it a source
+    /// with several fails with [`BitcacheError::AmbiguousVariant`]
+    pub variant: Option<String>,
+    /// Why the bitstream should no longer be used
+    pub reason: Option<String>,
+    /// Hash of the source whose entry to use instead, which must have one
+    pub replacement: Option<String>,
+    /// Clear the deprecation instead, with its reason and replacement
+    pub undo: bool,
+}
+
+impl DeprecateOptions {
+    /// Deprecate the entry of `md5`, giving `reason`
+    pub fn new(md5: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            md5: md5.into(),
+            variant: None,
+            reason: Some(reason.into()),
+            replacement: None,
+            undo: false,
+        }
+    }
+}
+
+/// Mark an entry as deprecated, keeping it and its bitstream
+///
+/// For a bitstream found to be faulty after publishing, when consumers should
+/// stop using it but the evidence should stay. [`crate::get`] warns about a
+/// deprecated entry, naming the replacement if there is one, and refuses it
+/// with [`crate::GetOptions::refuse_deprecated`]. The entry must exist, or
+/// this fails with [`BitcacheError::NotFound`]. Returns the entry as it is now, or `None`
+/// when it already was like that.
+///
+/// The commit moves the remote on, so copies in the local artifact cache of
+/// other machines are no longer served and their next get sees the
+/// deprecation.
+///
+/// ```no_run
+/// use bitcache::{Context, DeprecateOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let mut opts = DeprecateOptions::new("d3699e851d7f4fde53ee37c037408af7", "timing violation");
+/// opts.replacement = Some("0f343b0931126a20f133d67c2b018a3b".to_string());
+/// bitcache::deprecate(&remote, &opts, &Context::default())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn deprecate(
+    remote: &Remote,
+    opts: &DeprecateOptions,
+    ctx: &Context,
+) -> io::Result<Option<MetadataEntry>> {
+    deprecate_in(None, remote, opts, ctx)
+}
+
+/// [`deprecate`], in the clone kept by `pool` if given
+pub(crate) fn deprecate_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &DeprecateOptions,
+    ctx: &Context,
+) -> io::Result<Option<MetadataEntry>> {
+    let _entered = ctx.enter();
+    if let Some(replacement) = opts.replacement.as_deref().filter(|_| !opts.undo) {
+        if replacement == opts.md5 {
+            return Err(BitcacheError::InvalidArgument {
+                flag: "--replacement",
+                value: replacement.to_string(),
+                reason: "an entry cannot replace itself".to_string(),
+            }
+            .into());
+        }
+    }
+    let md5 = &opts.md5;
+    let label = match &opts.variant {
+        Some(variant) => format!("{} ({})", md5, variant),
+        None => md5.clone(),
+    };
+    let message = if opts.undo {
+        status!("Clearing the deprecation of MD5: {}", label);
+        format!("Clear the deprecation of bitstream for MD5: {}", label)
+    } else {
+        status!("Deprecating entry for MD5: {}", label);
+        format!("Deprecate bitstream for MD5: {}", label)
+    };
+
+    let changed = trash::change(pool, remote, ctx, &message, |_, metadata, _| {
+        let Some(existing) = metadata.select(md5, opts.variant.as_deref())? else {
+            return Err(BitcacheError::NotFound { md5: md5.clone() }.into());
+        };
+        let mut entry = existing.clone();
+        if opts.undo {
+            entry.deprecated = false;
+            entry.deprecation_reason = None;
+            entry.replacement = None;
+        } else {
+            if let Some(replacement) = &opts.replacement {
+                let targets = metadata.variants(replacement);
+                if targets.is_empty() {
+                    return Err(BitcacheError::InvalidArgument {
+                        flag: "--replacement",
+                        value: replacement.clone(),
+                        reason: "the repository has no entry for it".to_string(),
+                    }
+                    .into());
+                }
+                if targets.iter().all(|target| target.deprecated) {
+                    warning!("the replacement {} is deprecated too", replacement);
+                }
+            }
+            entry.deprecated = true;
+            entry.deprecation_reason = opts.reason.clone();
+            entry.replacement = opts.replacement.clone();
+        }
+        if entry == *existing {
+            return Ok(None);
+        }
+        metadata.insert_entry(entry.clone());
+        Ok(Some(entry))
+    })?;
+
+    // This machine's cached copy would keep serving the entry as it was
+    if let (Some(entry), Some(store)) = (&changed, ctx.artifact_store(remote)) {
+        if let Err(e) = store.remove(&entry.md5) {
+            warning!(
+                "could not remove the entry from the local artifact cache: {}",
+                e
+            );
+        }
+    }
+    Ok(changed)
+}
@@ -0,0 +1,253 @@
+//! Comparing two entries and their bitstreams.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::BitcacheError;
+use crate::get;
+use crate::progress::status;
+use crate::storage;
+use crate::{cancel, compress, Compression, Context, MetadataEntry, Remote};
+use chrono::DateTime;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Size of the read buffer of each side of a bitstream comparison
+const COMPARE_BUFFER: usize = 64 * 1024;
+
+/// Which two entries [`diff`] compares
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Hash of the source of the first entry
+    pub md5_a: String,
+    /// Variant of the first entry; may be left out when its source has only
+    /// one
+    pub variant_a: Option<String>,
+    /// Hash of the source of the second entry
+    pub md5_b: String,
+    /// Variant of the second entry
+    pub variant_b: Option<String>,
+}
+
+impl DiffOptions {
+    /// Compare the entries of `md5_a` and `md5_b`
+    pub fn new(md5_a: impl Into<String>, md5_b: impl Into<String>) -> Self {
+        Self {
+            md5_a: md5_a.into(),
+            variant_a: None,
+            md5_b: md5_b.into(),
+            variant_b: None,
+        }
+    }
+}
+
+/// A field the two entries of a [`diff`] disagree on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    /// Name of the field as in the metadata file; `tags.KEY` for a tag
+    pub field: String,
+    /// Value in the first entry, `None` when it lacks the field
+    pub a: Option<String>,
+    /// Value in the second entry, `None` when it lacks the field
+    pub b: Option<String>,
+}
+
+/// How the bitstreams of the two entries of a [`diff`] compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "result")]
+#[non_exhaustive]
+pub enum BinaryComparison {
+    /// The bitstreams have the same bytes
+    Identical,
+    /// The bitstreams differ; `offset` is that of the first byte that
+    /// differs, or the size of the shorter one when it is a prefix of the
+    /// other
+    Differ { offset: u64 },
+}
+
+/// The outcome of a [`diff`]
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryDiff {
+    /// The first entry
+    pub a: MetadataEntry,
+    /// The second entry
+    pub b: MetadataEntry,
+    /// Every field the entries disagree on, by name
+    pub changes: Vec<FieldChange>,
+    /// Seconds the second entry was published after the first, negative
+    /// when before; `None` if a timestamp can't be read
+    pub timestamp_delta: Option<i64>,
+    /// Size in bytes of the first bitstream, decompressed if stored
+    /// compressed
+    pub size_a: u64,
+    /// Size in bytes of the second bitstream, decompressed if stored
+    /// compressed
+    pub size_b: u64,
+    /// How the bitstreams compare
+    pub binaries: BinaryComparison,
+}
+
+impl EntryDiff {
+    /// Whether the two entries have the same bitstream
+    pub fn identical(&self) -> bool {
+        self.binaries == BinaryComparison::Identical
+    }
+}
+
+/// Compare two entries field by field and their bitstreams byte by byte
+///
+/// Both entries are picked like [`crate::get`] picks them, and a hash the
+/// repository has no entry for fails with [`BitcacheError::NotFound`].
+/// Compressed bitstreams are compared as [`crate::get`] would save them,
+/// decompressed. The bitstreams are read side by side through a small
+/// buffer, so neither is loaded whole.
+///
+/// ```no_run
+/// use bitcache::{Context, DiffOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = DiffOptions::new(
+///     "d3699e851d7f4fde53ee37c037408af7",
+///     "401b30e3b8b5d629635a5c613cdb7919",
+/// );
+/// let diff = bitcache::diff(&remote, &opts, &Context::default())?;
+/// for change in &diff.changes {
+///     println!("{}: {:?} -> {:?}", change.field, change.a, change.b);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn diff(remote: &Remote, opts: &DiffOptions, ctx: &Context) -> io::Result<EntryDiff> {
+    diff_in(None, remote, opts, ctx)
+}
+
+/// [`diff`], in the clone kept by `pool` if given
+pub(crate) fn diff_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &DiffOptions,
+    ctx: &Context,
+) -> io::Result<EntryDiff> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    status!("Comparing MD5 {} with {}", opts.md5_a, opts.md5_b);
+    let checkout = checkout::checkout_sparse(pool, remote, ctx)?;
+    let repo_dir = checkout.dir();
+    let find = |md5: &str, variant: Option<&str>| -> io::Result<MetadataEntry> {
+        let Some(entry) = get::find_entry(&checkout, remote, md5, variant, &BTreeMap::new())?
+        else {
+            let md5 = match variant {
+                Some(variant) => format!("{} ({})", md5, variant),
+                None => md5.to_string(),
+            };
+            return Err(BitcacheError::NotFound { md5 }.into());
+        };
+        entry.check_path()?;
+        checkout.include(&entry.binary_path, remote)?;
+        Ok(entry)
+    };
+    let a = find(&opts.md5_a, opts.variant_a.as_deref())?;
+    let b = find(&opts.md5_b, opts.variant_b.as_deref())?;
+
+    let scratch = ctx.temp_dir()?;
+    let bitstream_a = readable(repo_dir, &a, &scratch.path().join("a"))?;
+    let bitstream_b = readable(repo_dir, &b, &scratch.path().join("b"))?;
+    let size_a = fs::metadata(&bitstream_a)?.len();
+    let size_b = fs::metadata(&bitstream_b)?.len();
+    let binaries = match first_difference(&bitstream_a, &bitstream_b)? {
+        None => BinaryComparison::Identical,
+        Some(offset) => BinaryComparison::Differ { offset },
+    };
+
+    let timestamp_delta = match (
+        DateTime::parse_from_rfc3339(&a.timestamp),
+        DateTime::parse_from_rfc3339(&b.timestamp),
+    ) {
+        (Ok(time_a), Ok(time_b)) => Some((time_b - time_a).num_seconds()),
+        _ => None,
+    };
+    Ok(EntryDiff {
+        changes: changes(&a, &b)?,
+        a,
+        b,
+        timestamp_delta,
+        size_a,
+        size_b,
+        binaries,
+    })
+}
+
+/// The bitstream of `entry` as [`crate::get`] would save it: the file in
+/// the clone, or decompressed to `scratch` when stored compressed
+fn readable(repo_dir: &Path, entry: &MetadataEntry, scratch: &Path) -> io::Result<PathBuf> {
+    let stored = entry.stored_in(repo_dir)?;
+    if !stored.is_file() {
+        return Err(BitcacheError::MissingBinary {
+            binary_path: entry.binary_path.clone(),
+        }
+        .into());
+    }
+    match entry.compression()? {
+        Compression::None => Ok(stored),
+        compression => {
+            compress::decompress(compression, &stored, scratch)?;
+            Ok(scratch.to_path_buf())
+        }
+    }
+}
+
+/// Offset of the first byte at which the files `a` and `b` differ, `None`
+/// when they are the same
+fn first_difference(a: &Path, b: &Path) -> io::Result<Option<u64>> {
+    let mut a = BufReader::with_capacity(COMPARE_BUFFER, fs::File::open(a)?);
+    let mut b = BufReader::with_capacity(COMPARE_BUFFER, fs::File::open(b)?);
+    let mut offset = 0u64;
+    loop {
+        cancel::check()?;
+        let (chunk_a, chunk_b) = (a.fill_buf()?, b.fill_buf()?);
+        if chunk_a.is_empty() || chunk_b.is_empty() {
+            return Ok((chunk_a.len() != chunk_b.len()).then_some(offset));
+        }
+        let len = chunk_a.len().min(chunk_b.len());
+        if let Some(i) = (0..len).find(|&i| chunk_a[i] != chunk_b[i]) {
+            return Ok(Some(offset + i as u64));
+        }
+        a.consume(len);
+        b.consume(len);
+        offset += len as u64;
+    }
+}
+
+/// Fields `a` and `b` disagree on, with their tags compared one by one
+fn changes(a: &MetadataEntry, b: &MetadataEntry) -> io::Result<Vec<FieldChange>> {
+    let fields = |entry: &MetadataEntry| -> io::Result<Map<String, Value>> {
+        let Value::Object(mut fields) = serde_json::to_value(entry)? else {
+            unreachable!("metadata entries serialize to objects");
+        };
+        // One field per tag, so a changed tag doesn't show the others
+        if let Some(Value::Object(tags)) = fields.remove("tags") {
+            for (key, value) in tags {
+                fields.insert(format!("tags.{}", key), value);
+            }
+        }
+        Ok(fields)
+    };
+    let (fields_a, fields_b) = (fields(a)?, fields(b)?);
+    let text = |value: &Value| match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    };
+    let mut names: Vec<&String> = fields_a.keys().chain(fields_b.keys()).collect();
+    names.sort();
+    names.dedup();
+    Ok(names
+        .into_iter()
+        .filter(|name| fields_a.get(*name) != fields_b.get(*name))
+        .map(|name| FieldChange {
+            field: name.clone(),
+            a: fields_a.get(name).map(text),
+            b: fields_b.get(name).map(text),
+        })
+        .collect())
+}
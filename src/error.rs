@@ -0,0 +1,474 @@
+//! Typed errors for callers that need to tell failures apart.
+//!
+//! Operations return [`std::io::Result`] like the rest of the crate. Where a
+//! failure has a kind a caller can act on (a miss, a git or authentication
+//! failure, a conflicting publish, a rejected path), the `io::Error` carries
+//! a [`BitcacheError`] that [`BitcacheError::of`] gets back out. Every other
+//! error is a plain `io::Error` from the filesystem.
+//!
+//! Messages include their cause, so printing the top-level error is enough;
+//! [`std::error::Error::source`] still exposes the cause to programs. URLs
+//! in messages have their credentials replaced with `***`, and tokens given
+//! as [`crate::Auth::Token`] never reach an error at all.
+
+use crate::cancel;
+use crate::human::format_duration;
+use crate::MetadataEntry;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Result of every fallible bitcache function
+pub type Result<T> = io::Result<T>;
+
+/// Exit status of the CLI when the repository could not be reached or a
+/// transfer timed out, see [`BitcacheError::exit_code`]
+pub const EXIT_UNREACHABLE: i32 = 4;
+
+/// A failure a caller may want to handle on its own
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum BitcacheError {
+    /// The repository has no entry for this source MD5
+    NotFound { md5: String },
+    /// Several bitstreams were published for the source MD5 and none was
+    /// named to pick one
+    AmbiguousVariant { md5: String, variants: Vec<String> },
+    /// The repository has no metadata file, so nothing was ever published
+    MissingMetadata,
+    /// The metadata names a bitstream that is not in the repository
+    MissingBinary { binary_path: String },
+    /// The metadata file is not valid JSON of the expected shape
+    Metadata { source: serde_json::Error },
+    /// The metadata file has a schema version that cannot be migrated from
+    Migration { found: u32, reason: String },
+    /// A metadata entry points outside the repository
+    UnsafeMetadataPath {
+        md5: String,
+        binary_path: String,
+        reason: String,
+    },
+    /// An option has a value that can't be used
+    InvalidArgument {
+        flag: &'static str,
+        value: String,
+        reason: String,
+    },
+    /// The path a bitstream would be published to is not allowed
+    InvalidPath { path: String, reason: String },
+    /// The path a bitstream would be published to is already in use
+    PathTaken { path: String, reason: String },
+    /// There is no `git` executable to run
+    GitNotFound,
+    /// A git command failed
+    Git { action: String, stderr: String },
+    /// The remote refused the credentials, or none were available
+    Auth { action: String, stderr: String },
+    /// The host of the remote could not be resolved or refused the
+    /// connection
+    Unreachable { action: String, stderr: String },
+    /// A clone, fetch or push ran longer than [`crate::Context::timeout`]
+    /// and was killed
+    Timeout { action: String, elapsed: Duration },
+    /// Another run held the lock of a cached clone or local repository for
+    /// longer than [`crate::Context::lock_timeout`]
+    LockTimeout {
+        what: String,
+        /// Process ID the holder recorded in the lock file, if it did
+        holder: Option<u32>,
+        waited: Duration,
+    },
+    /// The lock of a cached clone or local repository is held, but the run
+    /// that recorded itself as its holder is no longer running
+    StaleLock {
+        what: String,
+        holder: u32,
+        path: PathBuf,
+    },
+    /// Other publishers kept moving the remote until publish gave up
+    PushRejected { attempts: u32, stderr: String },
+    /// The source file given to get hashes differently from the entry
+    SourceMismatch {
+        path: PathBuf,
+        hash_algo: &'static str,
+        expected: String,
+        actual: String,
+    },
+    /// The file get was told to save to already exists
+    OutputExists { path: PathBuf },
+    /// The hash and variant already have an entry publish won't replace:
+    /// one storing a different bitstream without
+    /// [`crate::PublishOptions::force`], or any with
+    /// [`crate::PublishOptions::fail_if_exists`], which `forcible` is false for
+    AlreadyPublished {
+        entry: Box<MetadataEntry>,
+        forcible: bool,
+    },
+    /// A retrieved bitstream does not hash to the MD5 recorded when it was
+    /// published; the saved copy has been removed
+    CorruptBinary {
+        binary_path: String,
+        expected: String,
+        actual: String,
+    },
+    /// The entry was deprecated and get was told to refuse such entries
+    Deprecated {
+        md5: String,
+        reason: Option<String>,
+        /// Hash of the source whose entry to use instead
+        replacement: Option<String>,
+    },
+    /// A concurrent publish changed the entry for the same MD5
+    Conflict {
+        md5: String,
+        /// The entry on the remote now, `None` if it was removed
+        remote: Option<Box<MetadataEntry>>,
+        /// The entry this publish wanted to write
+        ours: Box<MetadataEntry>,
+    },
+    /// A file could not be read or written
+    Io { context: String, source: io::Error },
+    /// The operation was cancelled, see [`crate::cancel`]
+    Interrupted,
+}
+
+impl BitcacheError {
+    /// The typed error carried by `e`, if it has one
+    pub fn of(e: &io::Error) -> Option<&BitcacheError> {
+        e.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+
+    /// The `io::ErrorKind` of the `io::Error` carrying this error
+    pub fn kind(&self) -> io::ErrorKind {
+        use io::ErrorKind;
+        match self {
+            BitcacheError::NotFound { .. }
+            | BitcacheError::MissingMetadata
+            | BitcacheError::MissingBinary { .. }
+            | BitcacheError::GitNotFound => ErrorKind::NotFound,
+            BitcacheError::Metadata { .. }
+            | BitcacheError::Migration { .. }
+            | BitcacheError::UnsafeMetadataPath { .. }
+            | BitcacheError::SourceMismatch { .. }
+            | BitcacheError::Deprecated { .. }
+            | BitcacheError::CorruptBinary { .. } => ErrorKind::InvalidData,
+            BitcacheError::AmbiguousVariant { .. }
+            | BitcacheError::InvalidArgument { .. }
+            | BitcacheError::InvalidPath { .. } => ErrorKind::InvalidInput,
+            BitcacheError::PathTaken { .. }
+            | BitcacheError::OutputExists { .. }
+            | BitcacheError::AlreadyPublished { .. } => ErrorKind::AlreadyExists,
+            BitcacheError::Auth { .. } => ErrorKind::PermissionDenied,
+            BitcacheError::Unreachable { .. } => ErrorKind::ConnectionRefused,
+            BitcacheError::Timeout { .. } | BitcacheError::LockTimeout { .. } => {
+                ErrorKind::TimedOut
+            }
+            BitcacheError::Git { .. }
+            | BitcacheError::PushRejected { .. }
+            | BitcacheError::StaleLock { .. }
+            | BitcacheError::Conflict { .. } => ErrorKind::Other,
+            BitcacheError::Io { source, .. } => source.kind(),
+            BitcacheError::Interrupted => ErrorKind::Interrupted,
+        }
+    }
+
+    /// Whether running the same operation again may succeed
+    ///
+    /// True for network trouble and for remotes that were busy with other
+    /// publishers; false when something has to change first.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BitcacheError::Git { stderr, .. } => is_network_failure(stderr),
+            BitcacheError::PushRejected { .. }
+            | BitcacheError::Unreachable { .. }
+            | BitcacheError::Timeout { .. }
+            | BitcacheError::LockTimeout { .. } => true,
+            BitcacheError::Io { source, .. } => kind_is_retryable(source.kind()),
+            _ => false,
+        }
+    }
+
+    /// Process exit status the CLI uses for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BitcacheError::Interrupted => cancel::EXIT_INTERRUPTED,
+            BitcacheError::Unreachable { .. } | BitcacheError::Timeout { .. } => EXIT_UNREACHABLE,
+            _ => 1,
+        }
+    }
+}
+
+/// [`BitcacheError::is_retryable`] for any error an operation returned
+pub fn is_retryable(e: &io::Error) -> bool {
+    match BitcacheError::of(e) {
+        Some(typed) => typed.is_retryable(),
+        None => kind_is_retryable(e.kind()),
+    }
+}
+
+/// [`BitcacheError::exit_code`] for any error an operation returned
+pub fn exit_code(e: &io::Error) -> i32 {
+    BitcacheError::of(e).map_or(1, BitcacheError::exit_code)
+}
+
+fn kind_is_retryable(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+/// Whether git's stderr describes a failure to reach the remote
+fn is_network_failure(stderr: &str) -> bool {
+    const PATTERNS: &[&str] = &[
+        "Could not resolve host",
+        "Connection timed out",
+        "Connection refused",
+        "Connection reset",
+        "Operation timed out",
+        "early EOF",
+        "The remote end hung up unexpectedly",
+        "Failed to connect",
+        "HTTP 5",
+        "returned error: 5",
+    ];
+    PATTERNS.iter().any(|pattern| stderr.contains(pattern))
+}
+
+/// Whether git's stderr says the host of the remote could not be reached
+/// at all, as opposed to a transfer that broke off
+pub(crate) fn is_unreachable(stderr: &str) -> bool {
+    const PATTERNS: &[&str] = &[
+        "Could not resolve host",
+        "Could not resolve hostname",
+        "Connection refused",
+        "Network is unreachable",
+        "No route to host",
+        "Failed to connect",
+    ];
+    PATTERNS.iter().any(|pattern| stderr.contains(pattern))
+}
+
+/// Whether git's stderr describes rejected or missing credentials
+pub(crate) fn is_auth_failure(stderr: &str) -> bool {
+    const PATTERNS: &[&str] = &[
+        "Permission denied",
+        "Authentication failed",
+        "could not read Username",
+        "could not read Password",
+        "returned error: 401",
+        "returned error: 403",
+        "Host key verification failed",
+    ];
+    PATTERNS.iter().any(|pattern| stderr.contains(pattern))
+}
+
+/// Replace the user and password in every URL in `text` with `***`
+pub(crate) fn redact(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(scheme_end) = rest.find("://") {
+        let (before, after) = rest.split_at(scheme_end + 3);
+        redacted.push_str(before);
+        let authority_end = after
+            .find(|c: char| c == '/' || c.is_whitespace() || c == '\'' || c == '"')
+            .unwrap_or(after.len());
+        match after[..authority_end].rfind('@') {
+            Some(at) => {
+                redacted.push_str("***");
+                rest = &after[at..];
+            }
+            None => rest = after,
+        }
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+impl fmt::Display for BitcacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BitcacheError::NotFound { md5 } => write!(f, "No binary found for MD5: {}", md5),
+            BitcacheError::AmbiguousVariant { md5, variants } => write!(
+                f,
+                "Several bitstreams were published for MD5 {}; pick one with --variant: {}",
+                md5,
+                variants.join(", ")
+            ),
+            BitcacheError::MissingMetadata => f.write_str("Metadata file not found in repository"),
+            BitcacheError::MissingBinary { binary_path } => {
+                write!(f, "Binary file not found: {}", binary_path)
+            }
+            BitcacheError::Metadata { source } => write!(
+                f,
+                "Failed to parse metadata: {}; bitcache repair salvages the entries that can still be read",
+                source
+            ),
+            BitcacheError::Migration { found, reason } => write!(
+                f,
+                "Cannot read metadata with schema version {}: {}",
+                found, reason
+            ),
+            BitcacheError::UnsafeMetadataPath {
+                md5,
+                binary_path,
+                reason,
+            } => write!(
+                f,
+                "Refusing unsafe binary_path '{}' in metadata entry {}: {}",
+                binary_path, md5, reason
+            ),
+            BitcacheError::InvalidArgument {
+                flag,
+                value,
+                reason,
+            } => write!(f, "Invalid {} '{}': {}", flag, redact(value), reason),
+            BitcacheError::InvalidPath { path, reason }
+            | BitcacheError::PathTaken { path, reason } => {
+                write!(f, "Cannot publish to '{}': {}", path, reason)
+            }
+            BitcacheError::GitNotFound => write!(
+                f,
+                "git executable not found in PATH; install git, or keep the repository in a plain directory with --backend dir"
+            ),
+            BitcacheError::Git { action, stderr } | BitcacheError::Auth { action, stderr } => {
+                write!(f, "Failed to {}: {}", action, redact(stderr))
+            }
+            BitcacheError::Unreachable { action, stderr } => write!(
+                f,
+                "Repository unreachable, failed to {}: {}",
+                action,
+                redact(stderr)
+            ),
+            BitcacheError::Timeout { action, elapsed } => write!(
+                f,
+                "Failed to {}: git was killed after {} without finishing; raise --timeout if the repository is large",
+                action,
+                format_duration(*elapsed)
+            ),
+            BitcacheError::LockTimeout {
+                what,
+                holder,
+                waited,
+            } => {
+                write!(
+                    f,
+                    "Gave up after {} waiting for another bitcache run to finish with {}",
+                    format_duration(*waited),
+                    what
+                )?;
+                if let Some(pid) = holder {
+                    write!(f, " (process {})", pid)?;
+                }
+                write!(f, "; raise --lock-timeout if its operations take longer")
+            }
+            BitcacheError::StaleLock { what, holder, path } => write!(
+                f,
+                "The lock of {} is held, but process {} that took it is no longer running; a process it started may still have {} open",
+                what,
+                holder,
+                path.display()
+            ),
+            BitcacheError::PushRejected { attempts, stderr } => write!(
+                f,
+                "Failed to push after {} attempt{}, the remote kept moving: {}",
+                attempts,
+                if *attempts == 1 { "" } else { "s" },
+                redact(stderr)
+            ),
+            BitcacheError::SourceMismatch {
+                path,
+                hash_algo,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Source file {} does not match the entry: its {} is {}, the entry was published for {}",
+                path.display(),
+                hash_algo,
+                actual,
+                expected
+            ),
+            BitcacheError::OutputExists { path } => write!(
+                f,
+                "{} already exists; pass --force to replace it",
+                path.display()
+            ),
+            BitcacheError::AlreadyPublished { entry, forcible } => {
+                write!(
+                    f,
+                    "MD5 {} already has a bitstream published at {}",
+                    entry.label(),
+                    entry.binary_path
+                )?;
+                if *forcible {
+                    write!(f, "; pass --force to replace the entry, or use update to replace only its bitstream")?;
+                }
+                Ok(())
+            }
+            BitcacheError::CorruptBinary {
+                binary_path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Retrieved bitstream {} is corrupt: it has MD5 {}, but {} was recorded when it was published; the saved copy was removed",
+                binary_path, actual, expected
+            ),
+            BitcacheError::Deprecated {
+                md5,
+                reason,
+                replacement,
+            } => {
+                write!(f, "The bitstream for MD5 {} is deprecated", md5)?;
+                if let Some(reason) = reason {
+                    write!(f, ": {}", reason)?;
+                }
+                match replacement {
+                    Some(replacement) => write!(f, "; get --md5 {} instead", replacement),
+                    None => Ok(()),
+                }
+            }
+            BitcacheError::Conflict { md5, remote, ours } => {
+                let json = |entry: &MetadataEntry| {
+                    serde_json::to_string_pretty(entry).map_err(|_| fmt::Error)
+                };
+                let remote = match remote {
+                    Some(entry) => json(entry)?,
+                    None => "(removed)".to_string(),
+                };
+                write!(
+                    f,
+                    "Conflicting concurrent publish for MD5 {}: the remote entry changed while publishing\nRemote entry:\n{}\nThis publish:\n{}",
+                    md5,
+                    remote,
+                    json(ours)?
+                )
+            }
+            BitcacheError::Io { context, source } => write!(f, "{}: {}", context, source),
+            BitcacheError::Interrupted => f.write_str("Interrupted by user"),
+        }
+    }
+}
+
+impl Error for BitcacheError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BitcacheError::Metadata { source } => Some(source),
+            BitcacheError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<BitcacheError> for io::Error {
+    fn from(e: BitcacheError) -> Self {
+        io::Error::new(e.kind(), e)
+    }
+}
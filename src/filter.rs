@@ -0,0 +1,909 @@
+//! Expressions picking metadata entries, as `list --filter` takes them.
+//!
+//! An expression compares fields of an entry with values and combines the
+//! comparisons:
+//!
+//! ```text
+//! tags.board = zedboard and age > 60d and size > 50M
+//! source ~ "*.vhd" or not (deprecated or branch = main)
+//! ```
+//!
+//! - A field is any member of the entry as the metadata stores it, such as
+//!   `md5`, `source_file`, `timestamp` or `deprecated`, including members
+//!   this version does not know. `tags.KEY` is a tag, and a dotted
+//!   path reaches into any other object. `age` is the time since the entry
+//!   was published, and `source` and `path` stand for `source_file` and
+//!   `binary_path`.
+//! - The operators are `=`, `!=`, `<`, `<=`, `>`, `>=`, `~`, which matches a
+//!   glob with `*`, `?` and `[...]`, and `!~`. A field on its own is true
+//!   when the entry has it and it is neither `false` nor empty.
+//! - Values are bare words, or quoted with `"` or `'` when they hold spaces,
+//!   parentheses, operators or `!`. `size` and `original_size` take sizes
+//!   such as `512K` or `1.5G`, `age` durations such as `90s`, `30m`, `12h`,
+//!   `60d` or `2w`, and `timestamp` RFC 3339 times or dates such as
+//!   `2024-01-31`. Other fields compare as numbers when both sides are
+//!   numbers, and as text otherwise.
+//! - `not` binds tighter than `and`, and `and` tighter than `or`; parentheses
+//!   group. `!`, `&&` and `||` may be written instead.
+//!
+//! A comparison on a field the entry lacks is false whatever the operator,
+//! so `not branch = main` picks entries without a branch while
+//! `branch != main` does not.
+//!
+//! ```
+//! use bitcache::filter::Filter;
+//! use bitcache::MetadataEntry;
+//!
+//! let filter: Filter = "source ~ '*.vhd' and not deprecated".parse()?;
+//! let entry = MetadataEntry::new(
+//!     "d3699e851d7f4fde53ee37c037408af7",
+//!     "boards/zedboard/top.bit",
+//!     "top.vhd",
+//!     "2024-01-31T12:00:00Z",
+//! );
+//! assert!(filter.matches(&entry));
+//! # Ok::<(), bitcache::filter::FilterError>(())
+//! ```
+
+use crate::human::ByteSize;
+use crate::MetadataEntry;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A parsed filter expression, see the [module documentation](self)
+///
+/// Displaying it gives the expression back with its grouping spelled out in
+/// parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+/// Why an expression could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    /// Column of the expression the problem is at, counting characters
+    /// from 1
+    pub column: usize,
+    /// What is wrong there
+    pub message: String,
+}
+
+impl FilterError {
+    fn at(column: usize, message: impl Into<String>) -> Self {
+        Self {
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "at column {}: {}", self.column, self.message)
+    }
+}
+
+impl Error for FilterError {}
+
+impl Filter {
+    /// Parse `input`, see the [module documentation](self) for the syntax
+    pub fn parse(input: &str) -> Result<Self, FilterError> {
+        let tokens = lex(input)?;
+        let end = input.chars().count() + 1;
+        if tokens.is_empty() {
+            return Err(FilterError::at(1, "the expression is empty"));
+        }
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            end,
+        };
+        let expr = parser.or()?;
+        if let Some(extra) = parser.peek() {
+            return Err(FilterError::at(
+                extra.column,
+                format!(
+                    "unexpected {}; join conditions with 'and' or 'or'",
+                    extra.token
+                ),
+            ));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Whether `entry` satisfies the expression now
+    pub fn matches(&self, entry: &MetadataEntry) -> bool {
+        self.matches_at(entry, Utc::now())
+    }
+
+    /// Whether `entry` satisfies the expression, taking `now` as the current
+    /// time for `age`
+    pub fn matches_at(&self, entry: &MetadataEntry, now: DateTime<Utc>) -> bool {
+        let mut fields = serde_json::to_value(entry).unwrap_or(Value::Null);
+        // Members left out of the file when they have their default value
+        if let Value::Object(map) = &mut fields {
+            map.insert("hash_algo".into(), entry.hash_algo.clone().into());
+            map.insert("deprecated".into(), entry.deprecated.into());
+        }
+        self.expr.eval(&fields, now)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.expr.fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Present(Field),
+    Compare(Field, Op, Operand),
+}
+
+impl Expr {
+    fn eval(&self, fields: &Value, now: DateTime<Utc>) -> bool {
+        match self {
+            Expr::And(left, right) => left.eval(fields, now) && right.eval(fields, now),
+            Expr::Or(left, right) => left.eval(fields, now) || right.eval(fields, now),
+            Expr::Not(inner) => !inner.eval(fields, now),
+            Expr::Present(field) => field.value(fields, now).is_some_and(|v| truthy(&v)),
+            Expr::Compare(field, op, operand) => field
+                .value(fields, now)
+                .is_some_and(|v| operand.compare(&v, *op)),
+        }
+    }
+
+    /// Write `self` as an operand of `parent`, in parentheses unless it
+    /// binds at least as tightly
+    fn fmt_within(&self, parent: &Expr, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let grouped = matches!(
+            (parent, self),
+            (Expr::Not(_), Expr::And(..) | Expr::Or(..))
+                | (Expr::And(..), Expr::Or(..))
+                | (Expr::Or(..), Expr::And(..))
+        );
+        if grouped {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                left.fmt_within(self, f)?;
+                f.write_str(if matches!(self, Expr::And(..)) {
+                    " and "
+                } else {
+                    " or "
+                })?;
+                right.fmt_within(self, f)
+            }
+            Expr::Not(inner) => {
+                f.write_str("not ")?;
+                inner.fmt_within(self, f)
+            }
+            Expr::Present(field) => write!(f, "{}", field),
+            Expr::Compare(field, op, operand) => {
+                write!(f, "{} {} {}", field, op, quoted(&operand.text))
+            }
+        }
+    }
+}
+
+/// A field of an entry, as the path of members leading to it
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    path: Vec<String>,
+}
+
+/// How a field's values are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Size,
+    Age,
+    Time,
+    Any,
+}
+
+impl Field {
+    fn parse(name: &str, column: usize) -> Result<Self, FilterError> {
+        let mut path: Vec<String> = name.split('.').map(str::to_string).collect();
+        if path.iter().any(String::is_empty) {
+            return Err(FilterError::at(
+                column,
+                format!("'{}' is not a field name", name),
+            ));
+        }
+        match path[0].as_str() {
+            "source" if path.len() == 1 => path[0] = "source_file".to_string(),
+            "path" if path.len() == 1 => path[0] = "binary_path".to_string(),
+            "tag" => path[0] = "tags".to_string(),
+            _ => {}
+        }
+        Ok(Self { path })
+    }
+
+    fn kind(&self) -> Kind {
+        match self.path.as_slice() {
+            [name] if name == "size" || name == "original_size" => Kind::Size,
+            [name] if name == "age" => Kind::Age,
+            [name] if name == "timestamp" => Kind::Time,
+            _ => Kind::Any,
+        }
+    }
+
+    /// The value of the field in `fields`, `None` if the entry lacks it;
+    /// `age` is in seconds
+    fn value(&self, fields: &Value, now: DateTime<Utc>) -> Option<Value> {
+        if self.kind() == Kind::Age {
+            let published =
+                DateTime::parse_from_rfc3339(fields.get("timestamp")?.as_str()?).ok()?;
+            let age = now.signed_duration_since(published).num_milliseconds() as f64 / 1000.0;
+            return Some(age.into());
+        }
+        let mut value = fields;
+        for name in &self.path {
+            value = value.get(name)?;
+        }
+        (!value.is_null()).then(|| value.clone())
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.path.join("."))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Glob,
+    NotGlob,
+}
+
+impl Op {
+    /// Whether a field ordered `ordering` against the value satisfies the
+    /// comparison; the glob operators never do
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Glob | Op::NotGlob => false,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Glob => "~",
+            Op::NotGlob => "!~",
+        })
+    }
+}
+
+/// The value a field is compared with, as written and as the field's kind
+/// reads it
+#[derive(Debug, Clone, PartialEq)]
+struct Operand {
+    text: String,
+    value: Literal,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Size(u64),
+    Age(Duration),
+    Time(DateTime<Utc>),
+    Text,
+}
+
+impl Operand {
+    fn parse(field: &Field, op: Op, text: &str) -> Result<Self, String> {
+        let value = match (field.kind(), op) {
+            (Kind::Age, Op::Glob | Op::NotGlob) => {
+                return Err(format!("'{}' cannot match a duration such as age", op));
+            }
+            (_, Op::Glob | Op::NotGlob) | (Kind::Any, _) => Literal::Text,
+            (Kind::Size, _) => Literal::Size(text.parse::<ByteSize>()?.0),
+            (Kind::Age, _) => Literal::Age(parse_duration(text)?),
+            (Kind::Time, _) => Literal::Time(parse_time(text)?),
+        };
+        Ok(Self {
+            text: text.to_string(),
+            value,
+        })
+    }
+
+    /// Whether a field with value `field` satisfies `op` against `self`
+    fn compare(&self, field: &Value, op: Op) -> bool {
+        match (&self.value, op) {
+            (_, Op::Glob) => glob_matches(&self.text, &text_of(field)),
+            (_, Op::NotGlob) => !glob_matches(&self.text, &text_of(field)),
+            (Literal::Size(size), _) => field.as_u64().is_some_and(|n| op.holds(n.cmp(size))),
+            (Literal::Age(age), _) => field
+                .as_f64()
+                .and_then(|seconds| seconds.partial_cmp(&age.as_secs_f64()))
+                .is_some_and(|ordering| op.holds(ordering)),
+            (Literal::Time(time), _) => field
+                .as_str()
+                .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                .is_some_and(|t| op.holds(t.with_timezone(&Utc).cmp(time))),
+            (Literal::Text, _) => match field {
+                Value::Number(n) => match (n.as_f64(), self.text.parse::<f64>()) {
+                    (Some(a), Ok(b)) => a.partial_cmp(&b).is_some_and(|o| op.holds(o)),
+                    _ => op.holds(n.to_string().cmp(&self.text)),
+                },
+                Value::Bool(b) => match op {
+                    Op::Eq => self.text.eq_ignore_ascii_case(&b.to_string()),
+                    Op::Ne => !self.text.eq_ignore_ascii_case(&b.to_string()),
+                    _ => false,
+                },
+                Value::String(s) => op.holds(s.as_str().cmp(self.text.as_str())),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Whether a field on its own counts as true
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(members) => !members.is_empty(),
+        Value::Number(_) => true,
+    }
+}
+
+/// A field's value as the text a glob is matched against
+fn text_of(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// A duration such as `90s`, `30m`, `12h`, `60d` or `2w`
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || format!("'{}' is not a duration such as 30m, 12h or 60d", text);
+    let unit_start = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .ok_or_else(invalid)?;
+    let (number, unit) = text.split_at(unit_start);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let seconds = match unit.to_ascii_lowercase().as_str() {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        "w" => 7.0 * 86400.0,
+        _ => return Err(invalid()),
+    };
+    Duration::try_from_secs_f64(number * seconds).map_err(|_| invalid())
+}
+
+/// An RFC 3339 time, or a date taken as its midnight in UTC
+fn parse_time(text: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+        .ok_or_else(|| {
+            format!(
+                "'{}' is not a time such as 2024-01-31 or 2024-01-31T12:00:00Z",
+                text
+            )
+        })
+}
+
+/// `text` as a bare word if it reads back as one, quoted otherwise
+fn quoted(text: &str) -> String {
+    if !text.is_empty() && text.chars().all(is_word_char) && keyword(text).is_none() {
+        return text.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Op(Op),
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => f.write_str("'('"),
+            Token::Close => f.write_str("')'"),
+            Token::And => f.write_str("'and'"),
+            Token::Or => f.write_str("'or'"),
+            Token::Not => f.write_str("'not'"),
+            Token::Op(op) => write!(f, "'{}'", op),
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Quoted(text) => write!(f, "{}", quoted(text)),
+        }
+    }
+}
+
+/// A token and the column it starts at
+#[derive(Debug, Clone)]
+struct Lexed {
+    token: Token,
+    column: usize,
+}
+
+/// Characters of a bare word, which field names and unquoted values are
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || "_.-*?[]/:+@%,".contains(c)
+}
+
+fn keyword(word: &str) -> Option<Token> {
+    match word.to_ascii_lowercase().as_str() {
+        "and" => Some(Token::And),
+        "or" => Some(Token::Or),
+        "not" => Some(Token::Not),
+        _ => None,
+    }
+}
+
+fn lex(input: &str) -> Result<Vec<Lexed>, FilterError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('=', _) => (Token::Op(Op::Eq), 1),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('!', Some('~')) => (Token::Op(Op::NotGlob), 2),
+            ('!', _) => (Token::Not, 1),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('~', _) => (Token::Op(Op::Glob), 1),
+            ('"' | '\'', _) => {
+                let mut text = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err(FilterError::at(column, "the quote is never closed")),
+                        Some(&quote) if quote == c => break,
+                        Some('\\') if chars.get(j + 1).is_some_and(|&e| e == c || e == '\\') => {
+                            text.push(chars[j + 1]);
+                            j += 2;
+                        }
+                        Some(&other) => {
+                            text.push(other);
+                            j += 1;
+                        }
+                    }
+                }
+                (Token::Quoted(text), j + 1 - i)
+            }
+            (c, _) if is_word_char(c) => {
+                let len = chars[i..].iter().take_while(|&&c| is_word_char(c)).count();
+                let word: String = chars[i..i + len].iter().collect();
+                (keyword(&word).unwrap_or(Token::Word(word)), len)
+            }
+            (c, _) => {
+                return Err(FilterError::at(
+                    column,
+                    format!("unexpected '{}'; quote values holding it", c),
+                ));
+            }
+        };
+        tokens.push(Lexed { token, column });
+        i += len;
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, one method per level of precedence
+struct Parser<'a> {
+    tokens: &'a [Lexed],
+    pos: usize,
+    /// Column just past the input, where running out of tokens is reported
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Lexed> {
+        self.tokens.get(self.pos)
+    }
+
+    fn column(&self) -> usize {
+        self.peek().map_or(self.end, |lexed| lexed.column)
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.and()?;
+        while self.peek().is_some_and(|lexed| lexed.token == Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.not()?;
+        while self.peek().is_some_and(|lexed| lexed.token == Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, FilterError> {
+        if self.peek().is_some_and(|lexed| lexed.token == Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, FilterError> {
+        let Some(lexed) = self.peek().cloned() else {
+            return Err(FilterError::at(
+                self.end,
+                "the expression ends where a field name or '(' was expected",
+            ));
+        };
+        self.pos += 1;
+        let name = match lexed.token {
+            Token::Open => {
+                let expr = self.or()?;
+                if self.peek().is_some_and(|lexed| lexed.token == Token::Close) {
+                    self.pos += 1;
+                    return Ok(expr);
+                }
+                return Err(FilterError::at(
+                    self.column(),
+                    format!("expected ')' to close the '(' at column {}", lexed.column),
+                ));
+            }
+            Token::Word(name) => name,
+            other => {
+                return Err(FilterError::at(
+                    lexed.column,
+                    format!("expected a field name or '(', found {}", other),
+                ));
+            }
+        };
+        let field = Field::parse(&name, lexed.column)?;
+        let Some(Token::Op(op)) = self.peek().map(|lexed| lexed.token.clone()) else {
+            return Ok(Expr::Present(field));
+        };
+        self.pos += 1;
+        let column = self.column();
+        let text = match self.peek().map(|lexed| &lexed.token) {
+            Some(Token::Word(text) | Token::Quoted(text)) => text.clone(),
+            _ => {
+                return Err(FilterError::at(
+                    column,
+                    format!("expected a value to compare {} with", field),
+                ));
+            }
+        };
+        self.pos += 1;
+        let operand = Operand::parse(&field, op, &text)
+            .map_err(|message| FilterError::at(column, message))?;
+        Ok(Expr::Compare(field, op, operand))
+    }
+}
+
+/// Whether `name` matches the shell glob `pattern`: `*` matches any run of
+/// characters, `?` any one character and `[...]` one of a set such as
+/// `[abc]`, `[a-z]` or `[!0-9]`
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where to resume after the last `*`: its position and the name
+    // position it currently stands for
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, end)) = class_matches(&pattern[p..], name[n]) {
+                    if matched {
+                        p += end;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == '[' {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(&c) if c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            Some((star, matched)) => {
+                p = star + 1;
+                n = matched + 1;
+                backtrack = Some((star, n));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match `c` against the `[...]` class at the start of `pattern`, returning
+/// whether it matched and the length of the class; `None` if the class is
+/// not closed
+fn class_matches(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = pattern.get(i)?;
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&end| end != ']') {
+            matched |= (start..=pattern[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= start == c;
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(fields: Value) -> MetadataEntry {
+        let mut base = json!({
+            "md5": "d3699e851d7f4fde53ee37c037408af7",
+            "binary_path": "boards/zedboard/top.bit",
+            "source_file": "top.vhd",
+            "timestamp": "2024-01-31T12:00:00Z",
+        });
+        if let (Value::Object(base), Value::Object(fields)) = (&mut base, fields) {
+            base.extend(fields);
+        }
+        serde_json::from_value(base).unwrap()
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2024-04-30T12:00:00Z".parse().unwrap()
+    }
+
+    /// Whether `expr` picks an entry with the extra `fields`
+    fn picks(expr: &str, fields: Value) -> bool {
+        let filter = Filter::parse(expr).unwrap_or_else(|e| panic!("{}: {}", expr, e));
+        filter.matches_at(&entry(fields), now())
+    }
+
+    #[test]
+    fn compares_fields() {
+        let cases = [
+            ("md5 = d3699e851d7f4fde53ee37c037408af7", true),
+            ("source = top.vhd", true),
+            ("source_file != top.vhd", false),
+            ("path ~ 'boards/*/top.bit'", true),
+            ("path !~ 'boards/*'", false),
+            ("source ~ 'top.v?d'", true),
+            ("source ~ '[!t]*'", false),
+            ("hash_algo = md5", true),
+            ("timestamp >= 2024-01-31", true),
+            ("timestamp < 2024-01-31T12:00:00Z", false),
+            ("timestamp > '2024-01-31T13:00:00+02:00'", true),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(picks(expr, json!({})), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn reads_age_sizes_and_unknown_members() {
+        let fields = json!({
+            "size": 64 * 1024 * 1024,
+            "tags": {"board": "zedboard"},
+            "build": {"jobs": 8, "tool": "vivado"},
+        });
+        let cases = [
+            ("age > 60d", true),
+            ("age > 13w", false),
+            ("age <= 90d", true),
+            ("size > 50M", true),
+            ("size < 1G and size >= 64M", true),
+            ("size = 64M", true),
+            ("tags.board = zedboard", true),
+            ("tag.board ~ 'zed*'", true),
+            ("tags.board = kc705", false),
+            ("build.jobs > 4", true),
+            ("build.jobs > 10", false),
+            ("build.tool = vivado", true),
+            ("tags.board = zedboard and age > 60d and size > 50M", true),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(picks(expr, fields.clone()), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn missing_fields_never_compare() {
+        let cases = [
+            ("branch = main", false),
+            ("branch != main", false),
+            ("not branch = main", true),
+            ("size < 1G", false),
+            ("tags.board !~ '*'", false),
+            ("branch", false),
+        ];
+        for (expr, expected) in cases {
+            assert_eq!(picks(expr, json!({})), expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn fields_on_their_own_test_truth() {
+        assert!(!picks("deprecated", json!({})));
+        assert!(picks("not deprecated", json!({})));
+        assert!(picks("deprecated", json!({"deprecated": true})));
+        assert!(picks("deprecated = true", json!({"deprecated": true})));
+        assert!(!picks("notes", json!({"notes": ""})));
+        assert!(picks("notes", json!({"notes": "timing fails at 200 MHz"})));
+        assert!(!picks("tags", json!({"tags": {}})));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_and_and_than_or() {
+        let grouping = [
+            ("a or b and c", "a or (b and c)"),
+            ("(a or b) and c", "(a or b) and c"),
+            ("not a and b", "not a and b"),
+            ("not (a and b)", "not (a and b)"),
+            ("!a && b || c", "(not a and b) or c"),
+            ("a and (b or not (c or d))", "a and (b or not (c or d))"),
+            ("source ~ '*.vhd'", "source_file ~ *.vhd"),
+            ("source = 'two words'", "source_file = \"two words\""),
+            ("size > 50M", "size > 50M"),
+        ];
+        for (expr, shown) in grouping {
+            assert_eq!(Filter::parse(expr).unwrap().to_string(), shown, "{}", expr);
+        }
+        // Displaying an expression gives back one that parses the same
+        let filter = Filter::parse("not (tags.board = 'two words' or x) and y").unwrap();
+        assert_eq!(Filter::parse(&filter.to_string()).unwrap(), filter);
+
+        assert!(picks(
+            "md5 = x or source = top.vhd and not deprecated",
+            json!({})
+        ));
+        assert!(!picks(
+            "(md5 = x or source = top.vhd) and deprecated",
+            json!({})
+        ));
+    }
+
+    #[test]
+    fn errors_point_at_their_column() {
+        let cases = [
+            ("", 1),
+            ("source =", 9),
+            ("source = top.vhd and", 21),
+            ("(source = top.vhd", 18),
+            ("source = top.vhd)", 17),
+            ("size > lots", 8),
+            ("age ~ 60d", 7),
+            ("age > 60 days", 7),
+            ("timestamp > yesterday", 13),
+            ("source = top.vhd md5 = x", 18),
+            ("tags..board = x", 1),
+        ];
+        for (expr, column) in cases {
+            let error = Filter::parse(expr).expect_err(expr);
+            assert_eq!(error.column, column, "{}: {}", expr, error);
+        }
+    }
+
+    #[test]
+    fn globs_match_like_a_shell() {
+        assert!(glob_matches("top*.vhd", "top_level.vhd"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "aXbYbZc"));
+        assert!(!glob_matches("a*b*c", "aXbYbZ"));
+        assert!(glob_matches("[a-c]?", "bz"));
+        assert!(!glob_matches("[!a-c]?", "bz"));
+        assert!(glob_matches("[]]", "]"));
+        assert!(glob_matches("[x", "[x"));
+        assert!(!glob_matches("top.vhd", "top.vhdl"));
+    }
+}
@@ -0,0 +1,642 @@
+//! File system helpers shared by publish and get.
+
+use crate::cancel;
+use crate::heartbeat::Heartbeat;
+use crate::human::format_size;
+use crate::paths;
+use crate::progress::{self, Event, Phase};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Size of the buffer used when streaming file contents, which bounds the
+/// memory hashing and copying need
+pub const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Copy `src` to `dst`, returning the number of bytes copied
+///
+/// Unlike `fs::copy` this polls for Ctrl-C between chunks, and on any failure
+/// (including cancellation) the partially written destination is removed.
+/// Sparse sources are copied hole-preserving; otherwise the kernel does the
+/// copy where `copy_file_range` is available, with a buffered loop as the
+/// fallback. Progress is reported through the heartbeat either way.
+pub fn copy_file(src: &Path, dst: &Path) -> io::Result<u64> {
+    let result = copy_contents(src, dst);
+    if result.is_err() {
+        let _ = fs::remove_file(dst);
+    }
+    result
+}
+
+/// How [`link_or_copy`] placed the destination file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// `dst` is a hard link to `src` (same inode)
+    Hardlink,
+    /// `dst` shares `src`'s data blocks copy-on-write
+    Reflink,
+    /// The contents were copied byte by byte
+    Copy,
+}
+
+impl fmt::Display for Placement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Placement::Hardlink => "hardlink",
+            Placement::Reflink => "reflink",
+            Placement::Copy => "copy",
+        })
+    }
+}
+
+/// Make `dst` have the same contents as `src` with as little I/O as possible
+///
+/// Tries a hard link first, then a copy-on-write clone where the file system
+/// supports one, then falls back to [`copy_file`]. Only use this when `dst` is
+/// never modified in place, since a hard link shares the inode with `src`. An
+/// existing `dst` is replaced.
+pub fn link_or_copy(src: &Path, dst: &Path) -> io::Result<(u64, Placement)> {
+    cancel::check()?;
+    let len = fs::metadata(src)?.len();
+    match fs::remove_file(dst) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    if fs::hard_link(src, dst).is_ok() {
+        if same_file(src, dst)? {
+            return Ok((len, Placement::Hardlink));
+        }
+        fs::remove_file(dst)?;
+    }
+
+    clone_or_copy(src, dst)
+}
+
+/// Make `dst` an independent copy of `src`, sharing data blocks copy-on-write
+/// where the file system supports it
+///
+/// Unlike [`link_or_copy`] the result can safely be modified or kept after
+/// `src` changes. An existing `dst` is replaced.
+pub fn clone_or_copy(src: &Path, dst: &Path) -> io::Result<(u64, Placement)> {
+    cancel::check()?;
+    let len = fs::metadata(src)?.len();
+    if reflink(src, dst).is_ok() {
+        return Ok((len, Placement::Reflink));
+    }
+
+    copy_file(src, dst).map(|copied| (copied, Placement::Copy))
+}
+
+/// Whether two paths refer to the same inode on the same device
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+/// Without inode numbers a successful `hard_link` is trusted as is
+#[cfg(not(unix))]
+fn same_file(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Permission bits of `path`, `None` on platforms without unix modes
+#[cfg(unix)]
+pub fn file_mode(path: &Path) -> io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Some(fs::metadata(path)?.permissions().mode() & 0o777))
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_path: &Path) -> io::Result<Option<u32>> {
+    Ok(None)
+}
+
+/// Give `path` the permission bits `mode`, as [`file_mode`] reads them
+#[cfg(unix)]
+pub fn set_file_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+/// Without unix modes there is nothing to give back
+#[cfg(not(unix))]
+pub fn set_file_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+/// Clone `src` into a new `dst` with the `FICLONE` ioctl (Btrfs, XFS, ...)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let reader = File::open(src)?;
+    let writer = File::create(dst)?;
+    // SAFETY: both descriptors are open for the duration of the call
+    let result = unsafe { libc::ioctl(writer.as_raw_fd(), libc::FICLONE, reader.as_raw_fd()) };
+    if result != 0 {
+        let error = io::Error::last_os_error();
+        drop(writer);
+        let _ = fs::remove_file(dst);
+        return Err(error);
+    }
+    let permissions = reader
+        .metadata()
+        .and_then(|metadata| fs::set_permissions(dst, metadata.permissions()));
+    if permissions.is_err() {
+        let _ = fs::remove_file(dst);
+    }
+    permissions
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+fn copy_contents(src: &Path, dst: &Path) -> io::Result<u64> {
+    let mut reader = File::open(src)?;
+    let src_metadata = reader.metadata()?;
+    let mut writer = File::create(dst)?;
+    let mut progress = Progress {
+        heartbeat: Heartbeat::start(Phase::Copying),
+        copied: 0,
+        total: src_metadata.len(),
+    };
+
+    if is_sparse(&src_metadata) {
+        copy_sparse(&mut reader, &mut writer, &mut progress)?;
+    } else if !copy_in_kernel(&reader, &writer, &mut progress)? {
+        copy_buffered(&mut reader, &mut writer, &mut progress)?;
+    }
+
+    writer.flush()?;
+    fs::set_permissions(dst, src_metadata.permissions())?;
+    Ok(progress.copied)
+}
+
+/// Bytes copied so far, reported through the heartbeat
+struct Progress {
+    heartbeat: Heartbeat,
+    copied: u64,
+    total: u64,
+}
+
+impl Progress {
+    fn advance(&mut self, bytes: u64) {
+        self.copied += bytes;
+        self.heartbeat.tick_bytes(self.copied, Some(self.total));
+    }
+}
+
+/// Read the next chunk, retrying reads interrupted by a signal
+fn read_chunk(reader: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        cancel::check()?;
+        match reader.read(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+fn copy_buffered(reader: &mut File, writer: &mut File, progress: &mut Progress) -> io::Result<()> {
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        let n = read_chunk(reader, &mut buffer)?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer.write_all(&buffer[..n])?;
+        progress.advance(n as u64);
+    }
+}
+
+/// Like [`copy_buffered`], but seeks over all-zero chunks so holes in the
+/// source stay holes in the destination
+fn copy_sparse(reader: &mut File, writer: &mut File, progress: &mut Progress) -> io::Result<()> {
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    loop {
+        let n = read_chunk(reader, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        if buffer[..n].iter().all(|&byte| byte == 0) {
+            writer.seek(SeekFrom::Current(n as i64))?;
+        } else {
+            writer.write_all(&buffer[..n])?;
+        }
+        progress.advance(n as u64);
+    }
+    // A trailing hole only exists once the length is set
+    writer.set_len(progress.copied)
+}
+
+/// Whether the file occupies fewer blocks on disk than its length implies
+#[cfg(unix)]
+fn is_sparse(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.blocks().saturating_mul(512) < metadata.len()
+}
+
+#[cfg(not(unix))]
+fn is_sparse(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Bytes handed to the kernel per `copy_file_range` call; small enough that
+/// Ctrl-C and heartbeats stay responsive
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const KERNEL_COPY_CHUNK: usize = 64 * 1024 * 1024;
+
+/// Copy with `copy_file_range`, which avoids moving the data through user
+/// space and lets NFS and similar file systems copy server-side
+///
+/// Returns `false` without copying anything if the kernel or file system
+/// doesn't support it, so the caller can fall back to a buffered copy.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn copy_in_kernel(reader: &File, writer: &File, progress: &mut Progress) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    loop {
+        cancel::check()?;
+        // SAFETY: both descriptors are open for the duration of the call and
+        // null offsets make the kernel use and advance the file positions
+        let n = unsafe {
+            libc::copy_file_range(
+                reader.as_raw_fd(),
+                std::ptr::null_mut(),
+                writer.as_raw_fd(),
+                std::ptr::null_mut(),
+                KERNEL_COPY_CHUNK,
+                0,
+            )
+        };
+        match n {
+            0 => return Ok(true),
+            n if n > 0 => progress.advance(n as u64),
+            _ => {
+                let error = io::Error::last_os_error();
+                let unsupported = matches!(
+                    error.raw_os_error(),
+                    Some(
+                        libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP | libc::EPERM
+                    )
+                );
+                match error.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    _ if unsupported && progress.copied == 0 => return Ok(false),
+                    _ => return Err(error),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn copy_in_kernel(_reader: &File, _writer: &File, _progress: &mut Progress) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Suffix of the temporary files used by [`atomic_write`]
+const ATOMIC_TEMP_SUFFIX: &str = ".tmp";
+
+/// Replace `path` with `contents` so readers see either the old or the new file
+///
+/// The data goes to a temporary file in the same directory, which is fsynced
+/// and renamed over the target; the directory is fsynced afterwards so the
+/// rename itself survives a crash. On failure the previous file is untouched.
+pub fn atomic_write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = parent_dir(path);
+    let mut temp = tempfile::Builder::new()
+        .prefix(&atomic_temp_prefix(path)?)
+        .suffix(ATOMIC_TEMP_SUFFIX)
+        .tempfile_in(dir)?;
+    temp.write_all(contents)?;
+    temp.as_file().sync_all()?;
+    // Keep the target's permissions instead of the temp file's 0600
+    if let Ok(metadata) = fs::metadata(path) {
+        temp.as_file().set_permissions(metadata.permissions())?;
+    }
+    temp.persist(path).map_err(|e| e.error)?;
+    sync_dir(dir)
+}
+
+/// Remove the directories of the repository path `path` under `root` that
+/// are left empty, innermost first, stopping at the first that is not
+///
+/// git doesn't track directories, but a clone or a local repository would
+/// keep them after the files in them were removed.
+pub fn remove_empty_parents(root: &Path, path: &str) {
+    let mut dir = path;
+    while let Some((parent, _)) = dir.rsplit_once('/') {
+        if parent.is_empty() || fs::remove_dir(paths::long_path(&root.join(parent))).is_err() {
+            break;
+        }
+        dir = parent;
+    }
+}
+
+/// Remove temporary files that an interrupted [`atomic_write`] left behind
+///
+/// Returns the number of files removed. Failures are ignored: a leftover temp
+/// file is harmless to readers of `path` itself.
+pub fn remove_stale_temp_files(path: &Path) -> usize {
+    let Ok(prefix) = atomic_temp_prefix(path) else {
+        return 0;
+    };
+    let Ok(entries) = fs::read_dir(parent_dir(path)) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(&prefix) && name.ends_with(ATOMIC_TEMP_SUFFIX)
+        })
+        .filter(|entry| fs::remove_file(entry.path()).is_ok())
+        .count()
+}
+
+fn atomic_temp_prefix(path: &Path) -> io::Result<String> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Not a file path: {}", path.display()),
+        )
+    })?;
+    Ok(format!(".{}.", name.to_string_lossy()))
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Flush a file's contents and its directory entry to disk
+pub fn sync_path(path: &Path) -> io::Result<()> {
+    // Unix can flush through a read-only descriptor, which also works for
+    // read-only files; Windows needs write access
+    let file = if cfg!(unix) {
+        File::open(path)?
+    } else {
+        fs::OpenOptions::new().write(true).open(path)?
+    };
+    file.sync_all()?;
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+/// Flush a directory entry change (create, rename) to disk
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened for syncing on this platform; the rename is
+/// still atomic
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// A temporary directory that is removed when dropped, on success, error and
+/// cancellation alike, unless it is kept for debugging
+pub struct ScratchDir {
+    dir: Option<TempDir>,
+    keep: bool,
+}
+
+impl ScratchDir {
+    pub fn path(&self) -> &Path {
+        self.dir
+            .as_ref()
+            .map(TempDir::path)
+            .expect("scratch directory is only taken on drop")
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if self.keep {
+            if let Some(dir) = self.dir.take() {
+                progress::emit(Event::Notice(&format!(
+                    "Kept temporary directory: {}",
+                    dir.keep().display()
+                )));
+            }
+        }
+    }
+}
+
+/// Create a uniquely named temporary directory for clones and staging files
+///
+/// The directory lives under `work_dir` when given, otherwise under the system
+/// temp dir, and is removed when the returned guard is dropped unless `keep`
+/// is set, in which case its path is printed instead.
+pub fn create_temp_dir(work_dir: Option<&Path>, keep: bool) -> io::Result<ScratchDir> {
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("bitcache-");
+    match work_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Cannot create work directory {}: {}", dir.display(), e),
+                )
+            })?;
+            builder.tempdir_in(dir)
+        }
+        None => builder.tempdir(),
+    }
+    .map(|dir| ScratchDir {
+        dir: Some(dir),
+        keep,
+    })
+}
+
+/// Check that files can be created in `dir` by creating and removing one
+///
+/// Permission bits alone don't tell, e.g. on read-only mounts or in sandboxes.
+pub fn check_writable_dir(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(".bitcache-probe-{}", std::process::id()));
+    let create = || {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+    };
+    if let Err(e) = create() {
+        // Left behind by an earlier process that had the same pid
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return Err(e);
+        }
+        fs::remove_file(&probe)?;
+        create()?;
+    }
+    fs::remove_file(&probe)
+}
+
+/// Fail early if `dir` has less than `needed` bytes available
+///
+/// `what` describes the data that needs the space and `hint` is appended to
+/// the error to tell the user how to point bitcache somewhere larger.
+pub fn ensure_free_space(dir: &Path, needed: u64, what: &str, hint: &str) -> io::Result<()> {
+    // Platforms or file systems that can't report free space skip the check
+    let Ok(available) = fs2::available_space(dir) else {
+        return Ok(());
+    };
+
+    if available < needed {
+        return Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "Not enough free space in {} for {}: need about {}, only {} available. {}",
+                dir.display(),
+                what,
+                format_size(needed),
+                format_size(available),
+                hint
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    /// More than two buffers' worth, ending in a partial one
+    fn contents() -> Vec<u8> {
+        (0..2 * COPY_BUFFER_SIZE + 4099)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn copies_a_file_larger_than_the_buffer() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        fs::write(&src, contents())?;
+        assert_eq!(copy_file(&src, &dst)?, contents().len() as u64);
+        assert_eq!(fs::read(&dst)?, contents());
+
+        // The buffered fallback the kernel copy normally stands in for
+        let (mut reader, mut writer) = (File::open(&src)?, File::create(&dst)?);
+        let mut progress = Progress {
+            heartbeat: Heartbeat::start(Phase::Copying),
+            copied: 0,
+            total: contents().len() as u64,
+        };
+        copy_buffered(&mut reader, &mut writer, &mut progress)?;
+        assert_eq!(progress.copied, progress.total);
+        assert_eq!(fs::read(&dst)?, contents());
+        Ok(())
+    }
+
+    #[test]
+    fn a_cancelled_copy_leaves_no_destination() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        fs::write(&src, contents())?;
+        let cancelled = Arc::new(AtomicBool::new(true));
+        let error = cancel::with_token(cancelled, || copy_file(&src, &dst))
+            .expect_err("copied after Ctrl-C");
+        assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+        assert!(!dst.exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_sparse_source_keeps_its_holes_and_its_digest() -> io::Result<()> {
+        use std::os::unix::fs::MetadataExt;
+        let dir = tempfile::tempdir()?;
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        // Data in the middle, with a hole before it and after it
+        let len = 16 * COPY_BUFFER_SIZE as u64;
+        let mut file = File::create(&src)?;
+        file.seek(SeekFrom::Start(len / 2))?;
+        file.write_all(&contents())?;
+        file.set_len(len)?;
+        drop(file);
+        let source = fs::metadata(&src)?;
+        if !is_sparse(&source) {
+            // The file system has no holes to keep
+            return Ok(());
+        }
+
+        assert_eq!(copy_file(&src, &dst)?, len);
+        let copy = fs::metadata(&dst)?;
+        assert_eq!(copy.len(), len);
+        assert!(
+            is_sparse(&copy),
+            "{} of {} blocks",
+            copy.blocks(),
+            len / 512
+        );
+        assert_eq!(crate::compute_md5(&dst)?, crate::compute_md5(&src)?);
+        Ok(())
+    }
+
+    #[test]
+    fn an_atomic_write_replaces_the_file_and_leaves_no_temp_file() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("bitcache_metadata.json");
+        fs::write(&path, "old")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+        }
+        atomic_write(&path, b"new")?;
+        assert_eq!(fs::read(&path)?, b"new");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o644);
+        }
+        assert_eq!(fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn a_failed_atomic_write_keeps_the_old_file() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("bitcache_metadata.json");
+        fs::write(&path, "old")?;
+        // A directory at the target can't be renamed over
+        let blocked = dir.path().join("blocked");
+        fs::create_dir_all(blocked.join("inside"))?;
+        assert!(atomic_write(&blocked, b"new").is_err());
+        assert!(blocked.join("inside").is_dir());
+        assert_eq!(fs::read(&path)?, b"old");
+        assert_eq!(remove_stale_temp_files(&blocked), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn removes_the_temp_files_of_an_interrupted_write() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("bitcache_metadata.json");
+        fs::write(&path, "current")?;
+        fs::write(dir.path().join(".bitcache_metadata.json.a1b2.tmp"), "half")?;
+        fs::write(dir.path().join(".bitcache_metadata.json.c3d4.tmp"), "half")?;
+        fs::write(dir.path().join(".other.json.e5f6.tmp"), "someone else's")?;
+        assert_eq!(remove_stale_temp_files(&path), 2);
+        assert_eq!(fs::read(&path)?, b"current");
+        assert!(dir.path().join(".other.json.e5f6.tmp").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! Finding bitstreams the metadata no longer refers to.
+//!
+//! [`crate::delete`] with `hard`, `verify --fix` and hand edits of the
+//! metadata leave bitstreams behind that no entry names. [`gc`] scans the
+//! directories entries publish into for such orphans, and for entries whose
+//! bitstream is gone. [`crate::prune`] looks for orphans the same way.
+
+use crate::checkout::{self, ClonePool};
+use crate::fsutil;
+use crate::layout::{self, MetadataFile};
+use crate::progress::status;
+use crate::repair::REJECTED_FILE;
+use crate::storage;
+use crate::trash;
+use crate::{cancel, paths, Context, Metadata, MetadataEntry, Remote, METADATA_FILE};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// What [`gc`] does besides scanning
+#[derive(Debug, Clone, Default)]
+pub struct GcOptions {
+    /// Remove the orphaned bitstreams from the repository, and commit and
+    /// push the result
+    pub prune: bool,
+}
+
+impl GcOptions {
+    /// Only scan, as the CLI does without `--prune`
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Result of [`gc`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    /// Number of files scanned
+    pub scanned: usize,
+    /// Repository paths of files no entry refers to, sorted
+    pub orphans: Vec<String>,
+    /// Entries whose bitstream is not in the repository, by MD5
+    pub broken: Vec<MetadataEntry>,
+    /// Whether the orphans were removed from the repository
+    pub pruned: bool,
+}
+
+/// Find the files in the repository that no entry refers to
+///
+/// Every directory an entry's bitstream is in is scanned, including its
+/// subdirectories. Hidden files, the files bitcache keeps at the top of the
+/// repository and the bitstreams of trashed entries left in place are never
+/// orphans. With [`GcOptions::prune`] the orphans are removed in one commit,
+/// along with the trashed entries past [`Context::trash_retention`]. Entries
+/// whose bitstream is missing are only reported; [`crate::verify`] with
+/// `fix` removes them.
+///
+/// ```no_run
+/// use bitcache::{Context, GcOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let report = bitcache::gc(&remote, &GcOptions::new(), &Context::default())?;
+/// for orphan in &report.orphans {
+///     println!("{}", orphan);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn gc(remote: &Remote, opts: &GcOptions, ctx: &Context) -> io::Result<GcReport> {
+    gc_in(None, remote, opts, ctx)
+}
+
+/// [`gc`], in the clone kept by `pool` if given
+pub(crate) fn gc_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &GcOptions,
+    ctx: &Context,
+) -> io::Result<GcReport> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let metadata_path = layout::normalize(&remote.metadata_path);
+    if !opts.prune {
+        let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+        let metadata_file = MetadataFile::of(checkout.dir(), remote);
+        if !metadata_file.exists() {
+            return Ok(GcReport::default());
+        }
+        let metadata = metadata_file.load()?;
+        return scan(checkout.dir(), &metadata, &metadata_path);
+    }
+
+    let mut clean = None;
+    let pruned = trash::change(
+        pool,
+        remote,
+        ctx,
+        "Remove bitstreams no entry refers to",
+        |repo_dir, metadata, written| {
+            // A retry starts over on the new remote head
+            let mut report = scan(repo_dir, metadata, &metadata_path)?;
+            if report.orphans.is_empty() {
+                clean = Some(report);
+                return Ok(None);
+            }
+            cancel::check()?;
+            remove_orphans(repo_dir, &report.orphans, written)?;
+            report.pruned = true;
+            Ok(Some(report))
+        },
+    );
+    Ok(pruned?.or(clean).unwrap_or_default())
+}
+
+/// Compare the files under the entries' directories with `metadata`, kept
+/// at `metadata_path`
+pub(crate) fn scan(
+    repo_dir: &Path,
+    metadata: &Metadata,
+    metadata_path: &str,
+) -> io::Result<GcReport> {
+    // A trashed entry whose bitstream another entry shared, or that was
+    // missing, left it in place
+    let trash = metadata.trash()?;
+    let referenced: BTreeSet<&str> = metadata
+        .iter()
+        .map(|entry| entry.binary_path.as_str())
+        .chain(
+            trash
+                .iter()
+                .filter(|item| item.trash_path.is_none())
+                .map(|item| item.entry.binary_path.as_str()),
+        )
+        .collect();
+
+    // Scanning a directory covers those below it, so skip those
+    let mut dirs: Vec<&str> = Vec::new();
+    let parents: BTreeSet<&str> = referenced
+        .iter()
+        .map(|path| path.rsplit_once('/').map_or("", |(dir, _)| dir))
+        .collect();
+    for dir in parents {
+        let covered = dirs.iter().any(|scanned| {
+            scanned.is_empty()
+                || dir
+                    .strip_prefix(scanned)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        });
+        if !covered {
+            dirs.push(dir);
+        }
+    }
+    status!("Scanning {} directories...", dirs.len());
+
+    let mut files = Vec::new();
+    for dir in dirs {
+        walk(repo_dir, dir, &mut files)?;
+    }
+    let mut report = GcReport {
+        scanned: files.len(),
+        ..GcReport::default()
+    };
+    report.orphans = files
+        .into_iter()
+        .filter(|path| !referenced.contains(path.as_str()) && path != metadata_path)
+        .collect();
+    report.orphans.sort();
+
+    let mut entries: Vec<_> = metadata.iter().collect();
+    entries.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+    report.broken = entries
+        .into_iter()
+        .filter(|entry| !paths::long_path(&repo_dir.join(&entry.binary_path)).is_file())
+        .cloned()
+        .collect();
+    Ok(report)
+}
+
+/// Remove `orphans` from the work tree of `repo_dir`, along with the
+/// directories they leave empty, adding them to `written`
+pub(crate) fn remove_orphans(
+    repo_dir: &Path,
+    orphans: &[String],
+    written: &mut Vec<String>,
+) -> io::Result<()> {
+    status!("Removing {} orphaned files...", orphans.len());
+    for orphan in orphans {
+        match fs::remove_file(paths::long_path(&repo_dir.join(orphan))) {
+            Ok(()) => fsutil::remove_empty_parents(repo_dir, orphan),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        written.push(orphan.clone());
+    }
+    Ok(())
+}
+
+/// Add the repository paths of the files under `dir` to `files`
+pub(crate) fn walk(repo_dir: &Path, dir: &str, files: &mut Vec<String>) -> io::Result<()> {
+    cancel::check()?;
+    let read = match fs::read_dir(paths::long_path(&repo_dir.join(dir))) {
+        Ok(read) => read,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for item in read {
+        let item = item?;
+        // Names are stored on disk as they are in the metadata, already
+        // encoded, see paths::encode_name
+        let name = item.file_name().into_string().map_err(|name| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{}: '{}' is not valid Unicode, so bitcache didn't store it; remove it by hand",
+                    if dir.is_empty() { "." } else { dir },
+                    name.to_string_lossy()
+                ),
+            )
+        })?;
+        if name.starts_with('.')
+            || (dir.is_empty() && (name == METADATA_FILE || name == REJECTED_FILE))
+        {
+            continue;
+        }
+        let path = if dir.is_empty() {
+            name
+        } else {
+            format!("{}/{}", dir, name)
+        };
+        // Never follows symbolic links, which git stores as files
+        if item.file_type()?.is_dir() {
+            walk(repo_dir, &path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
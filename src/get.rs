@@ -0,0 +1,829 @@
+//! Looking up and retrieving published bitstreams.
+
+use crate::checkout::{self, Checkout, ClonePool};
+use crate::compress::{self, Compression};
+use crate::error::BitcacheError;
+use crate::filter::{glob_matches, Filter};
+use crate::layout::MetadataFile;
+use crate::progress::{detail, status, warning};
+use crate::storage;
+use crate::{
+    cancel, compute_md5, compute_source_hash, fsutil, git, metadata, paths, store, verify_written,
+    Context, HashAlgo, MetadataEntry, Remote, SourceWalk,
+};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Which bitstream to retrieve and where to save it
+#[derive(Debug, Clone)]
+pub struct GetOptions {
+    /// MD5 hash of the source file, or its hash with `hash_algo`
+    pub md5: String,
+    /// Algorithm `md5` was computed with: it must then be a digest of that
+    /// algorithm, and `source` is hashed with it. `None` takes `md5` as it
+    /// is and hashes `source` with the algorithm the entry records
+    pub hash_algo: Option<HashAlgo>,
+    /// [`MetadataEntry::name`] of the variant to retrieve, when several were
+    /// published for the source; without it a source with several fails
+    /// with [`BitcacheError::AmbiguousVariant`]
+    pub variant: Option<String>,
+    /// Only consider the bitstreams tagged with every one of these; when
+    /// more than one is left, `variant` must pick among them
+    pub tags: BTreeMap<String, String>,
+    /// Where to save the bitstream: a file path, or a directory to save into,
+    /// which must exist or end in a separator; missing directories are
+    /// created. The current directory if unset
+    pub output: Option<PathBuf>,
+    /// Directory to save into when `output` is unset and the current
+    /// directory is not writable
+    pub output_dir: Option<PathBuf>,
+    /// Serve the bitstream from the local artifact store when it has it
+    ///
+    /// A hit is served only while the remote is still at the commit the
+    /// entry was cached from, which costs an `ls-remote` but no fetch; after
+    /// a [`crate::deprecate`], [`crate::update`] or [`crate::delete`] made
+    /// anywhere, the metadata is read again.
+    pub use_local_cache: bool,
+    /// Flush the saved file and its directory to disk before returning
+    pub sync: bool,
+    /// Local copy of the source file, or a directory of source files; when
+    /// set, its hash must match the entry's before anything is saved
+    pub source: Option<PathBuf>,
+    /// More source files or directories, hashed together with `source` as
+    /// [`crate::compute_source_hash`] does
+    pub extra_sources: Vec<PathBuf>,
+    /// Which files of a source directory are hashed
+    pub source_walk: SourceWalk,
+    /// Replace a file that already exists where the bitstream is saved,
+    /// whether at `output`, in the current directory or in `output_dir`;
+    /// without it such a get fails
+    pub force: bool,
+    /// Fail with [`BitcacheError::Deprecated`] on an entry marked by
+    /// [`crate::deprecate`], instead of only warning about it
+    pub refuse_deprecated: bool,
+    /// Hash the saved bitstream and check it against the MD5 recorded when
+    /// it was published, removing it and failing with
+    /// [`BitcacheError::CorruptBinary`] on a mismatch. Entries published
+    /// before checksums were recorded are saved unchecked.
+    pub verify: bool,
+}
+
+impl GetOptions {
+    /// Retrieve the bitstream published for `md5` into the current
+    /// directory, with the same defaults as the CLI
+    pub fn new(md5: impl Into<String>) -> Self {
+        Self {
+            md5: md5.into(),
+            hash_algo: None,
+            variant: None,
+            tags: BTreeMap::new(),
+            output: None,
+            output_dir: None,
+            use_local_cache: true,
+            sync: false,
+            source: None,
+            extra_sources: Vec::new(),
+            source_walk: SourceWalk::default(),
+            force: false,
+            refuse_deprecated: false,
+            verify: true,
+        }
+    }
+}
+
+/// A retrieved bitstream
+#[derive(Debug, Clone, Serialize)]
+pub struct Retrieved {
+    /// The metadata entry it was published under
+    #[serde(flatten)]
+    pub entry: MetadataEntry,
+    /// Where it was saved
+    pub path: PathBuf,
+    /// Size in bytes
+    pub size: u64,
+    /// Whether it came from the local artifact store instead of the
+    /// repository
+    pub from_cache: bool,
+    /// Whether it was flushed to disk, as [`GetOptions::sync`] asks
+    pub synced: bool,
+}
+
+/// Retrieve the bitstream published for a source MD5
+///
+/// Returns `None` when the repository has no entry for the MD5, or none of
+/// [`GetOptions::variant`].
+///
+/// ```no_run
+/// use bitcache::{Context, GetOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let mut opts = GetOptions::new(bitcache::compute_md5("top.vhd".as_ref())?);
+/// opts.output = Some("build".into());
+/// match bitcache::get(&remote, &opts, &Context::default())? {
+///     Some(retrieved) => println!("saved {}", retrieved.path.display()),
+///     None => println!("not published yet"),
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn get(remote: &Remote, opts: &GetOptions, ctx: &Context) -> io::Result<Option<Retrieved>> {
+    get_in(None, remote, opts, ctx)
+}
+
+/// [`get`], in the clone kept by `pool` if given
+pub(crate) fn get_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &GetOptions,
+    ctx: &Context,
+) -> io::Result<Option<Retrieved>> {
+    let _entered = ctx.enter();
+    let md5 = &opts.md5;
+    let name = opts.variant.as_deref();
+    storage::check(remote)?;
+    if let Some(algo) = opts.hash_algo {
+        algo.check_digest(md5)
+            .map_err(|reason| BitcacheError::InvalidArgument {
+                flag: "--md5",
+                value: md5.clone(),
+                reason,
+            })?;
+    }
+    let destination = Destination::resolve(opts)?;
+    status!(
+        "Retrieving bitstream for {}: {}",
+        opts.hash_algo.unwrap_or_default().label(),
+        md5
+    );
+
+    // Serve from the local artifact store while the remote is still at the
+    // commit the entry was read at, which is asked without fetching; which
+    // variants a tag filter leaves is only known from the metadata
+    let store = if opts.use_local_cache {
+        ctx.artifact_store(remote)
+    } else {
+        None
+    };
+    let cached = store.as_ref().filter(|_| opts.tags.is_empty());
+    if let Some(store) = cached {
+        match store.fetch(md5, name) {
+            Ok(Some(artifact)) if artifact.commit.is_some() => {
+                if git::remote_head(remote)? == artifact.commit {
+                    status!("Found in local artifact cache");
+                    return deliver(
+                        &destination,
+                        opts,
+                        artifact.entry,
+                        &artifact.blob,
+                        Source::Store,
+                        ctx,
+                    )
+                    .map(Some);
+                }
+                detail!("Local artifact cache is behind the repository");
+            }
+            Ok(Some(_)) => detail!("Local artifact cache entry records no commit"),
+            Ok(None) => {}
+            Err(e) if cancel::is_cancelled() => return Err(e),
+            Err(e) => warning!("ignoring local artifact cache: {}", e),
+        }
+    }
+
+    let checkout = checkout::checkout_sparse(pool, remote, ctx)?;
+    let repo_dir = checkout.dir();
+    let Some(entry) = find_entry(&checkout, remote, md5, name, &opts.tags)? else {
+        return Ok(None);
+    };
+    entry.check_path()?;
+    checkout.include(&entry.binary_path, remote)?;
+
+    // Get binary file path
+    let binary_path = entry.stored_in(repo_dir)?;
+    if !binary_path.exists() {
+        return Err(BitcacheError::MissingBinary {
+            binary_path: entry.binary_path,
+        }
+        .into());
+    }
+
+    // Keep a copy for next time; a cache failure never fails the get
+    if let Some(store) = &store {
+        let stored = git::head_commit(repo_dir)
+            .and_then(|commit| store.insert(&entry, name, &binary_path, commit.as_deref()))
+            .and_then(|_| store::enforce_limit(ctx.require_cache_dir()?, ctx.cache_max_size));
+        match stored {
+            Ok(()) => {}
+            Err(e) if cancel::is_cancelled() => return Err(e),
+            Err(e) => warning!("could not update local artifact cache: {}", e),
+        }
+    }
+
+    // A one-off clone is discarded afterwards, so the file can be hardlinked
+    // out of it; a kept clone must not share its files with the caller
+    let source = if checkout.is_pooled() {
+        Source::KeptClone
+    } else {
+        Source::Clone
+    };
+    let delivered = deliver(&destination, opts, entry, &binary_path, source, ctx);
+    // Don't serve the corrupt copy just cached next time
+    if let (Err(e), Some(store)) = (&delivered, &store) {
+        if matches!(
+            BitcacheError::of(e),
+            Some(BitcacheError::CorruptBinary { .. })
+        ) {
+            let _ = store.remove(md5);
+        }
+    }
+    delivered.map(Some)
+}
+
+/// The entry for `md5` and `name` with all of `tags` in `checkout` of
+/// `remote`, picked as [`metadata::select_variant`] picks it
+pub(crate) fn find_entry(
+    checkout: &Checkout,
+    remote: &Remote,
+    md5: &str,
+    name: Option<&str>,
+    tags: &BTreeMap<String, String>,
+) -> io::Result<Option<MetadataEntry>> {
+    let mut variants = MetadataFile::of(checkout.dir(), remote).lookup(md5, checkout, remote)?;
+    variants.retain(|entry| {
+        tags.iter()
+            .all(|(key, value)| entry.tags.get(key) == Some(value))
+    });
+    Ok(metadata::select_variant(&variants, name)?.cloned())
+}
+
+/// Decompress the bitstream at `binary_path` to `dest_path`, replacing
+/// whatever is there, and return its size
+fn decompress_to(
+    compression: Compression,
+    binary_path: &Path,
+    dest_path: &Path,
+) -> io::Result<u64> {
+    match fs::remove_file(dest_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    compress::decompress(compression, binary_path, dest_path)
+}
+
+/// Refuse an entry published for a different source than `opts.source`
+pub(crate) fn check_source(opts: &GetOptions, entry: &MetadataEntry) -> io::Result<()> {
+    let Some(path) = &opts.source else {
+        return Ok(());
+    };
+    let algo = match opts.hash_algo {
+        Some(algo) => algo,
+        None => entry.hash_algo.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Entry {} was hashed with '{}', which this version of bitcache does not support",
+                    entry.md5, entry.hash_algo
+                ),
+            )
+        })?,
+    };
+    let paths: Vec<PathBuf> = std::iter::once(path.clone())
+        .chain(opts.extra_sources.iter().cloned())
+        .collect();
+    let actual =
+        compute_source_hash(&paths, opts.source_walk, algo).map_err(|e| BitcacheError::Io {
+            context: format!("Cannot hash --source {}", path.display()),
+            source: e,
+        })?;
+    if actual != entry.md5 {
+        return Err(BitcacheError::SourceMismatch {
+            path: path.clone(),
+            hash_algo: algo.label(),
+            expected: entry.md5.clone(),
+            actual,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Whether the repository has an entry for a source MD5
+///
+/// ```no_run
+/// use bitcache::{Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let md5 = bitcache::compute_md5("top.vhd".as_ref())?;
+/// if !bitcache::exists(&remote, &md5, &Context::default())? {
+///     println!("{} needs a build", md5);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn exists(remote: &Remote, md5: &str, ctx: &Context) -> io::Result<bool> {
+    exists_in(None, remote, md5, ctx)
+}
+
+/// [`exists`], in the clone kept by `pool` if given
+pub(crate) fn exists_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    md5: &str,
+    ctx: &Context,
+) -> io::Result<bool> {
+    Ok(!find_in(pool, remote, md5, ctx)?.is_empty())
+}
+
+/// The entries published for a source hash, one per name; empty when there
+/// are none, also for a repository nothing was published to yet
+///
+/// Only the metadata is fetched, in a sparse clone, so a repository of large
+/// bitstreams answers as quickly as an empty one.
+///
+/// ```no_run
+/// use bitcache::{Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let md5 = bitcache::compute_md5("top.vhd".as_ref())?;
+/// for entry in bitcache::find(&remote, &md5, &Context::default())? {
+///     println!("{}  {}", entry.name(), entry.binary_path);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn find(remote: &Remote, md5: &str, ctx: &Context) -> io::Result<Vec<MetadataEntry>> {
+    find_in(None, remote, md5, ctx)
+}
+
+/// [`find`], in the clone kept by `pool` if given
+pub(crate) fn find_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    md5: &str,
+    ctx: &Context,
+) -> io::Result<Vec<MetadataEntry>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout_sparse(pool, remote, ctx)?;
+    MetadataFile::of(checkout.dir(), remote).lookup(md5, &checkout, remote)
+}
+
+/// An entry found by [`info`]
+#[derive(Debug, Clone)]
+pub struct EntryInfo {
+    /// The entry as the repository has it
+    pub entry: MetadataEntry,
+    /// Size in bytes of the bitstream as stored: [`MetadataEntry::size`],
+    /// or for an entry published before sizes were recorded the size of the
+    /// file in a clone that has it checked out; `None` otherwise
+    pub size: Option<u64>,
+}
+
+/// Look up the entry for a source MD5 without retrieving its bitstream
+///
+/// Only the metadata is fetched; the entry is picked like [`get`] picks
+/// it. Returns `None` when the repository has no such entry.
+///
+/// ```no_run
+/// use bitcache::{Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let md5 = "d3699e851d7f4fde53ee37c037408af7";
+/// if let Some(info) = bitcache::info(&remote, md5, None, &Context::default())? {
+///     println!("built from {} at {}", info.entry.source_file, info.entry.timestamp);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn info(
+    remote: &Remote,
+    md5: &str,
+    variant: Option<&str>,
+    ctx: &Context,
+) -> io::Result<Option<EntryInfo>> {
+    info_in(None, remote, md5, variant, ctx)
+}
+
+/// [`info`], in the clone kept by `pool` if given
+pub(crate) fn info_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    md5: &str,
+    variant: Option<&str>,
+    ctx: &Context,
+) -> io::Result<Option<EntryInfo>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout_sparse(pool, remote, ctx)?;
+    let repo_dir = checkout.dir();
+    let Some(entry) = find_entry(&checkout, remote, md5, variant, &BTreeMap::new())? else {
+        return Ok(None);
+    };
+    let stored = entry.stored_in(repo_dir)?;
+    let size = match entry.size {
+        Some(size) => Some(size),
+        None => fs::metadata(stored).ok().map(|meta| meta.len()),
+    };
+    Ok(Some(EntryInfo { entry, size }))
+}
+
+/// Every entry in the repository, oldest first
+///
+/// ```no_run
+/// use bitcache::{Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// for entry in bitcache::list(&remote, &Context::default())? {
+///     println!("{}  {}", entry.md5, entry.binary_path);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn list(remote: &Remote, ctx: &Context) -> io::Result<Vec<MetadataEntry>> {
+    list_in(None, remote, ctx)
+}
+
+/// [`list`], in the clone kept by `pool` if given
+pub(crate) fn list_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<Vec<MetadataEntry>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout_sparse(pool, remote, ctx)?;
+    let metadata_file = MetadataFile::of(checkout.dir(), remote);
+    let metadata = match metadata_file.load_sparse(&checkout, remote) {
+        Ok(metadata) => metadata,
+        Err(e) if matches!(BitcacheError::of(&e), Some(BitcacheError::MissingMetadata)) => {
+            return Ok(Vec::new())
+        }
+        Err(e) => return Err(e),
+    };
+    let mut entries: Vec<_> = metadata.entries.into_values().flatten().collect();
+    entries.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    Ok(entries)
+}
+
+/// Which entries [`search`] returns; an entry must meet every criterion
+/// given
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    /// Glob the source file name must match, with `*`, `?` and `[...]`
+    pub source_pattern: Option<String>,
+    /// Tags the entry must have, each with the value given
+    pub tags: BTreeMap<String, String>,
+    /// Expression the entry must match
+    pub filter: Option<Filter>,
+    /// Only entries published after this instant
+    pub since: Option<DateTime<FixedOffset>>,
+}
+
+impl SearchOptions {
+    /// Whether `entry` meets every criterion given
+    pub fn matches(&self, entry: &MetadataEntry) -> bool {
+        self.source_pattern
+            .as_deref()
+            .is_none_or(|pattern| glob_matches(pattern, &entry.source_file))
+            && self
+                .tags
+                .iter()
+                .all(|(key, value)| entry.tags.get(key) == Some(value))
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(entry))
+            && self.since.is_none_or(|since| {
+                DateTime::parse_from_rfc3339(&entry.timestamp).is_ok_and(|time| time > since)
+            })
+    }
+}
+
+/// The entries in the repository that `opts` picks, oldest first
+///
+/// ```no_run
+/// use bitcache::{Context, Remote, SearchOptions};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = SearchOptions {
+///     source_pattern: Some("top*.vhd".to_string()),
+///     filter: Some("not deprecated".parse()?),
+///     ..SearchOptions::default()
+/// };
+/// for entry in bitcache::search(&remote, &opts, &Context::default())? {
+///     println!("{}  {}", entry.md5, entry.binary_path);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn search(
+    remote: &Remote,
+    opts: &SearchOptions,
+    ctx: &Context,
+) -> io::Result<Vec<MetadataEntry>> {
+    search_in(None, remote, opts, ctx)
+}
+
+/// [`search`], in the clone kept by `pool` if given
+pub(crate) fn search_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &SearchOptions,
+    ctx: &Context,
+) -> io::Result<Vec<MetadataEntry>> {
+    let mut entries = list_in(pool, remote, ctx)?;
+    entries.retain(|entry| opts.matches(entry));
+    Ok(entries)
+}
+
+/// Where a bitstream being delivered comes from
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    /// The local artifact store
+    Store,
+    /// A clone removed after this operation
+    Clone,
+    /// A clone kept for the client's next operation
+    KeptClone,
+}
+
+/// Where `get` saves the bitstream
+pub(crate) enum Destination {
+    /// Save into this directory under the stored file name
+    Dir(PathBuf),
+    /// Save to exactly this path (from `--output`)
+    File(PathBuf),
+}
+
+impl Destination {
+    /// Pick and check the destination before any network I/O
+    ///
+    /// `--output` wins; otherwise the current directory is used, falling back
+    /// to `output_dir` when the current directory is not writable.
+    pub(crate) fn resolve(opts: &GetOptions) -> io::Result<Self> {
+        if let Some(output) = &opts.output {
+            // A trailing separator names a directory, which may not exist yet
+            let names_dir = output
+                .as_os_str()
+                .to_string_lossy()
+                .ends_with(std::path::is_separator);
+            let output = &std::path::absolute(output)?;
+            let destination = if names_dir || output.is_dir() {
+                Destination::Dir(output.clone())
+            } else {
+                Destination::File(output.clone())
+            };
+            let dir = destination.dir();
+            if !dir.is_dir() {
+                fs::create_dir_all(dir).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!(
+                            "Cannot write --output {}: cannot create directory {} ({})",
+                            output.display(),
+                            dir.display(),
+                            e
+                        ),
+                    )
+                })?;
+            }
+            fsutil::check_writable_dir(dir).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Cannot write --output {}: cannot create files in {} ({})",
+                        output.display(),
+                        dir.display(),
+                        e
+                    ),
+                )
+            })?;
+            if let Destination::File(path) = &destination {
+                check_vacant(path, opts)?;
+            }
+            return Ok(destination);
+        }
+
+        let current_dir = std::env::current_dir()?;
+        let cwd_error = match fsutil::check_writable_dir(&current_dir) {
+            Ok(()) => return Ok(Destination::Dir(current_dir)),
+            Err(e) => e,
+        };
+
+        if let Some(fallback) = &opts.output_dir {
+            fsutil::check_writable_dir(fallback).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Cannot create files in the current directory {} ({}) or in the output_dir fallback {} ({})",
+                        current_dir.display(),
+                        cwd_error,
+                        fallback.display(),
+                        e
+                    ),
+                )
+            })?;
+            status!(
+                "Current directory is not writable, saving to {}",
+                fallback.display()
+            );
+            return Ok(Destination::Dir(fallback.clone()));
+        }
+
+        Err(io::Error::new(
+            cwd_error.kind(),
+            format!(
+                "Cannot create files in the current directory {} ({}). Pass --output <PATH>, or set BITCACHE_OUTPUT_DIR (or output_dir in the config) to a writable directory",
+                current_dir.display(),
+                cwd_error
+            ),
+        ))
+    }
+
+    /// Directory the bitstream is written into
+    fn dir(&self) -> &Path {
+        match self {
+            Destination::Dir(dir) => dir,
+            Destination::File(path) => match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            },
+        }
+    }
+
+    /// Full path of the saved file for a bitstream called `filename`
+    pub(crate) fn file_path(&self, filename: &OsStr) -> PathBuf {
+        match self {
+            Destination::Dir(dir) => dir.join(filename),
+            Destination::File(path) => path.clone(),
+        }
+    }
+}
+
+/// Fail if saving to `path` would replace an existing file without
+/// `--force`, wherever the destination came from
+pub(crate) fn check_vacant(path: &Path, opts: &GetOptions) -> io::Result<()> {
+    if !opts.force && fs::symlink_metadata(path).is_ok() {
+        return Err(BitcacheError::OutputExists {
+            path: path.to_path_buf(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Warn about a deprecated entry, or refuse it if `opts` says so
+fn check_deprecated(opts: &GetOptions, entry: &MetadataEntry) -> io::Result<()> {
+    if !entry.deprecated {
+        return Ok(());
+    }
+    let deprecated = BitcacheError::Deprecated {
+        md5: entry.md5.clone(),
+        reason: entry.deprecation_reason.clone(),
+        replacement: entry.replacement.clone(),
+    };
+    if opts.refuse_deprecated {
+        return Err(deprecated.into());
+    }
+    warning!("{}", deprecated);
+    Ok(())
+}
+
+/// Place a retrieved bitstream at its destination
+///
+/// Only a bitstream from a one-off clone is hardlinked, since nothing else
+/// reads or modifies it afterwards; anything else is copied. One stored
+/// compressed is saved decompressed, without [`Compression::extension`],
+/// one a publish renamed under the name it had, and the saved copy gets the
+/// permission bits the bitstream was published with.
+fn deliver(
+    destination: &Destination,
+    opts: &GetOptions,
+    entry: MetadataEntry,
+    binary_path: &Path,
+    source: Source,
+    ctx: &Context,
+) -> io::Result<Retrieved> {
+    check_source(opts, &entry)?;
+    check_deprecated(opts, &entry)?;
+    let stored_name = entry
+        .binary_path
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid binary path"))?;
+    let compression = entry.compression()?;
+    let stored_name = match &entry.file_name {
+        Some(name) => name.as_str(),
+        None => match stored_name.strip_suffix(compression.extension()) {
+            Some(name) if !name.is_empty() => name,
+            _ => stored_name,
+        },
+    };
+    let filename = paths::decode_name(stored_name)
+        .map_err(|reason| io::Error::new(io::ErrorKind::InvalidData, reason))?;
+
+    let dest_path = paths::long_path(&destination.file_path(&filename));
+    check_vacant(&dest_path, opts)?;
+    fsutil::ensure_free_space(
+        destination.dir(),
+        fs::metadata(binary_path)?.len(),
+        "the retrieved bitstream",
+        "Free some space or choose another location with --output.",
+    )?;
+
+    status!(
+        "Copying {} to {}",
+        filename.to_string_lossy(),
+        dest_path.display()
+    );
+    let compressed = compression != Compression::None;
+    let size = if compressed {
+        let size = decompress_to(compression, binary_path, &dest_path)?;
+        detail!("Decompressed bitstream to {} bytes", size);
+        match entry.original_size {
+            Some(recorded) if recorded != size => {
+                let _ = fs::remove_file(&dest_path);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} decompressed to {} bytes, but {} bytes were recorded",
+                        entry.binary_path, size, recorded
+                    ),
+                ));
+            }
+            _ => size,
+        }
+    } else {
+        let placed = if source == Source::Clone {
+            fsutil::link_or_copy(binary_path, &dest_path)
+        } else {
+            fsutil::clone_or_copy(binary_path, &dest_path)
+        };
+        let (size, placement) = placed.map_err(|e| match e.kind() {
+            io::ErrorKind::Interrupted => e,
+            _ => BitcacheError::Io {
+                context: format!("Cannot write {}", dest_path.display()),
+                source: e,
+            }
+            .into(),
+        })?;
+        detail!("Placed bitstream via {}", placement);
+        size
+    };
+    // The checksum was recorded for the compressed copy
+    let checked = if compressed {
+        binary_path
+    } else {
+        dest_path.as_path()
+    };
+    let verified = match &entry.binary_md5 {
+        Some(recorded) if opts.verify => {
+            detail!("Verifying {} against its recorded MD5", checked.display());
+            compute_md5(checked).and_then(|actual| {
+                if actual == *recorded {
+                    return Ok(());
+                }
+                Err(BitcacheError::CorruptBinary {
+                    binary_path: entry.binary_path.clone(),
+                    expected: recorded.clone(),
+                    actual,
+                }
+                .into())
+            })
+        }
+        _ if ctx.paranoid && !compressed => {
+            compute_md5(binary_path).and_then(|digest| verify_written(&dest_path, &digest))
+        }
+        None if opts.verify => {
+            detail!(
+                "{} was published without a checksum, so it is not verified",
+                entry.binary_path
+            );
+            Ok(())
+        }
+        _ => Ok(()),
+    };
+    if let Err(e) = verified {
+        let _ = fs::remove_file(&dest_path);
+        return Err(e);
+    }
+    if let Some(mode) = entry.file_mode {
+        fsutil::set_file_mode(&dest_path, mode).map_err(|e| BitcacheError::Io {
+            context: format!("Cannot set the permissions of {}", dest_path.display()),
+            source: e,
+        })?;
+    }
+    if opts.sync {
+        fsutil::sync_path(&dest_path).map_err(|e| BitcacheError::Io {
+            context: format!("Cannot flush {} to disk", dest_path.display()),
+            source: e,
+        })?;
+    }
+
+    Ok(Retrieved {
+        entry,
+        path: dest_path,
+        size,
+        from_cache: source == Source::Store,
+        synced: opts.sync,
+    })
+}
@@ -0,0 +1,119 @@
+//! Checking on several repositories at once.
+
+use crate::checkout::{self, ClonePool};
+use crate::error;
+use crate::layout::MetadataFile;
+use crate::progress::status;
+use crate::storage;
+use crate::{Context, Remote};
+use chrono::DateTime;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io;
+use std::thread;
+
+/// What [`probe`] found in a repository
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RepoHealth {
+    /// URL of the repository, with any credentials redacted
+    pub url: String,
+    /// Branch read
+    pub branch: Option<String>,
+    /// Number of entries
+    pub entries: usize,
+    /// Bytes of the bitstreams whose entries record a `size`
+    pub bytes: u64,
+    /// Number of entries recording no `size`, left out of `bytes`
+    pub without_size: usize,
+    /// Timestamp of the latest entry published, `None` without entries
+    pub last_published: Option<String>,
+    /// Number of entries whose bitstream the repository lacks, which
+    /// [`crate::verify`] would report
+    pub missing: usize,
+}
+
+/// Read the metadata of a repository and sum it up
+///
+/// Only the metadata is fetched: sizes come from the entries' recorded
+/// `size` fields and missing bitstreams are found from the list of files the
+/// repository tracks, so a probe stays cheap however large the bitstreams
+/// are. A repository without metadata fails with
+/// [`crate::BitcacheError::MissingMetadata`].
+///
+/// ```no_run
+/// use bitcache::{Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let health = bitcache::probe(&remote, &Context::default())?;
+/// println!("{}: {} entries, {} missing", health.url, health.entries, health.missing);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn probe(remote: &Remote, ctx: &Context) -> io::Result<RepoHealth> {
+    probe_in(None, remote, ctx)
+}
+
+/// [`probe`], in the clone kept by `pool` if given
+pub(crate) fn probe_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<RepoHealth> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let url = error::redact(&remote.url);
+    status!("Probing repository: {}", url);
+    let checkout = checkout::checkout_sparse(pool, remote, ctx)?;
+    let repo_dir = checkout.dir();
+    let metadata = MetadataFile::of(repo_dir, remote).load_sparse(&checkout, remote)?;
+    let backend = storage::of(remote);
+    let tracked: HashSet<String> = backend.files(repo_dir)?.into_iter().collect();
+
+    let mut health = RepoHealth {
+        url,
+        branch: backend.branch(repo_dir)?,
+        ..RepoHealth::default()
+    };
+    let mut latest = None;
+    for entry in metadata.iter() {
+        health.entries += 1;
+        match entry.size {
+            Some(size) => health.bytes += size,
+            None => health.without_size += 1,
+        }
+        if !tracked.contains(&entry.binary_path) {
+            health.missing += 1;
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(&entry.timestamp) {
+            if latest.is_none_or(|latest| time > latest) {
+                latest = Some(time);
+                health.last_published = Some(entry.timestamp.clone());
+            }
+        }
+    }
+    Ok(health)
+}
+
+/// [`probe`] every repository of `remotes` at the same time
+///
+/// The results are in the order of `remotes`; one repository failing,
+/// e.g. for being unreachable, leaves the others' results alone. Each probe
+/// retries and times out as `ctx` says, like any other operation.
+pub fn probe_all(remotes: &[Remote], ctx: &Context) -> Vec<io::Result<RepoHealth>> {
+    if remotes.len() <= 1 {
+        return remotes.iter().map(|remote| probe(remote, ctx)).collect();
+    }
+    thread::scope(|scope| {
+        let probes: Vec<_> = remotes
+            .iter()
+            .map(|remote| scope.spawn(|| probe(remote, ctx)))
+            .collect();
+        probes
+            .into_iter()
+            .map(|probe| {
+                probe
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    })
+}
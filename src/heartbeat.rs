@@ -0,0 +1,171 @@
+//! Periodic "still working" lines for non-interactive runs.
+//!
+//! CI systems kill jobs that stay silent for too long, and a large clone or
+//! copy can easily exceed that once nothing is drawn to a terminal. Each long
+//! phase emits a single [`Event::Heartbeat`] line every few seconds stating
+//! what it is doing and how far it got. The CLI turns heartbeats off when
+//! stderr is a TTY and otherwise writes them whole to stderr, so they never
+//! split the captured git output or the results on stdout.
+
+use crate::human::{format_duration, format_size};
+use crate::progress::{self, Event, Phase};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Default seconds between heartbeat lines
+pub const DEFAULT_INTERVAL_SECS: u64 = 30;
+
+static INTERVAL_SECS: AtomicU64 = AtomicU64::new(DEFAULT_INTERVAL_SECS);
+
+/// Set the heartbeat interval in seconds; zero disables heartbeats
+pub fn set_interval(secs: u64) {
+    INTERVAL_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// Heartbeat for a single phase; it stops when dropped
+///
+/// It also reports the phase's start, byte progress and end as
+/// [`Event::PhaseStarted`], [`Event::Bytes`] and [`Event::PhaseFinished`].
+pub(crate) struct Heartbeat {
+    phase: Phase,
+    started: Instant,
+    last: Instant,
+    interval: Option<Duration>,
+}
+
+impl Heartbeat {
+    /// Start timing a phase
+    pub fn start(phase: Phase) -> Self {
+        progress::emit(Event::PhaseStarted(phase));
+        let secs = INTERVAL_SECS.load(Ordering::Relaxed);
+        let interval = (secs > 0).then(|| Duration::from_secs(secs));
+        let now = Instant::now();
+        Self {
+            phase,
+            started: now,
+            last: now,
+            interval,
+        }
+    }
+
+    /// Emit a line if the interval has elapsed
+    pub fn tick(&mut self) {
+        if self.due() {
+            progress::emit(Event::Heartbeat(&format!(
+                "[bitcache] {}: still running after {}",
+                self.phase.name(),
+                format_duration(self.started.elapsed())
+            )));
+        }
+    }
+
+    /// Emit a line with byte progress if the interval has elapsed
+    pub fn tick_bytes(&mut self, done: u64, total: Option<u64>) {
+        progress::emit(Event::Bytes {
+            phase: self.phase,
+            done,
+            total,
+        });
+        if self.due() {
+            let done = match total {
+                Some(total) => format!("{} of {}", format_size(done), format_size(total)),
+                None => format_size(done),
+            };
+            progress::emit(Event::Heartbeat(&format!(
+                "[bitcache] {}: {} after {}",
+                self.phase.name(),
+                done,
+                format_duration(self.started.elapsed())
+            )));
+        }
+    }
+
+    fn due(&mut self) -> bool {
+        match self.interval {
+            Some(interval) if self.last.elapsed() >= interval => {
+                self.last = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        progress::emit(Event::PhaseFinished(self.phase));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::{Recorded, RecordingObserver};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// The events of a heartbeat ticked `ticks` times, `every` apart, with
+    /// lines due every `interval`
+    fn ticked(interval: Option<Duration>, ticks: usize, every: Duration) -> Vec<Recorded> {
+        let recorder = Arc::new(RecordingObserver::new());
+        {
+            let _observing = progress::observe(Some(recorder.clone()));
+            let mut heartbeat = Heartbeat::start(Phase::Cloning);
+            heartbeat.interval = interval;
+            for tick in 0..ticks {
+                thread::sleep(every);
+                heartbeat.tick_bytes(tick as u64 * 1024, Some(4096));
+            }
+        }
+        recorder.events()
+    }
+
+    fn heartbeats(events: &[Recorded]) -> Vec<&str> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                Recorded::Heartbeat(line) => Some(line.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn emits_a_line_once_the_interval_has_elapsed() {
+        let events = ticked(
+            Some(Duration::from_millis(20)),
+            3,
+            Duration::from_millis(30),
+        );
+        let lines = heartbeats(&events);
+        assert_eq!(lines.len(), 3, "{:?}", events);
+        assert!(
+            lines[2].starts_with("[bitcache] cloning: 2.0 KiB of 4.0 KiB after "),
+            "{}",
+            lines[2]
+        );
+        // Bracketed by the start and end of the phase
+        assert_eq!(
+            events.first(),
+            Some(&Recorded::PhaseStarted(Phase::Cloning))
+        );
+        assert_eq!(
+            events.last(),
+            Some(&Recorded::PhaseFinished(Phase::Cloning))
+        );
+    }
+
+    #[test]
+    fn stays_quiet_within_the_interval_or_when_disabled() {
+        for interval in [Some(Duration::from_secs(60)), None] {
+            let events = ticked(interval, 3, Duration::from_millis(1));
+            assert!(heartbeats(&events).is_empty(), "{:?}", events);
+            // Byte progress still goes to observers
+            let bytes = events
+                .iter()
+                .filter(|event| matches!(event, Recorded::Bytes { .. }))
+                .count();
+            assert_eq!(bytes, 3);
+        }
+    }
+}
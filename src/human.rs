@@ -0,0 +1,306 @@
+//! Human-readable formatting helpers shared by all subcommands.
+//!
+//! Sizes use binary units (KiB, MiB, ...) with one decimal, durations use
+//! compact forms such as `850ms`, `12.3s` or `1m 23s`, and timestamps can be
+//! shown in UTC, local time or as a relative age. Formatting never depends on
+//! the process locale so output is identical on every machine.
+
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use clap::ValueEnum;
+use std::time::Duration;
+
+const SIZE_UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// How timestamps are rendered in human-readable output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimeStyle {
+    /// RFC 3339 in UTC, as stored in the metadata
+    #[default]
+    Utc,
+    /// RFC 3339 in the local timezone
+    Local,
+    /// Age relative to now, e.g. "3 days ago"
+    Relative,
+}
+
+impl std::str::FromStr for TimeStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// A byte count parsed from `4096`, `512K`, `1.5G`, `10GiB` and similar
+///
+/// Suffixes are binary (K = 1024) and case-insensitive; an optional `B` or
+/// `iB` after the unit letter is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl std::str::FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        let lower = input.to_ascii_lowercase();
+        let number_end = lower
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(lower.len());
+        let (number, unit) = lower.split_at(number_end);
+        let unit = unit.trim_start();
+        let unit = unit
+            .strip_suffix("ib")
+            .or_else(|| unit.strip_suffix('b'))
+            .unwrap_or(unit);
+
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("'{}' is not a size such as 512M or 10G", input))?;
+        let exponent = match unit {
+            "" => 0,
+            "k" => 1,
+            "m" => 2,
+            "g" => 3,
+            "t" => 4,
+            "p" => 5,
+            "e" => 6,
+            _ => return Err(format!("unknown size unit in '{}'", input)),
+        };
+        let bytes = number * 1024f64.powi(exponent);
+        if !bytes.is_finite() || bytes > u64::MAX as f64 {
+            return Err(format!("size '{}' is too large", input));
+        }
+        Ok(ByteSize(bytes.round() as u64))
+    }
+}
+
+/// Style used when printing sizes, durations and timestamps
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputStyle {
+    /// Print exact byte counts and milliseconds instead of rounded values
+    raw: bool,
+    /// How to render timestamps
+    time: TimeStyle,
+}
+
+impl OutputStyle {
+    pub fn new(raw: bool, time: TimeStyle) -> Self {
+        Self { raw, time }
+    }
+
+    /// Format a stored RFC 3339 timestamp
+    pub fn timestamp(&self, timestamp: &str) -> String {
+        format_timestamp(timestamp, self.time, Utc::now())
+    }
+
+    /// Format a byte count
+    pub fn size(&self, bytes: u64) -> String {
+        if self.raw {
+            format!("{} bytes", bytes)
+        } else {
+            format_size(bytes)
+        }
+    }
+
+    /// Format an elapsed time
+    pub fn duration(&self, duration: Duration) -> String {
+        if self.raw {
+            format!("{} ms", duration.as_millis())
+        } else {
+            format_duration(duration)
+        }
+    }
+}
+
+/// Format a byte count using binary units with one decimal (e.g. `1.5 MiB`)
+///
+/// Values that would round up to 1024 of a unit are promoted to the next one,
+/// so 1048575 bytes prints as `1.0 MiB` rather than `1024.0 KiB`.
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while unit + 1 < SIZE_UNITS.len() && (value * 10.0).round() >= 10240.0 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, SIZE_UNITS[unit])
+}
+
+/// Format a duration as `850ms`, `12.3s`, `1m 23s` or `2h 5m`
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if millis < 1000 {
+        return format!("{}ms", millis);
+    }
+
+    // Round to tenths first so 59.96s prints as 1m 0s instead of 60.0s
+    let tenths = (millis + 50) / 100;
+    if tenths < 600 {
+        return format!("{}.{}s", tenths / 10, tenths % 10);
+    }
+
+    let secs = (millis + 500) / 1000;
+    if secs < 3600 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {}m", secs / 3600, (secs % 3600) / 60)
+    }
+}
+
+/// Format a stored RFC 3339 timestamp in the given style
+///
+/// `now` is only used for [`TimeStyle::Relative`]. Timestamps that fail to
+/// parse are returned unchanged so hand-edited metadata still displays.
+pub fn format_timestamp(timestamp: &str, style: TimeStyle, now: DateTime<Utc>) -> String {
+    let Ok(parsed) = DateTime::parse_from_rfc3339(timestamp) else {
+        return timestamp.to_string();
+    };
+
+    match style {
+        TimeStyle::Utc => parsed
+            .with_timezone(&Utc)
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        TimeStyle::Local => parsed
+            .with_timezone(&Local)
+            .to_rfc3339_opts(SecondsFormat::Secs, false),
+        TimeStyle::Relative => format_age(now.signed_duration_since(parsed)),
+    }
+}
+
+/// Format a signed age as "3 days ago" or "in 2 hours"
+fn format_age(age: chrono::TimeDelta) -> String {
+    let secs = age.num_seconds();
+    if secs.abs() < 60 {
+        return "just now".to_string();
+    }
+
+    let magnitude = secs.unsigned_abs();
+    let (count, unit) = match magnitude {
+        s if s < 3600 => (s / 60, "minute"),
+        s if s < 86_400 => (s / 3600, "hour"),
+        s if s < 30 * 86_400 => (s / 86_400, "day"),
+        s if s < 365 * 86_400 => (s / (30 * 86_400), "month"),
+        s => (s / (365 * 86_400), "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+
+    if secs > 0 {
+        format!("{} {}{} ago", count, unit, plural)
+    } else {
+        format!("in {} {}{}", count, unit, plural)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_use_binary_units_with_one_decimal() {
+        let cases = [
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1.0 KiB"),
+            (1536, "1.5 KiB"),
+            (1_048_575, "1.0 MiB"),
+            (2_516_582, "2.4 MiB"),
+            (10 * 1024 * 1024 * 1024, "10.0 GiB"),
+            (u64::MAX, "16.0 EiB"),
+        ];
+        for (bytes, shown) in cases {
+            assert_eq!(format_size(bytes), shown, "{}", bytes);
+        }
+    }
+
+    #[test]
+    fn durations_grow_coarser_with_length() {
+        let cases = [
+            (Duration::from_millis(850), "850ms"),
+            (Duration::from_millis(12_340), "12.3s"),
+            (Duration::from_millis(59_960), "1m 0s"),
+            (Duration::from_secs(83), "1m 23s"),
+            (Duration::from_secs(2 * 3600 + 5 * 60 + 59), "2h 5m"),
+        ];
+        for (duration, shown) in cases {
+            assert_eq!(format_duration(duration), shown, "{:?}", duration);
+        }
+    }
+
+    #[test]
+    fn raw_units_print_exact_values() {
+        let style = OutputStyle::new(true, TimeStyle::Utc);
+        assert_eq!(style.size(2_516_582), "2516582 bytes");
+        assert_eq!(style.duration(Duration::from_secs(83)), "83000 ms");
+        let style = OutputStyle::default();
+        assert_eq!(style.size(2_516_582), "2.4 MiB");
+        assert_eq!(style.duration(Duration::from_secs(83)), "1m 23s");
+    }
+
+    #[test]
+    fn parses_sizes_with_units() {
+        let cases = [
+            ("4096", 4096),
+            ("512K", 512 * 1024),
+            ("1.5G", 3 * 512 * 1024 * 1024),
+            ("10GiB", 10 * 1024 * 1024 * 1024),
+            ("2 mb", 2 * 1024 * 1024),
+        ];
+        for (input, bytes) in cases {
+            assert_eq!(input.parse::<ByteSize>(), Ok(ByteSize(bytes)), "{}", input);
+        }
+        for input in ["", "M", "12X", "1e30"] {
+            assert!(input.parse::<ByteSize>().is_err(), "{}", input);
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2024-04-30T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn timestamps_show_in_utc_by_default() {
+        assert_eq!(
+            format_timestamp("2024-04-30T14:00:00+02:00", TimeStyle::Utc, now()),
+            "2024-04-30T12:00:00Z"
+        );
+        // Hand-edited timestamps that don't parse are shown as they are
+        assert_eq!(
+            format_timestamp("last tuesday", TimeStyle::Relative, now()),
+            "last tuesday"
+        );
+    }
+
+    #[test]
+    fn local_timestamps_keep_the_instant() {
+        let shown = format_timestamp("2024-04-30T12:00:00Z", TimeStyle::Local, now());
+        let parsed = DateTime::parse_from_rfc3339(&shown).expect("RFC 3339");
+        assert_eq!(parsed.with_timezone(&Utc), now());
+    }
+
+    #[test]
+    fn relative_timestamps_round_down_to_one_unit() {
+        let cases = [
+            ("2024-04-30T11:59:30Z", "just now"),
+            ("2024-04-30T11:59:00Z", "1 minute ago"),
+            ("2024-04-30T09:30:00Z", "2 hours ago"),
+            ("2024-04-27T12:00:00Z", "3 days ago"),
+            ("2024-01-30T12:00:00Z", "3 months ago"),
+            ("2021-04-30T12:00:00Z", "3 years ago"),
+            ("2024-05-02T12:00:00Z", "in 2 days"),
+        ];
+        for (timestamp, shown) in cases {
+            assert_eq!(
+                format_timestamp(timestamp, TimeStyle::Relative, now()),
+                shown,
+                "{}",
+                timestamp
+            );
+        }
+    }
+}
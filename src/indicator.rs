@@ -0,0 +1,200 @@
+//! Spinners and progress bars for interactive runs.
+//!
+//! The library reports long phases such as cloning, copying and pushing as
+//! [`Phase`] events. When stderr is a terminal the CLI draws the innermost
+//! running phase on a single stderr line: a progress bar once the phase has
+//! reported how many bytes it will process, a spinner with the elapsed time
+//! otherwise. A background thread redraws it, so git subprocesses that print
+//! nothing still show signs of life. Every other line the CLI prints goes
+//! through [`suspend`], which clears the drawing first.
+//!
+//! Redirected runs never see any of this; they get heartbeat lines instead,
+//! see [`bitcache::heartbeat`].
+
+use bitcache::human::{format_duration, format_size};
+use bitcache::progress::Phase;
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time between redraws
+const TICK: Duration = Duration::from_millis(100);
+
+/// Phases shorter than this are never drawn, so quick ones don't flicker
+const DELAY: Duration = Duration::from_millis(300);
+
+/// Width of the bar between its brackets
+const BAR_WIDTH: usize = 24;
+
+const SPINNER: [char; 4] = ['-', '\\', '|', '/'];
+
+static INDICATOR: OnceLock<Mutex<State>> = OnceLock::new();
+
+/// A running phase
+struct Running {
+    phase: Phase,
+    started: Instant,
+    done: u64,
+    total: Option<u64>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Running phases, innermost last
+    running: Vec<Running>,
+    /// Whether the stderr line currently holds a drawing
+    drawn: bool,
+    frame: usize,
+}
+
+impl State {
+    fn clear(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+            self.drawn = false;
+        }
+    }
+
+    fn draw(&mut self) {
+        let Some(running) = self.running.last() else {
+            return;
+        };
+        let elapsed = running.started.elapsed();
+        if elapsed < DELAY {
+            return;
+        }
+        let line = render(running, elapsed, SPINNER[self.frame % SPINNER.len()]);
+        self.frame += 1;
+        eprint!("\r\x1b[2K{}", line);
+        let _ = io::stderr().flush();
+        self.drawn = true;
+    }
+}
+
+/// The line drawn for `running` after `elapsed`, with `spin` as the
+/// spinner's current frame
+fn render(running: &Running, elapsed: Duration, spin: char) -> String {
+    match running.total {
+        Some(total) if total > 0 => {
+            let filled =
+                ((running.done.min(total) as u128 * BAR_WIDTH as u128) / total as u128) as usize;
+            format!(
+                "{} [{}{}] {} of {} {}",
+                running.phase.name(),
+                "=".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                format_size(running.done),
+                format_size(total),
+                format_duration(elapsed)
+            )
+        }
+        _ if running.done > 0 => format!(
+            "{} {} {} {}",
+            spin,
+            running.phase.name(),
+            format_size(running.done),
+            format_duration(elapsed)
+        ),
+        _ => format!(
+            "{} {} {}",
+            spin,
+            running.phase.name(),
+            format_duration(elapsed)
+        ),
+    }
+}
+
+fn lock() -> Option<MutexGuard<'static, State>> {
+    INDICATOR
+        .get()
+        .map(|state| state.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Start drawing phases on stderr
+pub fn enable() {
+    if INDICATOR.set(Mutex::new(State::default())).is_ok() {
+        thread::spawn(|| loop {
+            thread::sleep(TICK);
+            if let Some(mut state) = lock() {
+                state.draw();
+            }
+        });
+    }
+}
+
+/// Run `print` with the drawing cleared, so its output starts on a clean
+/// line; the next tick draws it again
+pub fn suspend<R>(print: impl FnOnce() -> R) -> R {
+    match lock() {
+        Some(mut state) => {
+            state.clear();
+            print()
+        }
+        None => print(),
+    }
+}
+
+/// A phase has started
+pub fn phase_started(phase: Phase) {
+    if let Some(mut state) = lock() {
+        state.running.push(Running {
+            phase,
+            started: Instant::now(),
+            done: 0,
+            total: None,
+        });
+    }
+}
+
+/// A phase has processed `done` of `total` bytes
+pub fn bytes_progressed(phase: Phase, done: u64, total: Option<u64>) {
+    if let Some(mut state) = lock() {
+        if let Some(running) = state.running.iter_mut().rev().find(|r| r.phase == phase) {
+            running.done = done;
+            running.total = total;
+        }
+    }
+}
+
+/// A phase has finished
+pub fn phase_finished(phase: Phase) {
+    if let Some(mut state) = lock() {
+        if let Some(i) = state.running.iter().rposition(|r| r.phase == phase) {
+            state.running.remove(i);
+        }
+        state.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running(done: u64, total: Option<u64>) -> Running {
+        Running {
+            phase: Phase::Copying,
+            started: Instant::now(),
+            done,
+            total,
+        }
+    }
+
+    #[test]
+    fn draws_a_bar_once_the_total_is_known() {
+        let line = render(&running(512, Some(1024)), Duration::from_secs(3), '-');
+        let bar = format!("[{}{}]", "=".repeat(12), " ".repeat(12));
+        assert!(line.starts_with(&format!("copying {} ", bar)), "{}", line);
+        // More than the total, as a growing file can report, fills it
+        let line = render(&running(4096, Some(1024)), Duration::from_secs(3), '-');
+        assert!(line.contains(&"=".repeat(BAR_WIDTH)), "{}", line);
+    }
+
+    #[test]
+    fn spins_without_a_total() {
+        let line = render(&running(0, None), Duration::from_secs(3), '|');
+        assert!(line.starts_with("| copying "), "{}", line);
+        assert!(!line.contains('['), "{}", line);
+    }
+}
@@ -0,0 +1,170 @@
+//! Setting up a repository for bitcache.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::{self, BitcacheError};
+use crate::git::PushOutcome;
+use crate::layout::{self, MetadataFile, MetadataLayout};
+use crate::progress::{self, status, warning, Event};
+use crate::publish::{self, push_backoff};
+use crate::storage;
+use crate::{cancel, Context, Metadata, Remote};
+use serde::Serialize;
+use std::io;
+use std::thread;
+
+/// Commit message of the metadata file [`init`] creates
+const INIT_MESSAGE: &str = "Initialize bitcache repository";
+
+/// What [`init`] does with a repository that already has metadata
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    /// Replace an existing metadata file with an empty one, dropping its
+    /// entries, instead of refusing to
+    pub force: bool,
+}
+
+impl InitOptions {
+    /// Refuse repositories that already have metadata, as the CLI does
+    /// without `--force`
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Result of [`init`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Initialized {
+    /// Number of entries the replaced metadata file had
+    pub dropped: usize,
+    /// Whether a commit was pushed; false when the repository already had
+    /// an empty metadata file
+    pub committed: bool,
+}
+
+/// Seed a repository with an empty metadata file
+///
+/// The repository must exist and may be empty, e.g. a new bare repository
+/// on the git server. The metadata file is written at the current schema
+/// version, in [`Remote::metadata_layout`], and pushed in one commit reading `Initialize bitcache
+/// repository`. A repository that already has a metadata file, even a
+/// damaged one, is refused unless [`InitOptions::force`].
+///
+/// ```no_run
+/// use bitcache::{Context, InitOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// bitcache::init(&remote, &InitOptions::new(), &Context::default())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn init(remote: &Remote, opts: &InitOptions, ctx: &Context) -> io::Result<Initialized> {
+    init_in(None, remote, opts, ctx)
+}
+
+/// [`init`], in the clone kept by `pool` if given
+pub(crate) fn init_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &InitOptions,
+    ctx: &Context,
+) -> io::Result<Initialized> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    status!(
+        "Initializing bitcache repository: {}",
+        error::redact(&remote.url)
+    );
+    let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    publish::checked_branch(repo_dir, remote)?;
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    let mut replaced = Metadata::new();
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(push_backoff(attempt));
+            progress::emit(Event::Retry {
+                attempt,
+                attempts,
+                reason: &rejection,
+            });
+            status!(
+                "Push rejected, checking the new remote head (attempt {} of {})",
+                attempt,
+                attempts
+            );
+            storage::of(remote).reset(repo_dir, auth)?;
+        }
+
+        replaced = Metadata::new();
+        if metadata_file.exists() {
+            if !opts.force {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "The repository already has {}; pass --force to replace it with an empty one, dropping its entries",
+                        metadata_file.repo_path
+                    ),
+                ));
+            }
+            // A damaged file is replaced all the same
+            if let Ok(existing) = metadata_file.load() {
+                replaced = existing;
+            }
+        }
+
+        cancel::check()?;
+        status!("Writing {}...", metadata_file.repo_path);
+        let mut metadata = Metadata::new();
+        metadata.set_layout(remote.metadata_layout);
+        // Replaced shards go too, which a sharded save does by itself
+        let shard_dir = layout::shard_dir(&metadata_file.path);
+        let had_shards = shard_dir.exists();
+        if metadata.layout() == MetadataLayout::Single {
+            layout::remove_shards(&shard_dir, |_| false)?;
+        }
+        let mut written = metadata_file.save(&metadata, ctx.paranoid)?;
+        if had_shards {
+            written = metadata_file.files(MetadataLayout::Sharded);
+        }
+        let written: Vec<&str> = written.iter().map(String::as_str).collect();
+        status!("Committing and pushing changes...");
+        if !storage::of(remote).commit(repo_dir, &written, INIT_MESSAGE)? {
+            status!("No changes to commit");
+            return Ok(Initialized::default());
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => break,
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+    let initialized = Initialized {
+        dropped: replaced.len(),
+        committed: true,
+    };
+    if initialized.dropped > 0 {
+        warning!(
+            "dropped {} entr{} of the replaced metadata file",
+            initialized.dropped,
+            if initialized.dropped == 1 { "y" } else { "ies" }
+        );
+    }
+
+    // The dropped entries may no longer be served
+    if let Some(store) = ctx.artifact_store(remote) {
+        for entry in replaced.iter() {
+            if let Err(e) = store.remove(&entry.md5) {
+                warning!(
+                    "could not remove the entry from the local artifact cache: {}",
+                    e
+                );
+            }
+        }
+    }
+    Ok(initialized)
+}
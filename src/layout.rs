@@ -0,0 +1,444 @@
+//! Where a repository keeps its metadata.
+//!
+//! In the default single layout every entry is in the metadata file,
+//! [`crate::METADATA_FILE`] at the repository root unless [`Remote::metadata_path`]
+//! names another. With tens of thousands of entries that file is megabytes
+//! long, every publish rewrites it and concurrent publishers keep
+//! conflicting on it. The sharded layout instead keeps the variants of each
+//! hash in a file of their own, in a [`SHARD_DIR`] directory next to the
+//! metadata file and named after the hash:
+//!
+//! ```text
+//! bitcache_metadata.json                            {"schema_version": 4, "layout": "sharded"}
+//! .bitcache/d3/699e851d7f4fde53ee37c037408af7.json  {"schema_version": 4, "entries": {"d3699e85...": [...]}}
+//! ```
+//!
+//! The metadata file then only holds the schema version, the layout and the
+//! members this version does not know, and each shard is a metadata
+//! document of one hash, so publishes of different sources change
+//! different files. Versions of bitcache before the sharded layout refuse
+//! such a metadata file for its missing `entries` rather than misreading
+//! it.
+//!
+//! Every command reads a repository's layout from its metadata file.
+//! [`Remote::metadata_layout`] only picks the layout of metadata created
+//! where there is none; [`migrate_metadata`] converts existing metadata.
+
+use crate::checkout::{self, Checkout};
+use crate::error::BitcacheError;
+use crate::git::PushOutcome;
+use crate::progress::{self, status, Event};
+use crate::publish::{self, push_backoff};
+use crate::storage;
+use crate::{cancel, paths, verify_written, Context, Metadata, MetadataEntry, Remote};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+
+/// Directory of the shards of the sharded layout, next to the metadata
+/// file
+pub const SHARD_DIR: &str = ".bitcache";
+
+/// Characters of a hash naming the directory its shard is in, which keeps
+/// directories to a few hundred files
+const PREFIX_LEN: usize = 2;
+
+/// How a repository's entries are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum MetadataLayout {
+    /// Every entry in the metadata file, the default
+    #[default]
+    Single,
+    /// The entries of each hash in a file of their own under .bitcache/
+    Sharded,
+}
+
+impl MetadataLayout {
+    /// Name stored in the `layout` member of the metadata file
+    pub fn name(self) -> &'static str {
+        match self {
+            MetadataLayout::Single => "single",
+            MetadataLayout::Sharded => "sharded",
+        }
+    }
+}
+
+impl fmt::Display for MetadataLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MetadataLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// The shard directory of the metadata file at `metadata_path`
+pub(crate) fn shard_dir(metadata_path: &Path) -> PathBuf {
+    match metadata_path.parent() {
+        Some(dir) => dir.join(SHARD_DIR),
+        None => PathBuf::from(SHARD_DIR),
+    }
+}
+
+/// The file under `shard_dir` holding the variants of `md5`, `None` for a
+/// hash that can't name one
+pub(crate) fn shard_file(shard_dir: &Path, md5: &str) -> Option<PathBuf> {
+    let (prefix, rest) = shard_name(md5)?;
+    Some(shard_dir.join(prefix).join(format!("{}.json", rest)))
+}
+
+/// The directory and file stem of the shard of `md5`; only hashes of ASCII
+/// letters and digits, as every [`crate::HashAlgo`] writes them, have one
+fn shard_name(md5: &str) -> Option<(&str, &str)> {
+    if md5.len() <= PREFIX_LEN || !md5.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(md5.split_at(PREFIX_LEN))
+}
+
+/// The shards under `shard_dir`, with the hash each is named after
+///
+/// Temporary files an interrupted save left behind are removed on the way.
+pub(crate) fn shard_files(shard_dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut shards = Vec::new();
+    let prefixes = match fs::read_dir(shard_dir) {
+        Ok(read) => read,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(shards),
+        Err(e) => return Err(e),
+    };
+    for prefix in prefixes {
+        let prefix = prefix?;
+        let prefix_name = prefix.file_name().to_string_lossy().into_owned();
+        if !prefix.file_type()?.is_dir() || prefix_name.starts_with('.') {
+            continue;
+        }
+        for shard in fs::read_dir(prefix.path())? {
+            cancel::check()?;
+            let shard = shard?;
+            let name = shard.file_name().to_string_lossy().into_owned();
+            if let Some(stem) = name
+                .strip_suffix(".json")
+                .filter(|_| !name.starts_with('.'))
+            {
+                shards.push((format!("{}{}", prefix_name, stem), shard.path()));
+            } else if name.starts_with('.') {
+                let _ = fs::remove_file(shard.path());
+            }
+        }
+    }
+    Ok(shards)
+}
+
+/// Remove the shards under `shard_dir` whose hash `keep` refuses, and the
+/// directories left empty
+pub(crate) fn remove_shards(shard_dir: &Path, keep: impl Fn(&str) -> bool) -> io::Result<()> {
+    for (md5, path) in shard_files(shard_dir)? {
+        if keep(&md5) {
+            continue;
+        }
+        fs::remove_file(&path)?;
+        if let Some(prefix) = path.parent() {
+            // Fails while other shards are left in it
+            let _ = fs::remove_dir(prefix);
+        }
+    }
+    Ok(())
+}
+
+/// The components of a repository path, without empty ones and `.`
+fn names(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .collect()
+}
+
+/// `path` as the repository lists it, without `./` and doubled `/`
+pub(crate) fn normalize(path: &str) -> String {
+    names(path).join("/")
+}
+
+/// Check that `path`, relative to the repository root, can hold a
+/// repository's metadata
+pub(crate) fn check_metadata_path(path: &str) -> io::Result<()> {
+    let invalid = |reason: String| -> io::Error {
+        BitcacheError::InvalidArgument {
+            flag: "--metadata-path",
+            value: path.to_string(),
+            reason,
+        }
+        .into()
+    };
+    paths::check_relative(path).map_err(|reason| invalid(reason.to_string()))?;
+    let names = names(path);
+    match names.last() {
+        None => return Err(invalid("expected the path of a file".to_string())),
+        Some(name) if path.ends_with('/') || *name == SHARD_DIR => {
+            return Err(invalid("expected the path of a file".to_string()))
+        }
+        Some(_) => {}
+    }
+    if names.iter().any(|name| name.eq_ignore_ascii_case(".git")) {
+        return Err(invalid("'.git' is git's own directory".to_string()));
+    }
+    if names.contains(&SHARD_DIR) {
+        return Err(invalid(format!(
+            "'{}' holds the shards of the sharded layout",
+            SHARD_DIR
+        )));
+    }
+    Ok(())
+}
+
+/// The metadata of a clone, where its remote keeps it
+pub(crate) struct MetadataFile {
+    /// The metadata file in the clone
+    pub(crate) path: PathBuf,
+    /// [`Remote::metadata_path`], relative to the root of the clone
+    pub(crate) repo_path: String,
+    /// Layout of metadata created where there is none
+    new_layout: MetadataLayout,
+}
+
+impl MetadataFile {
+    /// The metadata of `remote` in its clone at `repo_dir`
+    pub(crate) fn of(repo_dir: &Path, remote: &Remote) -> Self {
+        Self {
+            path: repo_dir.join(&remote.metadata_path),
+            repo_path: normalize(&remote.metadata_path),
+            new_layout: remote.metadata_layout,
+        }
+    }
+
+    /// Whether the clone has metadata
+    pub(crate) fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Load the metadata, failing with [`BitcacheError::MissingMetadata`]
+    /// when there is none
+    pub(crate) fn load(&self) -> io::Result<Metadata> {
+        if !self.exists() {
+            return Err(BitcacheError::MissingMetadata.into());
+        }
+        Metadata::load_from_file(&self.path)
+    }
+
+    /// Load the metadata, or start empty metadata in the remote's layout
+    /// when there is none
+    pub(crate) fn load_or_new(&self) -> io::Result<Metadata> {
+        if self.exists() {
+            return Metadata::load_from_file(&self.path);
+        }
+        let mut metadata = Metadata::new();
+        metadata.set_layout(self.new_layout);
+        Ok(metadata)
+    }
+
+    /// [`Self::load`] from a sparse checkout, fetching the shards first
+    pub(crate) fn load_sparse(&self, checkout: &Checkout, remote: &Remote) -> io::Result<Metadata> {
+        checkout.include(&self.repo_path, remote)?;
+        if !self.exists() {
+            return Err(BitcacheError::MissingMetadata.into());
+        }
+        Metadata::load_with(&self.path, |_| {
+            checkout.include_dir(&self.shard_repo_dir(), remote)
+        })
+    }
+
+    /// The variants of `md5`, fetching the file they are in when `checkout`
+    /// is sparse; none when there is no metadata
+    pub(crate) fn lookup(
+        &self,
+        md5: &str,
+        checkout: &Checkout,
+        remote: &Remote,
+    ) -> io::Result<Vec<MetadataEntry>> {
+        checkout.include(&self.repo_path, remote)?;
+        if !self.exists() {
+            return Ok(Vec::new());
+        }
+        Metadata::lookup_with(&self.path, md5, |_| match shard_name(md5) {
+            Some((prefix, _)) => {
+                checkout.include_dir(&format!("{}/{}", self.shard_repo_dir(), prefix), remote)
+            }
+            None => Ok(()),
+        })
+    }
+
+    /// Save `metadata`, read back if `paranoid`, returning the paths to
+    /// commit
+    pub(crate) fn save(&self, metadata: &Metadata, paranoid: bool) -> io::Result<Vec<String>> {
+        // The first save to a path in a directory creates it
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let digest = metadata.save_to_file(&self.path)?;
+        if paranoid {
+            verify_written(&self.path, &digest)?;
+        }
+        Ok(self.files(metadata.layout()))
+    }
+
+    /// The paths metadata in `layout` is stored in, relative to the root of
+    /// the clone
+    pub(crate) fn files(&self, layout: MetadataLayout) -> Vec<String> {
+        let mut files = vec![self.repo_path.clone()];
+        if layout == MetadataLayout::Sharded {
+            files.push(self.shard_repo_dir());
+        }
+        files
+    }
+
+    /// [`SHARD_DIR`] next to the metadata file, relative to the root of the
+    /// clone
+    fn shard_repo_dir(&self) -> String {
+        match self.repo_path.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/{}", dir, SHARD_DIR),
+            None => SHARD_DIR.to_string(),
+        }
+    }
+}
+
+/// What [`migrate_metadata`] converts to
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    /// Layout to store the metadata in
+    pub layout: MetadataLayout,
+}
+
+impl MigrateOptions {
+    /// Convert to the sharded layout, as the CLI does by default
+    pub fn new() -> Self {
+        Self {
+            layout: MetadataLayout::Sharded,
+        }
+    }
+}
+
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Result of [`migrate_metadata`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Migrated {
+    /// Number of entries moved to the new layout
+    pub entries: usize,
+    /// Whether a commit was pushed; false when the metadata already had the
+    /// layout
+    pub committed: bool,
+}
+
+/// Convert a repository's metadata to another layout in one commit
+///
+/// Every entry is kept as it is; only the files holding them change. Going
+/// to [`MetadataLayout::Sharded`] moves the entries out of the metadata
+/// file into [`SHARD_DIR`], and going back to [`MetadataLayout::Single`]
+/// moves them into the metadata file again and removes the shards.
+///
+/// ```no_run
+/// use bitcache::{Context, MigrateOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let migrated = bitcache::migrate_metadata(&remote, &MigrateOptions::new(), &Context::default())?;
+/// println!("{} entries sharded", migrated.entries);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn migrate_metadata(
+    remote: &Remote,
+    opts: &MigrateOptions,
+    ctx: &Context,
+) -> io::Result<Migrated> {
+    migrate_metadata_in(None, remote, opts, ctx)
+}
+
+/// [`migrate_metadata`], in the clone kept by `pool` if given
+pub(crate) fn migrate_metadata_in(
+    pool: Option<&checkout::ClonePool>,
+    remote: &Remote,
+    opts: &MigrateOptions,
+    ctx: &Context,
+) -> io::Result<Migrated> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    publish::checked_branch(repo_dir, remote)?;
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    let message = format!("Convert bitcache metadata to the {} layout", opts.layout);
+
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    let mut migrated = Migrated::default();
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(push_backoff(attempt));
+            progress::emit(Event::Retry {
+                attempt,
+                attempts,
+                reason: &rejection,
+            });
+            status!(
+                "Push rejected, converting the new remote head (attempt {} of {})",
+                attempt,
+                attempts
+            );
+            storage::of(remote).reset(repo_dir, auth)?;
+        }
+
+        let mut metadata = metadata_file.load()?;
+        migrated.entries = metadata.len();
+        if metadata.layout() == opts.layout {
+            status!("The metadata already has the {} layout", opts.layout);
+            return Ok(migrated);
+        }
+        cancel::check()?;
+        status!(
+            "Converting {} entr{} to the {} layout...",
+            migrated.entries,
+            if migrated.entries == 1 { "y" } else { "ies" },
+            opts.layout
+        );
+        // Listed while the shards are still there, so their removal is
+        // committed too
+        let files = metadata_file.files(MetadataLayout::Sharded);
+        metadata.set_layout(opts.layout);
+        metadata_file.save(&metadata, ctx.paranoid)?;
+        if opts.layout == MetadataLayout::Single {
+            // The emptied directory stays, so git still finds it to stage
+            // the removals
+            remove_shards(&shard_dir(&metadata_file.path), |_| false)?;
+        }
+
+        status!("Committing and pushing changes...");
+        let files: Vec<&str> = files.iter().map(String::as_str).collect();
+        if !storage::of(remote).commit(repo_dir, &files, &message)? {
+            status!("No changes to commit");
+            return Ok(migrated);
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => break,
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+    migrated.committed = true;
+    Ok(migrated)
+}
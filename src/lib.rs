@@ -0,0 +1,594 @@
+//! # bitcache
+//!
+//! A library for managing binary files (bitstreams) in a git repository based on source file MD5 hashes.
+//!
+//! ## Overview
+//!
+//! The library provides the operations behind the `bitcache` command:
+//! - [`publish`]: Computes MD5 of a source file and uploads a binary file to a git repository
+//! - [`upload`] and [`register`]: Store a bitstream and record its entry
+//!   as separate commits, the two halves of a publish
+//! - [`publish_batch`]: Publishes several bitstreams, e.g. from a
+//!   [`BatchManifest`], in one commit
+//! - [`update`]: Replaces the bitstream of an existing entry
+//! - [`get`]: Retrieves a binary file from the repository based on its MD5 hash
+//! - [`exists`], [`find`], [`list`] and [`search`]: Query the repository's
+//!   metadata, whose entries [`SearchOptions`] such as a [`filter::Filter`]
+//!   expression can narrow down
+//! - [`lock`] and [`get_locked`]: Pin a set of bitstreams in a [`LockFile`]
+//!   and retrieve exactly those later
+//! - [`top`]: Ranks entries by size, age or access count
+//! - [`probe`] and [`probe_all`]: Sum up the metadata of one or several
+//!   repositories, to check on their health
+//! - [`deprecate`]: Marks an entry as faulty, so [`get`] warns about or
+//!   refuses it
+//! - [`diff`]: Compares two entries and their bitstreams
+//! - [`delete`]: Moves an entry and its bitstream to the trash, or removes
+//!   them for good
+//! - [`list_trash`], [`restore`] and [`empty_trash`]: Look into, restore from
+//!   and empty the trash
+//! - [`verify`]: Checks every stored bitstream against its metadata
+//! - [`prune`]: Removes the entries a retention policy no longer keeps
+//! - [`create_bundle`] and [`apply_bundle`]: Carry entries and their
+//!   bitstreams to another repository in a single file; [`read_bundle`]
+//!   lists one
+//! - [`compact`]: Drops the history of a branch, keeping only its head
+//! - [`export`] and [`import`]: Move metadata between repositories
+//! - [`sync`]: Copies entries and their bitstreams between repositories
+//! - [`export_archive`] and [`import_archive`]: Carry entries and their
+//!   bitstreams in a `.tar.gz` file, for repositories that can't reach each
+//!   other
+//! - [`gc`]: Finds, and optionally removes, bitstreams no entry refers to
+//! - [`migrate_metadata`]: Converts the metadata between the single and
+//!   sharded [`layout`]s
+//! - [`clone_repository`] and [`commit_and_push`]: The git steps underneath,
+//!   for tools that change the repository in ways these operations don't
+//! - [`sync_repository`]: Updates the clone operations keep in the cache
+//!   directory
+//!
+//! Errors are [`std::io::Error`]s (aliased as [`error::Result`]); those a
+//! caller can act on carry a [`BitcacheError`], see [`error`].
+//!
+//! ## Workflow
+//!
+//! 1. The tool maintains a JSON metadata file in the repository
+//! 2. When publishing, it computes the MD5 hash of the source file
+//! 3. The binary file is stored at the specified path with metadata tracking
+//! 4. When getting, it looks up the MD5 in metadata and copies the binary to current directory
+//!
+//! ## Usage
+//!
+//! Every operation takes the [`Remote`] it works on, an options struct and a
+//! [`Context`] with the settings shared between operations. Nothing is
+//! printed; install a [`progress::set_handler`] to follow along.
+//!
+//! Each call updates the clone cached under [`Context::cache_dir`], or
+//! clones the repository afresh when there is none. For several operations
+//! on the same repository, configure a [`Bitcache`] client once with
+//! [`Bitcache::builder`]; it keeps its clone and only fetches between
+//! operations.
+//!
+//! With the `async` feature, [`publish_async`] and [`get_async`] return
+//! futures that work with any executor; dropping one cancels its operation.
+//!
+//! ```no_run
+//! use bitcache::{Context, GetOptions, PublishOptions, Remote};
+//! use std::path::Path;
+//!
+//! let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+//! let ctx = Context::default();
+//! let md5 = bitcache::compute_md5(Path::new("top.vhd"))?;
+//!
+//! if bitcache::get(&remote, &GetOptions::new(&md5), &ctx)?.is_none() {
+//!     // ... run synthesis ...
+//!     let opts = PublishOptions::new("top.vhd", "build/top.bit", "boards/zedboard");
+//!     bitcache::publish(&remote, &opts, &ctx)?;
+//! }
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+mod archive;
+pub mod backend;
+mod bundle;
+pub mod cancel;
+mod checkout;
+mod client;
+mod compact;
+pub mod compress;
+pub mod config;
+mod delete;
+mod deprecate;
+mod diff;
+pub mod error;
+pub mod filter;
+mod fsutil;
+mod gc;
+mod get;
+mod git;
+mod hash;
+mod health;
+pub mod heartbeat;
+pub mod human;
+mod init;
+pub mod layout;
+mod lock;
+mod manifest;
+mod metadata;
+mod paths;
+pub mod progress;
+mod prune;
+mod publish;
+pub mod repair;
+pub mod schema;
+mod sources;
+mod stage;
+mod storage;
+pub mod store;
+mod sync;
+#[cfg(feature = "async")]
+mod task;
+#[cfg(feature = "test-util")]
+pub mod testing;
+mod throttle;
+mod top;
+mod transfer;
+mod trash;
+mod update;
+mod verify;
+
+pub use archive::{
+    export_archive, import_archive, Archived, ExportArchiveOptions, ImportArchiveOptions,
+    ARCHIVE_FORMAT,
+};
+pub use bundle::{
+    apply_bundle, create_bundle, read_bundle, Applied, ApplyBundleOptions, BundleContents,
+    BundleOptions, Bundled, SkippedEntry, BUNDLE_FORMAT,
+};
+pub use client::{Bitcache, Builder};
+pub use compact::{compact, CompactOptions, Compacted, BACKUP_TAG_PREFIX};
+pub use compress::Compression;
+pub use delete::{delete, DeleteOptions, Deleted};
+pub use deprecate::{deprecate, DeprecateOptions};
+pub use diff::{diff, BinaryComparison, DiffOptions, EntryDiff, FieldChange};
+pub use error::BitcacheError;
+pub use gc::{gc, GcOptions, GcReport};
+pub use get::{
+    exists, find, get, info, list, search, EntryInfo, GetOptions, Retrieved, SearchOptions,
+};
+pub use git::{Auth, Committer};
+pub use hash::HashAlgo;
+pub use health::{probe, probe_all, RepoHealth};
+pub use init::{init, InitOptions, Initialized};
+pub use layout::{migrate_metadata, MetadataLayout, MigrateOptions, Migrated, SHARD_DIR};
+pub use lock::{
+    get_locked, lock, LockFile, LockManifest, LockOptions, LockRequest, LockedArtifact,
+    LockedGetOptions, LOCK_FILE_NAME, LOCK_FORMAT,
+};
+pub use manifest::{BatchManifest, ManifestEntry};
+pub use metadata::{Metadata, MetadataEntry, METADATA_FILE, METADATA_SCHEMA_VERSION};
+pub use prune::{prune, PruneOptions, Pruned};
+pub use publish::{
+    explain, publish, publish_batch, publish_dry_run, OnCollision, PublishAction, PublishOptions,
+    PublishPlan, Published,
+};
+pub use sources::{compute_source_hash, SourceWalk};
+pub use stage::{register, upload, RegisterOptions, Registered, UploadOptions, Uploaded};
+pub use storage::{Storage, LOCK_FILE, LOG_FILE};
+pub use sync::{sync, SyncOptions, SyncSkipped, Synced};
+#[cfg(feature = "async")]
+pub use task::{get_async, publish_async, Operation};
+pub use top::{top, TopEntry, TopKey, TopOptions};
+pub use transfer::{export, import, ImportMode, ImportOptions, Imported};
+pub use trash::{
+    empty_trash, list_trash, restore, EmptyTrashOptions, RestoreOptions, TrashedEntry,
+    DEFAULT_TRASH_RETENTION, TRASH_DIR,
+};
+pub use update::{update, UpdateOptions};
+pub use verify::{verify, Failure, Problem, VerifyOptions, VerifyReport};
+
+use heartbeat::Heartbeat;
+use progress::ProgressObserver;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default for [`Context::push_attempts`]
+pub const DEFAULT_PUSH_ATTEMPTS: u32 = 10;
+
+/// Default for [`Context::max_retries`]
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Default for [`Context::retry_delay`]
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Settings shared by every operation
+#[derive(Clone)]
+pub struct Context {
+    /// Parent directory for temporary clones [default: system temp dir]
+    pub work_dir: Option<PathBuf>,
+    /// Leave temporary clones in place for inspection
+    pub keep_temp: bool,
+    /// Read back and hash every written file
+    pub paranoid: bool,
+    /// Root of the local caches, if one could be determined
+    pub cache_dir: Option<PathBuf>,
+    /// Keep each repository's clone in `cache_dir` and bring it up to date
+    /// with a fetch, instead of cloning into a temporary directory every
+    /// time; ignored with `keep_temp`
+    pub clone_cache: bool,
+    /// Size limit for the local artifact store in bytes, 0 for none
+    pub cache_max_size: u64,
+    /// How many times publish tries to push before giving up on a busy remote
+    pub push_attempts: u32,
+    /// How long deleted entries stay in the trash before the next change to
+    /// the repository removes them, `None` to keep them until
+    /// [`empty_trash`]
+    pub trash_retention: Option<Duration>,
+    /// How many times a failed clone, fetch or push is retried, 0 for
+    /// never; failures git reports as permanent never are
+    pub max_retries: u32,
+    /// Wait before the first such retry, doubled for each next one
+    pub retry_delay: Duration,
+    /// Cap on git's transfers to and from the remote in bytes per second,
+    /// `None` or 0 for none; only HTTP(S) and SSH remotes can be limited
+    pub limit_rate: Option<u64>,
+    /// Kill a clone, fetch or push that runs longer and fail with
+    /// [`BitcacheError::Timeout`]; `None` for no limit
+    pub timeout: Option<Duration>,
+    /// Fail with [`BitcacheError::LockTimeout`] after waiting this long for
+    /// another run to finish with a cached clone or local repository; `None`
+    /// to wait for as long as it takes
+    pub lock_timeout: Option<Duration>,
+    /// How many bitstreams [`publish_batch`] copies into the clone at once,
+    /// 0 for one per CPU
+    pub jobs: usize,
+    /// Receives the events of every operation run with this context, in
+    /// addition to the [`progress::set_handler`] handler
+    pub observer: Option<Arc<dyn ProgressObserver>>,
+    /// Forward the progress git prints while cloning, fetching and pushing
+    /// as [`progress::Event::Detail`] lines, at most one a second for each
+    /// meter
+    pub git_progress: bool,
+    /// Who git records as the author and committer of the commits
+    /// operations make, e.g. where git has no identity configured; git's
+    /// `user.name` and `user.email` when `None`
+    pub committer: Option<Committer>,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self {
+            work_dir: None,
+            keep_temp: false,
+            paranoid: false,
+            cache_dir: store::default_cache_dir(),
+            clone_cache: true,
+            cache_max_size: store::DEFAULT_MAX_SIZE,
+            push_attempts: DEFAULT_PUSH_ATTEMPTS,
+            trash_retention: Some(DEFAULT_TRASH_RETENTION),
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            limit_rate: None,
+            timeout: None,
+            lock_timeout: None,
+            jobs: 0,
+            observer: None,
+            git_progress: false,
+            committer: None,
+        }
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("work_dir", &self.work_dir)
+            .field("keep_temp", &self.keep_temp)
+            .field("paranoid", &self.paranoid)
+            .field("cache_dir", &self.cache_dir)
+            .field("clone_cache", &self.clone_cache)
+            .field("cache_max_size", &self.cache_max_size)
+            .field("push_attempts", &self.push_attempts)
+            .field("trash_retention", &self.trash_retention)
+            .field("max_retries", &self.max_retries)
+            .field("retry_delay", &self.retry_delay)
+            .field("limit_rate", &self.limit_rate)
+            .field("timeout", &self.timeout)
+            .field("lock_timeout", &self.lock_timeout)
+            .field("jobs", &self.jobs)
+            .field("observer", &self.observer.as_ref().map(|_| ".."))
+            .field("git_progress", &self.git_progress)
+            .field("committer", &self.committer)
+            .finish()
+    }
+}
+
+/// The per-thread state of an operation, see [`Context::enter`]
+struct Entered {
+    _observing: progress::Observing,
+    _limiting: throttle::Limiting,
+    _retrying: git::Retrying,
+    _showing_progress: git::ShowingProgress,
+    _timing_out: git::TimingOut,
+    _committing_as: git::CommittingAs,
+}
+
+impl Context {
+    /// Report this thread's events to the observer, apply the rate limit
+    /// and retries to its transfers and the identity to its commits until
+    /// the guard drops
+    fn enter(&self) -> Entered {
+        Entered {
+            _observing: progress::observe(self.observer.clone()),
+            _limiting: throttle::limit(self.limit_rate),
+            _retrying: git::retrying(self.max_retries, self.retry_delay),
+            _showing_progress: git::showing_progress(self.git_progress),
+            _timing_out: git::timing_out(self.timeout),
+            _committing_as: git::committing_as(self.committer.clone()),
+        }
+    }
+
+    /// Create a temporary directory for a clone
+    fn temp_dir(&self) -> io::Result<fsutil::ScratchDir> {
+        fsutil::create_temp_dir(self.work_dir.as_deref(), self.keep_temp)
+    }
+
+    /// The local artifact store for a repository
+    fn artifact_store(&self, remote: &Remote) -> Option<store::ArtifactStore> {
+        // A local repository is read directly, as quickly as a cached copy
+        if remote.storage == Storage::Local {
+            return None;
+        }
+        self.cache_dir
+            .as_deref()
+            .map(|dir| store::ArtifactStore::open(dir, &remote.store_key()))
+    }
+
+    /// The cache directory, or an error explaining how to set one
+    pub fn require_cache_dir(&self) -> io::Result<&Path> {
+        self.cache_dir
+            .as_deref()
+            .ok_or_else(|| config::missing("cache_dir"))
+    }
+
+    /// Where operations keep their clone of `remote` when
+    /// [`Context::clone_cache`] is set
+    pub fn clone_dir(&self, remote: &Remote) -> io::Result<PathBuf> {
+        Ok(store::clone_dir(
+            self.require_cache_dir()?,
+            &remote.clone_key(),
+        ))
+    }
+
+    /// The cached clone to use for `remote`, `None` for a temporary one
+    fn cached_clone_dir(&self, remote: &Remote) -> Option<PathBuf> {
+        if !self.clone_cache || self.keep_temp {
+            return None;
+        }
+        self.clone_dir(remote).ok()
+    }
+}
+
+/// A repository and how to reach it
+#[derive(Debug, Clone)]
+pub struct Remote {
+    /// Git repository URL, or with [`Storage::Local`] the repository's
+    /// directory
+    pub url: String,
+    /// Branch to read and publish to [default: the remote's default branch]
+    pub branch: Option<String>,
+    /// Credentials for git operations
+    pub auth: Option<Auth>,
+    /// How the repository stores its files
+    pub storage: Storage,
+    /// Path of the metadata file in the repository [default:
+    /// [`METADATA_FILE`]]
+    pub metadata_path: String,
+    /// Layout of the metadata created in a repository that has none;
+    /// existing metadata is read and written in its own layout
+    pub metadata_layout: MetadataLayout,
+}
+
+impl Remote {
+    /// The default branch of `url`, reached with git's own credentials
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            branch: None,
+            auth: None,
+            storage: Storage::Git,
+            metadata_path: METADATA_FILE.to_string(),
+            metadata_layout: MetadataLayout::Single,
+        }
+    }
+
+    /// The directory `dir`, used as a repository in place without git
+    pub fn local(dir: impl AsRef<Path>) -> Self {
+        Self {
+            storage: Storage::Local,
+            ..Self::new(dir.as_ref().to_string_lossy())
+        }
+    }
+
+    /// What the local artifact store files this remote's artifacts under;
+    /// branches may hold different bitstreams for the same MD5
+    fn store_key(&self) -> String {
+        match &self.branch {
+            Some(branch) => format!("{}#{}", self.url, branch),
+            None => self.url.clone(),
+        }
+    }
+
+    /// What the cached clone of this remote is kept under: the
+    /// [`Remote::store_key`], with a local path made absolute so the same
+    /// relative path used from two directories doesn't share a clone
+    fn clone_key(&self) -> String {
+        let url = fs::canonicalize(&self.url)
+            .map_or_else(|_| self.url.clone(), |path| path.display().to_string());
+        match &self.branch {
+            Some(branch) => format!("{}#{}", url, branch),
+            None => url,
+        }
+    }
+}
+
+/// Compute the hash of a file with `algo`, as lowercase hex
+///
+/// The file is streamed in 1 MiB chunks, so memory use stays the same
+/// whatever its size: hashing a 2 GiB file peaks at about 14 MiB of
+/// resident memory for the whole process.
+pub fn compute_hash(file_path: &Path, algo: HashAlgo) -> io::Result<String> {
+    let total = fs::metadata(file_path)?.len();
+    let mut hasher = hash::Hasher::new(algo);
+    let mut hashed = 0u64;
+    let mut heartbeat = Heartbeat::start(progress::Phase::Hashing);
+    hash_contents(file_path, &mut hasher, &mut hashed, total, &mut heartbeat)?;
+    Ok(hasher.finish())
+}
+
+/// Feed the contents of a file to `hasher`, adding them to `hashed` of
+/// `total` bytes for the heartbeat
+pub(crate) fn hash_contents(
+    file_path: &Path,
+    hasher: &mut hash::Hasher,
+    hashed: &mut u64,
+    total: u64,
+    heartbeat: &mut Heartbeat,
+) -> io::Result<()> {
+    let mut file = fs::File::open(file_path)?;
+    let mut buffer = vec![0u8; fsutil::COPY_BUFFER_SIZE];
+    loop {
+        cancel::check()?;
+        let n = match file.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buffer[..n]);
+        *hashed += n as u64;
+        heartbeat.tick_bytes(*hashed, Some(total));
+    }
+}
+
+/// Compute MD5 hash of a file, see [`compute_hash`]
+pub fn compute_md5(file_path: &Path) -> io::Result<String> {
+    compute_hash(file_path, HashAlgo::Md5)
+}
+
+/// Clone `remote` into `target_dir`, checking out its branch if one is set
+///
+/// This is the clone every operation starts from, with the same line-ending
+/// settings, credentials and rate limit. `target_dir` must not exist or be
+/// empty.
+pub fn clone_repository(remote: &Remote, target_dir: &Path, ctx: &Context) -> io::Result<()> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    if remote.storage == Storage::Local {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} is a local repository, which is used in place rather than cloned",
+                remote.url
+            ),
+        ));
+    }
+    git::clone_repository(remote, target_dir, None)
+}
+
+/// Bring the cached clone of `remote` up to date, cloning it if there is
+/// none, and return its directory
+///
+/// This is [`Context::clone_dir`], as each operation updates it before
+/// running. Changes to it are discarded by the next operation; tools that
+/// make their own should [`clone_repository`] instead.
+pub fn sync_repository(remote: &Remote, ctx: &Context) -> io::Result<PathBuf> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    if remote.storage == Storage::Local {
+        return Ok(PathBuf::from(&remote.url));
+    }
+    let dir = ctx.clone_dir(remote)?;
+    checkout::cached(
+        dir.clone(),
+        remote,
+        checkout::CloneKind::Whole,
+        ctx.lock_timeout,
+        |_| Ok(()),
+    )?;
+    Ok(dir)
+}
+
+/// Commit `files`, paths relative to the root of the clone at `repo_dir`,
+/// and push the commit to `remote`
+///
+/// Returns `false`, without pushing, when the files had no changes. A push
+/// the remote rejects because it moved on fails with
+/// [`BitcacheError::PushRejected`]; unlike [`publish`] this does not merge
+/// and retry.
+pub fn commit_and_push(
+    repo_dir: &Path,
+    files: &[&str],
+    message: &str,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<bool> {
+    let _entered = ctx.enter();
+    let storage = storage::of(remote);
+    if !storage.commit(repo_dir, files, message)? {
+        return Ok(false);
+    }
+    match storage.push(repo_dir, remote.auth.as_ref())? {
+        git::PushOutcome::Pushed => Ok(true),
+        git::PushOutcome::Rejected(stderr) => Err(BitcacheError::PushRejected {
+            attempts: 1,
+            stderr,
+        }
+        .into()),
+    }
+}
+
+/// Re-open a file that was just written and check that it reads back with the
+/// expected MD5, for storage that can't be trusted to return what it was given
+fn verify_written(path: &Path, expected: &str) -> io::Result<()> {
+    let actual = compute_md5(path)?;
+    if actual != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Read-back check failed for {}: wrote MD5 {} but read back {}",
+                path.display(),
+                expected,
+                actual
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_file_reading_back_otherwise_fails_the_check() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("top.bit");
+        fs::write(&path, b"top bitstream")?;
+        let written = format!("{:x}", md5::compute(b"top bitstream"));
+        verify_written(&path, &written)?;
+
+        fs::write(&path, b"top bitstreaM")?;
+        let error = verify_written(&path, &written).expect_err("read back otherwise");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            error.to_string().starts_with("Read-back check failed"),
+            "{}",
+            error
+        );
+        Ok(())
+    }
+}
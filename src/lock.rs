@@ -0,0 +1,540 @@
+//! Pinning a set of bitstreams for reproducible retrieval.
+//!
+//! [`lock`] resolves what a [`LockManifest`] asks for to concrete entries and
+//! writes them to a [`LockFile`]; [`get_locked`] retrieves exactly those
+//! entries later, failing if the repository no longer has them as they were.
+//! The manifest is a TOML file with one `[[artifact]]` table per bitstream,
+//! each naming it and picking its entry by exactly one of `source` or `md5`:
+//!
+//! ```toml
+//! [[artifact]]
+//! name = "zedboard"
+//! source = "rtl/top.vhd"
+//!
+//! [[artifact]]
+//! name = "arty"
+//! md5 = "d3699e851d7f4fde53ee37c037408af7"
+//! variant = "arty-a7"
+//! ```
+//!
+//! A relative `source` is relative to the directory of the manifest, and is
+//! hashed with `hash_algo`, MD5 unless given. `variant` picks among the
+//! bitstreams published for a source with several. The lock file lists the artifacts
+//! sorted by name, one field per line, so it diffs well in the repository of
+//! the project that consumes it.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::{self, BitcacheError};
+use crate::get;
+use crate::git;
+use crate::layout::MetadataFile;
+use crate::metadata::is_variant_name;
+use crate::progress::status;
+use crate::storage::{self, Storage};
+use crate::{
+    compute_hash, compute_md5, fsutil, paths, Context, GetOptions, HashAlgo, Metadata,
+    MetadataEntry, Remote, Retrieved,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Version of the lock file format [`LockFile::save`] writes
+pub const LOCK_FORMAT: u32 = 1;
+
+/// Name the CLI gives the lock file unless told otherwise
+pub const LOCK_FILE_NAME: &str = "bitcache.lock";
+
+/// First lines of every lock file written
+const LOCK_HEADER: &str =
+    "# Pinned bitcache artifacts, written by `bitcache lock`; do not edit by hand\n\n";
+
+/// The bitstreams a [`lock`] should pin
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockManifest {
+    /// The artifacts, in any order
+    #[serde(rename = "artifact", default)]
+    pub artifacts: Vec<LockRequest>,
+}
+
+/// One artifact of a [`LockManifest`]
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockRequest {
+    /// Name of the artifact in the lock file, and of the directory
+    /// [`get_locked`] saves it in
+    pub name: String,
+    /// Source file to hash, to pin the entry published for it
+    #[serde(default)]
+    pub source: Option<PathBuf>,
+    /// Algorithm to hash `source` with, as it was published [default: md5]
+    #[serde(default)]
+    pub hash_algo: Option<String>,
+    /// Hash of the source, to pin the entry published for it
+    #[serde(default)]
+    pub md5: Option<String>,
+    /// [`MetadataEntry::name`] of the variant to pin, required when the
+    /// source has several
+    #[serde(default)]
+    pub variant: Option<String>,
+}
+
+impl LockManifest {
+    /// Read and check the manifest at `path`
+    ///
+    /// Every artifact needs a distinct name of letters, digits, `.`, `_` and
+    /// `-` and exactly one of `source` and `md5`; a `variant` is made of the
+    /// same characters.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read lock manifest {}: {}", path.display(), e),
+            )
+        })?;
+        let invalid = |problem: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid lock manifest {}: {}", path.display(), problem),
+            )
+        };
+        let mut manifest = toml::from_str::<Self>(&content).map_err(|e| invalid(e.to_string()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut names = HashSet::new();
+        for request in &mut manifest.artifacts {
+            // Also the name of a directory get_locked creates
+            if !is_variant_name(&request.name) {
+                return Err(invalid(format!(
+                    "artifact name '{}' must use letters, digits, '.', '_' and '-', and cannot start with '.' or '-'",
+                    request.name
+                )));
+            }
+            if !names.insert(request.name.clone()) {
+                return Err(invalid(format!(
+                    "artifact '{}' is listed twice",
+                    request.name
+                )));
+            }
+            if let Some(variant) = request.variant.as_deref().filter(|v| !is_variant_name(v)) {
+                return Err(invalid(format!(
+                    "artifact '{}' has variant '{}', which must use letters, digits, '.', '_' and '-', and cannot start with '.' or '-'",
+                    request.name, variant
+                )));
+            }
+            if request.source.is_some() == request.md5.is_some() {
+                return Err(invalid(format!(
+                    "artifact '{}' needs exactly one of source and md5",
+                    request.name
+                )));
+            }
+            if let Some(algo) = &request.hash_algo {
+                algo.parse::<HashAlgo>().map_err(|e| {
+                    invalid(format!("artifact '{}' has hash_algo {}", request.name, e))
+                })?;
+            }
+            if let Some(source) = &mut request.source {
+                *source = base.join(&*source);
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// A set of pinned artifacts, as written by [`lock`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockFile {
+    /// [`LOCK_FORMAT`] of the file
+    pub format: u32,
+    /// Repository the artifacts were pinned from, with any credentials
+    /// redacted
+    pub repository: String,
+    /// The artifacts, sorted by name
+    #[serde(rename = "artifact", default)]
+    pub artifacts: Vec<LockedArtifact>,
+}
+
+/// One pinned artifact of a [`LockFile`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockedArtifact {
+    /// Name from the [`LockManifest`]
+    pub name: String,
+    /// Hash of the source of the pinned entry
+    pub md5: String,
+    /// Variant of the pinned entry, as the manifest gave it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// Path of the bitstream in the repository
+    pub path: String,
+    /// MD5 of the bitstream as stored
+    pub binary_md5: String,
+    /// Size of the bitstream as stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Commit the repository was at when the artifact was pinned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+impl LockedArtifact {
+    /// What about `entry`, whose bitstream is `bitstream`, no longer
+    /// matches this pin, if anything
+    fn drift(&self, entry: &MetadataEntry, bitstream: &Path) -> io::Result<Option<String>> {
+        if entry.binary_path != self.path {
+            return Ok(Some(format!(
+                "its bitstream moved to {}",
+                entry.binary_path
+            )));
+        }
+        let (binary_md5, size) = digest(bitstream)?;
+        if binary_md5 != self.binary_md5 {
+            return Ok(Some(format!(
+                "its bitstream MD5 is {} instead of {}",
+                binary_md5, self.binary_md5
+            )));
+        }
+        Ok(match self.size {
+            Some(pinned) if size != pinned => Some(format!(
+                "its bitstream is {} bytes instead of {}",
+                size, pinned
+            )),
+            _ => None,
+        })
+    }
+}
+
+/// MD5 and size of the bitstream at `path`
+fn digest(path: &Path) -> io::Result<(String, u64)> {
+    let path = paths::long_path(path);
+    Ok((compute_md5(&path)?, fs::metadata(&path)?.len()))
+}
+
+impl LockFile {
+    /// Read and parse the lock file at `path`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read lock file {}: {}", path.display(), e),
+            )
+        })?;
+        let lock = toml::from_str::<Self>(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse lock file {}: {}", path.display(), e),
+            )
+        })?;
+        if lock.format != LOCK_FORMAT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Lock file {} has format {}, but this version of bitcache reads format {}",
+                    path.display(),
+                    lock.format,
+                    LOCK_FORMAT
+                ),
+            ));
+        }
+        Ok(lock)
+    }
+
+    /// The lock file as written by [`Self::save`]
+    pub fn to_toml(&self) -> io::Result<String> {
+        let body = toml::to_string(self).map_err(io::Error::other)?;
+        Ok(format!("{}{}", LOCK_HEADER, body))
+    }
+
+    /// Write the lock file to `path`, replacing it atomically
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fsutil::atomic_write(path, self.to_toml()?.as_bytes())
+    }
+
+    /// The artifact named `name`, if any
+    pub fn artifact(&self, name: &str) -> Option<&LockedArtifact> {
+        self.artifacts.iter().find(|artifact| artifact.name == name)
+    }
+}
+
+/// What [`lock`] resolves and what it may keep
+#[derive(Debug, Clone, Default)]
+pub struct LockOptions {
+    /// The artifacts to pin
+    pub manifest: LockManifest,
+    /// Lock file written before, whose pins are kept where they still
+    /// satisfy the manifest and match the repository; `None` to resolve
+    /// every artifact afresh, as `lock --update` does
+    pub previous: Option<LockFile>,
+}
+
+impl LockOptions {
+    /// Resolve every artifact of `manifest` afresh
+    pub fn new(manifest: LockManifest) -> Self {
+        Self {
+            manifest,
+            previous: None,
+        }
+    }
+}
+
+/// Resolve the artifacts of a [`LockManifest`] to the entries they pin
+///
+/// An artifact that matches no entry fails with [`BitcacheError::NotFound`].
+/// The MD5 and size pinned are those of the bitstream in the repository.
+/// When [`LockOptions::previous`] is given, an artifact that it pins to an
+/// entry the manifest still selects keeps that pin while the entry is
+/// unchanged, so that only changed artifacts move.
+///
+/// ```no_run
+/// use bitcache::{Context, LockManifest, LockOptions, Remote};
+/// use std::path::Path;
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let manifest = LockManifest::load(Path::new("inputs.toml"))?;
+/// let lock = bitcache::lock(&remote, &LockOptions::new(manifest), &Context::default())?;
+/// lock.save(Path::new("bitcache.lock"))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn lock(remote: &Remote, opts: &LockOptions, ctx: &Context) -> io::Result<LockFile> {
+    lock_in(None, remote, opts, ctx)
+}
+
+/// [`lock`], in the clone kept by `pool` if given
+pub(crate) fn lock_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &LockOptions,
+    ctx: &Context,
+) -> io::Result<LockFile> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let count = opts.manifest.artifacts.len();
+    status!(
+        "Resolving {} artifact{}...",
+        count,
+        if count == 1 { "" } else { "s" }
+    );
+    let checkout = checkout::checkout_shallow(pool, remote, ctx)?;
+    let repo_dir = checkout.dir();
+    let metadata = load_metadata(repo_dir, remote)?;
+    let commit = match remote.storage {
+        Storage::Git => git::head_commit(repo_dir)?,
+        Storage::Local => None,
+    };
+
+    let mut artifacts = Vec::with_capacity(opts.manifest.artifacts.len());
+    for request in &opts.manifest.artifacts {
+        let md5 = match (&request.md5, &request.source) {
+            (Some(md5), _) => md5.trim().to_ascii_lowercase(),
+            (None, Some(source)) => {
+                let algo = match &request.hash_algo {
+                    Some(algo) => algo.parse().map_err(io::Error::other)?,
+                    None => HashAlgo::default(),
+                };
+                compute_hash(source, algo).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("Cannot hash source file {}: {}", source.display(), e),
+                    )
+                })?
+            }
+            (None, None) => unreachable!("LockManifest::load requires a selector"),
+        };
+        let Some(entry) = metadata.select(&md5, request.variant.as_deref())? else {
+            return Err(BitcacheError::NotFound { md5 }.into());
+        };
+        let bitstream = entry.stored_in(repo_dir)?;
+
+        let kept = opts
+            .previous
+            .as_ref()
+            .and_then(|previous| previous.artifact(&request.name))
+            .filter(|pinned| pinned.md5 == entry.md5 && pinned.variant == request.variant);
+        if let Some(pinned) = kept {
+            if pinned.drift(entry, &bitstream)?.is_none() {
+                artifacts.push(pinned.clone());
+                continue;
+            }
+        }
+
+        let (binary_md5, size) = digest(&bitstream)?;
+        status!("Pinned {} to MD5 {}", request.name, entry.md5);
+        artifacts.push(LockedArtifact {
+            name: request.name.clone(),
+            md5: entry.md5.clone(),
+            variant: request.variant.clone(),
+            path: entry.binary_path.clone(),
+            binary_md5,
+            size: Some(size),
+            commit: commit.clone(),
+        });
+    }
+    artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(LockFile {
+        format: LOCK_FORMAT,
+        repository: error::redact(&remote.url),
+        artifacts,
+    })
+}
+
+/// The metadata of `remote` in its clone at `repo_dir`, empty before the
+/// first publish
+fn load_metadata(repo_dir: &Path, remote: &Remote) -> io::Result<Metadata> {
+    MetadataFile::of(repo_dir, remote).load_or_new()
+}
+
+/// Where [`get_locked`] saves the artifacts of a lock file
+#[derive(Debug, Clone)]
+pub struct LockedGetOptions {
+    /// Directory each artifact is saved in a subdirectory of, named after
+    /// the artifact
+    pub output: PathBuf,
+    /// Serve bitstreams from the local artifact store when it has them
+    pub use_local_cache: bool,
+    /// Replace files already saved there
+    pub force: bool,
+}
+
+impl LockedGetOptions {
+    /// Save into `output`, with the same defaults as the CLI
+    pub fn new(output: impl Into<PathBuf>) -> Self {
+        Self {
+            output: output.into(),
+            use_local_cache: true,
+            force: false,
+        }
+    }
+}
+
+/// Retrieve exactly the artifacts a lock file pins
+///
+/// Every artifact is first looked up in the repository, and nothing is saved
+/// if any of them is gone or its entry no longer matches the pin: another
+/// bitstream, path or size. Each bitstream is then saved to
+/// `output/<name>/` and checked against its pinned MD5.
+///
+/// ```no_run
+/// use bitcache::{Context, LockFile, LockedGetOptions, Remote};
+/// use std::path::Path;
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let lock = LockFile::load(Path::new("bitcache.lock"))?;
+/// let opts = LockedGetOptions::new("build/bitstreams");
+/// for retrieved in bitcache::get_locked(&remote, &lock, &opts, &Context::default())? {
+///     println!("saved {}", retrieved.path.display());
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn get_locked(
+    remote: &Remote,
+    lock: &LockFile,
+    opts: &LockedGetOptions,
+    ctx: &Context,
+) -> io::Result<Vec<Retrieved>> {
+    get_locked_in(None, remote, lock, opts, ctx)
+}
+
+/// [`get_locked`], in the clone kept by `pool` if given
+pub(crate) fn get_locked_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    lock: &LockFile,
+    opts: &LockedGetOptions,
+    ctx: &Context,
+) -> io::Result<Vec<Retrieved>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let count = lock.artifacts.len();
+    status!(
+        "Checking {} pinned artifact{}...",
+        count,
+        if count == 1 { "" } else { "s" }
+    );
+    let mut drifted = Vec::new();
+    // The name to get each artifact by, where its source has several
+    let mut names = Vec::with_capacity(lock.artifacts.len());
+    {
+        // Released before the gets, which take the pooled clone in turn
+        let checkout = checkout::checkout_shallow(pool, remote, ctx)?;
+        let metadata = load_metadata(checkout.dir(), remote)?;
+        for artifact in &lock.artifacts {
+            let variants = metadata.variants(&artifact.md5);
+            let pinned = match &artifact.variant {
+                Some(variant) => metadata.lookup(&artifact.md5, variant),
+                None => variants
+                    .iter()
+                    .find(|entry| entry.binary_path == artifact.path)
+                    .or(variants.first()),
+            };
+            names.push(
+                pinned
+                    .filter(|_| variants.len() > 1)
+                    .map(|entry| entry.name().to_string()),
+            );
+            match pinned {
+                None => drifted.push(format!(
+                    "{}: the repository has no entry for MD5 {}",
+                    artifact.name, artifact.md5
+                )),
+                Some(entry) => {
+                    let bitstream = entry.stored_in(checkout.dir())?;
+                    if let Some(drift) = artifact.drift(entry, &bitstream)? {
+                        drifted.push(format!("{}: {}", artifact.name, drift));
+                    }
+                }
+            }
+        }
+    }
+    if !drifted.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "The repository no longer matches the lock file, so nothing was saved; run lock --update to pin what it has now:\n  {}",
+                drifted.join("\n  ")
+            ),
+        ));
+    }
+
+    let mut retrieved = Vec::with_capacity(lock.artifacts.len());
+    for (artifact, name) in lock.artifacts.iter().zip(names) {
+        let dir = opts.output.join(&artifact.name);
+        fs::create_dir_all(&dir)?;
+        let mut get_opts = GetOptions {
+            variant: name,
+            output: Some(dir),
+            use_local_cache: opts.use_local_cache,
+            force: opts.force,
+            ..GetOptions::new(&artifact.md5)
+        };
+        let not_found = || -> io::Error {
+            BitcacheError::NotFound {
+                md5: artifact.md5.clone(),
+            }
+            .into()
+        };
+        let mut got = get::get_in(pool, remote, &get_opts, ctx)?.ok_or_else(not_found)?;
+        // The local artifact store keeps serving an entry replaced since,
+        // which the repository itself was just checked not to have
+        if got.from_cache && artifact.drift(&got.entry, &got.path)?.is_some() {
+            fs::remove_file(&got.path)?;
+            get_opts.use_local_cache = false;
+            got = get::get_in(pool, remote, &get_opts, ctx)?.ok_or_else(not_found)?;
+        }
+        if let Some(drift) = artifact.drift(&got.entry, &got.path)? {
+            let _ = fs::remove_file(&got.path);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The bitstream retrieved for {} does not match the lock file: {}",
+                    artifact.name, drift
+                ),
+            ));
+        }
+        retrieved.push(got);
+    }
+    Ok(retrieved)
+}
@@ -1,378 +1,4302 @@
-//! # bitcache
+//! The `bitcache` command.
 //!
-//! A tool for managing binary files (bitstreams) in a git repository based on source file MD5 hashes.
-//!
-//! ## Overview
-//!
-//! This tool provides two main operations:
-//! - **publish**: Computes MD5 of a source file and uploads a binary file to a git repository
-//! - **get**: Retrieves a binary file from the repository based on its MD5 hash
-//!
-//! ## Workflow
-//!
-//! 1. The tool maintains a JSON metadata file in the repository
-//! 2. When publishing, it computes the MD5 hash of the source file
-//! 3. The binary file is stored at the specified path with metadata tracking
-//! 4. When getting, it looks up the MD5 in metadata and copies the binary to current directory
-
-use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+//! A thin command-line front end for the [`bitcache`] library: it parses the
+//! arguments, layers them with the environment and config files, and prints
+//! the progress and results the library reports.
+
+mod indicator;
+mod output;
+mod plugin;
+mod version;
+
+use bitcache::filter::{self, Filter};
+use bitcache::human::{ByteSize, OutputStyle, TimeStyle};
+use bitcache::repair::Rejected;
+use bitcache::{
+    cancel, config, error, heartbeat, repair, schema, store, ApplyBundleOptions, Auth,
+    BatchManifest, BinaryComparison, BitcacheError, BundleOptions, CompactOptions, Compression,
+    Context, DeleteOptions, DeprecateOptions, DiffOptions, EmptyTrashOptions, ExportArchiveOptions,
+    GcOptions, GetOptions, HashAlgo, ImportArchiveOptions, ImportMode, ImportOptions, InitOptions,
+    LockFile, LockManifest, LockOptions, LockedGetOptions, Metadata, MetadataEntry, MetadataLayout,
+    MigrateOptions, OnCollision, Problem, PruneOptions, PublishAction, PublishOptions, Published,
+    RegisterOptions, Remote, RepoHealth, RestoreOptions, Retrieved, SearchOptions, SourceWalk,
+    SyncOptions, Synced, TopKey, TopOptions, UpdateOptions, UploadOptions, VerifyOptions,
+};
+use chrono::{DateTime, FixedOffset};
+use clap::{
+    error::ErrorKind, value_parser, ArgAction, ArgGroup, Args, CommandFactory, Parser, Subcommand,
+    ValueEnum,
+};
+use output::{outcome, status, EnvFormat, Formatter, LogLevel};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, IsTerminal, Read};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Command-line interface for bitcache
+#[derive(Parser)]
+#[command(
+    about = "Binary file cache manager using git and MD5 hashing",
+    long_about = None,
+    disable_version_flag = true
+)]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
+
+    /// Print version (combine with --verbose for build details)
+    #[arg(short = 'V', long, action = ArgAction::SetTrue)]
+    version: bool,
+
+    /// List the plugin commands found on PATH
+    #[arg(long)]
+    list_plugins: bool,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+/// Options shared by every subcommand
+#[derive(Args)]
+struct GlobalArgs {
+    /// Print exact byte counts and milliseconds instead of human-readable units
+    #[arg(long, global = true)]
+    raw_units: bool,
+
+    /// How to display timestamps [default: utc]
+    #[arg(long, global = true, value_enum)]
+    time: Option<TimeStyle>,
+
+    /// Print the result as one JSON document on stdout, and a failure as a
+    /// JSON object with an `error` field; progress goes to stderr
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// How get and list print entries; json is the same as --json, and csv
+    /// prints a header row and one row per entry [default: human]
+    #[arg(long, global = true, value_enum, value_name = "FORMAT")]
+    format: Option<Formatter>,
+
+    /// Seconds between progress lines when stderr is not a terminal, 0 disables [default: 30]
+    #[arg(long, global = true, value_name = "SECS")]
+    heartbeat: Option<u64>,
+
+    /// Directory for temporary clones and staging files [default: system temp dir]
+    #[arg(long, global = true, value_name = "DIR")]
+    work_dir: Option<PathBuf>,
+
+    /// Directory for the local artifact cache and cached clones [default: ~/.cache/bitcache]
+    #[arg(long, global = true, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// Clone into a temporary directory instead of reusing the clone kept in the cache directory
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    /// Keep temporary clones instead of deleting them, and print their paths
+    #[arg(long, global = true)]
+    keep_temp: bool,
+
+    /// Re-read every file bitcache writes and compare its MD5 before going on
+    #[arg(long, global = true)]
+    paranoid: bool,
+
+    /// Maximum total size of the local artifact cache, 0 for no limit [default: 10G]
+    #[arg(long, global = true, value_name = "SIZE")]
+    cache_max_size: Option<ByteSize>,
+
+    /// Cap git transfers to and from HTTP(S) and SSH remotes at this many bytes per second, e.g. 2M; 0 for no limit
+    #[arg(long, global = true, value_name = "RATE")]
+    limit_rate: Option<ByteSize>,
+
+    /// Retry a push the remote rejected because another run pushed first this many times, merging with its changes each time [default: 9]
+    #[arg(long, global = true, value_name = "N")]
+    push_retries: Option<u32>,
+
+    /// Days deleted entries stay in the trash before the next change to the repository removes them; 0 keeps them until `trash empty` [default: 30]
+    #[arg(long, global = true, value_name = "DAYS")]
+    trash_retention_days: Option<u64>,
+
+    /// Retry a failed clone, fetch or push this many times, unless git reports the failure as permanent [default: 3]
+    #[arg(long, global = true, value_name = "N")]
+    max_retries: Option<u32>,
+
+    /// Milliseconds to wait before the first retry, doubled for each next one [default: 500]
+    #[arg(long, global = true, value_name = "MS")]
+    retry_delay_ms: Option<u64>,
+
+    /// Kill a clone, fetch or push still running after this many seconds; 0 for no limit [default: 0]
+    #[arg(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Give up after waiting this many seconds for another run to finish with the cached clone or local repository; 0 to wait for as long as it takes [default: 0]
+    #[arg(long, global = true, value_name = "SECS")]
+    lock_timeout: Option<u64>,
+
+    /// Print more detail, including git's progress while cloning, fetching
+    /// and pushing
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Print only errors, the data a command is asked for and the line
+    /// summing up what it did
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// How much to print: error, info or debug, as --quiet, the default or
+    /// --verbose; only set from BITCACHE_LOG or the config files
+    #[arg(skip)]
+    log: Option<LogLevel>,
+
+    /// Access token for the HTTPS host of --repo, sent as the password of the user x-access-token; prefer BITCACHE_TOKEN, which other users can't read from the process list
+    #[arg(long, global = true, value_name = "TOKEN")]
+    token: Option<String>,
+
+    /// Where the repository lives: `git` for the git remote named by --repo, `dir` for a directory --repo names by path or file:// URL, used in place without git, or `local:<DIR>` for the directory DIR in place of --repo [default: git]
+    #[arg(long, global = true, value_name = "BACKEND")]
+    backend: Option<BackendArg>,
+
+    /// Path of the metadata file in the repository [default: bitcache_metadata.json]
+    #[arg(long, global = true, value_name = "PATH")]
+    metadata_path: Option<String>,
+
+    /// How init lays out a new metadata file: `single` keeps every entry in it, `sharded` keeps each hash in its own file under .bitcache/; existing metadata keeps its layout until migrate-metadata converts it [default: single]
+    #[arg(long, global = true, value_name = "LAYOUT", value_enum)]
+    metadata_layout: Option<MetadataLayout>,
+}
+
+/// Available subcommands
+#[derive(Subcommand)]
+enum Commands {
+    /// Seed a new repository with an empty metadata file
+    Init(InitArgs),
+    /// Publish a binary file to the repository
+    Publish(PublishArgs),
+    /// Store a bitstream without recording an entry for it, then print its
+    /// path in the repository for register
+    Upload(UploadArgs),
+    /// Record an entry for a bitstream already in the repository, e.g. one
+    /// stored with upload
+    Register(RegisterArgs),
+    /// Publish the bitstreams listed in a manifest in one commit
+    PublishBatch(PublishBatchArgs),
+    /// Replace the bitstream of an existing entry, keeping its MD5 and path
+    Update(UpdateArgs),
+    /// Get a binary file from the repository by MD5
+    Get(GetArgs),
+    /// Get the binary built from a local source file; exits 2 if there is none
+    GetBySource(GetBySourceArgs),
+    /// Check whether a source has a bitstream; exits 0 if it has, 1 if not
+    /// and 2 if the repository can't be read
+    Exists(ExistsArgs),
+    /// Show an entry without retrieving its binary; exits 2 if there is none
+    Info(InfoArgs),
+    /// Compare two entries and their bitstreams; exits 1 if the bitstreams
+    /// differ, 2 if an entry is missing
+    Diff(DiffArgs),
+    /// Recover a damaged metadata file
+    Repair(RepairArgs),
+    /// Convert the metadata file to the sharded layout or back
+    MigrateMetadata(MigrateMetadataArgs),
+    /// List the entries in the repository
+    List(ListArgs),
+    /// Check the syntax of a --filter expression and print how it groups
+    FilterCheck {
+        /// Expression to check, e.g. "source ~ 'top*' and age > 60d"
+        #[arg(value_name = "EXPR")]
+        expression: String,
+    },
+    /// Find entries by source file name or tag; exits 2 if none match
+    Search(SearchArgs),
+    /// Move an entry to the trash, or remove it for good
+    Delete(DeleteArgs),
+    /// Mark an entry as faulty, keeping it, so get warns about it
+    Deprecate(DeprecateArgs),
+    /// List, restore or empty the entries deleted into the trash
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
+    /// Rank entries by size or age
+    Top(TopArgs),
+    /// Pin the bitstreams a manifest asks for in a lock file
+    Lock(LockArgs),
+    /// Sum up the health of one or several repositories; exits 1 if any
+    /// can't be probed
+    Status(StatusArgs),
+    /// Carry entries and their bitstreams to another repository in a single file
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommand,
+    },
+    /// Remove the entries older than a given age, keeping the newest of each source
+    Prune(PruneArgs),
+    /// Replace the history of a branch with one commit holding its head
+    Compact(CompactArgs),
+    /// Check every bitstream against its metadata
+    Verify(VerifyArgs),
+    /// Find bitstreams no entry refers to; exits 3 if any are left
+    Gc(GcArgs),
+    /// Save the repository's metadata, or an archive with its bitstreams, to a local file
+    Export(ExportArgs),
+    /// Add the entries of an exported metadata file or archive to the repository
+    Import(ImportArgs),
+    /// Copy entries and their bitstreams from one repository to another
+    Sync(SyncArgs),
+    /// Inspect the effective configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Manage the local caches
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Print the JSON Schema of a document bitcache reads or writes
+    Schema {
+        /// Document to print the schema of
+        #[arg(value_enum)]
+        document: SchemaDocument,
+    },
+    /// Print version and environment details for a bug report
+    BugReport,
+    /// Run the `bitcache-<COMMAND>` plugin found on PATH
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
+}
+
+/// Documents `schema` can describe
+#[derive(Clone, Copy, ValueEnum)]
+enum SchemaDocument {
+    /// The bitcache_metadata.json file
+    Metadata,
+    /// The output of `publish --explain`
+    Plan,
+    /// A publish-batch manifest, in its JSON form
+    Manifest,
+    /// The output of `get --json`
+    Get,
+    /// The output of `list --json`
+    List,
+    /// The output of `verify --json`
+    Verify,
+}
+
+/// Subcommands of `config`
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print every option with its effective value and where it came from
+    Show,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove every artifact from the local artifact cache, and the cached clones
+    Clean {
+        /// Only remove the artifacts no get has used, and the clones no
+        /// command has updated, for this long, such as 30d or 12h
+        #[arg(long, value_name = "AGE", value_parser = filter::parse_duration)]
+        older_than: Option<Duration>,
+    },
+}
+
+/// Subcommands of `trash`
+#[derive(Subcommand)]
+enum TrashCommand {
+    /// List the entries in the trash, oldest first
+    List(TrashListArgs),
+    /// Put a trashed entry and its bitstream back
+    Restore(TrashRestoreArgs),
+    /// Remove trashed entries and their bitstreams for good
+    Empty(TrashEmptyArgs),
+}
+
+/// Subcommands of `bundle`
+#[derive(Subcommand)]
+enum BundleCommand {
+    /// Write entries and their bitstreams to a bundle file
+    Create(BundleCreateArgs),
+    /// Check a bundle and publish its entries in one commit
+    Apply(BundleApplyArgs),
+    /// List the entries of a bundle without applying it
+    List(BundleListArgs),
+}
+
+/// The repository a subcommand reads and publishes to
+#[derive(Args)]
+struct RepoArgs {
+    /// Git repository URL
+    #[arg(long)]
+    repo: Option<String>,
+
+    /// Path to SSH private key for git operations
+    #[arg(long)]
+    ssh_key: Option<PathBuf>,
+
+    /// Branch to read and publish to [default: the remote's default branch]
+    #[arg(long)]
+    branch: Option<String>,
+}
+
+impl RepoArgs {
+    fn repository(&self) -> io::Result<Remote> {
+        repository(&self.repo, &self.ssh_key, &self.branch)
+    }
+
+    fn layer(&mut self, config: &config::Config) -> io::Result<()> {
+        self.repo = config.layer("repo", self.repo.take())?;
+        scope_token(self.repo.as_deref());
+        self.ssh_key = config.layer("ssh_key", self.ssh_key.take())?;
+        self.branch = config.layer("branch", self.branch.take())?;
+        Ok(())
+    }
+}
+
+/// The repository a subcommand only reads
+#[derive(Args, Clone)]
+struct ReadRepoArgs {
+    /// Git repository URL
+    #[arg(long)]
+    repo: Option<String>,
+
+    /// Path to SSH private key for git operations
+    #[arg(long)]
+    ssh_key: Option<PathBuf>,
+
+    /// Branch or tag to read [default: the remote's default branch]
+    #[arg(long)]
+    branch: Option<String>,
+}
+
+impl ReadRepoArgs {
+    fn repository(&self) -> io::Result<Remote> {
+        repository(&self.repo, &self.ssh_key, &self.branch)
+    }
+
+    fn layer(&mut self, config: &config::Config) -> io::Result<()> {
+        self.repo = config.layer("repo", self.repo.take())?;
+        scope_token(self.repo.as_deref());
+        self.ssh_key = config.layer("ssh_key", self.ssh_key.take())?;
+        self.branch = config.layer("branch", self.branch.take())?;
+        Ok(())
+    }
+}
+
+/// Which files of a --source directory are hashed
+#[derive(Args, Clone, Copy)]
+struct SourceWalkArgs {
+    /// Hash the files and directories in a --source directory whose names
+    /// start with '.', such as .git, instead of skipping them
+    #[arg(long)]
+    include_hidden: bool,
+
+    /// Follow symlinks in a --source directory instead of skipping them
+    #[arg(long)]
+    include_symlinks: bool,
+}
+
+impl SourceWalkArgs {
+    fn walk(self) -> SourceWalk {
+        SourceWalk {
+            include_hidden: self.include_hidden,
+            include_symlinks: self.include_symlinks,
+        }
+    }
+}
+
+/// Arguments of the publish subcommand
+#[derive(Args)]
+struct PublishArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Source file path, or a directory of source files; repeat to hash
+    /// several together
+    #[arg(long, required = true)]
+    source: Vec<PathBuf>,
 
-/// Metadata entry for a cached binary file
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct MetadataEntry {
-    /// MD5 hash of the source file
+    #[command(flatten)]
+    walk: SourceWalkArgs,
+
+    /// Binary file (bitstream) path
+    #[arg(long)]
+    bitstream: PathBuf,
+
+    /// Target directory path in the repository
+    #[arg(long)]
+    path: Option<PathBuf>,
+
+    /// Print the publish plan as JSON without modifying the repository
+    #[arg(long)]
+    explain: bool,
+
+    /// Print what the publish would do without modifying the repository
+    #[arg(long, conflicts_with = "explain")]
+    dry_run: bool,
+
+    /// Refuse a --source or --bitstream that is a symlink instead of following it
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// File name to store the bitstream under, instead of its local name
+    #[arg(long, value_name = "NAME")]
+    rename_in_repo: Option<PathBuf>,
+
+    /// Publish as this variant of the source, e.g. the board it targets or a
+    /// flash image, so bitstreams built from the same source don't replace
+    /// each other [default: the file name it is stored under]
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Publish even if the source file is empty
+    #[arg(long)]
+    allow_empty_source: bool,
+
+    /// Algorithm to hash the source file with [default: md5]
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+
+    /// Store the bitstream compressed, with .gz or .zst appended to its
+    /// name; zstd if given without a value [default: none]
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "zstd", value_name = "COMPRESSION")]
+    compress: Option<Compression>,
+
+    /// Level for --compress: gzip 1 to 9 [default: 6], zstd 1 to 22
+    /// [default: 3]
+    #[arg(long, value_name = "LEVEL", value_parser = value_parser!(u8).range(1..=22))]
+    compress_level: Option<u8>,
+
+    /// Record a KEY=VALUE tag in the entry; repeat for more
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    tags: Vec<Tag>,
+
+    /// Replace an existing entry for the hash that stores a different
+    /// bitstream, or commit the same one again, e.g. to record new tags
+    #[arg(long)]
+    force: bool,
+
+    /// Fail when the hash already has an entry under this name, even one
+    /// storing this exact bitstream
+    #[arg(long, conflicts_with = "force")]
+    fail_if_exists: bool,
+
+    /// What to do when the path holds another entry's bitstream: store this
+    /// one under its name prefixed with the hash, fail, or replace it and
+    /// remove that entry [default: fail]
+    #[arg(long, value_enum, value_name = "ACTION")]
+    on_collision: Option<OnCollision>,
+
+    /// Operating system to record the bitstream as built for [default: this
+    /// machine's]
+    #[arg(long, value_name = "OS")]
+    os: Option<String>,
+
+    /// Architecture to record the bitstream as built for [default: this
+    /// machine's]
+    #[arg(long, value_name = "ARCH")]
+    arch: Option<String>,
+
+    /// Version of the tool that built the bitstream, e.g. "Vivado 2023.2"
+    #[arg(long, value_name = "VERSION")]
+    build_tool_version: Option<String>,
+
+    /// Who to record as publishing the bitstream [default: git user.name
+    /// and user.email]
+    #[arg(long, value_name = "NAME")]
+    author: Option<String>,
+
+    /// Commit of the source's repository to record [default: HEAD of the
+    /// git work tree --source is in]
+    #[arg(long, value_name = "COMMIT", conflicts_with = "no_source_commit")]
+    source_commit: Option<String>,
+
+    /// Record no source commit instead of looking it up
+    #[arg(long)]
+    no_source_commit: bool,
+}
+
+/// Arguments of the upload subcommand
+#[derive(Args)]
+struct UploadArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Binary file (bitstream) to store
+    #[arg(long)]
+    bitstream: PathBuf,
+
+    /// Target directory path in the repository
+    #[arg(long)]
+    path: Option<PathBuf>,
+
+    /// Refuse a --bitstream that is a symlink instead of following it
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// File name to store the bitstream under, instead of its local name
+    #[arg(long, value_name = "NAME")]
+    rename_in_repo: Option<PathBuf>,
+}
+
+/// Arguments of the register subcommand
+#[derive(Args)]
+struct RegisterArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Hash of the source file, MD5 unless --hash-algo says otherwise
+    #[arg(long, visible_alias = "hash")]
     md5: String,
-    /// Path to the binary file in the repository
+
+    /// Name of the source file to record
+    #[arg(long, value_name = "NAME")]
+    source_name: String,
+
+    /// Path of the bitstream in the repository, as upload prints it
+    #[arg(long, value_name = "PATH")]
     binary_path: String,
-    /// Original source filename
-    source_file: String,
-    /// Timestamp of publication
-    timestamp: String,
+
+    /// MD5 of the bitstream to record; when the branch has the file, its
+    /// MD5 is recorded instead and must match
+    #[arg(long, value_name = "MD5")]
+    binary_md5: Option<String>,
+
+    /// Variant to record the bitstream as, see publish --variant
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Record a KEY=VALUE tag in the entry; repeat for more
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    tags: Vec<Tag>,
+
+    /// Operating system to record the bitstream as built for [default: this
+    /// machine's]
+    #[arg(long, value_name = "OS")]
+    os: Option<String>,
+
+    /// Architecture to record the bitstream as built for [default: this
+    /// machine's]
+    #[arg(long, value_name = "ARCH")]
+    arch: Option<String>,
+
+    /// Version of the tool that built the bitstream, e.g. "Vivado 2023.2"
+    #[arg(long, value_name = "VERSION")]
+    build_tool_version: Option<String>,
+
+    /// Who to record as publishing the bitstream [default: git user.name
+    /// and user.email]
+    #[arg(long, value_name = "NAME")]
+    author: Option<String>,
+
+    /// Algorithm --md5 was computed with [default: md5]
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+}
+
+/// Arguments of the publish-batch subcommand
+#[derive(Args)]
+struct PublishBatchArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// TOML manifest with one [[entry]] table (source, bitstream, path) per
+    /// bitstream, or JSON if the name ends in .json
+    #[arg(long)]
+    manifest: PathBuf,
+
+    /// Copy this many bitstreams into the clone at once [default: one per CPU]
+    #[arg(short = 'j', long, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Target directory path in the repository for entries without a path
+    #[arg(long)]
+    path: Option<PathBuf>,
+
+    /// Refuse sources and bitstreams that are symlinks instead of following them
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// Publish even if a source file is empty
+    #[arg(long)]
+    allow_empty_source: bool,
+
+    /// Algorithm to hash the source files with [default: md5]
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+
+    /// Store the bitstreams compressed, with .gz or .zst appended to their
+    /// names; zstd if given without a value [default: none]
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "zstd", value_name = "COMPRESSION")]
+    compress: Option<Compression>,
+
+    /// Level for --compress: gzip 1 to 9 [default: 6], zstd 1 to 22
+    /// [default: 3]
+    #[arg(long, value_name = "LEVEL", value_parser = value_parser!(u8).range(1..=22))]
+    compress_level: Option<u8>,
+
+    /// Record a KEY=VALUE tag in every entry; repeat for more
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    tags: Vec<Tag>,
+
+    /// Variant for entries of the manifest without one
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Replace existing entries for the hashes that store a different
+    /// bitstream, or commit the same ones again
+    #[arg(long)]
+    force: bool,
+
+    /// Fail when any hash already has an entry under its name, even one
+    /// storing the same bitstream
+    #[arg(long, conflicts_with = "force")]
+    fail_if_exists: bool,
+
+    /// What to do when a path holds another entry's bitstream: store the new
+    /// one under its name prefixed with the hash, fail, or replace it and
+    /// remove that entry [default: fail]
+    #[arg(long, value_enum, value_name = "ACTION")]
+    on_collision: Option<OnCollision>,
+
+    /// Operating system to record the bitstreams as built for [default:
+    /// this machine's]
+    #[arg(long, value_name = "OS")]
+    os: Option<String>,
+
+    /// Architecture to record the bitstreams as built for [default: this
+    /// machine's]
+    #[arg(long, value_name = "ARCH")]
+    arch: Option<String>,
+
+    /// Version of the tool that built the bitstreams, e.g. "Vivado 2023.2"
+    #[arg(long, value_name = "VERSION")]
+    build_tool_version: Option<String>,
+
+    /// Who to record as publishing the bitstreams [default: git user.name
+    /// and user.email]
+    #[arg(long, value_name = "NAME")]
+    author: Option<String>,
+
+    /// Commit of the sources' repository to record for every entry
+    /// [default: HEAD of the git work tree each source is in]
+    #[arg(long, value_name = "COMMIT", conflicts_with = "no_source_commit")]
+    source_commit: Option<String>,
+
+    /// Record no source commits instead of looking them up
+    #[arg(long)]
+    no_source_commit: bool,
+}
+
+/// Arguments of the update subcommand
+#[derive(Args)]
+struct UpdateArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Hash of the source file whose entry to update
+    #[arg(long, visible_alias = "hash")]
+    md5: String,
+
+    /// Variant to update; required when the source has several
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// The corrected binary file (bitstream)
+    #[arg(long)]
+    bitstream: PathBuf,
+
+    /// Refuse a --bitstream that is a symlink instead of following it
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// Add a KEY=VALUE tag to the entry, replacing one with the same key;
+    /// repeat for more
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    tags: Vec<Tag>,
+}
+
+/// Arguments of the get subcommand
+#[derive(Args)]
+#[command(group(ArgGroup::new("key").required(true).multiple(true).args(["md5", "source", "locked"])))]
+struct GetArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Hash of the source file, MD5 unless it was published with another
+    /// --hash-algo [default: the hash of --source]
+    #[arg(long, visible_alias = "hash")]
+    md5: Option<String>,
+
+    /// Algorithm --md5 was computed with, checked against its length, and
+    /// to hash --source with [default: the one the entry was published
+    /// with, or md5 without --md5]
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+
+    /// Retrieve every artifact pinned in this lock file, written by lock,
+    /// into a directory per artifact under --output, from the repository
+    /// it records unless --repo is given
+    #[arg(
+        long,
+        value_name = "LOCK_FILE",
+        conflicts_with_all = ["md5", "filter_tags", "source", "env", "sync", "refuse_deprecated", "skip_verify", "hash_algo"]
+    )]
+    locked: Option<PathBuf>,
+
+    /// Repository to try when --repo fails or lacks the entry; repeat for
+    /// more, tried in order
+    #[arg(long = "fallback-repo", value_name = "URL")]
+    fallback_repos: Vec<String>,
+
+    /// Local source file, or directory of source files, to check against
+    /// the entry before saving anything, or without --md5 to find it by;
+    /// repeat for a source of several
+    #[arg(long)]
+    source: Vec<PathBuf>,
+
+    #[command(flatten)]
+    walk: SourceWalkArgs,
+
+    /// Variant to retrieve; required when the source has several
+    #[arg(
+        long,
+        visible_alias = "name",
+        value_name = "NAME",
+        conflicts_with = "locked"
+    )]
+    variant: Option<String>,
+
+    /// Only consider bitstreams tagged KEY=VALUE; repeat to require several
+    /// tags
+    #[arg(long = "filter-tag", visible_alias = "where", value_name = "KEY=VALUE")]
+    filter_tags: Vec<Tag>,
+
+    /// Print shell variable assignments (PREFIX_PATH, PREFIX_MD5, PREFIX_HIT, ...)
+    /// on stdout for `eval`, sending all other output to stderr
+    #[arg(long, value_name = "PREFIX", num_args = 0..=1, default_missing_value = "BITCACHE_")]
+    env: Option<String>,
+
+    /// Shell syntax for --env output
+    #[arg(long, value_enum, default_value_t = EnvFormat::Posix, requires = "env")]
+    env_format: EnvFormat,
+
+    /// Always fetch from the repository, bypassing the local artifact cache
+    #[arg(long)]
+    no_local_cache: bool,
+
+    /// Flush the saved bitstream and its directory to disk before reporting
+    /// success
+    #[arg(long)]
+    sync: bool,
+
+    /// Where to save the bitstream: a file path, or a directory to save into
+    /// (an existing one, or one ending in a separator); missing directories
+    /// are created [default: current directory]
+    #[arg(short, long, visible_alias = "dest", value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Replace the saved file if it already exists, at --output or in the
+    /// current directory
+    #[arg(long)]
+    force: bool,
+
+    /// Fail on an entry marked with deprecate instead of only warning
+    #[arg(long)]
+    refuse_deprecated: bool,
+
+    /// Don't hash the saved bitstream to check it against the MD5 recorded
+    /// when it was published, e.g. for large files where that is slow
+    #[arg(long, visible_alias = "no-verify")]
+    skip_verify: bool,
+
+    /// Directory to save into when the current directory is not writable;
+    /// only set from BITCACHE_OUTPUT_DIR or the config files
+    #[arg(skip)]
+    output_dir: Option<PathBuf>,
+}
+
+/// Arguments of the get-by-source subcommand
+#[derive(Args)]
+#[command(group(ArgGroup::new("key").required(true).args(["source", "source_md5_hint"])))]
+struct GetBySourceArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Repository to try when --repo fails or lacks the entry; repeat for
+    /// more, tried in order
+    #[arg(long = "fallback-repo", value_name = "URL")]
+    fallback_repos: Vec<String>,
+
+    /// Source file, or directory of source files, to hash; the bitstream
+    /// published for it is retrieved. Repeat for a source of several
+    #[arg(long)]
+    source: Vec<PathBuf>,
+
+    #[command(flatten)]
+    walk: SourceWalkArgs,
+
+    /// Hash of the source file, for when the file itself is not at hand
+    #[arg(long, value_name = "HASH")]
+    source_md5_hint: Option<String>,
+
+    /// Variant to retrieve; required when the source has several
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Algorithm to hash --source with, as it was published [default: md5]
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+
+    /// Where to save the bitstream: a file path, or a directory to save into
+    /// (an existing one, or one ending in a separator); missing directories
+    /// are created [default: current directory]
+    #[arg(short, long, visible_alias = "dest", value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Replace the saved file if it already exists, at --output or in the
+    /// current directory
+    #[arg(long)]
+    force: bool,
+
+    /// Fail on an entry marked with deprecate instead of only warning
+    #[arg(long)]
+    refuse_deprecated: bool,
+
+    /// Don't hash the saved bitstream to check it against the MD5 recorded
+    /// when it was published, e.g. for large files where that is slow
+    #[arg(long, visible_alias = "no-verify")]
+    skip_verify: bool,
+
+    /// Always fetch from the repository, bypassing the local artifact cache
+    #[arg(long)]
+    no_local_cache: bool,
+
+    /// Directory to save into when the current directory is not writable;
+    /// only set from BITCACHE_OUTPUT_DIR or the config files
+    #[arg(skip)]
+    output_dir: Option<PathBuf>,
+}
+
+/// Arguments of the exists subcommand
+#[derive(Args)]
+#[command(group(ArgGroup::new("key").required(true).args(["md5", "source"])))]
+struct ExistsArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Hash of the source file, MD5 unless it was published with another
+    /// --hash-algo
+    #[arg(long, visible_alias = "hash")]
+    md5: Option<String>,
+
+    /// Source file, or directory of source files, to hash; repeat for a
+    /// source of several
+    #[arg(long)]
+    source: Vec<PathBuf>,
+
+    #[command(flatten)]
+    walk: SourceWalkArgs,
+
+    /// Only count a hit for this variant [default: any]
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Algorithm to hash --source with, as it was published [default: md5]
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+}
+
+/// Arguments of the info subcommand
+#[derive(Args)]
+#[command(group(ArgGroup::new("key").required(true).args(["md5", "source"])))]
+struct InfoArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Hash of the source file, MD5 unless it was published with another
+    /// --hash-algo
+    #[arg(long, visible_alias = "hash")]
+    md5: Option<String>,
+
+    /// Source file, or directory of source files, to hash; the entry
+    /// published for it is shown. Repeat for a source of several
+    #[arg(long)]
+    source: Vec<PathBuf>,
+
+    #[command(flatten)]
+    walk: SourceWalkArgs,
+
+    /// Variant to show; required when the source has several
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Algorithm to hash --source with, as it was published [default: md5]
+    #[arg(long, value_enum, value_name = "ALGO")]
+    hash_algo: Option<HashAlgo>,
+}
+
+/// Arguments of the diff subcommand
+#[derive(Args)]
+struct DiffArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Hash of the source of the first entry
+    #[arg(long, value_name = "HASH")]
+    md5_a: String,
+
+    /// Hash of the source of the second entry
+    #[arg(long, value_name = "HASH")]
+    md5_b: String,
+
+    /// Variant of the first entry; required when its source has several
+    #[arg(long, value_name = "NAME")]
+    variant_a: Option<String>,
+
+    /// Variant of the second entry; required when its source has several
+    #[arg(long, value_name = "NAME")]
+    variant_b: Option<String>,
+}
+
+/// Arguments of the repair subcommand
+#[derive(Args)]
+struct RepairArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Commit and push the repaired metadata instead of only reporting
+    #[arg(long)]
+    yes: bool,
+}
+
+/// Arguments of the migrate-metadata subcommand
+#[derive(Args)]
+struct MigrateMetadataArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Layout to convert the metadata to
+    #[arg(long, value_enum, default_value_t = MetadataLayout::Sharded)]
+    layout: MetadataLayout,
+}
+
+/// Arguments of the list subcommand
+#[derive(Args)]
+struct ListArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Only list entries whose source file name matches this glob (`*`, `?`
+    /// and `[...]`)
+    #[arg(long, value_name = "GLOB")]
+    source_filter: Option<String>,
+
+    /// Only list entries published after this RFC 3339 instant
+    #[arg(long, value_name = "TIME")]
+    since: Option<DateTime<FixedOffset>>,
+
+    /// Only list entries this expression picks, e.g. "source ~ 'top*' and
+    /// age > 60d"; see filter-check
+    #[arg(long, value_name = "EXPR")]
+    filter: Option<Filter>,
+
+    /// Only list entries tagged KEY=VALUE; repeat to require several tags
+    #[arg(long = "filter-tag", visible_alias = "where", value_name = "KEY=VALUE")]
+    filter_tags: Vec<Tag>,
+}
+
+/// Arguments of the search subcommand
+#[derive(Args)]
+#[command(group(ArgGroup::new("query").required(true).multiple(true).args(["source_pattern", "tags", "filter"])))]
+struct SearchArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Only show entries whose source file name matches this glob (`*`, `?`
+    /// and `[...]`)
+    #[arg(long, value_name = "GLOB")]
+    source_pattern: Option<String>,
+
+    /// Only show entries tagged KEY=VALUE; repeat to require several tags
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    tags: Vec<Tag>,
+
+    /// Only show entries this expression picks, e.g. "size > 50M and not
+    /// deprecated"; see filter-check
+    #[arg(long, value_name = "EXPR")]
+    filter: Option<Filter>,
+}
+
+/// Arguments of the deprecate subcommand
+#[derive(Args)]
+struct DeprecateArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Hash of the source file whose entry to deprecate
+    #[arg(long, visible_alias = "hash")]
+    md5: String,
+
+    /// Variant to deprecate; required when the source has several
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Why the bitstream should no longer be used
+    #[arg(long, value_name = "TEXT", required_unless_present = "undo")]
+    reason: Option<String>,
+
+    /// Hash of the source whose entry get should suggest instead
+    #[arg(long, value_name = "HASH")]
+    replacement: Option<String>,
+
+    /// Clear the deprecation instead, with its reason and replacement
+    #[arg(long, conflicts_with_all = ["reason", "replacement"])]
+    undo: bool,
+}
+
+/// Arguments of the delete subcommand
+#[derive(Args)]
+struct DeleteArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Hash of the source file whose entry to delete
+    #[arg(long, visible_alias = "hash")]
+    md5: String,
+
+    /// Variant to delete; required when the source has several
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+
+    /// Remove the entry for good instead of moving it to the trash, leaving the bitstream in the repository
+    #[arg(long, visible_alias = "keep-binary")]
+    hard: bool,
+
+    /// Remove the entry and its bitstream for good; implies --hard
+    #[arg(long)]
+    purge_binary: bool,
+}
+
+/// Arguments of the trash list subcommand
+#[derive(Args)]
+struct TrashListArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+}
+
+/// Arguments of the trash restore subcommand
+#[derive(Args)]
+struct TrashRestoreArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Hash of the source file whose entry to restore
+    #[arg(long, visible_alias = "hash")]
+    md5: String,
+
+    /// Variant to restore; required when the trash has several of the
+    /// source
+    #[arg(long, visible_alias = "name", value_name = "NAME")]
+    variant: Option<String>,
+}
+
+/// Arguments of the trash empty subcommand
+#[derive(Args)]
+struct TrashEmptyArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Only remove the entries past --trash-retention-days
+    #[arg(long)]
+    expired: bool,
+}
+
+/// Arguments of the verify subcommand
+#[derive(Args)]
+struct VerifyArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Remove the entries that fail from the metadata and push the result
+    /// to --branch
+    #[arg(long)]
+    fix: bool,
+}
+
+/// Arguments of the init subcommand
+#[derive(Args)]
+struct InitArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Replace an existing metadata file with an empty one, dropping its
+    /// entries
+    #[arg(long)]
+    force: bool,
+}
+
+/// Arguments of the gc subcommand
+#[derive(Args)]
+struct GcArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Remove the orphaned bitstreams from the repository and push the result
+    #[arg(long)]
+    prune: bool,
+}
+
+/// Arguments of the export subcommand
+#[derive(Args)]
+struct ExportArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// File to write the metadata to; a name ending in .tar.gz or .tgz
+    /// writes an archive with the bitstreams too
+    #[arg(short, long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// Replace --output if it already exists
+    #[arg(long)]
+    force: bool,
+
+    /// Only export the entries for this source hash; repeat for several
+    #[arg(long, visible_alias = "hash", value_name = "HASH")]
+    md5: Vec<String>,
+}
+
+/// Arguments of the import subcommand
+#[derive(Args)]
+struct ImportArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Metadata file or archive written by export
+    #[arg(long, value_name = "FILE")]
+    input: PathBuf,
+
+    /// Keep the repository's own entries and add the imported ones (the
+    /// default)
+    #[arg(long, conflicts_with = "replace")]
+    merge: bool,
+
+    /// Remove the repository's entries that the file does not have, and take
+    /// the file's entry wherever both differ
+    #[arg(long)]
+    replace: bool,
+
+    /// When merging, replace entries that differ from the imported ones
+    /// instead of skipping them
+    #[arg(long, conflicts_with = "replace")]
+    overwrite: bool,
+}
+
+/// Arguments of the sync subcommand
+#[derive(Args)]
+struct SyncArgs {
+    /// Repository to copy entries from: a git URL, or a directory with --backend dir
+    #[arg(long, value_name = "URL")]
+    from: String,
+
+    /// Repository to copy entries to: a git URL, or a directory with --backend dir
+    #[arg(long, value_name = "URL")]
+    to: String,
+
+    /// Path to SSH private key for git operations on both repositories
+    #[arg(long)]
+    ssh_key: Option<PathBuf>,
+
+    /// Branch or tag of --from to read [default: the remote's default branch]
+    #[arg(long, value_name = "BRANCH")]
+    from_branch: Option<String>,
+
+    /// Branch of --to to publish to [default: the remote's default branch]
+    #[arg(long, value_name = "BRANCH")]
+    to_branch: Option<String>,
+
+    /// Only sync the entries for this source hash; repeat for several
+    #[arg(long, visible_alias = "hash", value_name = "HASH")]
+    md5: Vec<String>,
+
+    /// Replace entries of --to whose bitstream path or checksum differs
+    /// instead of skipping them
+    #[arg(long)]
+    overwrite: bool,
+
+    /// Print what would be synced and exit without modifying --to
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Arguments of the top subcommand
+#[derive(Args)]
+struct TopArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// What to rank entries by
+    #[arg(long, value_enum, default_value_t = TopKey::Size)]
+    by: TopKey,
+
+    /// Number of entries to show, 0 for all
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 10)]
+    limit: usize,
+
+    /// Only rank bitstreams in this directory of the repository
+    #[arg(long = "path", value_name = "PREFIX")]
+    prefix: Option<String>,
+}
+
+/// Arguments of the lock subcommand
+#[derive(Args)]
+struct LockArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// TOML manifest with one [[artifact]] table (name, and source or md5)
+    /// per bitstream to pin
+    #[arg(long)]
+    manifest: PathBuf,
+
+    /// Lock file to write; pins it already has are kept while the manifest
+    /// still selects them
+    #[arg(short, long, value_name = "PATH", default_value = bitcache::LOCK_FILE_NAME)]
+    output: PathBuf,
+
+    /// Pin every artifact afresh, ignoring the pins in --output
+    #[arg(long)]
+    update: bool,
+}
+
+/// Arguments of the status subcommand
+#[derive(Args)]
+struct StatusArgs {
+    /// Git repository URL; repeat to probe several at once [default: the
+    /// configured repo]
+    #[arg(long, value_name = "URL")]
+    repo: Vec<String>,
+
+    /// Path to SSH private key for git operations
+    #[arg(long)]
+    ssh_key: Option<PathBuf>,
+
+    /// Branch or tag to read [default: the remote's default branch]
+    #[arg(long)]
+    branch: Option<String>,
+}
+
+/// Arguments of the bundle create subcommand
+#[derive(Args)]
+struct BundleCreateArgs {
+    #[command(flatten)]
+    remote: ReadRepoArgs,
+
+    /// Only bundle the entry for this source hash; may be repeated
+    #[arg(long, value_name = "HASH")]
+    md5: Vec<String>,
+
+    /// Only bundle the entries whose bitstream is in this directory of the repository
+    #[arg(long = "path", value_name = "PREFIX")]
+    prefix: Option<String>,
+
+    /// Bundle file to write, replaced if it exists
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+/// Arguments of the bundle apply subcommand
+#[derive(Args)]
+struct BundleApplyArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Bundle written by `bundle create`
+    #[arg(value_name = "BUNDLE")]
+    bundle: PathBuf,
+
+    /// Replace entries the repository has with a different bitstream instead of skipping them
+    #[arg(long)]
+    overwrite: bool,
+}
+
+/// Arguments of the bundle list subcommand
+#[derive(Args)]
+struct BundleListArgs {
+    /// Bundle written by `bundle create`
+    #[arg(value_name = "BUNDLE")]
+    bundle: PathBuf,
+}
+
+/// Arguments of the prune subcommand
+#[derive(Args)]
+#[command(group(
+    ArgGroup::new("policy")
+        .args(["older_than", "keep_latest", "filter", "orphans"])
+        .multiple(true)
+        .required(true)
+))]
+struct PruneArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Only prune entries published longer ago than this, such as 90d or 12w
+    #[arg(long, value_name = "AGE", value_parser = filter::parse_duration)]
+    older_than: Option<Duration>,
+
+    /// Always keep the N newest entries of each source file, counting each --variant apart
+    #[arg(long, value_name = "N")]
+    keep_latest: Option<usize>,
+
+    /// Only prune entries this expression picks, e.g. "tags.board = zedboard
+    /// and size > 50M"; see filter-check
+    #[arg(long, value_name = "EXPR")]
+    filter: Option<Filter>,
+
+    /// Also remove the bitstreams no entry refers to
+    #[arg(long)]
+    orphans: bool,
+
+    /// Remove the entries and their bitstreams for good instead of moving them to the trash
+    #[arg(long)]
+    hard: bool,
+
+    /// Print what would be removed without modifying the repository
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Arguments of the compact subcommand
+#[derive(Args)]
+struct CompactArgs {
+    #[command(flatten)]
+    remote: RepoArgs,
+
+    /// Rewrite and force-push the branch; without it only the sizes are
+    /// estimated
+    #[arg(long)]
+    yes: bool,
+}
+
+/// The `--token` for the HTTPS repositories on [`TOKEN_HOST`], set once
+/// the configuration is applied
+static TOKEN: OnceLock<String> = OnceLock::new();
+
+/// The host of the configured `--repo`, the only one the token is sent to,
+/// so a fallback, sync target or lock file naming another server never
+/// receives it
+static TOKEN_HOST: OnceLock<String> = OnceLock::new();
+
+/// Send the token to the host of `repo`, the repository the configuration
+/// names, if it is an HTTPS one
+fn scope_token(repo: Option<&str>) {
+    if let Some(host) = repo.and_then(https_host) {
+        let _ = TOKEN_HOST.set(host);
+    }
+}
+
+/// The host and port of an `https://` URL, lowercased and without any user
+/// name; `None` for every other kind of URL
+fn https_host(url: &str) -> Option<String> {
+    let rest = url
+        .get(.."https://".len())
+        .filter(|scheme| scheme.eq_ignore_ascii_case("https://"))
+        .map(|_| &url["https://".len()..])?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    Some(host.to_ascii_lowercase()).filter(|host| !host.is_empty())
+}
+
+/// The `--backend` the repository of every command is reached through,
+/// set once the configuration is applied
+static BACKEND: OnceLock<BackendArg> = OnceLock::new();
+
+/// The `--metadata-path` of every repository, set once the configuration is
+/// applied
+static METADATA_PATH: OnceLock<String> = OnceLock::new();
+
+/// The `--metadata-layout` of every repository, set once the configuration
+/// is applied
+static METADATA_LAYOUT: OnceLock<MetadataLayout> = OnceLock::new();
+
+/// The repository a command works on: the directory of `--backend
+/// local:<DIR>`, which replaces `--repo`, `--ssh-key` and `--branch`, the
+/// directory `--repo` names with `--backend dir`, or else the git remote
+/// named by `--repo`
+fn repository(
+    repo: &Option<String>,
+    ssh_key: &Option<PathBuf>,
+    branch: &Option<String>,
+) -> io::Result<Remote> {
+    match BACKEND.get() {
+        Some(BackendArg::Local(dir)) => Ok(with_metadata(Remote::local(dir))),
+        Some(BackendArg::Dir) => directory(config::require(repo, "repo")?, "--repo", branch),
+        _ => remote(repo, ssh_key, branch),
+    }
+}
+
+/// `remote` with the `--metadata-path` and `--metadata-layout` given
+fn with_metadata(mut remote: Remote) -> Remote {
+    if let Some(path) = METADATA_PATH.get() {
+        remote.metadata_path = path.clone();
+    }
+    if let Some(layout) = METADATA_LAYOUT.get() {
+        remote.metadata_layout = *layout;
+    }
+    remote
+}
+
+/// The directory `url` names with `--backend dir`: a path, or a `file://`
+/// URL of one. A `branch` is kept so that the backend can reject it.
+fn directory(url: &str, flag: &'static str, branch: &Option<String>) -> io::Result<Remote> {
+    let dir = match url.strip_prefix("file://") {
+        Some(path) if path.starts_with('/') => path,
+        Some(_) => {
+            return Err(BitcacheError::InvalidArgument {
+                flag,
+                value: url.to_string(),
+                reason:
+                    "expected file:// followed by an absolute path, e.g. file:///srv/bitstreams"
+                        .to_string(),
+            }
+            .into())
+        }
+        None => url,
+    };
+    Ok(with_metadata(Remote {
+        branch: branch.clone(),
+        ..Remote::local(dir)
+    }))
+}
+
+/// The repository named by `--repo`, reached with `--ssh-key` if given
+fn remote(
+    repo: &Option<String>,
+    ssh_key: &Option<PathBuf>,
+    branch: &Option<String>,
+) -> io::Result<Remote> {
+    let url = config::require(repo, "repo")?;
+    // The token is only for HTTPS on the configured host, never sent in the
+    // clear to an http:// remote; an SSH remote uses the key or the agent
+    let for_token = https_host(url).is_some_and(|host| TOKEN_HOST.get() == Some(&host));
+    let auth = match (TOKEN.get(), ssh_key) {
+        (Some(token), _) if for_token => Some(Auth::Token(token.clone())),
+        (_, Some(key)) => Some(Auth::SshKey(key.clone())),
+        _ => None,
+    };
+    Ok(with_metadata(Remote {
+        auth,
+        branch: branch.clone(),
+        ..Remote::new(url.clone())
+    }))
+}
+
+/// What `--json` prints for publish: the new entry and where it went
+#[derive(Serialize)]
+struct PublishReport<'a> {
+    repo: &'a str,
+    #[serde(flatten)]
+    published: &'a Published,
+}
+
+/// What `--json` prints for publish-batch
+#[derive(Serialize)]
+struct BatchReport<'a> {
+    repo: &'a str,
+    published: &'a [Published],
+}
+
+/// What `--json` prints for get --locked, one per artifact of the lock file
+#[derive(Serialize)]
+struct RetrievedArtifact<'a> {
+    name: &'a str,
+    #[serde(flatten)]
+    retrieved: &'a Retrieved,
+}
+
+/// What `--json` prints for repair
+#[derive(Serialize)]
+struct RepairReport {
+    /// Why the strict parser refuses the metadata file
+    error: Option<String>,
+    entries: usize,
+    duplicates: usize,
+    rejected: Vec<Rejected>,
+    /// Whether the repaired metadata was committed
+    repaired: bool,
+}
+
+impl RepairReport {
+    fn print(&self) -> io::Result<()> {
+        if output::json() {
+            output::print_json(self)?;
+        }
+        Ok(())
+    }
+}
+
+/// What `--json` prints for deprecate
+#[derive(Serialize)]
+struct DeprecateReport<'a> {
+    /// Whether the entry was changed, rather than already as asked
+    changed: bool,
+    entry: Option<&'a MetadataEntry>,
+}
+
+/// What `--json` prints for filter-check
+#[derive(Serialize)]
+struct FilterCheck<'a> {
+    expression: &'a str,
+    /// The expression with its grouping made explicit
+    grouped: String,
+}
+
+/// What `--json` prints for cache clean
+#[derive(Serialize)]
+struct CacheCleaned<'a> {
+    cache_dir: &'a Path,
+    artifacts: usize,
+    bytes: u64,
+    clones: usize,
+}
+
+/// What `--json` prints for export
+#[derive(Serialize)]
+struct Exported<'a> {
+    output: &'a Path,
+    entries: usize,
+    /// Bytes of the bitstreams of an archive, before compression
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+}
+
+/// What `--json` prints for config show, one per option
+#[derive(Serialize)]
+struct Setting {
+    key: &'static str,
+    value: Option<String>,
+    /// Where the value came from, if it is set
+    source: Option<String>,
+}
+
+/// What `--json` prints for --list-plugins
+#[derive(Serialize)]
+struct Plugin<'a> {
+    name: &'a str,
+    path: &'a Path,
+}
+
+/// Handle the publish subcommand
+fn handle_publish(args: &PublishArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let started = Instant::now();
+    let remote = args.remote.repository()?;
+    let opts = PublishOptions {
+        source: args.source[0].clone(),
+        extra_sources: args.source[1..].to_vec(),
+        source_walk: args.walk.walk(),
+        bitstream: args.bitstream.clone(),
+        path: config::require(&args.path, "path")?.clone(),
+        follow_symlinks: !args.no_follow_symlinks,
+        rename_in_repo: args.rename_in_repo.clone(),
+        variant: args.variant.clone(),
+        allow_empty_source: args.allow_empty_source,
+        hash_algo: args.hash_algo.unwrap_or_default(),
+        compression: args.compress.unwrap_or_default(),
+        compress_level: args.compress_level,
+        tags: Tag::map(&args.tags),
+        force: args.force,
+        fail_if_exists: args.fail_if_exists,
+        on_collision: args.on_collision.unwrap_or_default(),
+        os: args
+            .os
+            .clone()
+            .or_else(|| Some(env::consts::OS.to_string())),
+        arch: args
+            .arch
+            .clone()
+            .or_else(|| Some(env::consts::ARCH.to_string())),
+        build_tool_version: args.build_tool_version.clone(),
+        author: args.author.clone(),
+        source_commit: args.source_commit.clone(),
+        detect_source_commit: !args.no_source_commit,
+    };
+
+    if args.explain {
+        output::status_to_stderr();
+        return output::print_json(&bitcache::explain(&remote, &opts, ctx)?);
+    }
+    if args.dry_run {
+        let planned = bitcache::publish_dry_run(&remote, &opts, ctx)?;
+        if planned.action == PublishAction::Unchanged {
+            status!(
+                "Dry run: already cached, skipping the upload: {} holds this bitstream for {} {}",
+                planned.binary_path,
+                opts.hash_algo.label(),
+                planned.md5
+            );
+        } else {
+            status!(
+                "Dry run: would publish {} as {} with {} {}",
+                args.bitstream.display(),
+                planned.binary_path,
+                opts.hash_algo.label(),
+                planned.md5
+            );
+            if planned.action == PublishAction::Overwrite {
+                status!(
+                    "  Replaces the existing entry named {} for this hash",
+                    planned.name
+                );
+            }
+            status!("  Size: {}", style.size(planned.size));
+        }
+        status!("  Commit: {}", planned.commit_message);
+        return Ok(());
+    }
+
+    let published = bitcache::publish(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&PublishReport {
+            repo: &remote.url,
+            published: &published,
+        })?;
+    }
+    if published.action == PublishAction::Unchanged {
+        outcome!(
+            "Already published: {} holds this bitstream for {} {}, nothing committed",
+            published.binary_path,
+            opts.hash_algo.label(),
+            published.md5
+        );
+        return Ok(());
+    }
+    outcome!(
+        "Successfully published bitstream with {}: {}",
+        opts.hash_algo.label(),
+        published.md5
+    );
+    if args.variant.is_some() {
+        status!("  Variant: {}", published.name);
+    }
+    status!("  Size: {}", style.size(published.size));
+    status!("  Elapsed: {}", style.duration(started.elapsed()));
+
+    Ok(())
+}
+
+/// Handle the upload subcommand
+fn handle_upload(args: &UploadArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    output::status_to_stderr();
+    let remote = args.remote.repository()?;
+    let opts = UploadOptions {
+        bitstream: args.bitstream.clone(),
+        path: config::require(&args.path, "path")?.clone(),
+        rename_in_repo: args.rename_in_repo.clone(),
+        follow_symlinks: !args.no_follow_symlinks,
+    };
+    let uploaded = bitcache::upload(&remote, &opts, ctx)?;
+    if !uploaded.unchanged {
+        status!("Uploaded bitstream with MD5: {}", uploaded.binary_md5);
+        status!("  Size: {}", style.size(uploaded.size));
+    }
+    if output::json() {
+        return output::print_json(&uploaded);
+    }
+    println!("{}", uploaded.binary_path);
+    Ok(())
+}
+
+/// Handle the register subcommand
+fn handle_register(args: &RegisterArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = RegisterOptions {
+        md5: args.md5.clone(),
+        hash_algo: args.hash_algo.unwrap_or_default(),
+        source_name: args.source_name.clone(),
+        binary_path: args.binary_path.clone(),
+        binary_md5: args.binary_md5.clone(),
+        size: None,
+        variant: args.variant.clone(),
+        tags: Tag::map(&args.tags),
+        os: args
+            .os
+            .clone()
+            .or_else(|| Some(env::consts::OS.to_string())),
+        arch: args
+            .arch
+            .clone()
+            .or_else(|| Some(env::consts::ARCH.to_string())),
+        build_tool_version: args.build_tool_version.clone(),
+        author: args.author.clone(),
+    };
+    let registered = bitcache::register(&remote, &opts, ctx)?;
+    if !registered.unchanged {
+        status!(
+            "Registered {} for {}: {}",
+            registered.entry.binary_path,
+            opts.hash_algo.label(),
+            registered.entry.md5
+        );
+    }
+    if output::json() {
+        output::print_json(&registered)?;
+    }
+    Ok(())
+}
+
+/// Handle the publish-batch subcommand
+fn handle_publish_batch(
+    args: &PublishBatchArgs,
+    ctx: &Context,
+    style: OutputStyle,
+) -> io::Result<()> {
+    let started = Instant::now();
+    let remote = args.remote.repository()?;
+    let ctx = &Context {
+        jobs: args.jobs.unwrap_or(0),
+        ..ctx.clone()
+    };
+    let defaults = PublishOptions {
+        follow_symlinks: !args.no_follow_symlinks,
+        allow_empty_source: args.allow_empty_source,
+        hash_algo: args.hash_algo.unwrap_or_default(),
+        compression: args.compress.unwrap_or_default(),
+        compress_level: args.compress_level,
+        tags: Tag::map(&args.tags),
+        variant: args.variant.clone(),
+        force: args.force,
+        fail_if_exists: args.fail_if_exists,
+        on_collision: args.on_collision.unwrap_or_default(),
+        os: args
+            .os
+            .clone()
+            .or_else(|| Some(env::consts::OS.to_string())),
+        arch: args
+            .arch
+            .clone()
+            .or_else(|| Some(env::consts::ARCH.to_string())),
+        build_tool_version: args.build_tool_version.clone(),
+        author: args.author.clone(),
+        source_commit: args.source_commit.clone(),
+        detect_source_commit: !args.no_source_commit,
+        ..PublishOptions::new("", "", args.path.clone().unwrap_or_default())
+    };
+    let batch = BatchManifest::load(&args.manifest)?.options(&defaults)?;
+
+    let published = bitcache::publish_batch(&remote, &batch, ctx)?;
+    if output::json() {
+        output::print_json(&BatchReport {
+            repo: &remote.url,
+            published: &published,
+        })?;
+    }
+    let committed = published
+        .iter()
+        .filter(|published| published.action != PublishAction::Unchanged)
+        .count();
+    if committed == 0 {
+        outcome!(
+            "All {} bitstreams are already published, nothing committed:",
+            published.len()
+        );
+    } else {
+        outcome!("Successfully published {} bitstreams:", committed);
+    }
+    for published in &published {
+        outcome!(
+            "  {}  {} ({}){}",
+            published.md5,
+            published.binary_path,
+            style.size(published.size),
+            if published.action == PublishAction::Unchanged {
+                ", already published"
+            } else {
+                ""
+            }
+        );
+    }
+    status!("  Elapsed: {}", style.duration(started.elapsed()));
+    Ok(())
+}
+
+/// Handle the update subcommand
+fn handle_update(args: &UpdateArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let started = Instant::now();
+    let remote = args.remote.repository()?;
+    let opts = UpdateOptions {
+        md5: args.md5.clone(),
+        variant: args.variant.clone(),
+        bitstream: args.bitstream.clone(),
+        follow_symlinks: !args.no_follow_symlinks,
+        tags: Tag::map(&args.tags),
+    };
+    let updated = bitcache::update(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&PublishReport {
+            repo: &remote.url,
+            published: &updated,
+        })?;
+    }
+    outcome!("Successfully updated bitstream for {}", updated.md5);
+    if args.variant.is_some() {
+        status!("  Variant: {}", updated.name);
+    }
+    status!("  Path: {}", updated.binary_path);
+    status!("  Size: {}", style.size(updated.size));
+    status!("  Elapsed: {}", style.duration(started.elapsed()));
+    Ok(())
+}
+
+/// Handle the repair subcommand
+fn handle_repair(args: &RepairArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let plan = repair::inspect(&remote, ctx)?;
+    let repaired = &plan.repair;
+    let mut report = RepairReport {
+        error: plan.strict_error.as_ref().map(ToString::to_string),
+        entries: repaired.metadata.len(),
+        duplicates: repaired.duplicates,
+        rejected: repaired.rejected.clone(),
+        repaired: false,
+    };
+
+    if !plan.needs_repair() {
+        status!(
+            "{} is valid ({} entries), nothing to repair",
+            remote.metadata_path,
+            repaired.metadata.len()
+        );
+        return report.print();
+    }
+
+    if let Some(e) = &plan.strict_error {
+        status!("{}", e);
+    }
+    status!("Recovered {} entries", repaired.metadata.len());
+    if repaired.duplicates > 0 {
+        status!(
+            "Dropped {} older duplicate(s), keeping the newest timestamp",
+            repaired.duplicates
+        );
+    }
+    if !repaired.rejected.is_empty() {
+        status!(
+            "Moving {} entr{} to {} for review:",
+            repaired.rejected.len(),
+            if repaired.rejected.len() == 1 {
+                "y"
+            } else {
+                "ies"
+            },
+            repair::REJECTED_FILE
+        );
+        for rejected in &repaired.rejected {
+            status!(
+                "  {}: {}",
+                rejected.key.as_deref().unwrap_or("(unreadable key)"),
+                rejected.reason
+            );
+        }
+    }
+
+    if !args.yes {
+        status!("Nothing was changed. Run again with --yes to commit the repaired metadata.");
+        return report.print();
+    }
+
+    if plan.apply()? {
+        status!("Repaired {}", remote.metadata_path);
+        report.repaired = true;
+    }
+    report.print()
+}
+
+/// Handle the bundle create subcommand
+fn handle_bundle_create(
+    args: &BundleCreateArgs,
+    ctx: &Context,
+    style: OutputStyle,
+) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = BundleOptions {
+        output: args.output.clone(),
+        md5s: args.md5.clone(),
+        path_prefix: args.prefix.clone(),
+    };
+    let bundled = bitcache::create_bundle(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&bundled)?;
+    }
+    status!(
+        "Bundled {} entr{} ({}) into {}",
+        bundled.entries.len(),
+        if bundled.entries.len() == 1 {
+            "y"
+        } else {
+            "ies"
+        },
+        style.size(bundled.bytes),
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// Handle the bundle apply subcommand
+fn handle_bundle_apply(args: &BundleApplyArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = ApplyBundleOptions {
+        input: args.bundle.clone(),
+        overwrite: args.overwrite,
+    };
+    let applied = bitcache::apply_bundle(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&applied)?;
+    }
+    for skipped in &applied.skipped {
+        status!("Skipped {}: {}", skipped.entry.md5, skipped.reason);
+    }
+    status!(
+        "{} added, {} replaced, {} already present, {} skipped",
+        applied.added.len(),
+        applied.replaced.len(),
+        applied.present.len(),
+        applied.skipped.len()
+    );
+    Ok(())
+}
+
+/// Handle the bundle list subcommand
+fn handle_bundle_list(args: &BundleListArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    output::status_to_stderr();
+    let contents = bitcache::read_bundle(&args.bundle, ctx)?;
+    if output::json() {
+        return output::print_json(&contents);
+    }
+    status!(
+        "Bundle written by bitcache {} at {}",
+        contents.bitcache_version,
+        style.timestamp(&contents.created)
+    );
+    if contents.entries.is_empty() {
+        status!("No entries");
+        return Ok(());
+    }
+    let mut rows = vec![["HASH", "SOURCE", "PATH"].map(String::from).to_vec()];
+    for entry in &contents.entries {
+        rows.push(vec![
+            entry.md5.clone(),
+            entry.source_file.clone(),
+            entry.binary_path.clone(),
+        ]);
+    }
+    output::print_table(&rows);
+    Ok(())
+}
+
+/// Handle the prune subcommand
+fn handle_prune(args: &PruneArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = PruneOptions {
+        older_than: args.older_than,
+        keep_latest: args.keep_latest,
+        filter: args.filter.clone(),
+        orphans: args.orphans,
+        hard: args.hard,
+        dry_run: args.dry_run,
+    };
+    let pruned = bitcache::prune(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&pruned)?;
+    }
+
+    for entry in &pruned.entries {
+        status!(
+            "PRUNE     {}  {}  {}  {}",
+            entry.label(),
+            entry.source_file,
+            style.timestamp(&entry.timestamp),
+            entry.binary_path
+        );
+    }
+    for orphan in &pruned.orphans {
+        status!("ORPHAN    {}", orphan);
+    }
+    let count = pruned.entries.len();
+    let orphans = pruned.orphans.len();
+    let entries = format!(
+        "{} entr{} ({})",
+        count,
+        if count == 1 { "y" } else { "ies" },
+        style.size(pruned.entry_bytes)
+    );
+    let orphaned = format!(
+        "{} orphaned file{} ({})",
+        orphans,
+        if orphans == 1 { "" } else { "s" },
+        style.size(pruned.orphan_bytes)
+    );
+    let destination = if args.hard {
+        "from the repository"
+    } else {
+        "to the trash"
+    };
+    if args.dry_run {
+        status!("Would prune {} {}", entries, destination);
+        if args.orphans {
+            status!("Would remove {}", orphaned);
+        }
+    } else if pruned.committed {
+        status!("Pruned {} {}", entries, destination);
+        if args.orphans {
+            status!("Removed {}", orphaned);
+        }
+    } else {
+        status!("Nothing to prune");
+    }
+    if !args.orphans && orphans > 0 {
+        status!("Found {}; pass --orphans to remove them", orphaned);
+    }
+    Ok(())
+}
+
+/// Handle the compact subcommand
+fn handle_compact(args: &CompactArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = CompactOptions { confirm: args.yes };
+    let compacted = bitcache::compact(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&compacted)?;
+    }
+    status!(
+        "{}: {} commit{}, {} of file versions in its history, {} at its head",
+        compacted.branch,
+        compacted.commits,
+        if compacted.commits == 1 { "" } else { "s" },
+        style.size(compacted.before),
+        style.size(compacted.after)
+    );
+    match &compacted.backup_tag {
+        Some(tag) => {
+            status!(
+                "Replaced the history of {} with one commit; the previous head is tagged {}",
+                compacted.branch,
+                tag
+            );
+            status!(
+                "Clones still fetch the old history through {}; delete it once the compacted branch is known to be good",
+                tag
+            );
+        }
+        None if compacted.commits <= 1 => status!("Nothing to compact"),
+        None => status!(
+            "Run compact --yes to replace the history, saving about {}",
+            style.size(compacted.before.saturating_sub(compacted.after))
+        ),
+    }
+    Ok(())
+}
+
+/// Handle the list subcommand
+fn handle_list(args: &ListArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    output::status_to_stderr();
+    let opts = SearchOptions {
+        source_pattern: args.source_filter.clone(),
+        tags: Tag::map(&args.filter_tags),
+        filter: args.filter.clone(),
+        since: args.since,
+    };
+    let entries = bitcache::search(&remote, &opts, ctx)?;
+    if output::json() {
+        return output::print_json(&entries);
+    }
+    if output::csv() {
+        output::print_csv(&entries);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        status!("No entries");
+        return Ok(());
+    }
+
+    // Entries published by older versions don't record their branch
+    let show_branch = entries.iter().any(|entry| entry.branch.is_some());
+    let show_deprecated = entries.iter().any(|entry| entry.deprecated);
+    // Only shown where publish --variant gave one
+    let show_variant = entries.iter().any(|entry| entry.variant.is_some());
+    let show_platform = entries
+        .iter()
+        .any(|entry| entry.os.is_some() || entry.arch.is_some());
+    let show_tool = entries
+        .iter()
+        .any(|entry| entry.build_tool_version.is_some());
+    let show_tags = entries.iter().any(|entry| !entry.tags.is_empty());
+    let mut header = vec!["HASH", "SOURCE", "PUBLISHED"];
+    if show_branch {
+        header.push("BRANCH");
+    }
+    if show_variant {
+        header.push("VARIANT");
+    }
+    if show_platform {
+        header.push("PLATFORM");
+    }
+    if show_tool {
+        header.push("TOOL");
+    }
+    header.push("PATH");
+    if show_deprecated {
+        header.push("DEPRECATED");
+    }
+    if show_tags {
+        header.push("TAGS");
+    }
+    let mut rows = vec![header.into_iter().map(String::from).collect::<Vec<_>>()];
+    for entry in &entries {
+        let mut row = vec![
+            entry.md5.clone(),
+            entry.source_file.clone(),
+            style.timestamp(&entry.timestamp),
+        ];
+        if show_branch {
+            row.push(entry.branch.clone().unwrap_or_else(|| "-".to_string()));
+        }
+        if show_variant {
+            row.push(entry.name().to_string());
+        }
+        if show_platform {
+            let part = |value: &Option<String>| value.clone().unwrap_or_else(|| "?".to_string());
+            row.push(match (&entry.os, &entry.arch) {
+                (None, None) => "-".to_string(),
+                (os, arch) => format!("{}/{}", part(os), part(arch)),
+            });
+        }
+        if show_tool {
+            row.push(
+                entry
+                    .build_tool_version
+                    .clone()
+                    .unwrap_or_else(|| "-".to_string()),
+            );
+        }
+        row.push(entry.binary_path.clone());
+        if show_deprecated {
+            row.push(match (entry.deprecated, &entry.replacement) {
+                (false, _) => "-".to_string(),
+                (true, Some(replacement)) => format!("use {}", replacement),
+                (true, None) => "yes".to_string(),
+            });
+        }
+        if show_tags {
+            row.push(entry.tag_labels().join(","));
+        }
+        rows.push(row);
+    }
+    output::print_table(&rows);
+    Ok(())
+}
+
+/// Handle the search subcommand
+///
+/// Exits with [`EXIT_NOT_FOUND`] when no entry matches, as get-by-source
+/// does for a miss.
+fn handle_search(args: &SearchArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    output::status_to_stderr();
+    let opts = SearchOptions {
+        source_pattern: args.source_pattern.clone(),
+        tags: Tag::map(&args.tags),
+        filter: args.filter.clone(),
+        since: None,
+    };
+    let entries = bitcache::search(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&entries)?;
+    }
+    if entries.is_empty() {
+        status!("No matching entries");
+        process::exit(EXIT_NOT_FOUND);
+    }
+    if output::json() {
+        return Ok(());
+    }
+
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        print_entry(entry, style);
+    }
+    status!(
+        "{} matching entr{}",
+        entries.len(),
+        if entries.len() == 1 { "y" } else { "ies" }
+    );
+    Ok(())
+}
+
+/// Print an entry as a block of its fields under its hash
+fn print_entry(entry: &MetadataEntry, style: OutputStyle) {
+    println!("{}", entry.md5);
+    if let Some(variant) = &entry.variant {
+        println!("  Name: {}", variant);
+    }
+    println!("  Source file: {}", entry.source_file);
+    if !entry.source_files.is_empty() {
+        println!("  Source files: {}", entry.source_files.len());
+    }
+    println!("  Published: {}", style.timestamp(&entry.timestamp));
+    println!("  Path: {}", entry.binary_path);
+    if let Some(size) = entry.size {
+        println!("  Size: {}", style.size(size));
+    }
+    if let Some(branch) = &entry.branch {
+        println!("  Branch: {}", branch);
+    }
+    println!("  Hash algorithm: {}", entry.hash_algo);
+    if let Some(binary_md5) = &entry.binary_md5 {
+        println!("  Bitstream MD5: {}", binary_md5);
+    }
+    if let Some(compression) = &entry.compression {
+        println!("  Compression: {}", compression);
+    }
+    if let Some(size) = entry.original_size {
+        println!("  Decompressed size: {}", style.size(size));
+    }
+    if let Some(mode) = entry.file_mode {
+        println!("  Mode: {:04o}", mode);
+    }
+    if let Some(name) = &entry.file_name {
+        println!("  Saved as: {}", name);
+    }
+    if let Some(os) = &entry.os {
+        println!("  OS: {}", os);
+    }
+    if let Some(arch) = &entry.arch {
+        println!("  Architecture: {}", arch);
+    }
+    if let Some(version) = &entry.build_tool_version {
+        println!("  Build tool: {}", version);
+    }
+    if let Some(published_by) = &entry.published_by {
+        println!("  Published by: {}", published_by);
+    }
+    if let Some(commit) = &entry.source_commit {
+        println!("  Source commit: {}", commit);
+    }
+    if let Some(version) = &entry.tool_version {
+        println!("  Bitcache version: {}", version);
+    }
+    if !entry.tags.is_empty() {
+        println!("  Tags: {}", entry.tag_labels().join(", "));
+    }
+}
+
+/// Value of `--backend`
+#[derive(Debug, Clone)]
+enum BackendArg {
+    Git,
+    Dir,
+    Local(PathBuf),
+}
+
+impl FromStr for BackendArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("local:") {
+            Some("") => {
+                Err("expected a directory after local:, e.g. local:/srv/bitstreams".to_string())
+            }
+            Some(dir) => Ok(BackendArg::Local(PathBuf::from(dir))),
+            None if s == "git" => Ok(BackendArg::Git),
+            None if s == "dir" => Ok(BackendArg::Dir),
+            None => Err(format!(
+                "unknown backend '{}': expected git, dir or local:<DIR>",
+                s
+            )),
+        }
+    }
+}
+
+/// A `KEY=VALUE` tag given on the command line
+#[derive(Debug, Clone)]
+struct Tag {
+    key: String,
+    value: String,
+}
+
+impl Tag {
+    /// The tags as a map, a later value winning for a repeated key
+    fn map(tags: &[Tag]) -> BTreeMap<String, String> {
+        tags.iter()
+            .map(|tag| (tag.key.clone(), tag.value.clone()))
+            .collect()
+    }
+}
+
+impl FromStr for Tag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(Tag {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err("expected KEY=VALUE with a non-empty key".to_string()),
+        }
+    }
+}
+
+/// Handle the filter-check subcommand: print the expression with its
+/// grouping, or point at what is wrong with it
+fn handle_filter_check(expression: &str) -> io::Result<()> {
+    match Filter::parse(expression) {
+        Ok(filter) if output::json() => output::print_json(&FilterCheck {
+            expression,
+            grouped: filter.to_string(),
+        }),
+        Ok(filter) => {
+            println!("{}", filter);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("  {}", expression);
+            eprintln!("  {:>width$}", "^", width = e.column);
+            Err(BitcacheError::InvalidArgument {
+                flag: "filter",
+                value: expression.to_string(),
+                reason: e.to_string(),
+            }
+            .into())
+        }
+    }
+}
+
+/// Handle the status subcommand
+///
+/// Every repository is probed at once and gets a row, whether or not its
+/// probe succeeds; the command exits 1 after printing them if any failed.
+fn handle_status(args: &StatusArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    if args.repo.is_empty() {
+        return Err(config::missing("repo"));
+    }
+    let remotes = args
+        .repo
+        .iter()
+        .map(|url| repository(&Some(url.clone()), &args.ssh_key, &args.branch))
+        .collect::<io::Result<Vec<_>>>()?;
+    output::status_to_stderr();
+    let probed = bitcache::probe_all(&remotes, ctx);
+
+    // What a row's STATUS column says, from the probe's outcome
+    let health = |result: &io::Result<RepoHealth>| -> String {
+        match result {
+            Ok(health) if health.missing == 0 => "ok".to_string(),
+            Ok(health) => format!("{} missing", health.missing),
+            Err(e) => match BitcacheError::of(e) {
+                Some(BitcacheError::Unreachable { .. }) => "unreachable",
+                Some(BitcacheError::Timeout { .. }) => "timed out",
+                Some(BitcacheError::MissingMetadata) => "not initialized",
+                _ => "error",
+            }
+            .to_string(),
+        }
+    };
+    let failed = probed.iter().any(Result::is_err);
+
+    if output::json() {
+        let mut rows = Vec::with_capacity(probed.len());
+        for (remote, result) in remotes.iter().zip(&probed) {
+            let mut row = match result {
+                Ok(health) => serde_json::to_value(health)?,
+                Err(e) => serde_json::json!({
+                    "url": &remote.url,
+                    "error": e.to_string().trim_end(),
+                }),
+            };
+            row["status"] = health(result).into();
+            rows.push(row);
+        }
+        output::print_json(&rows)?;
+    } else {
+        let mut rows = vec![
+            ["REPOSITORY", "STATUS", "ENTRIES", "SIZE", "LAST PUBLISHED"]
+                .map(String::from)
+                .to_vec(),
+        ];
+        for (remote, result) in remotes.iter().zip(&probed) {
+            let mut row = vec![remote.url.clone(), health(result)];
+            match result {
+                Ok(probed) => {
+                    let mut size = style.size(probed.bytes);
+                    if probed.without_size > 0 {
+                        size.push_str(&format!(" ({} unsized)", probed.without_size));
+                    }
+                    row.extend([
+                        probed.entries.to_string(),
+                        size,
+                        probed
+                            .last_published
+                            .as_deref()
+                            .map_or("-".to_string(), |time| style.timestamp(time)),
+                    ]);
+                }
+                Err(_) => row.extend(["-", "-", "-"].map(String::from)),
+            }
+            rows.push(row);
+        }
+        output::print_table(&rows);
+    }
+
+    for (remote, result) in remotes.iter().zip(&probed) {
+        if let Err(e) = result {
+            eprintln!("Error: {}: {}", remote.url, e.to_string().trim_end());
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Handle the deprecate subcommand
+fn handle_deprecate(args: &DeprecateArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = DeprecateOptions {
+        md5: args.md5.clone(),
+        variant: args.variant.clone(),
+        reason: args.reason.clone(),
+        replacement: args.replacement.clone(),
+        undo: args.undo,
+    };
+    let deprecated = bitcache::deprecate(&remote, &opts, ctx)?;
+    match &deprecated {
+        None if args.undo => status!("The entry is not deprecated"),
+        None => status!("The entry is already deprecated like that"),
+        Some(entry) if args.undo => {
+            status!("Cleared the deprecation of MD5: {}", entry.md5)
+        }
+        Some(entry) => {
+            status!("Deprecated entry for MD5: {}", entry.md5);
+            if let Some(replacement) = &entry.replacement {
+                status!("  Replacement: {}", replacement);
+            }
+        }
+    }
+    if output::json() {
+        output::print_json(&DeprecateReport {
+            changed: deprecated.is_some(),
+            entry: deprecated.as_ref(),
+        })?;
+    }
+    Ok(())
+}
+
+/// Handle the delete subcommand
+fn handle_delete(args: &DeleteArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = DeleteOptions {
+        md5: args.md5.clone(),
+        variant: args.variant.clone(),
+        purge_binary: args.purge_binary,
+        hard: args.hard,
+    };
+    let Some(deleted) = bitcache::delete(&remote, &opts, ctx)? else {
+        return Err(BitcacheError::NotFound {
+            md5: args.md5.clone(),
+        }
+        .into());
+    };
+    if output::json() {
+        output::print_json(&deleted)?;
+    }
+
+    if let Some(trashed) = &deleted.trashed {
+        status!("Moved entry for MD5 {} to the trash", deleted.entry.md5);
+        status!("  Source file: {}", deleted.entry.source_file);
+        match &trashed.trash_path {
+            Some(path) => status!("  Bitstream moved to: {}", path),
+            None => status!("  Bitstream left at: {}", deleted.entry.binary_path),
+        }
+        match ctx.trash_retention {
+            Some(retention) => status!(
+                "  Restore it with `bitcache trash restore` within {} days",
+                retention.as_secs() / 86400
+            ),
+            None => status!("  Restore it with `bitcache trash restore`"),
+        }
+        return Ok(());
+    }
+    outcome!("Deleted entry for MD5: {}", deleted.entry.md5);
+    status!("  Source file: {}", deleted.entry.source_file);
+    if deleted.purged {
+        status!("  Removed bitstream: {}", deleted.entry.binary_path);
+    } else {
+        status!(
+            "  Bitstream left in repository: {}",
+            deleted.entry.binary_path
+        );
+    }
+    Ok(())
+}
+
+/// Handle the trash list subcommand
+fn handle_trash_list(args: &TrashListArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    output::status_to_stderr();
+    let trash = bitcache::list_trash(&remote, ctx)?;
+
+    if output::json() {
+        return output::print_json(&trash);
+    }
+    if trash.is_empty() {
+        status!("The trash is empty");
+        return Ok(());
+    }
+    let mut rows = vec![["DELETED", "HASH", "SOURCE", "PATH"]
+        .map(String::from)
+        .to_vec()];
+    for item in &trash {
+        rows.push(vec![
+            style.timestamp(&item.trashed_at),
+            item.entry.md5.clone(),
+            item.entry.source_file.clone(),
+            item.entry.binary_path.clone(),
+        ]);
+    }
+    output::print_table(&rows);
+    Ok(())
+}
+
+/// Handle the trash restore subcommand
+fn handle_trash_restore(args: &TrashRestoreArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = RestoreOptions {
+        md5: args.md5.clone(),
+        variant: args.variant.clone(),
+    };
+    let Some(entry) = bitcache::restore(&remote, &opts, ctx)? else {
+        return Err(BitcacheError::NotFound {
+            md5: args.md5.clone(),
+        }
+        .into());
+    };
+    if output::json() {
+        output::print_json(&entry)?;
+    }
+    status!("Restored entry for MD5: {}", entry.md5);
+    status!("  Source file: {}", entry.source_file);
+    status!("  Bitstream: {}", entry.binary_path);
+    Ok(())
+}
+
+/// Handle the trash empty subcommand
+fn handle_trash_empty(args: &TrashEmptyArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = EmptyTrashOptions {
+        expired_only: args.expired,
+    };
+    let emptied = bitcache::empty_trash(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&emptied)?;
+    }
+    status!(
+        "Removed {} entr{} from the trash",
+        emptied.len(),
+        if emptied.len() == 1 { "y" } else { "ies" }
+    );
+    Ok(())
+}
+
+/// Handle the verify subcommand
+///
+/// Failures make the command fail unless `--fix` removed them.
+fn handle_verify(args: &VerifyArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = VerifyOptions { fix: args.fix };
+    let report = bitcache::verify(&remote, &opts, ctx)?;
+    if output::json() {
+        // The report names the failures, so it stands in for the error
+        output::print_json(&report)?;
+        if (!report.fixed && !report.failures.is_empty()) || !report.dangling.is_empty() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    for failure in &report.failures {
+        match &failure.problem {
+            Problem::Missing => status!(
+                "MISSING   {}  {}",
+                failure.entry.md5,
+                failure.entry.binary_path
+            ),
+            Problem::Mismatch { expected, actual } => status!(
+                "MISMATCH  {}  {}  (expected {}, found {})",
+                failure.entry.md5,
+                failure.entry.binary_path,
+                expected,
+                actual
+            ),
+            _ => status!(
+                "FAILED    {}  {}",
+                failure.entry.md5,
+                failure.entry.binary_path
+            ),
+        }
+    }
+    for entry in &report.dangling {
+        status!(
+            "DANGLING  {}  replacement {} has no entry",
+            entry.md5,
+            entry.replacement.as_deref().unwrap_or_default()
+        );
+    }
+    status!(
+        "Checked {} entries: {} failed, {} without a recorded binary MD5 (only checked to exist)",
+        report.checked,
+        report.failures.len(),
+        report.unverifiable
+    );
+    if report.fixed {
+        status!(
+            "Removed {} failed entries from the repository",
+            report.failures.len()
+        );
+    } else if !report.failures.is_empty() {
+        return Err(io::Error::other(format!(
+            "{} of {} entries failed verification; run verify --fix to remove them",
+            report.failures.len(),
+            report.checked
+        )));
+    }
+    if !report.dangling.is_empty() {
+        return Err(io::Error::other(format!(
+            "Found {} deprecated entr{} whose replacement has no entry; run deprecate again with another --replacement",
+            report.dangling.len(),
+            if report.dangling.len() == 1 { "y" } else { "ies" }
+        )));
+    }
+    Ok(())
+}
+
+/// Handle the migrate-metadata subcommand
+fn handle_migrate_metadata(args: &MigrateMetadataArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = MigrateOptions {
+        layout: args.layout,
+    };
+    let migrated = bitcache::migrate_metadata(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&migrated)?;
+    }
+    if migrated.committed {
+        status!(
+            "Converted {} entr{} to the {} layout",
+            migrated.entries,
+            if migrated.entries == 1 { "y" } else { "ies" },
+            args.layout
+        );
+    } else {
+        status!("The metadata already has the {} layout", args.layout);
+    }
+    Ok(())
+}
+
+/// Exit status of gc when it found orphans or broken entries and left them
+const EXIT_UNCLEAN: i32 = 3;
+
+/// Handle the init subcommand
+fn handle_init(args: &InitArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = InitOptions { force: args.force };
+    let initialized = bitcache::init(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&initialized)?;
+    }
+    if initialized.committed {
+        status!("Successfully initialized the repository");
+    } else {
+        status!("The repository already has an empty metadata file");
+    }
+    Ok(())
 }
 
-/// Root metadata structure
-#[derive(Debug, Serialize, Deserialize)]
-struct Metadata {
-    /// Map of MD5 hash to metadata entry
-    entries: HashMap<String, MetadataEntry>,
-}
+/// Handle the gc subcommand
+///
+/// Exits with [`EXIT_UNCLEAN`] when orphans are left because `--prune` was
+/// not given, or when an entry's bitstream is missing, which gc never fixes.
+fn handle_gc(args: &GcArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = GcOptions { prune: args.prune };
+    let report = bitcache::gc(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&report)?;
+    }
 
-impl Metadata {
-    fn new() -> Self {
-        Self {
-            entries: HashMap::new(),
-        }
+    for orphan in &report.orphans {
+        status!("ORPHAN    {}", orphan);
+    }
+    for entry in &report.broken {
+        status!("BROKEN    {}  {}", entry.md5, entry.binary_path);
+    }
+    status!(
+        "Scanned {} files: {} orphaned, {} entries with a missing bitstream",
+        report.scanned,
+        report.orphans.len(),
+        report.broken.len()
+    );
+    if report.pruned {
+        status!(
+            "Removed {} orphaned files from the repository",
+            report.orphans.len()
+        );
+    } else if !report.orphans.is_empty() {
+        status!("Run gc --prune to remove the orphaned files");
+    }
+    if !report.broken.is_empty() {
+        status!("Run verify --fix to remove the entries with a missing bitstream");
     }
+    if (!report.orphans.is_empty() && !report.pruned) || !report.broken.is_empty() {
+        process::exit(EXIT_UNCLEAN);
+    }
+    Ok(())
+}
 
-    fn load_from_file(path: &Path) -> io::Result<Self> {
-        let content = fs::read_to_string(path)?;
-        serde_json::from_str(&content).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to parse metadata: {}", e),
-            )
-        })
+/// Handle the top subcommand
+fn handle_top(args: &TopArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let opts = TopOptions {
+        by: args.by,
+        limit: Some(args.limit).filter(|&limit| limit > 0),
+        path_prefix: args.prefix.clone(),
+    };
+    output::status_to_stderr();
+    let ranked = bitcache::top(&remote, &opts, ctx)?;
+
+    if output::json() {
+        return output::print_json(&ranked);
+    }
+    if ranked.is_empty() {
+        status!("No entries");
+        return Ok(());
     }
 
-    fn save_to_file(&self, path: &Path) -> io::Result<()> {
-        let content = serde_json::to_string_pretty(&self)?;
-        fs::write(path, content)
+    // Columns a repository never records are left out rather than shown empty
+    let publisher = ranked
+        .iter()
+        .any(|ranked| ranked.entry.published_by.is_some());
+    let tags = ranked.iter().any(|ranked| !ranked.tags.is_empty());
+    let optional = |row: &mut Vec<String>, cells: [(bool, String); 2]| {
+        row.extend(
+            cells
+                .into_iter()
+                .filter(|(shown, _)| *shown)
+                .map(|(_, cell)| cell),
+        );
+    };
+
+    let mut header: Vec<String> = ["#", "SIZE", "PUBLISHED", "HASH"]
+        .map(String::from)
+        .to_vec();
+    optional(
+        &mut header,
+        [
+            (publisher, "PUBLISHER".to_string()),
+            (tags, "TAGS".to_string()),
+        ],
+    );
+    header.push("PATH".to_string());
+    let mut rows = vec![header];
+    for (rank, ranked) in ranked.iter().enumerate() {
+        let mut row = vec![
+            (rank + 1).to_string(),
+            ranked.size.map_or("-".to_string(), |size| style.size(size)),
+            style.timestamp(&ranked.entry.timestamp),
+            ranked.entry.md5.clone(),
+        ];
+        optional(
+            &mut row,
+            [
+                (
+                    publisher,
+                    ranked
+                        .entry
+                        .published_by
+                        .clone()
+                        .unwrap_or_else(|| "-".to_string()),
+                ),
+                (tags, ranked.tags.join(",")),
+            ],
+        );
+        row.push(ranked.entry.binary_path.clone());
+        rows.push(row);
     }
+    output::print_table(&rows);
+    Ok(())
 }
 
-/// Command-line interface for bitcache
-#[derive(Parser)]
-#[command(version, about = "Binary file cache manager using git and MD5 hashing", long_about = None)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
+/// Handle the export subcommand
+fn handle_export(args: &ExportArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    if !args.force && args.output.exists() {
+        return Err(BitcacheError::OutputExists {
+            path: args.output.clone(),
+        }
+        .into());
+    }
+    if is_archive_name(&args.output) {
+        let opts = ExportArchiveOptions {
+            md5s: args.md5.clone(),
+            ..ExportArchiveOptions::new(&args.output)
+        };
+        let archived = bitcache::export_archive(&remote, &opts, ctx)?;
+        if output::json() {
+            output::print_json(&Exported {
+                output: &args.output,
+                entries: archived.entries.len(),
+                bytes: Some(archived.bytes),
+            })?;
+        }
+        status!(
+            "Exported {} entr{} ({}) to {}",
+            archived.entries.len(),
+            if archived.entries.len() == 1 {
+                "y"
+            } else {
+                "ies"
+            },
+            style.size(archived.bytes),
+            args.output.display()
+        );
+        return Ok(());
+    }
+    let mut metadata = bitcache::export(&remote, ctx)?;
+    if !args.md5.is_empty() {
+        let mut selected = Metadata::new();
+        for md5 in &args.md5 {
+            let variants = metadata.variants(md5);
+            if variants.is_empty() {
+                eprintln!("Warning: the repository has no entry for MD5 {}", md5);
+            }
+            for entry in variants {
+                selected.insert_entry(entry.clone());
+            }
+        }
+        metadata = selected;
+    }
+    metadata.save_to_file(&args.output).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to write {}: {}", args.output.display(), e),
+        )
+    })?;
+    let entries = metadata.len();
+    if output::json() {
+        output::print_json(&Exported {
+            output: &args.output,
+            entries,
+            bytes: None,
+        })?;
+    }
+    status!(
+        "Exported {} entr{} to {}",
+        entries,
+        if entries == 1 { "y" } else { "ies" },
+        args.output.display()
+    );
+    Ok(())
 }
 
-/// Available subcommands
-#[derive(Subcommand)]
-enum Commands {
-    /// Publish a binary file to the repository
-    Publish {
-        /// Git repository URL
-        #[arg(long)]
-        repo: String,
-
-        /// Source file path
-        #[arg(long)]
-        source: PathBuf,
-
-        /// Binary file (bitstream) path
-        #[arg(long)]
-        bitstream: PathBuf,
-
-        /// Target directory path in the repository
-        #[arg(long)]
-        path: PathBuf,
-
-        /// Path to SSH private key for git operations
-        #[arg(long)]
-        ssh_key: Option<PathBuf>,
-    },
-    /// Get a binary file from the repository by MD5
-    Get {
-        /// Git repository URL
-        #[arg(long)]
-        repo: String,
-
-        /// MD5 hash of the source file
-        #[arg(long)]
-        md5: String,
-
-        /// Path to SSH private key for git operations
-        #[arg(long)]
-        ssh_key: Option<PathBuf>,
-    },
+/// Handle the import subcommand
+fn handle_import(args: &ImportArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    if is_archive(&args.input)? {
+        if args.replace {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--replace can't be used with an archive, whose entries are always merged",
+            ));
+        }
+        let opts = ImportArchiveOptions {
+            overwrite: args.overwrite,
+            ..ImportArchiveOptions::new(&args.input)
+        };
+        let imported = bitcache::import_archive(&remote, &opts, ctx)?;
+        return print_synced(
+            &imported,
+            &remote,
+            "Imported into",
+            false,
+            args.overwrite,
+            style,
+        );
+    }
+    let metadata = Metadata::load_from_file(&args.input).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to read {}: {}", args.input.display(), e),
+        )
+    })?;
+    let opts = ImportOptions {
+        mode: if args.replace {
+            ImportMode::Replace
+        } else {
+            ImportMode::Merge
+        },
+        overwrite: args.overwrite,
+        ..ImportOptions::new(metadata)
+    };
+    let imported = bitcache::import(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&imported)?;
+    }
+    for entry in &imported.skipped {
+        status!(
+            "Skipped {}: the repository has a different entry for it",
+            entry.label()
+        );
+    }
+    status!(
+        "Imported into {}: {} added, {} replaced, {} removed, {} unchanged, {} skipped",
+        remote.url,
+        imported.added.len(),
+        imported.replaced.len(),
+        imported.removed.len(),
+        imported.unchanged,
+        imported.skipped.len()
+    );
+    if !imported.skipped.is_empty() {
+        status!("Pass --overwrite to replace the skipped entries");
+    }
+    Ok(())
 }
 
-/// Compute MD5 hash of a file
-fn compute_md5(file_path: &Path) -> io::Result<String> {
-    let mut file = fs::File::open(file_path)?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
-
-    let digest = md5::compute(&buffer);
-    Ok(format!("{:x}", digest))
+/// Whether `path` names an archive for export to write, by its extension
+fn is_archive_name(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".tar.gz") || name.ends_with(".tgz")
 }
 
-/// Clone a git repository to a temporary location
-fn clone_repository(repo_url: &str, target_dir: &Path, ssh_key: Option<&Path>) -> io::Result<()> {
-    let mut cmd = Command::new("git");
-    cmd.arg("clone")
-        .arg(repo_url)
-        .arg(target_dir);
-
-    // If SSH key is provided, set GIT_SSH_COMMAND to use it
-    if let Some(key_path) = ssh_key {
-        let ssh_command = format!("ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=no", key_path.display());
-        cmd.env("GIT_SSH_COMMAND", ssh_command);
+/// Whether `path` is an archive rather than a metadata file, by its gzip
+/// magic number
+fn is_archive(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 2];
+    let read = fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", path.display(), e),
+            )
+        });
+    match read {
+        Ok(()) => Ok(magic == [0x1f, 0x8b]),
+        // Too short for either; the metadata parser says why
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
     }
+}
 
-    let output = cmd.output()?;
-
-    if !output.status.success() {
+/// Handle the sync subcommand
+fn handle_sync(args: &SyncArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    if let Some(BackendArg::Local(_)) = BACKEND.get() {
         return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Failed to clone repository: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ),
+            io::ErrorKind::InvalidInput,
+            "sync names its repositories with --from and --to; use --backend dir to sync directories",
         ));
     }
+    let (from, to) = match BACKEND.get() {
+        Some(BackendArg::Dir) => (
+            directory(&args.from, "--from", &args.from_branch)?,
+            directory(&args.to, "--to", &args.to_branch)?,
+        ),
+        _ => (
+            remote(&Some(args.from.clone()), &args.ssh_key, &args.from_branch)?,
+            remote(&Some(args.to.clone()), &args.ssh_key, &args.to_branch)?,
+        ),
+    };
+    let opts = SyncOptions {
+        md5s: args.md5.clone(),
+        overwrite: args.overwrite,
+        dry_run: args.dry_run,
+    };
+    let synced = bitcache::sync(&from, &to, &opts, ctx)?;
+    let done = if args.dry_run {
+        "Dry run, syncing"
+    } else {
+        "Synced"
+    };
+    print_synced(&synced, &to, done, args.dry_run, args.overwrite, style)
+}
 
+/// Print what a sync or an archive import copied into `to`, after `done`
+fn print_synced(
+    synced: &Synced,
+    to: &Remote,
+    done: &str,
+    dry_run: bool,
+    overwrite: bool,
+    style: OutputStyle,
+) -> io::Result<()> {
+    if output::json() {
+        return output::print_json(synced);
+    }
+    let verb = if dry_run { "Would copy" } else { "Copied" };
+    for entry in &synced.added {
+        status!("{} {}  {}", verb, entry.label(), entry.binary_path);
+    }
+    let verb = if dry_run { "Would replace" } else { "Replaced" };
+    for entry in &synced.replaced {
+        status!("{} {}  {}", verb, entry.label(), entry.binary_path);
+    }
+    for skipped in &synced.skipped {
+        status!("Skipped {}: {}", skipped.entry.label(), skipped.reason);
+    }
+    status!(
+        "{} {}: {} added, {} replaced, {} unchanged, {} skipped ({})",
+        done,
+        to.url,
+        synced.added.len(),
+        synced.replaced.len(),
+        synced.unchanged,
+        synced.skipped.len(),
+        style.size(synced.bytes)
+    );
+    if !overwrite
+        && synced
+            .skipped
+            .iter()
+            .any(|skipped| skipped.reason.starts_with("the destination has"))
+    {
+        status!("Pass --overwrite to replace the conflicting entries");
+    }
     Ok(())
 }
 
-/// Add, commit and push changes to the repository
-fn commit_and_push(repo_dir: &Path, message: &str, ssh_key: Option<&Path>) -> io::Result<()> {
-    // Add all changes
-    let add_output = Command::new("git")
-        .current_dir(repo_dir)
-        .arg("add")
-        .arg(".")
-        .output()?;
-
-    if !add_output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Failed to add files: {}",
-                String::from_utf8_lossy(&add_output.stderr)
-            ),
-        ));
+/// Handle the get subcommand
+fn handle_get(args: &GetArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    if let Some(lock) = &args.locked {
+        return handle_get_locked(lock, args, ctx, style);
+    }
+    let started = Instant::now();
+    let mut remotes = vec![args.remote.repository()?];
+    for url in &args.fallback_repos {
+        remotes.push(remote(
+            &Some(url.clone()),
+            &args.remote.ssh_key,
+            &args.remote.branch,
+        )?);
+    }
+    if let Some(prefix) = &args.env {
+        if output::json() || output::csv() {
+            return Err(BitcacheError::InvalidArgument {
+                flag: "--env",
+                value: prefix.clone(),
+                reason: "--json and --format already print the result on stdout".to_string(),
+            }
+            .into());
+        }
+        output::validate_env_prefix(prefix)?;
+        output::status_to_stderr();
     }
+    let env = output::EnvWriter::new(args.env.as_deref(), args.env_format);
+    // Without --md5 the entry is looked up by the hash of --source, which
+    // is then checked against it as with --md5
+    let md5 = match &args.md5 {
+        Some(md5) => md5.clone(),
+        None => hash_source(
+            &args.source,
+            args.walk.walk(),
+            args.hash_algo.unwrap_or_default(),
+        )?,
+    };
+    let opts = GetOptions {
+        md5: md5.clone(),
+        hash_algo: args.hash_algo,
+        variant: args.variant.clone(),
+        tags: Tag::map(&args.filter_tags),
+        output: args.output.clone(),
+        output_dir: args.output_dir.clone(),
+        use_local_cache: !args.no_local_cache,
+        sync: args.sync,
+        source: args.source.first().cloned(),
+        extra_sources: args.source.iter().skip(1).cloned().collect(),
+        source_walk: args.walk.walk(),
+        force: args.force,
+        refuse_deprecated: args.refuse_deprecated,
+        verify: !args.skip_verify,
+    };
 
-    // Commit changes
-    let commit_output = Command::new("git")
-        .current_dir(repo_dir)
-        .arg("commit")
-        .arg("-m")
-        .arg(message)
-        .output()?;
+    let Some(retrieved) = get_from_any(&remotes, &opts, ctx)? else {
+        env.emit(&[("HIT", "0"), ("MD5", &md5)]);
+        return Err(BitcacheError::NotFound { md5 }.into());
+    };
+    let entry = &retrieved.entry;
+    if output::json() {
+        output::print_json(&retrieved)?;
+    }
+    if output::csv() {
+        output::print_csv([entry]);
+    }
 
-    if !commit_output.status.success() {
-        let stderr = String::from_utf8_lossy(&commit_output.stderr);
-        // Check if there's nothing to commit
-        if stderr.contains("nothing to commit") {
-            println!("No changes to commit");
-            return Ok(());
-        }
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Failed to commit: {}", stderr),
-        ));
+    outcome!("Successfully retrieved bitstream:");
+    status!("  Source file: {}", entry.source_file);
+    status!("  MD5: {}", entry.md5);
+    status!("  Timestamp: {}", style.timestamp(&entry.timestamp));
+    if let Some(published_by) = &entry.published_by {
+        status!("  Published by: {}", published_by);
+    }
+    if let Some(commit) = &entry.source_commit {
+        status!("  Source commit: {}", commit);
     }
+    if let Some(version) = &entry.tool_version {
+        status!("  Bitcache version: {}", version);
+    }
+    outcome!("  Saved to: {}", retrieved.path.display());
+    status!("  Size: {}", style.size(retrieved.size));
+    status!("  Synced: {}", if retrieved.synced { "yes" } else { "no" });
+    status!("  Elapsed: {}", style.duration(started.elapsed()));
+
+    env.emit(&[
+        ("HIT", "1"),
+        ("MD5", &entry.md5),
+        ("PATH", &retrieved.path.to_string_lossy()),
+        ("SOURCE_FILE", &entry.source_file),
+        ("BINARY_PATH", &entry.binary_path),
+        ("TIMESTAMP", &entry.timestamp),
+        ("SIZE", &retrieved.size.to_string()),
+        ("SYNCED", if retrieved.synced { "1" } else { "0" }),
+    ]);
 
-    // Push changes
-    let mut push_cmd = Command::new("git");
-    push_cmd.current_dir(repo_dir).arg("push");
+    Ok(())
+}
 
-    // If SSH key is provided, set GIT_SSH_COMMAND to use it
-    if let Some(key_path) = ssh_key {
-        let ssh_command = format!("ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=no", key_path.display());
-        push_cmd.env("GIT_SSH_COMMAND", ssh_command);
+/// Handle get --locked: retrieve every artifact of the lock file at `lock`
+fn handle_get_locked(
+    lock: &Path,
+    args: &GetArgs,
+    ctx: &Context,
+    style: OutputStyle,
+) -> io::Result<()> {
+    let started = Instant::now();
+    let lock = LockFile::load(lock)?;
+    let repo = args
+        .remote
+        .repo
+        .clone()
+        .or_else(|| Some(lock.repository.clone()));
+    let remote = repository(&repo, &args.remote.ssh_key, &args.remote.branch)?;
+    let opts = LockedGetOptions {
+        output: args.output.clone().unwrap_or_else(|| PathBuf::from(".")),
+        use_local_cache: !args.no_local_cache,
+        force: args.force,
+    };
+    let retrieved = bitcache::get_locked(&remote, &lock, &opts, ctx)?;
+    if output::json() {
+        let artifacts: Vec<_> = lock
+            .artifacts
+            .iter()
+            .zip(&retrieved)
+            .map(|(artifact, retrieved)| RetrievedArtifact {
+                name: &artifact.name,
+                retrieved,
+            })
+            .collect();
+        output::print_json(&artifacts)?;
     }
+    status!(
+        "Retrieved {} pinned artifact{}:",
+        retrieved.len(),
+        if retrieved.len() == 1 { "" } else { "s" }
+    );
+    for (artifact, retrieved) in lock.artifacts.iter().zip(&retrieved) {
+        status!(
+            "  {}: {} ({})",
+            artifact.name,
+            retrieved.path.display(),
+            style.size(retrieved.size)
+        );
+    }
+    status!("  Elapsed: {}", style.duration(started.elapsed()));
+    Ok(())
+}
 
-    let push_output = push_cmd.output()?;
+/// Handle the lock subcommand
+///
+/// Pins the lock file already has are kept unless `--update`, so only the
+/// artifacts whose manifest entry changed, or whose entry changed in the
+/// repository, move.
+fn handle_lock(args: &LockArgs, ctx: &Context) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    let previous = if args.output.exists() {
+        Some(LockFile::load(&args.output)?)
+    } else {
+        None
+    };
+    let opts = LockOptions {
+        manifest: LockManifest::load(&args.manifest)?,
+        previous: previous.clone().filter(|_| !args.update),
+    };
+    let lock = bitcache::lock(&remote, &opts, ctx)?;
+    if output::json() {
+        output::print_json(&lock)?;
+    }
+    if previous.as_ref() == Some(&lock) {
+        status!("{} is up to date", args.output.display());
+        return Ok(());
+    }
+    lock.save(&args.output)?;
 
-    if !push_output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Failed to push: {}",
-                String::from_utf8_lossy(&push_output.stderr)
+    let previous = previous.unwrap_or_else(|| LockFile {
+        artifacts: Vec::new(),
+        ..lock.clone()
+    });
+    for artifact in &lock.artifacts {
+        match previous.artifact(&artifact.name) {
+            Some(pinned) if pinned == artifact => {}
+            // The same source with its bitstream replaced
+            Some(pinned) if pinned.md5 == artifact.md5 => status!(
+                "  Updated {}: bitstream {} -> {}",
+                artifact.name,
+                pinned.binary_md5,
+                artifact.binary_md5
             ),
-        ));
+            Some(pinned) => status!(
+                "  Updated {}: {} -> {}",
+                artifact.name,
+                pinned.md5,
+                artifact.md5
+            ),
+            None => status!("  Pinned {}: {}", artifact.name, artifact.md5),
+        }
     }
-
+    for pinned in &previous.artifacts {
+        if lock.artifact(&pinned.name).is_none() {
+            status!("  Removed {}", pinned.name);
+        }
+    }
+    status!(
+        "Wrote {} with {} artifact{}",
+        args.output.display(),
+        lock.artifacts.len(),
+        if lock.artifacts.len() == 1 { "" } else { "s" }
+    );
     Ok(())
 }
 
-/// Handle the publish subcommand
-fn handle_publish(
-    repo: &str,
-    source: &Path,
-    bitstream: &Path,
-    target_path: &Path,
-    ssh_key: Option<&Path>,
-) -> io::Result<()> {
-    println!("Publishing bitstream...");
-
-    // Compute MD5 of source file
-    println!("Computing MD5 of source file: {}", source.display());
-    let md5_hash = compute_md5(source)?;
-    println!("MD5: {}", md5_hash);
+/// Retrieve from the first of `remotes` that has the entry
+///
+/// A repository that fails or lacks the entry is reported and the next one
+/// tried. When none has it, the result is a miss if any of them answered,
+/// and the last failure otherwise.
+fn get_from_any(
+    remotes: &[Remote],
+    opts: &GetOptions,
+    ctx: &Context,
+) -> io::Result<Option<Retrieved>> {
+    let mut answered = false;
+    let mut failure = None;
+    for (i, remote) in remotes.iter().enumerate() {
+        let next = remotes.get(i + 1);
+        match bitcache::get(remote, opts, ctx) {
+            Ok(Some(retrieved)) => return Ok(Some(retrieved)),
+            Ok(None) => {
+                answered = true;
+                if let Some(next) = next {
+                    eprintln!(
+                        "Warning: {} has no entry for {}, trying {}",
+                        remote.url, opts.md5, next.url
+                    );
+                }
+            }
+            Err(e) if fails_everywhere(&e) => return Err(e),
+            Err(e) => {
+                if let Some(next) = next {
+                    eprintln!(
+                        "Warning: {} failed: {}; trying {}",
+                        remote.url,
+                        e.to_string().trim_end(),
+                        next.url
+                    );
+                }
+                failure = Some(e);
+            }
+        }
+    }
+    match failure {
+        Some(e) if !answered => Err(e),
+        _ => Ok(None),
+    }
+}
 
-    // Create temporary directory for repository
-    let temp_dir = tempfile::tempdir()?;
-    let repo_dir = temp_dir.path().join("repo");
+/// Whether a get failed for a reason another repository would not change
+fn fails_everywhere(e: &io::Error) -> bool {
+    matches!(
+        BitcacheError::of(e),
+        Some(
+            BitcacheError::InvalidArgument { .. }
+                | BitcacheError::AmbiguousVariant { .. }
+                | BitcacheError::SourceMismatch { .. }
+                | BitcacheError::OutputExists { .. }
+                | BitcacheError::GitNotFound
+                | BitcacheError::Interrupted
+        )
+    )
+}
 
-    // Clone repository
-    println!("Cloning repository: {}", repo);
-    clone_repository(repo, &repo_dir, ssh_key)?;
+/// Exit status of get-by-source when the repository has no bitstream for
+/// the source
+const EXIT_NOT_FOUND: i32 = 2;
 
-    // Load or create metadata
-    let metadata_path = repo_dir.join("bitcache_metadata.json");
-    let mut metadata = if metadata_path.exists() {
-        Metadata::load_from_file(&metadata_path)?
-    } else {
-        Metadata::new()
+/// Handle the get-by-source subcommand
+///
+/// A miss exits with [`EXIT_NOT_FOUND`] rather than returning, so callers
+/// can tell it from a failure, which exits 1.
+fn handle_get_by_source(
+    args: &GetBySourceArgs,
+    ctx: &Context,
+    style: OutputStyle,
+) -> io::Result<()> {
+    let md5 = match &args.source_md5_hint {
+        Some(hint) => hint.trim().to_ascii_lowercase(),
+        None => hash_source(
+            &args.source,
+            args.walk.walk(),
+            args.hash_algo.unwrap_or_default(),
+        )?,
     };
+    let get = GetArgs {
+        remote: args.remote.clone(),
+        md5: Some(md5),
+        hash_algo: args.hash_algo,
+        locked: None,
+        filter_tags: Vec::new(),
+        fallback_repos: args.fallback_repos.clone(),
+        source: Vec::new(),
+        walk: args.walk,
+        variant: args.variant.clone(),
+        env: None,
+        env_format: EnvFormat::Posix,
+        no_local_cache: args.no_local_cache,
+        sync: false,
+        output: args.output.clone(),
+        force: args.force,
+        refuse_deprecated: args.refuse_deprecated,
+        skip_verify: args.skip_verify,
+        output_dir: args.output_dir.clone(),
+    };
+    match handle_get(&get, ctx, style) {
+        Err(e) if matches!(BitcacheError::of(&e), Some(BitcacheError::NotFound { .. })) => {
+            output::print_error(&e.to_string(), EXIT_NOT_FOUND);
+            process::exit(EXIT_NOT_FOUND);
+        }
+        result => result,
+    }
+}
 
-    // Create target directory in repository
-    let full_target_path = repo_dir.join(target_path);
-    fs::create_dir_all(&full_target_path)?;
-
-    // Get bitstream filename
-    let bitstream_filename = bitstream
-        .file_name()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid bitstream path"))?;
+/// Hash a local source to find the entry published for it
+fn hash_source(source: &[PathBuf], walk: SourceWalk, algo: HashAlgo) -> io::Result<String> {
+    let names = source
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let hash = bitcache::compute_source_hash(source, walk, algo).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Cannot hash source file {}: {}", names, e),
+        )
+    })?;
+    status!("{} of {}: {}", algo.label(), names, hash);
+    Ok(hash)
+}
 
-    // Copy bitstream to target location
-    let dest_bitstream = full_target_path.join(bitstream_filename);
-    println!(
-        "Copying bitstream to: {}",
-        dest_bitstream.strip_prefix(&repo_dir).unwrap().display()
-    );
-    fs::copy(bitstream, &dest_bitstream)?;
+/// Exit status of diff when the two bitstreams differ
+const EXIT_DIFFERENT: i32 = 1;
 
-    // Update metadata
-    let source_filename = source
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown")
-        .to_string();
+/// Handle the diff subcommand
+///
+/// Exits with [`EXIT_DIFFERENT`] when the bitstreams differ and with
+/// [`EXIT_NOT_FOUND`] when either hash has no entry.
+fn handle_diff(args: &DiffArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    output::status_to_stderr();
+    let opts = DiffOptions {
+        md5_a: args.md5_a.trim().to_ascii_lowercase(),
+        variant_a: args.variant_a.clone(),
+        md5_b: args.md5_b.trim().to_ascii_lowercase(),
+        variant_b: args.variant_b.clone(),
+    };
+    let diff = match bitcache::diff(&remote, &opts, ctx) {
+        Err(e) if matches!(BitcacheError::of(&e), Some(BitcacheError::NotFound { .. })) => {
+            output::print_error(&e.to_string(), EXIT_NOT_FOUND);
+            process::exit(EXIT_NOT_FOUND);
+        }
+        result => result?,
+    };
 
-    let binary_rel_path = dest_bitstream
-        .strip_prefix(&repo_dir)
-        .unwrap()
-        .to_string_lossy()
-        .to_string();
+    if output::json() {
+        output::print_json(&diff)?;
+    } else {
+        println!("--- a: {}  {}", diff.a.label(), diff.a.binary_path);
+        println!("+++ b: {}  {}", diff.b.label(), diff.b.binary_path);
+        for change in &diff.changes {
+            let show = |value: &Option<String>| match (change.field.as_str(), value) {
+                (_, None) => "(none)".to_string(),
+                ("timestamp", Some(timestamp)) => style.timestamp(timestamp),
+                (_, Some(value)) => value.clone(),
+            };
+            println!(
+                "  {}: {} -> {}",
+                change.field,
+                show(&change.a),
+                show(&change.b)
+            );
+        }
+        if let Some(delta) = diff.timestamp_delta.filter(|delta| *delta != 0) {
+            println!(
+                "  b was published {} {} a",
+                style.duration(Duration::from_secs(delta.unsigned_abs())),
+                if delta > 0 { "after" } else { "before" }
+            );
+        }
+        match diff.binaries {
+            BinaryComparison::Identical => {
+                println!("Bitstreams are identical ({})", style.size(diff.size_a))
+            }
+            BinaryComparison::Differ { offset } => println!(
+                "Bitstreams differ from byte offset {} ({} and {})",
+                offset,
+                style.size(diff.size_a),
+                style.size(diff.size_b)
+            ),
+            _ => {}
+        }
+    }
+    if !diff.identical() {
+        process::exit(EXIT_DIFFERENT);
+    }
+    Ok(())
+}
 
-    let entry = MetadataEntry {
-        md5: md5_hash.clone(),
-        binary_path: binary_rel_path,
-        source_file: source_filename,
-        timestamp: chrono::Utc::now().to_rfc3339(),
+/// Handle the info subcommand
+///
+/// A miss exits with [`EXIT_NOT_FOUND`], as get-by-source does.
+fn handle_info(args: &InfoArgs, ctx: &Context, style: OutputStyle) -> io::Result<()> {
+    let remote = args.remote.repository()?;
+    output::status_to_stderr();
+    let md5 = match &args.md5 {
+        Some(md5) => md5.trim().to_ascii_lowercase(),
+        None => hash_source(
+            &args.source,
+            args.walk.walk(),
+            args.hash_algo.unwrap_or_default(),
+        )?,
+    };
+    let Some(info) = bitcache::info(&remote, &md5, args.variant.as_deref(), ctx)? else {
+        let md5 = match &args.variant {
+            Some(name) => format!("{} ({})", md5, name),
+            None => md5,
+        };
+        output::print_error(&BitcacheError::NotFound { md5 }.to_string(), EXIT_NOT_FOUND);
+        process::exit(EXIT_NOT_FOUND);
     };
 
-    metadata.entries.insert(md5_hash.clone(), entry);
-
-    // Save metadata
-    println!("Updating metadata...");
-    metadata.save_to_file(&metadata_path)?;
+    let mut entry = info.entry;
+    entry.size = info.size;
+    if output::json() {
+        output::print_json(&entry)
+    } else if output::csv() {
+        output::print_csv([&entry]);
+        Ok(())
+    } else {
+        print_entry(&entry, style);
+        Ok(())
+    }
+}
 
-    // Commit and push
-    println!("Committing and pushing changes...");
-    let commit_msg = format!("Add bitstream for source MD5: {}", md5_hash);
-    commit_and_push(&repo_dir, &commit_msg, ssh_key)?;
+/// Exit status of exists when the source has no bitstream
+const EXIT_MISSING: i32 = 1;
 
-    println!("Successfully published bitstream with MD5: {}", md5_hash);
+/// Exit status of exists when it can't tell, e.g. when the clone fails
+const EXIT_EXISTS_FAILED: i32 = 2;
 
+/// Handle the exists subcommand
+///
+/// Exits with [`EXIT_MISSING`] on a miss and with [`EXIT_EXISTS_FAILED`] on
+/// any failure, so a build script can tell a miss from a repository it
+/// can't reach. With `quiet` nothing is printed at all, errors included:
+/// the exit status is the result.
+fn handle_exists(
+    args: &ExistsArgs,
+    ctx: &Context,
+    style: OutputStyle,
+    quiet: bool,
+) -> io::Result<()> {
+    let (md5, entries) = match find_exists(args, ctx) {
+        Ok(found) => found,
+        Err(e) => {
+            output::print_error(&e.to_string(), EXIT_EXISTS_FAILED);
+            process::exit(EXIT_EXISTS_FAILED);
+        }
+    };
+    if quiet {
+        process::exit(if entries.is_empty() { EXIT_MISSING } else { 0 });
+    }
+    if output::json() {
+        output::print_json(&entries)?;
+    }
+    match entries.as_slice() {
+        [] => {
+            let md5 = match &args.variant {
+                Some(name) => format!("{} ({})", md5, name),
+                None => md5,
+            };
+            status!("No bitstream for {}", md5);
+            process::exit(EXIT_MISSING);
+        }
+        _ if output::json() => {}
+        [entry] => print_entry(entry, style),
+        several => {
+            let names: Vec<_> = several.iter().map(|entry| entry.name()).collect();
+            println!("{}  variants: {}", md5, names.join(", "));
+        }
+    }
     Ok(())
 }
 
-/// Handle the get subcommand
-fn handle_get(repo: &str, md5: &str, ssh_key: Option<&Path>) -> io::Result<()> {
-    println!("Retrieving bitstream for MD5: {}", md5);
+/// Look up the hash exists asks about, returning it with the entries of
+/// the variant asked for, or of every variant
+fn find_exists(args: &ExistsArgs, ctx: &Context) -> io::Result<(String, Vec<MetadataEntry>)> {
+    let remote = args.remote.repository()?;
+    let md5 = match &args.md5 {
+        Some(md5) => md5.trim().to_ascii_lowercase(),
+        None => hash_source(
+            &args.source,
+            args.walk.walk(),
+            args.hash_algo.unwrap_or_default(),
+        )?,
+    };
+    let mut entries = bitcache::find(&remote, &md5, ctx)?;
+    if let Some(variant) = &args.variant {
+        entries.retain(|entry| entry.name() == variant);
+    }
+    Ok((md5, entries))
+}
 
-    // Create temporary directory for repository
-    let temp_dir = tempfile::tempdir()?;
-    let repo_dir = temp_dir.path().join("repo");
+/// Handle the `cache clean` command
+fn handle_cache_clean(
+    older_than: Option<Duration>,
+    ctx: &Context,
+    style: OutputStyle,
+) -> io::Result<()> {
+    let cache_dir = ctx.require_cache_dir()?;
+    let ((count, bytes), clones) = match older_than {
+        Some(age) => (
+            store::clean_unused(cache_dir, age)?,
+            store::clean_unused_clones(cache_dir, age)?,
+        ),
+        None => (store::clean(cache_dir)?, store::clean_clones(cache_dir)?),
+    };
+    if output::json() {
+        return output::print_json(&CacheCleaned {
+            cache_dir,
+            artifacts: count,
+            bytes,
+            clones,
+        });
+    }
+    println!(
+        "Removed {} cached artifact{} ({}) and {} cached clone{} from {}",
+        count,
+        if count == 1 { "" } else { "s" },
+        style.size(bytes),
+        clones,
+        if clones == 1 { "" } else { "s" },
+        cache_dir.display()
+    );
+    Ok(())
+}
 
-    // Clone repository
-    println!("Cloning repository: {}", repo);
-    clone_repository(repo, &repo_dir, ssh_key)?;
+fn apply_config(cli: &mut Cli, config: &config::Config) -> io::Result<()> {
+    let global = &mut cli.global;
+    global.raw_units = config.flag("raw_units", global.raw_units)?;
+    global.time = config.layer("time", global.time.take())?;
+    global.json = config.flag("json", global.json)?;
+    global.format = config.layer("format", global.format.take())?;
+    global.heartbeat = config.layer("heartbeat", global.heartbeat.take())?;
+    global.work_dir = config.layer("work_dir", global.work_dir.take())?;
+    global.cache_dir = config.layer("cache_dir", global.cache_dir.take())?;
+    global.no_cache = config.flag("no_cache", global.no_cache)?;
+    global.cache_max_size = config.layer("cache_max_size", global.cache_max_size.take())?;
+    global.limit_rate = config.layer("limit_rate", global.limit_rate.take())?;
+    global.push_retries = config.layer("push_retries", global.push_retries.take())?;
+    global.trash_retention_days =
+        config.layer("trash_retention_days", global.trash_retention_days.take())?;
+    global.max_retries = config.layer("max_retries", global.max_retries.take())?;
+    global.retry_delay_ms = config.layer("retry_delay_ms", global.retry_delay_ms.take())?;
+    global.timeout = config.layer("timeout", global.timeout.take())?;
+    global.lock_timeout = config.layer("lock_timeout", global.lock_timeout.take())?;
+    global.paranoid = config.flag("paranoid", global.paranoid)?;
+    global.verbose = config.flag("verbose", global.verbose)?;
+    global.log = config.layer("log", None)?;
+    if global.quiet {
+        global.verbose = false;
+    } else if !global.verbose {
+        match global.log {
+            Some(LogLevel::Error) => global.quiet = true,
+            Some(LogLevel::Debug) => global.verbose = true,
+            Some(LogLevel::Info) | None => {}
+        }
+    }
+    global.token = config.layer("token", global.token.take())?;
+    if let Some(token) = &global.token {
+        let _ = TOKEN.set(token.clone());
+    }
+    global.backend = config.layer("backend", global.backend.take())?;
+    if let Some(backend) = &global.backend {
+        let _ = BACKEND.set(backend.clone());
+    }
+    global.metadata_path = config.layer("metadata_path", global.metadata_path.take())?;
+    if let Some(path) = &global.metadata_path {
+        let _ = METADATA_PATH.set(path.clone());
+    }
+    global.metadata_layout = config.layer("metadata_layout", global.metadata_layout.take())?;
+    if let Some(layout) = global.metadata_layout {
+        let _ = METADATA_LAYOUT.set(layout);
+    }
 
-    // Load metadata
-    let metadata_path = repo_dir.join("bitcache_metadata.json");
-    if !metadata_path.exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "Metadata file not found in repository",
-        ));
+    match &mut cli.command {
+        Some(Commands::Publish(args)) => {
+            args.no_follow_symlinks = config.flag("no_follow_symlinks", args.no_follow_symlinks)?;
+            args.remote.layer(config)?;
+            args.path = config.layer("path", args.path.take())?;
+            args.hash_algo = config.layer("hash_algo", args.hash_algo.take())?;
+            args.compress = config.layer("compress", args.compress.take())?;
+            args.compress_level = config.layer("compress_level", args.compress_level.take())?;
+            args.on_collision = config.layer("on_collision", args.on_collision.take())?;
+            args.build_tool_version =
+                config.layer("build_tool_version", args.build_tool_version.take())?;
+            args.author = config.layer("author", args.author.take())?;
+            args.no_source_commit = config.flag("no_source_commit", args.no_source_commit)?;
+        }
+        Some(Commands::Upload(args)) => {
+            args.no_follow_symlinks = config.flag("no_follow_symlinks", args.no_follow_symlinks)?;
+            args.remote.layer(config)?;
+            args.path = config.layer("path", args.path.take())?;
+        }
+        Some(Commands::Register(args)) => {
+            args.remote.layer(config)?;
+            args.hash_algo = config.layer("hash_algo", args.hash_algo.take())?;
+            args.build_tool_version =
+                config.layer("build_tool_version", args.build_tool_version.take())?;
+            args.author = config.layer("author", args.author.take())?;
+        }
+        Some(Commands::PublishBatch(args)) => {
+            args.no_follow_symlinks = config.flag("no_follow_symlinks", args.no_follow_symlinks)?;
+            args.remote.layer(config)?;
+            args.path = config.layer("path", args.path.take())?;
+            args.hash_algo = config.layer("hash_algo", args.hash_algo.take())?;
+            args.compress = config.layer("compress", args.compress.take())?;
+            args.compress_level = config.layer("compress_level", args.compress_level.take())?;
+            args.on_collision = config.layer("on_collision", args.on_collision.take())?;
+            args.build_tool_version =
+                config.layer("build_tool_version", args.build_tool_version.take())?;
+            args.author = config.layer("author", args.author.take())?;
+            args.no_source_commit = config.flag("no_source_commit", args.no_source_commit)?;
+            args.jobs = config.layer("jobs", args.jobs.take())?;
+        }
+        Some(Commands::Update(args)) => {
+            args.no_follow_symlinks = config.flag("no_follow_symlinks", args.no_follow_symlinks)?;
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Get(args)) => {
+            args.remote.layer(config)?;
+            args.fallback_repos =
+                config.list("fallback_repos", mem::take(&mut args.fallback_repos))?;
+            if args.locked.is_none() {
+                args.hash_algo = config.layer("hash_algo", args.hash_algo.take())?;
+            }
+            args.no_local_cache = config.flag("no_local_cache", args.no_local_cache)?;
+            args.sync = config.flag("sync", args.sync)?;
+            args.refuse_deprecated = config.flag("refuse_deprecated", args.refuse_deprecated)?;
+            args.skip_verify = config.flag("skip_verify", args.skip_verify)?;
+            args.output_dir = config.layer("output_dir", None)?;
+        }
+        Some(Commands::Exists(args)) => {
+            args.remote.layer(config)?;
+            args.hash_algo = config.layer("hash_algo", args.hash_algo.take())?;
+        }
+        Some(Commands::GetBySource(args)) => {
+            args.remote.layer(config)?;
+            args.fallback_repos =
+                config.list("fallback_repos", mem::take(&mut args.fallback_repos))?;
+            args.hash_algo = config.layer("hash_algo", args.hash_algo.take())?;
+            args.no_local_cache = config.flag("no_local_cache", args.no_local_cache)?;
+            args.refuse_deprecated = config.flag("refuse_deprecated", args.refuse_deprecated)?;
+            args.skip_verify = config.flag("skip_verify", args.skip_verify)?;
+            args.output_dir = config.layer("output_dir", None)?;
+        }
+        Some(Commands::List(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Search(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Delete(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Deprecate(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Verify(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Init(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Gc(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Export(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Import(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Sync(args)) => {
+            scope_token(config.layer::<String>("repo", None)?.as_deref());
+            args.ssh_key = config.layer("ssh_key", args.ssh_key.take())?;
+        }
+        Some(Commands::Diff(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Info(args)) => {
+            args.remote.layer(config)?;
+            args.hash_algo = config.layer("hash_algo", args.hash_algo.take())?;
+        }
+        Some(Commands::Top(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Repair(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Prune(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Lock(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Status(args)) => {
+            if args.repo.is_empty() {
+                args.repo.extend(config.layer::<String>("repo", None)?);
+            }
+            scope_token(args.repo.first().map(String::as_str));
+            args.ssh_key = config.layer("ssh_key", args.ssh_key.take())?;
+            args.branch = config.layer("branch", args.branch.take())?;
+        }
+        Some(Commands::Bundle { command }) => match command {
+            BundleCommand::Create(args) => args.remote.layer(config)?,
+            BundleCommand::Apply(args) => args.remote.layer(config)?,
+            BundleCommand::List(_) => {}
+        },
+        Some(Commands::Compact(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Trash { command }) => match command {
+            TrashCommand::List(args) => args.remote.layer(config)?,
+            TrashCommand::Restore(args) => args.remote.layer(config)?,
+            TrashCommand::Empty(args) => args.remote.layer(config)?,
+        },
+        Some(Commands::MigrateMetadata(args)) => {
+            args.remote.layer(config)?;
+        }
+        Some(Commands::Config { .. })
+        | Some(Commands::Cache { .. })
+        | Some(Commands::Schema { .. })
+        | Some(Commands::FilterCheck { .. })
+        | Some(Commands::BugReport)
+        | Some(Commands::External(_))
+        | None => {}
     }
 
-    let metadata = Metadata::load_from_file(&metadata_path)?;
+    Ok(())
+}
+
+fn run(mut cli: Cli) -> io::Result<()> {
+    let config = config::Config::load()?;
+    apply_config(&mut cli, &config)?;
 
-    // Find entry by MD5
-    let entry = metadata.entries.get(md5).ok_or_else(|| {
-        io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("No binary found for MD5: {}", md5),
-        )
-    })?;
+    let style = OutputStyle::new(cli.global.raw_units, cli.global.time.unwrap_or_default());
+    match (cli.global.json, cli.global.format) {
+        (true, Some(Formatter::Csv)) => {
+            return Err(BitcacheError::InvalidArgument {
+                flag: "--format",
+                value: "csv".to_string(),
+                reason: "--json asks for JSON; use one or the other".to_string(),
+            }
+            .into());
+        }
+        (true, _) => output::json_mode(),
+        (false, format) => output::set_format(format.unwrap_or_default()),
+    }
+    let ctx = Context {
+        work_dir: cli.global.work_dir.clone(),
+        keep_temp: cli.global.keep_temp,
+        paranoid: cli.global.paranoid,
+        git_progress: cli.global.verbose,
+        clone_cache: !cli.global.no_cache,
+        cache_dir: cli
+            .global
+            .cache_dir
+            .clone()
+            .or_else(store::default_cache_dir),
+        cache_max_size: cli
+            .global
+            .cache_max_size
+            .map_or(store::DEFAULT_MAX_SIZE, |size| size.0),
+        limit_rate: cli.global.limit_rate.map(|rate| rate.0),
+        push_attempts: cli
+            .global
+            .push_retries
+            .map_or(bitcache::DEFAULT_PUSH_ATTEMPTS, |retries| {
+                retries.saturating_add(1)
+            }),
+        trash_retention: match cli.global.trash_retention_days {
+            None => Some(bitcache::DEFAULT_TRASH_RETENTION),
+            Some(0) => None,
+            Some(days) => Some(Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+        },
+        max_retries: cli
+            .global
+            .max_retries
+            .unwrap_or(bitcache::DEFAULT_MAX_RETRIES),
+        retry_delay: cli
+            .global
+            .retry_delay_ms
+            .map_or(bitcache::DEFAULT_RETRY_DELAY, Duration::from_millis),
+        timeout: cli
+            .global
+            .timeout
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs),
+        lock_timeout: cli
+            .global
+            .lock_timeout
+            .filter(|secs| *secs > 0)
+            .map(Duration::from_secs),
+        ..Context::default()
+    };
+    output::print_progress(cli.global.verbose);
+    let quiet = cli.global.quiet;
+    if quiet {
+        output::quiet();
+    }
+    if quiet && matches!(&cli.command, Some(Commands::Exists(_))) {
+        output::silence();
+    }
+    // Heartbeats are for logs; a terminal gets a spinner or bar instead
+    let interactive = io::stderr().is_terminal()
+        && env::var_os("TERM").is_none_or(|term| term != "dumb")
+        && !quiet;
+    if interactive {
+        indicator::enable();
+    }
+    heartbeat::set_interval(if interactive {
+        0
+    } else {
+        cli.global
+            .heartbeat
+            .unwrap_or(heartbeat::DEFAULT_INTERVAL_SECS)
+    });
 
-    // Get binary file path
-    let binary_path = repo_dir.join(&entry.binary_path);
-    if !binary_path.exists() {
-        return Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            format!("Binary file not found: {}", entry.binary_path),
-        ));
+    if cli.version {
+        if output::json() {
+            return output::print_json(&version::build());
+        }
+        if cli.global.verbose {
+            println!("{}", version::long());
+        } else {
+            println!("{}", version::short());
+        }
+        return Ok(());
     }
 
-    // Copy to current directory
-    let filename = binary_path
-        .file_name()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid binary path"))?;
+    if cli.list_plugins {
+        let plugins = plugin::discover(&builtin_commands());
+        if output::json() {
+            let plugins: Vec<_> = plugins
+                .iter()
+                .map(|(name, path)| Plugin { name, path })
+                .collect();
+            return output::print_json(&plugins);
+        }
+        for (name, path) in plugins {
+            println!("{}  {}", name, path.display());
+        }
+        return Ok(());
+    }
 
-    let current_dir = std::env::current_dir()?;
-    let dest_path = current_dir.join(filename);
+    let Some(command) = cli.command else {
+        Cli::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit();
+    };
 
-    println!("Copying {} to {}", filename.to_string_lossy(), dest_path.display());
-    fs::copy(&binary_path, &dest_path)?;
+    match command {
+        Commands::Publish(args) => handle_publish(&args, &ctx, style),
+        Commands::Upload(args) => handle_upload(&args, &ctx, style),
+        Commands::Register(args) => handle_register(&args, &ctx),
+        Commands::PublishBatch(args) => handle_publish_batch(&args, &ctx, style),
+        Commands::Update(args) => handle_update(&args, &ctx, style),
+        Commands::Get(args) => handle_get(&args, &ctx, style),
+        Commands::GetBySource(args) => handle_get_by_source(&args, &ctx, style),
+        Commands::Exists(args) => handle_exists(&args, &ctx, style, quiet),
+        Commands::Info(args) => handle_info(&args, &ctx, style),
+        Commands::Diff(args) => handle_diff(&args, &ctx, style),
+        Commands::Config {
+            command: ConfigCommand::Show,
+        } if output::json() => {
+            let settings: Vec<_> = config::OPTIONS
+                .iter()
+                .map(|option| {
+                    let found = config.shown(option.key);
+                    Setting {
+                        key: option.key,
+                        source: found.as_ref().map(|(_, source)| source.to_string()),
+                        value: found.map(|(value, _)| value),
+                    }
+                })
+                .collect();
+            output::print_json(&settings)
+        }
+        Commands::Config {
+            command: ConfigCommand::Show,
+        } => {
+            print!("{}", config.describe());
+            Ok(())
+        }
+        Commands::Repair(args) => handle_repair(&args, &ctx),
+        Commands::MigrateMetadata(args) => handle_migrate_metadata(&args, &ctx),
+        Commands::List(args) => handle_list(&args, &ctx, style),
+        Commands::FilterCheck { expression } => handle_filter_check(&expression),
+        Commands::Search(args) => handle_search(&args, &ctx, style),
+        Commands::Delete(args) => handle_delete(&args, &ctx),
+        Commands::Deprecate(args) => handle_deprecate(&args, &ctx),
+        Commands::Trash { command } => match command {
+            TrashCommand::List(args) => handle_trash_list(&args, &ctx, style),
+            TrashCommand::Restore(args) => handle_trash_restore(&args, &ctx),
+            TrashCommand::Empty(args) => handle_trash_empty(&args, &ctx),
+        },
+        Commands::Top(args) => handle_top(&args, &ctx, style),
+        Commands::Lock(args) => handle_lock(&args, &ctx),
+        Commands::Status(args) => handle_status(&args, &ctx, style),
+        Commands::Bundle { command } => match command {
+            BundleCommand::Create(args) => handle_bundle_create(&args, &ctx, style),
+            BundleCommand::Apply(args) => handle_bundle_apply(&args, &ctx),
+            BundleCommand::List(args) => handle_bundle_list(&args, &ctx, style),
+        },
+        Commands::Prune(args) => handle_prune(&args, &ctx, style),
+        Commands::Compact(args) => handle_compact(&args, &ctx, style),
+        Commands::Verify(args) => handle_verify(&args, &ctx),
+        Commands::Init(args) => handle_init(&args, &ctx),
+        Commands::Gc(args) => handle_gc(&args, &ctx),
+        Commands::Export(args) => handle_export(&args, &ctx, style),
+        Commands::Import(args) => handle_import(&args, &ctx, style),
+        Commands::Sync(args) => handle_sync(&args, &ctx, style),
+        Commands::Cache {
+            command: CacheCommand::Clean { older_than },
+        } => handle_cache_clean(older_than, &ctx, style),
+        Commands::Schema { document } => {
+            let document = match document {
+                SchemaDocument::Metadata => schema::Document::Metadata,
+                SchemaDocument::Plan => schema::Document::Plan,
+                SchemaDocument::Manifest => schema::Document::Manifest,
+                SchemaDocument::Get => schema::Document::Get,
+                SchemaDocument::List => schema::Document::List,
+                SchemaDocument::Verify => schema::Document::Verify,
+            };
+            output::print_json(&schema::schema(document))
+        }
+        Commands::BugReport if output::json() => output::print_json(&version::environment()),
+        Commands::BugReport => {
+            println!("{}", version::bug_report());
+            Ok(())
+        }
+        Commands::External(args) => {
+            let (name, args) = args
+                .split_first()
+                .expect("clap passes the command name first");
+            let name = name.to_string_lossy();
+            let Some(path) = plugin::find(&name) else {
+                Cli::command()
+                    .error(
+                        ErrorKind::InvalidSubcommand,
+                        format!(
+                            "unrecognized subcommand '{0}': it is neither built in nor a bitcache-{0} plugin on PATH (see --list-plugins)",
+                            name
+                        ),
+                    )
+                    .exit();
+            };
+            let global = &cli.global;
+            let flag = |set: bool| set.then(|| OsString::from("true"));
+            let vars = plugin::environment(
+                &config,
+                &[
+                    ("raw_units", flag(global.raw_units)),
+                    ("json", flag(global.json)),
+                    (
+                        "format",
+                        global
+                            .format
+                            .and_then(|format| format.to_possible_value())
+                            .map(|value| value.get_name().into()),
+                    ),
+                    (
+                        "time",
+                        global
+                            .time
+                            .and_then(|time| time.to_possible_value())
+                            .map(|value| value.get_name().into()),
+                    ),
+                    (
+                        "heartbeat",
+                        global.heartbeat.map(|secs| secs.to_string().into()),
+                    ),
+                    ("work_dir", global.work_dir.clone().map(Into::into)),
+                    ("cache_dir", ctx.cache_dir.clone().map(Into::into)),
+                    ("no_cache", flag(global.no_cache)),
+                    (
+                        "cache_max_size",
+                        Some(ctx.cache_max_size.to_string().into()),
+                    ),
+                    (
+                        "limit_rate",
+                        ctx.limit_rate.map(|rate| rate.to_string().into()),
+                    ),
+                    (
+                        "push_retries",
+                        Some((ctx.push_attempts.max(1) - 1).to_string().into()),
+                    ),
+                    (
+                        "trash_retention_days",
+                        Some(
+                            ctx.trash_retention
+                                .map_or(0, |retention| retention.as_secs() / 86400)
+                                .to_string()
+                                .into(),
+                        ),
+                    ),
+                    ("max_retries", Some(ctx.max_retries.to_string().into())),
+                    (
+                        "retry_delay_ms",
+                        Some(ctx.retry_delay.as_millis().to_string().into()),
+                    ),
+                    (
+                        "timeout",
+                        ctx.timeout
+                            .map(|timeout| timeout.as_secs().to_string().into()),
+                    ),
+                    (
+                        "lock_timeout",
+                        ctx.lock_timeout
+                            .map(|timeout| timeout.as_secs().to_string().into()),
+                    ),
+                    ("paranoid", flag(global.paranoid)),
+                    ("verbose", flag(global.verbose)),
+                ],
+            );
+            process::exit(plugin::run(&path, args, &vars)?);
+        }
+    }
+}
 
-    println!("Successfully retrieved bitstream:");
-    println!("  Source file: {}", entry.source_file);
-    println!("  MD5: {}", entry.md5);
-    println!("  Timestamp: {}", entry.timestamp);
-    println!("  Saved to: {}", dest_path.display());
+/// Names of the built-in commands, which plugins cannot replace
+fn builtin_commands() -> Vec<String> {
+    let cli = Cli::command();
+    let mut names: Vec<String> = cli
+        .get_subcommands()
+        .flat_map(|command| command.get_name_and_visible_aliases())
+        .map(String::from)
+        .collect();
+    names.push("help".to_string());
+    names
+}
 
-    Ok(())
+/// Install the Ctrl-C handler
+///
+/// The first Ctrl-C requests a graceful stop; a second one exits immediately.
+fn install_interrupt_handler() -> io::Result<()> {
+    ctrlc::set_handler(|| {
+        if cancel::request() {
+            process::exit(cancel::EXIT_INTERRUPTED);
+        }
+        eprintln!("Interrupted, cleaning up (press Ctrl-C again to exit immediately)...");
+    })
+    .map_err(io::Error::other)
 }
 
-fn main() -> io::Result<()> {
+fn main() {
+    version::install_panic_hook();
     let cli = Cli::parse();
 
-    match cli.command {
-        Commands::Publish {
-            repo,
-            source,
-            bitstream,
-            path,
-            ssh_key,
-        } => handle_publish(&repo, &source, &bitstream, &path, ssh_key.as_deref()),
-        Commands::Get { repo, md5, ssh_key } => handle_get(&repo, &md5, ssh_key.as_deref()),
+    // Printing to a reader that went away, as in `bitcache list | head -1`,
+    // ends the command quietly once everything it was doing unwound
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        install_interrupt_handler().and_then(|_| run(cli))
+    }))
+    .unwrap_or_else(|payload| {
+        if version::panic_message(&*payload).is_some_and(version::is_broken_pipe) {
+            process::exit(0);
+        }
+        panic::resume_unwind(payload)
+    });
+    if let Err(e) = result {
+        let code = error::exit_code(&e);
+        if code == cancel::EXIT_INTERRUPTED {
+            eprintln!("Interrupted");
+        } else {
+            output::print_error(&e.to_string(), code);
+        }
+        process::exit(code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_token_host_is_that_of_an_https_url() {
+        for (url, host) in [
+            ("https://git.example.com/fpga.git", Some("git.example.com")),
+            (
+                "HTTPS://Git.Example.com:8443/fpga.git",
+                Some("git.example.com:8443"),
+            ),
+            (
+                "https://ci@git.example.com/fpga.git",
+                Some("git.example.com"),
+            ),
+            ("https://git.example.com", Some("git.example.com")),
+            ("https://git.example.com?x=y", Some("git.example.com")),
+            ("http://git.example.com/fpga.git", None),
+            ("git@git.example.com:fpga.git", None),
+            ("https:///fpga.git", None),
+            ("/srv/fpga.git", None),
+        ] {
+            assert_eq!(https_host(url).as_deref(), host, "{}", url);
+        }
+    }
+
+    #[test]
+    fn every_argument_is_layered_or_cli_only() {
+        let cli = Cli::command();
+        let mut commands = vec![&cli];
+        while let Some(command) = commands.pop() {
+            for arg in command.get_arguments() {
+                let id = arg.get_id().as_str();
+                assert!(
+                    config::OPTIONS.iter().any(|option| option.key == id)
+                        || config::CLI_ONLY.contains(&id),
+                    "argument '{}' of '{}' is neither in config::OPTIONS nor config::CLI_ONLY",
+                    id,
+                    command.get_name()
+                );
+            }
+            commands.extend(command.get_subcommands());
+        }
+    }
+
+    #[test]
+    fn no_help_text_has_rustdoc_markup() {
+        let cli = Cli::command();
+        let mut commands = vec![&cli];
+        while let Some(command) = commands.pop() {
+            let mut texts: Vec<String> = [command.get_about(), command.get_long_about()]
+                .into_iter()
+                .flatten()
+                .map(ToString::to_string)
+                .collect();
+            for arg in command.get_arguments() {
+                texts.extend(
+                    [arg.get_help(), arg.get_long_help()]
+                        .into_iter()
+                        .flatten()
+                        .map(ToString::to_string),
+                );
+                texts.extend(
+                    arg.get_possible_values()
+                        .iter()
+                        .filter_map(|value| value.get_help())
+                        .map(ToString::to_string),
+                );
+            }
+            for text in texts {
+                assert!(
+                    !text.contains("[`"),
+                    "help of '{}' links rustdoc: {}",
+                    command.get_name(),
+                    text
+                );
+            }
+            commands.extend(command.get_subcommands());
+        }
+    }
+
+    #[test]
+    fn only_commands_that_publish_say_so_of_branch() {
+        let cli = Cli::command();
+        for name in [
+            "get",
+            "get-by-source",
+            "exists",
+            "info",
+            "list",
+            "search",
+            "verify",
+            "top",
+            "diff",
+            "export",
+            "lock",
+            "status",
+        ] {
+            let command = cli.find_subcommand(name).expect(name);
+            let branch = command
+                .get_arguments()
+                .find(|arg| arg.get_id() == "branch")
+                .and_then(|arg| arg.get_help())
+                .expect("--branch help")
+                .to_string();
+            assert!(
+                branch.starts_with("Branch or tag to read "),
+                "{}: {}",
+                name,
+                branch
+            );
+        }
+        let publish = cli.find_subcommand("publish").expect("publish");
+        let branch = publish
+            .get_arguments()
+            .find(|arg| arg.get_id() == "branch")
+            .and_then(|arg| arg.get_help())
+            .expect("--branch help");
+        assert!(branch.to_string().contains("publish to"));
     }
 }
@@ -0,0 +1,247 @@
+//! The manifest listing what `publish-batch` publishes.
+//!
+//! A TOML file with one `[[entry]]` table per bitstream:
+//!
+//! ```toml
+//! [[entry]]
+//! source = "rtl/top.vhd"
+//! bitstream = "build/zedboard/top.bit"
+//! path = "boards/zedboard"
+//! variant = "zedboard"
+//!
+//! [[entry]]
+//! source = "rtl/top.vhd"
+//! bitstream = "build/arty/top.bit"
+//! path = "boards/arty"
+//! rename_in_repo = "top_arty.bit"
+//! variant = "arty"
+//! tags = { target_board = "arty-a7" }
+//! ```
+//!
+//! A file whose name ends in `.json` is read as JSON of the same shape,
+//! `{"entry": [{"source": ..., ...}]}`. Relative `source` and `bitstream`
+//! paths are relative to the directory of the manifest, so it can be used
+//! from anywhere.
+
+use crate::PublishOptions;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A parsed manifest
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchManifest {
+    /// The bitstreams to publish, in order
+    #[serde(rename = "entry", default)]
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// One bitstream of a manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// Source file whose hash identifies the bitstream
+    pub source: PathBuf,
+    /// Binary file (bitstream) to store
+    pub bitstream: PathBuf,
+    /// Target directory in the repository [default: the `--path` given for
+    /// the whole batch]
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// File name to store the bitstream under, instead of its local name
+    #[serde(default)]
+    pub rename_in_repo: Option<PathBuf>,
+    /// Variant to publish the bitstream as [default: the `--variant` given
+    /// for the whole batch]
+    #[serde(default)]
+    pub variant: Option<String>,
+    /// Tags to record in the entry, in addition to those given for the
+    /// whole batch; an entry's own value wins for the same key
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl BatchManifest {
+    /// Read and parse the manifest at `path`
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read manifest {}: {}", path.display(), e),
+            )
+        })?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let parsed = if is_json {
+            serde_json::from_str::<Self>(&content).map_err(|e| e.to_string())
+        } else {
+            toml::from_str::<Self>(&content).map_err(|e| e.to_string())
+        };
+        let mut manifest = parsed.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to parse manifest {}: {}", path.display(), e),
+            )
+        })?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for entry in &mut manifest.entries {
+            entry.source = base.join(&entry.source);
+            entry.bitstream = base.join(&entry.bitstream);
+        }
+        Ok(manifest)
+    }
+
+    /// The options to publish each entry with, taking everything an entry
+    /// does not set from `defaults`
+    ///
+    /// Fails if an entry has no `path` and `defaults` has none either.
+    pub fn options(&self, defaults: &PublishOptions) -> io::Result<Vec<PublishOptions>> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let path = match &entry.path {
+                    Some(path) => path.clone(),
+                    None if !defaults.path.as_os_str().is_empty() => defaults.path.clone(),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!(
+                                "Manifest entry for {} has no path and no --path was given",
+                                entry.bitstream.display()
+                            ),
+                        ))
+                    }
+                };
+                let mut tags = defaults.tags.clone();
+                tags.extend(entry.tags.clone());
+                Ok(PublishOptions {
+                    source: entry.source.clone(),
+                    bitstream: entry.bitstream.clone(),
+                    path,
+                    rename_in_repo: entry.rename_in_repo.clone(),
+                    variant: entry.variant.clone().or_else(|| defaults.variant.clone()),
+                    tags,
+                    ..defaults.clone()
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_toml_relative_to_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "bitstreams.toml",
+            r#"
+                [[entry]]
+                source = "rtl/top.vhd"
+                bitstream = "build/top.bit"
+                path = "boards/zedboard"
+
+                [[entry]]
+                source = "/abs/other.vhd"
+                bitstream = "build/other.bit"
+                rename_in_repo = "other_arty.bit"
+            "#,
+        );
+        let manifest = BatchManifest::load(&path).unwrap();
+        assert_eq!(manifest.entries.len(), 2);
+        let first = &manifest.entries[0];
+        assert_eq!(first.source, dir.path().join("rtl/top.vhd"));
+        assert_eq!(first.bitstream, dir.path().join("build/top.bit"));
+        assert_eq!(first.path.as_deref(), Some(Path::new("boards/zedboard")));
+        assert_eq!(manifest.entries[1].source, Path::new("/abs/other.vhd"));
+    }
+
+    #[test]
+    fn reads_json_of_the_same_shape() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "bitstreams.JSON",
+            r#"{"entry": [{"source": "top.vhd", "bitstream": "top.bit", "path": "boards"}]}"#,
+        );
+        let manifest = BatchManifest::load(&path).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert_eq!(manifest.entries[0].bitstream, dir.path().join("top.bit"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            dir.path(),
+            "bitstreams.toml",
+            "[[entry]]\nsource = \"a.vhd\"\nbitstream = \"a.bit\"\nbranch = \"main\"\n",
+        );
+        let error = BatchManifest::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("branch"), "{}", error);
+    }
+
+    #[test]
+    fn options_take_the_default_path() {
+        let manifest = BatchManifest {
+            entries: vec![
+                ManifestEntry {
+                    source: "a.vhd".into(),
+                    bitstream: "a.bit".into(),
+                    path: Some("boards/a".into()),
+                    rename_in_repo: None,
+                    variant: Some("arty".into()),
+                    tags: [("board".to_string(), "arty".to_string())].into(),
+                },
+                ManifestEntry {
+                    source: "b.vhd".into(),
+                    bitstream: "b.bit".into(),
+                    path: None,
+                    rename_in_repo: Some("b_arty.bit".into()),
+                    variant: None,
+                    tags: BTreeMap::new(),
+                },
+            ],
+        };
+        let defaults = PublishOptions {
+            follow_symlinks: false,
+            tags: [("board", "zedboard"), ("ci", "yes")]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .into(),
+            variant: Some("default".into()),
+            ..PublishOptions::new("", "", "boards/default")
+        };
+        let options = manifest.options(&defaults).unwrap();
+        assert_eq!(options[0].path, Path::new("boards/a"));
+        assert_eq!(options[1].path, Path::new("boards/default"));
+        assert_eq!(
+            options[1].rename_in_repo.as_deref(),
+            Some(Path::new("b_arty.bit"))
+        );
+        assert!(options.iter().all(|opts| !opts.follow_symlinks));
+        assert_eq!(options[0].variant.as_deref(), Some("arty"));
+        assert_eq!(options[1].variant.as_deref(), Some("default"));
+        // An entry's own tag wins over the batch's
+        assert_eq!(options[0].tags["board"], "arty");
+        assert_eq!(options[0].tags["ci"], "yes");
+        assert_eq!(options[1].tags, defaults.tags);
+
+        let no_path = PublishOptions::new("", "", "");
+        assert!(manifest.options(&no_path).is_err());
+    }
+}
@@ -0,0 +1,1137 @@
+//! The metadata file that maps source MD5 hashes to published bitstreams.
+//!
+//! The file is a JSON object whose `entries` member maps each source MD5 to
+//! the list of [`MetadataEntry`] variants published for it, such as
+//! bitstreams for two boards or a bitstream and a flash image built from the
+//! same source:
+//!
+//! ```json
+//! {
+//!   "schema_version": 4,
+//!   "entries": {
+//!     "d3699e851d7f4fde53ee37c037408af7": [
+//!       {
+//!         "md5": "d3699e851d7f4fde53ee37c037408af7",
+//!         "binary_path": "boards/zedboard/top.bit",
+//!         "source_file": "top.vhd",
+//!         "timestamp": "2024-05-02T09:14:27.118Z"
+//!       }
+//!     ],
+//!     "6f1ed002ab5595859014ebf0951522d9": [
+//!       {
+//!         "md5": "6f1ed002ab5595859014ebf0951522d9",
+//!         "binary_path": "boards/arty/arty.bit",
+//!         "source_file": "arty.vhd",
+//!         "timestamp": "2024-05-03T10:02:51.506Z"
+//!       },
+//!       {
+//!         "md5": "6f1ed002ab5595859014ebf0951522d9",
+//!         "binary_path": "boards/arty/arty.mcs",
+//!         "source_file": "arty.vhd",
+//!         "timestamp": "2024-05-03T10:03:12.940Z",
+//!         "variant": "flash"
+//!       }
+//!     ]
+//!   }
+//! }
+//! ```
+//!
+//! The entries of one source are told apart by [`MetadataEntry::name`].
+//! Before version 3 a source with a single entry was written as that entry
+//! alone; such files are still read. Members and entry fields this
+//! version does not know about, such as those added by a newer bitcache,
+//! are kept when a file is loaded and written back unchanged when it is
+//! saved, so tools of different versions can share a repository without
+//! losing each other's data. Entries are written in no particular order.
+//!
+//! Changes that older versions could not read correctly bump the top-level
+//! `schema_version`; a file without it is version 1, the shape of the first
+//! release. Loading a file runs the migrations from its version up to
+//! [`METADATA_SCHEMA_VERSION`], and the next save writes the current
+//! version. A file from a newer schema than this bitcache knows is refused
+//! rather than misread.
+//!
+//! In the sharded layout the file holds no `entries`; they are in a
+//! directory of files of their own next to it, see [`crate::layout`]. The
+//! functions here read and write both layouts.
+
+use crate::error::BitcacheError;
+use crate::layout::{self, MetadataLayout};
+use crate::trash::{TrashedEntry, TRASH_MEMBER};
+use crate::{cancel, fsutil, paths, Compression, HashAlgo};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Metadata entry for a cached binary file
+///
+/// New fields may be added in later versions; create entries with
+/// [`MetadataEntry::new`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct MetadataEntry {
+    /// Hash of the source file; MD5 unless `hash_algo` says otherwise,
+    /// the field keeping its name from when MD5 was the only algorithm
+    pub md5: String,
+    /// Path to the binary file in the repository
+    pub binary_path: String,
+    /// Original source filename; the names of the files or directories
+    /// given, separated by `, `, for a source of several
+    pub source_file: String,
+    /// Every file of a source of several, by the name it was hashed under
+    /// as [`crate::compute_source_hash`] describes; empty for a source of
+    /// one plain file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_files: Vec<String>,
+    /// Timestamp of publication, RFC 3339
+    pub timestamp: String,
+    /// [`crate::HashAlgo::name`] of the algorithm `md5` was computed with;
+    /// only written to the file when it is not `md5`, and `md5` when absent
+    #[serde(
+        default = "default_hash_algo",
+        skip_serializing_if = "is_default_hash_algo"
+    )]
+    pub hash_algo: String,
+    /// Whether the bitstream was found faulty and should no longer be
+    /// used, see [`crate::deprecate`]
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub deprecated: bool,
+    /// Why the bitstream was deprecated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecation_reason: Option<String>,
+    /// Hash of the source whose entry to use instead of a deprecated one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement: Option<String>,
+    /// MD5 of the bitstream as it was published, for [`crate::verify`];
+    /// `None` for entries published before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_md5: Option<String>,
+    /// Branch the entry was published to; `None` for entries published
+    /// before it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// [`Compression::name`] of how the bitstream is stored, with the
+    /// compression's extension appended to its name; `None` for one stored
+    /// as it is, including every entry published before compression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// Size in bytes of a compressed bitstream once decompressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_size: Option<u64>,
+    /// Size in bytes of the bitstream as stored in the repository, so it is
+    /// known without fetching the file; `None` for entries published before
+    /// it was recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Name of the bitstream among those published for the same source,
+    /// when it is not the file name; see [`MetadataEntry::name`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// User-defined `key = value` tags given to publish with `--tag`
+    ///
+    /// Some tools write tags as a list of strings instead; each is read as a
+    /// `key=value` pair, or as a key with an empty value if it has no `=`,
+    /// and saved back as an object.
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "deserialize_tags"
+    )]
+    pub tags: BTreeMap<String, String>,
+    /// Unix permission bits the bitstream had when published, given back to
+    /// the copy [`crate::get`] saves; `None` when published elsewhere or
+    /// before they were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<u32>,
+    /// Name [`crate::get`] saves the bitstream under when it is stored under
+    /// another, after a publish renamed it to avoid a collision
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// Operating system the bitstream was built for, by default the
+    /// [`std::env::consts::OS`] of the machine that published it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    /// Architecture the bitstream was built for, by default the
+    /// [`std::env::consts::ARCH`] of the machine that published it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// Version of the tool that built the bitstream, e.g. `Vivado 2023.2`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_tool_version: Option<String>,
+    /// Who published the bitstream, by default as `Name <email>` from the
+    /// git configuration of the publishing machine
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_by: Option<String>,
+    /// Version of bitcache that published the bitstream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_version: Option<String>,
+    /// Commit of the git work tree the source file was in when published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_commit: Option<String>,
+    /// Fields this version does not know, kept for re-saving
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+}
+
+impl MetadataEntry {
+    /// An entry for the bitstream at `binary_path`, published from
+    /// `source_file` with MD5 `md5`
+    pub fn new(
+        md5: impl Into<String>,
+        binary_path: impl Into<String>,
+        source_file: impl Into<String>,
+        timestamp: impl Into<String>,
+    ) -> Self {
+        Self {
+            md5: md5.into(),
+            binary_path: binary_path.into(),
+            source_file: source_file.into(),
+            source_files: Vec::new(),
+            timestamp: timestamp.into(),
+            hash_algo: default_hash_algo(),
+            deprecated: false,
+            deprecation_reason: None,
+            replacement: None,
+            binary_md5: None,
+            branch: None,
+            compression: None,
+            original_size: None,
+            size: None,
+            variant: None,
+            tags: BTreeMap::new(),
+            file_mode: None,
+            file_name: None,
+            os: None,
+            arch: None,
+            build_tool_version: None,
+            published_by: None,
+            tool_version: None,
+            source_commit: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// The name telling this bitstream apart from others published for the
+    /// same source: its `variant`, or else the name of the file `get` saves
+    pub fn name(&self) -> &str {
+        if let Some(variant) = &self.variant {
+            return variant;
+        }
+        if let Some(file_name) = &self.file_name {
+            return file_name;
+        }
+        let file_name = self
+            .binary_path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.binary_path);
+        let extension = self.compression().map_or("", Compression::extension);
+        match file_name.strip_suffix(extension) {
+            Some(stripped) if !stripped.is_empty() => stripped,
+            _ => file_name,
+        }
+    }
+
+    /// How the bitstream is stored, failing for a compression this version
+    /// does not know
+    pub(crate) fn compression(&self) -> io::Result<Compression> {
+        let Some(name) = &self.compression else {
+            return Ok(Compression::None);
+        };
+        name.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "The entry for MD5 {} is stored with '{}' compression ({}), which this version of bitcache cannot decompress",
+                    self.label(),
+                    name,
+                    self.binary_path
+                ),
+            )
+        })
+    }
+
+    /// Record that the bitstream is stored with `compression`
+    pub(crate) fn set_compression(&mut self, compression: Compression) {
+        self.compression =
+            (compression != Compression::None).then(|| compression.name().to_string());
+    }
+
+    /// The MD5, followed by the name unless it is the file name, for
+    /// messages
+    pub fn label(&self) -> String {
+        match &self.variant {
+            Some(variant) => format!("{} ({})", self.md5, variant),
+            None => self.md5.clone(),
+        }
+    }
+
+    /// Publication order, for listings
+    pub(crate) fn sort_key(&self) -> (&str, &str, &str) {
+        (&self.timestamp, &self.md5, self.name())
+    }
+
+    /// The tags as `key=value` strings, or just `key` for an empty value
+    pub fn tag_labels(&self) -> Vec<String> {
+        self.tags
+            .iter()
+            .map(|(key, value)| match value.as_str() {
+                "" => key.clone(),
+                value => format!("{}={}", key, value),
+            })
+            .collect()
+    }
+
+    /// A field this version does not know, as found in the metadata file
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        self.extra.get(name)
+    }
+
+    /// Refuse entries whose binary would be read or written outside the clone
+    pub(crate) fn check_path(&self) -> io::Result<()> {
+        paths::check_relative(&self.binary_path).map_err(|reason| {
+            BitcacheError::UnsafeMetadataPath {
+                md5: self.md5.clone(),
+                binary_path: self.binary_path.clone(),
+                reason: reason.to_string(),
+            }
+            .into()
+        })
+    }
+
+    /// Where the binary of this entry is in the clone at `repo_dir`,
+    /// refusing one reached through a symbolic link the repository holds
+    pub(crate) fn stored_in(&self, repo_dir: &Path) -> io::Result<PathBuf> {
+        self.check_path()?;
+        paths::check_no_symlinks(repo_dir, &self.binary_path).map_err(|reason| {
+            io::Error::from(BitcacheError::UnsafeMetadataPath {
+                md5: self.md5.clone(),
+                binary_path: self.binary_path.clone(),
+                reason,
+            })
+        })?;
+        Ok(paths::long_path(&repo_dir.join(&self.binary_path)))
+    }
+}
+
+/// Split a `key=value` tag; a tag without `=` has an empty value
+fn split_tag(tag: &str) -> (String, String) {
+    match tag.split_once('=') {
+        Some((key, value)) => (key.to_string(), value.to_string()),
+        None => (tag.to_string(), String::new()),
+    }
+}
+
+/// Tags as an object of strings, or as a list of `key=value` strings
+fn deserialize_tags<'de, D>(deserializer: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tags {
+        Map(BTreeMap<String, String>),
+        List(Vec<String>),
+    }
+    match Tags::deserialize(deserializer) {
+        Ok(Tags::Map(tags)) => Ok(tags),
+        Ok(Tags::List(tags)) => Ok(tags.iter().map(|tag| split_tag(tag)).collect()),
+        Err(_) => Err(de::Error::custom(
+            "expected tags as an object of strings or a list of strings",
+        )),
+    }
+}
+
+fn default_hash_algo() -> String {
+    HashAlgo::Md5.name().to_string()
+}
+
+fn is_default_hash_algo(hash_algo: &str) -> bool {
+    hash_algo == HashAlgo::Md5.name()
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Name of the metadata file at the repository root
+pub const METADATA_FILE: &str = "bitcache_metadata.json";
+
+/// Version of the metadata schema this bitcache writes
+pub const METADATA_SCHEMA_VERSION: u32 = 4;
+
+/// Version of a file without `schema_version`
+const UNVERSIONED: u32 = 1;
+
+/// Root metadata structure
+///
+/// New fields may be added in later versions; create it with
+/// [`Metadata::new`] or by loading a file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct Metadata {
+    /// Schema version of the document, [`METADATA_SCHEMA_VERSION`] once
+    /// loaded
+    pub schema_version: u32,
+    /// Map of MD5 hash to the entries published for it, each with a
+    /// different [`MetadataEntry::name`]
+    pub entries: HashMap<String, Vec<MetadataEntry>>,
+    /// Members this version does not know, kept for re-saving
+    #[serde(flatten)]
+    extra: Map<String, Value>,
+    /// How the entries are stored; serializing writes them all in one
+    /// document whatever it is
+    #[serde(skip)]
+    layout: MetadataLayout,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Self {
+            schema_version: METADATA_SCHEMA_VERSION,
+            entries: HashMap::new(),
+            extra: Map::new(),
+            layout: MetadataLayout::Single,
+        }
+    }
+}
+
+/// The metadata file of the sharded layout
+#[derive(Serialize)]
+struct Header<'a> {
+    schema_version: u32,
+    layout: MetadataLayout,
+    #[serde(flatten)]
+    extra: &'a Map<String, Value>,
+}
+
+/// A shard of the sharded layout, the metadata document of one hash
+#[derive(Serialize)]
+struct Shard<'a> {
+    schema_version: u32,
+    entries: BTreeMap<&'a str, &'a [MetadataEntry]>,
+}
+
+impl Metadata {
+    /// Metadata without any entries
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How the entries are stored
+    pub fn layout(&self) -> MetadataLayout {
+        self.layout
+    }
+
+    /// Store the entries in `layout` from the next save on, see
+    /// [`crate::migrate_metadata`]
+    pub fn set_layout(&mut self, layout: MetadataLayout) {
+        self.layout = layout;
+    }
+
+    /// Every entry, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &MetadataEntry> {
+        self.entries.values().flatten()
+    }
+
+    /// Number of entries, counting each of a source's
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    /// Whether there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The entries published for a source MD5
+    pub fn variants(&self, md5: &str) -> &[MetadataEntry] {
+        self.entries.get(md5).map_or(&[], Vec::as_slice)
+    }
+
+    /// The entry of a source MD5 with this [`MetadataEntry::name`]
+    pub fn lookup(&self, md5: &str, name: &str) -> Option<&MetadataEntry> {
+        find_variant(self.variants(md5), name)
+    }
+
+    /// The entry for a source MD5 as `get` picks it
+    ///
+    /// A `name` picks the entry of that name. Without one, a source's only
+    /// entry is picked; a source with several fails with
+    /// [`BitcacheError::AmbiguousVariant`], which lists their names.
+    pub fn select(&self, md5: &str, name: Option<&str>) -> io::Result<Option<&MetadataEntry>> {
+        select_variant(self.variants(md5), name)
+    }
+
+    /// Add an entry under its MD5, returning the entry of the same name it
+    /// replaces
+    pub fn insert_entry(&mut self, entry: MetadataEntry) -> Option<MetadataEntry> {
+        let variants = self.entries.entry(entry.md5.clone()).or_default();
+        match variants
+            .iter_mut()
+            .rev()
+            .find(|other| other.name() == entry.name())
+        {
+            Some(slot) => Some(std::mem::replace(slot, entry)),
+            None => {
+                variants.push(entry);
+                None
+            }
+        }
+    }
+
+    /// Remove the entry of a source MD5 with this name, returning it
+    pub fn remove_entry(&mut self, md5: &str, name: &str) -> Option<MetadataEntry> {
+        let variants = self.entries.get_mut(md5)?;
+        let index = variants.iter().rposition(|entry| entry.name() == name)?;
+        let removed = variants.remove(index);
+        if variants.is_empty() {
+            self.entries.remove(md5);
+        }
+        Some(removed)
+    }
+
+    /// The entries [`crate::delete`] moved to the trash
+    pub(crate) fn trash(&self) -> io::Result<Vec<TrashedEntry>> {
+        match self.extra.get(TRASH_MEMBER) {
+            Some(trash) => Vec::deserialize(trash).map_err(parse_error),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Replace the entries in the trash; kept with the members this version
+    /// does not know, so that older versions keep them when re-saving
+    pub(crate) fn set_trash(&mut self, trash: Vec<TrashedEntry>) -> io::Result<()> {
+        if trash.is_empty() {
+            self.extra.remove(TRASH_MEMBER);
+        } else {
+            let trash = serde_json::to_value(trash).map_err(parse_error)?;
+            self.extra.insert(TRASH_MEMBER.to_string(), trash);
+        }
+        Ok(())
+    }
+
+    /// Every entry published from a source file of this name, oldest first
+    pub fn iter_by_source<'a>(
+        &'a self,
+        source_file: &'a str,
+    ) -> impl Iterator<Item = &'a MetadataEntry> + 'a {
+        let mut matching: Vec<_> = self
+            .iter()
+            .filter(|entry| entry.source_file == source_file)
+            .collect();
+        matching.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        matching.into_iter()
+    }
+
+    /// Read a metadata file, and in the sharded layout its shards, refusing
+    /// entries with unsafe paths
+    pub fn load_from_file(path: &Path) -> io::Result<Self> {
+        Self::load_with(path, |_| Ok(()))
+    }
+
+    /// [`Self::load_from_file`], calling `fetch` with the shard directory
+    /// before reading it in the sharded layout
+    pub(crate) fn load_with(
+        path: &Path,
+        fetch: impl FnOnce(&Path) -> io::Result<()>,
+    ) -> io::Result<Self> {
+        // A crash during a previous save may have left a temp file next to
+        // the metadata; the metadata itself is always complete
+        fsutil::remove_stale_temp_files(path);
+        let mut metadata = Self::read(path)?;
+        if metadata.layout == MetadataLayout::Sharded {
+            let shard_dir = layout::shard_dir(path);
+            fetch(&shard_dir)?;
+            for (_, shard) in layout::shard_files(&shard_dir)? {
+                metadata.entries.extend(Self::read(&shard)?.entries);
+            }
+        }
+        for entry in metadata.iter() {
+            entry.check_path()?;
+        }
+        Ok(metadata)
+    }
+
+    /// Parse and migrate one metadata document
+    fn read(path: &Path) -> io::Result<Self> {
+        let content = fs::read(path)?;
+        let metadata: Self = serde_json::from_slice(&content).map_err(parse_error)?;
+        migrate(metadata)
+    }
+
+    /// Look up the entries of a single MD5 without building the whole map
+    ///
+    /// Every other entry is skipped during parsing, which keeps `get` fast on
+    /// metadata files with hundreds of thousands of entries. In the sharded
+    /// layout only the shard of `md5` is read.
+    pub fn lookup_in_file(path: &Path, md5: &str) -> io::Result<Vec<MetadataEntry>> {
+        Self::lookup_with(path, md5, |_| Ok(()))
+    }
+
+    /// [`Self::lookup_in_file`], calling `fetch` with the shard of `md5`
+    /// before reading it in the sharded layout
+    pub(crate) fn lookup_with(
+        path: &Path,
+        md5: &str,
+        fetch: impl FnOnce(&Path) -> io::Result<()>,
+    ) -> io::Result<Vec<MetadataEntry>> {
+        fsutil::remove_stale_temp_files(path);
+        let content = fs::read(path)?;
+        let mut deserializer = serde_json::Deserializer::from_slice(&content);
+        let (schema_version, layout, mut variants) = MetadataLookup { md5 }
+            .deserialize(&mut deserializer)
+            .and_then(|variants| deserializer.end().map(|_| variants))
+            .map_err(parse_error)?;
+        check_version(schema_version)?;
+        if layout == MetadataLayout::Sharded {
+            let Some(shard) = layout::shard_file(&layout::shard_dir(path), md5) else {
+                return Ok(Vec::new());
+            };
+            fetch(&shard)?;
+            if !shard.exists() {
+                return Ok(Vec::new());
+            }
+            return Self::lookup_in_file(&shard, md5);
+        }
+        for entry in &mut variants {
+            // The only migration that changes an entry
+            if schema_version < 4 {
+                upgrade_compressed_flag(entry);
+            }
+            entry.check_path()?;
+        }
+        Ok(variants)
+    }
+
+    /// Write the metadata atomically, returning the MD5 of what was written
+    /// to `path`
+    ///
+    /// In the sharded layout only the shards whose entries changed are
+    /// written, and those of hashes without entries are removed.
+    pub fn save_to_file(&self, path: &Path) -> io::Result<String> {
+        let content = match self.layout {
+            MetadataLayout::Single => serde_json::to_string_pretty(&self)?,
+            MetadataLayout::Sharded => {
+                self.save_shards(&layout::shard_dir(path))?;
+                serde_json::to_string_pretty(&Header {
+                    schema_version: self.schema_version,
+                    layout: self.layout,
+                    extra: &self.extra,
+                })?
+            }
+        };
+        fsutil::atomic_write(path, content.as_bytes())?;
+        Ok(format!("{:x}", md5::compute(content.as_bytes())))
+    }
+
+    /// Write the shard of every hash whose entries changed into `shard_dir`
+    fn save_shards(&self, shard_dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(shard_dir)?;
+        for (md5, variants) in &self.entries {
+            cancel::check()?;
+            let shard = layout::shard_file(shard_dir, md5).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "MD5 {} cannot name a shard of the sharded layout; only letters and digits can",
+                        md5
+                    ),
+                )
+            })?;
+            let content = serde_json::to_string_pretty(&Shard {
+                schema_version: METADATA_SCHEMA_VERSION,
+                entries: BTreeMap::from([(md5.as_str(), variants.as_slice())]),
+            })?;
+            if fs::read(&shard).is_ok_and(|existing| existing == content.as_bytes()) {
+                continue;
+            }
+            if let Some(dir) = shard.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fsutil::atomic_write(&shard, content.as_bytes())?;
+        }
+        layout::remove_shards(shard_dir, |md5| self.entries.contains_key(md5))
+    }
+}
+
+/// The entry named `name` among the entries of one MD5; the last one wins
+/// if a hand-edited file has the same name twice
+fn find_variant<'a>(variants: &'a [MetadataEntry], name: &str) -> Option<&'a MetadataEntry> {
+    variants.iter().rev().find(|entry| entry.name() == name)
+}
+
+/// [`Metadata::select`] among the entries published for one MD5
+pub(crate) fn select_variant<'a>(
+    variants: &'a [MetadataEntry],
+    name: Option<&str>,
+) -> io::Result<Option<&'a MetadataEntry>> {
+    match (name, variants) {
+        (Some(name), _) => Ok(find_variant(variants, name)),
+        (None, []) => Ok(None),
+        (None, [only]) => Ok(Some(only)),
+        (None, [first, ..]) => Err(BitcacheError::AmbiguousVariant {
+            md5: first.md5.clone(),
+            variants: variant_names(variants),
+        }
+        .into()),
+    }
+}
+
+/// Whether `name` can be given to `publish --variant`: letters, digits, `.`,
+/// `_` and `-`, not starting with `.` or `-`
+pub(crate) fn is_variant_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['.', '-'])
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// The names of `variants`, sorted, for messages
+pub(crate) fn variant_names(variants: &[MetadataEntry]) -> Vec<String> {
+    let mut names: Vec<_> = variants
+        .iter()
+        .map(|entry| entry.name().to_string())
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The value of one member of `entries`: the list of the entries published
+/// for the MD5, or a single entry as versions before 3 wrote it
+struct Variants(Vec<MetadataEntry>);
+
+impl<'de> Deserialize<'de> for Variants {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(VariantsVisitor)
+    }
+}
+
+struct VariantsVisitor;
+
+impl<'de> Visitor<'de> for VariantsVisitor {
+    type Value = Variants;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an entry or a list of entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        Vec::deserialize(de::value::SeqAccessDeserializer::new(seq)).map(Variants)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        MetadataEntry::deserialize(de::value::MapAccessDeserializer::new(map))
+            .map(|entry| Variants(vec![entry]))
+    }
+}
+
+/// Bring a document of any known schema version up to
+/// [`METADATA_SCHEMA_VERSION`]
+fn migrate(mut metadata: Metadata) -> io::Result<Metadata> {
+    check_version(metadata.schema_version)?;
+    while metadata.schema_version < METADATA_SCHEMA_VERSION {
+        metadata = match metadata.schema_version {
+            1 => migrate_v1_to_v2(metadata),
+            2 => migrate_v2_to_v3(metadata),
+            3 => migrate_v3_to_v4(metadata),
+            found => unreachable!("no migration from schema version {}", found),
+        };
+    }
+    Ok(metadata)
+}
+
+/// Refuse schema versions this bitcache cannot migrate from
+fn check_version(found: u32) -> io::Result<()> {
+    if found < UNVERSIONED {
+        return Err(BitcacheError::Migration {
+            found,
+            reason: "schema versions start at 1".to_string(),
+        }
+        .into());
+    }
+    if found > METADATA_SCHEMA_VERSION {
+        return Err(BitcacheError::Migration {
+            found,
+            reason: format!(
+                "this bitcache only knows versions up to {}; upgrade bitcache to use this repository",
+                METADATA_SCHEMA_VERSION
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Version 2 records `schema_version` and writes tags as an object
+///
+/// Tags written as a list by other tools are already converted while the
+/// entries are parsed, so only the version changes.
+fn migrate_v1_to_v2(metadata: Metadata) -> Metadata {
+    Metadata {
+        schema_version: 2,
+        ..metadata
+    }
+}
+
+/// Version 3 writes the entries of every MD5 as a list, even a single one,
+/// so other tools reading the file only need to handle one shape. A single
+/// entry is already read as a list of one while the entries are parsed, so
+/// only the version changes.
+fn migrate_v2_to_v3(metadata: Metadata) -> Metadata {
+    Metadata {
+        schema_version: 3,
+        ..metadata
+    }
+}
+
+/// Version 4 records how a bitstream is compressed in `compression`, which
+/// may name gzip as well as zstd, instead of the `compressed` flag that
+/// only ever meant zstd
+fn migrate_v3_to_v4(mut metadata: Metadata) -> Metadata {
+    for entry in metadata.entries.values_mut().flatten() {
+        upgrade_compressed_flag(entry);
+    }
+    Metadata {
+        schema_version: 4,
+        ..metadata
+    }
+}
+
+/// Turn the `compressed` flag of an entry from before version 4 into its
+/// `compression`
+fn upgrade_compressed_flag(entry: &mut MetadataEntry) {
+    if let Some(Value::Bool(compressed)) = entry.extra.remove("compressed") {
+        if compressed && entry.compression.is_none() {
+            entry.compression = Some(Compression::Zstd.name().to_string());
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Metadata {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MetadataVisitor)
+    }
+}
+
+/// Deserializes a [`Metadata`] document; unlike a derived `flatten`, this
+/// parses `entries` directly instead of buffering it first
+struct MetadataVisitor;
+
+impl<'de> Visitor<'de> for MetadataVisitor {
+    type Value = Metadata;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a metadata object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut schema_version = UNVERSIONED;
+        let mut entries = None;
+        let mut extra = Map::new();
+        let mut layout = MetadataLayout::Single;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "entries" {
+                let members: HashMap<String, Variants> = map.next_value()?;
+                entries = Some(
+                    members
+                        .into_iter()
+                        .filter(|(_, variants)| !variants.0.is_empty())
+                        .map(|(md5, variants)| (md5, variants.0))
+                        .collect(),
+                );
+            } else if key == "schema_version" {
+                schema_version = map.next_value()?;
+            } else if key == "layout" {
+                layout = map.next_value()?;
+            } else {
+                let value = map.next_value()?;
+                extra.insert(key, value);
+            }
+        }
+        // The sharded layout keeps its entries in the shards
+        let entries = match (entries, layout) {
+            (Some(entries), _) => entries,
+            (None, MetadataLayout::Sharded) => HashMap::new(),
+            (None, MetadataLayout::Single) => return Err(de::Error::missing_field("entries")),
+        };
+        Ok(Metadata {
+            schema_version,
+            entries,
+            extra,
+            layout,
+        })
+    }
+}
+
+fn parse_error(e: serde_json::Error) -> io::Error {
+    BitcacheError::Metadata { source: e }.into()
+}
+
+/// Deserializes a [`Metadata`] document down to the entries of one MD5
+struct MetadataLookup<'a> {
+    md5: &'a str,
+}
+
+impl<'de> DeserializeSeed<'de> for MetadataLookup<'_> {
+    type Value = (u32, MetadataLayout, Vec<MetadataEntry>);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for MetadataLookup<'_> {
+    type Value = (u32, MetadataLayout, Vec<MetadataEntry>);
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a metadata object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut schema_version = UNVERSIONED;
+        let mut entries = None;
+        let mut layout = MetadataLayout::Single;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "entries" {
+                entries = Some(map.next_value_seed(EntriesLookup { md5: self.md5 })?);
+            } else if key == "schema_version" {
+                schema_version = map.next_value()?;
+            } else if key == "layout" {
+                layout = map.next_value()?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        let variants = match (entries, layout) {
+            (Some(variants), _) => variants,
+            (None, MetadataLayout::Sharded) => Vec::new(),
+            (None, MetadataLayout::Single) => return Err(de::Error::missing_field("entries")),
+        };
+        Ok((schema_version, layout, variants))
+    }
+}
+
+/// Deserializes the `entries` map, keeping only the entries of one MD5
+struct EntriesLookup<'a> {
+    md5: &'a str,
+}
+
+impl<'de> DeserializeSeed<'de> for EntriesLookup<'_> {
+    type Value = Vec<MetadataEntry>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntriesLookup<'_> {
+    type Value = Vec<MetadataEntry>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a map of MD5 hashes to entries")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // Like the HashMap in Metadata, the last duplicate key wins
+        let mut found = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if key == self.md5 {
+                found = map.next_value::<Variants>()?.0;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const MD5: &str = "6f1ed002ab5595859014ebf0951522d9";
+
+    fn entry(binary_path: &str, variant: Option<&str>) -> MetadataEntry {
+        let mut entry = MetadataEntry::new(MD5, binary_path, "arty.vhd", "2024-05-03T10:02:51Z");
+        entry.variant = variant.map(String::from);
+        entry
+    }
+
+    #[test]
+    fn names_default_to_the_file_name() {
+        assert_eq!(entry("boards/arty/arty.bit", None).name(), "arty.bit");
+        assert_eq!(entry("boards/arty/arty.mcs", Some("flash")).name(), "flash");
+        let mut compressed = entry("boards/arty/arty.bit.gz", None);
+        compressed.compression = Some("gzip".to_string());
+        assert_eq!(compressed.name(), "arty.bit");
+        assert!(is_variant_name("flash-2.rev_b"));
+        for name in ["", ".hidden", "-flag", "a/b", "a@b"] {
+            assert!(!is_variant_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn every_source_is_written_as_a_list() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(METADATA_FILE);
+        let mut metadata = Metadata::new();
+        metadata.insert_entry(entry("boards/arty/arty.bit", None));
+        metadata.save_to_file(&path)?;
+        let written: Value = serde_json::from_slice(&fs::read(&path)?)?;
+        assert_eq!(written["schema_version"], 4);
+        assert_eq!(written["entries"][MD5].as_array().map(Vec::len), Some(1));
+
+        metadata.insert_entry(entry("boards/arty/arty.mcs", Some("flash")));
+        metadata.save_to_file(&path)?;
+        let written: Value = serde_json::from_slice(&fs::read(&path)?)?;
+        assert_eq!(written["entries"][MD5].as_array().map(Vec::len), Some(2));
+
+        let read = Metadata::load_from_file(&path)?;
+        assert_eq!(read, metadata);
+        assert_eq!(Metadata::lookup_in_file(&path, MD5)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_the_single_entry_format() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(METADATA_FILE);
+        let old = json!({
+            "schema_version": 2,
+            "entries": { MD5: entry("boards/arty/arty.bit", None) }
+        });
+        fs::write(&path, serde_json::to_vec(&old)?)?;
+        let metadata = Metadata::load_from_file(&path)?;
+        assert_eq!(metadata.schema_version, METADATA_SCHEMA_VERSION);
+        assert_eq!(metadata.len(), 1);
+        assert!(metadata.lookup(MD5, "arty.bit").is_some());
+        assert_eq!(
+            Metadata::lookup_in_file(&path, MD5)?,
+            [entry("boards/arty/arty.bit", None)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn migrates_an_unversioned_file_and_refuses_a_newer_one() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(METADATA_FILE);
+        let old = json!({ "entries": { MD5: entry("boards/arty/arty.bit", None) } });
+        fs::write(&path, serde_json::to_vec(&old)?)?;
+        let metadata = Metadata::load_from_file(&path)?;
+        assert_eq!(metadata.schema_version, METADATA_SCHEMA_VERSION);
+        metadata.save_to_file(&path)?;
+        let written: Value = serde_json::from_slice(&fs::read(&path)?)?;
+        assert_eq!(written["schema_version"], METADATA_SCHEMA_VERSION);
+
+        for version in [0, METADATA_SCHEMA_VERSION + 1] {
+            let file = json!({ "schema_version": version, "entries": {} });
+            fs::write(&path, serde_json::to_vec(&file)?)?;
+            let expect_refused = |result: io::Result<()>| match result {
+                Err(e) => match BitcacheError::of(&e) {
+                    Some(BitcacheError::Migration { found, .. }) => assert_eq!(*found, version),
+                    other => panic!("unexpected error {:?}", other),
+                },
+                Ok(()) => panic!("read schema version {}", version),
+            };
+            expect_refused(Metadata::load_from_file(&path).map(drop));
+            expect_refused(Metadata::lookup_in_file(&path, MD5).map(drop));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reads_the_compressed_flag_of_version_3_as_zstd() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(METADATA_FILE);
+        let mut old = serde_json::to_value(entry("boards/arty/arty.bit.zst", None))?;
+        old["compressed"] = json!(true);
+        let file = json!({ "schema_version": 3, "entries": { MD5: [old] } });
+        fs::write(&path, serde_json::to_vec(&file)?)?;
+
+        let metadata = Metadata::load_from_file(&path)?;
+        let loaded = &metadata.variants(MD5)[0];
+        assert_eq!(loaded.compression()?, Compression::Zstd);
+        assert_eq!(loaded.name(), "arty.bit");
+        assert!(loaded.field("compressed").is_none());
+        let looked_up = Metadata::lookup_in_file(&path, MD5)?;
+        assert_eq!(&looked_up[0], loaded);
+
+        metadata.save_to_file(&path)?;
+        let written: Value = serde_json::from_slice(&fs::read(&path)?)?;
+        assert_eq!(written["entries"][MD5][0]["compression"], "zstd");
+        assert!(written["entries"][MD5][0].get("compressed").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn picks_the_only_entry_and_refuses_to_guess_among_several() -> io::Result<()> {
+        let mut metadata = Metadata::new();
+        metadata.insert_entry(entry("boards/arty/arty.bit", None));
+        let only = metadata.select(MD5, None)?.map(MetadataEntry::name);
+        assert_eq!(only, Some("arty.bit"));
+
+        metadata.insert_entry(entry("boards/arty/arty.mcs", Some("flash")));
+        let error = metadata.select(MD5, None).expect_err("picked one of two");
+        match BitcacheError::of(&error) {
+            Some(BitcacheError::AmbiguousVariant { variants, .. }) => {
+                assert_eq!(variants, &["arty.bit", "flash"])
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        let flash = metadata.select(MD5, Some("flash"))?;
+        assert_eq!(
+            flash.map(|e| e.binary_path.as_str()),
+            Some("boards/arty/arty.mcs")
+        );
+        assert!(metadata.select(MD5, Some("other"))?.is_none());
+
+        // Publishing a name again replaces only that entry
+        let mut again = entry("boards/arty/arty2.mcs", Some("flash"));
+        again.timestamp = "2024-05-04T00:00:00Z".to_string();
+        assert!(metadata.insert_entry(again).is_some());
+        assert_eq!(metadata.len(), 2);
+        assert!(metadata.remove_entry(MD5, "flash").is_some());
+        assert_eq!(
+            metadata.select(MD5, None)?.map(MetadataEntry::name),
+            Some("arty.bit")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reads_tags_as_an_object_or_a_list() -> Result<(), serde_json::Error> {
+        let mut tagged = entry("boards/arty/arty.bit", None);
+        tagged
+            .tags
+            .insert("board".to_string(), "arty-a7".to_string());
+        tagged.tags.insert("nightly".to_string(), String::new());
+        let saved = serde_json::to_value(&tagged)?;
+        assert_eq!(saved["tags"], json!({ "board": "arty-a7", "nightly": "" }));
+        assert_eq!(serde_json::from_value::<MetadataEntry>(saved)?, tagged);
+
+        // As some tools write them
+        let mut listed = serde_json::to_value(entry("boards/arty/arty.bit", None))?;
+        listed["tags"] = json!(["board=arty-a7", "nightly"]);
+        let listed: MetadataEntry = serde_json::from_value(listed)?;
+        assert_eq!(listed.tags, tagged.tags);
+        assert_eq!(listed.tag_labels(), ["board=arty-a7", "nightly"]);
+
+        // Untagged entries leave the member out
+        let plain = serde_json::to_value(entry("boards/arty/arty.bit", None))?;
+        assert!(plain.get("tags").is_none());
+        let mut wrong = plain;
+        wrong["tags"] = json!(7);
+        assert!(serde_json::from_value::<MetadataEntry>(wrong).is_err());
+        Ok(())
+    }
+}
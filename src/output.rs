@@ -0,0 +1,408 @@
+//! Routing of human-readable progress messages, JSON results and shell-eval
+//! output.
+//!
+//! Progress lines, whether printed here or reported by the library as
+//! [`Event`]s, normally go to stdout. Modes that print a machine-readable
+//! document on stdout (such as `--json`, `--format csv`, `publish --explain`
+//! or `get --env`) redirect them to stderr so the document can be piped straight into
+//! another tool. `--quiet` drops them, along with warnings, but keeps
+//! errors and the result lines printed with [`outcome!`].
+
+use crate::indicator;
+use bitcache::progress::{self, Event, Phase, ProgressObserver};
+use bitcache::MetadataEntry;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+static CSV: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+static SILENT: AtomicBool = AtomicBool::new(false);
+
+/// Send all subsequent status messages to stderr
+pub fn status_to_stderr() {
+    STATUS_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Switch to `--json` output: results and errors are printed as JSON on
+/// stdout, and status messages go to stderr
+pub fn json_mode() {
+    JSON.store(true, Ordering::Relaxed);
+    status_to_stderr();
+}
+
+/// Whether `--json` output was asked for
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Switch to the output `--format` asks for; json is the same as `--json`
+pub fn set_format(format: Formatter) {
+    match format {
+        Formatter::Human => {}
+        Formatter::Json => json_mode(),
+        Formatter::Csv => {
+            CSV.store(true, Ordering::Relaxed);
+            status_to_stderr();
+        }
+    }
+}
+
+/// Whether `--format csv` output was asked for
+pub fn csv() -> bool {
+    CSV.load(Ordering::Relaxed)
+}
+
+/// Drop all subsequent status, detail and heartbeat messages and warnings,
+/// as `--quiet` asks for; errors and [`outcome!`] lines are still printed
+pub fn quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+/// Drop every subsequent message, errors and results included, as
+/// `exists --quiet` asks for
+pub fn silence() {
+    quiet();
+    SILENT.store(true, Ordering::Relaxed);
+}
+
+fn quieted() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+fn silenced() -> bool {
+    SILENT.load(Ordering::Relaxed)
+}
+
+/// Print `value` on stdout as one JSON document
+pub fn print_json(value: &impl Serialize) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, value)?;
+    writeln!(stdout)
+}
+
+/// What `--json` prints for a failed run
+#[derive(Serialize)]
+struct ErrorReport<'a> {
+    error: &'a str,
+    exit_code: i32,
+}
+
+/// Print the error a run failed with: as `Error: ...` on stderr, or with
+/// `--json` as an object with its message and exit code on stdout
+pub fn print_error(message: &str, exit_code: i32) {
+    if silenced() {
+        return;
+    }
+    if json() {
+        let report = ErrorReport {
+            error: message.trim_end(),
+            exit_code,
+        };
+        // stdout may be what failed, so there is nowhere else to report to
+        let _ = print_json(&report);
+        return;
+    }
+    eprintln!("Error: {}", message);
+}
+
+/// Print a status message; use the [`status!`] macro instead of calling this
+pub fn print_status(args: fmt::Arguments) {
+    if quieted() {
+        return;
+    }
+    print_line(args);
+}
+
+/// Print the line that sums up what a command did, which `--quiet` keeps;
+/// use the [`outcome!`] macro instead of calling this
+pub fn print_outcome(args: fmt::Arguments) {
+    if silenced() {
+        return;
+    }
+    print_line(args);
+}
+
+fn print_line(args: fmt::Arguments) {
+    indicator::suspend(|| {
+        if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!("{}", args);
+        } else {
+            println!("{}", args);
+        }
+    })
+}
+
+/// Prints the library's text events and hands phases to the [`indicator`]
+struct Printer {
+    /// Also print details
+    verbose: bool,
+}
+
+impl ProgressObserver for Printer {
+    fn phase_started(&self, phase: Phase) {
+        indicator::phase_started(phase);
+    }
+
+    fn phase_finished(&self, phase: Phase) {
+        indicator::phase_finished(phase);
+    }
+
+    fn bytes_progressed(&self, phase: Phase, done: u64, total: Option<u64>) {
+        indicator::bytes_progressed(phase, done, total);
+    }
+
+    fn warning(&self, message: &str) {
+        if quieted() {
+            return;
+        }
+        indicator::suspend(|| eprintln!("Warning: {}", message));
+    }
+
+    fn message(&self, event: &Event) {
+        if quieted() {
+            return;
+        }
+        match *event {
+            Event::Status(line) => print_status(format_args!("{}", line)),
+            Event::Detail(line) if self.verbose => indicator::suspend(|| eprintln!("{}", line)),
+            Event::Notice(line) | Event::Heartbeat(line) => {
+                indicator::suspend(|| eprintln!("{}", line))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Print the library's progress events; details only with `verbose`
+pub fn print_progress(verbose: bool) {
+    let printer = Printer { verbose };
+    progress::set_handler(move |event| printer.event(event));
+}
+
+/// Print a human-readable progress or summary line
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::output::print_status(format_args!($($arg)*))
+    };
+}
+pub(crate) use status;
+
+/// Print the result line of a command, e.g. what was published, which
+/// `--quiet` keeps
+macro_rules! outcome {
+    ($($arg:tt)*) => {
+        $crate::output::print_outcome(format_args!($($arg)*))
+    };
+}
+pub(crate) use outcome;
+
+/// How much the CLI prints, as set with `BITCACHE_LOG` or `log` in the
+/// config files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    /// Errors and results only, as with `--quiet`
+    #[value(alias = "quiet")]
+    Error,
+    /// Progress and warnings too, the default
+    #[value(alias = "normal")]
+    Info,
+    /// Details too, as with `--verbose`
+    #[value(alias = "verbose")]
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// Shell syntax used by `--env` output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum EnvFormat {
+    /// `NAME='value'` for sh, bash, zsh and make's `$(shell ...)`
+    #[default]
+    Posix,
+    /// `$env:NAME = 'value'` for PowerShell
+    Powershell,
+}
+
+/// Writes `--env` assignments to stdout; does nothing without a prefix
+pub struct EnvWriter<'a> {
+    prefix: Option<&'a str>,
+    format: EnvFormat,
+}
+
+impl<'a> EnvWriter<'a> {
+    pub fn new(prefix: Option<&'a str>, format: EnvFormat) -> Self {
+        Self { prefix, format }
+    }
+
+    /// Print `PREFIX` + name assignments for each variable
+    pub fn emit(&self, vars: &[(&str, &str)]) {
+        if let Some(prefix) = self.prefix {
+            for (name, value) in vars {
+                let name = format!("{}{}", prefix, name);
+                println!("{}", env_assignment(&name, value, self.format));
+            }
+        }
+    }
+}
+
+/// Validate an `--env` prefix so generated names are valid shell identifiers
+pub fn validate_env_prefix(prefix: &str) -> io::Result<()> {
+    let mut chars = prefix.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid --env prefix '{}': must be a non-empty shell identifier such as BITCACHE_",
+                prefix
+            ),
+        ))
+    }
+}
+
+/// Render one variable assignment, quoting the value for the target shell
+pub fn env_assignment(name: &str, value: &str, format: EnvFormat) -> String {
+    match format {
+        EnvFormat::Posix => format!("{}='{}'", name, value.replace('\'', "'\\''")),
+        EnvFormat::Powershell => format!("$env:{} = '{}'", name, value.replace('\'', "''")),
+    }
+}
+
+/// How `get` and `list` print entries, chosen with `--format`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Formatter {
+    /// Text for people
+    #[default]
+    Human,
+    /// JSON, each entry an object as in the metadata file
+    Json,
+    /// CSV with a header row
+    Csv,
+}
+
+impl FromStr for Formatter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// The header row of [`Formatter::Csv`] output
+pub const CSV_HEADER: &str = "md5,variant,source_file,timestamp,hash_algo,binary_path,binary_md5,branch,tags,compression,original_size,size,file_mode,file_name,os,arch,build_tool_version,published_by,tool_version,source_commit,deprecated,deprecation_reason,replacement";
+
+/// Render one entry: a JSON object, a CSV row under [`CSV_HEADER`], or for
+/// people its hash, source file and path
+///
+/// Fields an entry lacks are empty in CSV, and its tags are one cell of
+/// `KEY=VALUE` pairs separated by `;`.
+pub fn format_entry(entry: &MetadataEntry, fmt: Formatter) -> String {
+    match fmt {
+        Formatter::Human => format!(
+            "{}  {}  {}",
+            entry.label(),
+            entry.source_file,
+            entry.binary_path
+        ),
+        Formatter::Json => {
+            serde_json::to_string_pretty(entry).expect("metadata entries serialize to JSON")
+        }
+        Formatter::Csv => {
+            let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+            [
+                entry.md5.clone(),
+                optional(&entry.variant),
+                entry.source_file.clone(),
+                entry.timestamp.clone(),
+                entry.hash_algo.clone(),
+                entry.binary_path.clone(),
+                optional(&entry.binary_md5),
+                optional(&entry.branch),
+                entry.tag_labels().join(";"),
+                optional(&entry.compression),
+                entry
+                    .original_size
+                    .map(|size| size.to_string())
+                    .unwrap_or_default(),
+                entry.size.map(|size| size.to_string()).unwrap_or_default(),
+                entry
+                    .file_mode
+                    .map(|mode| format!("{:04o}", mode))
+                    .unwrap_or_default(),
+                optional(&entry.file_name),
+                optional(&entry.os),
+                optional(&entry.arch),
+                optional(&entry.build_tool_version),
+                optional(&entry.published_by),
+                optional(&entry.tool_version),
+                optional(&entry.source_commit),
+                entry.deprecated.to_string(),
+                optional(&entry.deprecation_reason),
+                optional(&entry.replacement),
+            ]
+            .iter()
+            .map(|cell| csv_cell(cell))
+            .collect::<Vec<_>>()
+            .join(",")
+        }
+    }
+}
+
+/// Print `entries` on stdout as [`CSV_HEADER`] and one row each
+pub fn print_csv<'a>(entries: impl IntoIterator<Item = &'a MetadataEntry>) {
+    println!("{}", CSV_HEADER);
+    for entry in entries {
+        println!("{}", format_entry(entry, Formatter::Csv));
+    }
+}
+
+/// Quote a CSV cell that holds a separator, quote or line break, as RFC 4180
+/// does
+fn csv_cell(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Print `rows` on stdout as left-aligned columns separated by two spaces,
+/// the first row being the header
+pub fn print_table(rows: &[Vec<String>]) {
+    let mut widths = Vec::new();
+    for row in rows {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows {
+        let mut line = String::new();
+        for (i, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            if i + 1 == row.len() {
+                line += cell;
+            } else {
+                line += &format!("{:<width$}  ", cell, width = width);
+            }
+        }
+        println!("{}", line);
+    }
+}
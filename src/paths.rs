@@ -0,0 +1,413 @@
+//! Validation of repository-relative paths.
+//!
+//! `binary_path` values come from a metadata file that anyone with push access
+//! can edit, and `--path` comes from the command line. Both are joined onto
+//! the clone directory, so they must stay inside it on every platform: no
+//! absolute paths, drive letters or `..` components, whichever separator is
+//! used and however the path is percent-encoded.
+//!
+//! Publishing never writes over files that bitcache or git itself relies on,
+//! such as the metadata file or anything under `.git`.
+//!
+//! Paths written into the metadata are also kept portable: `/` separators,
+//! file names that Windows accepts, and no two paths that differ only in
+//! letter case, so a repository published from Linux still checks out on
+//! Windows and macOS.
+//!
+//! File names are stored as NFC-normalized UTF-8 so names typed on macOS
+//! (NFD) and Linux (NFC) map to the same entry. A literal `%` is stored as
+//! `%25` and bytes that are not valid UTF-8 as `%XX`, which makes every stored
+//! name decode back to the exact original on Unix.
+
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// How many rounds of percent-decoding are undone before checking, so
+/// double-encoded sequences such as `%252e` are caught as well
+const MAX_DECODE_ROUNDS: usize = 3;
+
+/// Check that `path` is a relative path that stays inside the directory it
+/// is joined onto, returning the reason if it is not
+///
+/// An empty path or `.` is accepted and means the directory itself.
+pub fn check_relative(path: &str) -> Result<(), String> {
+    let mut candidate = path.to_string();
+    for _ in 0..=MAX_DECODE_ROUNDS {
+        check_decoded(&candidate)?;
+        match percent_decode(&candidate) {
+            Some(decoded) if decoded != candidate => candidate = decoded,
+            _ => return Ok(()),
+        }
+    }
+    Err("too many layers of percent-encoding".to_string())
+}
+
+/// Check that no component of `path` inside `dir` is a symbolic link,
+/// returning the reason if one is
+///
+/// A repository can commit a link to anywhere, so a path that passes
+/// [`check_relative`] can still lead outside the clone through one.
+/// Components that don't exist yet are fine: publish creates them as
+/// directories. Run this after [`check_relative`], right before reading or
+/// writing through `path`.
+pub fn check_no_symlinks(dir: &Path, path: &str) -> Result<(), String> {
+    let mut at = dir.to_path_buf();
+    for name in path
+        .split(['/', '\\'])
+        .filter(|name| !name.is_empty() && *name != ".")
+    {
+        at.push(name);
+        match fs::symlink_metadata(long_path(&at)) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let link = at.strip_prefix(dir).unwrap_or(&at);
+                return Err(format!(
+                    "'{}' is a symbolic link in the repository",
+                    link.display()
+                ));
+            }
+            Ok(_) => {}
+            // Nothing further along exists, or reading it fails as it would
+            Err(_) => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// Check that every component of a repository path is a valid file name on
+/// Windows as well as Unix, returning the reason if one is not
+pub fn check_portable(path: &str) -> Result<(), String> {
+    path.split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .try_for_each(check_portable_name)
+}
+
+fn check_portable_name(name: &str) -> Result<(), String> {
+    if let Some(c) = name
+        .chars()
+        .find(|c| INVALID_CHARS.contains(c) || c.is_control())
+    {
+        return Err(format!(
+            "'{}' contains {:?}, which is not allowed in Windows file names",
+            name, c
+        ));
+    }
+    if name.ends_with(['.', ' ']) {
+        return Err(format!(
+            "'{}' ends with a dot or space, which Windows silently strips",
+            name
+        ));
+    }
+    // CON, con.bit and "nul .txt" all refer to the device on Windows
+    let stem = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    if is_reserved_device(stem) {
+        return Err(format!("'{}' is a reserved device name on Windows", name));
+    }
+    Ok(())
+}
+
+/// Characters Windows rejects in file names; `/` is the separator
+const INVALID_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+
+fn is_reserved_device(stem: &str) -> bool {
+    let upper = stem.to_ascii_uppercase();
+    match upper.as_str() {
+        "CON" | "PRN" | "AUX" | "NUL" => true,
+        _ => {
+            let mut chars = upper.chars();
+            let prefix: String = chars.by_ref().take(3).collect();
+            let digit = chars.next();
+            (prefix == "COM" || prefix == "LPT")
+                && digit.is_some_and(|c| c.is_ascii_digit() || "¹²³".contains(c))
+                && chars.next().is_none()
+        }
+    }
+}
+
+/// Files at the repository root that belong to bitcache
+const RESERVED_ROOT_FILES: &[&str] = &[
+    crate::METADATA_FILE,
+    crate::repair::REJECTED_FILE,
+    "bitcache_policy.json",
+];
+
+/// Files git reads from any directory of the working tree
+const GIT_CONTROL_FILES: &[&str] = &[".gitattributes", ".gitignore", ".gitmodules"];
+
+/// Check that a repository path does not collide with a file bitcache or git
+/// uses, returning the conflict if it does
+///
+/// Names are compared case-insensitively, as they would collide on Windows and
+/// macOS checkouts.
+pub fn check_reserved(path: &str) -> Result<(), String> {
+    let names: Vec<String> = path
+        .split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .map(fold)
+        .collect();
+    if let Some(first) = names.first() {
+        if let Some(reserved) = RESERVED_ROOT_FILES.iter().find(|name| fold(name) == *first) {
+            return Err(format!(
+                "'{}' at the repository root is reserved for bitcache",
+                reserved
+            ));
+        }
+    }
+    if names.iter().any(|name| name == ".git") {
+        return Err("'.git' is git's own directory".to_string());
+    }
+    if names.iter().any(|name| *name == fold(crate::SHARD_DIR)) {
+        return Err(format!(
+            "'{}' holds the shards of the sharded metadata layout",
+            crate::SHARD_DIR
+        ));
+    }
+    if let Some(last) = names.last() {
+        if let Some(control) = GIT_CONTROL_FILES.iter().find(|name| fold(name) == *last) {
+            return Err(format!("'{}' is read by git itself", control));
+        }
+    }
+    Ok(())
+}
+
+/// Render a relative path as stored in the metadata: `/` separators and
+/// every component encoded with [`encode_name`]
+pub fn to_repo_path(path: &Path) -> Result<String, String> {
+    let names = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(encode_name(name)),
+            _ => None,
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(names.join("/"))
+}
+
+/// Encode a file name for storage in the metadata
+///
+/// Fails only on platforms whose names are not byte strings (Windows) when
+/// the name is not valid Unicode.
+pub fn encode_name(name: &OsStr) -> Result<String, String> {
+    let mut encoded = String::new();
+    for chunk in name_bytes(name)?.utf8_chunks() {
+        for c in chunk.valid().nfc() {
+            match c {
+                '%' => encoded.push_str("%25"),
+                c => encoded.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Ok(encoded)
+}
+
+/// Decode a stored file name into the name to create on disk
+pub fn decode_name(name: &str) -> Result<OsString, String> {
+    let bytes = percent_decode_bytes(name);
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Ok(OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        String::from_utf8(bytes).map(OsString::from).map_err(|_| {
+            format!(
+                "'{}' was published from a system that allows non-Unicode file names and cannot be created here",
+                name
+            )
+        })
+    }
+}
+
+#[cfg(unix)]
+fn name_bytes(name: &OsStr) -> Result<&[u8], String> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(name.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: &OsStr) -> Result<&[u8], String> {
+    name.to_str().map(str::as_bytes).ok_or_else(|| {
+        format!(
+            "'{}' is not valid Unicode and cannot be stored in the metadata",
+            name.to_string_lossy()
+        )
+    })
+}
+
+/// Find an existing repository path that `path` collides with on a
+/// case-insensitive or normalizing file system, i.e. the first component that
+/// differs does so only in letter case or Unicode normalization
+pub fn find_case_collision<'a, I>(path: &str, existing: I) -> Option<&'a str>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    existing.into_iter().find(|other| {
+        path.split('/')
+            .zip(other.split('/'))
+            .find(|(ours, theirs)| ours != theirs)
+            .is_some_and(|(ours, theirs)| fold(ours) == fold(theirs))
+    })
+}
+
+/// Case- and normalization-insensitive form of a file name
+fn fold(name: &str) -> String {
+    name.nfc().collect::<String>().to_lowercase()
+}
+
+/// Prefix long paths with `\\?\` so Windows APIs accept more than 260
+/// characters; other platforms have no such limit
+#[cfg(windows)]
+pub fn long_path(path: &Path) -> PathBuf {
+    const MAX_PATH: usize = 260;
+    let display = path.to_string_lossy();
+    if display.len() < MAX_PATH || display.starts_with(r"\\?\") {
+        return path.to_path_buf();
+    }
+    match std::path::absolute(path) {
+        Ok(absolute) => {
+            let absolute = absolute.to_string_lossy();
+            match absolute.strip_prefix(r"\\") {
+                Some(share) => PathBuf::from(format!(r"\\?\UNC\{}", share)),
+                None => PathBuf::from(format!(r"\\?\{}", absolute)),
+            }
+        }
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn long_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+fn check_decoded(path: &str) -> Result<(), String> {
+    if path.contains('\0') {
+        return Err("contains a NUL byte".to_string());
+    }
+    if path.starts_with(['/', '\\']) {
+        return Err("is absolute".to_string());
+    }
+    if has_drive_prefix(path) {
+        return Err("starts with a drive letter".to_string());
+    }
+    if path.split(['/', '\\']).any(|component| component == "..") {
+        return Err("contains a '..' component".to_string());
+    }
+    Ok(())
+}
+
+/// `C:`, `c:foo` and similar, which Windows resolves outside the clone
+fn has_drive_prefix(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Decode `%XX` escapes, or `None` if the result is not valid UTF-8
+fn percent_decode(path: &str) -> Option<String> {
+    String::from_utf8(percent_decode_bytes(path)).ok()
+}
+
+fn percent_decode_bytes(path: &str) -> Vec<u8> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_paths_inside_the_directory() {
+        for path in [
+            "",
+            ".",
+            "top.bit",
+            "boards/zedboard/top.bit",
+            "a/./b",
+            "..top.bit",
+            "v1..2/top.bit",
+        ] {
+            assert_eq!(check_relative(path), Ok(()), "{}", path);
+        }
+    }
+
+    #[test]
+    fn refuses_paths_that_escape() {
+        let cases = [
+            ("../top.bit", "contains a '..' component"),
+            ("boards/../../top.bit", "contains a '..' component"),
+            ("boards\\..\\..\\top.bit", "contains a '..' component"),
+            ("/etc/passwd", "is absolute"),
+            ("\\\\server\\share", "is absolute"),
+            ("C:\\Windows", "starts with a drive letter"),
+            ("c:top.bit", "starts with a drive letter"),
+            ("top.bit\0.txt", "contains a NUL byte"),
+        ];
+        for (path, reason) in cases {
+            assert_eq!(check_relative(path), Err(reason.to_string()), "{}", path);
+        }
+    }
+
+    #[test]
+    fn sees_through_percent_encoding() {
+        for path in [
+            "%2e%2e/top.bit",
+            "%2E%2E%2Ftop.bit",
+            "%252e%252e/top.bit",
+            "%2fetc/passwd",
+        ] {
+            assert!(check_relative(path).is_err(), "{}", path);
+        }
+        assert!(check_relative("%25252525252e%25252525252e/top.bit").is_err());
+        // An escape that decodes to nothing unsafe is fine
+        assert_eq!(check_relative("boards/100%25/top.bit"), Ok(()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_a_path_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("boards")).unwrap();
+        fs::write(dir.path().join("boards/top.bit"), "top").unwrap();
+        std::os::unix::fs::symlink("/tmp", dir.path().join("evil")).unwrap();
+        std::os::unix::fs::symlink("top.bit", dir.path().join("boards/link.bit")).unwrap();
+
+        for path in ["boards/top.bit", "boards/new/top.bit", "new/top.bit"] {
+            assert_eq!(check_no_symlinks(dir.path(), path), Ok(()), "{}", path);
+        }
+        for (path, link) in [
+            ("evil/top.bit", "evil"),
+            ("./evil/a/b.bit", "evil"),
+            ("boards/link.bit", "boards/link.bit"),
+        ] {
+            assert_eq!(
+                check_no_symlinks(dir.path(), path),
+                Err(format!("'{}' is a symbolic link in the repository", link)),
+                "{}",
+                path
+            );
+        }
+    }
+}
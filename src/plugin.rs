@@ -0,0 +1,174 @@
+//! External subcommands.
+//!
+//! `bitcache foo args...`, where `foo` is not a built-in command, runs the
+//! first executable named `bitcache-foo` on `PATH` with `args...`. Built-in
+//! commands always win, so a plugin can add commands but never replace one.
+//!
+//! The plugin inherits bitcache's environment plus:
+//!
+//! - `BITCACHE_<KEY>` for every option in [`config::OPTIONS`] that resolved to
+//!   a value, whether it came from a flag before the plugin name, the
+//!   environment or a config file. These are the variables bitcache itself
+//!   reads, so a plugin that runs bitcache passes the configuration on
+//!   without doing anything; a plugin that reads `BITCACHE_REPO` sees the
+//!   same repository bitcache would use. Credentials are only ever passed as
+//!   `BITCACHE_SSH_KEY`, a path to the key: the token is never exported, and
+//!   `BITCACHE_TOKEN` is removed from the environment the plugin inherits.
+//! - `BITCACHE_BIN`, the path of the running bitcache, for calling back into it.
+//! - `BITCACHE_PROJECT_CONFIG` and `BITCACHE_USER_CONFIG`, the config files
+//!   that were loaded, when there are any.
+//! - `BITCACHE_PLUGIN_API`, [`API_VERSION`], bumped when this list changes
+//!   incompatibly.
+//!
+//! bitcache exits with the plugin's exit status.
+
+use bitcache::config;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// Version of the environment contract described in the module docs
+pub const API_VERSION: u32 = 1;
+
+/// Prefix of plugin executable names
+const PREFIX: &str = "bitcache-";
+
+/// Plugins on `PATH` by command name, except those a built-in command
+/// shadows; the first executable on `PATH` wins for each name
+pub fn discover(builtins: &[String]) -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    for dir in path_dirs() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str().and_then(command_name) else {
+                continue;
+            };
+            if builtins.iter().any(|builtin| builtin == name) || plugins.contains_key(name) {
+                continue;
+            }
+            let path = entry.path();
+            if is_executable(&path) {
+                plugins.insert(name.to_string(), path);
+            }
+        }
+    }
+    plugins
+}
+
+/// The executable implementing the command `name`, if one is on `PATH`
+pub fn find(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return None;
+    }
+    let file_name = format!("{}{}{}", PREFIX, name, env::consts::EXE_SUFFIX);
+    path_dirs()
+        .map(|dir| dir.join(&file_name))
+        .find(|path| is_executable(path))
+}
+
+/// Run the plugin at `path` with `args` and `vars` added to its environment
+///
+/// Returns the exit status bitcache should exit with.
+pub fn run(path: &Path, args: &[OsString], vars: &[(String, OsString)]) -> io::Result<i32> {
+    let mut command = Command::new(path);
+    for option in config::OPTIONS
+        .iter()
+        .filter(|option| config::is_secret(option.key))
+    {
+        command.env_remove(config::env_var(option.key));
+    }
+    let status = command
+        .args(args)
+        .envs(vars.iter().map(|(name, value)| (name, value)))
+        .status()
+        .map_err(|e| io::Error::new(e.kind(), format!("Cannot run {}: {}", path.display(), e)))?;
+    Ok(exit_code(status))
+}
+
+/// The variables the module docs promise, given the options already
+/// resolved from the command line
+///
+/// `resolved` holds the options whose final value bitcache already knows;
+/// every other option is looked up in the environment and config files.
+pub fn environment(
+    config: &config::Config,
+    resolved: &[(&str, Option<OsString>)],
+) -> Vec<(String, OsString)> {
+    let mut vars = Vec::new();
+    for option in config::OPTIONS
+        .iter()
+        .filter(|option| !config::is_secret(option.key))
+    {
+        let value = match resolved.iter().find(|(key, _)| *key == option.key) {
+            Some((_, value)) => value.clone(),
+            None => config.lookup(option.key).map(|(value, _)| value.into()),
+        };
+        if let Some(value) = value {
+            vars.push((config::env_var(option.key), value));
+        }
+    }
+    if let Ok(exe) = env::current_exe() {
+        vars.push(("BITCACHE_BIN".to_string(), exe.into()));
+    }
+    if let Some(path) = config.project_path() {
+        vars.push(("BITCACHE_PROJECT_CONFIG".to_string(), path.into()));
+    }
+    if let Some(path) = config.user_path() {
+        vars.push(("BITCACHE_USER_CONFIG".to_string(), path.into()));
+    }
+    vars.push((
+        "BITCACHE_PLUGIN_API".to_string(),
+        API_VERSION.to_string().into(),
+    ));
+    vars
+}
+
+fn path_dirs() -> impl Iterator<Item = PathBuf> {
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|dir| !dir.as_os_str().is_empty())
+}
+
+/// The command a plugin file name provides, e.g. `foo` for `bitcache-foo`
+fn command_name(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_prefix(PREFIX)?;
+    let name = if env::consts::EXE_SUFFIX.is_empty() {
+        name
+    } else {
+        name.strip_suffix(env::consts::EXE_SUFFIX)?
+    };
+    Some(name).filter(|name| !name.is_empty())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// The status to exit with after the plugin exited with `status`; a
+/// plugin killed by a signal gives 128 plus the signal, as in a shell
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
@@ -0,0 +1,286 @@
+//! Progress reporting for library callers.
+//!
+//! The library never prints. Operations describe what they are doing as
+//! [`Event`]s and hand them to a process-wide handler installed with
+//! [`set_handler`], and to the [`ProgressObserver`] of the operation's
+//! [`Context`](crate::Context) if it has one; without either the events are
+//! dropped. The CLI installs a handler that prints them the way bitcache
+//! always has.
+//!
+//! Besides the text lines a CLI prints, events mark where each [`Phase`]
+//! starts and finishes, how many bytes it has processed, which git commands
+//! run and when a publish retries, so a GUI can draw progress bars from them.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Something an operation wants to tell the user
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum Event<'a> {
+    /// A step of the operation, such as "Cloning repository: ..."
+    Status(&'a str),
+    /// A problem that did not stop the operation
+    Warning(&'a str),
+    /// Extra detail, printed by the CLI with `--verbose`
+    Detail(&'a str),
+    /// Information about the run itself, such as a kept temporary directory
+    Notice(&'a str),
+    /// A periodic line from a long phase, see [`crate::heartbeat`]
+    Heartbeat(&'a str),
+    /// A phase has started
+    PhaseStarted(Phase),
+    /// A phase has finished, successfully or not
+    PhaseFinished(Phase),
+    /// A phase has processed `done` of `total` bytes
+    Bytes {
+        phase: Phase,
+        done: u64,
+        total: Option<u64>,
+    },
+    /// A git command is about to run, such as "clone" or "push"
+    GitCommand(&'a str),
+    /// An operation is trying again after its push was rejected, or after
+    /// a git transfer failed in a way that may pass
+    Retry {
+        /// The attempt about to start, counting from 1
+        attempt: u32,
+        /// The most attempts it will make
+        attempts: u32,
+        /// Why the previous attempt failed
+        reason: &'a str,
+    },
+}
+
+/// A stretch of work that can take long enough to show progress for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Phase {
+    /// Computing the MD5 of a file
+    Hashing,
+    /// Copying a bitstream
+    Copying,
+    /// Cloning the repository
+    Cloning,
+    /// Fetching into an existing clone
+    Fetching,
+    /// Reading the state of the clone
+    Inspecting,
+    /// Resetting the clone to the remote head
+    Resetting,
+    /// Removing untracked files from the clone
+    Cleaning,
+    /// Staging files for a commit
+    Staging,
+    /// Committing
+    Committing,
+    /// Pushing to the remote
+    Pushing,
+}
+
+impl Phase {
+    /// Lowercase name, as used in heartbeat lines
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Hashing => "hashing",
+            Phase::Copying => "copying",
+            Phase::Cloning => "cloning",
+            Phase::Fetching => "fetching",
+            Phase::Inspecting => "inspecting",
+            Phase::Resetting => "resetting",
+            Phase::Cleaning => "cleaning",
+            Phase::Staging => "staging",
+            Phase::Committing => "committing",
+            Phase::Pushing => "pushing",
+        }
+    }
+}
+
+/// Receives the events of the operations it is given to
+///
+/// Every method does nothing by default, so an observer only implements what
+/// it shows. [`ProgressObserver::event`] routes each event to the other
+/// methods; override it to see events in their original form.
+pub trait ProgressObserver: Send + Sync {
+    /// Called for every event
+    fn event(&self, event: &Event) {
+        match *event {
+            Event::PhaseStarted(phase) => self.phase_started(phase),
+            Event::PhaseFinished(phase) => self.phase_finished(phase),
+            Event::Bytes { phase, done, total } => self.bytes_progressed(phase, done, total),
+            Event::GitCommand(subcommand) => self.git_command_started(subcommand),
+            Event::Retry {
+                attempt,
+                attempts,
+                reason,
+            } => self.retrying(attempt, attempts, reason),
+            Event::Warning(message) => self.warning(message),
+            _ => self.message(event),
+        }
+    }
+
+    /// A phase has started
+    fn phase_started(&self, _phase: Phase) {}
+
+    /// A phase has finished, successfully or not
+    fn phase_finished(&self, _phase: Phase) {}
+
+    /// A phase has processed `done` of `total` bytes
+    fn bytes_progressed(&self, _phase: Phase, _done: u64, _total: Option<u64>) {}
+
+    /// A git command such as "clone" or "push" is about to run
+    fn git_command_started(&self, _subcommand: &str) {}
+
+    /// A publish is starting `attempt` of `attempts` after a rejected push
+    fn retrying(&self, _attempt: u32, _attempts: u32, _reason: &str) {}
+
+    /// A problem that did not stop the operation
+    fn warning(&self, _message: &str) {}
+
+    /// A text line: [`Event::Status`], [`Event::Detail`], [`Event::Notice`]
+    /// or [`Event::Heartbeat`]
+    fn message(&self, _event: &Event) {}
+}
+
+/// An owned copy of an [`Event`], as kept by [`RecordingObserver`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Recorded {
+    Status(String),
+    Warning(String),
+    Detail(String),
+    Notice(String),
+    Heartbeat(String),
+    PhaseStarted(Phase),
+    PhaseFinished(Phase),
+    Bytes {
+        phase: Phase,
+        done: u64,
+        total: Option<u64>,
+    },
+    GitCommand(String),
+    Retry {
+        attempt: u32,
+        attempts: u32,
+        reason: String,
+    },
+}
+
+impl From<&Event<'_>> for Recorded {
+    fn from(event: &Event) -> Self {
+        match *event {
+            Event::Status(line) => Recorded::Status(line.to_string()),
+            Event::Warning(line) => Recorded::Warning(line.to_string()),
+            Event::Detail(line) => Recorded::Detail(line.to_string()),
+            Event::Notice(line) => Recorded::Notice(line.to_string()),
+            Event::Heartbeat(line) => Recorded::Heartbeat(line.to_string()),
+            Event::PhaseStarted(phase) => Recorded::PhaseStarted(phase),
+            Event::PhaseFinished(phase) => Recorded::PhaseFinished(phase),
+            Event::Bytes { phase, done, total } => Recorded::Bytes { phase, done, total },
+            Event::GitCommand(subcommand) => Recorded::GitCommand(subcommand.to_string()),
+            Event::Retry {
+                attempt,
+                attempts,
+                reason,
+            } => Recorded::Retry {
+                attempt,
+                attempts,
+                reason: reason.to_string(),
+            },
+        }
+    }
+}
+
+/// An observer that keeps every event, for tests that check what an
+/// operation reported
+#[derive(Debug, Default)]
+pub struct RecordingObserver {
+    events: Mutex<Vec<Recorded>>,
+}
+
+impl RecordingObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The events seen so far, oldest first
+    pub fn events(&self) -> Vec<Recorded> {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl ProgressObserver for RecordingObserver {
+    fn event(&self, event: &Event) {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(event.into());
+    }
+}
+
+type Handler = Box<dyn Fn(&Event) + Send + Sync>;
+
+static HANDLER: RwLock<Option<Handler>> = RwLock::new(None);
+
+thread_local! {
+    /// Observer of the operation running on this thread
+    static OBSERVER: RefCell<Option<Arc<dyn ProgressObserver>>> = const { RefCell::new(None) };
+}
+
+/// Install the handler that receives every event, replacing any previous one
+pub fn set_handler(handler: impl Fn(&Event) + Send + Sync + 'static) {
+    *HANDLER.write().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+}
+
+/// Pass an event to the installed handler and the current observer, if any
+pub(crate) fn emit(event: Event) {
+    if let Some(handler) = &*HANDLER.read().unwrap_or_else(|e| e.into_inner()) {
+        handler(&event);
+    }
+    let observer = OBSERVER.with(|observer| observer.borrow().clone());
+    if let Some(observer) = observer {
+        observer.event(&event);
+    }
+}
+
+/// Send the events on this thread also to `observer` until the returned
+/// guard is dropped
+pub(crate) fn observe(observer: Option<Arc<dyn ProgressObserver>>) -> Observing {
+    Observing(OBSERVER.with(|current| current.replace(observer)))
+}
+
+/// Restores the previous observer when dropped, see [`observe`]
+pub(crate) struct Observing(Option<Arc<dyn ProgressObserver>>);
+
+impl Drop for Observing {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        OBSERVER.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Report a step of the operation
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::progress::emit($crate::progress::Event::Status(&format!($($arg)*)))
+    };
+}
+
+/// Report a problem that did not stop the operation
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::progress::emit($crate::progress::Event::Warning(&format!($($arg)*)))
+    };
+}
+
+/// Report extra detail
+macro_rules! detail {
+    ($($arg:tt)*) => {
+        $crate::progress::emit($crate::progress::Event::Detail(&format!($($arg)*)))
+    };
+}
+
+pub(crate) use {detail, status, warning};
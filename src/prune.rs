@@ -0,0 +1,266 @@
+//! Removing the entries a retention policy no longer keeps.
+
+use crate::checkout::ClonePool;
+use crate::error::BitcacheError;
+use crate::filter::Filter;
+use crate::fsutil;
+use crate::gc;
+use crate::layout::{self, MetadataFile};
+use crate::progress::{status, warning};
+use crate::storage;
+use crate::trash;
+use crate::{checkout, paths, Context, Metadata, MetadataEntry, Remote};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Which entries [`prune`] removes, and how
+#[derive(Debug, Clone, Default)]
+pub struct PruneOptions {
+    /// Only remove entries published longer ago than this
+    pub older_than: Option<Duration>,
+    /// Always keep this many of the newest entries of each source file,
+    /// counting those published under a [`crate::PublishOptions::variant`]
+    /// apart
+    pub keep_latest: Option<usize>,
+    /// Only remove entries this expression picks
+    pub filter: Option<Filter>,
+    /// Also remove the bitstreams no entry refers to
+    pub orphans: bool,
+    /// Remove the entries and their bitstreams for good instead of moving
+    /// them to the trash
+    pub hard: bool,
+    /// Only report what would be removed
+    pub dry_run: bool,
+}
+
+impl PruneOptions {
+    /// Remove nothing until a policy is set
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Result of [`prune`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Pruned {
+    /// The entries removed, or that would be with
+    /// [`PruneOptions::dry_run`], oldest first
+    pub entries: Vec<MetadataEntry>,
+    /// Total size of their bitstreams in the repository
+    pub entry_bytes: u64,
+    /// Repository paths of the bitstreams no entry refers to, sorted;
+    /// removed with [`PruneOptions::orphans`]
+    pub orphans: Vec<String>,
+    /// Total size of the orphans
+    pub orphan_bytes: u64,
+    /// Whether the repository was changed
+    pub committed: bool,
+}
+
+/// Remove the entries that fall outside a retention policy, in one commit
+///
+/// An entry is removed when it was published longer ago than
+/// [`PruneOptions::older_than`] and is not among the
+/// [`PruneOptions::keep_latest`] newest entries of its source file and
+/// name; with only one of them set, that one decides. Entries
+/// [`PruneOptions::filter`] doesn't pick are kept, though they still count
+/// among the newest; with only a filter, every entry it picks is removed.
+/// An entry whose timestamp can't be read is kept. Unless [`PruneOptions::hard`], the
+/// entries and their bitstreams move to the trash as with [`crate::delete`].
+/// The orphaned bitstreams are always reported, and removed too with
+/// [`PruneOptions::orphans`]. Pruned entries are dropped from the local
+/// artifact store.
+///
+/// ```no_run
+/// use bitcache::{Context, PruneOptions, Remote};
+/// use std::time::Duration;
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let mut opts = PruneOptions::new();
+/// opts.older_than = Some(Duration::from_secs(90 * 24 * 60 * 60));
+/// opts.keep_latest = Some(3);
+/// let pruned = bitcache::prune(&remote, &opts, &Context::default())?;
+/// println!("removed {} entries", pruned.entries.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn prune(remote: &Remote, opts: &PruneOptions, ctx: &Context) -> io::Result<Pruned> {
+    prune_in(None, remote, opts, ctx)
+}
+
+/// [`prune`], in the clone kept by `pool` if given
+pub(crate) fn prune_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &PruneOptions,
+    ctx: &Context,
+) -> io::Result<Pruned> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    if opts.older_than.is_none()
+        && opts.keep_latest.is_none()
+        && opts.filter.is_none()
+        && !opts.orphans
+    {
+        return Err(BitcacheError::InvalidArgument {
+            flag: "--older-than",
+            value: String::new(),
+            reason: "give --older-than, --keep-latest, --filter or --orphans to say what to prune"
+                .to_string(),
+        }
+        .into());
+    }
+
+    let metadata_path = layout::normalize(&remote.metadata_path);
+    if opts.dry_run {
+        let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+        let metadata_file = MetadataFile::of(checkout.dir(), remote);
+        if !metadata_file.exists() {
+            return Ok(Pruned::default());
+        }
+        let metadata = metadata_file.load()?;
+        return plan(checkout.dir(), &metadata, &metadata_path, opts);
+    }
+
+    status!("Pruning entries...");
+    let mut unchanged = None;
+    let pruned = trash::change(
+        pool,
+        remote,
+        ctx,
+        "Prune bitstreams",
+        |repo_dir, metadata, written| {
+            let mut pruned = plan(repo_dir, metadata, &metadata_path, opts)?;
+            let removes_orphans = opts.orphans && !pruned.orphans.is_empty();
+            if pruned.entries.is_empty() && !removes_orphans {
+                unchanged = Some(pruned);
+                return Ok(None);
+            }
+
+            for entry in &pruned.entries {
+                metadata.remove_entry(&entry.md5, entry.name());
+            }
+            for entry in &pruned.entries {
+                if opts.hard {
+                    purge(repo_dir, metadata, entry, written)?;
+                } else {
+                    trash::trash_entry(repo_dir, metadata, entry.clone(), written)?;
+                }
+            }
+            if removes_orphans {
+                gc::remove_orphans(repo_dir, &pruned.orphans, written)?;
+            }
+            pruned.committed = true;
+            Ok(Some(pruned))
+        },
+    )?;
+    let Some(pruned) = pruned.or(unchanged) else {
+        return Ok(Pruned::default());
+    };
+
+    if let Some(store) = ctx.artifact_store(remote) {
+        for entry in &pruned.entries {
+            if let Err(e) = store.remove(&entry.md5) {
+                warning!(
+                    "could not remove {} from the local artifact cache: {}",
+                    entry.label(),
+                    e
+                );
+            }
+        }
+    }
+    Ok(pruned)
+}
+
+/// Pick the entries of `metadata`, kept at `metadata_path`, that `opts`
+/// prunes, and find the orphans
+fn plan(
+    repo_dir: &Path,
+    metadata: &Metadata,
+    metadata_path: &str,
+    opts: &PruneOptions,
+) -> io::Result<Pruned> {
+    let now = Utc::now();
+    let published = |entry: &MetadataEntry| {
+        DateTime::parse_from_rfc3339(&entry.timestamp)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    };
+
+    // Newest first within each source file and name given to publish
+    let mut groups: BTreeMap<_, Vec<(DateTime<Utc>, &MetadataEntry)>> = BTreeMap::new();
+    for entry in metadata.iter() {
+        let Some(time) = published(entry) else {
+            continue;
+        };
+        groups
+            .entry((entry.source_file.as_str(), entry.variant.as_deref()))
+            .or_default()
+            .push((time, entry));
+    }
+    let cutoff = opts
+        .older_than
+        .and_then(|age| chrono::Duration::from_std(age).ok())
+        .map(|age| now - age);
+    let mut chosen = Vec::new();
+    for mut group in groups.into_values() {
+        group.sort_by_key(|(time, _)| std::cmp::Reverse(*time));
+        let kept = opts.keep_latest.unwrap_or(0);
+        for (time, entry) in group.into_iter().skip(kept) {
+            let picked = opts
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(entry));
+            if picked && cutoff.is_none_or(|cutoff| time < cutoff) {
+                chosen.push((time, entry.clone()));
+            }
+        }
+    }
+    chosen.sort_by(|a, b| (a.0, &a.1.md5).cmp(&(b.0, &b.1.md5)));
+
+    let orphans = gc::scan(repo_dir, metadata, metadata_path)?.orphans;
+    let size = |path: &str| {
+        fs::metadata(paths::long_path(&repo_dir.join(path))).map_or(0, |file| file.len())
+    };
+    let entries: Vec<MetadataEntry> = chosen.into_iter().map(|(_, entry)| entry).collect();
+    Ok(Pruned {
+        entry_bytes: entries.iter().map(|entry| size(&entry.binary_path)).sum(),
+        orphan_bytes: orphans.iter().map(|orphan| size(orphan)).sum(),
+        entries,
+        orphans,
+        committed: false,
+    })
+}
+
+/// Remove the bitstream of `entry`, already removed from `metadata`, unless
+/// another entry still uses it
+fn purge(
+    repo_dir: &Path,
+    metadata: &Metadata,
+    entry: &MetadataEntry,
+    written: &mut Vec<String>,
+) -> io::Result<()> {
+    if metadata
+        .iter()
+        .any(|other| other.binary_path == entry.binary_path)
+    {
+        warning!(
+            "keeping {}, another entry uses the same bitstream",
+            entry.binary_path
+        );
+        return Ok(());
+    }
+    match fs::remove_file(paths::long_path(&repo_dir.join(&entry.binary_path))) {
+        Ok(()) => {
+            fsutil::remove_empty_parents(repo_dir, &entry.binary_path);
+            written.push(entry.binary_path.clone());
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
@@ -0,0 +1,1709 @@
+//! Publishing a bitstream under the MD5 of its source.
+
+use crate::checkout::{self, Checkout, ClonePool};
+use crate::compress::{self, Compression};
+use crate::error::BitcacheError;
+use crate::git::{self, PushOutcome};
+use crate::layout::{self, MetadataFile};
+use crate::progress::{self, detail, status, warning, Event};
+use crate::sources::{self, SourceWalk, Sources};
+use crate::storage;
+use crate::trash;
+use crate::{
+    cancel, compute_md5, fsutil, metadata, paths, verify_written, Context, HashAlgo, Metadata,
+    MetadataEntry, Remote,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Version of bitcache recorded in the entries it publishes
+pub(crate) const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Hint appended to out-of-space errors in the work directory
+pub(crate) const WORK_DIR_HINT: &str =
+    "Use --work-dir (or BITCACHE_WORK_DIR) to choose a larger location.";
+
+/// What a publish does when its path holds another entry's different
+/// bitstream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, ValueEnum)]
+#[non_exhaustive]
+pub enum OnCollision {
+    /// Store the bitstream under its name prefixed with the source hash,
+    /// recording the name to save it as
+    Rename,
+    /// Fail, naming the entries that use the path; the default
+    #[default]
+    Fail,
+    /// Store it over the other bitstream, removing the entries that
+    /// referred to that one
+    Overwrite,
+}
+
+impl FromStr for OnCollision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(s, true)
+    }
+}
+
+/// What to publish and where in the repository
+#[derive(Debug, Clone)]
+pub struct PublishOptions {
+    /// Source file whose hash identifies the bitstream, or a directory of
+    /// source files
+    pub source: PathBuf,
+    /// More source files or directories, hashed together with `source` as
+    /// [`crate::compute_source_hash`] does
+    pub extra_sources: Vec<PathBuf>,
+    /// Which files of a source directory are hashed
+    pub source_walk: SourceWalk,
+    /// Binary file (bitstream) to store
+    pub bitstream: PathBuf,
+    /// Target directory in the repository
+    pub path: PathBuf,
+    /// Follow `source` and `bitstream` if they are symlinks instead of
+    /// refusing them
+    pub follow_symlinks: bool,
+    /// File name to store the bitstream under, instead of its local name
+    pub rename_in_repo: Option<PathBuf>,
+    /// Variant telling this bitstream apart from others built from the same
+    /// source, e.g. the board it targets; see [`MetadataEntry::name`]. The
+    /// file name it is stored under names it if unset
+    pub variant: Option<String>,
+    /// Publish even if the source file is empty
+    pub allow_empty_source: bool,
+    /// Algorithm the source file is hashed with
+    pub hash_algo: HashAlgo,
+    /// How to store the bitstream, with [`Compression::extension`]
+    /// appended to its name
+    pub compression: Compression,
+    /// Level to compress at, within [`Compression::levels`]; `None` for
+    /// [`Compression::default_level`]
+    pub compress_level: Option<u8>,
+    /// Tags to record in the entry
+    pub tags: BTreeMap<String, String>,
+    /// Replace an entry for the same hash and variant that stores a
+    /// different bitstream; without it such a publish fails with
+    /// [`BitcacheError::AlreadyPublished`]. Publishing the identical
+    /// bitstream to the same path again is always allowed, and commits
+    /// nothing unless this is set.
+    pub force: bool,
+    /// Fail with [`BitcacheError::AlreadyPublished`] whenever the hash and
+    /// variant already have an entry, even one storing this exact bitstream
+    pub fail_if_exists: bool,
+    /// What happens when the path holds another entry's bitstream
+    pub on_collision: OnCollision,
+    /// Operating system to record the bitstream as built for
+    pub os: Option<String>,
+    /// Architecture to record the bitstream as built for
+    pub arch: Option<String>,
+    /// Version of the tool that built the bitstream, to record in the entry
+    pub build_tool_version: Option<String>,
+    /// Who to record as publishing the bitstream; the git `user.name` and
+    /// `user.email` configured for the source's directory when `None`
+    pub author: Option<String>,
+    /// Commit of the source's repository to record; when `None` and
+    /// `detect_source_commit` is set, the `HEAD` of the git work tree the
+    /// source is in, if any
+    pub source_commit: Option<String>,
+    /// Look up the commit of the source's work tree when `source_commit`
+    /// is not given
+    pub detect_source_commit: bool,
+}
+
+impl PublishOptions {
+    /// Publish `bitstream` to directory `path` of the repository, keyed by
+    /// the MD5 of `source`, with the same defaults as the CLI
+    ///
+    /// The entry records the operating system and architecture of this
+    /// machine; set [`PublishOptions::os`] and [`PublishOptions::arch`] for
+    /// a bitstream built elsewhere.
+    pub fn new(
+        source: impl Into<PathBuf>,
+        bitstream: impl Into<PathBuf>,
+        path: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            source: source.into(),
+            extra_sources: Vec::new(),
+            source_walk: SourceWalk::default(),
+            bitstream: bitstream.into(),
+            path: path.into(),
+            follow_symlinks: true,
+            rename_in_repo: None,
+            variant: None,
+            allow_empty_source: false,
+            hash_algo: HashAlgo::Md5,
+            compression: Compression::None,
+            compress_level: None,
+            tags: BTreeMap::new(),
+            force: false,
+            fail_if_exists: false,
+            on_collision: OnCollision::Fail,
+            os: Some(std::env::consts::OS.to_string()),
+            arch: Some(std::env::consts::ARCH.to_string()),
+            build_tool_version: None,
+            author: None,
+            source_commit: None,
+            detect_source_commit: true,
+        }
+    }
+
+    /// `source` followed by `extra_sources`
+    pub(crate) fn source_paths(&self) -> Vec<&Path> {
+        std::iter::once(self.source.as_path())
+            .chain(self.extra_sources.iter().map(PathBuf::as_path))
+            .collect()
+    }
+}
+
+/// A completed publish
+#[derive(Debug, Clone, Serialize)]
+pub struct Published {
+    /// MD5 of the source file, the key of the new entry
+    pub md5: String,
+    /// Where the bitstream is stored, relative to the repository root
+    pub binary_path: String,
+    /// [`MetadataEntry::name`] of the new entry
+    pub name: String,
+    /// Size of the bitstream in bytes
+    pub size: u64,
+    /// Message of the commit that published it, empty when nothing was
+    /// committed
+    pub commit_message: String,
+    /// What was done to the entry
+    pub action: PublishAction,
+}
+
+/// What a publish will do to the metadata entry for its hash and name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PublishAction {
+    /// No entry exists for the hash and name yet
+    Create,
+    /// An existing entry for the hash and name is replaced
+    Overwrite,
+    /// The existing entry already stores this bitstream at the same path,
+    /// so nothing is committed
+    Unchanged,
+}
+
+/// Everything a publish will do, computed before any write happens
+///
+/// Printed by `publish --explain`, and logged to stderr with `--verbose`
+/// during a real publish so both runs can be compared.
+#[derive(Debug, Serialize)]
+pub struct PublishPlan {
+    /// Repository URL
+    pub repo: String,
+    /// Branch the commit will be pushed to
+    pub branch: Option<String>,
+    /// Hash algorithm applied to the source file
+    pub hash_algo: &'static str,
+    /// Hash of the source file
+    pub hash: String,
+    /// [`MetadataEntry::name`] of the entry
+    pub name: String,
+    /// Whether an entry for the hash and name already exists
+    pub entry_exists: bool,
+    /// Effect on the metadata entry
+    pub action: PublishAction,
+    /// Whether the existing entry already stores this exact bitstream at
+    /// the same path, so publishing commits nothing unless
+    /// [`PublishOptions::force`]
+    pub unchanged: bool,
+    /// Destination of the binary relative to the repository root
+    pub binary_path: String,
+    /// Bytes that will be added to the repository
+    pub upload_bytes: u64,
+    /// Message of the commit that will be pushed
+    pub commit_message: String,
+    /// Repository policies that apply to the entry
+    pub policies: Vec<String>,
+}
+
+/// The path itself if it is a single file name, with no directories or `.`
+/// and `..` components
+pub(crate) fn single_file_name(path: &Path) -> Option<&OsStr> {
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) => Some(name),
+        _ => None,
+    }
+}
+
+/// Check a publish input and resolve it to the regular file to read
+///
+/// Symlinks are followed unless `follow` is false. `flag` names the input in
+/// errors, e.g. "--source".
+pub(crate) fn resolve_input(path: &Path, flag: &str, follow: bool) -> io::Result<PathBuf> {
+    let metadata = fs::symlink_metadata(path)
+        .map_err(|e| io_error(format!("{} {}", flag, path.display()), e))?;
+
+    let resolved = if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path).unwrap_or_default();
+        if !follow {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} {} is a symlink to {} and --no-follow-symlinks is set",
+                    flag,
+                    path.display(),
+                    target.display()
+                ),
+            ));
+        }
+        match fs::canonicalize(path) {
+            Ok(resolved) => resolved,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "{} {} is a dangling symlink (points to {})",
+                        flag,
+                        path.display(),
+                        target.display()
+                    ),
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+    } else {
+        path.to_path_buf()
+    };
+
+    if !fs::metadata(&resolved)?.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} {} is not a regular file", flag, path.display()),
+        ));
+    }
+    fs::File::open(&resolved)
+        .map_err(|e| io_error(format!("{} {} cannot be read", flag, path.display()), e))?;
+    Ok(resolved)
+}
+
+/// Delay before push attempt `attempt`, growing with each retry plus jitter
+/// so concurrent publishers don't retry in lockstep
+pub(crate) fn push_backoff(attempt: u32) -> Duration {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |now| now.subsec_nanos());
+    let jitter = (nanos ^ process::id().wrapping_mul(2_654_435_761)) % 500;
+    Duration::from_millis(100 * u64::from(attempt.min(10)) + u64::from(jitter))
+}
+
+/// Whether a concurrently published entry is exactly what this publish
+/// would have written
+fn is_same_publish(
+    remote: &MetadataEntry,
+    ours: &MetadataEntry,
+    repo_dir: &Path,
+    bitstream: &Path,
+) -> io::Result<bool> {
+    if remote.binary_path != ours.binary_path || remote.source_file != ours.source_file {
+        return Ok(false);
+    }
+    let published = repo_dir.join(&remote.binary_path);
+    if !published.is_file() {
+        return Ok(false);
+    }
+    Ok(compute_md5(&published)? == compute_md5(bitstream)?)
+}
+
+/// Another entry whose bitstream is at `path` and differs from `bitstream`,
+/// which storing `bitstream` there for `md5` and `name` would replace
+///
+/// Sharing a path is allowed when both sources built identical bitstreams.
+fn path_owner<'a>(
+    metadata: &'a Metadata,
+    (md5, name): (&str, &str),
+    path: &str,
+    repo_dir: &Path,
+    bitstream: &Path,
+) -> io::Result<Option<&'a MetadataEntry>> {
+    let Some(owner) = metadata.iter().find(|entry| {
+        (entry.md5.as_str(), entry.name()) != (md5, name) && entry.binary_path == path
+    }) else {
+        return Ok(None);
+    };
+    let published = repo_dir.join(&owner.binary_path);
+    if published.is_file() && compute_md5(&published)? == compute_md5(bitstream)? {
+        return Ok(None);
+    }
+    Ok(Some(owner))
+}
+
+/// Refuse to publish to a path that already holds a different source's
+/// bitstream, which would leave that entry pointing at the wrong content
+fn check_shared_path(
+    metadata: &Metadata,
+    ours: &MetadataEntry,
+    repo_dir: &Path,
+    bitstream: &Path,
+) -> io::Result<()> {
+    let key = (ours.md5.as_str(), ours.name());
+    let Some(owner) = path_owner(metadata, key, &ours.binary_path, repo_dir, bitstream)? else {
+        return Ok(());
+    };
+    Err(BitcacheError::PathTaken {
+        path: ours.binary_path.clone(),
+        reason: format!(
+            "it already holds the bitstream of {} (MD5 {}). Choose a different --path, store this one under another name with --rename-in-repo <NAME>, or pass --on-collision rename",
+            owner.source_file, owner.md5
+        ),
+    }
+    .into())
+}
+
+/// Where to store the bitstream of `md5` and `name` instead of
+/// `binary_rel_path` when that holds another entry's and `on_collision` is
+/// [`OnCollision::Rename`], with the name [`crate::get`] saves it as
+///
+/// The stored name is prefixed with the hash, and the variant if there is
+/// one, which no other entry's is.
+fn renamed_path(
+    metadata: &Metadata,
+    key: (&str, &str),
+    variant: Option<&str>,
+    binary_rel_path: &str,
+    compression: Compression,
+    repo_dir: &Path,
+    bitstream: &Path,
+) -> io::Result<Option<(String, String)>> {
+    let Some(owner) = path_owner(metadata, key, binary_rel_path, repo_dir, bitstream)? else {
+        return Ok(None);
+    };
+    let (dir, name) = match binary_rel_path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), binary_rel_path),
+    };
+    let prefix = match variant {
+        Some(variant) => format!("{}-{}", key.0, variant),
+        None => key.0.to_string(),
+    };
+    let renamed = format!("{}{}-{}", dir, prefix, name);
+    paths::check_portable(&renamed).map_err(|reason| BitcacheError::InvalidPath {
+        path: renamed.clone(),
+        reason: reason.to_string(),
+    })?;
+    warning!(
+        "{} holds the bitstream of {} (MD5 {}); storing this one as {}",
+        binary_rel_path,
+        owner.source_file,
+        owner.label(),
+        renamed
+    );
+    let saved_name = name
+        .strip_suffix(compression.extension())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(name);
+    Ok(Some((renamed, saved_name.to_string())))
+}
+
+/// Refuse to publish to `path` in the clone at `repo_dir` through a
+/// symbolic link the repository holds, which can lead outside the clone
+fn check_no_symlinks(repo_dir: &Path, path: &str) -> io::Result<()> {
+    paths::check_no_symlinks(repo_dir, path).map_err(|reason| {
+        BitcacheError::InvalidPath {
+            path: path.to_string(),
+            reason,
+        }
+        .into()
+    })
+}
+
+/// Remove the entries other than `ours` that refer to the bitstream at its
+/// path, which is about to be replaced with a different one
+fn displace(metadata: &mut Metadata, ours: &Staged) -> io::Result<()> {
+    if same_contents(&ours.bitstream, &ours.dest_bitstream)? {
+        return Ok(());
+    }
+    let displaced: Vec<(String, String)> = metadata
+        .iter()
+        .filter(|entry| {
+            (&entry.md5, entry.name()) != (&ours.entry.md5, ours.entry.name())
+                && entry.binary_path == ours.entry.binary_path
+        })
+        .map(|entry| (entry.md5.clone(), entry.name().to_string()))
+        .collect();
+    for (md5, name) in displaced {
+        if let Some(removed) = metadata.remove_entry(&md5, &name) {
+            warning!(
+                "overwriting {} removes the entry for MD5 {}, whose bitstream it was",
+                ours.entry.binary_path,
+                removed.label()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Refuse to replace an entry that stores a different bitstream unless
+/// [`PublishOptions::force`], so a publish never clobbers one by accident,
+/// and any entry at all with [`PublishOptions::fail_if_exists`]
+pub(crate) fn check_overwrite(
+    existing: Option<&MetadataEntry>,
+    unchanged: bool,
+    opts: &PublishOptions,
+) -> io::Result<()> {
+    match existing {
+        Some(existing) if opts.fail_if_exists || (!unchanged && !opts.force) => {
+            Err(BitcacheError::AlreadyPublished {
+                entry: Box::new(existing.clone()),
+                forcible: !opts.fail_if_exists,
+            }
+            .into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Trailer lines of the commit adding an entry, naming who published it and
+/// the commit of its source
+fn provenance(published_by: Option<&str>, source_commit: Option<&str>) -> Vec<String> {
+    let mut trailers = Vec::new();
+    if let Some(published_by) = published_by {
+        trailers.push(format!("Published-by: {}", published_by));
+    }
+    if let Some(commit) = source_commit {
+        trailers.push(format!("Source-commit: {}", commit));
+    }
+    trailers
+}
+
+/// `message` followed by the `trailers` of its entries, each once, and the
+/// version of bitcache
+fn with_trailers(mut message: String, trailers: Vec<String>) -> String {
+    let mut unique: Vec<String> = Vec::with_capacity(trailers.len() + 1);
+    for trailer in trailers {
+        if !unique.contains(&trailer) {
+            unique.push(trailer);
+        }
+    }
+    unique.push(format!("Bitcache-version: {}", TOOL_VERSION));
+    message.push_str("\n\n");
+    message.push_str(&unique.join("\n"));
+    message
+}
+
+/// Error for a concurrent publish that changed the same MD5 differently
+fn publish_conflict(remote: Option<&MetadataEntry>, ours: &MetadataEntry) -> io::Error {
+    BitcacheError::Conflict {
+        md5: ours.md5.clone(),
+        remote: remote.cloned().map(Box::new),
+        ours: Box::new(ours.clone()),
+    }
+    .into()
+}
+
+/// Refuse to store a bitstream over the metadata file of `remote`
+pub(crate) fn check_metadata_clash(binary_rel_path: &str, remote: &Remote) -> io::Result<()> {
+    if !binary_rel_path.eq_ignore_ascii_case(&layout::normalize(&remote.metadata_path)) {
+        return Ok(());
+    }
+    Err(BitcacheError::PathTaken {
+        path: binary_rel_path.to_string(),
+        reason: "the repository keeps its metadata there".to_string(),
+    }
+    .into())
+}
+
+/// Refuse a path that differs only in letter case from one already in the
+/// repository
+pub(crate) fn check_case_collision<'a>(
+    binary_rel_path: &str,
+    existing: impl IntoIterator<Item = &'a str>,
+) -> io::Result<()> {
+    match paths::find_case_collision(binary_rel_path, existing) {
+        None => Ok(()),
+        Some(existing) => Err(BitcacheError::PathTaken {
+            path: binary_rel_path.to_string(),
+            reason: format!(
+                "it differs only in letter case from '{}' already in the repository, which breaks checkouts on Windows and macOS",
+                existing
+            ),
+        }
+        .into()),
+    }
+}
+
+/// Subject of the commit adding `hash`
+pub(crate) fn add_subject(algo: HashAlgo, hash: &str) -> String {
+    format!("Add bitstream for source {}: {}", algo.label(), hash)
+}
+
+/// An I/O error on `context`, such as "--source top.vhd"
+fn io_error(context: String, source: io::Error) -> io::Error {
+    BitcacheError::Io { context, source }.into()
+}
+
+/// A publish worked out up to the point where it would start writing
+struct Prepared<'a> {
+    checkout: Checkout<'a>,
+    metadata_file: MetadataFile,
+    metadata: Metadata,
+    /// The bitstream to read, with symlinks resolved, or its compressed
+    /// copy
+    bitstream: PathBuf,
+    /// Set when the bitstream is stored compressed
+    compressed: Option<Compressed>,
+    /// Where the bitstream goes in the clone
+    dest_bitstream: PathBuf,
+    source_filename: String,
+    variant: Option<String>,
+    /// Names the source files were hashed under, when there are several
+    source_files: Vec<String>,
+    /// Permission bits of the bitstream as given
+    file_mode: Option<u32>,
+    /// Name to save the bitstream as, when it was renamed to avoid a
+    /// collision
+    file_name: Option<String>,
+    /// Who publishes, as recorded in the metadata
+    published_by: Option<String>,
+    /// Commit the source was taken from, as recorded in the metadata
+    source_commit: Option<String>,
+    plan: PublishPlan,
+}
+
+/// A bitstream compressed to be stored, in a scratch directory removed with
+/// it
+pub(crate) struct Compressed {
+    _scratch: fsutil::ScratchDir,
+    /// The compressed copy
+    pub(crate) path: PathBuf,
+    /// Size of the compressed copy in bytes
+    pub(crate) size: u64,
+    /// Size of the bitstream in bytes
+    pub(crate) original_size: u64,
+}
+
+/// Compress `bitstream` with `compression` at `level` for storing, or
+/// `None` to store it as it is
+pub(crate) fn compress_input(
+    bitstream: &Path,
+    size: u64,
+    compression: Compression,
+    level: Option<u8>,
+    ctx: &Context,
+) -> io::Result<Option<Compressed>> {
+    if compression == Compression::None {
+        return Ok(None);
+    }
+    let level = level.unwrap_or(compression.default_level());
+    let scratch = ctx.temp_dir()?;
+    fsutil::ensure_free_space(
+        scratch.path(),
+        size,
+        "the compressed bitstream",
+        WORK_DIR_HINT,
+    )?;
+    status!(
+        "Compressing bitstream with {} at level {}: {}",
+        compression,
+        level,
+        bitstream.display()
+    );
+    let path = scratch.path().join("bitstream");
+    let compressed_size = compress::compress(compression, bitstream, &path, level)?;
+    detail!("Compressed {} bytes to {} bytes", size, compressed_size);
+    Ok(Some(Compressed {
+        _scratch: scratch,
+        path,
+        size: compressed_size,
+        original_size: size,
+    }))
+}
+
+/// The inputs of a publish, checked before anything touches the repository
+pub(crate) struct Inputs {
+    /// The source files, with symlinks resolved
+    pub(crate) source: Sources,
+    /// The bitstream, with symlinks resolved
+    pub(crate) bitstream: PathBuf,
+    pub(crate) bitstream_size: u64,
+    /// Permission bits of the bitstream, recorded so get gives them back
+    pub(crate) file_mode: Option<u32>,
+    /// Where the bitstream goes, as recorded in the metadata, with
+    /// [`Compression::extension`] of how it is stored
+    pub(crate) binary_rel_path: String,
+    /// The source's file name, as recorded in the metadata
+    pub(crate) source_filename: String,
+    /// [`MetadataEntry::name`] of the entry
+    pub(crate) name: String,
+    /// The name to record, when it is not the file name
+    pub(crate) variant: Option<String>,
+    /// Who publishes, as recorded in the metadata
+    pub(crate) published_by: Option<String>,
+    /// Commit the source was taken from, as recorded in the metadata
+    pub(crate) source_commit: Option<String>,
+}
+
+/// Check the files and paths of a publish, so a bad one fails without
+/// network I/O
+pub(crate) fn check_inputs(opts: &PublishOptions) -> io::Result<Inputs> {
+    let target_path = &opts.path;
+    paths::check_relative(&target_path.to_string_lossy()).map_err(|reason| {
+        BitcacheError::InvalidArgument {
+            flag: "--path",
+            value: target_path.display().to_string(),
+            reason: reason.to_string(),
+        }
+    })?;
+    // Names come from the paths as given, contents from the link targets
+    let follow = opts.follow_symlinks;
+    let source_paths = opts.source_paths();
+    let mut resolved = Vec::with_capacity(source_paths.len());
+    for path in &source_paths {
+        let is_link = fs::symlink_metadata(path).is_ok_and(|meta| meta.is_symlink());
+        if fs::metadata(path).is_ok_and(|meta| meta.is_dir()) && (follow || !is_link) {
+            resolved.push(path.to_path_buf());
+        } else {
+            resolved.push(resolve_input(path, "--source", follow)?);
+        }
+    }
+    let resolved: Vec<&Path> = resolved.iter().map(PathBuf::as_path).collect();
+    let source = Sources::collect(&resolved, opts.source_walk, "--source")?;
+    let bitstream = resolve_input(&opts.bitstream, "--bitstream", follow)?;
+    if source.len()? == 0 {
+        let given = sources::list(&source_paths);
+        if !opts.allow_empty_source {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "--source {} is empty; pass --allow-empty-source to publish it anyway",
+                    given
+                ),
+            ));
+        }
+        warning!(
+            "--source {} is empty, so its MD5 only identifies empty sources",
+            given
+        );
+    }
+    check_tags(&opts.tags)?;
+    let bitstream_size = fs::metadata(&bitstream)?.len();
+    if bitstream_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--bitstream {} is empty", opts.bitstream.display()),
+        ));
+    }
+    let file_mode = fsutil::file_mode(&bitstream)?;
+
+    // Work out the repository path before the clone, so a bad name fails
+    // without network I/O
+    let bitstream_filename = match &opts.rename_in_repo {
+        Some(name) => single_file_name(name).ok_or_else(|| BitcacheError::InvalidArgument {
+            flag: "--rename-in-repo",
+            value: name.display().to_string(),
+            reason: "expected a file name without directories".to_string(),
+        })?,
+        None => opts
+            .bitstream
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid bitstream path"))?,
+    };
+
+    let mut bitstream_filename = bitstream_filename.to_os_string();
+    let levels = opts.compression.levels();
+    match opts.compress_level {
+        Some(level) if opts.compression != Compression::None && !levels.contains(&level) => {
+            return Err(BitcacheError::InvalidArgument {
+                flag: "--compress-level",
+                value: level.to_string(),
+                reason: format!(
+                    "expected a {} level from {} to {}",
+                    opts.compression,
+                    levels.start(),
+                    levels.end()
+                ),
+            }
+            .into());
+        }
+        _ => bitstream_filename.push(opts.compression.extension()),
+    }
+    let binary_rel_path = paths::to_repo_path(&target_path.join(&bitstream_filename))
+        .and_then(|path| paths::check_portable(&path).map(|_| path))
+        .and_then(|path| paths::check_reserved(&path).map(|_| path))
+        .map_err(|reason| BitcacheError::InvalidPath {
+            path: target_path.join(&bitstream_filename).display().to_string(),
+            reason: reason.to_string(),
+        })?;
+    let mut source_names = Vec::with_capacity(source_paths.len());
+    for path in &source_paths {
+        // `.` and `..` have no name of their own
+        let name = match path.file_name() {
+            Some(name) => Some(name.to_os_string()),
+            None => fs::canonicalize(path)?.file_name().map(OsStr::to_os_string),
+        };
+        source_names.push(match name {
+            Some(name) => paths::encode_name(&name).map_err(|reason| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Cannot record source file name: {}", reason),
+                )
+            })?,
+            None => "unknown".to_string(),
+        });
+    }
+    let source_filename = source_names.join(", ");
+    let mut stored = MetadataEntry::new("", binary_rel_path.as_str(), "", "");
+    stored.set_compression(opts.compression);
+    let file_name = stored.name();
+    let variant = match opts.variant.as_deref() {
+        Some(name) if !metadata::is_variant_name(name) => {
+            return Err(BitcacheError::InvalidArgument {
+                flag: "--variant",
+                value: name.to_string(),
+                reason: "expected letters, digits, '.', '_' and '-', not starting with '.' or '-'"
+                    .to_string(),
+            }
+            .into());
+        }
+        Some(name) if name != file_name => Some(name.to_string()),
+        _ => None,
+    };
+    let source_dir = source.dir();
+    let published_by = match &opts.author {
+        Some(author) => Some(author.clone()),
+        None => git::user_identity(source_dir)?,
+    };
+    let source_commit = match &opts.source_commit {
+        Some(commit) => Some(commit.clone()),
+        None if opts.detect_source_commit => git::head_commit(source_dir)?,
+        None => None,
+    };
+    let name = variant.clone().unwrap_or_else(|| file_name.to_string());
+
+    Ok(Inputs {
+        source,
+        bitstream,
+        bitstream_size,
+        file_mode,
+        binary_rel_path,
+        source_filename,
+        name,
+        variant,
+        published_by,
+        source_commit,
+    })
+}
+
+/// Refuse tag keys that could not be given back as `--tag KEY=VALUE`
+pub(crate) fn check_tags(tags: &BTreeMap<String, String>) -> io::Result<()> {
+    match tags.keys().find(|key| key.is_empty() || key.contains('=')) {
+        Some(key) => Err(BitcacheError::InvalidArgument {
+            flag: "--tag",
+            value: key.clone(),
+            reason: "tag keys must be non-empty and cannot contain '='".to_string(),
+        }
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// The branch checked out in the clone, refusing a `--branch` that named a
+/// tag since there is nothing to push to
+pub(crate) fn checked_branch(repo_dir: &Path, remote: &Remote) -> io::Result<Option<String>> {
+    let branch = storage::of(remote).branch(repo_dir)?;
+    if let (None, Some(name)) = (&branch, &remote.branch) {
+        return Err(BitcacheError::InvalidArgument {
+            flag: "--branch",
+            value: name.clone(),
+            reason: "it names a tag, which can be read from but not published to".to_string(),
+        }
+        .into());
+    }
+    Ok(branch)
+}
+
+/// Check the inputs, check out the repository and plan the publish
+fn prepare<'a>(
+    pool: Option<&'a ClonePool>,
+    remote: &Remote,
+    opts: &PublishOptions,
+    ctx: &Context,
+) -> io::Result<Prepared<'a>> {
+    storage::check(remote)?;
+    let Inputs {
+        source,
+        bitstream,
+        bitstream_size,
+        file_mode,
+        mut binary_rel_path,
+        source_filename,
+        name,
+        variant,
+        published_by,
+        source_commit,
+    } = check_inputs(opts)?;
+
+    status!("Publishing bitstream...");
+
+    let algo = opts.hash_algo;
+    status!(
+        "Computing {} of source file: {}",
+        algo.label(),
+        sources::list(&opts.source_paths())
+    );
+    let md5_hash = source.hash(algo)?;
+    status!("{}: {}", algo.label(), md5_hash);
+    let compressed = compress_input(
+        &bitstream,
+        bitstream_size,
+        opts.compression,
+        opts.compress_level,
+        ctx,
+    )?;
+    let (bitstream, bitstream_size) = match &compressed {
+        Some(compressed) => (compressed.path.clone(), compressed.size),
+        None => (bitstream, bitstream_size),
+    };
+
+    // The bitstream ends up both in the working tree and in a git object
+    let checkout = checkout::checkout_to_publish(pool, remote, ctx, |dir| {
+        fsutil::ensure_free_space(
+            dir,
+            bitstream_size.saturating_mul(2),
+            "the bitstream in the clone",
+            WORK_DIR_HINT,
+        )
+    })?;
+    let repo_dir = checkout.dir();
+
+    // Load or create metadata
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    let metadata = metadata_file.load_or_new()?;
+
+    check_no_symlinks(repo_dir, &binary_rel_path)?;
+    let mut file_name = None;
+    if opts.on_collision == OnCollision::Rename {
+        let renamed = renamed_path(
+            &metadata,
+            (&md5_hash, &name),
+            variant.as_deref(),
+            &binary_rel_path,
+            opts.compression,
+            repo_dir,
+            &bitstream,
+        )?;
+        if let Some((path, name)) = renamed {
+            check_no_symlinks(repo_dir, &path)?;
+            binary_rel_path = path;
+            file_name = Some(name);
+        }
+    }
+
+    // The clone holds the file under its stored name, so get finds it again
+    let dest_bitstream = paths::long_path(&repo_dir.join(&binary_rel_path));
+    let tracked = storage::of(remote).files(repo_dir)?;
+    check_metadata_clash(&binary_rel_path, remote)?;
+    check_case_collision(&binary_rel_path, tracked.iter().map(String::as_str))?;
+
+    let branch = checked_branch(repo_dir, remote)?;
+    let existing = metadata.lookup(&md5_hash, &name);
+    let unchanged = match existing {
+        Some(existing) if existing.binary_path == binary_rel_path => {
+            same_contents(&bitstream, &dest_bitstream)?
+        }
+        _ => false,
+    };
+
+    let plan = PublishPlan {
+        repo: remote.url.clone(),
+        branch,
+        hash_algo: algo.name(),
+        hash: md5_hash.clone(),
+        name,
+        entry_exists: existing.is_some(),
+        action: match existing {
+            Some(_) if unchanged && !opts.force => PublishAction::Unchanged,
+            Some(_) => PublishAction::Overwrite,
+            None => PublishAction::Create,
+        },
+        unchanged,
+        binary_path: binary_rel_path,
+        upload_bytes: bitstream_size,
+        commit_message: with_trailers(
+            add_subject(algo, &md5_hash),
+            provenance(published_by.as_deref(), source_commit.as_deref()),
+        ),
+        policies: Vec::new(),
+    };
+
+    Ok(Prepared {
+        checkout,
+        metadata_file,
+        metadata,
+        bitstream,
+        compressed,
+        dest_bitstream,
+        source_filename,
+        variant,
+        source_files: source.names(),
+        file_mode,
+        file_name,
+        published_by,
+        source_commit,
+        plan,
+    })
+}
+
+/// Whether `stored` exists and has the same contents as `bitstream`
+fn same_contents(bitstream: &Path, stored: &Path) -> io::Result<bool> {
+    match fs::metadata(stored) {
+        Ok(meta) if meta.len() == fs::metadata(bitstream)?.len() => {
+            Ok(compute_md5(stored)? == compute_md5(bitstream)?)
+        }
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Work out what [`publish`] would do without modifying the repository
+///
+/// The inputs are checked and the repository is cloned exactly as for a real
+/// publish; nothing is committed or pushed.
+pub fn explain(remote: &Remote, opts: &PublishOptions, ctx: &Context) -> io::Result<PublishPlan> {
+    let _entered = ctx.enter();
+    prepare(None, remote, opts, ctx).map(|prepared| prepared.plan)
+}
+
+/// Check a [`publish`] without committing anything
+///
+/// Everything [`publish`] checks is checked, so a publish it would refuse
+/// fails here with the same error, e.g. [`BitcacheError::AlreadyPublished`].
+/// The [`Published`] returned describes what it would have committed.
+pub fn publish_dry_run(
+    remote: &Remote,
+    opts: &PublishOptions,
+    ctx: &Context,
+) -> io::Result<Published> {
+    let _entered = ctx.enter();
+    publish_or_check(None, remote, opts, ctx, false)
+}
+
+/// Publish a bitstream, retrying when concurrent publishers move the remote
+///
+/// An entry the hash and variant already have is only replaced with
+/// [`PublishOptions::force`], unless it stores the same bitstream at the
+/// same path; [`crate::update`] replaces just the bitstream. Such an entry
+/// is left alone without `force`, nothing is committed, and
+/// [`Published::action`] is [`PublishAction::Unchanged`].
+///
+/// ```no_run
+/// use bitcache::{Context, PublishOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = PublishOptions::new("top.vhd", "build/top.bit", "boards/zedboard");
+/// let published = bitcache::publish(&remote, &opts, &Context::default())?;
+/// println!("stored {} as {}", published.md5, published.binary_path);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn publish(remote: &Remote, opts: &PublishOptions, ctx: &Context) -> io::Result<Published> {
+    publish_in(None, remote, opts, ctx)
+}
+
+/// [`publish`], in the clone kept by `pool` if given
+pub(crate) fn publish_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &PublishOptions,
+    ctx: &Context,
+) -> io::Result<Published> {
+    let _entered = ctx.enter();
+    publish_or_check(pool, remote, opts, ctx, true)
+}
+
+/// [`publish_in`], or with `commit` unset [`publish_dry_run`], which stops
+/// once every check has passed
+fn publish_or_check(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &PublishOptions,
+    ctx: &Context,
+    commit: bool,
+) -> io::Result<Published> {
+    let Prepared {
+        checkout,
+        metadata_file,
+        metadata,
+        bitstream,
+        compressed,
+        dest_bitstream,
+        source_filename,
+        variant,
+        source_files,
+        file_mode,
+        file_name,
+        published_by,
+        source_commit,
+        plan,
+    } = prepare(pool, remote, opts, ctx)?;
+    let repo_dir = checkout.dir();
+    let md5_hash = &plan.hash;
+    let binary_rel_path = &plan.binary_path;
+    detail!("Publish plan:\n{}", serde_json::to_string_pretty(&plan)?);
+
+    if plan.action == PublishAction::Unchanged {
+        check_overwrite(metadata.lookup(md5_hash, &plan.name), true, opts)?;
+        if commit {
+            status!(
+                "Already published: {} holds this bitstream, nothing to commit",
+                binary_rel_path
+            );
+        }
+        return Ok(Published {
+            md5: plan.hash,
+            binary_path: plan.binary_path,
+            name: plan.name,
+            size: plan.upload_bytes,
+            commit_message: String::new(),
+            action: plan.action,
+        });
+    }
+
+    // Update metadata
+    let mut entry = MetadataEntry::new(
+        md5_hash.clone(),
+        binary_rel_path.clone(),
+        source_filename,
+        chrono::Utc::now().to_rfc3339(),
+    );
+    entry.hash_algo = plan.hash_algo.to_string();
+    entry.source_files = source_files;
+    entry.branch = plan.branch.clone();
+    entry.tags = opts.tags.clone();
+    entry.size = Some(plan.upload_bytes);
+    entry.file_mode = file_mode;
+    entry.file_name = file_name;
+    entry.os = opts.os.clone();
+    entry.arch = opts.arch.clone();
+    entry.build_tool_version = opts.build_tool_version.clone();
+    entry.published_by = published_by;
+    entry.tool_version = Some(TOOL_VERSION.to_string());
+    entry.source_commit = source_commit;
+    // Recorded so verify can tell a damaged bitstream from a good one; with
+    // --paranoid also what the copy in the clone must read back as
+    let bitstream_digest = compute_md5(&bitstream)?;
+    entry.binary_md5 = Some(bitstream_digest.clone());
+    entry.set_compression(opts.compression);
+    entry.original_size = compressed.as_ref().map(|c| c.original_size);
+    entry.variant = variant;
+
+    check_overwrite(metadata.lookup(md5_hash, &plan.name), plan.unchanged, opts)?;
+    let displaces = opts.on_collision == OnCollision::Overwrite;
+    if !displaces {
+        check_shared_path(&metadata, &entry, repo_dir, &bitstream)?;
+    }
+    if !commit {
+        return Ok(Published {
+            md5: plan.hash,
+            binary_path: plan.binary_path,
+            name: plan.name,
+            size: plan.upload_bytes,
+            commit_message: plan.commit_message,
+            action: plan.action,
+        });
+    }
+
+    let staged = [Staged {
+        base_entry: metadata.lookup(md5_hash, &plan.name).cloned(),
+        entry,
+        bitstream,
+        dest_bitstream,
+        digest: bitstream_digest,
+        size: plan.upload_bytes,
+        displaces,
+    }];
+    let sizes = commit_staged(
+        &checkout,
+        &metadata_file,
+        metadata,
+        &staged,
+        &plan.commit_message,
+        remote,
+        ctx,
+    )?;
+
+    Ok(Published {
+        md5: plan.hash,
+        binary_path: plan.binary_path,
+        name: plan.name,
+        size: sizes[0],
+        commit_message: plan.commit_message,
+        action: plan.action,
+    })
+}
+
+/// Publish several bitstreams in one commit
+///
+/// Every entry is checked and hashed before the repository is cloned, and
+/// nothing is committed unless all of them can be published: a missing
+/// file, two entries for the same source hash and variant or two different
+/// bitstreams for the same path fail the whole batch, as does an existing
+/// entry [`publish`] would refuse to replace. Entries [`publish`] would
+/// leave unchanged are left out of the commit, and returned in their place
+/// with [`PublishAction::Unchanged`]. The bitstreams are copied into the
+/// clone [`Context::jobs`] at a time. Concurrent publishers are merged with
+/// as for [`publish`].
+///
+/// ```no_run
+/// use bitcache::{Context, PublishOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let batch = [
+///     PublishOptions::new("top.vhd", "build/top.bit", "boards/zedboard"),
+///     PublishOptions::new("top.vhd", "build/top_arty.bit", "boards/arty"),
+/// ];
+/// for published in bitcache::publish_batch(&remote, &batch, &Context::default())? {
+///     println!("stored {} as {}", published.md5, published.binary_path);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn publish_batch(
+    remote: &Remote,
+    batch: &[PublishOptions],
+    ctx: &Context,
+) -> io::Result<Vec<Published>> {
+    publish_batch_in(None, remote, batch, ctx)
+}
+
+/// [`publish_batch`], in the clone kept by `pool` if given
+pub(crate) fn publish_batch_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    batch: &[PublishOptions],
+    ctx: &Context,
+) -> io::Result<Vec<Published>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    if batch.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The batch has no entries to publish",
+        ));
+    }
+    let inputs = batch
+        .iter()
+        .map(check_inputs)
+        .collect::<io::Result<Vec<_>>>()?;
+
+    status!("Publishing {} bitstreams...", batch.len());
+    let mut hashes = Vec::with_capacity(batch.len());
+    for (opts, input) in batch.iter().zip(&inputs) {
+        let algo = opts.hash_algo;
+        let hash = input.source.hash(algo)?;
+        status!(
+            "{} of {}: {}",
+            algo.label(),
+            sources::list(&opts.source_paths()),
+            hash
+        );
+        hashes.push((hash, compute_md5(&input.bitstream)?));
+    }
+    let compressed = batch
+        .iter()
+        .zip(&inputs)
+        .map(|(opts, input)| {
+            compress_input(
+                &input.bitstream,
+                input.bitstream_size,
+                opts.compression,
+                opts.compress_level,
+                ctx,
+            )
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    for (i, (opts, input)) in batch.iter().zip(&inputs).enumerate() {
+        for (j, earlier) in inputs[..i].iter().enumerate() {
+            if hashes[j].0 == hashes[i].0 && earlier.name == input.name {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} and {} both have {} {} and the name {}; a batch can publish one bitstream per source and name",
+                        sources::list(&batch[j].source_paths()),
+                        sources::list(&opts.source_paths()),
+                        opts.hash_algo.label(),
+                        hashes[i].0,
+                        input.name
+                    ),
+                ));
+            }
+            if earlier.binary_rel_path == input.binary_rel_path && hashes[j].1 != hashes[i].1 {
+                return Err(BitcacheError::PathTaken {
+                    path: input.binary_rel_path.clone(),
+                    reason: format!(
+                        "the batch puts both {} and {} there",
+                        batch[j].bitstream.display(),
+                        opts.bitstream.display()
+                    ),
+                }
+                .into());
+            }
+        }
+    }
+
+    // Every bitstream ends up both in the working tree and in a git object
+    let total: u64 = inputs
+        .iter()
+        .zip(&compressed)
+        .map(|(input, compressed)| compressed.as_ref().map_or(input.bitstream_size, |c| c.size))
+        .sum();
+    let checkout = checkout::checkout_to_publish(pool, remote, ctx, |dir| {
+        fsutil::ensure_free_space(
+            dir,
+            total.saturating_mul(2),
+            "the bitstreams in the clone",
+            WORK_DIR_HINT,
+        )
+    })?;
+    let repo_dir = checkout.dir();
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    let metadata = metadata_file.load_or_new()?;
+    let tracked = storage::of(remote).files(repo_dir)?;
+    let branch = checked_branch(repo_dir, remote)?;
+
+    let timestamp = chrono::Utc::now().to_rfc3339();
+    let mut staged = Vec::with_capacity(batch.len());
+    // In batch order; `None` for the staged ones, filled in once committed
+    let mut results = Vec::with_capacity(batch.len());
+    let mut subjects = String::new();
+    let mut trailers = Vec::new();
+    for (((opts, mut input), (hash, digest)), compressed) in
+        batch.iter().zip(inputs).zip(hashes).zip(&compressed)
+    {
+        // A compressed bitstream is stored, and checked, as its copy
+        let (bitstream, size, digest) = match compressed {
+            Some(compressed) => (
+                compressed.path.clone(),
+                compressed.size,
+                compute_md5(&compressed.path)?,
+            ),
+            None => (input.bitstream, input.bitstream_size, digest),
+        };
+        check_no_symlinks(repo_dir, &input.binary_rel_path)?;
+        let mut file_name = None;
+        if opts.on_collision == OnCollision::Rename {
+            let renamed = renamed_path(
+                &metadata,
+                (&hash, &input.name),
+                input.variant.as_deref(),
+                &input.binary_rel_path,
+                opts.compression,
+                repo_dir,
+                &bitstream,
+            )?;
+            if let Some((path, name)) = renamed {
+                check_no_symlinks(repo_dir, &path)?;
+                input.binary_rel_path = path;
+                file_name = Some(name);
+            }
+        }
+        let others = staged
+            .iter()
+            .map(|other: &Staged| other.entry.binary_path.as_str());
+        check_metadata_clash(&input.binary_rel_path, remote)?;
+        check_case_collision(
+            &input.binary_rel_path,
+            tracked.iter().map(String::as_str).chain(others),
+        )?;
+
+        let mut entry = MetadataEntry::new(
+            hash.clone(),
+            input.binary_rel_path.clone(),
+            input.source_filename,
+            timestamp.clone(),
+        );
+        entry.hash_algo = opts.hash_algo.name().to_string();
+        entry.source_files = input.source.names();
+        entry.branch = branch.clone();
+        entry.tags = opts.tags.clone();
+        entry.size = Some(size);
+        entry.file_mode = input.file_mode;
+        entry.file_name = file_name;
+        entry.os = opts.os.clone();
+        entry.arch = opts.arch.clone();
+        entry.build_tool_version = opts.build_tool_version.clone();
+        entry.published_by = input.published_by.take();
+        entry.tool_version = Some(TOOL_VERSION.to_string());
+        entry.source_commit = input.source_commit.take();
+        entry.binary_md5 = Some(digest.clone());
+        entry.set_compression(opts.compression);
+        entry.original_size = compressed.as_ref().map(|c| c.original_size);
+        entry.variant = input.variant;
+        let existing = metadata.lookup(&hash, &input.name);
+        let dest_bitstream = paths::long_path(&repo_dir.join(&input.binary_rel_path));
+        let unchanged = match existing {
+            Some(existing) if existing.binary_path == input.binary_rel_path => {
+                same_contents(&bitstream, &dest_bitstream)?
+            }
+            _ => false,
+        };
+        check_overwrite(existing, unchanged, opts)?;
+        if unchanged && !opts.force {
+            status!(
+                "Already published: {} holds the bitstream for {}",
+                input.binary_rel_path,
+                entry.label()
+            );
+            results.push(Some(Published {
+                name: input.name,
+                md5: entry.md5,
+                binary_path: entry.binary_path,
+                size,
+                commit_message: String::new(),
+                action: PublishAction::Unchanged,
+            }));
+            continue;
+        }
+        let displaces = opts.on_collision == OnCollision::Overwrite;
+        if !displaces {
+            check_shared_path(&metadata, &entry, repo_dir, &bitstream)?;
+        }
+
+        subjects += &format!("\n{}", add_subject(opts.hash_algo, &hash));
+        trailers.extend(provenance(
+            entry.published_by.as_deref(),
+            entry.source_commit.as_deref(),
+        ));
+        results.push(None);
+        staged.push(Staged {
+            base_entry: existing.cloned(),
+            dest_bitstream,
+            entry,
+            bitstream,
+            digest,
+            size,
+            displaces,
+        });
+    }
+
+    if staged.is_empty() {
+        status!("Every bitstream is already published, nothing to commit");
+        return Ok(results.into_iter().flatten().collect());
+    }
+    let message = with_trailers(
+        format!(
+            "Add {} bitstream{}\n{}",
+            staged.len(),
+            if staged.len() == 1 { "" } else { "s" },
+            subjects
+        ),
+        trailers,
+    );
+    let sizes = commit_staged(
+        &checkout,
+        &metadata_file,
+        metadata,
+        &staged,
+        &message,
+        remote,
+        ctx,
+    )?;
+    let mut committed = staged
+        .into_iter()
+        .zip(sizes)
+        .map(|(staged, size)| Published {
+            action: if staged.base_entry.is_some() {
+                PublishAction::Overwrite
+            } else {
+                PublishAction::Create
+            },
+            name: staged.entry.name().to_string(),
+            md5: staged.entry.md5,
+            binary_path: staged.entry.binary_path,
+            size,
+            commit_message: message.clone(),
+        });
+    Ok(results
+        .into_iter()
+        .map(|result| result.or_else(|| committed.next()))
+        .collect::<Option<_>>()
+        .expect("one result per batch entry"))
+}
+
+/// A bitstream ready to be placed in the clone, with its entry
+pub(crate) struct Staged {
+    pub(crate) entry: MetadataEntry,
+    /// The bitstream to read, with symlinks resolved
+    pub(crate) bitstream: PathBuf,
+    /// Where the bitstream goes in the clone
+    pub(crate) dest_bitstream: PathBuf,
+    /// MD5 of the bitstream, what the copy in the clone must read back as
+    pub(crate) digest: String,
+    /// Size of the bitstream in bytes
+    pub(crate) size: u64,
+    /// What the entry replaces; if a concurrent publisher changes the same
+    /// MD5 and name in the meantime the two publishes conflict
+    pub(crate) base_entry: Option<MetadataEntry>,
+    /// Replace a different bitstream another entry has at the path,
+    /// removing that entry, instead of refusing to
+    pub(crate) displaces: bool,
+}
+
+/// Place `staged` in the clone, record them in the metadata and push them in
+/// one commit, retrying when concurrent publishers move the remote
+///
+/// Returns the bytes placed for each. An entry a concurrent run already
+/// published identically is left as the remote has it.
+pub(crate) fn commit_staged(
+    checkout: &Checkout,
+    metadata_file: &MetadataFile,
+    mut metadata: Metadata,
+    staged: &[Staged],
+    message: &str,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<Vec<u64>> {
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    let mut sizes: Vec<u64> = staged.iter().map(|staged| staged.size).collect();
+    // Indices of the entries no concurrent run has published meanwhile
+    let mut pending: Vec<usize> = (0..staged.len()).collect();
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(push_backoff(attempt));
+            progress::emit(Event::Retry {
+                attempt,
+                attempts,
+                reason: &rejection,
+            });
+            status!(
+                "Push rejected, merging with the new remote head (attempt {} of {})",
+                attempt,
+                attempts
+            );
+            storage::of(remote).reset(repo_dir, auth)?;
+            metadata = metadata_file.load_or_new()?;
+
+            let mut still_pending = Vec::with_capacity(pending.len());
+            for i in pending {
+                let ours = &staged[i];
+                let remote_entry = metadata.lookup(&ours.entry.md5, ours.entry.name());
+                if remote_entry != ours.base_entry.as_ref() {
+                    if let Some(remote) = remote_entry {
+                        if is_same_publish(remote, &ours.entry, repo_dir, &ours.bitstream)? {
+                            status!(
+                                "Bitstream {} was already published by a concurrent run",
+                                ours.entry.binary_path
+                            );
+                            continue;
+                        }
+                    }
+                    return Err(publish_conflict(remote_entry, &ours.entry));
+                }
+                if !ours.displaces {
+                    check_shared_path(&metadata, &ours.entry, repo_dir, &ours.bitstream)?;
+                }
+                still_pending.push(i);
+            }
+            pending = still_pending;
+            if pending.is_empty() {
+                break;
+            }
+        }
+
+        // A clone that outlives the operation must not keep a bitstream
+        // this attempt fails to push; a one-off clone is removed anyway, or
+        // kept as the failure left it with --keep-temp
+        let mut placed = Placed {
+            repo_dir,
+            paths: Vec::new(),
+        };
+        if checkout.is_pooled() {
+            placed.paths = pending
+                .iter()
+                .map(|&i| &staged[i])
+                .filter(|ours| fs::symlink_metadata(&ours.dest_bitstream).is_err())
+                .map(|ours| ours.entry.binary_path.as_str())
+                .collect();
+        }
+        let mut written = Vec::with_capacity(pending.len() + 2);
+        for &i in &pending {
+            let ours = &staged[i];
+            // Create target directory in repository
+            if let Some(dir) = ours.dest_bitstream.parent() {
+                fs::create_dir_all(dir)?;
+            }
+
+            if ours.displaces {
+                displace(&mut metadata, ours)?;
+            }
+        }
+        for (i, placed) in place_bitstreams(staged, &pending, checkout.is_pooled(), ctx)? {
+            sizes[i] = placed;
+        }
+        for &i in &pending {
+            metadata.insert_entry(staged[i].entry.clone());
+            written.push(staged[i].entry.binary_path.as_str());
+        }
+
+        let mut expired = Vec::new();
+        trash::expire(repo_dir, &mut metadata, ctx, &mut expired)?;
+
+        // Save metadata
+        cancel::check()?;
+        status!("Updating metadata...");
+        let mut marked = None;
+        for &i in &pending {
+            marked = storage::of(remote)
+                .mark_binary(repo_dir, &staged[i].entry.binary_path)?
+                .or(marked);
+        }
+        let metadata_files = metadata_file.save(&metadata, ctx.paranoid)?;
+
+        // Commit and push
+        status!("Committing and pushing changes...");
+        written.extend(metadata_files.iter().map(String::as_str));
+        written.extend(marked);
+        written.extend(expired.iter().map(String::as_str));
+        if !storage::of(remote).commit(repo_dir, &written, message)? {
+            status!("No changes to commit");
+            placed.keep();
+            break;
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => {
+                placed.keep();
+                break;
+            }
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+    Ok(sizes)
+}
+
+/// Bitstreams placed in the clone where there was no file before, removed
+/// again when dropped unless [`Placed::keep`] was called
+struct Placed<'a> {
+    repo_dir: &'a Path,
+    paths: Vec<&'a str>,
+}
+
+impl Placed<'_> {
+    /// Keep the bitstreams, which went through
+    fn keep(&mut self) {
+        self.paths.clear();
+    }
+}
+
+impl Drop for Placed<'_> {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(paths::long_path(&self.repo_dir.join(path)));
+            fsutil::remove_empty_parents(self.repo_dir, path);
+        }
+    }
+}
+
+/// Copy the bitstreams of the `pending` entries of `staged` into the clone,
+/// up to [`Context::jobs`] at a time, returning the bytes placed for each
+///
+/// The copies go to different paths, except for entries sharing one, whose
+/// bitstream is copied once. A clone that outlives the operation, such as a
+/// local repository used in place, never shares an inode with the user's
+/// file, or changing that file later would change the published bitstream.
+fn place_bitstreams(
+    staged: &[Staged],
+    pending: &[usize],
+    persistent: bool,
+    ctx: &Context,
+) -> io::Result<Vec<(usize, u64)>> {
+    let mut copies: Vec<usize> = Vec::with_capacity(pending.len());
+    for &i in pending {
+        if !copies
+            .iter()
+            .any(|&j| staged[j].dest_bitstream == staged[i].dest_bitstream)
+        {
+            copies.push(i);
+        }
+    }
+    let copy = |i: usize| -> io::Result<u64> {
+        cancel::check()?;
+        let ours = &staged[i];
+        // Copy bitstream to target location
+        status!("Copying bitstream to: {}", ours.entry.binary_path);
+        let (placed, placement) = if persistent {
+            fsutil::clone_or_copy(&ours.bitstream, &ours.dest_bitstream)?
+        } else {
+            fsutil::link_or_copy(&ours.bitstream, &ours.dest_bitstream)?
+        };
+        detail!("Placed bitstream in clone via {}", placement);
+        if ctx.paranoid {
+            verify_written(&ours.dest_bitstream, &ours.digest)?;
+        }
+        Ok(placed)
+    };
+
+    let jobs = match ctx.jobs {
+        0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        jobs => jobs,
+    };
+    let placed = if jobs <= 1 || copies.len() <= 1 {
+        copies
+            .iter()
+            .map(|&i| Ok((i, copy(i)?)))
+            .collect::<io::Result<Vec<_>>>()?
+    } else {
+        let next = AtomicUsize::new(0);
+        let placed = Mutex::new(Vec::with_capacity(copies.len()));
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs.min(copies.len()))
+                .map(|_| {
+                    scope.spawn(|| -> io::Result<()> {
+                        let _entered = ctx.enter();
+                        while let Some(&i) = copies.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            match copy(i) {
+                                Ok(size) => placed.lock().unwrap().push((i, size)),
+                                Err(e) => {
+                                    // The other workers stop at their next copy
+                                    next.store(copies.len(), Ordering::Relaxed);
+                                    return Err(e);
+                                }
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            workers.into_iter().try_for_each(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+        })?;
+        placed.into_inner().unwrap()
+    };
+
+    Ok(pending
+        .iter()
+        .map(|&i| {
+            let (_, size) = placed
+                .iter()
+                .find(|(j, _)| staged[*j].dest_bitstream == staged[i].dest_bitstream)
+                .expect("every path is copied");
+            (i, *size)
+        })
+        .collect())
+}
@@ -0,0 +1,849 @@
+//! Recovery of damaged metadata files.
+//!
+//! A bad manual merge can leave `bitcache_metadata.json` with trailing
+//! commas, comments, duplicated keys or a half-deleted entry, after which the
+//! strict parser used by every other command refuses it. This module salvages
+//! what it can:
+//!
+//! - the document is read with a forgiving JSON dialect: trailing and
+//!   doubled commas, `//` and `/* */` comments, single-quoted strings and
+//!   bare keys are accepted
+//! - each member of `entries` is parsed on its own, so one broken entry is
+//!   cut out at the next top-level `,` or `}` instead of losing the file
+//! - entries that still don't form a valid [`MetadataEntry`] are rejected
+//!   with their original text, for a human to review
+//! - duplicated keys keep the entry with the newest timestamp; the older ones
+//!   are rejected as well, so nothing is dropped silently
+//!
+//! The parser never panics on any input: it indexes the source only through
+//! bounds-checked accessors and limits nesting depth.
+//!
+//! [`inspect`] runs this on a cloned repository, and [`RepairPlan::apply`]
+//! commits the result.
+
+use crate::checkout::{self, Checkout};
+use crate::error::BitcacheError;
+use crate::git::PushOutcome;
+use crate::layout::{self, MetadataFile};
+use crate::progress::{self, status, ProgressObserver};
+use crate::storage;
+use crate::{fsutil, Context, Metadata, MetadataEntry, Remote, SHARD_DIR};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+/// Deepest nesting the parser follows before giving up on a value
+const MAX_DEPTH: usize = 128;
+
+/// An entry that could not be recovered, kept for review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rejected {
+    /// Key the entry was stored under, if it could be read
+    pub key: Option<String>,
+    /// Why the entry was rejected
+    pub reason: String,
+    /// The entry's text as found in the damaged file
+    pub raw: String,
+}
+
+/// Result of salvaging a metadata file
+pub struct Repair {
+    /// The recovered metadata
+    pub metadata: Metadata,
+    /// Entries that were dropped, including older duplicates
+    pub rejected: Vec<Rejected>,
+    /// How many of the rejected entries were older duplicates
+    pub duplicates: usize,
+}
+
+/// Where [`RepairPlan::apply`] puts entries it could not recover, for human
+/// review
+pub const REJECTED_FILE: &str = "bitcache_metadata.rejected.json";
+
+/// Contents of [`REJECTED_FILE`]
+#[derive(Serialize, Deserialize)]
+struct RejectedEntries {
+    rejected: Vec<Rejected>,
+}
+
+/// The metadata file of a cloned repository, salvaged but not yet written
+pub struct RepairPlan {
+    /// Keeps the clone alive until the plan is applied or dropped
+    checkout: Checkout<'static>,
+    remote: Remote,
+    observer: Option<Arc<dyn ProgressObserver>>,
+    /// Why the strict parser refuses the file, if it does
+    pub strict_error: Option<io::Error>,
+    /// What could be salvaged
+    pub repair: Repair,
+}
+
+/// Clone a repository and salvage its metadata file without changing anything
+///
+/// ```no_run
+/// use bitcache::{repair, Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let plan = repair::inspect(&remote, &Context::default())?;
+/// if plan.needs_repair() && plan.repair.rejected.is_empty() {
+///     plan.apply()?;
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn inspect(remote: &Remote, ctx: &Context) -> io::Result<RepairPlan> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+
+    let checkout = checkout::checkout(None, remote, ctx, |_| Ok(()))?;
+    let metadata_file = MetadataFile::of(checkout.dir(), remote);
+    let metadata_path = &metadata_file.path;
+    let content = fs::read(metadata_path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => BitcacheError::MissingMetadata.into(),
+        _ => e,
+    })?;
+    // Each shard holds a single hash, small enough to fix by hand
+    if layout::shard_dir(metadata_path).exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "The repository keeps its entries in the sharded layout under {}, which repair does not read; a damaged shard holds a single hash and can be fixed by hand",
+                SHARD_DIR
+            ),
+        ));
+    }
+    let strict_error = match Metadata::load_from_file(metadata_path) {
+        // Repairing would rewrite a newer schema in this version's shape
+        Err(e) if matches!(BitcacheError::of(&e), Some(BitcacheError::Migration { .. })) => {
+            return Err(e)
+        }
+        result => result.err(),
+    };
+    let repair = repair(&content).map_err(|reason| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cannot repair {}: {}", metadata_file.repo_path, reason),
+        )
+    })?;
+
+    Ok(RepairPlan {
+        checkout,
+        remote: remote.clone(),
+        observer: ctx.observer.clone(),
+        strict_error,
+        repair,
+    })
+}
+
+impl RepairPlan {
+    /// Whether the file is damaged at all
+    pub fn needs_repair(&self) -> bool {
+        self.strict_error.is_some() || !self.repair.rejected.is_empty()
+    }
+
+    /// Commit and push the repaired metadata, moving rejected entries to
+    /// [`REJECTED_FILE`]
+    ///
+    /// Returns `false` when there was nothing to commit.
+    pub fn apply(self) -> io::Result<bool> {
+        let _observing = progress::observe(self.observer.clone());
+        let repo_dir = self.checkout.dir();
+        let metadata_file = MetadataFile::of(repo_dir, &self.remote);
+        let mut written = vec![metadata_file.repo_path.as_str()];
+        if !self.repair.rejected.is_empty() {
+            // Keep what earlier repairs set aside
+            let rejected_path = repo_dir.join(REJECTED_FILE);
+            let mut review = match fs::read(&rejected_path) {
+                Ok(content) => serde_json::from_slice(&content).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Failed to parse {}: {}", REJECTED_FILE, e),
+                    )
+                })?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => RejectedEntries {
+                    rejected: Vec::new(),
+                },
+                Err(e) => return Err(e),
+            };
+            review.rejected.extend(self.repair.rejected);
+            fsutil::atomic_write(&rejected_path, &serde_json::to_vec_pretty(&review)?)?;
+            written.push(REJECTED_FILE);
+        }
+        self.repair.metadata.save_to_file(&metadata_file.path)?;
+
+        status!("Committing and pushing changes...");
+        if !storage::of(&self.remote).commit(repo_dir, &written, "Repair bitcache metadata")? {
+            status!("No changes to commit");
+            return Ok(false);
+        }
+        if let PushOutcome::Rejected(stderr) =
+            storage::of(&self.remote).push(repo_dir, self.remote.auth.as_ref())?
+        {
+            return Err(io::Error::other(format!(
+                "The remote changed while repairing, run repair again: {}",
+                stderr
+            )));
+        }
+        Ok(true)
+    }
+}
+
+/// Salvage the entries of a metadata document
+///
+/// Fails only when no `entries` object can be found at all.
+pub fn repair(content: &[u8]) -> Result<Repair, String> {
+    let text = String::from_utf8_lossy(content);
+    let mut parser = Parser::new(text.as_bytes());
+    let members = parser.root_entries()?;
+
+    let mut rejected = Vec::new();
+    let mut candidates: Vec<(MetadataEntry, String)> = Vec::new();
+    for member in members {
+        let Member { key, value, raw } = member;
+        let value = match value {
+            Ok(value) => value,
+            Err(reason) => {
+                rejected.push(Rejected { key, reason, raw });
+                continue;
+            }
+        };
+        let key = key.unwrap_or_default();
+        // The entries of a source with several are each recovered on their
+        // own
+        let values = match value {
+            Value::Array(values) => values
+                .into_iter()
+                .map(|value| {
+                    let raw = value.to_string();
+                    (value, raw)
+                })
+                .collect(),
+            value => vec![(value, raw)],
+        };
+        for (value, raw) in values {
+            match into_entry(&key, value) {
+                Ok(entry) => candidates.push((entry, raw)),
+                Err(reason) => rejected.push(Rejected {
+                    key: Some(key.clone()),
+                    reason,
+                    raw,
+                }),
+            }
+        }
+    }
+
+    // Newest timestamp wins; on a tie the later occurrence, as a strict
+    // parser would have kept it
+    let mut newest: HashMap<(String, String), usize> = HashMap::new();
+    for (index, (entry, _)) in candidates.iter().enumerate() {
+        let id = (entry.md5.clone(), entry.name().to_string());
+        match newest.get(&id) {
+            Some(&kept) if sort_key(&candidates[kept].0) > sort_key(entry) => {}
+            _ => {
+                newest.insert(id, index);
+            }
+        }
+    }
+
+    let mut metadata = Metadata::new();
+    let mut duplicates = 0;
+    for (index, (entry, raw)) in candidates.into_iter().enumerate() {
+        let id = (entry.md5.clone(), entry.name().to_string());
+        if newest.get(&id) == Some(&index) {
+            metadata.insert_entry(entry);
+        } else {
+            duplicates += 1;
+            rejected.push(Rejected {
+                key: Some(entry.md5.clone()),
+                reason: "older duplicate of an entry with the same MD5 and name".to_string(),
+                raw,
+            });
+        }
+    }
+
+    Ok(Repair {
+        metadata,
+        rejected,
+        duplicates,
+    })
+}
+
+fn into_entry(key: &str, value: Value) -> Result<MetadataEntry, String> {
+    let entry: MetadataEntry =
+        serde_json::from_value(value).map_err(|e| format!("not a valid entry: {}", e))?;
+    if entry.md5 != key {
+        return Err(format!(
+            "stored under key '{}' but its md5 field is '{}'",
+            key, entry.md5
+        ));
+    }
+    entry.check_path().map_err(|e| e.to_string())?;
+    Ok(entry)
+}
+
+/// Ordering used to pick among duplicates; unparsable timestamps lose
+fn sort_key(entry: &MetadataEntry) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    chrono::DateTime::parse_from_rfc3339(&entry.timestamp).ok()
+}
+
+/// One member of the `entries` object
+struct Member {
+    key: Option<String>,
+    value: Result<Value, String>,
+    raw: String,
+}
+
+/// A forgiving JSON reader over the raw bytes of the document
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    /// Step over `byte` if it is next, leaving the position on anything else
+    /// so an error points at it
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn raw(&self, start: usize) -> String {
+        let end = self.pos.min(self.src.len());
+        let start = start.min(end);
+        String::from_utf8_lossy(self.src.get(start..end).unwrap_or_default())
+            .trim()
+            .to_string()
+    }
+
+    fn error(&self, what: &str) -> String {
+        match self.peek() {
+            Some(byte) => format!(
+                "{} at line {}, byte {} (found {:?})",
+                what,
+                self.line(),
+                self.pos,
+                byte as char
+            ),
+            None => format!("{} at end of file", what),
+        }
+    }
+
+    /// Line of the current position, counted from 1
+    fn line(&self) -> usize {
+        let end = self.pos.min(self.src.len());
+        1 + self
+            .src
+            .get(..end)
+            .unwrap_or_default()
+            .iter()
+            .filter(|&&byte| byte == b'\n')
+            .count()
+    }
+
+    /// Skip whitespace and comments
+    fn skip_trivia(&mut self) {
+        loop {
+            match (self.peek(), self.src.get(self.pos + 1).copied()) {
+                (Some(byte), _) if byte.is_ascii_whitespace() => self.pos += 1,
+                (Some(b'/'), Some(b'/')) => while !matches!(self.bump(), Some(b'\n') | None) {},
+                (Some(b'/'), Some(b'*')) => {
+                    self.pos += 2;
+                    while self.pos < self.src.len() && !self.src[self.pos..].starts_with(b"*/") {
+                        self.pos += 1;
+                    }
+                    self.pos = (self.pos + 2).min(self.src.len());
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Find the root object's `entries` member and read it member by member
+    fn root_entries(&mut self) -> Result<Vec<Member>, String> {
+        self.skip_trivia();
+        if self.bump() != Some(b'{') {
+            return Err("the file does not start with a JSON object".to_string());
+        }
+        loop {
+            self.skip_commas();
+            match self.peek() {
+                None | Some(b'}') => return Err("no \"entries\" object found".to_string()),
+                _ => {}
+            }
+            let key = self.key()?;
+            self.skip_trivia();
+            if !self.eat(b':') {
+                return Err(self.error(&format!("expected ':' after key \"{}\"", key)));
+            }
+            self.skip_trivia();
+            if key == "entries" && self.peek() == Some(b'{') {
+                self.pos += 1;
+                return Ok(self.entries());
+            }
+            // Some other root member; recovery is enough to step over it
+            if self.value(0).is_err() {
+                self.recover();
+            }
+        }
+    }
+
+    /// Read the members of the `entries` object, cutting out broken ones
+    fn entries(&mut self) -> Vec<Member> {
+        let mut members = Vec::new();
+        loop {
+            self.skip_commas();
+            match self.peek() {
+                None | Some(b'}') => return members,
+                _ => {}
+            }
+            let start = self.pos;
+            let mut key = None;
+            let value = self.key().and_then(|read| {
+                key = Some(read);
+                self.skip_trivia();
+                if !self.eat(b':') {
+                    return Err(self.error("expected ':' after the key"));
+                }
+                self.skip_trivia();
+                self.value(0)
+            });
+            if value.is_err() {
+                self.pos = start;
+                self.recover();
+            }
+            members.push(Member {
+                key,
+                value,
+                raw: self.raw(start),
+            });
+            self.skip_trivia();
+            match self.peek() {
+                Some(b',') | Some(b'}') | None => {}
+                // A missing comma between members
+                Some(b'"') | Some(b'\'') => {}
+                Some(_) => {
+                    let start = self.pos;
+                    self.recover();
+                    members.push(Member {
+                        key: None,
+                        value: Err("unexpected text between entries".to_string()),
+                        raw: self.raw(start),
+                    });
+                }
+            }
+        }
+    }
+
+    fn skip_commas(&mut self) {
+        self.skip_trivia();
+        while self.peek() == Some(b',') {
+            self.pos += 1;
+            self.skip_trivia();
+        }
+    }
+
+    /// Advance to the next `,` or `}` that is not nested inside a value,
+    /// stepping over strings, so a broken member can be cut out
+    fn recover(&mut self) {
+        let mut depth = 0usize;
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'"' | b'\'' => {
+                    if self.string().is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' if depth > 0 => depth -= 1,
+                b'}' => return,
+                b',' if depth == 0 => return,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// An object key: a quoted string or a bare identifier
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some(b'"') | Some(b'\'') => self.string(),
+            Some(byte) if is_bare(byte) => Ok(self.bare()),
+            _ => Err(self.error("expected a key")),
+        }
+    }
+
+    fn bare(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(is_bare) {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.src[start..self.pos]).into_owned()
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("values nested too deeply"));
+        }
+        self.skip_trivia();
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') | Some(b'\'') => self.string().map(Value::String),
+            Some(byte) if byte == b'-' || byte == b'+' || byte.is_ascii_digit() => self.number(),
+            Some(byte) if is_bare(byte) => match self.bare().as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                "null" => Ok(Value::Null),
+                other => Err(format!("unexpected word '{}'", other)),
+            },
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1;
+        let mut map = Map::new();
+        loop {
+            self.skip_commas();
+            if self.peek() == Some(b'}') {
+                self.pos += 1;
+                return Ok(Value::Object(map));
+            }
+            let key = self.key()?;
+            self.skip_trivia();
+            if !self.eat(b':') {
+                return Err(self.error(&format!("expected ':' after key \"{}\"", key)));
+            }
+            let value = self.value(depth + 1)?;
+            map.insert(key, value);
+            self.skip_trivia();
+            match self.peek() {
+                Some(b',') | Some(b'}') => {}
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_commas();
+            if self.peek() == Some(b']') {
+                self.pos += 1;
+                return Ok(Value::Array(items));
+            }
+            items.push(self.value(depth + 1)?);
+            self.skip_trivia();
+            match self.peek() {
+                Some(b',') | Some(b']') => {}
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|byte| byte.is_ascii_alphanumeric() || b"+-.".contains(&byte))
+        {
+            self.pos += 1;
+        }
+        let text = String::from_utf8_lossy(&self.src[start..self.pos]);
+        let text = text.strip_prefix('+').unwrap_or(&text);
+        serde_json::from_str::<Number>(text)
+            .map(Value::Number)
+            .map_err(|_| format!("'{}' is not a number", text))
+    }
+
+    /// A string in double or single quotes, with JSON escapes
+    fn string(&mut self) -> Result<String, String> {
+        let Some(quote) = self.bump() else {
+            return Err(self.error("expected a string"));
+        };
+        let mut bytes = Vec::new();
+        loop {
+            match self.bump() {
+                None => return Err("unterminated string".to_string()),
+                Some(byte) if byte == quote => break,
+                Some(b'\\') => self.escape(&mut bytes)?,
+                Some(byte) => bytes.push(byte),
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    fn escape(&mut self, bytes: &mut Vec<u8>) -> Result<(), String> {
+        let decoded = match self.bump() {
+            None => return Err("unterminated string".to_string()),
+            Some(b'n') => '\n',
+            Some(b't') => '\t',
+            Some(b'r') => '\r',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'u') => {
+                let high = self.hex4()?;
+                let code = if (0xD800..0xDC00).contains(&high)
+                    && self.src.get(self.pos..self.pos + 2) == Some(b"\\u")
+                {
+                    self.pos += 2;
+                    match self.hex4()? {
+                        low @ 0xDC00..=0xDFFF => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                        _ => u32::MAX,
+                    }
+                } else {
+                    high
+                };
+                char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+            // \" \\ \/ \' and anything unknown stand for themselves
+            Some(byte) => {
+                bytes.push(byte);
+                return Ok(());
+            }
+        };
+        let mut buffer = [0; 4];
+        bytes.extend_from_slice(decoded.encode_utf8(&mut buffer).as_bytes());
+        Ok(())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+}
+
+/// Characters allowed in bare keys and words
+fn is_bare(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::METADATA_FILE;
+    use std::fs;
+
+    const GOOD: &str = "0cc175b9c0f1b6a831c399e269772661";
+    const BAD: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+
+    /// A metadata file whose second entry lost the ':' after a key
+    fn damaged() -> String {
+        format!(
+            concat!(
+                "{{\n",
+                "  \"entries\": {{\n",
+                "    \"{good}\": {{\"md5\": \"{good}\", \"binary_path\": \"a/a.bit\",\n",
+                "      \"source_file\": \"a.vhd\", \"timestamp\": \"2024-01-01T00:00:00Z\"}},\n",
+                "    \"{bad}\": {{\"md5\" \"{bad}\"}}\n",
+                "  }}\n",
+                "}}\n"
+            ),
+            good = GOOD,
+            bad = BAD
+        )
+    }
+
+    #[test]
+    fn salvages_what_parses_and_points_at_the_rest() {
+        let repair = repair(damaged().as_bytes()).unwrap();
+        assert_eq!(repair.metadata.len(), 1);
+        assert!(repair.metadata.lookup(GOOD, "a.bit").is_some());
+        assert_eq!(repair.rejected.len(), 1);
+        let rejected = &repair.rejected[0];
+        assert_eq!(rejected.key.as_deref(), Some(BAD));
+        // The position is of the character found instead of the ':'
+        let at = damaged().find(&format!("\"md5\" \"{}", BAD)).unwrap() + 6;
+        assert!(
+            rejected
+                .reason
+                .ends_with(&format!("at line 5, byte {} (found '\"')", at)),
+            "{}",
+            rejected.reason
+        );
+    }
+
+    /// A valid file with a source of two variants, tags and escapes
+    fn valid() -> String {
+        format!(
+            concat!(
+                "{{\n",
+                "  \"schema_version\": 4,\n",
+                "  \"entries\": {{\n",
+                "    \"{good}\": [\n",
+                "      {{\"md5\": \"{good}\", \"binary_path\": \"a/a.bit\", \"source_file\": \"a.vhd\",\n",
+                "       \"timestamp\": \"2024-01-01T00:00:00Z\", \"tags\": {{\"board\": \"\\u00e9\\\"x\"}}}},\n",
+                "      {{\"md5\": \"{good}\", \"binary_path\": \"a/a.mcs\", \"source_file\": \"a.vhd\",\n",
+                "       \"timestamp\": \"2024-01-02T00:00:00Z\", \"original_size\": 1024}}\n",
+                "    ],\n",
+                "    \"{bad}\": {{\"md5\": \"{bad}\", \"binary_path\": \"b/b.bit\",\n",
+                "      \"source_file\": \"b.vhd\", \"timestamp\": \"2024-01-01T00:00:00Z\"}}\n",
+                "  }}\n",
+                "}}\n"
+            ),
+            good = GOOD,
+            bad = BAD
+        )
+    }
+
+    /// A deterministic xorshift generator, so a failure can be replayed
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+    }
+
+    /// `content` with a few bytes replaced, inserted or removed, favouring
+    /// the ones JSON gives a meaning
+    fn mutated(content: &[u8], rng: &mut Rng) -> Vec<u8> {
+        const SIGNIFICANT: &[u8] = b"{}[]\",:'\\/*0-eu \n\xff";
+        let mut bytes = content.to_vec();
+        for _ in 0..1 + rng.below(4) {
+            let at = rng.below(bytes.len() + 1);
+            let byte = match rng.below(3) {
+                0 => rng.next() as u8,
+                _ => SIGNIFICANT[rng.below(SIGNIFICANT.len())],
+            };
+            match rng.below(3) {
+                0 if at < bytes.len() => bytes[at] = byte,
+                1 if at < bytes.len() => {
+                    bytes.remove(at);
+                }
+                _ => bytes.insert(at, byte),
+            }
+        }
+        bytes
+    }
+
+    /// `repair` must not panic on `content`; which input it did is reported
+    fn survives(content: &[u8]) {
+        let outcome = std::panic::catch_unwind(|| repair(content));
+        assert!(
+            outcome.is_ok(),
+            "repair panicked on {:?}",
+            String::from_utf8_lossy(content)
+        );
+    }
+
+    #[test]
+    fn never_panics_on_truncated_or_mutated_metadata() {
+        let documents = [valid(), damaged()];
+        for document in &documents {
+            let repair = repair(document.as_bytes()).unwrap();
+            assert!(!repair.metadata.is_empty());
+            // Cut off anywhere
+            for end in 0..=document.len() {
+                survives(&document.as_bytes()[..end]);
+            }
+        }
+        assert_eq!(repair(valid().as_bytes()).unwrap().rejected.len(), 0);
+
+        let mut rng = Rng(0x5eed_b17c_ac4e);
+        for round in 0..3000 {
+            let document = documents[round % documents.len()].as_bytes();
+            let mut bytes = mutated(document, &mut rng);
+            if round % 5 == 0 {
+                bytes.truncate(rng.below(bytes.len() + 1));
+            }
+            survives(&bytes);
+        }
+        // Nesting deeper than any value is read
+        survives(format!("{{\"entries\": {{\"{}\": {}", GOOD, "[".repeat(10_000)).as_bytes());
+    }
+
+    #[test]
+    fn accepts_trailing_commas() {
+        let content = format!(
+            concat!(
+                "{{\"entries\": {{\n",
+                "  \"{good}\": [{{\"md5\": \"{good}\", \"binary_path\": \"a/a.bit\",\n",
+                "    \"source_file\": \"a.vhd\", \"timestamp\": \"2024-01-01T00:00:00Z\",}},],\n",
+                "  \"{bad}\": {{\"md5\": \"{bad}\", \"binary_path\": \"b/b.bit\",\n",
+                "    \"source_file\": \"b.vhd\", \"timestamp\": \"2024-01-01T00:00:00Z\",}},\n",
+                "}},}}\n"
+            ),
+            good = GOOD,
+            bad = BAD
+        );
+        let repair = repair(content.as_bytes()).unwrap();
+        assert!(repair.rejected.is_empty(), "{:?}", repair.rejected.len());
+        assert!(repair.metadata.lookup(GOOD, "a.bit").is_some());
+        assert!(repair.metadata.lookup(BAD, "b.bit").is_some());
+    }
+
+    #[test]
+    fn keeps_the_newest_of_duplicates() {
+        let entry = |path: &str, timestamp: &str| {
+            format!(
+                "\"{md5}\": {{\"md5\": \"{md5}\", \"binary_path\": \"{path}\", \
+                 \"source_file\": \"a.vhd\", \"timestamp\": \"{timestamp}\"}}",
+                md5 = GOOD,
+                path = path,
+                timestamp = timestamp
+            )
+        };
+        // The newest comes first, then an older one and one that can't be
+        // dated, either of which a strict parser would have kept instead
+        let content = format!(
+            "{{\"entries\": {{{}, {}, {}}}}}",
+            entry("new/a.bit", "2024-03-01T00:00:00Z"),
+            entry("old/a.bit", "2024-01-01T00:00:00+01:00"),
+            entry("undated/a.bit", "yesterday"),
+        );
+        let repair = repair(content.as_bytes()).unwrap();
+        assert_eq!(repair.duplicates, 2);
+        assert_eq!(repair.metadata.len(), 1);
+        let kept = repair.metadata.lookup(GOOD, "a.bit").unwrap();
+        assert_eq!(kept.binary_path, "new/a.bit");
+        assert!(repair
+            .rejected
+            .iter()
+            .all(|rejected| rejected.reason.starts_with("older duplicate")));
+    }
+
+    #[test]
+    fn the_strict_error_names_repair() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(METADATA_FILE);
+        fs::write(&path, damaged())?;
+        let error = Metadata::load_from_file(&path).expect_err("loaded a damaged file");
+        assert!(error.to_string().contains("bitcache repair"), "{}", error);
+        Ok(())
+    }
+}
@@ -0,0 +1,436 @@
+//! JSON Schemas for the documents bitcache reads and writes.
+//!
+//! `bitcache schema <document>` prints these so integrations can validate
+//! the metadata file, batch manifests and the `--json` output of `publish
+//! --explain`, `get`, `list` and `verify`. They are written by hand next to
+//! the types they describe; a change to one of those types must update its
+//! schema here and, if the change is not backwards compatible, bump
+//! [`SCHEMA_VERSION`]. The schema tests check real documents against them,
+//! and fail on a field a schema doesn't declare.
+
+use serde_json::{json, Value};
+
+/// Version of the schemas, part of each schema's `$id`
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A document with a schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Document {
+    /// `bitcache_metadata.json`, see [`crate::Metadata`]
+    Metadata,
+    /// The output of `publish --explain`, see [`crate::PublishPlan`]
+    Plan,
+    /// A `publish-batch` manifest in its JSON form, see
+    /// [`crate::BatchManifest`]
+    Manifest,
+    /// The output of `get --json`, see [`crate::Retrieved`]
+    Get,
+    /// The output of `list --json`, an array of [`crate::MetadataEntry`]
+    List,
+    /// The output of `verify --json`, see [`crate::VerifyReport`]
+    Verify,
+}
+
+/// The JSON Schema of `document`
+pub fn schema(document: Document) -> Value {
+    match document {
+        Document::Metadata => metadata(),
+        Document::Plan => plan(),
+        Document::Manifest => manifest(),
+        Document::Get => get(),
+        Document::List => list(),
+        Document::Verify => verify(),
+    }
+}
+
+fn id(name: &str) -> String {
+    format!(
+        "https://github.com/BondMachineHQ/bitcache/schemas/v{}/{}.json",
+        SCHEMA_VERSION, name
+    )
+}
+
+fn metadata() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": id("metadata"),
+        "title": "bitcache metadata file",
+        "description": "Maps source MD5 hashes to the bitstreams published for them. Members not listed here are preserved by bitcache when it rewrites the file.",
+        "type": "object",
+        "required": ["entries"],
+        "properties": {
+            "schema_version": {
+                "description": "Version of the metadata schema; absent in files written before it existed, which are version 1",
+                "type": "integer",
+                "minimum": 1,
+                "default": 1
+            },
+            "entries": {
+                "description": "Entries keyed by the MD5 in their md5 field, as a list of the variants published for it with different names. Versions before 3 wrote a source's only entry as a single object, which is still read",
+                "type": "object",
+                "additionalProperties": {
+                    "oneOf": [
+                        { "type": "array", "items": { "$ref": "#/$defs/entry" }, "minItems": 1 },
+                        { "$ref": "#/$defs/entry" }
+                    ]
+                }
+            },
+            "trash": {
+                "description": "Entries delete moved to the trash, which get, list and the other commands ignore until trash restore puts them back",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["entry", "trashed_at"],
+                    "properties": {
+                        "entry": { "$ref": "#/$defs/entry" },
+                        "trashed_at": {
+                            "description": "When the entry was deleted, in RFC 3339",
+                            "type": "string",
+                            "format": "date-time"
+                        },
+                        "trash_path": {
+                            "description": "Where the bitstream was moved to, under .bitcache_trash/; absent when it stayed at the entry's binary_path because another entry uses it",
+                            "type": "string"
+                        }
+                    }
+                }
+            }
+        },
+        "$defs": { "entry": entry() }
+    })
+}
+
+/// A metadata entry, as in the metadata file and the `--json` output
+fn entry() -> Value {
+    json!({
+        "type": "object",
+        "required": ["md5", "binary_path", "source_file", "timestamp"],
+        "properties": {
+            "md5": {
+                "description": "Hash of the source file, computed with hash_algo",
+                "type": "string"
+            },
+            "hash_algo": {
+                "description": "Algorithm the md5 field was computed with: md5, sha256 or sha512",
+                "type": "string",
+                "default": "md5"
+            },
+            "binary_path": {
+                "description": "Relative path of the bitstream in the repository, with / separators and % encoded names",
+                "type": "string"
+            },
+            "source_file": {
+                "description": "File name of the source the hash was computed from; the names given, separated by ', ', for a source of several files or a directory",
+                "type": "string"
+            },
+            "source_files": {
+                "description": "Paths of every file of a source of several files or a directory, as they were hashed; absent for a source of one file",
+                "type": "array",
+                "items": { "type": "string" }
+            },
+            "timestamp": {
+                "description": "Time of publication",
+                "type": "string",
+                "format": "date-time"
+            },
+            "deprecated": {
+                "description": "Whether the bitstream was found faulty; get warns about it, or refuses it with --refuse-deprecated",
+                "type": "boolean",
+                "default": false
+            },
+            "deprecation_reason": {
+                "description": "Why the bitstream was deprecated",
+                "type": "string"
+            },
+            "replacement": {
+                "description": "Hash of the source whose entry to use instead of this deprecated one",
+                "type": "string"
+            },
+            "binary_md5": {
+                "description": "MD5 of the bitstream as published, checked by bitcache verify; absent for entries published by older versions",
+                "type": "string"
+            },
+            "branch": {
+                "description": "Branch the entry was published to; absent for entries published by older versions",
+                "type": "string"
+            },
+            "compression": {
+                "description": "How the bitstream is stored compressed, with .gz or .zst appended to binary_path; binary_md5 is then of the compressed file. Absent for a bitstream stored as it is. Versions before 4 wrote a compressed flag meaning zstd instead, which is still read",
+                "enum": ["none", "gzip", "zstd"]
+            },
+            "original_size": {
+                "description": "Size in bytes of a compressed bitstream once decompressed",
+                "type": "integer",
+                "minimum": 0
+            },
+            "size": {
+                "description": "Size in bytes of the bitstream as stored in the repository, compressed if it is; absent for entries published by older versions",
+                "type": "integer",
+                "minimum": 0
+            },
+            "file_mode": {
+                "description": "Unix permission bits of the bitstream when it was published, given to the copy get saves; absent for bitstreams published on other platforms or by older versions",
+                "type": "integer",
+                "minimum": 0
+            },
+            "file_name": {
+                "description": "Name get saves the bitstream under, for one publish --on-collision rename stored under a prefixed name",
+                "type": "string"
+            },
+            "os": {
+                "description": "Operating system the bitstream was built for, that of the publishing machine unless publish was given --os",
+                "type": "string"
+            },
+            "arch": {
+                "description": "Architecture the bitstream was built for, that of the publishing machine unless publish was given --arch",
+                "type": "string"
+            },
+            "build_tool_version": {
+                "description": "Version of the tool that built the bitstream, as given to publish --build-tool-version",
+                "type": "string"
+            },
+            "published_by": {
+                "description": "Who published the bitstream, as given to publish --author or else as Name <email> from the publisher's git configuration",
+                "type": "string"
+            },
+            "tool_version": {
+                "description": "Version of bitcache that published the bitstream",
+                "type": "string"
+            },
+            "source_commit": {
+                "description": "Commit of the git work tree the source file was in, or as given to publish --source-commit",
+                "type": "string"
+            },
+            "variant": {
+                "description": "Name telling the bitstream apart from others published for the same source, given with publish --variant; absent when it is the file name of binary_path without .gz or .zst",
+                "type": "string"
+            },
+            "tags": {
+                "description": "User-defined tags given to publish with --tag; a list of key=value strings is also read",
+                "oneOf": [
+                    { "type": "object", "additionalProperties": { "type": "string" } },
+                    { "type": "array", "items": { "type": "string" } }
+                ]
+            }
+        }
+    })
+}
+
+fn plan() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": id("plan"),
+        "title": "bitcache publish plan",
+        "description": "What a publish will do, as printed by publish --explain",
+        "type": "object",
+        "required": [
+            "repo", "branch", "hash_algo", "hash", "name", "entry_exists", "action",
+            "unchanged", "binary_path", "upload_bytes", "commit_message", "policies"
+        ],
+        "properties": {
+            "repo": { "description": "Repository URL", "type": "string" },
+            "branch": {
+                "description": "Branch the commit will be pushed to, null when the clone is not on a branch",
+                "type": ["string", "null"]
+            },
+            "hash_algo": {
+                "description": "Hash algorithm applied to the source file: md5, sha256 or sha512",
+                "type": "string"
+            },
+            "hash": { "description": "Hash of the source file", "type": "string" },
+            "name": {
+                "description": "Name of the entry among those published for the hash",
+                "type": "string"
+            },
+            "entry_exists": {
+                "description": "Whether an entry for the hash and name already exists",
+                "type": "boolean"
+            },
+            "action": {
+                "description": "Effect on the metadata entry; unchanged when nothing will be committed",
+                "enum": ["create", "overwrite", "unchanged"]
+            },
+            "unchanged": {
+                "description": "Whether the existing entry already stores this exact bitstream at the same path",
+                "type": "boolean"
+            },
+            "binary_path": {
+                "description": "Destination of the bitstream relative to the repository root",
+                "type": "string"
+            },
+            "upload_bytes": {
+                "description": "Bytes that will be added to the repository",
+                "type": "integer",
+                "minimum": 0
+            },
+            "commit_message": {
+                "description": "Message of the commit that will be pushed",
+                "type": "string"
+            },
+            "policies": {
+                "description": "Repository policies that apply to the entry",
+                "type": "array",
+                "items": { "type": "string" }
+            }
+        }
+    })
+}
+
+fn manifest() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": id("manifest"),
+        "title": "bitcache batch manifest",
+        "description": "The bitstreams publish-batch publishes in one commit; the TOML form has the same shape, with one [[entry]] table per bitstream",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "entry": {
+                "description": "The bitstreams to publish, in order",
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "required": ["source", "bitstream"],
+                    "properties": {
+                        "source": {
+                            "description": "Source file whose hash identifies the bitstream, relative to the manifest's directory",
+                            "type": "string"
+                        },
+                        "bitstream": {
+                            "description": "Bitstream to store, relative to the manifest's directory",
+                            "type": "string"
+                        },
+                        "path": {
+                            "description": "Target directory in the repository, defaulting to the --path given for the batch",
+                            "type": "string"
+                        },
+                        "rename_in_repo": {
+                            "description": "File name to store the bitstream under, instead of its local name",
+                            "type": "string"
+                        },
+                        "variant": {
+                            "description": "Variant to publish the bitstream as, defaulting to the --variant given for the batch",
+                            "type": "string"
+                        },
+                        "tags": {
+                            "description": "Tags to record in the entry, added to those given with --tag",
+                            "type": "object",
+                            "additionalProperties": { "type": "string" }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn get() -> Value {
+    let mut retrieved = entry();
+    retrieved["$schema"] = json!("https://json-schema.org/draft/2020-12/schema");
+    retrieved["$id"] = json!(id("get"));
+    retrieved["title"] = json!("bitcache get output");
+    retrieved["description"] =
+        json!("The metadata entry of the retrieved bitstream, with where get saved it");
+    let fields = &mut retrieved["properties"];
+    fields["path"] = json!({ "description": "Where the bitstream was saved", "type": "string" });
+    fields["size"] = json!({
+        "description": "Size in bytes of the bitstream as saved, decompressed if it was stored compressed",
+        "type": "integer",
+        "minimum": 0
+    });
+    fields["from_cache"] = json!({
+        "description": "Whether it came from the local artifact cache instead of the repository",
+        "type": "boolean"
+    });
+    fields["synced"] = json!({
+        "description": "Whether get --sync flushed it to disk",
+        "type": "boolean"
+    });
+    retrieved["required"]
+        .as_array_mut()
+        .expect("entry required")
+        .extend(["path", "size", "from_cache", "synced"].map(Value::from));
+    retrieved
+}
+
+fn list() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": id("list"),
+        "title": "bitcache list output",
+        "description": "The entries list --json prints, sorted by source hash and name",
+        "type": "array",
+        "items": { "$ref": "#/$defs/entry" },
+        "$defs": { "entry": entry() }
+    })
+}
+
+fn verify() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": id("verify"),
+        "title": "bitcache verify report",
+        "description": "What verify --json prints; verify exits 1 when failures were left unfixed or dangling is not empty",
+        "type": "object",
+        "required": ["checked", "unverifiable", "failures", "dangling", "fixed"],
+        "properties": {
+            "checked": {
+                "description": "Number of entries checked",
+                "type": "integer",
+                "minimum": 0
+            },
+            "unverifiable": {
+                "description": "Entries without a recorded binary_md5, so only checked to exist",
+                "type": "integer",
+                "minimum": 0
+            },
+            "failures": {
+                "description": "Entries that failed, each with its problem",
+                "type": "array",
+                "items": failure()
+            },
+            "dangling": {
+                "description": "Deprecated entries whose replacement has no entry",
+                "type": "array",
+                "items": { "$ref": "#/$defs/entry" }
+            },
+            "fixed": {
+                "description": "Whether the failed entries were removed from the repository",
+                "type": "boolean"
+            }
+        },
+        "$defs": { "entry": entry() }
+    })
+}
+
+/// An entry of a verify report's failures: the entry, with its problem
+fn failure() -> Value {
+    let mut failure = entry();
+    failure["properties"]["problem"] = json!({
+        "oneOf": [
+            {
+                "description": "The bitstream is not in the repository",
+                "type": "object",
+                "required": ["kind"],
+                "properties": { "kind": { "const": "missing" } }
+            },
+            {
+                "description": "The bitstream hashes to something else than the binary_md5 recorded when it was published",
+                "type": "object",
+                "required": ["kind", "expected", "actual"],
+                "properties": {
+                    "kind": { "const": "mismatch" },
+                    "expected": { "type": "string" },
+                    "actual": { "type": "string" }
+                }
+            }
+        ]
+    });
+    failure["required"]
+        .as_array_mut()
+        .expect("entry required")
+        .push(json!("problem"));
+    failure
+}
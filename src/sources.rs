@@ -0,0 +1,245 @@
+//! Sources made of several files.
+
+use crate::heartbeat::Heartbeat;
+use crate::{hash, hash_contents, paths, progress, HashAlgo};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Which files in a source directory are hashed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceWalk {
+    /// Hash files and directories whose names start with `.`, such as
+    /// `.git`, instead of skipping them
+    pub include_hidden: bool,
+    /// Follow symlinks in a source directory instead of skipping them
+    pub include_symlinks: bool,
+}
+
+/// The files a source is hashed from
+#[derive(Debug, Clone)]
+pub(crate) enum Sources {
+    /// One plain file, hashed by itself
+    File(PathBuf),
+    /// Several files, or those of a directory, by the name they are hashed
+    /// under
+    Combined(BTreeMap<String, PathBuf>),
+}
+
+impl Sources {
+    /// Find the files of the source given as `paths`
+    ///
+    /// `flag` names the paths in errors, e.g. "--source". Symlinks among
+    /// `paths` themselves are followed.
+    pub(crate) fn collect(paths: &[&Path], walk: SourceWalk, flag: &str) -> io::Result<Self> {
+        if let [path] = paths {
+            if !fs::metadata(path)?.is_dir() {
+                return Ok(Sources::File(path.to_path_buf()));
+            }
+        }
+        let mut files = BTreeMap::new();
+        for path in paths {
+            if fs::metadata(path)?.is_dir() {
+                let mut ancestors = vec![fs::canonicalize(path)?];
+                walk_dir(path, "", walk, flag, &mut ancestors, &mut files)?;
+            } else {
+                let name = path.file_name().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} {} is not a file name", flag, path.display()),
+                    )
+                })?;
+                add(&mut files, encode(name)?, path.to_path_buf(), flag)?;
+            }
+        }
+        if files.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} {} holds no files to hash", flag, list(paths)),
+            ));
+        }
+        Ok(Sources::Combined(files))
+    }
+
+    /// Total size of the files in bytes
+    pub(crate) fn len(&self) -> io::Result<u64> {
+        match self {
+            Sources::File(path) => Ok(fs::metadata(path)?.len()),
+            Sources::Combined(files) => files
+                .values()
+                .map(|path| fs::metadata(path).map(|meta| meta.len()))
+                .sum(),
+        }
+    }
+
+    /// The names the files are hashed under, sorted; empty for a single
+    /// plain file, which is hashed without a name
+    pub(crate) fn names(&self) -> Vec<String> {
+        match self {
+            Sources::File(_) => Vec::new(),
+            Sources::Combined(files) => files.keys().cloned().collect(),
+        }
+    }
+
+    /// The directory to look up the author and commit in: the one holding
+    /// the first file
+    pub(crate) fn dir(&self) -> &Path {
+        let first = match self {
+            Sources::File(path) => path,
+            Sources::Combined(files) => files.values().next().expect("sources are never empty"),
+        };
+        match first.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Hash the files with `algo`, as lowercase hex
+    pub(crate) fn hash(&self, algo: HashAlgo) -> io::Result<String> {
+        let files = match self {
+            Sources::File(path) => return crate::compute_hash(path, algo),
+            Sources::Combined(files) => files,
+        };
+        let total = self.len()?;
+        let mut hasher = hash::Hasher::new(algo);
+        let mut hashed = 0u64;
+        let mut heartbeat = Heartbeat::start(progress::Phase::Hashing);
+        for (name, path) in files {
+            let size = fs::metadata(path)?.len();
+            hasher.update(name.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(size.to_string().as_bytes());
+            hasher.update(b"\0");
+            hash_contents(path, &mut hasher, &mut hashed, total, &mut heartbeat).map_err(|e| {
+                io::Error::new(e.kind(), format!("Cannot hash {}: {}", path.display(), e))
+            })?;
+        }
+        Ok(hasher.finish())
+    }
+}
+
+/// Compute the hash of a source given as several files or directories with
+/// `algo`, as lowercase hex
+///
+/// Each file is fed to the hash under its name: the file name for a file
+/// given directly, the path relative to the directory for a file found in
+/// one, with `/` separators and names encoded as in the repository. The
+/// files go in sorted by that name, each as the name, a NUL byte, its size
+/// in decimal digits, a NUL byte and its contents, so the hash is the same
+/// on every machine and no two different sets of files run together into
+/// the same bytes. Files `walk` leaves out are skipped, and two files with
+/// the same name are refused.
+///
+/// A single plain file is hashed by itself, giving the same hash as
+/// [`crate::compute_hash`], so entries published from one source file keep
+/// their hash.
+///
+/// ```no_run
+/// use bitcache::{HashAlgo, SourceWalk};
+/// use std::path::PathBuf;
+///
+/// let sources = [PathBuf::from("rtl"), PathBuf::from("constraints/top.xdc")];
+/// let hash = bitcache::compute_source_hash(&sources, SourceWalk::default(), HashAlgo::Md5)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn compute_source_hash(
+    paths: &[PathBuf],
+    walk: SourceWalk,
+    algo: HashAlgo,
+) -> io::Result<String> {
+    let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    Sources::collect(&paths, walk, "--source")?.hash(algo)
+}
+
+/// Add the files under `dir` to `files`, named with `prefix` in front of
+/// their path relative to it
+fn walk_dir(
+    dir: &Path,
+    prefix: &str,
+    walk: SourceWalk,
+    flag: &str,
+    ancestors: &mut Vec<PathBuf>,
+    files: &mut BTreeMap<String, PathBuf>,
+) -> io::Result<()> {
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        let file_name = item.file_name();
+        if !walk.include_hidden && file_name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = item.path();
+        let name = format!("{}{}", prefix, encode(&file_name)?);
+        let mut file_type = item.file_type()?;
+        if file_type.is_symlink() {
+            if !walk.include_symlinks {
+                continue;
+            }
+            match fs::metadata(&path) {
+                Ok(target) => file_type = target.file_type(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{} is a dangling symlink", path.display()),
+                    ));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if file_type.is_dir() {
+            // A symlink back up the tree would be walked forever
+            let resolved = fs::canonicalize(&path)?;
+            if ancestors.contains(&resolved) {
+                continue;
+            }
+            ancestors.push(resolved);
+            walk_dir(&path, &format!("{}/", name), walk, flag, ancestors, files)?;
+            ancestors.pop();
+        } else if file_type.is_file() {
+            add(files, name, path, flag)?;
+        }
+    }
+    Ok(())
+}
+
+/// Add a file under `name`, refusing a name two files would share
+fn add(
+    files: &mut BTreeMap<String, PathBuf>,
+    name: String,
+    path: PathBuf,
+    flag: &str,
+) -> io::Result<()> {
+    if let Some(other) = files.get(&name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} {} and {} would both be hashed as {}; give the directory holding both instead",
+                flag,
+                other.display(),
+                path.display(),
+                name
+            ),
+        ));
+    }
+    files.insert(name, path);
+    Ok(())
+}
+
+/// A file name as it is hashed and recorded
+fn encode(name: &std::ffi::OsStr) -> io::Result<String> {
+    paths::encode_name(name).map_err(|reason| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Cannot hash source file name: {}", reason),
+        )
+    })
+}
+
+/// `paths` for messages
+pub(crate) fn list(paths: &[&Path]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,478 @@
+//! Publishing a bitstream and recording its entry as separate steps.
+//!
+//! [`upload`] stores a bitstream in a repository and [`register`] records an
+//! entry for it in the metadata, each in a commit of its own, so the two can
+//! run in different jobs with different permissions. [`crate::publish`] does
+//! both in one commit. Either step run again after it succeeded changes
+//! nothing, so a failed job can simply be retried.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::BitcacheError;
+use crate::git::{self, PushOutcome};
+use crate::layout::MetadataFile;
+use crate::metadata::is_variant_name;
+use crate::progress::{self, status, warning, Event};
+use crate::publish::{
+    add_subject, check_case_collision, check_metadata_clash, check_tags, checked_branch,
+    push_backoff, resolve_input, single_file_name, TOOL_VERSION,
+};
+use crate::storage;
+use crate::trash;
+use crate::{
+    cancel, compute_md5, fsutil, paths, verify_written, Context, HashAlgo, MetadataEntry, Remote,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// What [`upload`] stores and where
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Bitstream to store
+    pub bitstream: PathBuf,
+    /// Directory of the repository to store it in
+    pub path: PathBuf,
+    /// Name to store it under instead of its own
+    pub rename_in_repo: Option<PathBuf>,
+    /// Follow a `bitstream` that is a symlink instead of refusing it
+    pub follow_symlinks: bool,
+}
+
+impl UploadOptions {
+    /// Store `bitstream` in the directory `path` of the repository
+    pub fn new(bitstream: impl Into<PathBuf>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            bitstream: bitstream.into(),
+            path: path.into(),
+            rename_in_repo: None,
+            follow_symlinks: true,
+        }
+    }
+}
+
+/// A bitstream [`upload`] stored
+#[derive(Debug, Clone, Serialize)]
+pub struct Uploaded {
+    /// Where it is in the repository, as [`register`] takes it
+    pub binary_path: String,
+    /// MD5 of the bitstream
+    pub binary_md5: String,
+    /// Its size in bytes
+    pub size: u64,
+    /// Whether the repository already held it there, so nothing was
+    /// committed
+    pub unchanged: bool,
+}
+
+/// Store a bitstream in a repository without recording an entry for it
+///
+/// The bitstream is committed on its own at `opts.path`, under its file
+/// name. A path that already holds the same bitstream is left alone, and
+/// one that holds another bitstream fails with
+/// [`BitcacheError::PathTaken`]. Follow up with [`register`] to make
+/// [`crate::get`] find it.
+///
+/// ```no_run
+/// use bitcache::{Context, Remote, UploadOptions};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = UploadOptions::new("build/top.bit", "boards/zedboard");
+/// let uploaded = bitcache::upload(&remote, &opts, &Context::default())?;
+/// println!("{}", uploaded.binary_path);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn upload(remote: &Remote, opts: &UploadOptions, ctx: &Context) -> io::Result<Uploaded> {
+    upload_in(None, remote, opts, ctx)
+}
+
+/// [`upload`], in the clone kept by `pool` if given
+pub(crate) fn upload_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &UploadOptions,
+    ctx: &Context,
+) -> io::Result<Uploaded> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    paths::check_relative(&opts.path.to_string_lossy()).map_err(|reason| {
+        BitcacheError::InvalidArgument {
+            flag: "--path",
+            value: opts.path.display().to_string(),
+            reason: reason.to_string(),
+        }
+    })?;
+    let bitstream = resolve_input(&opts.bitstream, "--bitstream", opts.follow_symlinks)?;
+    let size = fs::metadata(&bitstream)?.len();
+    if size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--bitstream {} is empty", opts.bitstream.display()),
+        ));
+    }
+    let file_name = match &opts.rename_in_repo {
+        Some(name) => single_file_name(name).ok_or_else(|| BitcacheError::InvalidArgument {
+            flag: "--rename-in-repo",
+            value: name.display().to_string(),
+            reason: "expected a file name without directories".to_string(),
+        })?,
+        None => opts
+            .bitstream
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid bitstream path"))?,
+    };
+    let binary_path = repo_path(&opts.path.join(file_name))?;
+    status!("Computing MD5 of bitstream: {}", opts.bitstream.display());
+    let binary_md5 = compute_md5(&bitstream)?;
+    let mut uploaded = Uploaded {
+        binary_path,
+        binary_md5,
+        size,
+        unchanged: false,
+    };
+
+    let checkout = checkout::checkout_to_publish(pool, remote, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    checked_branch(repo_dir, remote)?;
+    let dest = paths::long_path(&repo_dir.join(&uploaded.binary_path));
+    let message = format!("Upload bitstream {}", uploaded.binary_path);
+
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            retry(repo_dir, remote, attempt, attempts, &rejection)?;
+        }
+        if dest.is_file() {
+            if compute_md5(&dest)? == uploaded.binary_md5 {
+                status!("{} already holds this bitstream", uploaded.binary_path);
+                uploaded.unchanged = true;
+                return Ok(uploaded);
+            }
+            return Err(BitcacheError::PathTaken {
+                path: uploaded.binary_path.clone(),
+                reason: "it already holds another bitstream. Choose a different --path, or store this one under another name with --rename-in-repo <NAME>".to_string(),
+            }
+            .into());
+        }
+        let tracked = storage::of(remote).files(repo_dir)?;
+        check_metadata_clash(&uploaded.binary_path, remote)?;
+        check_case_collision(&uploaded.binary_path, tracked.iter().map(String::as_str))?;
+
+        cancel::check()?;
+        status!("Copying bitstream to: {}", uploaded.binary_path);
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fsutil::copy_file(&bitstream, &dest)?;
+        if ctx.paranoid {
+            verify_written(&dest, &uploaded.binary_md5)?;
+        }
+        let mut written = vec![uploaded.binary_path.as_str()];
+        let marked = storage::of(remote).mark_binary(repo_dir, &uploaded.binary_path)?;
+        written.extend(marked);
+
+        status!("Committing and pushing changes...");
+        if !storage::of(remote).commit(repo_dir, &written, &message)? {
+            uploaded.unchanged = true;
+            return Ok(uploaded);
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => return Ok(uploaded),
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+    unreachable!("the last attempt returns")
+}
+
+/// The entry [`register`] records
+#[derive(Debug, Clone)]
+pub struct RegisterOptions {
+    /// Hash of the source file, the key of the entry
+    pub md5: String,
+    /// Algorithm `md5` was computed with
+    pub hash_algo: HashAlgo,
+    /// Name of the source file to record
+    pub source_name: String,
+    /// Where the bitstream is in the repository, e.g. [`Uploaded::binary_path`]
+    pub binary_path: String,
+    /// MD5 of the bitstream; taken from the file when the clone has it
+    pub binary_md5: Option<String>,
+    /// Size of the bitstream in bytes; taken from the file when the clone
+    /// has it
+    pub size: Option<u64>,
+    /// Variant to record the bitstream as, see [`crate::PublishOptions::variant`]
+    pub variant: Option<String>,
+    /// Tags to record
+    pub tags: BTreeMap<String, String>,
+    /// Operating system to record the bitstream as built for
+    pub os: Option<String>,
+    /// Architecture to record the bitstream as built for
+    pub arch: Option<String>,
+    /// Version of the tool that built the bitstream, to record in the entry
+    pub build_tool_version: Option<String>,
+    /// Who to record as publishing it [default: the git user of the current
+    /// directory]
+    pub author: Option<String>,
+}
+
+impl RegisterOptions {
+    /// Record `binary_path` as the bitstream built from `source_name`, whose
+    /// MD5 is `md5`, on this machine's operating system and architecture as
+    /// [`crate::PublishOptions::new`] does
+    pub fn new(
+        md5: impl Into<String>,
+        source_name: impl Into<String>,
+        binary_path: impl Into<String>,
+    ) -> Self {
+        Self {
+            md5: md5.into(),
+            hash_algo: HashAlgo::default(),
+            source_name: source_name.into(),
+            binary_path: binary_path.into(),
+            binary_md5: None,
+            size: None,
+            variant: None,
+            tags: BTreeMap::new(),
+            os: Some(std::env::consts::OS.to_string()),
+            arch: Some(std::env::consts::ARCH.to_string()),
+            build_tool_version: None,
+            author: None,
+        }
+    }
+}
+
+/// An entry [`register`] recorded
+#[derive(Debug, Clone, Serialize)]
+pub struct Registered {
+    /// The entry as it is in the metadata
+    #[serde(flatten)]
+    pub entry: MetadataEntry,
+    /// Whether the metadata already had it, so nothing was committed
+    pub unchanged: bool,
+}
+
+/// Record an entry for a bitstream in the metadata, without storing the
+/// bitstream
+///
+/// Only the metadata is committed. An entry the hash already has for the same
+/// path, and the same bitstream MD5 if one is given, is left alone; one for
+/// another bitstream fails, as it is [`crate::update`] that replaces one.
+/// [`crate::get`] looks for the bitstream in the same branch as the entry,
+/// so a warning says when the clone doesn't have it.
+///
+/// ```no_run
+/// use bitcache::{Context, RegisterOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = RegisterOptions::new(
+///     "d3699e851d7f4fde53ee37c037408af7",
+///     "top.vhd",
+///     "boards/zedboard/top.bit",
+/// );
+/// bitcache::register(&remote, &opts, &Context::default())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn register(remote: &Remote, opts: &RegisterOptions, ctx: &Context) -> io::Result<Registered> {
+    register_in(None, remote, opts, ctx)
+}
+
+/// [`register`], in the clone kept by `pool` if given
+pub(crate) fn register_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &RegisterOptions,
+    ctx: &Context,
+) -> io::Result<Registered> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let binary_path = repo_path(Path::new(&opts.binary_path))?;
+    check_metadata_clash(&binary_path, remote)?;
+    check_tags(&opts.tags)?;
+    if let Some(variant) = opts
+        .variant
+        .as_deref()
+        .filter(|name| !is_variant_name(name))
+    {
+        return Err(BitcacheError::InvalidArgument {
+            flag: "--variant",
+            value: variant.to_string(),
+            reason: "expected letters, digits, '.', '_' and '-', not starting with '.' or '-'"
+                .to_string(),
+        }
+        .into());
+    }
+    let published_by = match &opts.author {
+        Some(author) => Some(author.clone()),
+        None => git::user_identity(Path::new("."))?,
+    };
+    status!(
+        "Registering entry for {}: {}",
+        opts.hash_algo.label(),
+        opts.md5
+    );
+
+    let checkout = checkout::checkout_to_publish(pool, remote, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    let mut entry = MetadataEntry::new(
+        &opts.md5,
+        &binary_path,
+        &opts.source_name,
+        chrono::Utc::now().to_rfc3339(),
+    );
+    entry.hash_algo = opts.hash_algo.name().to_string();
+    entry.binary_md5 = opts.binary_md5.clone();
+    entry.size = opts.size;
+    entry.branch = checked_branch(repo_dir, remote)?;
+    entry.variant = opts
+        .variant
+        .clone()
+        .filter(|variant| variant != entry.name());
+    entry.tags = opts.tags.clone();
+    entry.os = opts.os.clone();
+    entry.arch = opts.arch.clone();
+    entry.build_tool_version = opts.build_tool_version.clone();
+    entry.published_by = published_by;
+    entry.tool_version = Some(TOOL_VERSION.to_string());
+    let message = add_subject(opts.hash_algo, &opts.md5);
+
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            retry(repo_dir, remote, attempt, attempts, &rejection)?;
+        }
+        let stored = paths::long_path(&repo_dir.join(&binary_path));
+        let present = stored.is_file();
+        if present {
+            let digest = compute_md5(&stored)?;
+            if entry.binary_md5.as_ref().is_some_and(|md5| *md5 != digest) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} holds a bitstream with MD5 {}, not the {} given",
+                        binary_path,
+                        digest,
+                        entry.binary_md5.as_deref().unwrap_or_default()
+                    ),
+                ));
+            }
+            entry.binary_md5 = Some(digest);
+            entry.size = Some(fs::metadata(&stored)?.len());
+        }
+
+        let mut metadata = metadata_file.load_or_new()?;
+        if let Some(existing) = metadata.lookup(&entry.md5, entry.name()) {
+            let same = existing.binary_path == entry.binary_path
+                && (entry.binary_md5.is_none()
+                    || existing.binary_md5.is_none()
+                    || existing.binary_md5 == entry.binary_md5);
+            if !same {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "{} {} already has an entry for {}; run update to replace its bitstream, or delete it first",
+                        opts.hash_algo.label(),
+                        entry.md5,
+                        existing.binary_path
+                    ),
+                ));
+            }
+            status!("The entry is already registered");
+            return Ok(Registered {
+                entry: existing.clone(),
+                unchanged: true,
+            });
+        }
+        if let Some(owner) = metadata.iter().find(|other| {
+            other.binary_path == entry.binary_path
+                && other.binary_md5.is_some()
+                && entry.binary_md5.is_some()
+                && other.binary_md5 != entry.binary_md5
+        }) {
+            return Err(BitcacheError::PathTaken {
+                path: entry.binary_path.clone(),
+                reason: format!(
+                    "the entry for MD5 {} records another bitstream there",
+                    owner.label()
+                ),
+            }
+            .into());
+        }
+        if !present {
+            warning!(
+                "{} is not in this branch; get fails on the entry until it is",
+                binary_path
+            );
+        }
+        metadata.insert_entry(entry.clone());
+        let mut written = Vec::new();
+        trash::expire(repo_dir, &mut metadata, ctx, &mut written)?;
+
+        cancel::check()?;
+        status!("Updating metadata...");
+        written.extend(metadata_file.save(&metadata, ctx.paranoid)?);
+        let written: Vec<&str> = written.iter().map(String::as_str).collect();
+        status!("Committing and pushing changes...");
+        if !storage::of(remote).commit(repo_dir, &written, &message)? {
+            status!("No changes to commit");
+            break;
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => break,
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+    Ok(Registered {
+        entry,
+        unchanged: false,
+    })
+}
+
+/// The repository path of `path`, refusing one a publish would refuse
+fn repo_path(path: &Path) -> io::Result<String> {
+    paths::to_repo_path(path)
+        .and_then(|repo_path| paths::check_relative(&repo_path).map(|_| repo_path))
+        .and_then(|repo_path| paths::check_portable(&repo_path).map(|_| repo_path))
+        .and_then(|repo_path| paths::check_reserved(&repo_path).map(|_| repo_path))
+        .map_err(|reason| {
+            BitcacheError::InvalidPath {
+                path: path.display().to_string(),
+                reason: reason.to_string(),
+            }
+            .into()
+        })
+}
+
+/// Wait out a rejected push and reset the clone to the new remote head
+fn retry(
+    repo_dir: &Path,
+    remote: &Remote,
+    attempt: u32,
+    attempts: u32,
+    rejection: &str,
+) -> io::Result<()> {
+    thread::sleep(push_backoff(attempt));
+    progress::emit(Event::Retry {
+        attempt,
+        attempts,
+        reason: rejection,
+    });
+    status!(
+        "Push rejected, merging with the new remote head (attempt {} of {})",
+        attempt,
+        attempts
+    );
+    storage::of(remote).reset(repo_dir, remote.auth.as_ref())
+}
@@ -0,0 +1,174 @@
+//! Where a repository's files live.
+//!
+//! Every operation works in a directory holding the metadata file and the
+//! bitstreams: a clone of a git remote, or with [`Storage::Local`] a plain
+//! directory, used in place. Reading and writing the metadata and the
+//! bitstreams is the same for both; what differs, checking the location and
+//! recording and sharing changes, is behind [`StorageBackend`]. The
+//! directory itself is handed out by [`crate::checkout`].
+
+use crate::error::BitcacheError;
+use crate::git::{self, PushOutcome};
+use crate::{gc, layout, Auth, Remote};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// File in a local repository that every change is appended to, in place
+/// of git history
+pub const LOG_FILE: &str = ".bitcache.log";
+
+/// File in a local repository that runs lock while they use it
+pub const LOCK_FILE: &str = ".bitcache.lock";
+
+/// How a [`Remote`] stores its files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Storage {
+    /// A git repository, cloned for each operation
+    #[default]
+    Git,
+    /// A directory on a local or network file system, read and written in
+    /// place without git; [`Remote::url`] is its path
+    Local,
+}
+
+/// The steps that differ between kinds of [`Storage`]
+pub(crate) trait StorageBackend: Sync {
+    /// Check the location and credentials of `remote` before any I/O on it
+    fn check(&self, remote: &Remote) -> io::Result<()>;
+
+    /// Branch the directory is on, `None` for a detached clone or a local
+    /// directory
+    fn branch(&self, dir: &Path) -> io::Result<Option<String>>;
+
+    /// Every file in the repository, with `/` separators
+    fn files(&self, dir: &Path) -> io::Result<Vec<String>>;
+
+    /// Make sure the bitstream at `binary_path` is stored byte for byte,
+    /// returning the file changed for it, if any
+    fn mark_binary(&self, dir: &Path, binary_path: &str) -> io::Result<Option<&'static str>>;
+
+    /// Record the changes to `files`; `false` when there were none
+    fn commit(&self, dir: &Path, files: &[&str], message: &str) -> io::Result<bool>;
+
+    /// Share the recorded changes
+    fn push(&self, dir: &Path, auth: Option<&Auth>) -> io::Result<PushOutcome>;
+
+    /// Drop the directory's changes after a rejected push
+    fn reset(&self, dir: &Path, auth: Option<&Auth>) -> io::Result<()>;
+}
+
+/// The backend for `remote`
+pub(crate) fn of(remote: &Remote) -> &'static dyn StorageBackend {
+    match remote.storage {
+        Storage::Git => &GitBackend,
+        Storage::Local => &LocalBackend,
+    }
+}
+
+/// Check the location and credentials of `remote`, see
+/// [`StorageBackend::check`], and where it keeps its metadata
+pub(crate) fn check(remote: &Remote) -> io::Result<()> {
+    layout::check_metadata_path(&remote.metadata_path)?;
+    of(remote).check(remote)
+}
+
+struct GitBackend;
+
+impl StorageBackend for GitBackend {
+    fn check(&self, remote: &Remote) -> io::Result<()> {
+        git::check_remote(remote)
+    }
+
+    fn branch(&self, dir: &Path) -> io::Result<Option<String>> {
+        git::current_branch(dir)
+    }
+
+    fn files(&self, dir: &Path) -> io::Result<Vec<String>> {
+        git::tracked_files(dir)
+    }
+
+    fn mark_binary(&self, dir: &Path, binary_path: &str) -> io::Result<Option<&'static str>> {
+        Ok(git::ensure_binary_attributes(dir, binary_path)?.then_some(git::ATTRIBUTES_FILE))
+    }
+
+    fn commit(&self, dir: &Path, files: &[&str], message: &str) -> io::Result<bool> {
+        git::commit_changes(dir, files, message)
+    }
+
+    fn push(&self, dir: &Path, auth: Option<&Auth>) -> io::Result<PushOutcome> {
+        git::push(dir, auth)
+    }
+
+    fn reset(&self, dir: &Path, auth: Option<&Auth>) -> io::Result<()> {
+        git::reset_to_remote(dir, auth)
+    }
+}
+
+/// A plain directory. Runs take turns through [`LOCK_FILE`], so nothing
+/// moves under a run and pushes are never rejected.
+struct LocalBackend;
+
+impl StorageBackend for LocalBackend {
+    fn check(&self, remote: &Remote) -> io::Result<()> {
+        if let Some(branch) = &remote.branch {
+            return Err(BitcacheError::InvalidArgument {
+                flag: "--branch",
+                value: branch.clone(),
+                reason: "a local repository has no branches".to_string(),
+            }
+            .into());
+        }
+        match fs::metadata(&remote.url) {
+            Ok(meta) if meta.is_dir() => Ok(()),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Local repository {} is not a directory", remote.url),
+            )),
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("Local repository {}: {}", remote.url, e),
+            )),
+        }
+    }
+
+    fn branch(&self, _dir: &Path) -> io::Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn files(&self, dir: &Path) -> io::Result<Vec<String>> {
+        let mut files = Vec::new();
+        gc::walk(dir, "", &mut files)?;
+        Ok(files)
+    }
+
+    fn mark_binary(&self, _dir: &Path, _binary_path: &str) -> io::Result<Option<&'static str>> {
+        Ok(None)
+    }
+
+    fn commit(&self, dir: &Path, _files: &[&str], message: &str) -> io::Result<bool> {
+        let mut record = format!("{} ", chrono::Utc::now().to_rfc3339());
+        for line in message.lines().filter(|line| !line.is_empty()) {
+            if record.ends_with('\n') {
+                record.push_str("    ");
+            }
+            record.push_str(line);
+            record.push('\n');
+        }
+        let mut log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        log.write_all(record.as_bytes())?;
+        Ok(true)
+    }
+
+    fn push(&self, _dir: &Path, _auth: Option<&Auth>) -> io::Result<PushOutcome> {
+        Ok(PushOutcome::Pushed)
+    }
+
+    fn reset(&self, _dir: &Path, _auth: Option<&Auth>) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,489 @@
+//! Local artifact store.
+//!
+//! Bitstreams retrieved by `get` are kept under the cache directory so that
+//! the next `get` for the same MD5 is served without any network I/O:
+//!
+//! ```text
+//! <cache_dir>/artifacts/<repo digest>/<md5>/entry.json
+//! <cache_dir>/artifacts/<repo digest>/<md5>/blob
+//! <cache_dir>/artifacts/<repo digest>/<md5>@<name>/...
+//! ```
+//!
+//! A `get` without a name is cached under the MD5 alone, and one naming a
+//! bitstream among several published for the source under `<md5>@<name>`.
+//!
+//! The same directory holds the clone each repository's operations work in,
+//! see [`clone_dir`], with a lock file next to it that the operation using
+//! the clone holds:
+//!
+//! ```text
+//! <cache_dir>/clones/<repo digest>/
+//! <cache_dir>/clones/<repo digest>.lock
+//! ```
+//!
+//! Entries are namespaced by repository URL because two repositories may hold
+//! different binaries for the same source MD5. `entry.json` records the
+//! metadata entry together with the blob's size and MD5 digest and is written
+//! last, so a directory without it is an unfinished insert and never served.
+//! A blob that no longer matches its digest is evicted and refetched.
+//!
+//! `entry.json` also records the commit the repository was at when the
+//! entry was read. `get` serves a hit only while the remote's branch is
+//! still at that commit, which it asks the remote for without fetching, so
+//! an update, deprecation, deletion or new variant made there since, from
+//! whichever machine, is never hidden by the cache.
+
+use crate::fsutil;
+use crate::{compute_md5, config, metadata, MetadataEntry};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default upper bound for the total size of stored blobs
+pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+
+const ARTIFACTS_DIR: &str = "artifacts";
+const CLONES_DIR: &str = "clones";
+const ENTRY_FILE: &str = "entry.json";
+const BLOB_FILE: &str = "blob";
+
+/// Contents of `entry.json`
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredEntry {
+    /// The repository's metadata entry at the time of the fetch
+    entry: MetadataEntry,
+    /// Size of the blob in bytes
+    size: u64,
+    /// MD5 of the blob contents
+    digest: String,
+    /// Seconds since the Unix epoch of the last hit, for LRU eviction
+    last_used: u64,
+    /// Commit of the repository the entry was read at; an artifact stored
+    /// before this was recorded is never current
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit: Option<String>,
+}
+
+/// A verified artifact served from the store
+pub struct Artifact {
+    pub entry: MetadataEntry,
+    pub blob: PathBuf,
+    /// Commit of the repository the entry was read at, if known
+    pub commit: Option<String>,
+}
+
+/// Artifacts cached for one repository
+pub struct ArtifactStore {
+    dir: PathBuf,
+}
+
+impl ArtifactStore {
+    pub fn open(cache_dir: &Path, repo: &str) -> Self {
+        let repo_digest = format!("{:x}", md5::compute(repo.as_bytes()));
+        Self {
+            dir: cache_dir.join(ARTIFACTS_DIR).join(repo_digest),
+        }
+    }
+
+    /// Look up a verified artifact, evicting it if the blob is damaged
+    ///
+    /// `name` is the [`MetadataEntry::name`] the `get` asked for, if any.
+    pub fn fetch(&self, md5: &str, name: Option<&str>) -> io::Result<Option<Artifact>> {
+        let Some(key) = key(md5, name) else {
+            return Ok(None);
+        };
+        let dir = self.dir.join(key);
+        let entry_path = dir.join(ENTRY_FILE);
+        let content = match fs::read(&entry_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let blob = dir.join(BLOB_FILE);
+        let mut stored = match serde_json::from_slice::<StoredEntry>(&content) {
+            Ok(stored)
+                if stored.entry.md5 == md5
+                    && name.is_none_or(|name| stored.entry.name() == name)
+                    && blob_matches(&blob, &stored)? =>
+            {
+                stored
+            }
+            _ => {
+                fs::remove_dir_all(&dir)?;
+                return Ok(None);
+            }
+        };
+
+        // Recording the hit is best effort; a stale timestamp only affects
+        // which entry is evicted first
+        stored.last_used = now();
+        if let Ok(content) = serde_json::to_vec_pretty(&stored) {
+            let _ = fsutil::atomic_write(&entry_path, &content);
+        }
+
+        Ok(Some(Artifact {
+            entry: stored.entry,
+            blob,
+            commit: stored.commit,
+        }))
+    }
+
+    /// Drop every artifact for an MD5, whatever name it was fetched by
+    pub fn remove(&self, md5: &str) -> io::Result<()> {
+        if key(md5, None).is_none() {
+            return Ok(());
+        }
+        let named = format!("{}@", md5);
+        let dirs = match fs::read_dir(&self.dir) {
+            Ok(dirs) => dirs,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for dir in dirs {
+            let dir = dir?;
+            let file_name = dir.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_name != md5 && !file_name.starts_with(&named) {
+                continue;
+            }
+            match fs::remove_dir_all(dir.path()) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a fetched artifact, replacing any previous entry for its MD5 and
+    /// the `name` it was fetched by; `commit` is the commit of the
+    /// repository `entry` was read at
+    ///
+    /// Entries whose MD5 isn't a plain alphanumeric string are not stored.
+    pub fn insert(
+        &self,
+        entry: &MetadataEntry,
+        name: Option<&str>,
+        blob_src: &Path,
+        commit: Option<&str>,
+    ) -> io::Result<()> {
+        let Some(key) = key(&entry.md5, name) else {
+            return Ok(());
+        };
+        let dir = self.dir.join(key);
+        let result = Self::write_entry(&dir, entry, blob_src, commit);
+        // Never leave a blob without its entry.json: scans would skip it and
+        // it could not be evicted
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&dir);
+        }
+        result
+    }
+
+    fn write_entry(
+        dir: &Path,
+        entry: &MetadataEntry,
+        blob_src: &Path,
+        commit: Option<&str>,
+    ) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let entry_path = dir.join(ENTRY_FILE);
+        match fs::remove_file(&entry_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        let staged = tempfile::Builder::new()
+            .prefix(".blob.")
+            .suffix(".tmp")
+            .tempfile_in(dir)?
+            .into_temp_path();
+        let (size, _) = fsutil::clone_or_copy(blob_src, &staged)?;
+        let digest = compute_md5(&staged)?;
+        staged.persist(dir.join(BLOB_FILE)).map_err(|e| e.error)?;
+
+        let stored = StoredEntry {
+            entry: entry.clone(),
+            size,
+            digest,
+            last_used: now(),
+            commit: commit.map(str::to_string),
+        };
+        fsutil::atomic_write(&entry_path, &serde_json::to_vec_pretty(&stored)?)
+    }
+}
+
+/// Evict least recently used artifacts until the store fits in `max_size`
+/// bytes; 0 means no limit
+pub fn enforce_limit(cache_dir: &Path, max_size: u64) -> io::Result<()> {
+    if max_size == 0 {
+        return Ok(());
+    }
+
+    let mut entries = scan(cache_dir)?;
+    let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+    entries.sort_by_key(|entry| entry.last_used);
+    for entry in entries {
+        if total <= max_size {
+            break;
+        }
+        fs::remove_dir_all(&entry.dir)?;
+        total -= entry.size;
+    }
+    Ok(())
+}
+
+/// Remove every stored artifact, returning the number of entries and bytes
+/// freed
+pub fn clean(cache_dir: &Path) -> io::Result<(usize, u64)> {
+    let entries = scan(cache_dir)?;
+    let freed = (entries.len(), entries.iter().map(|entry| entry.size).sum());
+    match fs::remove_dir_all(cache_dir.join(ARTIFACTS_DIR)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(freed),
+    }
+}
+
+/// Remove the stored artifacts no `get` has used for `unused_for`,
+/// returning the number of entries and bytes freed
+pub fn clean_unused(cache_dir: &Path, unused_for: Duration) -> io::Result<(usize, u64)> {
+    let cutoff = now().saturating_sub(unused_for.as_secs());
+    let mut freed = (0, 0);
+    for entry in scan(cache_dir)? {
+        if entry.last_used >= cutoff {
+            continue;
+        }
+        fs::remove_dir_all(&entry.dir)?;
+        freed.0 += 1;
+        freed.1 += entry.size;
+    }
+    Ok(freed)
+}
+
+/// Where the cached clone of `repo` lives
+pub fn clone_dir(cache_dir: &Path, repo: &str) -> PathBuf {
+    let repo_digest = format!("{:x}", md5::compute(repo.as_bytes()));
+    cache_dir.join(CLONES_DIR).join(repo_digest)
+}
+
+/// The lock file of the cached clone at `dir`, from [`clone_dir`]
+pub(crate) fn clone_lock(dir: &Path) -> io::Result<fs::File> {
+    fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(dir.with_extension("lock"))
+}
+
+/// Remove every cached clone no operation is using, returning how many were
+/// removed
+pub fn clean_clones(cache_dir: &Path) -> io::Result<usize> {
+    remove_clones(cache_dir, |_| true)
+}
+
+/// [`clean_clones`], for the clones no fetch has updated for `unused_for`
+pub fn clean_unused_clones(cache_dir: &Path, unused_for: Duration) -> io::Result<usize> {
+    let cutoff = SystemTime::now()
+        .checked_sub(unused_for)
+        .unwrap_or(UNIX_EPOCH);
+    remove_clones(cache_dir, |dir| {
+        // A clone's HEAD is written when it is made, FETCH_HEAD whenever a
+        // later operation brings it up to date
+        let updated = ["FETCH_HEAD", "HEAD"]
+            .iter()
+            .filter_map(|name| fs::metadata(dir.join(".git").join(name)).ok())
+            .filter_map(|metadata| metadata.modified().ok())
+            .max();
+        updated.is_none_or(|updated| updated < cutoff)
+    })
+}
+
+/// Remove the cached clones `remove` picks by their directory that no
+/// operation is using, returning how many were removed
+fn remove_clones(cache_dir: &Path, remove: impl Fn(&Path) -> bool) -> io::Result<usize> {
+    let clones = match fs::read_dir(cache_dir.join(CLONES_DIR)) {
+        Ok(clones) => clones,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = 0;
+    for clone in clones {
+        let dir = clone?.path();
+        if !dir.is_dir() || !remove(&dir) {
+            continue;
+        }
+        let lock = clone_lock(&dir)?;
+        if lock.try_lock_exclusive().is_err() {
+            continue;
+        }
+        fs::remove_dir_all(&dir)?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+/// Default cache directory (`$XDG_CACHE_HOME/bitcache` or `~/.cache/bitcache`)
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| config::home_dir().map(|home| home.join(".cache")))?;
+    Some(cache_home.join("bitcache"))
+}
+
+/// A complete entry found while walking the store
+struct ScannedEntry {
+    dir: PathBuf,
+    size: u64,
+    last_used: u64,
+}
+
+/// List every complete entry across all repositories
+fn scan(cache_dir: &Path) -> io::Result<Vec<ScannedEntry>> {
+    let mut entries = Vec::new();
+    let repos = match fs::read_dir(cache_dir.join(ARTIFACTS_DIR)) {
+        Ok(repos) => repos,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(e),
+    };
+
+    for repo in repos {
+        let repo = repo?.path();
+        if !repo.is_dir() {
+            continue;
+        }
+        for artifact in fs::read_dir(repo)? {
+            let dir = artifact?.path();
+            let Ok(content) = fs::read(dir.join(ENTRY_FILE)) else {
+                continue;
+            };
+            if let Ok(stored) = serde_json::from_slice::<StoredEntry>(&content) {
+                entries.push(ScannedEntry {
+                    dir,
+                    size: stored.size,
+                    last_used: stored.last_used,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// The directory name inside the store for an MD5 fetched by `name`, if
+/// both can be used in one
+fn key(md5: &str, name: Option<&str>) -> Option<String> {
+    if md5.is_empty() || !md5.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    match name {
+        None => Some(md5.to_string()),
+        Some(name) if metadata::is_variant_name(name) => Some(format!("{}@{}", md5, name)),
+        Some(_) => None,
+    }
+}
+
+fn blob_matches(blob: &Path, stored: &StoredEntry) -> io::Result<bool> {
+    match fs::metadata(blob) {
+        Ok(metadata) if metadata.len() == stored.size => Ok(compute_md5(blob)? == stored.digest),
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Store an artifact for `md5` last used `age` ago
+    fn stored(cache_dir: &Path, md5: &str, age: Duration) {
+        let blob = cache_dir.join("blob.src");
+        fs::write(&blob, md5).unwrap();
+        let store = ArtifactStore::open(cache_dir, "repo");
+        let entry = MetadataEntry::new(md5, "top.bit", "top.vhd", "2024-01-01T00:00:00Z");
+        store.insert(&entry, None, &blob, None).unwrap();
+
+        let entry_path = store.dir.join(md5).join(ENTRY_FILE);
+        let mut stored: StoredEntry =
+            serde_json::from_slice(&fs::read(&entry_path).unwrap()).unwrap();
+        stored.last_used = now() - age.as_secs();
+        fs::write(&entry_path, serde_json::to_vec(&stored).unwrap()).unwrap();
+    }
+
+    /// A cached clone last updated `age` ago
+    fn clone(cache_dir: &Path, repo: &str, age: Duration) -> PathBuf {
+        let dir = clone_dir(cache_dir, repo);
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        let head = fs::File::create(dir.join(".git/HEAD")).unwrap();
+        head.set_modified(SystemTime::now() - age).unwrap();
+        dir
+    }
+
+    #[test]
+    fn clean_unused_keeps_recent_artifacts() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        stored(cache_dir.path(), "old", 40 * DAY);
+        stored(cache_dir.path(), "recent", DAY);
+
+        let (count, bytes) = clean_unused(cache_dir.path(), 30 * DAY).unwrap();
+        assert_eq!((count, bytes), (1, 3));
+        let store = ArtifactStore::open(cache_dir.path(), "repo");
+        assert!(store.fetch("old", None).unwrap().is_none());
+        assert!(store.fetch("recent", None).unwrap().is_some());
+    }
+
+    #[test]
+    fn clean_unused_clones_keeps_recent_clones() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let old = clone(cache_dir.path(), "old", 40 * DAY);
+        let recent = clone(cache_dir.path(), "recent", DAY);
+
+        assert_eq!(clean_unused_clones(cache_dir.path(), 30 * DAY).unwrap(), 1);
+        assert!(!old.exists());
+        assert!(recent.exists());
+        assert_eq!(clean_clones(cache_dir.path()).unwrap(), 1);
+        assert!(!recent.exists());
+    }
+
+    #[test]
+    fn names_are_cached_apart_and_removed_together() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let blob = cache_dir.path().join("blob.src");
+        fs::write(&blob, "top").unwrap();
+        let store = ArtifactStore::open(cache_dir.path(), "repo");
+        let md5 = "d3699e851d7f4fde53ee37c037408af7";
+        let mut flash = MetadataEntry::new(md5, "top.mcs", "top.vhd", "2024-01-01T00:00:00Z");
+        flash.variant = Some("flash".to_string());
+        store.insert(&flash, Some("flash"), &blob, None).unwrap();
+        assert!(store.fetch(md5, None).unwrap().is_none());
+        assert!(store.fetch(md5, Some("top.bit")).unwrap().is_none());
+        store.insert(&flash, None, &blob, None).unwrap();
+        assert_eq!(store.fetch(md5, None).unwrap().unwrap().entry, flash);
+        assert_eq!(
+            store.fetch(md5, Some("flash")).unwrap().unwrap().entry,
+            flash
+        );
+        // A name that is not a directory name is never looked up
+        assert!(store.fetch(md5, Some("../flash")).unwrap().is_none());
+
+        store.remove(md5).unwrap();
+        assert!(store.fetch(md5, None).unwrap().is_none());
+        assert!(store.fetch(md5, Some("flash")).unwrap().is_none());
+    }
+}
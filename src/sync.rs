@@ -0,0 +1,313 @@
+//! Copying entries and their bitstreams between repositories.
+//!
+//! Unlike [`crate::import`], which moves only metadata, [`sync`] copies each
+//! entry's bitstream too, so the destination can serve everything it
+//! receives on its own.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::{self, BitcacheError};
+use crate::layout::MetadataFile;
+use crate::progress::{status, warning};
+use crate::publish::{self, Staged};
+use crate::storage;
+use crate::{compute_md5, paths, Context, Metadata, MetadataEntry, Remote};
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Which entries [`sync`] copies and what it does on conflicts
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Only sync the entries for these source hashes, every variant of
+    /// each; every entry when empty
+    pub md5s: Vec<String>,
+    /// Replace a destination entry whose bitstream path or checksum differs
+    /// from the source's instead of skipping it
+    pub overwrite: bool,
+    /// Work out what would be synced without copying, committing or pushing
+    /// anything
+    pub dry_run: bool,
+}
+
+impl SyncOptions {
+    /// Sync every entry, skipping conflicts
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// A source entry [`sync`] left out
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncSkipped {
+    /// The entry as the source has it
+    #[serde(flatten)]
+    pub entry: MetadataEntry,
+    /// Why it was left out
+    pub reason: String,
+}
+
+/// The outcome of a [`sync`], each list sorted by MD5 and variant
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Synced {
+    /// Source entries the destination did not have
+    pub added: Vec<MetadataEntry>,
+    /// Source entries that replaced a conflicting destination entry
+    pub replaced: Vec<MetadataEntry>,
+    /// Source entries that conflicted with the destination's, or could not
+    /// be copied, and were not synced
+    pub skipped: Vec<SyncSkipped>,
+    /// Number of source entries the destination already has with the same
+    /// bitstream at the same path
+    pub unchanged: usize,
+    /// Hashes of [`SyncOptions::md5s`] the source has no entry for
+    pub not_found: Vec<String>,
+    /// Bytes of the bitstreams copied, or that would be on a dry run
+    pub bytes: u64,
+    /// Whether the destination was committed and pushed
+    pub committed: bool,
+}
+
+/// Copy the entries of one repository, with their bitstreams, into another
+///
+/// Entries are compared by hash and variant. One the destination lacks is
+/// added with its bitstream at the same path, and one the destination has
+/// with the same bitstream at the same path is left alone. When the
+/// destination's entry has another path or checksum it is kept unless
+/// `opts.overwrite`. Entries whose bitstream is missing or damaged in the
+/// source, or whose path holds another entry's bitstream in the destination,
+/// are always skipped. Everything is pushed in one commit listing the synced
+/// hashes; concurrent publishers are merged with as for [`crate::publish`].
+///
+/// ```no_run
+/// use bitcache::{Context, Remote, SyncOptions};
+///
+/// let internal = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let public = Remote::new("git@github.com:example/bitstreams.git");
+/// let synced = bitcache::sync(&internal, &public, &SyncOptions::new(), &Context::default())?;
+/// for skipped in &synced.skipped {
+///     println!("{}: {}", skipped.entry.label(), skipped.reason);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn sync(from: &Remote, to: &Remote, opts: &SyncOptions, ctx: &Context) -> io::Result<Synced> {
+    let _entered = ctx.enter();
+    storage::check(from)?;
+    storage::check(to)?;
+    if from.url == to.url && from.branch == to.branch {
+        return Err(BitcacheError::InvalidArgument {
+            flag: "--to",
+            value: error::redact(&to.url),
+            reason: "it is the repository synced from".to_string(),
+        }
+        .into());
+    }
+    let origin = Origin {
+        name: &error::redact(&from.url),
+        doing: "Syncing",
+        verb: "Sync",
+    };
+    copy_entries(None, from, to, opts, ctx, &origin)
+}
+
+/// How [`copy_entries`] names where it copies from, in its status and its
+/// commit message
+pub(crate) struct Origin<'a> {
+    /// The repository or file copied from
+    pub(crate) name: &'a str,
+    /// What the status line starts with, e.g. `Syncing`
+    pub(crate) doing: &'a str,
+    /// What the commit message starts with, e.g. `Sync`
+    pub(crate) verb: &'a str,
+}
+
+/// [`sync`] without the checks of its arguments, into the clone kept by
+/// `pool` if given
+pub(crate) fn copy_entries(
+    pool: Option<&ClonePool>,
+    from: &Remote,
+    to: &Remote,
+    opts: &SyncOptions,
+    ctx: &Context,
+    origin: &Origin,
+) -> io::Result<Synced> {
+    let source = checkout::checkout(None, from, ctx, |_| Ok(()))?;
+    let source_dir = source.dir();
+    let source_metadata = MetadataFile::of(source_dir, from).load()?;
+    let mut synced = Synced::default();
+    for md5 in &opts.md5s {
+        if source_metadata.variants(md5).is_empty() && !synced.not_found.contains(md5) {
+            warning!("the source has no entry for MD5 {}", md5);
+            synced.not_found.push(md5.clone());
+        }
+    }
+    let mut entries: Vec<_> = source_metadata
+        .iter()
+        .filter(|entry| opts.md5s.is_empty() || opts.md5s.contains(&entry.md5))
+        .collect();
+    entries.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+    status!(
+        "{} {} entr{} from {}",
+        origin.doing,
+        entries.len(),
+        if entries.len() == 1 { "y" } else { "ies" },
+        origin.name
+    );
+
+    let checkout = checkout::checkout_to_publish(pool, to, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let metadata_file = MetadataFile::of(repo_dir, to);
+    let metadata = metadata_file.load_or_new()?;
+    let branch = publish::checked_branch(repo_dir, to)?;
+
+    let mut staged = Vec::new();
+    for entry in entries {
+        let skip = |reason: String| SyncSkipped {
+            entry: entry.clone(),
+            reason,
+        };
+        let bitstream = paths::long_path(&source_dir.join(&entry.binary_path));
+        if !bitstream.is_file() {
+            synced.skipped.push(skip(
+                "its bitstream is missing from the source repository".to_string(),
+            ));
+            continue;
+        }
+        let digest = compute_md5(&bitstream)?;
+        if entry
+            .binary_md5
+            .as_ref()
+            .is_some_and(|recorded| *recorded != digest)
+        {
+            synced.skipped.push(skip(
+                "its bitstream in the source repository does not match its recorded MD5"
+                    .to_string(),
+            ));
+            continue;
+        }
+
+        let existing = metadata.lookup(&entry.md5, entry.name());
+        if let Some(existing) = existing {
+            if existing.binary_path == entry.binary_path
+                && stored_digest(repo_dir, existing)?.as_ref() == Some(&digest)
+            {
+                synced.unchanged += 1;
+                continue;
+            }
+            if !opts.overwrite {
+                let reason = if existing.binary_path == entry.binary_path {
+                    "the destination has a different bitstream for it".to_string()
+                } else {
+                    format!("the destination has it at {}", existing.binary_path)
+                };
+                synced.skipped.push(skip(reason));
+                continue;
+            }
+        }
+        if let Some(reason) = clash(repo_dir, &metadata, entry, &digest)? {
+            synced.skipped.push(skip(reason));
+            continue;
+        }
+
+        let size = fs::metadata(&bitstream)?.len();
+        let mut ours = entry.clone();
+        ours.branch = branch.clone();
+        ours.binary_md5 = Some(digest.clone());
+        ours.size = Some(size);
+        synced.bytes += size;
+        match existing {
+            None => synced.added.push(ours.clone()),
+            Some(_) => synced.replaced.push(ours.clone()),
+        }
+        staged.push(Staged {
+            base_entry: existing.cloned(),
+            dest_bitstream: paths::long_path(&repo_dir.join(&ours.binary_path)),
+            entry: ours,
+            bitstream,
+            digest,
+            size,
+            displaces: false,
+        });
+    }
+
+    if opts.dry_run {
+        return Ok(synced);
+    }
+    if staged.is_empty() {
+        status!("No changes to {}", origin.verb.to_lowercase());
+        return Ok(synced);
+    }
+
+    let mut message = format!(
+        "{} {} bitstream{} from {}\n\n",
+        origin.verb,
+        staged.len(),
+        if staged.len() == 1 { "" } else { "s" },
+        origin.name
+    );
+    for (label, entries) in [("Added", &synced.added), ("Replaced", &synced.replaced)] {
+        for entry in entries {
+            message.push_str(&format!("{}: {}\n", label, entry.label()));
+        }
+    }
+    publish::commit_staged(
+        &checkout,
+        &metadata_file,
+        metadata,
+        &staged,
+        &message,
+        to,
+        ctx,
+    )?;
+    synced.committed = true;
+
+    // Cached artifacts of entries that changed no longer match
+    if let Some(store) = ctx.artifact_store(to) {
+        for entry in &synced.replaced {
+            if let Err(e) = store.remove(&entry.md5) {
+                warning!(
+                    "could not remove the entry from the local artifact cache: {}",
+                    e
+                );
+            }
+        }
+    }
+    Ok(synced)
+}
+
+/// MD5 of the destination's bitstream for `entry`, as recorded or else as
+/// stored; `None` when it has neither
+fn stored_digest(repo_dir: &Path, entry: &MetadataEntry) -> io::Result<Option<String>> {
+    if let Some(recorded) = &entry.binary_md5 {
+        return Ok(Some(recorded.clone()));
+    }
+    let stored = paths::long_path(&repo_dir.join(&entry.binary_path));
+    if !stored.is_file() {
+        return Ok(None);
+    }
+    compute_md5(&stored).map(Some)
+}
+
+/// Why `entry` can't go into the destination, whose path holds another
+/// entry's bitstream there; no `overwrite` changes that
+fn clash(
+    repo_dir: &Path,
+    metadata: &Metadata,
+    entry: &MetadataEntry,
+    digest: &str,
+) -> io::Result<Option<String>> {
+    let owner = metadata.iter().find(|other| {
+        (&other.md5, other.name()) != (&entry.md5, entry.name())
+            && other.binary_path == entry.binary_path
+    });
+    if let Some(owner) = owner {
+        if stored_digest(repo_dir, owner)?.as_deref() != Some(digest) {
+            return Ok(Some(format!(
+                "{} holds the bitstream of MD5 {} in the destination",
+                entry.binary_path,
+                owner.label()
+            )));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,111 @@
+//! Async entry points, enabled by the `async` feature.
+//!
+//! Each operation runs the blocking implementation on a thread of its own
+//! and completes an [`Operation`] future when it is done, so the same code
+//! path serves sync and async callers and any executor can await it without
+//! `spawn_blocking`. Dropping the future before it completes cancels the
+//! operation: it stops at its next cancellation check, kills its git child
+//! and removes its temporary clone and partial files, without affecting other
+//! operations.
+
+use crate::{
+    cancel, get, publish, Context, GetOptions, PublishOptions, Published, Remote, Retrieved,
+};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+use std::thread;
+
+/// A library operation running in the background
+///
+/// Resolves to the operation's result; dropping it cancels the operation.
+pub struct Operation<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    cancel: Arc<AtomicBool>,
+}
+
+struct Shared<T> {
+    result: Option<io::Result<T>>,
+    waker: Option<Waker>,
+    finished: bool,
+}
+
+impl<T: Send + 'static> Operation<T> {
+    fn spawn(name: &str, f: impl FnOnce() -> io::Result<T> + Send + 'static) -> Self {
+        let shared = Arc::new(Mutex::new(Shared {
+            result: None,
+            waker: None,
+            finished: false,
+        }));
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let (thread_shared, token) = (shared.clone(), cancel.clone());
+        let spawned = thread::Builder::new()
+            .name(format!("bitcache-{}", name))
+            .spawn(move || {
+                let result = cancel::with_token(token, f);
+                let mut shared = thread_shared.lock().unwrap_or_else(|e| e.into_inner());
+                shared.result = Some(result);
+                shared.finished = true;
+                if let Some(waker) = shared.waker.take() {
+                    waker.wake();
+                }
+            });
+        if let Err(e) = spawned {
+            let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            state.result = Some(Err(e));
+            state.finished = true;
+        }
+
+        Self { shared, cancel }
+    }
+}
+
+impl<T> Future for Operation<T> {
+    type Output = io::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None if shared.finished => {
+                Poll::Ready(Err(io::Error::other("Operation polled after completion")))
+            }
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Operation<T> {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Async version of [`publish()`]
+///
+/// ```no_run
+/// use bitcache::{Context, PublishOptions, Remote};
+///
+/// async fn upload() -> std::io::Result<()> {
+///     let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+///     let opts = PublishOptions::new("top.vhd", "build/top.bit", "boards/zedboard");
+///     let published = bitcache::publish_async(remote, opts, Context::default()).await?;
+///     println!("stored {}", published.binary_path);
+///     Ok(())
+/// }
+/// ```
+pub fn publish_async(remote: Remote, opts: PublishOptions, ctx: Context) -> Operation<Published> {
+    Operation::spawn("publish", move || publish(&remote, &opts, &ctx))
+}
+
+/// Async version of [`get()`]
+pub fn get_async(remote: Remote, opts: GetOptions, ctx: Context) -> Operation<Option<Retrieved>> {
+    Operation::spawn("get", move || get(&remote, &opts, &ctx))
+}
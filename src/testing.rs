@@ -0,0 +1,452 @@
+//! Helpers for testing code built on the library, enabled by the `test-util`
+//! feature.
+//!
+//! - [`MemoryBackend`] keeps entries and bitstreams in memory and never runs
+//!   git. It checks its inputs like a real publish and can be told to fail
+//!   or slow down.
+//! - [`TestRepo`] is a bare git repository in a temporary directory. It can
+//!   be seeded with entries and is removed when dropped, for tests that go
+//!   through the real [`Bitcache`] client.
+//!
+//! Commits made in a [`TestRepo`] are by [`committer`], so tests pass where
+//! git has no identity configured.
+
+use crate::backend::Backend;
+use crate::error::BitcacheError;
+use crate::get::{self, Destination};
+use crate::layout::MetadataFile;
+use crate::publish::{self, Inputs};
+use crate::{
+    cancel, fsutil, paths, Bitcache, Builder, Committer, Context, DeleteOptions, Deleted,
+    GetOptions, Metadata, MetadataEntry, PublishAction, PublishOptions, Published, Remote,
+    Retrieved,
+};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// A [`Backend`] operation, for [`MemoryBackend::fail_next`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Op {
+    Publish,
+    Get,
+    Exists,
+    List,
+    Delete,
+}
+
+/// A [`Backend`] holding everything in memory
+///
+/// ```no_run
+/// use bitcache::backend::Backend;
+/// use bitcache::testing::{MemoryBackend, Op};
+/// use bitcache::MetadataEntry;
+///
+/// let backend = MemoryBackend::new();
+/// backend.insert(
+///     MetadataEntry::new("d3699e851d7f4fde53ee37c037408af7", "bits/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+///     b"bitstream".to_vec(),
+/// );
+/// assert!(backend.exists("d3699e851d7f4fde53ee37c037408af7")?);
+///
+/// backend.fail_next(Op::List, std::io::Error::other("remote unreachable"));
+/// assert!(backend.list().is_err());
+/// assert_eq!(backend.list()?.len(), 1);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: Metadata,
+    /// Bitstream contents by `binary_path`
+    blobs: HashMap<String, Vec<u8>>,
+    failures: VecDeque<(Op, io::Error)>,
+    latency: Duration,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry and its bitstream, replacing any entry for the same MD5
+    /// and name
+    pub fn insert(&self, entry: MetadataEntry, contents: Vec<u8>) {
+        let mut state = self.lock();
+        state.blobs.insert(entry.binary_path.clone(), contents);
+        state.entries.insert_entry(entry);
+    }
+
+    /// The bitstream stored at `binary_path`
+    pub fn contents(&self, binary_path: &str) -> Option<Vec<u8>> {
+        self.lock().blobs.get(binary_path).cloned()
+    }
+
+    /// Make the next `op` fail with `error` instead of running
+    ///
+    /// Failures queue up; each one is used once.
+    pub fn fail_next(&self, op: Op, error: io::Error) {
+        self.lock().failures.push_back((op, error));
+    }
+
+    /// Wait this long at the start of every operation
+    pub fn set_latency(&self, latency: Duration) {
+        self.lock().latency = latency;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply the latency and any failure queued for `op`
+    fn begin(&self, op: Op) -> io::Result<()> {
+        let latency = self.lock().latency;
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+        cancel::check()?;
+        let mut state = self.lock();
+        match state
+            .failures
+            .iter()
+            .position(|(failing, _)| *failing == op)
+        {
+            Some(index) => Err(state.failures.remove(index).unwrap().1),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn publish(&self, opts: &PublishOptions) -> io::Result<Published> {
+        self.begin(Op::Publish)?;
+        let Inputs {
+            source,
+            bitstream,
+            binary_rel_path,
+            source_filename,
+            name,
+            variant,
+            ..
+        } = publish::check_inputs(opts)?;
+        let md5 = source.hash(opts.hash_algo)?;
+        let contents = fs::read(&bitstream)?;
+
+        let mut state = self.lock();
+        let existing = state.entries.lookup(&md5, &name);
+        let unchanged = existing.is_some_and(|existing| {
+            existing.binary_path == binary_rel_path
+                && state.blobs.get(&binary_rel_path) == Some(&contents)
+        });
+        publish::check_overwrite(existing, unchanged, opts)?;
+        let action = match existing {
+            Some(_) if unchanged && !opts.force => PublishAction::Unchanged,
+            Some(_) => PublishAction::Overwrite,
+            None => PublishAction::Create,
+        };
+        let owner = state.entries.iter().find(|entry| {
+            (&entry.md5, entry.name()) != (&md5, name.as_str())
+                && entry.binary_path == binary_rel_path
+        });
+        if let Some(owner) = owner {
+            if state.blobs.get(&binary_rel_path) != Some(&contents) {
+                return Err(BitcacheError::PathTaken {
+                    path: binary_rel_path,
+                    reason: format!(
+                        "it already holds the bitstream of {} (MD5 {}). Choose a different --path or store this one under another name with --rename-in-repo <NAME>",
+                        owner.source_file, owner.md5
+                    ),
+                }
+                .into());
+            }
+        }
+
+        let size = contents.len() as u64;
+        let mut entry = MetadataEntry::new(
+            md5.clone(),
+            binary_rel_path.clone(),
+            source_filename,
+            chrono::Utc::now().to_rfc3339(),
+        );
+        entry.hash_algo = opts.hash_algo.name().to_string();
+        entry.source_files = source.names();
+        entry.tags = opts.tags.clone();
+        entry.binary_md5 = Some(format!("{:x}", md5::compute(&contents)));
+        entry.variant = variant;
+        let commit_message = if action == PublishAction::Unchanged {
+            String::new()
+        } else {
+            state.blobs.insert(binary_rel_path.clone(), contents);
+            state.entries.insert_entry(entry);
+            publish::add_subject(opts.hash_algo, &md5)
+        };
+        Ok(Published {
+            commit_message,
+            md5,
+            binary_path: binary_rel_path,
+            name,
+            size,
+            action,
+        })
+    }
+
+    fn get(&self, opts: &GetOptions) -> io::Result<Option<Retrieved>> {
+        self.begin(Op::Get)?;
+        let destination = Destination::resolve(opts)?;
+        let (entry, contents) = {
+            let state = self.lock();
+            let Some(entry) = state
+                .entries
+                .select(&opts.md5, opts.variant.as_deref())?
+                .cloned()
+            else {
+                return Ok(None);
+            };
+            let Some(contents) = state.blobs.get(&entry.binary_path).cloned() else {
+                return Err(BitcacheError::MissingBinary {
+                    binary_path: entry.binary_path,
+                }
+                .into());
+            };
+            (entry, contents)
+        };
+        get::check_source(opts, &entry)?;
+
+        let stored_name = entry.binary_path.rsplit('/').next().unwrap_or_default();
+        let filename = paths::decode_name(stored_name)
+            .map_err(|reason| io::Error::new(io::ErrorKind::InvalidData, reason))?;
+        let path = destination.file_path(&filename);
+        get::check_vacant(&path, opts)?;
+        fs::write(&path, &contents)?;
+        if opts.sync {
+            fsutil::sync_path(&path)?;
+        }
+        Ok(Some(Retrieved {
+            entry,
+            path,
+            size: contents.len() as u64,
+            from_cache: false,
+            synced: opts.sync,
+        }))
+    }
+
+    fn exists(&self, md5: &str) -> io::Result<bool> {
+        self.begin(Op::Exists)?;
+        Ok(!self.lock().entries.variants(md5).is_empty())
+    }
+
+    fn list(&self) -> io::Result<Vec<MetadataEntry>> {
+        self.begin(Op::List)?;
+        let mut entries: Vec<_> = self.lock().entries.iter().cloned().collect();
+        entries.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+        Ok(entries)
+    }
+
+    fn delete(&self, opts: &DeleteOptions) -> io::Result<Option<Deleted>> {
+        self.begin(Op::Delete)?;
+        let mut state = self.lock();
+        let Some(name) = state
+            .entries
+            .select(&opts.md5, opts.variant.as_deref())?
+            .map(|entry| entry.name().to_string())
+        else {
+            return Ok(None);
+        };
+        let Some(entry) = state.entries.remove_entry(&opts.md5, &name) else {
+            return Ok(None);
+        };
+        let shared = state
+            .entries
+            .iter()
+            .any(|other| other.binary_path == entry.binary_path);
+        let purged =
+            opts.purge_binary && !shared && state.blobs.remove(&entry.binary_path).is_some();
+        // There is no trash in memory, every delete is hard
+        Ok(Some(Deleted {
+            entry,
+            purged,
+            trashed: None,
+        }))
+    }
+}
+
+/// A bare git repository in a temporary directory, removed when dropped
+///
+/// ```no_run
+/// use bitcache::testing::TestRepo;
+/// use bitcache::MetadataEntry;
+///
+/// let repo = TestRepo::new()?;
+/// repo.seed(
+///     MetadataEntry::new("d3699e851d7f4fde53ee37c037408af7", "bits/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+///     b"bitstream",
+/// )?;
+/// let client = repo.client()?;
+/// assert!(client.exists("d3699e851d7f4fde53ee37c037408af7")?);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TestRepo {
+    dir: TempDir,
+    url: String,
+}
+
+impl TestRepo {
+    /// An empty repository whose default branch is `main`
+    pub fn new() -> io::Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("bitcache-test-")
+            .tempdir()?;
+        let bare = dir.path().join("remote.git");
+        git(
+            dir.path(),
+            &["init", "--quiet", "--bare", &bare.to_string_lossy()],
+        )?;
+        git(&bare, &["symbolic-ref", "HEAD", "refs/heads/main"])?;
+        let url = bare.to_string_lossy().into_owned();
+        Ok(Self { dir, url })
+    }
+
+    /// URL to pass as `--repo`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The repository as a [`Remote`]
+    pub fn remote(&self) -> Remote {
+        Remote::new(self.url.clone())
+    }
+
+    /// A client builder for this repository that works inside the temporary
+    /// directory, has no local cache and commits as [`committer`]
+    pub fn builder(&self) -> io::Result<Builder> {
+        Ok(Bitcache::builder()
+            .repo(self.url.clone())
+            .work_dir(self.work_dir()?)
+            .cache_dir(None)
+            .committer(committer()))
+    }
+
+    /// A [`Context`] for the free-function operations with the settings of
+    /// [`TestRepo::builder`]
+    pub fn context(&self) -> io::Result<Context> {
+        Ok(Context {
+            work_dir: Some(self.work_dir()?),
+            cache_dir: None,
+            committer: Some(committer()),
+            ..Context::default()
+        })
+    }
+
+    fn work_dir(&self) -> io::Result<PathBuf> {
+        let work_dir = self.dir.path().join("work");
+        fs::create_dir_all(&work_dir)?;
+        Ok(work_dir)
+    }
+
+    /// A client for this repository, see [`TestRepo::builder`]
+    pub fn client(&self) -> io::Result<Bitcache> {
+        self.builder()?.build()
+    }
+
+    /// Commit `entry` and its bitstream `contents` to the repository, as if
+    /// it had been published
+    pub fn seed(&self, entry: MetadataEntry, contents: &[u8]) -> io::Result<()> {
+        paths::check_relative(&entry.binary_path)
+            .map_err(|reason| io::Error::new(io::ErrorKind::InvalidInput, reason))?;
+        let clone = self.dir.path().join("seed");
+        if clone.exists() {
+            fs::remove_dir_all(&clone)?;
+        }
+        git(
+            self.dir.path(),
+            &["clone", "--quiet", &self.url, &clone.to_string_lossy()],
+        )?;
+        git(&clone, &["symbolic-ref", "HEAD", "refs/heads/main"])?;
+
+        let binary = clone.join(&entry.binary_path);
+        if let Some(parent) = binary.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&binary, contents)?;
+        let metadata_file = MetadataFile::of(&clone, &self.remote());
+        let mut metadata = metadata_file.load_or_new()?;
+        let message = format!("Seed bitstream for source MD5: {}", entry.md5);
+        let binary_path = entry.binary_path.clone();
+        metadata.insert_entry(entry);
+        let mut args = vec!["add".to_string(), "--".to_string(), binary_path];
+        args.extend(metadata_file.save(&metadata, false)?);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        git(&clone, &args)?;
+        git(
+            &clone,
+            &["commit", "--quiet", "--no-verify", "-m", &message],
+        )?;
+        git(&clone, &["push", "--quiet", "origin", "HEAD:main"])?;
+        fs::remove_dir_all(&clone)
+    }
+
+    /// Directory holding the repository and the clients' clones
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// The identity of the commits made in a [`TestRepo`]
+///
+/// Tests that run the `bitcache` binary can pass it to each command in the
+/// `GIT_AUTHOR_*` and `GIT_COMMITTER_*` variables, see [`identity_env`].
+pub fn committer() -> Committer {
+    Committer::new("bitcache", "bitcache@localhost")
+}
+
+/// The environment variables giving a git command [`committer`]'s identity
+pub fn identity_env() -> [(&'static str, String); 4] {
+    let Committer { name, email } = committer();
+    [
+        ("GIT_AUTHOR_NAME", name.clone()),
+        ("GIT_AUTHOR_EMAIL", email.clone()),
+        ("GIT_COMMITTER_NAME", name),
+        ("GIT_COMMITTER_EMAIL", email),
+    ]
+}
+
+/// Run git in `dir` with [`committer`]'s identity and no user or system
+/// config
+fn git(dir: &Path, args: &[&str]) -> io::Result<()> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", null_config())
+        .envs(identity_env())
+        .args(["-c", "commit.gpgsign=false"])
+        .args(args)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git {} failed: {}",
+            args.first().copied().unwrap_or_default(),
+            String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+    Ok(())
+}
+
+/// An empty config file, to keep the user's git config out of test repos
+fn null_config() -> PathBuf {
+    if cfg!(windows) {
+        PathBuf::from("NUL")
+    } else {
+        PathBuf::from("/dev/null")
+    }
+}
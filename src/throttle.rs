@@ -0,0 +1,462 @@
+//! Bandwidth limiting for git transfers.
+//!
+//! git has no option to cap its transfer rate, so while a limit is set (see
+//! [`limit`]) the commands that talk to the remote reach it through a
+//! [`Relay`] on the loopback interface. The relay forwards at most the limit
+//! in bytes per second, both directions and all connections together, and
+//! counts what it forwarded so heartbeats can report it. HTTP(S) remotes use
+//! it as git's proxy; for SSH remotes it forwards raw TCP to the SSH server.
+
+use crate::cancel;
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+thread_local! {
+    /// Limit of the operation running on this thread, in bytes per second
+    static LIMIT: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Limit the git transfers on this thread to `rate` bytes per second until
+/// the returned guard is dropped; `None` or 0 means no limit
+pub(crate) fn limit(rate: Option<u64>) -> Limiting {
+    Limiting(LIMIT.with(|current| current.replace(rate.filter(|&rate| rate > 0))))
+}
+
+/// The limit set on this thread, if any
+pub(crate) fn current() -> Option<u64> {
+    LIMIT.with(Cell::get)
+}
+
+/// Restores the previous limit when dropped, see [`limit`]
+pub(crate) struct Limiting(Option<u64>);
+
+impl Drop for Limiting {
+    fn drop(&mut self) {
+        LIMIT.with(|current| current.set(self.0));
+    }
+}
+
+/// Where a [`Relay`] forwards connections to
+#[derive(Debug, Clone)]
+pub(crate) enum Route {
+    /// Every connection to this host and port, as for an SSH server
+    Tcp { host: String, port: u16 },
+    /// Act as an HTTP proxy: `CONNECT` tunnels and absolute-URI requests go
+    /// to the host they name, or through `upstream` unchanged if set
+    Http { upstream: Option<(String, u16)> },
+}
+
+/// A forwarding relay on the loopback interface, stopped when dropped
+pub(crate) struct Relay {
+    addr: SocketAddr,
+    transferred: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl Relay {
+    /// Start relaying connections along `route` at `rate` bytes per second
+    pub(crate) fn start(route: Route, rate: u64) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let addr = listener.local_addr()?;
+        // Polled so that dropping the relay can stop the acceptor
+        listener.set_nonblocking(true)?;
+
+        let bucket = Arc::new(Bucket::new(rate));
+        let transferred = Arc::new(AtomicU64::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let acceptor = {
+            let transferred = Arc::clone(&transferred);
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name("bitcache-relay".into())
+                .spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((client, _)) => {
+                                let route = route.clone();
+                                let bucket = Arc::clone(&bucket);
+                                let transferred = Arc::clone(&transferred);
+                                thread::spawn(move || {
+                                    let _ = serve(client, &route, &bucket, &transferred);
+                                });
+                            }
+                            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                                thread::sleep(Duration::from_millis(10))
+                            }
+                            Err(_) => thread::sleep(Duration::from_millis(10)),
+                        }
+                    }
+                })?
+        };
+
+        Ok(Self {
+            addr,
+            transferred,
+            stop,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Address git should connect to
+    pub(crate) fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Bytes forwarded so far, in both directions
+    pub(crate) fn transferred(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        // Connections still open end when git, their other side, has exited
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+/// Longest HTTP request head the proxy accepts
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Forward one client connection along `route`
+fn serve(
+    client: TcpStream,
+    route: &Route,
+    bucket: &Arc<Bucket>,
+    transferred: &Arc<AtomicU64>,
+) -> io::Result<()> {
+    client.set_nonblocking(false)?;
+    let (client, server, pending) = match route {
+        Route::Tcp { host, port } => {
+            let server = TcpStream::connect((host.as_str(), *port))?;
+            (client, server, Vec::new())
+        }
+        Route::Http { upstream } => {
+            let mut client = client;
+            let head = read_head(&mut client)?;
+            let Some((connect, host, port)) = proxy_target(&head) else {
+                client.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+                return Ok(());
+            };
+            let next_hop = match upstream {
+                Some((proxy_host, proxy_port)) => (proxy_host.as_str(), *proxy_port),
+                None => (host.as_str(), port),
+            };
+            let server = match TcpStream::connect(next_hop) {
+                Ok(server) => server,
+                Err(e) => {
+                    client.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n")?;
+                    return Err(e);
+                }
+            };
+            match upstream {
+                // The upstream proxy gets the request as git sent it
+                Some(_) => (client, server, head),
+                None => {
+                    let end = head_end(&head).unwrap_or(head.len());
+                    if connect {
+                        client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+                        (client, server, head[end..].to_vec())
+                    } else {
+                        let mut request = origin_request(&head[..end]);
+                        request.extend_from_slice(&head[end..]);
+                        (client, server, request)
+                    }
+                }
+            }
+        }
+    };
+
+    let mut server_writer = server.try_clone()?;
+    bucket.take(pending.len());
+    server_writer.write_all(&pending)?;
+    transferred.fetch_add(pending.len() as u64, Ordering::Relaxed);
+
+    let upload = {
+        let client = client.try_clone()?;
+        let bucket = Arc::clone(bucket);
+        let transferred = Arc::clone(transferred);
+        thread::spawn(move || pump(client, server_writer, &bucket, &transferred))
+    };
+    pump(server, client.try_clone()?, bucket, transferred);
+    let _ = upload.join();
+    let _ = client.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+/// Copy `from` to `to` at the bucket's rate until either side closes
+fn pump(mut from: TcpStream, mut to: TcpStream, bucket: &Bucket, transferred: &AtomicU64) {
+    let mut buffer = vec![0u8; bucket.chunk];
+    loop {
+        let n = match from.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        bucket.take(n);
+        if cancel::is_cancelled() || to.write_all(&buffer[..n]).is_err() {
+            break;
+        }
+        transferred.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+/// Read an HTTP request head, and whatever followed it in the same reads
+fn read_head(client: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 4096];
+    while head_end(&head).is_none() {
+        let n = client.read(&mut buffer)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buffer[..n]);
+        if head.len() > MAX_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP request head too long",
+            ));
+        }
+    }
+    Ok(head)
+}
+
+/// Offset just past the blank line that ends an HTTP head
+fn head_end(head: &[u8]) -> Option<usize> {
+    head.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|at| at + 4)
+}
+
+/// The proxy request `head` as the origin server expects it: the path
+/// instead of the full URL, and closing the connection after the response
+///
+/// The relay only rewrites the first request on a connection, so the close
+/// makes git open a new connection, and thus a new first request, for each.
+fn origin_request(head: &[u8]) -> Vec<u8> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    let mut words = request_line.splitn(3, ' ');
+    let (method, target, version) = (
+        words.next().unwrap_or(""),
+        words.next().unwrap_or(""),
+        words.next().unwrap_or("HTTP/1.1"),
+    );
+    let path = target
+        .splitn(4, '/')
+        .nth(3)
+        .map_or("/".to_string(), |path| format!("/{}", path));
+    let mut request = format!("{} {} {}\r\n", method, path, version);
+    for line in lines.filter(|line| !line.is_empty()) {
+        let name = line.split(':').next().unwrap_or("").trim();
+        let hop_by_hop = [
+            "connection",
+            "proxy-connection",
+            "keep-alive",
+            "proxy-authorization",
+        ]
+        .iter()
+        .any(|header| name.eq_ignore_ascii_case(header));
+        if !hop_by_hop {
+            request += line;
+            request += "\r\n";
+        }
+    }
+    request += "Connection: close\r\n\r\n";
+    request.into_bytes()
+}
+
+/// Whether the request is a `CONNECT`, and the host and port it is for
+fn proxy_target(head: &[u8]) -> Option<(bool, String, u16)> {
+    let line = head.split(|&byte| byte == b'\r').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut words = line.split(' ');
+    let method = words.next()?;
+    let target = words.next()?;
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_host_port(target, None)?;
+        return Some((true, host, port));
+    }
+    let rest = target
+        .strip_prefix("http://")
+        .or_else(|| target.strip_prefix("HTTP://"))?;
+    let authority = rest.split(['/', '?']).next()?;
+    let (host, port) = split_host_port(authority, Some(80))?;
+    Some((false, host, port))
+}
+
+/// Split `host:port`, also in the `[v6]:port` form, defaulting the port
+pub(crate) fn split_host_port(authority: &str, default: Option<u16>) -> Option<(String, u16)> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => {
+            let (host, after) = rest.split_once(']')?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default?,
+    };
+    (!host.is_empty()).then(|| (host.to_string(), port))
+}
+
+/// Token bucket shared by every connection of a relay
+///
+/// Tokens may go negative: a caller takes what it needs and sleeps off the
+/// debt, so concurrent callers queue up fairly without holding the lock.
+struct Bucket {
+    rate: f64,
+    /// Most bytes moved per read, and the most tokens that build up
+    chunk: usize,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        // About 20 steps per second keeps the rate even without tiny writes
+        let chunk = (rate / 20).clamp(1024, 64 * 1024) as usize;
+        Self {
+            rate: rate as f64,
+            chunk,
+            state: Mutex::new((chunk as f64, Instant::now())),
+        }
+    }
+
+    /// Wait until `bytes` may be sent
+    fn take(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.rate)
+                .min(self.chunk as f64);
+            *last = now;
+            *tokens -= bytes as f64;
+            (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / self.rate))
+        };
+        if let Some(wait) = wait {
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A server on the loopback interface sending `size` bytes to each
+    /// connection, then closing it
+    fn sender(size: usize) -> io::Result<u16> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let port = listener.local_addr()?.port();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.write_all(&vec![7u8; size]);
+            }
+        });
+        Ok(port)
+    }
+
+    /// A port nothing listens on
+    fn closed_port() -> io::Result<u16> {
+        Ok(TcpListener::bind(("127.0.0.1", 0))?.local_addr()?.port())
+    }
+
+    /// Send `request` to the proxy at `relay` and read until it closes
+    fn proxied(relay: &Relay, request: &str) -> io::Result<Vec<u8>> {
+        let mut client = TcpStream::connect(relay.addr())?;
+        client.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        client.read_to_end(&mut response)?;
+        Ok(response)
+    }
+
+    #[test]
+    fn forwards_at_most_the_rate() -> io::Result<()> {
+        const RATE: u64 = 64 * 1024;
+        let size = 48 * 1024;
+        let port = sender(size)?;
+        let relay = Relay::start(
+            Route::Tcp {
+                host: "127.0.0.1".to_string(),
+                port,
+            },
+            RATE,
+        )?;
+        let started = Instant::now();
+        let mut received = Vec::new();
+        TcpStream::connect(relay.addr())?.read_to_end(&mut received)?;
+        let elapsed = started.elapsed();
+
+        assert_eq!(received, vec![7u8; size]);
+        assert_eq!(relay.transferred(), size as u64);
+        // All but the burst the bucket starts with waits for the rate
+        let burst = Bucket::new(RATE).chunk;
+        let least = Duration::from_secs_f64((size - burst) as f64 / RATE as f64);
+        assert!(elapsed >= least, "{:?} is under {:?}", elapsed, least);
+        Ok(())
+    }
+
+    #[test]
+    fn tunnels_a_connect_at_the_rate() -> io::Result<()> {
+        const RATE: u64 = 64 * 1024;
+        let size = 32 * 1024;
+        let port = sender(size)?;
+        let relay = Relay::start(Route::Http { upstream: None }, RATE)?;
+        let started = Instant::now();
+        let response = proxied(
+            &relay,
+            &format!("CONNECT 127.0.0.1:{} HTTP/1.1\r\n\r\n", port),
+        )?;
+        let elapsed = started.elapsed();
+
+        let established = b"HTTP/1.1 200 Connection established\r\n\r\n";
+        assert!(response.starts_with(established));
+        assert_eq!(response[established.len()..], vec![7u8; size][..]);
+        let burst = Bucket::new(RATE).chunk;
+        let least = Duration::from_secs_f64((size - burst) as f64 / RATE as f64);
+        assert!(elapsed >= least, "{:?} is under {:?}", elapsed, least);
+        Ok(())
+    }
+
+    #[test]
+    fn answers_a_failed_connect_with_an_error() -> io::Result<()> {
+        let relay = Relay::start(Route::Http { upstream: None }, 1024 * 1024)?;
+        let port = closed_port()?;
+        let response = proxied(
+            &relay,
+            &format!("CONNECT 127.0.0.1:{} HTTP/1.1\r\n\r\n", port),
+        )?;
+        assert!(
+            response.starts_with(b"HTTP/1.1 502 Bad Gateway\r\n"),
+            "{}",
+            String::from_utf8_lossy(&response)
+        );
+
+        // A request naming no host it could connect to is refused outright
+        let response = proxied(&relay, "CONNECT nowhere HTTP/1.1\r\n\r\n")?;
+        assert!(
+            response.starts_with(b"HTTP/1.1 400 Bad Request\r\n"),
+            "{}",
+            String::from_utf8_lossy(&response)
+        );
+        assert_eq!(relay.transferred(), 0);
+        Ok(())
+    }
+}
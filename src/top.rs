@@ -0,0 +1,155 @@
+//! Ranking entries to find what makes a repository big.
+
+use crate::checkout::{self, ClonePool};
+use crate::layout::MetadataFile;
+use crate::storage;
+use crate::{paths, Context, MetadataEntry, Remote};
+use chrono::DateTime;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+
+/// What [`top`] ranks entries by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+#[non_exhaustive]
+pub enum TopKey {
+    /// Largest bitstream first
+    #[default]
+    Size,
+    /// Most recently published first
+    Age,
+}
+
+/// Which entries [`top`] ranks and how
+#[derive(Debug, Clone)]
+pub struct TopOptions {
+    /// Ranking key
+    pub by: TopKey,
+    /// Return at most this many entries
+    pub limit: Option<usize>,
+    /// Only rank entries whose bitstream is in this directory of the
+    /// repository
+    pub path_prefix: Option<String>,
+}
+
+impl TopOptions {
+    /// The ten largest entries, as the CLI ranks by default
+    pub fn new() -> Self {
+        Self {
+            by: TopKey::Size,
+            limit: Some(10),
+            path_prefix: None,
+        }
+    }
+}
+
+impl Default for TopOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An entry with the figures it was ranked on
+#[derive(Debug, Clone, Serialize)]
+pub struct TopEntry {
+    pub entry: MetadataEntry,
+    /// Size of the bitstream in bytes: the recorded `size` field, or the
+    /// size of the file in the repository; `None` if neither exists
+    pub size: Option<u64>,
+    /// The entry's tags as `key=value`, or just `key` for an empty value
+    pub tags: Vec<String>,
+}
+
+/// Rank the repository's entries by `opts.by`
+///
+/// Entries missing the figure they are ranked on come last.
+///
+/// ```no_run
+/// use bitcache::{Context, Remote, TopOptions};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// for ranked in bitcache::top(&remote, &TopOptions::new(), &Context::default())? {
+///     println!("{:?}  {}", ranked.size, ranked.entry.binary_path);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn top(remote: &Remote, opts: &TopOptions, ctx: &Context) -> io::Result<Vec<TopEntry>> {
+    top_in(None, remote, opts, ctx)
+}
+
+/// [`top`], in the clone kept by `pool` if given
+pub(crate) fn top_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &TopOptions,
+    ctx: &Context,
+) -> io::Result<Vec<TopEntry>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout_shallow(pool, remote, ctx)?;
+    let metadata_file = MetadataFile::of(checkout.dir(), remote);
+    if !metadata_file.exists() {
+        return Ok(Vec::new());
+    }
+    let prefix = opts
+        .path_prefix
+        .as_deref()
+        .map(|prefix| prefix.trim_matches('/'))
+        .filter(|prefix| !prefix.is_empty());
+
+    let mut ranked = Vec::new();
+    for entry in metadata_file.load()?.entries.into_values().flatten() {
+        if let Some(prefix) = prefix {
+            let inside = entry
+                .binary_path
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with('/'));
+            if !inside {
+                continue;
+            }
+        }
+        let size = match entry.size {
+            Some(size) => Some(size),
+            None => fs::metadata(paths::long_path(&checkout.dir().join(&entry.binary_path)))
+                .ok()
+                .map(|meta| meta.len()),
+        };
+        ranked.push(TopEntry {
+            size,
+            tags: entry.tag_labels(),
+            entry,
+        });
+    }
+
+    ranked.sort_by(|a, b| {
+        let by_key = match opts.by {
+            TopKey::Size => descending(a.size, b.size),
+            TopKey::Age => descending(published(&a.entry), published(&b.entry)),
+        };
+        by_key.then_with(|| a.entry.md5.cmp(&b.entry.md5))
+    });
+    if let Some(limit) = opts.limit {
+        ranked.truncate(limit);
+    }
+    Ok(ranked)
+}
+
+/// Publication time in milliseconds since the epoch, `None` if the
+/// timestamp does not parse
+fn published(entry: &MetadataEntry) -> Option<i64> {
+    DateTime::parse_from_rfc3339(&entry.timestamp)
+        .ok()
+        .map(|time| time.timestamp_millis())
+}
+
+/// Largest first, with missing values last
+fn descending<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => b.cmp(&a),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
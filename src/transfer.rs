@@ -0,0 +1,228 @@
+//! Moving metadata between repositories.
+//!
+//! [`export`] reads a repository's metadata and [`import`] writes entries
+//! into another one. Only the metadata moves: bitstreams stay where they are,
+//! so an imported entry can only be retrieved once its bitstream is in the
+//! destination at the same path. [`crate::export_archive`] carries the
+//! bitstreams too.
+
+use crate::checkout::{self, ClonePool};
+use crate::layout::{MetadataFile, MetadataLayout};
+use crate::progress::{status, warning};
+use crate::storage;
+use crate::{paths, trash, Context, Metadata, MetadataEntry, Remote};
+use serde::Serialize;
+use std::io;
+
+/// What [`import`] does with the destination's own entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ImportMode {
+    /// Keep them, adding the imported entries alongside
+    #[default]
+    Merge,
+    /// Drop them, leaving only the imported entries
+    Replace,
+}
+
+/// Which entries to import and how
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// The entries to import, e.g. from [`export`] or
+    /// [`Metadata::load_from_file`]
+    pub metadata: Metadata,
+    /// What happens to the destination's entries
+    pub mode: ImportMode,
+    /// When merging, replace a destination entry that differs from the
+    /// imported entry for the same hash instead of keeping it
+    pub overwrite: bool,
+}
+
+impl ImportOptions {
+    /// Merge `metadata` into the destination, keeping its entries on
+    /// conflicts
+    pub fn new(metadata: Metadata) -> Self {
+        Self {
+            metadata,
+            mode: ImportMode::Merge,
+            overwrite: false,
+        }
+    }
+}
+
+/// The outcome of an [`import`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Imported {
+    /// Entries the destination did not have
+    pub added: Vec<MetadataEntry>,
+    /// Imported entries that replaced a different one of the destination
+    pub replaced: Vec<MetadataEntry>,
+    /// Imported entries that conflicted with the destination's and were
+    /// not imported
+    pub skipped: Vec<MetadataEntry>,
+    /// Destination entries dropped by [`ImportMode::Replace`]
+    pub removed: Vec<MetadataEntry>,
+    /// Number of imported entries identical to the destination's
+    pub unchanged: usize,
+    /// Added or replaced entries whose bitstream is not in the destination
+    pub missing_binaries: usize,
+}
+
+impl Imported {
+    /// Whether the import changed the destination's metadata
+    pub fn changed(&self) -> bool {
+        !(self.added.is_empty() && self.replaced.is_empty() && self.removed.is_empty())
+    }
+}
+
+/// Read the metadata of a repository, to save or [`import`] elsewhere
+///
+/// The metadata comes in the single layout whatever the repository's, so
+/// saving it writes one file.
+///
+/// ```no_run
+/// use bitcache::{Context, Remote};
+/// use std::path::Path;
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let metadata = bitcache::export(&remote, &Context::default())?;
+/// metadata.save_to_file(Path::new("bitstreams.json"))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn export(remote: &Remote, ctx: &Context) -> io::Result<Metadata> {
+    export_in(None, remote, ctx)
+}
+
+/// [`export`], in the clone kept by `pool` if given
+pub(crate) fn export_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<Metadata> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+    let mut metadata = MetadataFile::of(checkout.dir(), remote).load()?;
+    // Saved in one file wherever it goes
+    metadata.set_layout(MetadataLayout::Single);
+    Ok(metadata)
+}
+
+/// Write entries from another repository's metadata into this one
+///
+/// Entries are compared by hash and [`MetadataEntry::name`]. One the
+/// destination lacks is added, and one it already has unchanged is left
+/// alone. When both have different entries, the destination's is kept
+/// unless `opts.overwrite`. [`ImportMode::Replace`] always takes the
+/// imported entry and removes the destination's other entries too. Nothing
+/// is committed when nothing changes. Concurrent publishers are merged
+/// with as for [`crate::publish`].
+///
+/// ```no_run
+/// use bitcache::{Context, ImportOptions, Metadata, Remote};
+/// use std::path::Path;
+///
+/// let metadata = Metadata::load_from_file(Path::new("bitstreams.json"))?;
+/// let remote = Remote::new("git@example.com:fpga/mirror.git");
+/// let imported = bitcache::import(&remote, &ImportOptions::new(metadata), &Context::default())?;
+/// println!("{} added, {} skipped", imported.added.len(), imported.skipped.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn import(remote: &Remote, opts: &ImportOptions, ctx: &Context) -> io::Result<Imported> {
+    import_in(None, remote, opts, ctx)
+}
+
+/// [`import`], in the clone kept by `pool` if given
+pub(crate) fn import_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &ImportOptions,
+    ctx: &Context,
+) -> io::Result<Imported> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    for entry in opts.metadata.iter() {
+        entry.check_path()?;
+    }
+    let count = opts.metadata.len();
+    status!(
+        "Importing {} entr{}",
+        count,
+        if count == 1 { "y" } else { "ies" }
+    );
+
+    let message = match opts.mode {
+        ImportMode::Merge => "Import bitstream metadata",
+        ImportMode::Replace => "Replace bitstream metadata with an import",
+    };
+    let mut imported = Imported::default();
+    trash::change_or_create(pool, remote, ctx, message, |repo_dir, metadata, _| {
+        // A retry starts over on the new remote head
+        imported = merge(metadata, opts);
+        imported.missing_binaries = imported
+            .added
+            .iter()
+            .chain(&imported.replaced)
+            .filter(|entry| !paths::long_path(&repo_dir.join(&entry.binary_path)).exists())
+            .count();
+        Ok(imported.changed().then_some(()))
+    })?;
+    if !imported.changed() {
+        status!("No changes to import");
+    }
+    if imported.missing_binaries > 0 {
+        warning!(
+            "{} imported entr{} no bitstream in the repository yet; copy the bitstreams to the same paths before retrieving them",
+            imported.missing_binaries,
+            if imported.missing_binaries == 1 { "y has" } else { "ies have" }
+        );
+    }
+
+    // Cached artifacts of entries that changed no longer match
+    if let Some(store) = ctx.artifact_store(remote) {
+        for entry in imported.replaced.iter().chain(&imported.removed) {
+            if let Err(e) = store.remove(&entry.md5) {
+                warning!(
+                    "could not remove the entry from the local artifact cache: {}",
+                    e
+                );
+            }
+        }
+    }
+    Ok(imported)
+}
+
+/// Apply the imported entries to the destination's `metadata`
+fn merge(metadata: &mut Metadata, opts: &ImportOptions) -> Imported {
+    let mut imported = Imported::default();
+    if opts.mode == ImportMode::Replace {
+        let mut removed: Vec<MetadataEntry> = metadata
+            .iter()
+            .filter(|entry| opts.metadata.lookup(&entry.md5, entry.name()).is_none())
+            .cloned()
+            .collect();
+        removed.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+        for entry in &removed {
+            metadata.remove_entry(&entry.md5, entry.name());
+        }
+        imported.removed = removed;
+    }
+
+    let mut incoming: Vec<&MetadataEntry> = opts.metadata.iter().collect();
+    incoming.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+    for entry in incoming {
+        match metadata.lookup(&entry.md5, entry.name()) {
+            None => {
+                metadata.insert_entry(entry.clone());
+                imported.added.push(entry.clone());
+            }
+            Some(existing) if existing == entry => imported.unchanged += 1,
+            Some(_) if opts.overwrite || opts.mode == ImportMode::Replace => {
+                metadata.insert_entry(entry.clone());
+                imported.replaced.push(entry.clone());
+            }
+            Some(_) => imported.skipped.push(entry.clone()),
+        }
+    }
+    imported
+}
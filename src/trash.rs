@@ -0,0 +1,516 @@
+//! Keeping deleted entries for a while before they are gone for good.
+//!
+//! [`crate::delete`] moves an entry into the `trash` member of the metadata
+//! and its bitstream under [`TRASH_DIR`], from where [`restore`] puts both
+//! back. Items trashed longer ago than [`Context::trash_retention`] are
+//! removed by the next operation that changes the repository, or at once by
+//! [`empty_trash`]. Trashed entries are invisible to every other operation:
+//! `get` misses them and `list` leaves them out.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::BitcacheError;
+use crate::fsutil;
+use crate::git::PushOutcome;
+use crate::layout::MetadataFile;
+use crate::progress::{self, status, warning, Event};
+use crate::publish::push_backoff;
+use crate::storage;
+use crate::{cancel, metadata, paths, Context, Metadata, MetadataEntry, Remote};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Directory of the repository the bitstreams of trashed entries are kept in
+pub const TRASH_DIR: &str = ".bitcache_trash";
+
+/// Member of the metadata document holding the trashed entries
+pub(crate) const TRASH_MEMBER: &str = "trash";
+
+/// How long trashed entries are kept unless configured otherwise: 30 days
+pub const DEFAULT_TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// An entry [`crate::delete`] moved to the trash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrashedEntry {
+    /// The entry as it was before it was deleted
+    pub entry: MetadataEntry,
+    /// When it was deleted, in RFC 3339
+    pub trashed_at: String,
+    /// Where its bitstream was moved to in the repository; `None` when the
+    /// bitstream stayed at `entry.binary_path`, because another entry uses
+    /// it or it was already missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trash_path: Option<String>,
+}
+
+impl TrashedEntry {
+    /// Whether the item was trashed more than `retention` before `now`;
+    /// an unreadable time counts as long ago
+    fn expired(&self, retention: Duration, now: DateTime<Utc>) -> bool {
+        let Ok(retention) = chrono::Duration::from_std(retention) else {
+            return false;
+        };
+        DateTime::parse_from_rfc3339(&self.trashed_at).map_or(true, |trashed| {
+            now - trashed.with_timezone(&Utc) > retention
+        })
+    }
+}
+
+/// Move `entry`, already removed from `metadata`, to its trash, adding the
+/// repository paths it changes to `written`
+///
+/// The bitstream moves to `TRASH_DIR/<md5>/<name>` unless another entry
+/// still uses it. A previous item for the same hash is replaced.
+pub(crate) fn trash_entry(
+    repo_dir: &Path,
+    metadata: &mut Metadata,
+    entry: MetadataEntry,
+    written: &mut Vec<String>,
+) -> io::Result<TrashedEntry> {
+    let mut trash = metadata.trash()?;
+    if let Some(i) = trash
+        .iter()
+        .position(|item| item.entry.md5 == entry.md5 && item.entry.name() == entry.name())
+    {
+        let replaced = trash.remove(i);
+        remove_trashed_file(repo_dir, &replaced, written)?;
+    }
+
+    let shared = metadata
+        .iter()
+        .any(|other| other.binary_path == entry.binary_path);
+    let source = paths::long_path(&repo_dir.join(&entry.binary_path));
+    let trash_path = if shared {
+        warning!(
+            "keeping {} in place, another entry uses the same bitstream",
+            entry.binary_path
+        );
+        None
+    } else if !source.is_file() {
+        warning!(
+            "{} is already missing from the repository",
+            entry.binary_path
+        );
+        None
+    } else {
+        let name = entry
+            .binary_path
+            .rsplit_once('/')
+            .map_or(entry.binary_path.as_str(), |(_, name)| name);
+        let trash_path = format!("{}/{}/{}", TRASH_DIR, entry.md5, name);
+        paths::check_relative(&trash_path).map_err(|reason| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Cannot trash {}: {}", entry.binary_path, reason),
+            )
+        })?;
+        let dest = paths::long_path(&repo_dir.join(&trash_path));
+        if let Some(dir) = dest.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::rename(&source, &dest)?;
+        fsutil::remove_empty_parents(repo_dir, &entry.binary_path);
+        written.push(entry.binary_path.clone());
+        written.push(trash_path.clone());
+        Some(trash_path)
+    };
+
+    let trashed = TrashedEntry {
+        entry,
+        trashed_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        trash_path,
+    };
+    trash.push(trashed.clone());
+    metadata.set_trash(trash)?;
+    Ok(trashed)
+}
+
+/// Remove the items of `metadata`'s trash older than
+/// [`Context::trash_retention`] and their bitstreams, adding the repository
+/// paths removed to `written`
+///
+/// Returns the number of items removed. Every operation that commits a change
+/// to the metadata calls this first.
+pub(crate) fn expire(
+    repo_dir: &Path,
+    metadata: &mut Metadata,
+    ctx: &Context,
+    written: &mut Vec<String>,
+) -> io::Result<usize> {
+    let Some(retention) = ctx.trash_retention else {
+        return Ok(0);
+    };
+    let now = Utc::now();
+    let (expired, kept): (Vec<_>, Vec<_>) = metadata
+        .trash()?
+        .into_iter()
+        .partition(|item| item.expired(retention, now));
+    if expired.is_empty() {
+        return Ok(0);
+    }
+    status!(
+        "Emptying {} trashed entr{} past the retention period...",
+        expired.len(),
+        if expired.len() == 1 { "y" } else { "ies" }
+    );
+    for item in &expired {
+        remove_trashed_file(repo_dir, item, written)?;
+    }
+    metadata.set_trash(kept)?;
+    Ok(expired.len())
+}
+
+/// Remove the bitstream `item` moved to the trash, if it has one
+fn remove_trashed_file(
+    repo_dir: &Path,
+    item: &TrashedEntry,
+    written: &mut Vec<String>,
+) -> io::Result<()> {
+    let Some(trash_path) = &item.trash_path else {
+        return Ok(());
+    };
+    match fs::remove_file(paths::long_path(&repo_dir.join(trash_path))) {
+        Ok(()) => {
+            fsutil::remove_empty_parents(repo_dir, trash_path);
+            written.push(trash_path.clone());
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// The entries in the repository's trash, oldest first
+///
+/// ```no_run
+/// use bitcache::{Context, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// for item in bitcache::list_trash(&remote, &Context::default())? {
+///     println!("{}  {}", item.trashed_at, item.entry.md5);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn list_trash(remote: &Remote, ctx: &Context) -> io::Result<Vec<TrashedEntry>> {
+    list_trash_in(None, remote, ctx)
+}
+
+/// [`list_trash`], in the clone kept by `pool` if given
+pub(crate) fn list_trash_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+) -> io::Result<Vec<TrashedEntry>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout_shallow(pool, remote, ctx)?;
+    let metadata_file = MetadataFile::of(checkout.dir(), remote);
+    if !metadata_file.exists() {
+        return Ok(Vec::new());
+    }
+    let metadata = metadata_file.load()?;
+    let mut trash = metadata.trash()?;
+    trash.sort_by(|a, b| a.trashed_at.cmp(&b.trashed_at));
+    Ok(trash)
+}
+
+/// Which trashed entry [`restore`] puts back
+#[derive(Debug, Clone)]
+pub struct RestoreOptions {
+    /// Hash of the source of the entry
+    pub md5: String,
+    /// [`MetadataEntry::name`] of the variant to restore; without it a
+    /// source with several in the trash fails with
+    /// [`BitcacheError::AmbiguousVariant`]
+    pub variant: Option<String>,
+}
+
+impl RestoreOptions {
+    /// Restore the trashed entry of `md5`
+    pub fn new(md5: impl Into<String>) -> Self {
+        Self {
+            md5: md5.into(),
+            variant: None,
+        }
+    }
+}
+
+/// Put a trashed entry and its bitstream back where they were
+///
+/// Returns `None` when the trash has no such entry. Fails, changing
+/// nothing, when the hash was published again since, or another bitstream
+/// now is at the entry's path.
+///
+/// ```no_run
+/// use bitcache::{Context, Remote, RestoreOptions};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = RestoreOptions::new("d3699e851d7f4fde53ee37c037408af7");
+/// if bitcache::restore(&remote, &opts, &Context::default())?.is_none() {
+///     println!("not in the trash");
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn restore(
+    remote: &Remote,
+    opts: &RestoreOptions,
+    ctx: &Context,
+) -> io::Result<Option<MetadataEntry>> {
+    restore_in(None, remote, opts, ctx)
+}
+
+/// [`restore`], in the clone kept by `pool` if given
+pub(crate) fn restore_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &RestoreOptions,
+    ctx: &Context,
+) -> io::Result<Option<MetadataEntry>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    status!("Restoring entry for MD5: {}", opts.md5);
+    let message = format!("Restore bitstream for MD5: {}", opts.md5);
+    change(
+        pool,
+        remote,
+        ctx,
+        &message,
+        |repo_dir, metadata, written| {
+            let mut trash = metadata.trash()?;
+            let matching: Vec<MetadataEntry> = trash
+                .iter()
+                .filter(|item| item.entry.md5 == opts.md5)
+                .filter(|item| {
+                    opts.variant
+                        .as_deref()
+                        .is_none_or(|variant| item.entry.name() == variant)
+                })
+                .map(|item| item.entry.clone())
+                .collect();
+            let names = metadata::variant_names(&matching);
+            if names.len() > 1 {
+                return Err(BitcacheError::AmbiguousVariant {
+                    md5: opts.md5.clone(),
+                    variants: names,
+                }
+                .into());
+            }
+            let Some(i) = trash.iter().position(|item| {
+                item.entry.md5 == opts.md5 && names.iter().any(|name| name == item.entry.name())
+            }) else {
+                return Ok(None);
+            };
+            let item = trash.remove(i);
+            let entry = item.entry.clone();
+            if metadata.lookup(&entry.md5, entry.name()).is_some() {
+                return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "MD5 {} was published again since it was deleted; delete that entry before restoring this one",
+                    entry.label()
+                ),
+            ));
+            }
+            let dest = entry.stored_in(repo_dir)?;
+            match &item.trash_path {
+                Some(trash_path) => {
+                    if dest.exists() {
+                        return Err(BitcacheError::PathTaken {
+                            path: entry.binary_path.clone(),
+                            reason:
+                                "another bitstream was stored there since the entry was deleted"
+                                    .to_string(),
+                        }
+                        .into());
+                    }
+                    if let Some(dir) = dest.parent() {
+                        fs::create_dir_all(dir)?;
+                    }
+                    fs::rename(paths::long_path(&repo_dir.join(trash_path)), &dest)?;
+                    fsutil::remove_empty_parents(repo_dir, trash_path);
+                    written.push(trash_path.clone());
+                    written.push(entry.binary_path.clone());
+                }
+                None if !dest.is_file() => {
+                    return Err(BitcacheError::MissingBinary {
+                        binary_path: entry.binary_path.clone(),
+                    }
+                    .into());
+                }
+                None => {}
+            }
+            metadata.set_trash(trash)?;
+            metadata.insert_entry(entry.clone());
+            Ok(Some(entry))
+        },
+    )
+}
+
+/// Which trashed entries [`empty_trash`] removes
+#[derive(Debug, Clone, Default)]
+pub struct EmptyTrashOptions {
+    /// Only remove those past [`Context::trash_retention`], as any operation
+    /// changing the repository does; otherwise all of them
+    pub expired_only: bool,
+}
+
+impl EmptyTrashOptions {
+    /// Remove every trashed entry
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Remove trashed entries and their bitstreams for good
+///
+/// Returns the entries removed.
+///
+/// ```no_run
+/// use bitcache::{Context, EmptyTrashOptions, Remote};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let emptied = bitcache::empty_trash(&remote, &EmptyTrashOptions::new(), &Context::default())?;
+/// println!("removed {} entries", emptied.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn empty_trash(
+    remote: &Remote,
+    opts: &EmptyTrashOptions,
+    ctx: &Context,
+) -> io::Result<Vec<TrashedEntry>> {
+    empty_trash_in(None, remote, opts, ctx)
+}
+
+/// [`empty_trash`], in the clone kept by `pool` if given
+pub(crate) fn empty_trash_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &EmptyTrashOptions,
+    ctx: &Context,
+) -> io::Result<Vec<TrashedEntry>> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    status!("Emptying the trash...");
+    let emptied = change(
+        pool,
+        remote,
+        ctx,
+        "Empty the bitcache trash",
+        |repo_dir, metadata, written| {
+            let now = Utc::now();
+            let (emptied, kept): (Vec<_>, Vec<_>) =
+                metadata.trash()?.into_iter().partition(|item| {
+                    !opts.expired_only
+                        || ctx
+                            .trash_retention
+                            .is_some_and(|retention| item.expired(retention, now))
+                });
+            if emptied.is_empty() {
+                return Ok(None);
+            }
+            for item in &emptied {
+                remove_trashed_file(repo_dir, item, written)?;
+            }
+            metadata.set_trash(kept)?;
+            Ok(Some(emptied))
+        },
+    )?;
+    Ok(emptied.unwrap_or_default())
+}
+
+/// Apply `apply` to the metadata of a clone of `remote` and push the result
+/// in one commit reading `message`, retrying on a busy remote
+///
+/// `apply` adds the repository paths it changes to its last argument and
+/// returns `None` when there is nothing to change, which is returned without
+/// committing. A repository without metadata has no entries for it to find,
+/// so nothing changes there either. On a retry it is applied again to the new
+/// remote head. Trash items past their retention go in the same commit.
+pub(crate) fn change<T>(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+    message: &str,
+    apply: impl FnMut(&Path, &mut Metadata, &mut Vec<String>) -> io::Result<Option<T>>,
+) -> io::Result<Option<T>> {
+    change_metadata(pool, remote, ctx, message, false, apply)
+}
+
+/// [`change`], starting from empty metadata when the repository has none yet,
+/// as the first publish to it does
+pub(crate) fn change_or_create<T>(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+    message: &str,
+    apply: impl FnMut(&Path, &mut Metadata, &mut Vec<String>) -> io::Result<Option<T>>,
+) -> io::Result<Option<T>> {
+    change_metadata(pool, remote, ctx, message, true, apply)
+}
+
+fn change_metadata<T>(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    ctx: &Context,
+    message: &str,
+    create: bool,
+    mut apply: impl FnMut(&Path, &mut Metadata, &mut Vec<String>) -> io::Result<Option<T>>,
+) -> io::Result<Option<T>> {
+    let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    // Nothing was ever published, so `apply` misses as it would on metadata
+    // lacking the entry, and whatever it finds to change is not there to save
+    if !create && !metadata_file.exists() {
+        return apply(repo_dir, &mut Metadata::new(), &mut Vec::new()).map(|_| None);
+    }
+
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(push_backoff(attempt));
+            progress::emit(Event::Retry {
+                attempt,
+                attempts,
+                reason: &rejection,
+            });
+            status!(
+                "Push rejected, merging with the new remote head (attempt {} of {})",
+                attempt,
+                attempts
+            );
+            storage::of(remote).reset(repo_dir, auth)?;
+        }
+
+        let mut metadata = metadata_file.load_or_new()?;
+        let mut written = Vec::new();
+        let Some(changed) = apply(repo_dir, &mut metadata, &mut written)? else {
+            return Ok(None);
+        };
+        expire(repo_dir, &mut metadata, ctx, &mut written)?;
+
+        cancel::check()?;
+        status!("Updating metadata...");
+        written.extend(metadata_file.save(&metadata, ctx.paranoid)?);
+        let written: Vec<&str> = written.iter().map(String::as_str).collect();
+        status!("Committing and pushing changes...");
+        if !storage::of(remote).commit(repo_dir, &written, message)? {
+            status!("No changes to commit");
+            return Ok(Some(changed));
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => return Ok(Some(changed)),
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+    unreachable!("the last attempt returns")
+}
@@ -0,0 +1,192 @@
+//! Replacing the bitstream of an existing entry.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::BitcacheError;
+use crate::layout::MetadataFile;
+use crate::progress::{status, warning};
+use crate::publish::{self, PublishAction, Published, Staged};
+use crate::storage;
+use crate::{compute_md5, fsutil, Context, HashAlgo, Remote};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Which entry to update and with what
+#[derive(Debug, Clone)]
+pub struct UpdateOptions {
+    /// Hash of the source file, the key of the entry
+    pub md5: String,
+    /// Variant to update; may be left out when the source has only one
+    pub variant: Option<String>,
+    /// The new bitstream, stored over the entry's
+    pub bitstream: PathBuf,
+    /// Follow `bitstream` if it is a symlink instead of refusing it
+    pub follow_symlinks: bool,
+    /// Tags to add to the entry's, replacing those with the same key
+    pub tags: BTreeMap<String, String>,
+}
+
+impl UpdateOptions {
+    /// Store `bitstream` for the entry of `md5`, with the same defaults as
+    /// the CLI
+    pub fn new(md5: impl Into<String>, bitstream: impl Into<PathBuf>) -> Self {
+        Self {
+            md5: md5.into(),
+            variant: None,
+            bitstream: bitstream.into(),
+            follow_symlinks: true,
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+/// Replace the bitstream of an existing entry, keeping its hash and path
+///
+/// For a bitstream found to be wrong after publishing when its source has
+/// not changed. The entry is picked like [`crate::get`] picks it and must
+/// exist, or this fails with [`BitcacheError::NotFound`]. The new bitstream
+/// is stored at the entry's path, compressed the way the old one was, and
+/// the entry gets the current time and `opts.tags` on top of its own. A path another entry shares can't be
+/// updated, since that entry would change too. Concurrent publishers are
+/// merged with as for [`crate::publish`].
+///
+/// ```no_run
+/// use bitcache::{Context, Remote, UpdateOptions};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let opts = UpdateOptions::new("d3699e851d7f4fde53ee37c037408af7", "build/top.bit");
+/// let updated = bitcache::update(&remote, &opts, &Context::default())?;
+/// println!("replaced {}", updated.binary_path);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn update(remote: &Remote, opts: &UpdateOptions, ctx: &Context) -> io::Result<Published> {
+    update_in(None, remote, opts, ctx)
+}
+
+/// [`update`], in the clone kept by `pool` if given
+pub(crate) fn update_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &UpdateOptions,
+    ctx: &Context,
+) -> io::Result<Published> {
+    let _entered = ctx.enter();
+    let md5 = &opts.md5;
+    storage::check(remote)?;
+    publish::check_tags(&opts.tags)?;
+    let bitstream = publish::resolve_input(&opts.bitstream, "--bitstream", opts.follow_symlinks)?;
+    let size = fs::metadata(&bitstream)?.len();
+    if size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("--bitstream {} is empty", opts.bitstream.display()),
+        ));
+    }
+    status!("Updating bitstream for MD5: {}", md5);
+
+    let checkout = checkout::checkout(pool, remote, ctx, |dir| {
+        fsutil::ensure_free_space(
+            dir,
+            size.saturating_mul(2),
+            "the bitstream in the clone",
+            publish::WORK_DIR_HINT,
+        )
+    })?;
+    let repo_dir = checkout.dir();
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    let metadata = metadata_file.load_or_new()?;
+    let Some(existing) = metadata.select(md5, opts.variant.as_deref())?.cloned() else {
+        let md5 = match &opts.variant {
+            Some(variant) => format!("{} ({})", md5, variant),
+            None => md5.clone(),
+        };
+        return Err(BitcacheError::NotFound { md5 }.into());
+    };
+    existing.check_path()?;
+    let branch = publish::checked_branch(repo_dir, remote)?;
+    let file_mode = fsutil::file_mode(&bitstream)?;
+    let compressed = publish::compress_input(&bitstream, size, existing.compression()?, None, ctx)?;
+    let (bitstream, size) = match &compressed {
+        Some(compressed) => (compressed.path.clone(), compressed.size),
+        None => (bitstream, size),
+    };
+
+    let dest_bitstream = existing.stored_in(repo_dir)?;
+    let digest = compute_md5(&bitstream)?;
+    let shared = metadata.iter().find(|other| {
+        (other.md5.as_str(), other.name()) != (existing.md5.as_str(), existing.name())
+            && other.binary_path == existing.binary_path
+    });
+    if let Some(other) = shared {
+        if !dest_bitstream.is_file() || compute_md5(&dest_bitstream)? != digest {
+            return Err(BitcacheError::PathTaken {
+                path: existing.binary_path.clone(),
+                reason: format!(
+                    "it also holds the bitstream of {} (MD5 {}), which would change too; publish the new bitstream to its own path with --force instead",
+                    other.source_file,
+                    other.label()
+                ),
+            }
+            .into());
+        }
+    }
+    if existing.binary_md5.as_ref() == Some(&digest) {
+        warning!(
+            "{} already stores this bitstream; only the entry is updated",
+            existing.binary_path
+        );
+    }
+
+    let mut entry = existing.clone();
+    entry.timestamp = chrono::Utc::now().to_rfc3339();
+    entry.branch = branch;
+    entry.binary_md5 = Some(digest.clone());
+    entry.size = Some(size);
+    entry.file_mode = file_mode;
+    entry.original_size = compressed.as_ref().map(|c| c.original_size);
+    entry.tags.extend(opts.tags.clone());
+    let label = entry
+        .hash_algo
+        .parse::<HashAlgo>()
+        .map_or("hash", HashAlgo::label);
+    let message = format!("Update bitstream for source {}: {}", label, entry.label());
+
+    let staged = [Staged {
+        base_entry: Some(existing),
+        entry,
+        bitstream,
+        dest_bitstream,
+        digest,
+        size,
+        displaces: false,
+    }];
+    let sizes = publish::commit_staged(
+        &checkout,
+        &metadata_file,
+        metadata,
+        &staged,
+        &message,
+        remote,
+        ctx,
+    )?;
+    let [Staged { entry, .. }] = staged;
+
+    // The cached artifact is the bitstream just replaced
+    if let Some(store) = ctx.artifact_store(remote) {
+        if let Err(e) = store.remove(&entry.md5) {
+            warning!(
+                "could not remove the entry from the local artifact cache: {}",
+                e
+            );
+        }
+    }
+    Ok(Published {
+        md5: entry.md5.clone(),
+        binary_path: entry.binary_path.clone(),
+        name: entry.name().to_string(),
+        size: sizes[0],
+        commit_message: message,
+        action: PublishAction::Overwrite,
+    })
+}
@@ -0,0 +1,212 @@
+//! Checking the stored bitstreams against their metadata.
+
+use crate::checkout::{self, ClonePool};
+use crate::error::BitcacheError;
+use crate::git::PushOutcome;
+use crate::layout::MetadataFile;
+use crate::progress::{self, status, warning, Event};
+use crate::publish::push_backoff;
+use crate::storage;
+use crate::{cancel, compute_md5, paths, Context, Metadata, MetadataEntry, Remote};
+use serde::Serialize;
+use std::io;
+use std::path::Path;
+use std::thread;
+
+/// What [`verify`] does besides checking
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    /// Remove the entries that failed from the metadata, and commit and push
+    /// the result; the bitstream files are left alone
+    pub fix: bool,
+}
+
+impl VerifyOptions {
+    /// Only check, as the CLI does without `--fix`
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Why an entry failed verification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum Problem {
+    /// The bitstream the entry names is not in the repository
+    Missing,
+    /// The bitstream's MD5 differs from the one recorded at publish
+    Mismatch {
+        /// The recorded `binary_md5`
+        expected: String,
+        /// What the file in the repository hashes to
+        actual: String,
+    },
+}
+
+/// An entry that failed verification
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    #[serde(flatten)]
+    pub entry: MetadataEntry,
+    pub problem: Problem,
+}
+
+/// Result of [`verify`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    /// Number of entries checked
+    pub checked: usize,
+    /// Entries without a recorded `binary_md5`, so only checked to exist
+    pub unverifiable: usize,
+    /// Entries that failed, by MD5
+    pub failures: Vec<Failure>,
+    /// Deprecated entries whose replacement has no entry, by MD5; fixing
+    /// leaves them, since only a person can pick another replacement
+    pub dangling: Vec<MetadataEntry>,
+    /// Whether the failed entries were removed from the repository
+    pub fixed: bool,
+}
+
+/// Check every entry's bitstream against its metadata
+///
+/// A bitstream must exist, and if the entry recorded a `binary_md5` when it
+/// was published, hash to it. Entries published before that was recorded
+/// can only be checked to exist. A deprecated entry's replacement must have
+/// an entry, or it is reported in [`VerifyReport::dangling`]. With
+/// [`VerifyOptions::fix`] the failed entries are then removed like
+/// [`crate::delete`] does, and dropped from the local artifact store.
+///
+/// ```no_run
+/// use bitcache::{Context, Remote, VerifyOptions};
+///
+/// let remote = Remote::new("git@example.com:fpga/bitstreams.git");
+/// let report = bitcache::verify(&remote, &VerifyOptions::new(), &Context::default())?;
+/// for failure in &report.failures {
+///     eprintln!("{}: {:?}", failure.entry.binary_path, failure.problem);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn verify(remote: &Remote, opts: &VerifyOptions, ctx: &Context) -> io::Result<VerifyReport> {
+    verify_in(None, remote, opts, ctx)
+}
+
+/// [`verify`], in the clone kept by `pool` if given
+pub(crate) fn verify_in(
+    pool: Option<&ClonePool>,
+    remote: &Remote,
+    opts: &VerifyOptions,
+    ctx: &Context,
+) -> io::Result<VerifyReport> {
+    let _entered = ctx.enter();
+    storage::check(remote)?;
+    let checkout = checkout::checkout(pool, remote, ctx, |_| Ok(()))?;
+    let repo_dir = checkout.dir();
+    let auth = remote.auth.as_ref();
+    let metadata_file = MetadataFile::of(repo_dir, remote);
+    if !metadata_file.exists() {
+        return Ok(VerifyReport::default());
+    }
+
+    let attempts = ctx.push_attempts.max(1);
+    let mut rejection = String::new();
+    let mut report = VerifyReport::default();
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(push_backoff(attempt));
+            progress::emit(Event::Retry {
+                attempt,
+                attempts,
+                reason: &rejection,
+            });
+            status!(
+                "Push rejected, checking the new remote head (attempt {} of {})",
+                attempt,
+                attempts
+            );
+            storage::of(remote).reset(repo_dir, auth)?;
+        }
+
+        let mut metadata = metadata_file.load()?;
+        report = check(repo_dir, &metadata)?;
+        if !opts.fix || report.failures.is_empty() {
+            return Ok(report);
+        }
+
+        for failure in &report.failures {
+            metadata.remove_entry(&failure.entry.md5, failure.entry.name());
+        }
+        cancel::check()?;
+        status!("Removing {} failed entries...", report.failures.len());
+        let written = metadata_file.save(&metadata, ctx.paranoid)?;
+        let written: Vec<&str> = written.iter().map(String::as_str).collect();
+        let message = format!(
+            "Remove {} entries that failed verification",
+            report.failures.len()
+        );
+        if !storage::of(remote).commit(repo_dir, &written, &message)? {
+            break;
+        }
+        match storage::of(remote).push(repo_dir, auth)? {
+            PushOutcome::Pushed => break,
+            PushOutcome::Rejected(stderr) if attempt == attempts => {
+                return Err(BitcacheError::PushRejected { attempts, stderr }.into());
+            }
+            PushOutcome::Rejected(stderr) => rejection = stderr,
+        }
+    }
+
+    if let Some(store) = ctx.artifact_store(remote) {
+        for failure in &report.failures {
+            if let Err(e) = store.remove(&failure.entry.md5) {
+                warning!(
+                    "could not remove {} from the local artifact cache: {}",
+                    failure.entry.md5,
+                    e
+                );
+            }
+        }
+    }
+    report.fixed = true;
+    Ok(report)
+}
+
+/// Check the bitstream of every entry in `metadata`, in MD5 order
+fn check(repo_dir: &Path, metadata: &Metadata) -> io::Result<VerifyReport> {
+    let mut entries: Vec<_> = metadata.iter().collect();
+    entries.sort_by(|a, b| (&a.md5, a.name()).cmp(&(&b.md5, b.name())));
+    status!("Verifying {} entries...", entries.len());
+
+    let mut report = VerifyReport {
+        checked: entries.len(),
+        ..VerifyReport::default()
+    };
+    for entry in entries {
+        cancel::check()?;
+        let path = paths::long_path(&repo_dir.join(&entry.binary_path));
+        let problem = if !path.is_file() {
+            Some(Problem::Missing)
+        } else if let Some(expected) = &entry.binary_md5 {
+            let actual = compute_md5(&path)?;
+            (actual != *expected).then(|| Problem::Mismatch {
+                expected: expected.clone(),
+                actual,
+            })
+        } else {
+            report.unverifiable += 1;
+            None
+        };
+        if let Some(problem) = problem {
+            report.failures.push(Failure {
+                entry: entry.clone(),
+                problem,
+            });
+        }
+        if let Some(replacement) = &entry.replacement {
+            if metadata.variants(replacement).is_empty() {
+                report.dangling.push(entry.clone());
+            }
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,133 @@
+//! Version and build provenance reporting.
+
+use serde::Serialize;
+use std::any::Any;
+use std::panic;
+use std::process::Command;
+
+/// Package version, e.g. `0.1.0`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// One-line version string, as printed by `--version`
+pub fn short() -> String {
+    format!("bitcache {}", VERSION)
+}
+
+/// Multi-line build information, as printed by `--version --verbose`
+pub fn long() -> String {
+    format!(
+        "bitcache {}\n\
+         commit: {}\n\
+         build date: {}\n\
+         rustc: {}\n\
+         target: {}\n\
+         features: {}",
+        VERSION,
+        env!("BITCACHE_GIT_COMMIT"),
+        env!("BITCACHE_BUILD_DATE"),
+        env!("BITCACHE_RUSTC_VERSION"),
+        env!("BITCACHE_TARGET"),
+        env!("BITCACHE_FEATURES"),
+    )
+}
+
+/// Build information, as printed by `--version --json`
+#[derive(Serialize)]
+pub struct Build {
+    version: &'static str,
+    commit: &'static str,
+    build_date: &'static str,
+    rustc: &'static str,
+    target: &'static str,
+    features: &'static str,
+}
+
+/// The details [`long`] prints
+pub fn build() -> Build {
+    Build {
+        version: VERSION,
+        commit: env!("BITCACHE_GIT_COMMIT"),
+        build_date: env!("BITCACHE_BUILD_DATE"),
+        rustc: env!("BITCACHE_RUSTC_VERSION"),
+        target: env!("BITCACHE_TARGET"),
+        features: env!("BITCACHE_FEATURES"),
+    }
+}
+
+/// Build and environment details, as printed by `bug-report --json`
+#[derive(Serialize)]
+pub struct Environment {
+    #[serde(flatten)]
+    build: Build,
+    os: &'static str,
+    arch: &'static str,
+    git: String,
+}
+
+/// The details [`bug_report`] prints
+pub fn environment() -> Environment {
+    let git = Command::new("git")
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| "not found in PATH".to_string());
+    Environment {
+        build: build(),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        git,
+    }
+}
+
+/// Environment block for pasting into an issue
+pub fn bug_report() -> String {
+    let environment = environment();
+    format!(
+        "```\n\
+         {}\n\
+         os: {} ({})\n\
+         git: {}\n\
+         ```",
+        long(),
+        environment.os,
+        environment.arch,
+        environment.git,
+    )
+}
+
+/// Append build information to panic messages so crash reports are actionable
+///
+/// A broken pipe on stdout is no crash, so it is left for
+/// [`is_broken_pipe`] to catch without a word.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if info.payload_as_str().is_some_and(is_broken_pipe) {
+            return;
+        }
+        default_hook(info);
+        eprintln!();
+        eprintln!("bitcache crashed. Please report this at https://github.com/BondMachineHQ/bitcache/issues");
+        eprintln!("and include the following build information:");
+        eprintln!("{}", long());
+    }));
+}
+
+/// Whether a panic with `message` is `println!` failing because the reader
+/// of stdout, e.g. `head`, exited before everything was printed
+pub fn is_broken_pipe(message: &str) -> bool {
+    // Linux and macOS, then Windows
+    const BROKEN_PIPE: &[&str] = &["Broken pipe", "(os error 232)"];
+    message.starts_with("failed printing to stdout")
+        && BROKEN_PIPE.iter().any(|text| message.contains(text))
+}
+
+/// The message of a panic's `payload`, if it has one
+pub fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+}
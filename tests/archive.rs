@@ -0,0 +1,271 @@
+//! `export` to a `.tar.gz` writes an archive carrying the entries with their
+//! bitstreams, and `import` checks every bitstream of one before merging its
+//! entries in one commit, skipping conflicts unless told to overwrite them.
+
+mod common;
+
+use bitcache::testing::{self, TestRepo};
+use bitcache::{Context, ExportArchiveOptions, GetOptions, ImportArchiveOptions, MetadataEntry};
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+const FIRST_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const SECOND_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// A source repository with two entries, and an empty destination
+fn repos() -> io::Result<(TestRepo, TestRepo)> {
+    let from = TestRepo::new()?;
+    for (md5, path) in [
+        (FIRST_MD5, "boards/first.bit"),
+        (SECOND_MD5, "boards/second.bit"),
+    ] {
+        from.seed(
+            MetadataEntry::new(md5, path, "top.vhd", "2024-01-01T00:00:00Z"),
+            path.as_bytes(),
+        )?;
+    }
+    Ok((from, TestRepo::new()?))
+}
+
+/// A context whose clones stay inside the test's directory
+fn context(repo: &TestRepo) -> Context {
+    Context {
+        work_dir: Some(repo.path().join("work")),
+        cache_dir: None,
+        committer: Some(testing::committer()),
+        ..Context::default()
+    }
+}
+
+/// Number of commits on the head of `repo`
+fn commits(repo: &TestRepo) -> io::Result<usize> {
+    let output = Command::new("git")
+        .args(["--git-dir", repo.url(), "rev-list", "--count", "main"])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .unwrap_or(0))
+}
+
+/// What `get` saves from `repo` for `md5`
+fn get(repo: &TestRepo, md5: &str) -> io::Result<String> {
+    let output = repo.path().join(format!("{}.bit", md5));
+    if output.exists() {
+        fs::remove_file(&output)?;
+    }
+    let opts = GetOptions {
+        output: Some(output.clone()),
+        ..GetOptions::new(md5)
+    };
+    repo.client()?.get(&opts)?.expect("imported entry");
+    fs::read_to_string(output)
+}
+
+/// Run tar in `dir`, failing the test when it fails
+fn tar(dir: &Path, args: &[&str]) {
+    let status = Command::new("tar")
+        .current_dir(dir)
+        .args(args)
+        .status()
+        .expect("tar runs");
+    assert!(status.success(), "tar {:?}", args);
+}
+
+#[test]
+fn carries_the_entries_and_their_bitstreams() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let archive = from.path().join("cache.tar.gz");
+    let archived = bitcache::export_archive(
+        &from.remote(),
+        &ExportArchiveOptions::new(&archive),
+        &context(&from),
+    )?;
+    let exported: Vec<_> = archived.entries.iter().map(|entry| &entry.md5).collect();
+    assert_eq!(exported, [FIRST_MD5, SECOND_MD5]);
+    assert_eq!(
+        archived.bytes,
+        ("boards/first.bit".len() + "boards/second.bit".len()) as u64
+    );
+    assert!(archived.not_found.is_empty());
+
+    let before = commits(&to)?;
+    let ctx = context(&to);
+    let imported =
+        bitcache::import_archive(&to.remote(), &ImportArchiveOptions::new(&archive), &ctx)?;
+    assert!(imported.committed);
+    assert_eq!(imported.added.len(), 2);
+    assert_eq!(commits(&to)?, before + 1);
+    assert_eq!(get(&to, FIRST_MD5)?, "boards/first.bit");
+    assert_eq!(get(&to, SECOND_MD5)?, "boards/second.bit");
+
+    // Importing it again changes nothing
+    let again = bitcache::import_archive(&to.remote(), &ImportArchiveOptions::new(&archive), &ctx)?;
+    assert!(!again.committed);
+    assert_eq!(again.unchanged, 2);
+    assert_eq!(commits(&to)?, before + 1);
+    Ok(())
+}
+
+#[test]
+fn exports_the_entries_asked_for() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let archive = from.path().join("cache.tar.gz");
+    let opts = ExportArchiveOptions {
+        md5s: vec![SECOND_MD5.to_string(), MISSING_MD5.to_string()],
+        ..ExportArchiveOptions::new(&archive)
+    };
+    let archived = bitcache::export_archive(&from.remote(), &opts, &context(&from))?;
+    let exported: Vec<_> = archived.entries.iter().map(|entry| &entry.md5).collect();
+    assert_eq!(exported, [SECOND_MD5]);
+    assert_eq!(archived.not_found, [MISSING_MD5]);
+
+    bitcache::import_archive(
+        &to.remote(),
+        &ImportArchiveOptions::new(&archive),
+        &context(&to),
+    )?;
+    let entries = to.client()?.list()?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].md5, SECOND_MD5);
+    Ok(())
+}
+
+#[test]
+fn refuses_a_damaged_archive_before_committing() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let archive = from.path().join("cache.tar.gz");
+    bitcache::export_archive(
+        &from.remote(),
+        &ExportArchiveOptions::new(&archive),
+        &context(&from),
+    )?;
+    let unpacked = from.path().join("unpacked");
+    fs::create_dir(&unpacked)?;
+    tar(&unpacked, &["-xzf", &archive.to_string_lossy()]);
+    fs::write(unpacked.join("repo/boards/second.bit"), "tampered")?;
+    let damaged = from.path().join("damaged.tar.gz");
+    tar(
+        &unpacked,
+        &["-czf", &damaged.to_string_lossy(), "manifest.json", "repo"],
+    );
+
+    let before = commits(&to)?;
+    let error = bitcache::import_archive(
+        &to.remote(),
+        &ImportArchiveOptions::new(&damaged),
+        &context(&to),
+    )
+    .expect_err("imported a damaged archive");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        error
+            .to_string()
+            .contains("boards/second.bit does not match its recorded checksum"),
+        "{}",
+        error
+    );
+    assert_eq!(commits(&to)?, before);
+    assert!(to.client()?.list()?.is_empty());
+
+    // An archive of a later format is refused too
+    let manifest = unpacked.join("manifest.json");
+    let mut head: Value = serde_json::from_slice(&fs::read(&manifest)?)?;
+    head["format"] = (bitcache::ARCHIVE_FORMAT + 1).into();
+    fs::write(&manifest, serde_json::to_vec(&head)?)?;
+    tar(
+        &unpacked,
+        &["-czf", &damaged.to_string_lossy(), "manifest.json", "repo"],
+    );
+    let error = bitcache::import_archive(
+        &to.remote(),
+        &ImportArchiveOptions::new(&damaged),
+        &context(&to),
+    )
+    .expect_err("imported an archive of a later format");
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    Ok(())
+}
+
+#[test]
+fn skips_conflicts_unless_overwriting() -> io::Result<()> {
+    let (from, to) = repos()?;
+    to.seed(
+        MetadataEntry::new(
+            FIRST_MD5,
+            "old/first.bit",
+            "top.vhd",
+            "2023-01-01T00:00:00Z",
+        ),
+        b"old bitstream",
+    )?;
+    let archive = from.path().join("cache.tar.gz");
+    bitcache::export_archive(
+        &from.remote(),
+        &ExportArchiveOptions::new(&archive),
+        &context(&from),
+    )?;
+
+    let ctx = context(&to);
+    let imported =
+        bitcache::import_archive(&to.remote(), &ImportArchiveOptions::new(&archive), &ctx)?;
+    assert_eq!(imported.added.len(), 1);
+    assert_eq!(imported.skipped.len(), 1);
+    assert_eq!(imported.skipped[0].entry.md5, FIRST_MD5);
+    assert_eq!(get(&to, FIRST_MD5)?, "old bitstream");
+
+    let overwrite = ImportArchiveOptions {
+        overwrite: true,
+        ..ImportArchiveOptions::new(&archive)
+    };
+    let imported = bitcache::import_archive(&to.remote(), &overwrite, &ctx)?;
+    assert_eq!(imported.replaced.len(), 1);
+    assert_eq!(get(&to, FIRST_MD5)?, "boards/first.bit");
+    Ok(())
+}
+
+#[test]
+fn the_command_line_picks_an_archive_by_name_and_content() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let output = bitcache(
+        &from,
+        &["--json", "export", "-o", "cache.tgz", "--md5", FIRST_MD5],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let exported: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(exported["entries"], 1);
+    assert_eq!(exported["bytes"], "boards/first.bit".len());
+
+    // import recognizes it whatever its name
+    let renamed = from.path().join("cache.dat");
+    fs::rename(from.path().join("cache.tgz"), &renamed)?;
+    let output = bitcache(
+        &to,
+        &["--json", "import", "--input", &renamed.to_string_lossy()],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let imported: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(imported["added"][0]["md5"], FIRST_MD5);
+    assert_eq!(get(&to, FIRST_MD5)?, "boards/first.bit");
+
+    let output = bitcache(
+        &to,
+        &["import", "--input", &renamed.to_string_lossy(), "--replace"],
+    )?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--replace"));
+    Ok(())
+}
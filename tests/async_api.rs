@@ -0,0 +1,129 @@
+//! `publish_async` and `get_async` complete on any executor, here a minimal
+//! one, and dropping an unfinished operation cancels it: its git child is
+//! killed and its temporary clone removed, with nothing pushed.
+#![cfg(feature = "async")]
+
+use bitcache::progress::{Event, Phase, ProgressObserver};
+use bitcache::testing::TestRepo;
+use bitcache::{GetOptions, PublishOptions};
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::pin;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{self, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll `future` on this thread until it completes
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = task::Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+fn inputs(dir: &Path) -> io::Result<PublishOptions> {
+    fs::write(dir.join("top.vhd"), "entity top is end;\n")?;
+    fs::write(dir.join("top.bit"), b"top bitstream")?;
+    Ok(PublishOptions::new(
+        dir.join("top.vhd"),
+        dir.join("top.bit"),
+        "bits",
+    ))
+}
+
+fn head(repo: &TestRepo) -> io::Result<String> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.url())
+        .args(["rev-parse", "--verify", "--quiet", "main"])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[test]
+fn publish_async_and_get_async_round_trip() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let opts = inputs(repo.path())?;
+    let published = block_on(bitcache::publish_async(
+        repo.remote(),
+        opts,
+        repo.context()?,
+    ))?;
+
+    let output = repo.path().join("out.bit");
+    let mut opts = GetOptions::new(&published.md5);
+    opts.output = Some(output.clone());
+    let retrieved = block_on(bitcache::get_async(repo.remote(), opts, repo.context()?))?;
+    assert_eq!(retrieved.expect("published").entry.md5, published.md5);
+    assert_eq!(fs::read(output)?, b"top bitstream");
+    Ok(())
+}
+
+/// Raises its flag once a push starts
+#[derive(Default)]
+struct Pushing(AtomicBool);
+
+impl ProgressObserver for Pushing {
+    fn event(&self, event: &Event) {
+        if let Event::PhaseStarted(Phase::Pushing) = event {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+fn wait_for(what: &str, done: impl Fn() -> io::Result<bool>) -> io::Result<()> {
+    let started = Instant::now();
+    while !done()? {
+        assert!(
+            started.elapsed() < Duration::from_secs(20),
+            "timed out waiting for {}",
+            what
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn dropping_an_unfinished_publish_cancels_it() -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let repo = TestRepo::new()?;
+    // A push that would take a minute to be accepted
+    let hook = Path::new(repo.url()).join("hooks/pre-receive");
+    fs::write(&hook, "#!/bin/sh\nsleep 60\n")?;
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755))?;
+
+    let pushing = Arc::new(Pushing::default());
+    let mut ctx = repo.context()?;
+    ctx.observer = Some(pushing.clone());
+    let work_dir = ctx.work_dir.clone().expect("work dir");
+    let operation = bitcache::publish_async(repo.remote(), inputs(repo.path())?, ctx);
+    wait_for("the push", || Ok(pushing.0.load(Ordering::SeqCst)))?;
+
+    // Well before the hook would have let the push through
+    drop(operation);
+    wait_for("the clone to be removed", || {
+        Ok(fs::read_dir(&work_dir)?.next().is_none())
+    })?;
+    assert_eq!(head(&repo)?, "");
+    Ok(())
+}
@@ -0,0 +1,264 @@
+//! The behaviour every [`Backend`] shares, run against both the in-memory
+//! backend and a client of a bare git repository so that they stay alike.
+
+use bitcache::backend::Backend;
+use bitcache::testing::{MemoryBackend, TestRepo};
+use bitcache::{BitcacheError, DeleteOptions, GetOptions, PublishAction, PublishOptions};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Run each case as a test against a fresh backend of either kind, given a
+/// directory to write its inputs and outputs in
+macro_rules! matrix {
+    ($($case:ident),* $(,)?) => {
+        mod memory {
+            use bitcache::testing::{MemoryBackend, TestRepo};
+            $(
+                #[test]
+                fn $case() -> std::io::Result<()> {
+                    // Only for its directory
+                    let repo = TestRepo::new()?;
+                    super::$case(&MemoryBackend::new(), repo.path())
+                }
+            )*
+        }
+
+        mod git {
+            use bitcache::testing::TestRepo;
+            $(
+                #[test]
+                fn $case() -> std::io::Result<()> {
+                    let repo = TestRepo::new()?;
+                    super::$case(&repo.client()?, repo.path())
+                }
+            )*
+        }
+    };
+}
+
+matrix!(
+    publishes_and_gets_a_bitstream,
+    misses_an_unknown_source,
+    republishes_only_with_force,
+    refuses_a_path_holding_another_bitstream,
+    needs_a_variant_among_several,
+    keeps_a_saved_file_without_force,
+    deletes_an_entry,
+    lists_every_entry,
+);
+
+/// Options publishing `bitstream` for a source named `name`, written to `dir`
+fn inputs(dir: &Path, name: &str, bitstream: &str) -> io::Result<PublishOptions> {
+    let source = dir.join(format!("{}.vhd", name));
+    let binary = dir.join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&binary, bitstream)?;
+    Ok(PublishOptions::new(source, binary, "boards/zedboard"))
+}
+
+/// Get the bitstream of `md5` into `dir/out`
+fn get_into(backend: &dyn Backend, dir: &Path, md5: &str) -> io::Result<Option<String>> {
+    let out = dir.join("out");
+    fs::create_dir_all(&out)?;
+    let opts = GetOptions {
+        output: Some(out),
+        force: true,
+        ..GetOptions::new(md5)
+    };
+    match backend.get(&opts)? {
+        Some(retrieved) => Ok(Some(fs::read_to_string(retrieved.path)?)),
+        None => Ok(None),
+    }
+}
+
+fn publishes_and_gets_a_bitstream(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    let published = backend.publish(&inputs(dir, "top", "bitstream")?)?;
+    assert_eq!(published.action, PublishAction::Create);
+    assert_eq!(published.binary_path, "boards/zedboard/top.bit");
+    assert_eq!(published.size, "bitstream".len() as u64);
+    assert!(backend.exists(&published.md5)?);
+
+    let opts = GetOptions {
+        output: Some(dir.join("saved.bit")),
+        ..GetOptions::new(&published.md5)
+    };
+    let retrieved = backend.get(&opts)?.expect("published");
+    assert_eq!(retrieved.path, dir.join("saved.bit"));
+    assert_eq!(retrieved.size, "bitstream".len() as u64);
+    assert_eq!(retrieved.entry.md5, published.md5);
+    assert_eq!(retrieved.entry.source_file, "top.vhd");
+    assert!(!retrieved.from_cache);
+    assert!(!retrieved.synced);
+    assert_eq!(fs::read_to_string(dir.join("saved.bit"))?, "bitstream");
+    Ok(())
+}
+
+fn misses_an_unknown_source(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    let md5 = "ffffffffffffffffffffffffffffffff";
+    // Before anything is published as after
+    for published in 0..2 {
+        assert!(!backend.exists(md5)?);
+        assert_eq!(get_into(backend, dir, md5)?, None);
+        assert!(backend.delete(&DeleteOptions::new(md5))?.is_none());
+        assert_eq!(backend.list()?.len(), published);
+        backend.publish(&inputs(dir, "top", "bitstream")?)?;
+    }
+    Ok(())
+}
+
+fn republishes_only_with_force(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    let opts = inputs(dir, "top", "bitstream")?;
+    let md5 = backend.publish(&opts)?.md5;
+    assert_eq!(backend.publish(&opts)?.action, PublishAction::Unchanged);
+
+    let rebuilt = inputs(dir, "top", "rebuilt bitstream")?;
+    let error = backend.publish(&rebuilt).expect_err("replaced the entry");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::AlreadyPublished { forcible: true, .. })
+        ),
+        "{}",
+        error
+    );
+    assert_eq!(get_into(backend, dir, &md5)?.as_deref(), Some("bitstream"));
+
+    let forced = PublishOptions {
+        force: true,
+        ..rebuilt
+    };
+    assert_eq!(backend.publish(&forced)?.action, PublishAction::Overwrite);
+    assert_eq!(
+        get_into(backend, dir, &md5)?.as_deref(),
+        Some("rebuilt bitstream")
+    );
+    Ok(())
+}
+
+fn refuses_a_path_holding_another_bitstream(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    backend.publish(&inputs(dir, "top", "bitstream")?)?;
+    // Another source whose bitstream has the same file name
+    let other = dir.join("other");
+    fs::create_dir(&other)?;
+    let opts = inputs(&other, "top", "another bitstream")?;
+    let opts = PublishOptions {
+        source: other.join("other.vhd"),
+        ..opts
+    };
+    fs::write(&opts.source, "entity other is end;\n")?;
+    let error = backend
+        .publish(&opts)
+        .expect_err("replaced another bitstream");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::PathTaken { .. })
+        ),
+        "{}",
+        error
+    );
+    assert_eq!(backend.list()?.len(), 1);
+    Ok(())
+}
+
+fn needs_a_variant_among_several(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    let mut md5 = String::new();
+    for (variant, bitstream) in [("arty", "arty bitstream"), ("zed", "zed bitstream")] {
+        let opts = PublishOptions {
+            variant: Some(variant.to_string()),
+            path: format!("boards/{}", variant).into(),
+            ..inputs(dir, "top", bitstream)?
+        };
+        md5 = backend.publish(&opts)?.md5;
+    }
+    let error = get_into(backend, dir, &md5).expect_err("picked a variant");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::AmbiguousVariant { .. })
+        ),
+        "{}",
+        error
+    );
+
+    let opts = GetOptions {
+        variant: Some("zed".to_string()),
+        output: Some(dir.join("zed.bit")),
+        ..GetOptions::new(&md5)
+    };
+    backend.get(&opts)?.expect("published");
+    assert_eq!(fs::read_to_string(dir.join("zed.bit"))?, "zed bitstream");
+
+    let deleted = backend.delete(&DeleteOptions {
+        variant: Some("arty".to_string()),
+        ..DeleteOptions::new(&md5)
+    })?;
+    assert_eq!(deleted.expect("deleted").entry.name(), "arty");
+    assert_eq!(
+        get_into(backend, dir, &md5)?.as_deref(),
+        Some("zed bitstream")
+    );
+    Ok(())
+}
+
+fn keeps_a_saved_file_without_force(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    let md5 = backend.publish(&inputs(dir, "top", "bitstream")?)?.md5;
+    let saved = dir.join("saved.bit");
+    fs::write(&saved, "mine")?;
+    let opts = GetOptions {
+        output: Some(saved.clone()),
+        ..GetOptions::new(&md5)
+    };
+    let error = backend.get(&opts).expect_err("replaced the saved file");
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists, "{}", error);
+    assert_eq!(fs::read_to_string(&saved)?, "mine");
+
+    let forced = GetOptions {
+        force: true,
+        sync: true,
+        ..opts
+    };
+    assert!(backend.get(&forced)?.expect("published").synced);
+    assert_eq!(fs::read_to_string(&saved)?, "bitstream");
+    Ok(())
+}
+
+fn deletes_an_entry(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    let md5 = backend.publish(&inputs(dir, "top", "bitstream")?)?.md5;
+    let deleted = backend.delete(&DeleteOptions {
+        purge_binary: true,
+        ..DeleteOptions::new(&md5)
+    })?;
+    let deleted = deleted.expect("deleted");
+    assert_eq!(deleted.entry.md5, md5);
+    assert!(deleted.purged);
+    assert!(!backend.exists(&md5)?);
+    assert_eq!(get_into(backend, dir, &md5)?, None);
+    assert!(backend.delete(&DeleteOptions::new(&md5))?.is_none());
+    Ok(())
+}
+
+fn lists_every_entry(backend: &dyn Backend, dir: &Path) -> io::Result<()> {
+    let mut published = Vec::new();
+    for name in ["top", "uart"] {
+        published.push(backend.publish(&inputs(dir, name, name)?)?.md5);
+    }
+    let mut listed: Vec<_> = backend.list()?.into_iter().map(|entry| entry.md5).collect();
+    listed.sort();
+    published.sort();
+    assert_eq!(listed, published);
+    Ok(())
+}
+
+/// Only the in-memory backend can be told to fail
+#[test]
+fn the_memory_backend_fails_when_told_to() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let backend = MemoryBackend::new();
+    backend.publish(&inputs(repo.path(), "top", "bitstream")?)?;
+    backend.fail_next(bitcache::testing::Op::List, io::Error::other("unreachable"));
+    assert!(backend.list().is_err());
+    assert_eq!(backend.list()?.len(), 1);
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! Publishing to a `--branch` the repository lacks starts it from the default
+//! branch, while reading from one fails. Entries record their branch, and a
+//! tag can be read from but not published to.
+
+use bitcache::testing::TestRepo;
+use bitcache::{GetOptions, MetadataEntry, PublishOptions};
+use std::fs;
+use std::io;
+use std::process::Command;
+
+const SEEDED_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(
+            SEEDED_MD5,
+            "main/old.bit",
+            "old.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"old bitstream",
+    )?;
+    Ok(repo)
+}
+
+fn md5s(entries: Vec<MetadataEntry>) -> Vec<String> {
+    let mut md5s: Vec<_> = entries.into_iter().map(|entry| entry.md5).collect();
+    md5s.sort();
+    md5s
+}
+
+#[test]
+fn publish_starts_a_missing_branch_from_the_default_branch() -> io::Result<()> {
+    let repo = seeded()?;
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, b"zynq bitstream")?;
+
+    let zynq = repo.builder()?.branch("zynq").build()?;
+    let published = zynq.publish(&PublishOptions::new(&source, &bitstream, "zynq"))?;
+
+    let mut expected = vec![SEEDED_MD5.to_string(), published.md5.clone()];
+    expected.sort();
+    assert_eq!(md5s(zynq.list()?), expected);
+    let recorded = zynq.list()?.into_iter().find(|e| e.md5 == published.md5);
+    assert_eq!(recorded.and_then(|e| e.branch).as_deref(), Some("zynq"));
+    // The default branch is left as it was
+    assert_eq!(md5s(repo.client()?.list()?), [SEEDED_MD5]);
+
+    // A new client clones the branch that now exists and publishes to it
+    let again = repo.builder()?.branch("zynq").build()?;
+    let other = repo.path().join("other.vhd");
+    fs::write(&other, "entity other is end;\n")?;
+    again.publish(&PublishOptions::new(&other, &bitstream, "zynq/other"))?;
+    assert_eq!(zynq.list()?.len(), 3);
+    assert_eq!(repo.client()?.list()?.len(), 1);
+
+    let output = repo.path().join("out.bit");
+    let mut opts = GetOptions::new(&published.md5);
+    opts.output = Some(output.clone());
+    assert!(again.get(&opts)?.is_some());
+    assert_eq!(fs::read(&output)?, b"zynq bitstream");
+    Ok(())
+}
+
+#[test]
+fn reading_a_missing_branch_fails() -> io::Result<()> {
+    let repo = seeded()?;
+    let artix7 = repo.builder()?.branch("artix7").build()?;
+    assert!(artix7.list().is_err());
+    assert!(artix7.exists(SEEDED_MD5).is_err());
+    Ok(())
+}
+
+#[test]
+fn tags_are_read_only() -> io::Result<()> {
+    let repo = seeded()?;
+    let status = Command::new("git")
+        .args(["--git-dir", repo.url(), "tag", "v1", "main"])
+        .status()?;
+    assert!(status.success());
+
+    let v1 = repo.builder()?.branch("v1").build()?;
+    assert_eq!(md5s(v1.list()?), [SEEDED_MD5]);
+
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, b"tagged bitstream")?;
+    let error = v1
+        .publish(&PublishOptions::new(&source, &bitstream, "v1"))
+        .expect_err("published to a tag");
+    assert!(error.to_string().contains("names a tag"), "{}", error);
+    Ok(())
+}
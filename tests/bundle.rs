@@ -0,0 +1,246 @@
+//! `bundle create` writes entries with their bitstreams to a single file,
+//! `bundle list` reads its entries back, and `bundle apply` checks every
+//! bitstream of one before publishing its entries in one commit, refusing a
+//! tampered or truncated bundle before anything is written.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{ApplyBundleOptions, BundleOptions, GetOptions, MetadataEntry};
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const FIRST_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const SECOND_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// A source repository with two entries, and an empty destination
+fn repos() -> io::Result<(TestRepo, TestRepo)> {
+    let from = TestRepo::new()?;
+    for (md5, path) in [
+        (FIRST_MD5, "boards/first.bit"),
+        (SECOND_MD5, "other/second.bit"),
+    ] {
+        from.seed(
+            MetadataEntry::new(md5, path, "top.vhd", "2024-01-01T00:00:00Z"),
+            path.as_bytes(),
+        )?;
+    }
+    Ok((from, TestRepo::new()?))
+}
+
+/// Bundle every entry of `from`
+fn bundled(from: &TestRepo) -> io::Result<PathBuf> {
+    let bundle = from.path().join("cache.bundle");
+    from.client()?.create_bundle(&BundleOptions::new(&bundle))?;
+    Ok(bundle)
+}
+
+/// Number of commits on the head of `repo`
+fn commits(repo: &TestRepo) -> io::Result<usize> {
+    let output = Command::new("git")
+        .args(["--git-dir", repo.url(), "rev-list", "--count", "main"])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .unwrap_or(0))
+}
+
+/// What `get` saves from `repo` for `md5`
+fn get(repo: &TestRepo, md5: &str) -> io::Result<String> {
+    let output = repo.path().join(format!("{}.bit", md5));
+    let opts = GetOptions {
+        output: Some(output.clone()),
+        force: true,
+        ..GetOptions::new(md5)
+    };
+    repo.client()?.get(&opts)?.expect("applied entry");
+    fs::read_to_string(output)
+}
+
+/// Run tar with zstd in `dir`, failing the test when it fails
+fn tar(dir: &Path, args: &[&str]) {
+    let status = Command::new("tar")
+        .current_dir(dir)
+        .arg("--zstd")
+        .args(args)
+        .status()
+        .expect("tar runs");
+    assert!(status.success(), "tar {:?}", args);
+}
+
+/// Apply `bundle` to `to`, expecting it refused with nothing written
+fn refused(to: &TestRepo, bundle: &Path) -> io::Result<io::Error> {
+    let before = commits(to)?;
+    let error = to
+        .client()?
+        .apply_bundle(&ApplyBundleOptions::new(bundle))
+        .expect_err("applied a damaged bundle");
+    assert_eq!(commits(to)?, before);
+    assert!(to.client()?.list()?.is_empty());
+    Ok(error)
+}
+
+#[test]
+fn creates_lists_and_applies_a_bundle() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let bundle = from.path().join("cache.bundle");
+    let created = from.client()?.create_bundle(&BundleOptions::new(&bundle))?;
+    let md5s: Vec<_> = created.entries.iter().map(|entry| &entry.md5).collect();
+    assert_eq!(md5s, [FIRST_MD5, SECOND_MD5]);
+    assert_eq!(
+        created.bytes,
+        ("boards/first.bit".len() + "other/second.bit".len()) as u64
+    );
+
+    let contents = bitcache::read_bundle(&bundle, &from.context()?)?;
+    assert_eq!(contents.format, bitcache::BUNDLE_FORMAT);
+    assert_eq!(contents.bitcache_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(contents.entries, created.entries);
+
+    let before = commits(&to)?;
+    let client = to.client()?;
+    let applied = client.apply_bundle(&ApplyBundleOptions::new(&bundle))?;
+    assert_eq!(applied.added, created.entries);
+    assert_eq!(commits(&to)?, before + 1);
+    assert_eq!(get(&to, FIRST_MD5)?, "boards/first.bit");
+    assert_eq!(get(&to, SECOND_MD5)?, "other/second.bit");
+
+    // Applying it again changes nothing
+    let again = client.apply_bundle(&ApplyBundleOptions::new(&bundle))?;
+    assert!(again.added.is_empty());
+    assert_eq!(again.present.len(), 2);
+    assert_eq!(commits(&to)?, before + 1);
+    Ok(())
+}
+
+#[test]
+fn bundles_the_entries_asked_for() -> io::Result<()> {
+    let (from, _) = repos()?;
+    let client = from.client()?;
+    let bundle = from.path().join("some.bundle");
+    let by_path = client.create_bundle(&BundleOptions {
+        path_prefix: Some("other".to_string()),
+        ..BundleOptions::new(&bundle)
+    })?;
+    let md5s: Vec<_> = by_path.entries.iter().map(|entry| &entry.md5).collect();
+    assert_eq!(md5s, [SECOND_MD5]);
+
+    let by_md5 = client.create_bundle(&BundleOptions {
+        md5s: vec![FIRST_MD5.to_string()],
+        ..BundleOptions::new(&bundle)
+    })?;
+    let md5s: Vec<_> = by_md5.entries.iter().map(|entry| &entry.md5).collect();
+    assert_eq!(md5s, [FIRST_MD5]);
+
+    // A hash without an entry fails, leaving the last bundle as it was
+    let error = client
+        .create_bundle(&BundleOptions {
+            md5s: vec![FIRST_MD5.to_string(), MISSING_MD5.to_string()],
+            ..BundleOptions::new(&bundle)
+        })
+        .expect_err("bundled a missing entry");
+    assert!(error.to_string().contains(MISSING_MD5), "{}", error);
+    let contents = bitcache::read_bundle(&bundle, &from.context()?)?;
+    assert_eq!(contents.entries, by_md5.entries);
+    Ok(())
+}
+
+#[test]
+fn refuses_a_tampered_bundle_before_writing() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let bundle = bundled(&from)?;
+    let unpacked = from.path().join("unpacked");
+    fs::create_dir(&unpacked)?;
+    tar(&unpacked, &["-xf", &bundle.to_string_lossy()]);
+    fs::write(unpacked.join("repo/other/second.bit"), "tampered")?;
+    let tampered = from.path().join("tampered.bundle");
+    tar(
+        &unpacked,
+        &["-cf", &tampered.to_string_lossy(), "manifest.json", "repo"],
+    );
+
+    let error = refused(&to, &tampered)?;
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        error
+            .to_string()
+            .contains("the bitstream other/second.bit does not match its recorded checksum"),
+        "{}",
+        error
+    );
+
+    // A bundle of a later format is refused before its bitstreams are read
+    let manifest = unpacked.join("manifest.json");
+    let mut head: Value = serde_json::from_slice(&fs::read(&manifest)?)?;
+    head["format"] = (bitcache::BUNDLE_FORMAT + 1).into();
+    fs::write(&manifest, serde_json::to_vec(&head)?)?;
+    tar(
+        &unpacked,
+        &["-cf", &tampered.to_string_lossy(), "manifest.json", "repo"],
+    );
+    let error = refused(&to, &tampered)?;
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    Ok(())
+}
+
+#[test]
+fn refuses_a_truncated_bundle_before_writing() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let bundle = bundled(&from)?;
+    let whole = fs::read(&bundle)?;
+    let truncated = from.path().join("truncated.bundle");
+    for end in [whole.len() / 2, whole.len() - 8] {
+        fs::write(&truncated, &whole[..end])?;
+        let error = refused(&to, &truncated)?;
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", error);
+    }
+    Ok(())
+}
+
+#[test]
+fn the_command_line_creates_lists_and_applies() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let output = bitcache(&from, &["--json", "bundle", "create", "-o", "cache.bundle"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let created: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(created["entries"].as_array().map(Vec::len), Some(2));
+
+    let bundle = from.path().join("cache.bundle");
+    // Listing needs no repository
+    let output = Command::new(env!("CARGO_BIN_EXE_bitcache"))
+        .args(["bundle", "list"])
+        .arg(&bundle)
+        .env("XDG_CONFIG_HOME", from.path().join("config"))
+        .output()?;
+    let listed = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(
+        listed.contains(FIRST_MD5) && listed.contains("other/second.bit"),
+        "{}",
+        listed
+    );
+
+    let output = bitcache(
+        &to,
+        &["--json", "bundle", "apply", &bundle.to_string_lossy()],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let applied: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(applied["added"][0]["md5"], FIRST_MD5);
+    assert_eq!(get(&to, SECOND_MD5)?, "other/second.bit");
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Free-function operations keep a shallow clone of each repository in the
+//! cache directory and update it instead of cloning again.
+
+use bitcache::progress::{Recorded, RecordingObserver};
+use bitcache::testing::{self, TestRepo};
+use bitcache::{Context, MetadataEntry, PublishOptions, Remote, METADATA_FILE};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+const TOP_MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+const OTHER_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+
+fn entry(md5: &str, binary_path: &str) -> MetadataEntry {
+    MetadataEntry::new(md5, binary_path, "top.vhd", "2024-01-01T00:00:00Z")
+}
+
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(entry(TOP_MD5, "bits/top.bit"), b"top bitstream")?;
+    Ok(repo)
+}
+
+/// A context caching clones under the test repository's directory
+fn caching(repo: &TestRepo) -> Context {
+    Context {
+        work_dir: Some(repo.path().to_path_buf()),
+        cache_dir: Some(repo.path().join("cache")),
+        committer: Some(testing::committer()),
+        ..Context::default()
+    }
+}
+
+fn md5s(entries: Vec<MetadataEntry>) -> Vec<String> {
+    let mut md5s: Vec<_> = entries.into_iter().map(|entry| entry.md5).collect();
+    md5s.sort();
+    md5s
+}
+
+fn is_shallow(dir: &Path) -> io::Result<bool> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["rev-parse", "--is-shallow-repository"])
+        .output()?;
+    assert!(output.status.success());
+    Ok(String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+#[test]
+fn later_runs_update_the_cached_clone() -> io::Result<()> {
+    let repo = seeded()?;
+    let ctx = caching(&repo);
+    // git clones a local path whole, and a file:// URL like a remote
+    let remote = Remote::new(format!("file://{}", repo.url()));
+    assert_eq!(md5s(bitcache::list(&remote, &ctx)?), [TOP_MD5]);
+
+    let clone = ctx.clone_dir(&remote)?;
+    assert!(clone.join(METADATA_FILE).is_file());
+    assert!(is_shallow(&clone)?);
+    // Leftovers from an earlier run are cleaned away
+    fs::write(clone.join("stray"), b"left behind")?;
+
+    repo.seed(entry(OTHER_MD5, "bits/other.bit"), b"other bitstream")?;
+    assert_eq!(md5s(bitcache::list(&remote, &ctx)?), [OTHER_MD5, TOP_MD5]);
+    assert!(!clone.join("stray").exists());
+    assert!(is_shallow(&clone)?);
+    Ok(())
+}
+
+#[test]
+fn publishes_from_the_cached_clone() -> io::Result<()> {
+    let repo = seeded()?;
+    let ctx = caching(&repo);
+    let remote = Remote::new(format!("file://{}", repo.url()));
+    let source = repo.path().join("new.vhd");
+    let bitstream = repo.path().join("new.bit");
+    fs::write(&source, "entity new is end;\n")?;
+    fs::write(&bitstream, b"new bitstream")?;
+
+    let published = bitcache::publish(
+        &remote,
+        &PublishOptions::new(&source, &bitstream, "new"),
+        &ctx,
+    )?;
+    let mut expected = vec![TOP_MD5.to_string(), published.md5];
+    expected.sort();
+    // Another client reads what the shallow clone pushed
+    assert_eq!(md5s(repo.client()?.list()?), expected);
+    Ok(())
+}
+
+#[test]
+fn a_clone_of_an_empty_repository_is_updated_like_any_other() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let recorder = Arc::new(RecordingObserver::new());
+    let ctx = Context {
+        observer: Some(recorder.clone()),
+        ..caching(&repo)
+    };
+    let remote = Remote::new(format!("file://{}", repo.url()));
+    for name in ["first", "second"] {
+        let source = repo.path().join(format!("{}.vhd", name));
+        let bitstream = repo.path().join(format!("{}.bit", name));
+        fs::write(&source, format!("entity {} is end;\n", name))?;
+        fs::write(&bitstream, name)?;
+        bitcache::publish(
+            &remote,
+            &PublishOptions::new(&source, &bitstream, "bits"),
+            &ctx,
+        )?;
+    }
+    let events = recorder.events();
+    let clones = events
+        .iter()
+        .filter(|event| **event == Recorded::GitCommand("clone".to_string()))
+        .count();
+    assert_eq!(clones, 1, "{:?}", events);
+    let warnings: Vec<_> = events
+        .iter()
+        .filter(|event| matches!(event, Recorded::Warning(_)))
+        .collect();
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert_eq!(repo.client()?.list()?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn no_cache_clones_into_a_temporary_directory() -> io::Result<()> {
+    let repo = seeded()?;
+    let ctx = Context {
+        clone_cache: false,
+        ..caching(&repo)
+    };
+    assert_eq!(md5s(bitcache::list(&repo.remote(), &ctx)?), [TOP_MD5]);
+    assert!(!repo.path().join("cache/clones").exists());
+    Ok(())
+}
+
+#[test]
+fn sync_repository_returns_the_updated_clone() -> io::Result<()> {
+    let repo = seeded()?;
+    let ctx = caching(&repo);
+    let remote = repo.remote();
+    let dir = bitcache::sync_repository(&remote, &ctx)?;
+    assert_eq!(dir, ctx.clone_dir(&remote)?);
+    assert!(dir.join("bits/top.bit").is_file());
+
+    repo.seed(entry(OTHER_MD5, "bits/other.bit"), b"other bitstream")?;
+    bitcache::sync_repository(&remote, &ctx)?;
+    assert_eq!(fs::read(dir.join("bits/other.bit"))?, b"other bitstream");
+    Ok(())
+}
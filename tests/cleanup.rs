@@ -0,0 +1,210 @@
+//! A publish that fails while cloning, copying, committing or pushing
+//! removes its temporary directory and leaves the remote as it was; with
+//! `--keep-temp` the directory is kept as the failure left it, and a cached
+//! clone is left without the bitstream.
+#![cfg(unix)]
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{Context, MetadataEntry, Remote};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const SEEDED_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+
+#[derive(Clone, Copy, Debug)]
+enum Failure {
+    Clone,
+    Copy,
+    Commit,
+    Push,
+}
+
+/// Write an executable hook script that rejects everything
+fn rejecting_hook(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path.parent().expect("hook directory"))?;
+    fs::write(path, "#!/bin/sh\necho rejected by the test >&2\nexit 1\n")?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+}
+
+fn git(repo: &TestRepo, args: &[&str]) -> io::Result<String> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.url())
+        .args(args)
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The temporary directories left in the work directory
+fn scratch_dirs(repo: &TestRepo) -> io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for dir in fs::read_dir(repo.path())? {
+        let dir = dir?;
+        if dir.file_name().to_string_lossy().starts_with("bitcache-") {
+            dirs.push(dir.path());
+        }
+    }
+    Ok(dirs)
+}
+
+/// Publish with `options` into a repository set up to fail at `failure`,
+/// returning the output, the head of main before the publish and the
+/// repository
+fn failed_publish(failure: Failure, options: &[&str]) -> io::Result<(Output, String, TestRepo)> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(
+            SEEDED_MD5,
+            "bits/top.bit/inside.bit",
+            "other.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"other bitstream",
+    )?;
+    let head = git(&repo, &["rev-parse", "main"])?;
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(repo.path().join("top.bit"), b"top bitstream")?;
+
+    let mut args = vec!["--push-retries", "0"];
+    args.extend_from_slice(options);
+    // The seeded entry's directory is where this bitstream would go
+    let path = if matches!(failure, Failure::Copy) {
+        "bits"
+    } else {
+        "new"
+    };
+    args.extend([
+        "publish",
+        "--source",
+        "top.vhd",
+        "--bitstream",
+        "top.bit",
+        "--path",
+        path,
+    ]);
+    let mut command = common::command(&repo, &args)?;
+
+    let moved = repo.path().join("moved.git");
+    match failure {
+        // An existing directory, but no repository to clone
+        Failure::Clone => {
+            fs::rename(repo.url(), &moved)?;
+            fs::create_dir(repo.url())?;
+        }
+        Failure::Copy => {}
+        Failure::Commit => {
+            let hooks = repo.path().join("hooks");
+            rejecting_hook(&hooks.join("pre-commit"))?;
+            let config = repo.path().join("gitconfig");
+            fs::write(
+                &config,
+                format!("[core]\n\thooksPath = {}\n", hooks.display()),
+            )?;
+            command.env("GIT_CONFIG_GLOBAL", config);
+        }
+        Failure::Push => rejecting_hook(&Path::new(repo.url()).join("hooks/pre-receive"))?,
+    }
+    let output = command.output()?;
+    if matches!(failure, Failure::Clone) {
+        fs::remove_dir(repo.url())?;
+        fs::rename(&moved, repo.url())?;
+    }
+    Ok((output, head, repo))
+}
+
+/// The error each failure is expected to end with
+fn expected(failure: Failure) -> &'static str {
+    match failure {
+        Failure::Clone => "clone",
+        Failure::Copy => "Is a directory",
+        Failure::Commit => "rejected by the test",
+        Failure::Push => "remote: rejected by the test",
+    }
+}
+
+const FAILURES: [Failure; 4] = [
+    Failure::Clone,
+    Failure::Copy,
+    Failure::Commit,
+    Failure::Push,
+];
+
+#[test]
+fn a_failure_at_any_phase_leaves_nothing_behind() -> io::Result<()> {
+    for failure in FAILURES {
+        let (output, head, repo) = failed_publish(failure, &["--no-cache"])?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{:?} succeeded", failure);
+        assert!(
+            stderr.contains(expected(failure)),
+            "{:?} failed otherwise: {}",
+            failure,
+            stderr
+        );
+        assert_eq!(scratch_dirs(&repo)?, Vec::<PathBuf>::new(), "{:?}", failure);
+        assert_eq!(git(&repo, &["rev-parse", "main"])?, head, "{:?}", failure);
+    }
+    Ok(())
+}
+
+#[test]
+fn keep_temp_keeps_the_clone_as_the_failure_left_it() -> io::Result<()> {
+    for failure in FAILURES {
+        let (output, head, repo) = failed_publish(failure, &["--no-cache", "--keep-temp"])?;
+        assert!(!output.status.success(), "{:?} succeeded", failure);
+        let kept = scratch_dirs(&repo)?;
+        assert_eq!(kept.len(), 1, "{:?}", failure);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!("Kept temporary directory: {}", kept[0].display())),
+            "{:?}: {}",
+            failure,
+            stderr
+        );
+        assert_eq!(git(&repo, &["rev-parse", "main"])?, head, "{:?}", failure);
+
+        let clone = kept[0].join("repo");
+        match failure {
+            Failure::Clone => {}
+            Failure::Copy => assert!(clone.join("bits/top.bit").is_dir()),
+            // The bitstream is in place, and committed when the push failed
+            Failure::Commit | Failure::Push => {
+                assert_eq!(fs::read(clone.join("new/top.bit"))?, b"top bitstream");
+                let committed = Command::new("git")
+                    .current_dir(&clone)
+                    .args(["rev-parse", "HEAD"])
+                    .output()?;
+                let committed = String::from_utf8_lossy(&committed.stdout);
+                assert_eq!(
+                    committed.trim() != head,
+                    matches!(failure, Failure::Push),
+                    "{:?}",
+                    failure
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn a_cached_clone_keeps_no_bitstream_that_failed_to_publish() -> io::Result<()> {
+    for failure in [Failure::Commit, Failure::Push] {
+        let (output, head, repo) = failed_publish(failure, &[])?;
+        assert!(!output.status.success(), "{:?} succeeded", failure);
+        assert_eq!(git(&repo, &["rev-parse", "main"])?, head, "{:?}", failure);
+        let ctx = Context {
+            cache_dir: Some(repo.path().join("cache")),
+            ..repo.context()?
+        };
+        let clone = ctx.clone_dir(&Remote::new(repo.url()))?;
+        assert!(clone.join(".git").is_dir(), "{:?}", failure);
+        assert!(!clone.join("new").exists(), "{:?}", failure);
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! The library drives a repository without the command line: a [`Bitcache`]
+//! client publishes, lists and retrieves against a bare repository, and its
+//! errors tell a missing entry from a failing git.
+
+use bitcache::testing::{self, TestRepo};
+use bitcache::{
+    Auth, Bitcache, BitcacheError, Context, GetOptions, MetadataEntry, PublishOptions, Remote,
+};
+use std::fs;
+use std::io;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// A source and bitstream in the test repository's directory
+fn inputs(repo: &TestRepo) -> io::Result<PublishOptions> {
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, b"top bitstream")?;
+    Ok(PublishOptions::new(source, bitstream, "boards/zedboard"))
+}
+
+#[test]
+fn publishes_lists_and_gets() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    assert!(client.list()?.is_empty());
+
+    let published = client.publish(&inputs(&repo)?)?;
+    assert_eq!(
+        published.md5,
+        bitcache::compute_md5(&repo.path().join("top.vhd"))?
+    );
+    assert_eq!(published.binary_path, "boards/zedboard/top.bit");
+    assert!(client.exists(&published.md5)?);
+
+    let entries: Vec<MetadataEntry> = client.list()?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].md5, published.md5);
+    assert_eq!(entries[0].source_file, "top.vhd");
+
+    let output = repo.path().join("out.bit");
+    let opts = GetOptions {
+        output: Some(output.clone()),
+        ..GetOptions::new(&published.md5)
+    };
+    let retrieved = client.get(&opts)?.expect("published entry");
+    assert_eq!(retrieved.path, output);
+    assert_eq!(fs::read(output)?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn free_functions_see_what_a_client_published() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let published = repo.client()?.publish(&inputs(&repo)?)?;
+    let ctx = Context {
+        work_dir: Some(repo.path().join("work")),
+        cache_dir: None,
+        committer: Some(testing::committer()),
+        ..Context::default()
+    };
+    assert!(bitcache::exists(&repo.remote(), &published.md5, &ctx)?);
+    assert_eq!(bitcache::list(&repo.remote(), &ctx)?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn a_missing_entry_is_not_an_error() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    // Until something is published there is no metadata, and so no entry
+    assert!(repo.client()?.get(&GetOptions::new(MISSING_MD5))?.is_none());
+
+    repo.seed(
+        MetadataEntry::new(
+            "0cc175b9c0f1b6a831c399e269772661",
+            "old/old.bit",
+            "old.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"old bitstream",
+    )?;
+    let client = repo.client()?;
+    assert!(!client.exists(MISSING_MD5)?);
+    assert!(client.get(&GetOptions::new(MISSING_MD5))?.is_none());
+    Ok(())
+}
+
+#[test]
+fn a_failing_git_is_a_git_error() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let missing = repo.path().join("missing.git");
+    let client = Bitcache::builder()
+        .repo(format!("file://{}", missing.display()))
+        .work_dir(repo.path().join("work"))
+        .cache_dir(None)
+        .build()?;
+    let error = client.list().expect_err("listed a missing repository");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::Git { .. }) => {}
+        other => panic!("unexpected error {:?}", other),
+    }
+    // The free functions fail the same way
+    let error = bitcache::list(&Remote::new(client.remote().url.clone()), client.context())
+        .expect_err("listed a missing repository");
+    assert!(matches!(
+        BitcacheError::of(&error),
+        Some(BitcacheError::Git { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn a_missing_ssh_key_fails_before_git_runs() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let key = repo.path().join("missing_key");
+    let check = |error: io::Error| match BitcacheError::of(&error) {
+        Some(BitcacheError::InvalidArgument { flag, value, .. }) => {
+            assert_eq!(*flag, "--ssh-key");
+            assert_eq!(value, &key.display().to_string());
+        }
+        other => panic!("unexpected error {:?}", other),
+    };
+    match repo.builder()?.ssh_key(&key).build() {
+        Ok(_) => panic!("built with a missing key"),
+        Err(error) => check(error),
+    }
+
+    let remote = Remote {
+        auth: Some(Auth::SshKey(key.clone())),
+        ..repo.remote()
+    };
+    let ctx = Context {
+        work_dir: Some(repo.path().join("clones")),
+        cache_dir: None,
+        ..Context::default()
+    };
+    check(bitcache::list(&remote, &ctx).expect_err("listed with a missing key"));
+    // Nothing was cloned
+    assert!(!repo.path().join("clones").exists());
+    Ok(())
+}
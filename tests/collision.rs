@@ -0,0 +1,174 @@
+//! `get` gives the saved bitstream the permission bits it was published
+//! with, and `publish --on-collision` decides what happens when the path
+//! already holds another entry's bitstream.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, GetOptions, OnCollision, PublishOptions};
+use common::bitcache;
+use std::fs;
+use std::io;
+
+/// Options publishing `bitstream` as `top.bit` for the source `name`
+fn inputs(repo: &TestRepo, name: &str, bitstream: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let binary = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&binary, bitstream)?;
+    Ok(PublishOptions {
+        rename_in_repo: Some("top.bit".into()),
+        ..PublishOptions::new(source, binary, "boards/zedboard")
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn get_restores_the_permission_bits() -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let opts = inputs(&repo, "top", "top bitstream")?;
+    fs::set_permissions(&opts.bitstream, fs::Permissions::from_mode(0o755))?;
+    let published = client.publish(&opts)?;
+    assert_eq!(client.list()?[0].file_mode, Some(0o755));
+
+    let output = repo.path().join("out.bit");
+    client
+        .get(&GetOptions {
+            output: Some(output.clone()),
+            ..GetOptions::new(&published.md5)
+        })?
+        .expect("published entry");
+    assert_eq!(fs::metadata(&output)?.permissions().mode() & 0o777, 0o755);
+    Ok(())
+}
+
+#[test]
+fn fails_by_default() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    client.publish(&inputs(&repo, "top", "top bitstream")?)?;
+    let error = client
+        .publish(&inputs(&repo, "uart", "uart bitstream")?)
+        .expect_err("published over another bitstream");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::PathTaken { path, reason }) => {
+            assert_eq!(path, "boards/zedboard/top.bit");
+            assert!(reason.contains("--on-collision rename"), "{}", reason);
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn rename_stores_under_the_hash_and_gets_the_name() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let top = client.publish(&inputs(&repo, "top", "top bitstream")?)?;
+    let opts = PublishOptions {
+        on_collision: OnCollision::Rename,
+        ..inputs(&repo, "uart", "uart bitstream")?
+    };
+    let uart = client.publish(&opts)?;
+    assert_eq!(
+        uart.binary_path,
+        format!("boards/zedboard/{}-top.bit", uart.md5)
+    );
+    assert_eq!(uart.name, "top.bit");
+    // Publishing it again finds it where it was stored
+    assert_eq!(
+        client.publish(&opts)?.action,
+        bitcache::PublishAction::Unchanged
+    );
+
+    let entries = client.list()?;
+    assert_eq!(entries.len(), 2);
+    let dir = repo.path().join("saved");
+    fs::create_dir(&dir)?;
+    let retrieved = client
+        .get(&GetOptions {
+            output: Some(dir.clone()),
+            ..GetOptions::new(&uart.md5)
+        })?
+        .expect("renamed entry");
+    assert_eq!(retrieved.path, dir.join("top.bit"));
+    assert_eq!(fs::read(&retrieved.path)?, b"uart bitstream");
+
+    // The first entry still has its own bitstream
+    let output = repo.path().join("top-out.bit");
+    client
+        .get(&GetOptions {
+            output: Some(output.clone()),
+            ..GetOptions::new(&top.md5)
+        })?
+        .expect("first entry");
+    assert_eq!(fs::read(output)?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn overwrite_removes_the_other_entry() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let top = client.publish(&inputs(&repo, "top", "top bitstream")?)?;
+    let uart = client.publish(&PublishOptions {
+        on_collision: OnCollision::Overwrite,
+        ..inputs(&repo, "uart", "uart bitstream")?
+    })?;
+    assert_eq!(uart.binary_path, "boards/zedboard/top.bit");
+
+    let entries = client.list()?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].md5, uart.md5);
+    assert!(!client.exists(&top.md5)?);
+    Ok(())
+}
+
+#[test]
+fn the_command_line_takes_on_collision() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    repo.client()?
+        .publish(&inputs(&repo, "top", "top bitstream")?)?;
+    inputs(&repo, "uart", "uart bitstream")?;
+    let publish = |action: &str| {
+        bitcache(
+            &repo,
+            &[
+                "publish",
+                "--source",
+                "uart.vhd",
+                "--bitstream",
+                "uart.bit",
+                "--path",
+                "boards/zedboard",
+                "--rename-in-repo",
+                "top.bit",
+                "--on-collision",
+                action,
+            ],
+        )
+    };
+
+    let output = publish("fail")?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--on-collision rename"));
+    let output = publish("sideways")?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("sideways"));
+
+    let output = publish("rename")?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let entries = repo.client()?.list()?;
+    assert_eq!(entries.len(), 2);
+    assert!(entries
+        .iter()
+        .any(|entry| entry.file_name.as_deref() == Some("top.bit")));
+    Ok(())
+}
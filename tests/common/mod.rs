@@ -0,0 +1,45 @@
+//! Fixtures shared by the integration tests that run the `bitcache` binary.
+// Each test crate compiles this module and uses only some of it
+#![allow(dead_code)]
+
+use bitcache::testing::{self, TestRepo};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Run the bitcache binary against `repo`, away from any config file or git
+/// identity of the user running the tests
+pub fn bitcache(repo: &TestRepo, args: &[&str]) -> io::Result<Output> {
+    command(repo, args)?.output()
+}
+
+/// The command [`bitcache`] runs, to spawn it instead
+pub fn command(repo: &TestRepo, args: &[&str]) -> io::Result<Command> {
+    let mut command = command_in(repo.path(), args)?;
+    command.args(["--repo", repo.url()]);
+    Ok(command)
+}
+
+/// Run the bitcache binary in `dir`, keeping its configuration, cache and
+/// clones there, for runs that name their repositories themselves
+pub fn bitcache_in(dir: &Path, args: &[&str]) -> io::Result<Output> {
+    command_in(dir, args)?.output()
+}
+
+/// The command [`bitcache_in`] runs, to add to it
+pub fn command_in(dir: &Path, args: &[&str]) -> io::Result<Command> {
+    let config = dir.join("config");
+    fs::create_dir_all(&config)?;
+    let mut command = Command::new(env!("CARGO_BIN_EXE_bitcache"));
+    command
+        .args(args)
+        .arg("--cache-dir")
+        .arg(dir.join("cache"))
+        .arg("--work-dir")
+        .arg(dir)
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", &config)
+        .envs(testing::identity_env());
+    Ok(command)
+}
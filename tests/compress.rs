@@ -0,0 +1,232 @@
+//! `publish --compress` stores a bitstream gzip or zstd compressed, and
+//! `get` saves it decompressed under its original name.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, Compression, GetOptions, MetadataEntry, PublishOptions};
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+const SEEDED_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Compressible, like a real bitstream's runs of unused frames
+fn bitstream() -> Vec<u8> {
+    (0..256 * 1024u32)
+        .map(|i| if i % 13 == 0 { (i % 241) as u8 } else { 0 })
+        .collect()
+}
+
+/// A repository with one entry stored as it is, and the options to
+/// publish a zstd compressed bitstream to it
+fn seeded() -> io::Result<(TestRepo, PublishOptions)> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(SEEDED_MD5, "old/old.bit", "old.vhd", "2024-01-01T00:00:00Z"),
+        b"old bitstream",
+    )?;
+    let source = repo.path().join("top.vhd");
+    let bitstream_path = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream_path, bitstream())?;
+    let opts = PublishOptions {
+        compression: Compression::Zstd,
+        ..PublishOptions::new(source, bitstream_path, "boards/zedboard")
+    };
+    Ok((repo, opts))
+}
+
+fn get_into(repo: &TestRepo, md5: &str, dir: &str) -> io::Result<Vec<u8>> {
+    let mut output = repo.path().join(dir).into_os_string();
+    output.push(std::path::MAIN_SEPARATOR_STR);
+    let opts = GetOptions {
+        output: Some(output.into()),
+        ..GetOptions::new(md5)
+    };
+    let retrieved = repo.client()?.get(&opts)?.expect("entry exists");
+    fs::read(retrieved.path)
+}
+
+#[test]
+fn round_trips_a_compressed_bitstream() -> io::Result<()> {
+    let (repo, opts) = seeded()?;
+    let published = repo.client()?.publish(&opts)?;
+    assert_eq!(published.binary_path, "boards/zedboard/top.bit.zst");
+    assert!(published.size < bitstream().len() as u64);
+
+    let entry = repo
+        .client()?
+        .list()?
+        .into_iter()
+        .find(|entry| entry.md5 == published.md5)
+        .expect("published entry");
+    assert_eq!(entry.compression.as_deref(), Some("zstd"));
+    assert_eq!(entry.original_size, Some(bitstream().len() as u64));
+    let stored = stored(&repo, &entry)?;
+    assert_eq!(stored[..4], ZSTD_MAGIC);
+    // The recorded checksum is of what the repository holds
+    assert_eq!(
+        entry.binary_md5,
+        Some(format!("{:x}", md5::compute(&stored)))
+    );
+
+    assert_eq!(get_into(&repo, &published.md5, "out")?, bitstream());
+    assert!(repo.path().join("out/top.bit").is_file());
+    Ok(())
+}
+
+#[test]
+fn round_trips_a_gzip_compressed_bitstream() -> io::Result<()> {
+    let (repo, opts) = seeded()?;
+    let opts = PublishOptions {
+        compression: Compression::Gzip,
+        ..opts
+    };
+    let published = repo.client()?.publish(&opts)?;
+    assert_eq!(published.binary_path, "boards/zedboard/top.bit.gz");
+    assert!(published.size < bitstream().len() as u64);
+
+    let entry = repo
+        .client()?
+        .list()?
+        .into_iter()
+        .find(|entry| entry.md5 == published.md5)
+        .expect("published entry");
+    assert_eq!(entry.compression.as_deref(), Some("gzip"));
+    assert_eq!(entry.name(), "top.bit");
+    assert_eq!(stored(&repo, &entry)?[..2], GZIP_MAGIC);
+
+    assert_eq!(get_into(&repo, &published.md5, "out")?, bitstream());
+    assert!(repo.path().join("out/top.bit").is_file());
+
+    // Publishing the same bitstream again finds it unchanged
+    let again = repo.client()?.publish(&opts)?;
+    assert!(again.commit_message.is_empty());
+    Ok(())
+}
+
+#[test]
+fn serves_a_compressed_bitstream_from_the_artifact_cache() -> io::Result<()> {
+    let (repo, opts) = seeded()?;
+    let published = repo.client()?.publish(&opts)?;
+    let client = repo
+        .builder()?
+        .cache_dir(Some(repo.path().join("cache")))
+        .build()?;
+    for dir in ["first", "second"] {
+        let opts = GetOptions {
+            output: Some(repo.path().join(dir).join("top.bit")),
+            ..GetOptions::new(&published.md5)
+        };
+        let retrieved = client.get(&opts)?.expect("entry exists");
+        assert_eq!(retrieved.from_cache, dir == "second");
+        assert_eq!(fs::read(retrieved.path)?, bitstream());
+    }
+    Ok(())
+}
+
+#[test]
+fn uncompressed_entries_keep_working() -> io::Result<()> {
+    let (repo, opts) = seeded()?;
+    repo.client()?.publish(&opts)?;
+    assert_eq!(get_into(&repo, SEEDED_MD5, "out")?, b"old bitstream");
+    assert!(repo.path().join("out/old.bit").is_file());
+    Ok(())
+}
+
+#[test]
+fn every_level_round_trips() -> io::Result<()> {
+    let (repo, opts) = seeded()?;
+    for level in [1, 19, 22] {
+        let source = repo.path().join(format!("top{}.vhd", level));
+        fs::write(&source, format!("entity top{} is end;\n", level))?;
+        // Each level stores different bytes, so each needs its own path
+        let opts = PublishOptions {
+            source,
+            path: format!("level{}", level).into(),
+            compress_level: Some(level),
+            ..opts.clone()
+        };
+        let published = repo.client()?.publish(&opts)?;
+        let dir = format!("level{}", level);
+        assert_eq!(get_into(&repo, &published.md5, &dir)?, bitstream());
+    }
+    Ok(())
+}
+
+#[test]
+fn refuses_a_level_the_compression_lacks() -> io::Result<()> {
+    let (repo, opts) = seeded()?;
+    for (compression, level) in [(Compression::Zstd, 23), (Compression::Gzip, 10)] {
+        let opts = PublishOptions {
+            compression,
+            compress_level: Some(level),
+            ..opts.clone()
+        };
+        let error = repo.client()?.publish(&opts).expect_err("published");
+        match BitcacheError::of(&error) {
+            Some(BitcacheError::InvalidArgument { flag, reason, .. }) => {
+                assert_eq!(*flag, "--compress-level");
+                assert!(reason.contains(compression.name()), "{}", reason);
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn the_command_line_compresses_with_zstd_unless_told_otherwise() -> io::Result<()> {
+    let (repo, _) = seeded()?;
+    let publish = |name: &str, compress: &[&str]| {
+        let source = format!("{}.vhd", name);
+        let bitstream_file = format!("{}.bit", name);
+        fs::write(
+            repo.path().join(&source),
+            format!("entity {} is end;\n", name),
+        )?;
+        fs::write(repo.path().join(&bitstream_file), bitstream())?;
+        let mut args = vec![
+            "publish",
+            "--source",
+            &source,
+            "--bitstream",
+            &bitstream_file,
+        ];
+        args.extend(compress);
+        args.extend(["--path", "boards/zedboard"]);
+        bitcache(&repo, &args)
+    };
+    for (name, compress, stored) in [
+        ("bare", &["--compress"][..], "bare.bit.zst"),
+        ("gzip", &["--compress", "gzip"][..], "gzip.bit.gz"),
+        ("plain", &[][..], "plain.bit"),
+    ] {
+        let output = publish(name, compress)?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(repo
+            .client()?
+            .list()?
+            .iter()
+            .any(|entry| entry.binary_path == format!("boards/zedboard/{}", stored)));
+    }
+    Ok(())
+}
+
+/// What the repository holds for `entry`
+fn stored(repo: &TestRepo, entry: &MetadataEntry) -> io::Result<Vec<u8>> {
+    let stored = Command::new("git")
+        .args(["--git-dir", repo.url(), "show"])
+        .arg(format!("main:{}", entry.binary_path))
+        .output()?;
+    assert!(stored.status.success());
+    Ok(stored.stdout)
+}
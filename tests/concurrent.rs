@@ -0,0 +1,156 @@
+//! Publishers racing for one repository all land: a publish whose push is
+//! rejected because the remote moved merges its entry into the new head and
+//! pushes again, and only fails when the remote changed the same entry.
+
+use bitcache::progress::{Event, ProgressObserver};
+use bitcache::testing::{self, TestRepo};
+use bitcache::{BitcacheError, Context, MetadataEntry, PublishOptions};
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const PUBLISHERS: usize = 6;
+
+/// Options publishing a bitstream for the source `name`, written under `repo`
+fn inputs(repo: &TestRepo, name: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let bitstream = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&bitstream, format!("{} bitstream", name))?;
+    Ok(PublishOptions::new(source, bitstream, "boards/zedboard"))
+}
+
+/// Runs `action` once, the first time the publish is about to push
+struct BeforePush(Mutex<Option<Box<dyn FnOnce() + Send>>>);
+
+impl BeforePush {
+    fn new(action: impl FnOnce() + Send + 'static) -> Arc<Self> {
+        Arc::new(Self(Mutex::new(Some(Box::new(action)))))
+    }
+}
+
+impl ProgressObserver for BeforePush {
+    fn event(&self, event: &Event) {
+        if let Event::Status(status) = event {
+            if status.starts_with("Committing and pushing") {
+                if let Some(action) = self.0.lock().unwrap().take() {
+                    action();
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn concurrent_publishers_all_land() -> io::Result<()> {
+    let repo = Arc::new(TestRepo::new()?);
+    let publishers: Vec<_> = (0..PUBLISHERS)
+        .map(|i| {
+            let repo = Arc::clone(&repo);
+            thread::spawn(move || -> io::Result<String> {
+                let opts = inputs(&repo, &format!("design{}", i))?;
+                let work = repo.path().join(format!("work{}", i));
+                fs::create_dir_all(&work)?;
+                let ctx = Context {
+                    work_dir: Some(work),
+                    cache_dir: None,
+                    push_attempts: 4 * PUBLISHERS as u32,
+                    committer: Some(testing::committer()),
+                    ..Context::default()
+                };
+                Ok(bitcache::publish(&repo.remote(), &opts, &ctx)?.md5)
+            })
+        })
+        .collect();
+    let mut published: Vec<String> = publishers
+        .into_iter()
+        .map(|publisher| publisher.join().expect("publisher panicked"))
+        .collect::<io::Result<_>>()?;
+    published.sort();
+
+    let mut listed: Vec<String> = repo
+        .client()?
+        .list()?
+        .into_iter()
+        .map(|entry| entry.md5)
+        .collect();
+    listed.sort();
+    assert_eq!(listed, published);
+    Ok(())
+}
+
+#[test]
+fn a_rejected_push_merges_with_the_new_head() -> io::Result<()> {
+    let repo = Arc::new(TestRepo::new()?);
+    let racing = Arc::clone(&repo);
+    let client = repo
+        .builder()?
+        .observer(BeforePush::new(move || {
+            racing
+                .seed(
+                    MetadataEntry::new(
+                        "0cc175b9c0f1b6a831c399e269772661",
+                        "boards/arty/top.bit",
+                        "other.vhd",
+                        "2024-01-01T00:00:00Z",
+                    ),
+                    b"other bitstream",
+                )
+                .expect("race a publish");
+        }))
+        .build()?;
+    let published = client.publish(&inputs(&repo, "top")?)?;
+
+    let mut listed: Vec<String> = repo
+        .client()?
+        .list()?
+        .into_iter()
+        .map(|entry| entry.md5)
+        .collect();
+    listed.sort();
+    assert_eq!(
+        listed,
+        ["0cc175b9c0f1b6a831c399e269772661", published.md5.as_str()]
+    );
+    Ok(())
+}
+
+#[test]
+fn the_same_entry_changed_remotely_is_a_conflict() -> io::Result<()> {
+    let repo = Arc::new(TestRepo::new()?);
+    let opts = inputs(&repo, "top")?;
+    let md5 = bitcache::compute_md5(&opts.source)?;
+    let racing = Arc::clone(&repo);
+    let theirs = md5.clone();
+    let client = repo
+        .builder()?
+        .observer(BeforePush::new(move || {
+            racing
+                .seed(
+                    MetadataEntry::new(
+                        theirs,
+                        "boards/arty/top.bit",
+                        "top.vhd",
+                        "2024-01-01T00:00:00Z",
+                    ),
+                    b"their bitstream",
+                )
+                .expect("race a publish");
+        }))
+        .build()?;
+    let error = client
+        .publish(&opts)
+        .expect_err("overwrote a concurrent publish");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::Conflict {
+            md5: conflicting, ..
+        }) => assert_eq!(*conflicting, md5),
+        other => panic!("unexpected error {:?}", other),
+    }
+    // The remote keeps the entry the other run published
+    let entries = repo.client()?.list()?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].binary_path, "boards/arty/top.bit");
+    Ok(())
+}
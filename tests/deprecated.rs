@@ -0,0 +1,110 @@
+//! `get` warns about a deprecated entry, or refuses it with
+//! `refuse_deprecated`, even when the bitstream was cached before it was
+//! deprecated, and when it is served from the local artifact cache.
+
+use bitcache::progress::{Recorded, RecordingObserver};
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, DeprecateOptions, GetOptions, MetadataEntry};
+use std::io;
+use std::sync::Arc;
+
+const MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+
+#[test]
+fn a_deprecation_from_elsewhere_is_refused_despite_the_cache() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(MD5, "bits/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+        b"faulty bitstream",
+    )?;
+    let cache_dir = repo.path().join("cache");
+    let client = repo.builder()?.cache_dir(Some(cache_dir)).build()?;
+    let opts = GetOptions {
+        output: Some(repo.path().join("top.bit")),
+        force: true,
+        ..GetOptions::new(MD5)
+    };
+    assert!(!client.get(&opts)?.expect("seeded").from_cache);
+
+    // Another machine, with a cache of its own, deprecates the entry
+    repo.client()?
+        .deprecate(&DeprecateOptions::new(MD5, "timing violation"))?
+        .expect("deprecated");
+
+    // The remote moved on, so a plain get reads the entry again
+    assert!(!client.get(&opts)?.expect("deprecated").from_cache);
+    let refusing = GetOptions {
+        refuse_deprecated: true,
+        ..opts
+    };
+    let error = client
+        .get(&refusing)
+        .expect_err("served a deprecated bitstream");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::Deprecated { .. })
+        ),
+        "{}",
+        error
+    );
+    Ok(())
+}
+
+#[test]
+fn a_warm_cache_warns_about_a_deprecated_entry() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(MD5, "bits/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+        b"faulty bitstream",
+    )?;
+    let recorder = Arc::new(RecordingObserver::new());
+    let client = repo
+        .builder()?
+        .cache_dir(Some(repo.path().join("cache")))
+        .observer(recorder.clone())
+        .build()?;
+    let opts = GetOptions {
+        output: Some(repo.path().join("top.bit")),
+        force: true,
+        ..GetOptions::new(MD5)
+    };
+    assert!(!client.get(&opts)?.expect("seeded").from_cache);
+    repo.client()?
+        .deprecate(&DeprecateOptions::new(MD5, "timing violation"))?
+        .expect("deprecated");
+
+    let warnings = || {
+        recorder
+            .events()
+            .iter()
+            .filter(|event| {
+                matches!(event, Recorded::Warning(line) if line.contains("timing violation"))
+            })
+            .count()
+    };
+    assert_eq!(warnings(), 0);
+    // Read again and cached as deprecated, then served from the cache
+    for (warned, from_cache) in [(1, false), (2, true)] {
+        let retrieved = client.get(&opts)?.expect("deprecated");
+        assert_eq!(retrieved.from_cache, from_cache);
+        assert_eq!(warnings(), warned, "from_cache {}", from_cache);
+    }
+
+    let refusing = GetOptions {
+        refuse_deprecated: true,
+        ..opts
+    };
+    let error = client
+        .get(&refusing)
+        .expect_err("served a deprecated bitstream");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::Deprecated { .. })
+        ),
+        "{}",
+        error
+    );
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! `diff` prints the fields two entries disagree on and where their
+//! bitstreams first differ, exiting 0 when they are the same, 1 when they
+//! differ and 2 when an entry is missing.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{BinaryComparison, BitcacheError, Compression, DiffOptions, PublishOptions};
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// Options publishing `bitstream` for the source `name`, tagged with `board`
+fn inputs(repo: &TestRepo, name: &str, bitstream: &str, board: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let binary = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&binary, bitstream)?;
+    Ok(PublishOptions {
+        tags: [("board".to_string(), board.to_string())].into(),
+        ..PublishOptions::new(source, binary, format!("boards/{}", name))
+    })
+}
+
+#[test]
+fn reports_the_fields_and_the_first_differing_byte() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let rev1 = client.publish(&inputs(&repo, "rev1", "bitstream AAAA", "zedboard")?)?;
+    let rev2 = client.publish(&inputs(&repo, "rev2", "bitstream AABA tail", "arty")?)?;
+
+    let diff = client.diff(&DiffOptions::new(&rev1.md5, &rev2.md5))?;
+    assert_eq!(diff.a.md5, rev1.md5);
+    assert_eq!(diff.b.md5, rev2.md5);
+    assert!(!diff.identical());
+    assert_eq!(diff.binaries, BinaryComparison::Differ { offset: 12 });
+    assert_eq!(diff.size_a, 14);
+    assert_eq!(diff.size_b, 19);
+    assert!(diff.timestamp_delta.is_some_and(|delta| delta >= 0));
+
+    let fields: Vec<&str> = diff.changes.iter().map(|c| c.field.as_str()).collect();
+    for field in ["md5", "source_file", "binary_path", "tags.board"] {
+        assert!(fields.contains(&field), "{:?}", fields);
+    }
+    // Fields both entries share are left out
+    assert!(!fields.contains(&"hash_algo"), "{:?}", fields);
+    let board = diff
+        .changes
+        .iter()
+        .find(|change| change.field == "tags.board")
+        .expect("the tag differs");
+    assert_eq!(board.a.as_deref(), Some("zedboard"));
+    assert_eq!(board.b.as_deref(), Some("arty"));
+
+    let error = client
+        .diff(&DiffOptions::new(&rev1.md5, MISSING_MD5))
+        .expect_err("compared with a missing entry");
+    assert!(matches!(
+        BitcacheError::of(&error),
+        Some(BitcacheError::NotFound { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn compares_compressed_bitstreams_as_get_saves_them() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let plain = client.publish(&inputs(&repo, "plain", "same bitstream", "zedboard")?)?;
+    let packed = client.publish(&PublishOptions {
+        compression: Compression::Zstd,
+        ..inputs(&repo, "packed", "same bitstream", "zedboard")?
+    })?;
+
+    let diff = client.diff(&DiffOptions::new(&plain.md5, &packed.md5))?;
+    assert!(diff.identical(), "{:?}", diff.binaries);
+    assert_eq!(diff.size_a, diff.size_b);
+    assert!(diff
+        .changes
+        .iter()
+        .any(|change| change.field == "compression"));
+    Ok(())
+}
+
+#[test]
+fn the_command_line_exits_with_the_outcome() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let rev1 = client.publish(&inputs(&repo, "rev1", "bitstream AAAA", "zedboard")?)?;
+    let rev2 = client.publish(&inputs(&repo, "rev2", "bitstream AABA", "zedboard")?)?;
+    let copy = client.publish(&inputs(&repo, "copy", "bitstream AAAA", "zedboard")?)?;
+
+    let output = bitcache(&repo, &["diff", "--md5-a", &rev1.md5, "--md5-b", &rev2.md5])?;
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Bitstreams differ from byte offset 12"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("  source_file: rev1.vhd -> rev2.vhd"),
+        "{}",
+        stdout
+    );
+
+    let output = bitcache(&repo, &["diff", "--md5-a", &rev1.md5, "--md5-b", &copy.md5])?;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Bitstreams are identical"));
+
+    let output = bitcache(
+        &repo,
+        &["diff", "--md5-a", &rev1.md5, "--md5-b", MISSING_MD5],
+    )?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(MISSING_MD5));
+
+    // The global --json prints the comparison, and keeps the exit status
+    let output = bitcache(
+        &repo,
+        &["--json", "diff", "--md5-a", &rev1.md5, "--md5-b", &rev2.md5],
+    )?;
+    assert_eq!(output.status.code(), Some(1));
+    let diff: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(diff["binaries"]["result"], "differ");
+    assert_eq!(diff["binaries"]["offset"], 12);
+    assert_eq!(diff["a"]["md5"], rev1.md5.as_str());
+    let output = bitcache(
+        &repo,
+        &[
+            "--json",
+            "diff",
+            "--md5-a",
+            &rev1.md5,
+            "--md5-b",
+            MISSING_MD5,
+        ],
+    )?;
+    assert_eq!(output.status.code(), Some(2));
+    let error: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(error["exit_code"], 2);
+    Ok(())
+}
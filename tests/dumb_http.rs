@@ -0,0 +1,98 @@
+//! A server speaking git's dumb HTTP protocol cannot send a shallow history,
+//! so bitcache clones and fetches it whole instead of failing.
+
+use bitcache::progress::{Recorded, RecordingObserver};
+use bitcache::testing::TestRepo;
+use bitcache::{GetOptions, MetadataEntry};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+
+const MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+
+/// Serve the files under `root` over plain HTTP, as a dumb git server does,
+/// returning its URL
+fn serve(root: PathBuf) -> io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}/", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = respond(&root, stream);
+        }
+    });
+    Ok(url)
+}
+
+fn respond(root: &Path, mut stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let path = target.split('?').next().unwrap_or_default();
+    match fs::read(root.join(path.trim_start_matches('/'))) {
+        Ok(body) => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )?;
+            stream.write_all(&body)
+        }
+        Err(_) => stream
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    }
+}
+
+/// Let a dumb server find the refs and packs of `repo`
+fn update_server_info(repo: &TestRepo) -> io::Result<()> {
+    let status = Command::new("git")
+        .args(["--git-dir", repo.url(), "update-server-info"])
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn gets_from_a_server_without_shallow_clones() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(MD5, "bits/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+        b"top bitstream",
+    )?;
+    update_server_info(&repo)?;
+    let url = serve(PathBuf::from(repo.url()))?;
+
+    let recorder = Arc::new(RecordingObserver::new());
+    let client = repo
+        .builder()?
+        .repo(url)
+        .observer(recorder.clone())
+        .build()?;
+    for round in 0..2 {
+        let opts = GetOptions {
+            output: Some(repo.path().join("top.bit")),
+            force: true,
+            ..GetOptions::new(MD5)
+        };
+        client.get(&opts)?.expect("seeded");
+        assert_eq!(
+            fs::read(repo.path().join("top.bit"))?,
+            b"top bitstream",
+            "round {}",
+            round
+        );
+    }
+    let warned = recorder.events().into_iter().any(|event| {
+        matches!(event, Recorded::Warning(line) if line.contains("does not support shallow clones"))
+    });
+    assert!(warned);
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! A repository nothing was published to yet has no metadata file, which
+//! every command reads as a repository with no entries: lookups miss,
+//! listings are empty and maintenance finds nothing to do.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{
+    BitcacheError, DeleteOptions, DeprecateOptions, GcOptions, GetOptions, PruneOptions,
+    VerifyOptions,
+};
+use common::bitcache;
+use std::io;
+
+const MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+
+#[test]
+fn the_library_sees_no_entries() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    assert!(client.get(&GetOptions::new(MD5))?.is_none());
+    assert!(!client.exists(MD5)?);
+    assert!(client.info(MD5, None)?.is_none());
+    assert!(client.find(MD5)?.is_empty());
+    assert!(client.list()?.is_empty());
+    assert!(client.delete(&DeleteOptions::new(MD5))?.is_none());
+
+    let error = client
+        .deprecate(&DeprecateOptions::new(MD5, "timing violation"))
+        .expect_err("deprecated a missing entry");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::NotFound { .. })
+        ),
+        "{}",
+        error
+    );
+
+    let pruned = client.prune(&PruneOptions {
+        keep_latest: Some(1),
+        orphans: true,
+        ..PruneOptions::new()
+    })?;
+    assert!(pruned.entries.is_empty() && pruned.orphans.is_empty());
+    assert_eq!(client.verify(&VerifyOptions::new())?.checked, 0);
+    assert!(client.gc(&GcOptions::new())?.orphans.is_empty());
+    Ok(())
+}
+
+#[test]
+fn the_command_line_misses_like_on_any_other_repository() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    for command in ["get", "info"] {
+        let output = bitcache(&repo, &[command, "--md5", MD5])?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{}", command);
+        assert!(
+            stderr.contains(MD5) && !stderr.contains("Metadata"),
+            "{}: {}",
+            command,
+            stderr
+        );
+    }
+
+    for (args, expected) in [
+        (&["list"][..], ""),
+        (&["prune", "--keep-latest", "1"][..], "Nothing to prune"),
+        (&["compact"][..], "Nothing to compact"),
+    ] {
+        let output = bitcache(&repo, args)?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(stdout.contains(expected), "{}: {}", args[0], stdout);
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! `get --env` prints shell assignments on stdout that `eval` reads back
+//! unchanged, whatever the values hold, and everything else on stderr.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::MetadataEntry;
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+const MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+const AWKWARD: &str = "it's a \"$HOME\" `top`.vhd";
+
+/// A repository with one entry whose source file name needs quoting
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(MD5, "boards/top.bit", AWKWARD, "2024-05-03T10:02:51Z"),
+        b"top bitstream",
+    )?;
+    Ok(repo)
+}
+
+/// Evaluate `assignments` in sh and print `variable` back
+fn eval(assignments: &[u8], variable: &str) -> io::Result<String> {
+    let script = format!(
+        "{}\nprintf %s \"${}\"",
+        String::from_utf8_lossy(assignments),
+        variable
+    );
+    let output = Command::new("sh").args(["-c", &script]).output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn assignments_survive_eval() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = bitcache(&repo, &["get", "--md5", MD5, "--env", "BC_"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.lines().all(|line| line.starts_with("BC_")),
+        "{}",
+        stdout
+    );
+    // The summary goes to stderr instead
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Successfully retrieved"));
+
+    assert_eq!(eval(&output.stdout, "BC_HIT")?, "1");
+    assert_eq!(eval(&output.stdout, "BC_MD5")?, MD5);
+    assert_eq!(eval(&output.stdout, "BC_SOURCE_FILE")?, AWKWARD);
+    assert_eq!(eval(&output.stdout, "BC_SIZE")?, "13");
+    let path = eval(&output.stdout, "BC_PATH")?;
+    assert_eq!(fs::read(path)?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn a_miss_still_sets_hit() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = bitcache(&repo, &["get", "--md5", MISSING_MD5, "--env"])?;
+    assert!(!output.status.success());
+    assert_eq!(eval(&output.stdout, "BITCACHE_HIT")?, "0");
+    assert_eq!(eval(&output.stdout, "BITCACHE_MD5")?, MISSING_MD5);
+    Ok(())
+}
+
+#[test]
+fn powershell_quotes_its_own_way() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = bitcache(
+        &repo,
+        &["get", "--md5", MD5, "--env", "--env-format", "powershell"],
+    )?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("$env:BITCACHE_SOURCE_FILE = 'it''s a \"$HOME\" `top`.vhd'"),
+        "{}",
+        stdout
+    );
+    Ok(())
+}
+
+#[test]
+fn refuses_a_prefix_that_is_no_identifier() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = bitcache(&repo, &["get", "--md5", MD5, "--env", "1-BAD"])?;
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid --env prefix"));
+    Ok(())
+}
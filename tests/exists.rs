@@ -0,0 +1,98 @@
+//! `exists` tells a hit (0) from a miss (1) and from a repository it
+//! can't read (2), for build scripts deciding whether to synthesize.
+
+use bitcache::testing::TestRepo;
+use bitcache::MetadataEntry;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::process::{Command, Output};
+
+const SEEDED_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// Run `exists` with `args` against `repo_url`, away from any config file
+/// of the user running the tests
+fn exists(repo: &TestRepo, repo_url: &str, args: &[&str]) -> io::Result<Output> {
+    let config = repo.path().join("config");
+    fs::create_dir_all(&config)?;
+    Command::new(env!("CARGO_BIN_EXE_bitcache"))
+        .arg("exists")
+        .args(args)
+        .args(["--repo", repo_url])
+        .arg("--cache-dir")
+        .arg(repo.path().join("cache"))
+        .arg("--work-dir")
+        .arg(repo.path())
+        .current_dir(repo.path())
+        .env("XDG_CONFIG_HOME", &config)
+        .output()
+}
+
+/// A repository with the source `a.vhd` published as `top.bit`
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(
+            SEEDED_MD5,
+            "boards/top.bit",
+            "a.vhd",
+            "2024-05-03T10:02:51Z",
+        ),
+        b"top bitstream",
+    )?;
+    // The MD5 of a file holding "a"
+    fs::write(repo.path().join("a.vhd"), "a")?;
+    Ok(repo)
+}
+
+#[test]
+fn a_hit_prints_the_entry() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = exists(&repo, repo.url(), &["--md5", SEEDED_MD5])?;
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("  Path: boards/top.bit"), "{}", stdout);
+
+    let output = exists(&repo, repo.url(), &["--source", "a.vhd", "--quiet"])?;
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+    Ok(())
+}
+
+#[test]
+fn a_miss_exits_1() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = exists(&repo, repo.url(), &["--md5", MISSING_MD5])?;
+    assert_eq!(output.status.code(), Some(1));
+    let output = exists(&repo, repo.url(), &["--md5", SEEDED_MD5, "--name", "flash"])?;
+    assert_eq!(output.status.code(), Some(1));
+    let output = exists(&repo, repo.url(), &["--json", "--md5", MISSING_MD5])?;
+    assert_eq!(output.status.code(), Some(1));
+    let entries: Value = serde_json::from_slice(&output.stdout).expect("JSON on stdout");
+    assert_eq!(entries, Value::Array(Vec::new()));
+
+    // So is a repository nothing was published to yet
+    let empty = TestRepo::new()?;
+    let output = exists(&empty, empty.url(), &["--md5", SEEDED_MD5])?;
+    assert_eq!(output.status.code(), Some(1));
+    Ok(())
+}
+
+#[test]
+fn a_repository_it_cannot_read_exits_2() -> io::Result<()> {
+    let repo = seeded()?;
+    let missing = format!("file://{}", repo.path().join("missing.git").display());
+    let output = exists(&repo, &missing, &["--md5", SEEDED_MD5])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error:"));
+
+    let output = exists(&repo, &missing, &["--md5", SEEDED_MD5, "--quiet"])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+
+    // A source that can't be hashed can't be looked up either
+    let output = exists(&repo, repo.url(), &["--source", "missing.vhd"])?;
+    assert_eq!(output.status.code(), Some(2));
+    Ok(())
+}
@@ -0,0 +1,84 @@
+//! `get --fallback-repo` tries further repositories in order when `--repo`
+//! fails or has no entry for the hash.
+
+use bitcache::testing::TestRepo;
+use bitcache::MetadataEntry;
+use std::fs;
+use std::io;
+use std::process::{Command, Output};
+
+const SEEDED_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const OTHER_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+
+/// A repository holding one entry for `md5`
+fn seeded(md5: &str, contents: &[u8]) -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(md5, "boards/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+        contents,
+    )?;
+    Ok(repo)
+}
+
+/// Run `get` for `md5` from `repo`, falling back to `fallbacks`, away from
+/// any config file of the user running the tests
+fn get(work: &TestRepo, repo: &str, fallbacks: &[&str], md5: &str) -> io::Result<Output> {
+    let config = work.path().join("config");
+    fs::create_dir_all(&config)?;
+    let mut command = Command::new(env!("CARGO_BIN_EXE_bitcache"));
+    command
+        .args(["get", "--md5", md5, "--no-local-cache", "--repo", repo])
+        .arg("--output")
+        .arg(work.path().join("out.bit"))
+        .arg("--work-dir")
+        .arg(work.path().join("work"))
+        .current_dir(work.path())
+        .env("XDG_CONFIG_HOME", &config);
+    for url in fallbacks {
+        command.args(["--fallback-repo", url]);
+    }
+    command.output()
+}
+
+#[test]
+fn falls_back_when_the_entry_is_missing() -> io::Result<()> {
+    let primary = seeded(OTHER_MD5, b"other bitstream")?;
+    let mirror = seeded(SEEDED_MD5, b"top bitstream")?;
+    let output = get(&primary, primary.url(), &[mirror.url()], SEEDED_MD5)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("has no entry"));
+    assert_eq!(fs::read(primary.path().join("out.bit"))?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn falls_back_when_the_repository_fails() -> io::Result<()> {
+    let mirror = seeded(SEEDED_MD5, b"top bitstream")?;
+    let missing = format!("file://{}", mirror.path().join("missing.git").display());
+    let output = get(&mirror, &missing, &[mirror.url()], SEEDED_MD5)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("failed"));
+    assert_eq!(fs::read(mirror.path().join("out.bit"))?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn a_miss_everywhere_stays_a_miss() -> io::Result<()> {
+    let primary = seeded(OTHER_MD5, b"other bitstream")?;
+    let missing = format!("file://{}", primary.path().join("missing.git").display());
+    let output = get(&primary, &missing, &[primary.url()], SEEDED_MD5)?;
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // One repository answered, so its miss is reported rather than the failure
+    assert!(stderr.contains(SEEDED_MD5), "{}", stderr);
+    assert!(!primary.path().join("out.bit").exists());
+    Ok(())
+}
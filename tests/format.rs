@@ -0,0 +1,114 @@
+//! `--format json` is another name for `--json`, and `--format csv` prints
+//! the entries of `get` and `list` as a header row and one row each.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+const CSV_HEADER: &str = "md5,variant,source_file,timestamp,hash_algo,binary_path,binary_md5,branch,tags,compression,original_size,size,file_mode,file_name,os,arch,build_tool_version,published_by,tool_version,source_commit,deprecated,deprecation_reason,replacement";
+
+/// Publish a bitstream with a tag holding a comma, returning its MD5
+fn publish(repo: &TestRepo) -> io::Result<String> {
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(repo.path().join("top.bit"), b"top bitstream")?;
+    let output = bitcache(
+        repo,
+        &[
+            "publish",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+            "--tag",
+            "board=zedboard,rev b",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    bitcache::compute_md5(&repo.path().join("top.vhd"))
+}
+
+#[test]
+fn json_is_the_same_as_the_json_flag() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let md5 = publish(&repo)?;
+    let flag = bitcache(&repo, &["--json", "list"])?;
+    let format = bitcache(&repo, &["--format", "json", "list"])?;
+    assert!(format.status.success());
+    assert_eq!(format.stdout, flag.stdout);
+    let entries: Value = serde_json::from_slice(&format.stdout)?;
+    assert_eq!(entries[0]["md5"], md5.as_str());
+
+    // Failures are error objects too, for every command
+    let output = bitcache(&repo, &["--format", "json", "get", "--md5", MISSING_MD5])?;
+    assert_eq!(output.status.code(), Some(1));
+    let error: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(error["exit_code"], 1);
+    Ok(())
+}
+
+#[test]
+fn csv_prints_a_header_and_a_row_per_entry() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let md5 = publish(&repo)?;
+    let output = bitcache(&repo, &["--format", "csv", "list"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert_eq!(lines[0], CSV_HEADER);
+    assert!(
+        lines[1].starts_with(&format!("{},,top.vhd,", md5)),
+        "{}",
+        lines[1]
+    );
+    // A cell holding a comma is quoted
+    assert!(
+        lines[1].contains(",\"board=zedboard,rev b\","),
+        "{}",
+        lines[1]
+    );
+
+    let output = bitcache(
+        &repo,
+        &[
+            "--format", "csv", "get", "--md5", &md5, "--output", "out.bit",
+        ],
+    )?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().next(), Some(CSV_HEADER));
+    assert_eq!(stdout.lines().count(), 2, "{}", stdout);
+    assert_eq!(fs::read(repo.path().join("out.bit"))?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn refuses_csv_with_json_or_env() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let output = bitcache(&repo, &["--json", "--format", "csv", "list"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--format"));
+
+    let output = bitcache(
+        &repo,
+        &["--format", "csv", "get", "--md5", MISSING_MD5, "--env"],
+    )?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--env"));
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! `gc` finds the bitstreams no entry refers to and the entries whose
+//! bitstream is gone, and with `--prune` removes the former.
+
+mod common;
+
+use bitcache::testing::{self, TestRepo};
+use bitcache::{DeleteOptions, GcOptions, MetadataEntry};
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+const KEPT_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const DELETED_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+const TRASHED_MD5: &str = "4a8a08f09d37b73795649038408b5f33";
+
+/// A repository with three entries under `boards/`
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    for (md5, path) in [
+        (KEPT_MD5, "boards/kept.bit"),
+        (DELETED_MD5, "boards/deleted.bit"),
+        (TRASHED_MD5, "boards/trashed.bit"),
+    ] {
+        repo.seed(
+            MetadataEntry::new(md5, path, "top.vhd", "2024-01-01T00:00:00Z"),
+            path.as_bytes(),
+        )?;
+    }
+    Ok(repo)
+}
+
+/// Change the work tree of a clone of `repo` behind bitcache's back, and
+/// commit and push the result
+fn edit(repo: &TestRepo, change: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let clone = repo.path().join("edit");
+    let git = |args: &[&str]| -> io::Result<()> {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(&clone)
+            .envs(testing::identity_env())
+            .status()?;
+        assert!(status.success(), "git {:?}", args);
+        Ok(())
+    };
+    fs::create_dir_all(&clone)?;
+    git(&["clone", "--quiet", repo.url(), "."])?;
+    change(&clone)?;
+    git(&["add", "--all"])?;
+    git(&["commit", "--quiet", "-m", "Edit by hand"])?;
+    git(&["push", "--quiet", "origin", "HEAD:main"])?;
+    fs::remove_dir_all(&clone)
+}
+
+#[test]
+fn finds_orphans_and_broken_entries() -> io::Result<()> {
+    // A repository without metadata has nothing to scan
+    let empty = TestRepo::new()?;
+    assert_eq!(empty.client()?.gc(&GcOptions::new())?.scanned, 0);
+
+    let repo = seeded()?;
+    edit(&repo, |clone| {
+        fs::remove_file(clone.join("boards/kept.bit"))
+    })?;
+    let client = repo.client()?;
+    client.delete(&DeleteOptions {
+        hard: true,
+        ..DeleteOptions::new(DELETED_MD5)
+    })?;
+    // Its bitstream goes to the trash, so it is no orphan
+    client.delete(&DeleteOptions::new(TRASHED_MD5))?;
+
+    let report = client.gc(&GcOptions::new())?;
+    assert!(!report.pruned);
+    assert_eq!(report.scanned, 1);
+    assert_eq!(report.orphans, ["boards/deleted.bit"]);
+    let broken: Vec<_> = report.broken.iter().map(|entry| &entry.md5).collect();
+    assert_eq!(broken, [KEPT_MD5]);
+    Ok(())
+}
+
+#[test]
+fn prune_removes_the_orphans_in_one_commit() -> io::Result<()> {
+    let repo = seeded()?;
+    edit(&repo, |clone| {
+        fs::create_dir_all(clone.join("boards/nested"))?;
+        fs::write(clone.join("boards/nested/stray.bit"), b"stray")
+    })?;
+    let client = repo.client()?;
+    let report = client.gc(&GcOptions { prune: true })?;
+    assert!(report.pruned);
+    assert_eq!(report.orphans, ["boards/nested/stray.bit"]);
+    assert!(report.broken.is_empty());
+    assert_eq!(client.list()?.len(), 3);
+
+    let tree = Command::new("git")
+        .args([
+            "--git-dir",
+            repo.url(),
+            "ls-tree",
+            "-r",
+            "--name-only",
+            "main",
+        ])
+        .output()?;
+    let tree = String::from_utf8_lossy(&tree.stdout);
+    assert!(!tree.contains("stray.bit"), "{}", tree);
+    assert!(tree.contains("boards/kept.bit"), "{}", tree);
+
+    // Nothing is left to remove, so nothing is committed
+    let report = client.gc(&GcOptions { prune: true })?;
+    assert!(!report.pruned);
+    assert!(report.orphans.is_empty());
+    Ok(())
+}
+
+#[test]
+fn exits_3_while_orphans_are_left() -> io::Result<()> {
+    let repo = seeded()?;
+    let gc = |args: &[&str]| -> io::Result<Option<i32>> {
+        let output = bitcache(&repo, &[&["gc"], args].concat())?;
+        Ok(output.status.code())
+    };
+    assert_eq!(gc(&[])?, Some(0));
+    repo.client()?.delete(&DeleteOptions {
+        hard: true,
+        ..DeleteOptions::new(DELETED_MD5)
+    })?;
+    assert_eq!(gc(&[])?, Some(3));
+    assert_eq!(gc(&["--prune"])?, Some(0));
+    assert_eq!(gc(&[])?, Some(0));
+    Ok(())
+}
+
+#[test]
+fn takes_file_names_as_stored() -> io::Result<()> {
+    let repo = seeded()?;
+    // publish stores a bitstream named 50%.bit as 50%25.bit
+    repo.seed(
+        MetadataEntry::new(
+            "d3699e851d7f4fde53ee37c037408af7",
+            "boards/50%25.bit",
+            "top.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"half",
+    )?;
+    edit(&repo, |clone| {
+        fs::write(clone.join("boards/100%.bit"), b"stray")
+    })?;
+    let client = repo.client()?;
+    let report = client.gc(&GcOptions::new())?;
+    assert_eq!(report.orphans, ["boards/100%.bit"]);
+    assert!(report.broken.is_empty());
+
+    let report = client.gc(&GcOptions { prune: true })?;
+    assert_eq!(report.orphans, ["boards/100%.bit"]);
+    assert!(client.gc(&GcOptions::new())?.orphans.is_empty());
+    assert_eq!(client.list()?.len(), 4);
+    Ok(())
+}
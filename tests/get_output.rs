@@ -0,0 +1,147 @@
+//! `get --output` creates missing directories, treats an existing directory
+//! or a trailing separator as a directory to save into and refuses an
+//! existing file unless forced, as a get into the current directory does,
+//! and with `--sync` flushes what it saved to disk.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{GetOptions, MetadataEntry};
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const TOP_MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(TOP_MD5, "bits/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+        b"top bitstream",
+    )?;
+    Ok(repo)
+}
+
+fn get_to(repo: &TestRepo, output: PathBuf, force: bool) -> io::Result<()> {
+    let mut opts = GetOptions::new(TOP_MD5);
+    opts.output = Some(output);
+    opts.force = force;
+    assert!(repo.client()?.get(&opts)?.is_some());
+    Ok(())
+}
+
+#[test]
+fn creates_missing_parent_directories() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = repo.path().join("a/b/c/board.bit");
+    get_to(&repo, output.clone(), false)?;
+    assert_eq!(fs::read(output)?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn trailing_separator_names_a_directory() -> io::Result<()> {
+    let repo = seeded()?;
+    let dir = repo.path().join("new/dir");
+    let mut output = dir.clone().into_os_string();
+    output.push(std::path::MAIN_SEPARATOR_STR);
+    get_to(&repo, output.into(), false)?;
+    assert_eq!(fs::read(dir.join("top.bit"))?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn existing_directory_is_saved_into() -> io::Result<()> {
+    let repo = seeded()?;
+    let dir = repo.path().join("bits");
+    fs::create_dir(&dir)?;
+    get_to(&repo, dir.clone(), false)?;
+    assert_eq!(fs::read(dir.join("top.bit"))?, b"top bitstream");
+
+    // The file saved into it is guarded like any other
+    let mut opts = GetOptions::new(TOP_MD5);
+    opts.output = Some(dir.clone());
+    let error = repo
+        .client()?
+        .get(&opts)
+        .expect_err("replaced without force");
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    get_to(&repo, dir.clone(), true)?;
+    assert_eq!(fs::read_dir(&dir)?.count(), 1);
+    Ok(())
+}
+
+#[test]
+fn existing_file_needs_force() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = repo.path().join("board.bit");
+    fs::write(&output, b"older")?;
+    let mut opts = GetOptions::new(TOP_MD5);
+    opts.output = Some(output.clone());
+    assert!(repo.client()?.get(&opts).is_err());
+    assert_eq!(fs::read(&output)?, b"older");
+
+    get_to(&repo, output.clone(), true)?;
+    assert_eq!(fs::read(output)?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn existing_file_in_the_current_directory_needs_force() -> io::Result<()> {
+    let repo = seeded()?;
+    let saved = repo.path().join("top.bit");
+    fs::write(&saved, b"older")?;
+    let output = bitcache(&repo, &["get", "--md5", TOP_MD5])?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("already exists; pass --force to replace it"),
+        "{}",
+        stderr
+    );
+    assert_eq!(fs::read(&saved)?, b"older");
+
+    let output = bitcache(&repo, &["get", "--md5", TOP_MD5, "--force"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(saved)?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn sync_flushes_the_saved_file_and_says_so() -> io::Result<()> {
+    let repo = seeded()?;
+    let mut opts = GetOptions::new(TOP_MD5);
+    opts.output = Some(repo.path().join("library.bit"));
+    opts.sync = true;
+    let retrieved = repo.client()?.get(&opts)?.expect("seeded");
+    assert!(retrieved.synced);
+    assert_eq!(fs::read(retrieved.path)?, b"top bitstream");
+
+    let get = ["get", "--md5", TOP_MD5, "--force"];
+    let output = bitcache(&repo, &[&["--json"], &get[..], &["--sync"]].concat())?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let printed: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(printed["synced"], true);
+
+    for (flag, summary, env) in [
+        (None, "Synced: no", "BITCACHE_SYNCED='0'"),
+        (Some("--sync"), "Synced: yes", "BITCACHE_SYNCED='1'"),
+    ] {
+        let args = [&get[..], flag.as_slice()].concat();
+        let stdout = String::from_utf8_lossy(&bitcache(&repo, &args)?.stdout).into_owned();
+        assert!(stdout.contains(summary), "{}", stdout);
+        let args = [&args[..], &["--env"]].concat();
+        let stdout = String::from_utf8_lossy(&bitcache(&repo, &args)?.stdout).into_owned();
+        assert!(stdout.contains(env), "{}", stdout);
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+//! `get` checks the bitstream it saved against the binary_md5 recorded when
+//! it was published, and with `--paranoid` reads back every file it or
+//! publish writes.
+
+mod common;
+
+use bitcache::store::ArtifactStore;
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, GetOptions, MetadataEntry};
+use common::bitcache;
+use std::fs;
+use std::io;
+
+const GOOD_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const BAD_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+const OLD_MD5: &str = "4a8a08f09d37b73795649038408b5f33";
+
+fn entry(md5: &str, binary_path: &str, published: Option<&[u8]>) -> MetadataEntry {
+    let mut entry = MetadataEntry::new(md5, binary_path, "top.vhd", "2024-01-01T00:00:00Z");
+    entry.binary_md5 = published.map(|contents| format!("{:x}", md5::compute(contents)));
+    entry
+}
+
+/// A repository with an intact entry, one whose bitstream was edited after
+/// it was published and one from before binary_md5 was recorded
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(entry(GOOD_MD5, "good/top.bit", Some(b"good")), b"good")?;
+    repo.seed(
+        entry(BAD_MD5, "bad/top.bit", Some(b"as published")),
+        b"edited",
+    )?;
+    repo.seed(entry(OLD_MD5, "old/top.bit", None), b"old")?;
+    Ok(repo)
+}
+
+fn opts(repo: &TestRepo, md5: &str) -> GetOptions {
+    GetOptions {
+        output: Some(repo.path().join(format!("{}.bit", md5))),
+        ..GetOptions::new(md5)
+    }
+}
+
+#[test]
+fn saves_intact_and_unchecked_bitstreams() -> io::Result<()> {
+    let repo = seeded()?;
+    let client = repo.client()?;
+    for (md5, contents) in [(GOOD_MD5, &b"good"[..]), (OLD_MD5, &b"old"[..])] {
+        let retrieved = client.get(&opts(&repo, md5))?.expect("entry exists");
+        assert_eq!(fs::read(retrieved.path)?, contents);
+    }
+    Ok(())
+}
+
+#[test]
+fn removes_a_corrupt_bitstream() -> io::Result<()> {
+    let repo = seeded()?;
+    let cache_dir = repo.path().join("cache");
+    let client = repo.builder()?.cache_dir(Some(cache_dir.clone())).build()?;
+    let opts = opts(&repo, BAD_MD5);
+
+    let error = client.get(&opts).expect_err("saved a corrupt bitstream");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::CorruptBinary {
+            expected, actual, ..
+        }) => {
+            assert_eq!(*expected, format!("{:x}", md5::compute(b"as published")));
+            assert_eq!(*actual, format!("{:x}", md5::compute(b"edited")));
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(!opts.output.as_ref().unwrap().exists());
+    // Nor is it kept in the local artifact cache for the next get
+    let store = ArtifactStore::open(&cache_dir, repo.url());
+    assert!(store.fetch(BAD_MD5, None)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn no_verify_saves_it_anyway() -> io::Result<()> {
+    let repo = seeded()?;
+    let opts = GetOptions {
+        verify: false,
+        ..opts(&repo, BAD_MD5)
+    };
+    let retrieved = repo.client()?.get(&opts)?.expect("entry exists");
+    assert_eq!(fs::read(retrieved.path)?, b"edited");
+    Ok(())
+}
+
+#[test]
+fn the_command_line_skips_with_skip_verify_or_no_verify() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = bitcache(&repo, &["get", "--md5", BAD_MD5, "--output", "checked.bit"])?;
+    assert!(!output.status.success());
+    assert!(!repo.path().join("checked.bit").exists());
+
+    for (flag, file) in [("--skip-verify", "skipped.bit"), ("--no-verify", "old.bit")] {
+        let output = bitcache(&repo, &["get", "--md5", BAD_MD5, "--output", file, flag])?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(fs::read(repo.path().join(file))?, b"edited");
+    }
+
+    // So does the skip_verify config key
+    let config = repo.path().join("config/bitcache");
+    fs::create_dir_all(&config)?;
+    fs::write(config.join("config.toml"), "skip_verify = true\n")?;
+    let output = bitcache(&repo, &["get", "--md5", BAD_MD5, "--output", "config.bit"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
+#[test]
+fn paranoid_reads_back_what_it_saves() -> io::Result<()> {
+    let repo = seeded()?;
+    // Even a bitstream without a recorded checksum is read back
+    let output = bitcache(
+        &repo,
+        &["--paranoid", "get", "--md5", OLD_MD5, "--output", "old.bit"],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(repo.path().join("old.bit"))?, b"old");
+
+    // And publish reads back the bitstream and metadata it commits, set by
+    // the config key as well
+    let config = repo.path().join("config/bitcache");
+    fs::create_dir_all(&config)?;
+    fs::write(config.join("config.toml"), "paranoid = true\n")?;
+    fs::write(repo.path().join("new.vhd"), "entity new is end;\n")?;
+    fs::write(repo.path().join("new.bit"), b"new bitstream")?;
+    let output = bitcache(
+        &repo,
+        &[
+            "publish",
+            "--source",
+            "new.vhd",
+            "--bitstream",
+            "new.bit",
+            "--path",
+            "new",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let md5 = bitcache::compute_md5(&repo.path().join("new.vhd"))?;
+    let retrieved = repo.client()?.get(&opts(&repo, &md5))?.expect("published");
+    assert_eq!(fs::read(retrieved.path)?, b"new bitstream");
+    Ok(())
+}
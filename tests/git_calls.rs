@@ -0,0 +1,137 @@
+//! Publish checks everything it can before running git, so a doomed publish
+//! fails without cloning, and a publish clones and pushes once. Counted
+//! with a `git` shim on PATH that logs each command before running git.
+#![cfg(unix)]
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+
+/// The git the shim hands each command to
+fn real_git() -> PathBuf {
+    env::split_paths(&env::var_os("PATH").unwrap_or_default())
+        .map(|dir| dir.join("git"))
+        .find(|git| git.is_file())
+        .expect("git on PATH")
+}
+
+/// Run bitcache with a `git` shim first on PATH, returning its output and
+/// the git subcommands it ran, in order
+fn with_shim(repo: &TestRepo, args: &[&str]) -> io::Result<(Output, Vec<String>)> {
+    let shim = repo.path().join("shim");
+    fs::create_dir_all(&shim)?;
+    let log = repo.path().join("git.log");
+    fs::write(&log, "")?;
+    let script = shim.join("git");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nprintf '%s\\n' \"$*\" >> '{}'\nexec '{}' \"$@\"\n",
+            log.display(),
+            real_git().display()
+        ),
+    )?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+
+    let path = env::join_paths(
+        [shim]
+            .into_iter()
+            .chain(env::split_paths(&env::var_os("PATH").unwrap_or_default())),
+    )
+    .expect("PATH");
+    let output = common::command(repo, args)?.env("PATH", path).output()?;
+    let subcommands = fs::read_to_string(&log)?
+        .lines()
+        .map(|line| subcommand(line).to_string())
+        .collect();
+    Ok((output, subcommands))
+}
+
+/// The subcommand of a logged command line, past any `-c` or `-C` options
+fn subcommand(line: &str) -> &str {
+    let mut words = line.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "-c" || word == "-C" {
+            words.next();
+        } else if !word.starts_with('-') {
+            return word;
+        }
+    }
+    ""
+}
+
+fn count(subcommands: &[String], name: &str) -> usize {
+    subcommands.iter().filter(|run| *run == name).count()
+}
+
+fn write_inputs(dir: &Path) -> io::Result<()> {
+    fs::write(dir.join("top.vhd"), "entity top is end;\n")?;
+    fs::write(dir.join("top.bit"), b"top bitstream")?;
+    fs::write(dir.join("empty.bit"), b"")
+}
+
+fn publish<'a>(extra: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec!["publish", "--source", "top.vhd", "--path", "bits"];
+    args.extend_from_slice(extra);
+    args
+}
+
+#[test]
+fn a_doomed_publish_runs_no_git() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    write_inputs(repo.path())?;
+    for (extra, reason) in [
+        (&["--bitstream", "missing.bit"][..], "missing.bit"),
+        (&["--bitstream", "empty.bit"][..], "is empty"),
+        (
+            &["--bitstream", "top.bit", "--rename-in-repo", "a/b.bit"][..],
+            "--rename-in-repo",
+        ),
+        (
+            &["--bitstream", "top.bit", "--rename-in-repo", "con.bit"][..],
+            "con.bit",
+        ),
+    ] {
+        let (output, subcommands) = with_shim(&repo, &publish(extra))?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{:?} published", extra);
+        assert!(stderr.contains(reason), "{:?}: {}", extra, stderr);
+        assert_eq!(subcommands, Vec::<String>::new(), "{:?}", extra);
+    }
+    Ok(())
+}
+
+#[test]
+fn a_publish_clones_and_pushes_once_and_a_republish_not_at_all() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    write_inputs(repo.path())?;
+    let (output, subcommands) = with_shim(&repo, &publish(&["--bitstream", "top.bit"]))?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(count(&subcommands, "clone"), 1, "{:?}", subcommands);
+    assert_eq!(count(&subcommands, "commit"), 1, "{:?}", subcommands);
+    assert_eq!(count(&subcommands, "push"), 1, "{:?}", subcommands);
+
+    // The cached clone is brought up to date instead of cloned again, and
+    // there is nothing to commit
+    let (output, subcommands) = with_shim(&repo, &publish(&["--bitstream", "top.bit"]))?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Already published"));
+    for name in ["clone", "commit", "push"] {
+        assert_eq!(count(&subcommands, name), 0, "{}: {:?}", name, subcommands);
+    }
+    Ok(())
+}
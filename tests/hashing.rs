@@ -0,0 +1,205 @@
+//! Hashing streams a file in chunks, so a file several chunks long hashes
+//! the same as its contents read at once, each algorithm gives the digests
+//! of the FIPS 180-4 test vectors, and an entry published under SHA-256 is
+//! found and listed by that hash, which `get --hash-algo` checks.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, GetOptions, HashAlgo, PublishOptions};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// Several 1 MiB chunks and then some, so no chunk boundary lines up
+fn write_large(path: &Path) -> io::Result<Vec<u8>> {
+    let contents: Vec<u8> = (0..3 * 1024 * 1024 + 333u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect();
+    fs::write(path, &contents)?;
+    Ok(contents)
+}
+
+#[test]
+fn md5_of_a_file_larger_than_a_chunk() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("large.bin");
+    let contents = write_large(&path)?;
+    assert_eq!(
+        bitcache::compute_md5(&path)?,
+        format!("{:x}", md5::compute(&contents))
+    );
+    Ok(())
+}
+
+#[test]
+fn sha_digests_of_a_file_larger_than_a_chunk_match_coreutils() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("large.bin");
+    write_large(&path)?;
+    for (algo, program) in [
+        (HashAlgo::Sha256, "sha256sum"),
+        (HashAlgo::Sha512, "sha512sum"),
+    ] {
+        let Ok(output) = Command::new(program).arg(&path).output() else {
+            eprintln!("{} not found, skipping", program);
+            continue;
+        };
+        let expected = String::from_utf8_lossy(&output.stdout);
+        let expected = expected.split_whitespace().next().expect("a digest");
+        assert_eq!(bitcache::compute_hash(&path, algo)?, expected, "{}", algo);
+    }
+    Ok(())
+}
+
+#[test]
+fn sha_digests_match_the_fips_test_vectors() -> io::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let million_a = "a".repeat(1_000_000);
+    for (message, sha256, sha512) in [
+        (
+            "",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+        ),
+        (
+            "abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+        ),
+        (
+            "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            "204a8fc6dda82f0a0ced7beb8e08a41657c16ef468b228a8279be331a703c33596fd15c13b1b07f9aa1d3bea57789ca031ad85c7a71dd70354ec631238ca3445",
+        ),
+        (
+            "abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+            "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+            "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909",
+        ),
+        (
+            million_a.as_str(),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+            "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973ebde0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b",
+        ),
+    ] {
+        let path = dir.path().join("message");
+        fs::write(&path, message)?;
+        let shown = &message[..message.len().min(16)];
+        assert_eq!(
+            bitcache::compute_hash(&path, HashAlgo::Sha256)?,
+            sha256,
+            "{:?}",
+            shown
+        );
+        assert_eq!(
+            bitcache::compute_hash(&path, HashAlgo::Sha512)?,
+            sha512,
+            "{:?}",
+            shown
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn an_entry_published_under_sha256_is_found_by_it() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "abc")?;
+    fs::write(&bitstream, "top bitstream")?;
+    let client = repo.client()?;
+    let published = client.publish(&PublishOptions {
+        hash_algo: HashAlgo::Sha256,
+        ..PublishOptions::new(&source, &bitstream, "boards/zedboard")
+    })?;
+    let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(published.md5, sha256);
+    assert_eq!(client.list()?[0].hash_algo, "sha256");
+
+    let output = repo.path().join("got.bit");
+    client
+        .get(&GetOptions {
+            output: Some(output.clone()),
+            ..GetOptions::new(sha256)
+        })?
+        .expect("found by its SHA-256");
+    assert_eq!(fs::read_to_string(output)?, "top bitstream");
+
+    // list heads the column with the hash, whatever its algorithm
+    let config = repo.path().join("config");
+    fs::create_dir_all(&config)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_bitcache"))
+        .args(["list", "--repo", repo.url(), "--no-cache"])
+        .arg("--work-dir")
+        .arg(repo.path())
+        .env("XDG_CONFIG_HOME", &config)
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("HASH "), "{}", stdout);
+    assert!(stdout.contains(sha256), "{}", stdout);
+    Ok(())
+}
+
+#[test]
+fn get_checks_the_hash_and_the_source_with_hash_algo() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "abc")?;
+    fs::write(&bitstream, "top bitstream")?;
+    let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    repo.client()?.publish(&PublishOptions {
+        hash_algo: HashAlgo::Sha256,
+        ..PublishOptions::new(&source, &bitstream, "boards/zedboard")
+    })?;
+
+    // An MD5 given as a SHA-256 is refused before the repository is read
+    let md5 = bitcache::compute_md5(&source)?;
+    let error = repo
+        .client()?
+        .get(&GetOptions {
+            hash_algo: Some(HashAlgo::Sha256),
+            ..GetOptions::new(&md5)
+        })
+        .expect_err("took an MD5 for a SHA-256");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::InvalidArgument { flag: "--md5", .. })
+        ),
+        "{}",
+        error
+    );
+    let output = common::bitcache(&repo, &["get", "--hash-algo", "sha256", "--hash", &md5])?;
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("64 hexadecimal digits"), "{}", stderr);
+
+    let output = common::bitcache(
+        &repo,
+        &[
+            "get",
+            "--hash-algo",
+            "sha256",
+            "--hash",
+            sha256,
+            "--source",
+            "top.vhd",
+            "--output",
+            "got.bit",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(repo.path().join("got.bit"))?,
+        "top bitstream"
+    );
+    Ok(())
+}
@@ -0,0 +1,123 @@
+//! `info` shows an entry, with the size of its bitstream, from the metadata
+//! alone, and exits 2 for a source without one.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{MetadataEntry, PublishOptions, RegisterOptions, UploadOptions};
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// Options publishing a bitstream for `top.vhd`
+fn inputs(repo: &TestRepo) -> io::Result<PublishOptions> {
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, b"top bitstream")?;
+    Ok(PublishOptions::new(source, bitstream, "boards/zedboard"))
+}
+
+#[test]
+fn shows_the_entry_and_its_recorded_size() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let published = client.publish(&inputs(&repo)?)?;
+    assert_eq!(client.list()?[0].size, Some(13));
+
+    let info = client.info(&published.md5, None)?.expect("published entry");
+    assert_eq!(info.entry.md5, published.md5);
+    assert_eq!(info.entry.source_file, "top.vhd");
+    assert_eq!(info.size, Some(13));
+    // Nothing was retrieved
+    assert!(!repo.path().join("top.bit.out").exists());
+
+    assert!(client.info(MISSING_MD5, None)?.is_none());
+    assert!(client.info(&published.md5, Some("kc705"))?.is_none());
+    Ok(())
+}
+
+#[test]
+fn register_records_the_size_of_an_uploaded_bitstream() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&bitstream, "top bitstream")?;
+    let uploaded = client.upload(&UploadOptions::new(&bitstream, "boards/zedboard"))?;
+    let registered = client.register(&RegisterOptions::new(
+        MISSING_MD5,
+        "top.vhd",
+        &uploaded.binary_path,
+    ))?;
+    assert_eq!(registered.entry.size, Some(13));
+    let info = client.info(MISSING_MD5, None)?.expect("registered entry");
+    assert_eq!(info.entry.size, Some(13));
+
+    // Without the file, the size given is recorded
+    let registered = client.register(&RegisterOptions {
+        size: Some(42),
+        ..RegisterOptions::new(
+            "0cc175b9c0f1b6a831c399e269772661",
+            "uart.vhd",
+            "boards/zedboard/uart.bit",
+        )
+    })?;
+    assert_eq!(registered.entry.size, Some(42));
+    Ok(())
+}
+
+#[test]
+fn an_entry_from_before_sizes_has_none_recorded() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(
+            "0cc175b9c0f1b6a831c399e269772661",
+            "old/old.bit",
+            "old.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"old bitstream",
+    )?;
+    let info = repo
+        .client()?
+        .info("0cc175b9c0f1b6a831c399e269772661", None)?
+        .expect("seeded entry");
+    assert_eq!(info.entry.size, None);
+    // A clone of a local remote has the file, so its size is read from it
+    assert_eq!(info.size, Some(13));
+    Ok(())
+}
+
+#[test]
+fn the_command_line_prints_the_entry() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let published = repo.client()?.publish(&inputs(&repo)?)?;
+
+    let output = bitcache(&repo, &["info", "--source", "top.vhd"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with(&published.md5), "{}", stdout);
+    assert!(stdout.contains("  Size: "), "{}", stdout);
+
+    let output = bitcache(&repo, &["--json", "info", "--md5", &published.md5])?;
+    assert!(output.status.success());
+    let entry: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(entry["md5"], published.md5.as_str());
+    assert_eq!(entry["size"], 13);
+
+    let output = bitcache(&repo, &["--format", "csv", "info", "--md5", &published.md5])?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 2);
+
+    let output = bitcache(&repo, &["info", "--md5", MISSING_MD5])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(MISSING_MD5));
+    Ok(())
+}
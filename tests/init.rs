@@ -0,0 +1,100 @@
+//! `init` seeds an empty repository with empty metadata, and refuses one
+//! that already has metadata unless forced.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{InitOptions, MetadataEntry, METADATA_FILE, METADATA_SCHEMA_VERSION};
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+/// The metadata file at the head of `repo`, read from a fresh clone
+fn remote_metadata(repo: &TestRepo) -> io::Result<Value> {
+    let clone = tempfile::tempdir()?;
+    let status = Command::new("git")
+        .args(["clone", "--quiet", repo.url()])
+        .arg(clone.path())
+        .status()?;
+    assert!(status.success());
+    let log = Command::new("git")
+        .args(["log", "-1", "--format=%s"])
+        .current_dir(clone.path())
+        .output()?;
+    assert_eq!(
+        String::from_utf8_lossy(&log.stdout).trim(),
+        "Initialize bitcache repository"
+    );
+    let text = fs::read_to_string(clone.path().join(METADATA_FILE))?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+#[test]
+fn seeds_an_empty_repository() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let initialized = client.init(&InitOptions::new())?;
+    assert!(initialized.committed);
+    assert_eq!(initialized.dropped, 0);
+    assert!(client.list()?.is_empty());
+
+    let metadata = remote_metadata(&repo)?;
+    assert_eq!(metadata["schema_version"], METADATA_SCHEMA_VERSION);
+    assert_eq!(metadata["entries"], serde_json::json!({}));
+    Ok(())
+}
+
+#[test]
+fn refuses_existing_metadata_unless_forced() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(
+            "0cc175b9c0f1b6a831c399e269772661",
+            "old/old.bit",
+            "old.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"old bitstream",
+    )?;
+    let client = repo.client()?;
+    let error = client
+        .init(&InitOptions::new())
+        .expect_err("replaced existing metadata");
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(client.list()?.len(), 1);
+
+    let initialized = client.init(&InitOptions { force: true })?;
+    assert_eq!(initialized.dropped, 1);
+    assert!(client.list()?.is_empty());
+
+    // An empty metadata file is already what init would write
+    let initialized = client.init(&InitOptions { force: true })?;
+    assert!(!initialized.committed);
+    Ok(())
+}
+
+#[test]
+fn the_command_line_initializes() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let output = bitcache(&repo, &["--json", "init"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let initialized: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(initialized["committed"], true);
+
+    let output = bitcache(&repo, &["init"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--force"));
+    let output = bitcache(&repo, &["init", "--force"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
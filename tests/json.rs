@@ -0,0 +1,181 @@
+//! With `--json` every command prints one JSON document on stdout, its
+//! progress on stderr, and a failure as an object with an `error` field.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::process::Output;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// Run the bitcache binary with `--json` against `repo`
+fn bitcache(repo: &TestRepo, args: &[&str]) -> io::Result<Output> {
+    common::bitcache(repo, &[&["--json"], args].concat())
+}
+
+/// stdout of a run, which must be nothing but one JSON document
+fn parsed(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+        panic!(
+            "stdout is not JSON ({}):\n{}",
+            e,
+            String::from_utf8_lossy(&output.stdout)
+        )
+    })
+}
+
+/// Publish a source and bitstream, returning what publish printed
+fn publish(repo: &TestRepo) -> io::Result<Value> {
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(repo.path().join("top.bit"), b"top bitstream")?;
+    let output = bitcache(
+        repo,
+        &[
+            "publish",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Progress still goes somewhere a person can see it
+    assert!(!output.stderr.is_empty());
+    Ok(parsed(&output))
+}
+
+#[test]
+fn publish_and_get_print_what_they_did() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let published = publish(&repo)?;
+    let md5 = bitcache::compute_md5(&repo.path().join("top.vhd"))?;
+    assert_eq!(published["md5"], md5.as_str());
+    assert_eq!(published["binary_path"], "boards/zedboard/top.bit");
+    assert_eq!(published["repo"], repo.url());
+    assert!(published["commit_message"]
+        .as_str()
+        .is_some_and(|message| message.contains(&md5)));
+
+    let output = bitcache(&repo, &["get", "--md5", &md5, "--output", "out.bit"])?;
+    assert!(output.status.success());
+    let retrieved = parsed(&output);
+    assert_eq!(retrieved["md5"], md5.as_str());
+    assert_eq!(retrieved["source_file"], "top.vhd");
+    assert!(retrieved["timestamp"].is_string());
+    assert_eq!(
+        retrieved["path"],
+        repo.path().join("out.bit").to_str().unwrap()
+    );
+    assert_eq!(fs::read(repo.path().join("out.bit"))?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn publish_explain_prints_the_plan_without_publishing() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(repo.path().join("top.bit"), b"top bitstream")?;
+    let explain = [
+        "publish",
+        "--source",
+        "top.vhd",
+        "--bitstream",
+        "top.bit",
+        "--path",
+        "boards/zedboard",
+        "--explain",
+    ];
+    // A plan is JSON even without --json
+    let output = common::bitcache(&repo, &explain)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let plan = parsed(&output);
+    let md5 = bitcache::compute_md5(&repo.path().join("top.vhd"))?;
+    assert_eq!(plan["repo"], repo.url());
+    assert_eq!(plan["hash_algo"], "md5");
+    assert_eq!(plan["hash"], md5);
+    assert_eq!(plan["entry_exists"], false);
+    assert_eq!(plan["action"], "create");
+    assert_eq!(plan["binary_path"], "boards/zedboard/top.bit");
+    assert_eq!(plan["upload_bytes"], b"top bitstream".len());
+    assert!(plan["commit_message"]
+        .as_str()
+        .is_some_and(|m| m.contains(&md5)));
+    assert!(repo.client()?.list()?.is_empty());
+
+    publish(&repo)?;
+    let plan = parsed(&common::bitcache(&repo, &explain)?);
+    assert_eq!(plan["entry_exists"], true);
+    assert_eq!(plan["action"], "unchanged");
+    Ok(())
+}
+
+#[test]
+fn list_prints_an_array_of_entries() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let published = publish(&repo)?;
+    let output = bitcache(&repo, &["list"])?;
+    assert!(output.status.success());
+    let entries = parsed(&output);
+    let entries = entries.as_array().expect("an array");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["md5"], published["md5"]);
+    Ok(())
+}
+
+#[test]
+fn a_failure_is_an_error_object() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    publish(&repo)?;
+    let output = bitcache(&repo, &["get", "--md5", MISSING_MD5])?;
+    assert_eq!(output.status.code(), Some(1));
+    let error = parsed(&output);
+    assert!(error["error"]
+        .as_str()
+        .is_some_and(|message| message.contains(MISSING_MD5)));
+    assert_eq!(error["exit_code"], 1);
+
+    // get-by-source keeps its own exit status for a miss
+    fs::write(repo.path().join("other.vhd"), "entity other is end;\n")?;
+    let output = bitcache(&repo, &["get-by-source", "--source", "other.vhd"])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(parsed(&output)["exit_code"], 2);
+    Ok(())
+}
+
+#[test]
+fn refuses_env_output_alongside() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let output = bitcache(&repo, &["get", "--md5", MISSING_MD5, "--env"])?;
+    assert!(!output.status.success());
+    assert!(parsed(&output)["error"]
+        .as_str()
+        .is_some_and(|message| message.contains("--env")));
+    Ok(())
+}
+
+#[test]
+fn redirected_progress_is_never_drawn() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    publish(&repo)?;
+    let output = bitcache(&repo, &["list"])?;
+    assert!(output.status.success());
+    // Spinners and bars are for terminals; a log gets plain lines only
+    for stream in [&output.stdout, &output.stderr] {
+        assert!(!stream.contains(&b'\r'));
+        assert!(!stream.contains(&0x1b));
+    }
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! The metadata can live at another path than [`METADATA_FILE`], and in the
+//! sharded layout keeps the entries of each hash in a file of their own.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{
+    BitcacheError, GcOptions, GetOptions, MetadataLayout, MigrateOptions, PublishOptions,
+    RegisterOptions, METADATA_FILE, SHARD_DIR,
+};
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+/// Options publishing a bitstream for the source `name`
+fn inputs(repo: &TestRepo, name: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let bitstream = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&bitstream, format!("{} bitstream", name))?;
+    Ok(PublishOptions::new(source, bitstream, "boards/zedboard"))
+}
+
+/// A fresh clone of the head of `repo`
+fn clone(repo: &TestRepo) -> io::Result<TempDir> {
+    let clone = tempfile::tempdir()?;
+    let status = Command::new("git")
+        .args(["clone", "--quiet", repo.url()])
+        .arg(clone.path())
+        .status()?;
+    assert!(status.success());
+    Ok(clone)
+}
+
+/// The JSON document at `path`
+fn read_json(path: &Path) -> io::Result<Value> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// The shard of `md5` in `clone`, next to a metadata file at its root
+fn shard(clone: &Path, md5: &str) -> std::path::PathBuf {
+    clone
+        .join(SHARD_DIR)
+        .join(&md5[..2])
+        .join(format!("{}.json", &md5[2..]))
+}
+
+#[test]
+fn the_sharded_layout_keeps_each_hash_in_a_file_of_its_own() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let sharded = repo
+        .builder()?
+        .metadata_layout(MetadataLayout::Sharded)
+        .build()?;
+    let top = sharded.publish(&inputs(&repo, "top")?)?;
+    let uart = sharded.publish(&inputs(&repo, "uart")?)?;
+
+    let clone = clone(&repo)?;
+    let header = read_json(&clone.path().join(METADATA_FILE))?;
+    assert_eq!(header["layout"], "sharded");
+    assert!(header.get("entries").is_none(), "{}", header);
+    for md5 in [&top.md5, &uart.md5] {
+        let shard = read_json(&shard(clone.path(), md5))?;
+        assert_eq!(shard["entries"].as_object().map(|e| e.len()), Some(1));
+        assert!(shard["entries"].get(md5.as_str()).is_some(), "{}", shard);
+    }
+
+    // A client that wasn't told the layout reads it from the file
+    let client = repo.client()?;
+    assert_eq!(client.list()?.len(), 2);
+    let output = repo.path().join("out.bit");
+    client
+        .get(&GetOptions {
+            output: Some(output.clone()),
+            ..GetOptions::new(&uart.md5)
+        })?
+        .expect("sharded entry");
+    assert_eq!(fs::read(output)?, b"uart bitstream");
+    Ok(())
+}
+
+#[test]
+fn migrates_between_layouts() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let top = client.publish(&inputs(&repo, "top")?)?;
+    client.publish(&inputs(&repo, "uart")?)?;
+
+    let migrated = client.migrate_metadata(&MigrateOptions::new())?;
+    assert_eq!(migrated.entries, 2);
+    assert!(migrated.committed);
+    assert!(!client.migrate_metadata(&MigrateOptions::new())?.committed);
+    let sharded = clone(&repo)?;
+    assert!(shard(sharded.path(), &top.md5).is_file());
+    assert_eq!(client.list()?.len(), 2);
+
+    let migrated = client.migrate_metadata(&MigrateOptions {
+        layout: MetadataLayout::Single,
+    })?;
+    assert!(migrated.committed);
+    let single = clone(&repo)?;
+    assert!(!single.path().join(SHARD_DIR).exists());
+    let metadata = read_json(&single.path().join(METADATA_FILE))?;
+    assert_eq!(metadata["entries"].as_object().map(|e| e.len()), Some(2));
+    assert_eq!(client.list()?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn keeps_the_metadata_at_the_metadata_path() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.builder()?.metadata_path("meta/index.json").build()?;
+    let published = client.publish(&inputs(&repo, "top")?)?;
+
+    let clone = clone(&repo)?;
+    assert!(clone.path().join("meta/index.json").is_file());
+    assert!(!clone.path().join(METADATA_FILE).exists());
+    assert!(client.exists(&published.md5)?);
+    // The metadata file is no orphan
+    assert!(client.gc(&GcOptions::new())?.orphans.is_empty());
+
+    // Nor can a bitstream be stored over it
+    let error = client
+        .publish(&PublishOptions {
+            rename_in_repo: Some("index.json".into()),
+            ..PublishOptions::new(
+                repo.path().join("top.vhd"),
+                repo.path().join("top.bit"),
+                "meta",
+            )
+        })
+        .expect_err("published over the metadata");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::PathTaken { path, .. }) => assert_eq!(path, "meta/index.json"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    // Nor registered there
+    let error = client
+        .register(&RegisterOptions::new(
+            &published.md5,
+            "top.vhd",
+            "meta/index.json",
+        ))
+        .expect_err("registered the metadata as a bitstream");
+    assert!(matches!(
+        BitcacheError::of(&error),
+        Some(BitcacheError::PathTaken { .. })
+    ));
+
+    let error = repo
+        .builder()?
+        .metadata_path(".git/index.json")
+        .build()
+        .err()
+        .expect("kept the metadata inside .git");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::InvalidArgument { flag, .. }) => assert_eq!(*flag, "--metadata-path"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn the_command_line_takes_the_path_and_migrates() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    inputs(&repo, "top")?;
+    let output = bitcache(
+        &repo,
+        &[
+            "publish",
+            "--metadata-path",
+            "meta/index.json",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = bitcache(
+        &repo,
+        &[
+            "--json",
+            "migrate-metadata",
+            "--metadata-path",
+            "meta/index.json",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let migrated: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(migrated["entries"], 1);
+    assert_eq!(migrated["committed"], true);
+
+    let clone = clone(&repo)?;
+    assert_eq!(
+        read_json(&clone.path().join("meta/index.json"))?["layout"],
+        "sharded"
+    );
+    assert!(clone.path().join("meta").join(SHARD_DIR).is_dir());
+
+    let output = bitcache(&repo, &["list", "--metadata-path", "meta/index.json"])?;
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("top.vhd"));
+    Ok(())
+}
@@ -0,0 +1,73 @@
+//! A bitstream full of CR and LF bytes is pushed and retrieved byte for byte
+//! by a user whose git config converts line endings.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+/// Every line ending git might rewrite, and a lone CR at the end
+const BITSTREAM: &[u8] = b"header\r\nbody\nmore\r\r\n\x00\xff\r\n\n\r";
+
+fn run(repo: &TestRepo, config: &std::path::Path, args: &[&str]) -> io::Result<()> {
+    let output = common::command(repo, args)?
+        .env("GIT_CONFIG_GLOBAL", config)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
+#[test]
+fn crlf_bytes_round_trip_with_autocrlf() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let config = repo.path().join("gitconfig");
+    fs::write(
+        &config,
+        "[core]\n\tautocrlf = true\n\teol = crlf\n\tsafecrlf = false\n",
+    )?;
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\r\n")?;
+    fs::write(repo.path().join("top.bit"), BITSTREAM)?;
+    run(
+        &repo,
+        &config,
+        &[
+            "--no-cache",
+            "publish",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "bits",
+        ],
+    )?;
+
+    // The blob in the remote is the bitstream as it was
+    let blob = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.url())
+        .args(["cat-file", "blob", "main:bits/top.bit"])
+        .output()?;
+    assert_eq!(blob.stdout, BITSTREAM);
+
+    let md5 = bitcache::compute_md5(&repo.path().join("top.vhd"))?;
+    for cached in [false, true] {
+        let output = repo.path().join(format!("out-{}.bit", cached));
+        let mut args = vec!["get", "--md5", &md5, "--force"];
+        if !cached {
+            args.extend(["--no-cache", "--no-local-cache"]);
+        }
+        let path = output.to_string_lossy().into_owned();
+        args.extend(["--output", &path]);
+        run(&repo, &config, &args)?;
+        assert_eq!(fs::read(&output)?, BITSTREAM, "cached clone: {}", cached);
+    }
+    Ok(())
+}
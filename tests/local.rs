@@ -0,0 +1,330 @@
+//! A local repository is a plain directory used in place: commands read and
+//! write the metadata and the bitstreams in it, log each change to
+//! [`LOG_FILE`] and never run git.
+
+mod common;
+
+use bitcache::{
+    Bitcache, BitcacheError, CompactOptions, Context, DeleteOptions, GetOptions, Metadata,
+    PublishOptions, Remote, LOCK_FILE, LOG_FILE, METADATA_FILE,
+};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Output;
+use tempfile::TempDir;
+
+/// A scratch directory holding an empty `repo` directory and the inputs to
+/// publish from it
+fn scratch() -> io::Result<TempDir> {
+    let tmp = tempfile::tempdir()?;
+    fs::create_dir(tmp.path().join("repo"))?;
+    fs::write(tmp.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(tmp.path().join("top.bit"), b"top bitstream")?;
+    Ok(tmp)
+}
+
+/// A client for the local repository in `tmp`
+fn client(tmp: &Path) -> io::Result<Bitcache> {
+    Bitcache::builder()
+        .local(tmp.join("repo"))
+        .work_dir(tmp.join("work"))
+        .cache_dir(None)
+        .build()
+}
+
+/// Run the bitcache binary against the local repository in `tmp`
+fn bitcache(tmp: &Path, args: &[&str]) -> io::Result<Output> {
+    common::command_in(tmp, args)?
+        .arg("--backend")
+        .arg(format!("local:{}", tmp.join("repo").display()))
+        .output()
+}
+
+#[test]
+fn publishes_and_gets_in_place() -> io::Result<()> {
+    let tmp = scratch()?;
+    let repo = tmp.path().join("repo");
+    let client = client(tmp.path())?;
+    let published = client.publish(&PublishOptions::new(
+        tmp.path().join("top.vhd"),
+        tmp.path().join("top.bit"),
+        "boards/zedboard",
+    ))?;
+
+    // The files are written straight into the directory, without git
+    assert_eq!(
+        fs::read(repo.join("boards/zedboard/top.bit"))?,
+        b"top bitstream"
+    );
+    let metadata = Metadata::load_from_file(&repo.join(METADATA_FILE))?;
+    assert_eq!(metadata.len(), 1);
+    assert!(!repo.join(".git").exists());
+    assert!(repo.join(LOCK_FILE).exists());
+    let log = fs::read_to_string(repo.join(LOG_FILE))?;
+    // One record, its later lines indented
+    let records = log.lines().filter(|line| !line.starts_with(' ')).count();
+    assert_eq!(records, 1, "{}", log);
+    assert!(log.contains(&published.md5), "{}", log);
+
+    let output = tmp.path().join("out.bit");
+    let opts = GetOptions {
+        output: Some(output.clone()),
+        ..GetOptions::new(&published.md5)
+    };
+    client.get(&opts)?.expect("published entry");
+    assert_eq!(fs::read(output)?, b"top bitstream");
+    assert_eq!(client.list()?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn the_published_bitstream_is_a_copy() -> io::Result<()> {
+    let tmp = scratch()?;
+    let client = client(tmp.path())?;
+    let published = client.publish(&PublishOptions::new(
+        tmp.path().join("top.vhd"),
+        tmp.path().join("top.bit"),
+        "boards/zedboard",
+    ))?;
+
+    // Rebuilding the bitstream afterwards leaves what was published alone
+    fs::write(tmp.path().join("top.bit"), b"rebuilt bitstream")?;
+    let stored = tmp.path().join("repo/boards/zedboard/top.bit");
+    assert_eq!(fs::read(&stored)?, b"top bitstream");
+    let output = tmp.path().join("out.bit");
+    let opts = GetOptions {
+        output: Some(output.clone()),
+        ..GetOptions::new(&published.md5)
+    };
+    client.get(&opts)?.expect("published entry");
+    assert_eq!(fs::read(output)?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn every_change_is_logged() -> io::Result<()> {
+    let tmp = scratch()?;
+    let repo = tmp.path().join("repo");
+    let client = client(tmp.path())?;
+    let published = client.publish(&PublishOptions::new(
+        tmp.path().join("top.vhd"),
+        tmp.path().join("top.bit"),
+        "boards/zedboard",
+    ))?;
+    client.delete(&DeleteOptions::new(&published.md5))?;
+    assert!(!client.exists(&published.md5)?);
+
+    let log = fs::read_to_string(repo.join(LOG_FILE))?;
+    let records: Vec<&str> = log.lines().filter(|line| !line.starts_with(' ')).collect();
+    assert_eq!(records.len(), 2, "{}", log);
+    // Each record starts with when it was made
+    for record in records {
+        let (time, _) = record.split_once(' ').expect("a timestamp");
+        assert!(
+            chrono::DateTime::parse_from_rfc3339(time).is_ok(),
+            "{}",
+            record
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn refuses_what_a_directory_cannot_do() -> io::Result<()> {
+    let tmp = scratch()?;
+    let ctx = Context {
+        work_dir: Some(tmp.path().join("work")),
+        cache_dir: None,
+        ..Context::default()
+    };
+
+    let missing = Remote::local(tmp.path().join("missing"));
+    let error = bitcache::list(&missing, &ctx).expect_err("listed a missing directory");
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    let branched = Remote {
+        branch: Some("main".to_string()),
+        ..Remote::local(tmp.path().join("repo"))
+    };
+    match BitcacheError::of(&bitcache::list(&branched, &ctx).expect_err("listed a branch")) {
+        Some(BitcacheError::InvalidArgument { flag, .. }) => assert_eq!(*flag, "--branch"),
+        other => panic!("unexpected error {:?}", other),
+    }
+
+    let local = Remote::local(tmp.path().join("repo"));
+    let error =
+        bitcache::compact(&local, &CompactOptions::new(), &ctx).expect_err("compacted a directory");
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    let error = bitcache::clone_repository(&local, &tmp.path().join("clone"), &ctx)
+        .expect_err("cloned a directory");
+    assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    Ok(())
+}
+
+#[test]
+fn the_command_line_takes_a_local_backend() -> io::Result<()> {
+    let tmp = scratch()?;
+    let output = bitcache(
+        tmp.path(),
+        &[
+            "publish",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let md5 = bitcache::compute_md5(&tmp.path().join("top.vhd"))?;
+    let output = bitcache(tmp.path(), &["get", "--md5", &md5, "--output", "out.bit"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(tmp.path().join("out.bit"))?, b"top bitstream");
+
+    // sync names both of its repositories itself
+    let output = bitcache(tmp.path(), &["sync", "--from", "a", "--to", "b"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--from and --to"));
+
+    let output = common::bitcache_in(tmp.path(), &["--backend", "local:", "list"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected a directory"));
+    Ok(())
+}
+
+/// Run the bitcache binary in `tmp` with `--backend dir`
+fn bitcache_dir(tmp: &Path, args: &[&str]) -> io::Result<Output> {
+    common::command_in(tmp, args)?
+        .args(["--backend", "dir"])
+        .output()
+}
+
+#[test]
+fn a_dir_backend_takes_a_path_or_a_file_url() -> io::Result<()> {
+    let tmp = scratch()?;
+    let url = format!("file://{}", tmp.path().join("repo").display());
+    let output = bitcache_dir(
+        tmp.path(),
+        &[
+            "publish",
+            "--repo",
+            &url,
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(tmp.path().join("repo/boards/zedboard/top.bit").exists());
+
+    // The same directory by path
+    let md5 = bitcache::compute_md5(&tmp.path().join("top.vhd"))?;
+    let output = bitcache_dir(
+        tmp.path(),
+        &[
+            "get", "--repo", "repo", "--md5", &md5, "--output", "out.bit",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(tmp.path().join("out.bit"))?, b"top bitstream");
+
+    // A directory has no branches, and a file:// URL must be absolute
+    let output = bitcache_dir(tmp.path(), &["list", "--repo", "repo", "--branch", "main"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--branch"));
+    let output = bitcache_dir(tmp.path(), &["list", "--repo", "file://repo"])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("absolute path"));
+    Ok(())
+}
+
+#[test]
+fn syncs_between_directories() -> io::Result<()> {
+    let tmp = scratch()?;
+    fs::create_dir(tmp.path().join("mirror"))?;
+    client(tmp.path())?.publish(&PublishOptions::new(
+        tmp.path().join("top.vhd"),
+        tmp.path().join("top.bit"),
+        "boards/zedboard",
+    ))?;
+    let output = bitcache_dir(tmp.path(), &["sync", "--from", "repo", "--to", "mirror"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mirror = tmp.path().join("mirror");
+    assert_eq!(
+        fs::read(mirror.join("boards/zedboard/top.bit"))?,
+        b"top bitstream"
+    );
+    assert_eq!(
+        Metadata::load_from_file(&mirror.join(METADATA_FILE))?.len(),
+        1
+    );
+    assert!(mirror.join(LOG_FILE).exists());
+    Ok(())
+}
+
+#[test]
+fn needs_no_git_where_git_is_missing() -> io::Result<()> {
+    let tmp = scratch()?;
+    let empty = tmp.path().join("empty");
+    fs::create_dir(&empty)?;
+    let run = |args: &[&str]| {
+        common::command_in(tmp.path(), args)?
+            .env("PATH", &empty)
+            .output()
+    };
+
+    // A git remote fails at once, naming what is missing
+    let output = run(&["list", "--repo", "https://example.com/bitstreams.git"])?;
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("git executable not found in PATH"),
+        "{}",
+        stderr
+    );
+
+    // A directory is read and written without it
+    let local = format!("local:{}", tmp.path().join("repo").display());
+    let output = run(&[
+        "--backend",
+        &local,
+        "publish",
+        "--source",
+        "top.vhd",
+        "--bitstream",
+        "top.bit",
+        "--path",
+        "boards/zedboard",
+    ])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
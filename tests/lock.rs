@@ -0,0 +1,246 @@
+//! `lock` pins the bitstreams a manifest asks for, keeping the pins of a
+//! previous lock file that still hold unless `--update`, and `get --locked`
+//! retrieves exactly those, saving nothing once the repository has drifted
+//! from any of them.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{
+    LockFile, LockManifest, LockOptions, LockedGetOptions, MetadataEntry, LOCK_FILE_NAME,
+};
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const ARTY_MD5: &str = "6f1ed002ab5595859014ebf0951522d9";
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// Publish `contents` as the bitstream of `md5` at `path`, replacing any
+/// bitstream it had
+fn seed(repo: &TestRepo, md5: &str, path: &str, contents: &[u8]) -> io::Result<()> {
+    repo.seed(
+        MetadataEntry::new(md5, path, "top.vhd", "2024-01-01T00:00:00Z"),
+        contents,
+    )
+}
+
+/// A repository with the bitstreams of top.vhd and of the arty source, and
+/// a manifest asking for both, the first by its source
+fn seeded() -> io::Result<(TestRepo, String)> {
+    let repo = TestRepo::new()?;
+    let source = repo.path().join("top.vhd");
+    fs::write(&source, "entity top is end;\n")?;
+    let top_md5 = bitcache::compute_md5(&source)?;
+    seed(&repo, &top_md5, "boards/top.bit", b"top bitstream")?;
+    seed(&repo, ARTY_MD5, "boards/arty.bit", b"arty bitstream")?;
+    fs::write(
+        repo.path().join("inputs.toml"),
+        format!(
+            "[[artifact]]\nname = \"zed\"\nsource = \"top.vhd\"\n\n\
+             [[artifact]]\nname = \"arty\"\nmd5 = \"{}\"\n",
+            ARTY_MD5
+        ),
+    )?;
+    Ok((repo, top_md5))
+}
+
+fn manifest(repo: &TestRepo) -> io::Result<LockManifest> {
+    LockManifest::load(&repo.path().join("inputs.toml"))
+}
+
+fn md5_of(contents: &[u8]) -> String {
+    format!("{:x}", md5::compute(contents))
+}
+
+/// Files saved under `dir`, if it exists
+fn saved(dir: &Path) -> io::Result<usize> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        count += if path.is_dir() { saved(&path)? } else { 1 };
+    }
+    Ok(count)
+}
+
+#[test]
+fn pins_and_retrieves_the_artifacts_of_a_manifest() -> io::Result<()> {
+    let (repo, top_md5) = seeded()?;
+    let client = repo.client()?;
+    let lock = client.lock(&LockOptions::new(manifest(&repo)?))?;
+    let names: Vec<_> = lock.artifacts.iter().map(|a| a.name.as_str()).collect();
+    assert_eq!(names, ["arty", "zed"]);
+    let zed = lock.artifact("zed").expect("zed pinned");
+    assert_eq!(zed.md5, top_md5);
+    assert_eq!(zed.path, "boards/top.bit");
+    assert_eq!(zed.binary_md5, md5_of(b"top bitstream"));
+    assert_eq!(zed.size, Some(b"top bitstream".len() as u64));
+    assert!(zed.commit.is_some());
+
+    // What is saved is read back alike
+    let path = repo.path().join(LOCK_FILE_NAME);
+    lock.save(&path)?;
+    assert_eq!(LockFile::load(&path)?, lock);
+
+    let out = repo.path().join("out");
+    let retrieved = client.get_locked(&lock, &LockedGetOptions::new(&out))?;
+    assert_eq!(retrieved.len(), 2);
+    assert_eq!(fs::read(out.join("arty/arty.bit"))?, b"arty bitstream");
+    assert_eq!(fs::read(out.join("zed/top.bit"))?, b"top bitstream");
+    Ok(())
+}
+
+#[test]
+fn a_drifted_repository_fails_before_anything_is_saved() -> io::Result<()> {
+    let (repo, _) = seeded()?;
+    let lock = repo.client()?.lock(&LockOptions::new(manifest(&repo)?))?;
+    seed(
+        &repo,
+        ARTY_MD5,
+        "boards/arty.bit",
+        b"arty bitstream, rebuilt",
+    )?;
+
+    let out = repo.path().join("out");
+    let error = repo
+        .client()?
+        .get_locked(&lock, &LockedGetOptions::new(&out))
+        .expect_err("retrieved a drifted artifact");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    let message = error.to_string();
+    assert!(
+        message.contains(&format!(
+            "arty: its bitstream MD5 is {} instead of {}",
+            md5_of(b"arty bitstream, rebuilt"),
+            md5_of(b"arty bitstream")
+        )) && message.contains("lock --update"),
+        "{}",
+        message
+    );
+    // Not even the artifact that still matches
+    assert_eq!(saved(&out)?, 0);
+
+    // Nor when an entry is gone altogether
+    let mut gone = lock.clone();
+    gone.artifacts[0].md5 = MISSING_MD5.to_string();
+    let error = repo
+        .client()?
+        .get_locked(&gone, &LockedGetOptions::new(&out))
+        .expect_err("retrieved a missing artifact");
+    assert!(
+        error.to_string().contains(&format!(
+            "the repository has no entry for MD5 {}",
+            MISSING_MD5
+        )),
+        "{}",
+        error
+    );
+    assert_eq!(saved(&out)?, 0);
+    Ok(())
+}
+
+#[test]
+fn a_relock_moves_only_what_changed_unless_updating() -> io::Result<()> {
+    let (repo, _) = seeded()?;
+    let client = repo.client()?;
+    let first = client.lock(&LockOptions::new(manifest(&repo)?))?;
+    seed(
+        &repo,
+        ARTY_MD5,
+        "boards/arty.bit",
+        b"arty bitstream, rebuilt",
+    )?;
+
+    let relocked = client.lock(&LockOptions {
+        manifest: manifest(&repo)?,
+        previous: Some(first.clone()),
+    })?;
+    // The unchanged artifact keeps its pin, commit and all
+    assert_eq!(relocked.artifact("zed"), first.artifact("zed"));
+    let arty = relocked.artifact("arty").expect("arty pinned");
+    assert_eq!(arty.binary_md5, md5_of(b"arty bitstream, rebuilt"));
+    assert_ne!(arty.commit, first.artifact("arty").expect("arty").commit);
+
+    // Updating pins every artifact at the commit the repository is at now
+    let updated = client.lock(&LockOptions::new(manifest(&repo)?))?;
+    assert_eq!(updated.artifact("arty"), Some(arty));
+    assert_eq!(
+        updated.artifact("zed").expect("zed pinned").commit,
+        arty.commit
+    );
+    assert_ne!(updated.artifact("zed"), first.artifact("zed"));
+    Ok(())
+}
+
+#[test]
+fn the_command_line_locks_detects_drift_and_updates() -> io::Result<()> {
+    let (repo, _) = seeded()?;
+    let lock = ["lock", "--manifest", "inputs.toml"];
+    let output = bitcache(&repo, &lock)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let path = repo.path().join(LOCK_FILE_NAME);
+    let written = fs::read_to_string(&path)?;
+    assert!(
+        written.starts_with("# Pinned bitcache artifacts"),
+        "{}",
+        written
+    );
+
+    let output = bitcache(&repo, &lock)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("is up to date"), "{}", stdout);
+    assert_eq!(fs::read_to_string(&path)?, written);
+
+    seed(
+        &repo,
+        ARTY_MD5,
+        "boards/arty.bit",
+        b"arty bitstream, rebuilt",
+    )?;
+    let get = ["get", "--locked", LOCK_FILE_NAME, "--output", "out"];
+    let output = bitcache(&repo, &get)?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("no longer matches the lock file") && stderr.contains("arty: "),
+        "{}",
+        stderr
+    );
+    assert_eq!(saved(&repo.path().join("out"))?, 0);
+
+    let output = bitcache(&repo, &["lock", "--manifest", "inputs.toml", "--update"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains(&format!(
+            "Updated arty: bitstream {} -> {}",
+            md5_of(b"arty bitstream"),
+            md5_of(b"arty bitstream, rebuilt")
+        )),
+        "{}",
+        stdout
+    );
+    let output = bitcache(&repo, &get)?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read(repo.path().join("out/arty/arty.bit"))?,
+        b"arty bitstream, rebuilt"
+    );
+    Ok(())
+}
@@ -0,0 +1,164 @@
+//! Runs sharing a cached clone take turns on its lock file: a second run
+//! waits for the first, or gives up after `--lock-timeout`, and fails at
+//! once when the run recorded as holding the lock has died.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{Context, MetadataEntry};
+use fs2::FileExt;
+use std::fs;
+use std::io::{self, Write};
+use std::process::{self, Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+
+/// A repository with one entry to list
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(MD5, "bits/top.bit", "top.vhd", "2024-01-01T00:00:00Z"),
+        b"top bitstream",
+    )?;
+    Ok(repo)
+}
+
+/// Take the lock of the cached clone the binary uses for `repo`, recording
+/// `pid` as its holder
+fn hold_lock(repo: &TestRepo, pid: u32) -> io::Result<fs::File> {
+    let ctx = Context {
+        cache_dir: Some(repo.path().join("cache")),
+        ..Context::default()
+    };
+    let path = ctx.clone_dir(&repo.remote())?.with_extension("lock");
+    fs::create_dir_all(path.parent().expect("under the cache dir"))?;
+    let mut lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(path)?;
+    lock.try_lock_exclusive()?;
+    writeln!(lock, "{}", pid)?;
+    Ok(lock)
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn a_second_run_waits_for_the_first() -> io::Result<()> {
+    let repo = seeded()?;
+    let lock = hold_lock(&repo, process::id())?;
+    let waiting = common::command(&repo, &["list"])?
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    thread::sleep(Duration::from_secs(1));
+    let mut waiting = waiting;
+    assert!(waiting.try_wait()?.is_none(), "did not wait for the lock");
+
+    drop(lock);
+    let output = waiting.wait_with_output()?;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(MD5));
+    assert!(
+        stderr(&output).contains(&format!(
+            "Waiting for another bitcache run (process {})",
+            process::id()
+        )),
+        "{}",
+        stderr(&output)
+    );
+    Ok(())
+}
+
+#[test]
+fn two_processes_publishing_through_one_clone_both_land() -> io::Result<()> {
+    let repo = seeded()?;
+    let publishers = ["left", "right"]
+        .into_iter()
+        .map(|name| {
+            let source = format!("{}.vhd", name);
+            let bitstream = format!("{}.bit", name);
+            fs::write(
+                repo.path().join(&source),
+                format!("entity {} is end;\n", name),
+            )?;
+            fs::write(repo.path().join(&bitstream), format!("{} bitstream", name))?;
+            common::command(
+                &repo,
+                &[
+                    "publish",
+                    "--source",
+                    &source,
+                    "--bitstream",
+                    &bitstream,
+                    "--path",
+                    "boards/zedboard",
+                ],
+            )?
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    for publisher in publishers {
+        let output = publisher.wait_with_output()?;
+        assert!(output.status.success(), "{}", stderr(&output));
+    }
+
+    let listed = common::bitcache(&repo, &["list"])?;
+    assert!(listed.status.success(), "{}", stderr(&listed));
+    let listed = String::from_utf8_lossy(&listed.stdout);
+    for name in ["left", "right"] {
+        let md5 = bitcache::compute_md5(&repo.path().join(format!("{}.vhd", name)))?;
+        assert!(listed.contains(&md5), "{} is missing:\n{}", name, listed);
+    }
+    Ok(())
+}
+
+#[test]
+fn gives_up_after_the_lock_timeout() -> io::Result<()> {
+    let repo = seeded()?;
+    let _lock = hold_lock(&repo, process::id())?;
+    let started = Instant::now();
+    let output = common::bitcache(&repo, &["list", "--lock-timeout", "1"])?;
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(started.elapsed() < Duration::from_secs(30));
+    assert!(
+        stderr(&output).contains(&format!(
+            "waiting for another bitcache run to finish with the cached clone (process {})",
+            process::id()
+        )),
+        "{}",
+        stderr(&output)
+    );
+    Ok(())
+}
+
+#[test]
+fn a_lock_held_after_its_holder_died_fails_at_once() -> io::Result<()> {
+    let repo = seeded()?;
+    let mut exited = Command::new("git")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .spawn()?;
+    let dead = exited.id();
+    exited.wait()?;
+    let _lock = hold_lock(&repo, dead)?;
+    // The timeout only keeps a regression from hanging the test
+    let output = common::bitcache(&repo, &["list", "--lock-timeout", "60"])?;
+    assert_eq!(output.status.code(), Some(1), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(&format!(
+            "process {} that took it is no longer running",
+            dead
+        )),
+        "{}",
+        stderr(&output)
+    );
+    Ok(())
+}
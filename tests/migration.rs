@@ -0,0 +1,84 @@
+//! A metadata file of every schema version, as tests/fixtures/metadata holds
+//! one of each with the same entries, loads as the current version and saves
+//! as it, keeping the fields and members this bitcache does not know.
+
+use bitcache::{Metadata, METADATA_FILE, METADATA_SCHEMA_VERSION};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+const TOP_MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+const ARTY_MD5: &str = "6f1ed002ab5595859014ebf0951522d9";
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/metadata")
+        .join(name)
+}
+
+/// Copy a fixture into `dir` as the metadata file of a repository
+fn copied(dir: &TempDir, name: &str) -> io::Result<PathBuf> {
+    let path = dir.path().join(METADATA_FILE);
+    fs::copy(fixture(name), &path)?;
+    Ok(path)
+}
+
+fn json(path: &Path) -> io::Result<Value> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+#[test]
+fn every_schema_version_migrates_to_the_current_one() -> io::Result<()> {
+    let current = json(&fixture(&format!("v{}.json", METADATA_SCHEMA_VERSION)))?;
+    for version in 1..=METADATA_SCHEMA_VERSION {
+        let name = format!("v{}.json", version);
+        let dir = TempDir::new()?;
+        let path = copied(&dir, &name)?;
+        let metadata = Metadata::load_from_file(&path)?;
+        assert_eq!(metadata.schema_version, METADATA_SCHEMA_VERSION, "{}", name);
+        assert_eq!(metadata.len(), 2, "{}", name);
+
+        let arty = &metadata.variants(ARTY_MD5)[0];
+        assert_eq!(arty.compression.as_deref(), Some("zstd"), "{}", name);
+        assert_eq!(arty.field("compressed"), None, "{}", name);
+        // Reading a single hash migrates its entries alike
+        assert_eq!(
+            Metadata::lookup_in_file(&path, ARTY_MD5)?,
+            std::slice::from_ref(arty)
+        );
+
+        metadata.save_to_file(&path)?;
+        assert_eq!(json(&path)?, current, "{} saved", name);
+    }
+    Ok(())
+}
+
+#[test]
+fn unknown_fields_survive_a_load_and_save() -> io::Result<()> {
+    let dir = TempDir::new()?;
+    let path = copied(&dir, "v1.json")?;
+    for round in 0..2 {
+        let metadata = Metadata::load_from_file(&path)?;
+        let top = &metadata.variants(TOP_MD5)[0];
+        assert_eq!(
+            top.field("x_build_host"),
+            Some(&Value::from("ci-7")),
+            "round {}",
+            round
+        );
+        metadata.save_to_file(&path)?;
+    }
+
+    let looked_up = Metadata::lookup_in_file(&path, TOP_MD5)?;
+    assert_eq!(
+        looked_up[0].field("x_build_host"),
+        Some(&Value::from("ci-7"))
+    );
+    let saved = json(&path)?;
+    assert_eq!(saved["x_farm"]["name"], "fpga-farm");
+    assert_eq!(saved["x_farm"]["runs"], 3);
+    assert_eq!(saved["entries"][TOP_MD5][0]["x_build_host"], "ci-7");
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Publish hardlinks the bitstream into a throwaway clone, and reflinks or
+//! copies it into a cached one or across file systems, but the tree it
+//! pushes is the same whichever way the bitstream got there.
+
+use bitcache::progress::{Event, ProgressObserver};
+use bitcache::testing::TestRepo;
+use bitcache::{Context, PublishOptions, METADATA_FILE};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+const BITSTREAM: &[u8] = b"\x00\xff\x00\xffplaced bitstream\r\n";
+
+/// Keeps the `--verbose` details of a run
+#[derive(Default)]
+struct Details(Mutex<Vec<String>>);
+
+impl ProgressObserver for Details {
+    fn event(&self, event: &Event) {
+        if let Event::Detail(message) = event {
+            self.0.lock().unwrap().push(message.to_string());
+        }
+    }
+}
+
+/// How publish placed the bitstream, as it reports it
+fn placement(details: &Details) -> String {
+    let details = details.0.lock().unwrap();
+    let placed = details
+        .iter()
+        .find_map(|detail| detail.strip_prefix("Placed bitstream in clone via "))
+        .unwrap_or_else(|| panic!("no placement in {:?}", details));
+    placed.to_string()
+}
+
+/// Every file of the pushed tree but the metadata, whose timestamps differ
+/// from run to run, with its blob id
+fn pushed_tree(repo: &TestRepo) -> io::Result<Vec<String>> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.url())
+        .args(["ls-tree", "-r", "main"])
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.ends_with(METADATA_FILE))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Publish the same source and bitstream, the bitstream written in `dir` or
+/// else beside the repository, returning the pushed tree and how the
+/// bitstream was placed
+fn publish(cached: bool, dir: Option<&Path>) -> io::Result<(Vec<String>, String)> {
+    let repo = TestRepo::new()?;
+    let source = repo.path().join("top.vhd");
+    fs::write(&source, "entity top is end;\n")?;
+    let bitstream: PathBuf = dir.unwrap_or(repo.path()).join("top.bit");
+    fs::write(&bitstream, BITSTREAM)?;
+
+    let details = Arc::new(Details::default());
+    let ctx = Context {
+        cache_dir: cached.then(|| repo.path().join("cache")),
+        observer: Some(details.clone()),
+        ..repo.context()?
+    };
+    bitcache::publish(
+        &repo.remote(),
+        &PublishOptions::new(&source, &bitstream, "bits"),
+        &ctx,
+    )?;
+    Ok((pushed_tree(&repo)?, placement(&details)))
+}
+
+#[test]
+fn hardlink_reflink_and_copy_push_the_same_tree() -> io::Result<()> {
+    let (linked, placed) = publish(false, None)?;
+    assert_eq!(placed, "hardlink");
+    // A cached clone outlives the run, so it never shares the caller's inode
+    let (cloned, placed) = publish(true, None)?;
+    assert!(placed == "reflink" || placed == "copy", "{}", placed);
+    assert_eq!(cloned, linked);
+
+    // Neither a hard link nor a reflink crosses file systems
+    let elsewhere = Path::new("/dev/shm");
+    if elsewhere.is_dir() && !same_device(elsewhere, &std::env::temp_dir())? {
+        let dir = tempfile::tempdir_in(elsewhere)?;
+        let (copied, placed) = publish(false, Some(dir.path()))?;
+        assert_eq!(placed, "copy");
+        assert_eq!(copied, linked);
+    }
+
+    let blob = linked
+        .iter()
+        .find(|line| line.ends_with("bits/top.bit"))
+        .expect("bitstream pushed");
+    assert!(blob.contains(&hash_object(BITSTREAM)?), "{}", blob);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn same_device(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(fs::metadata(a)?.dev() == fs::metadata(b)?.dev())
+}
+
+#[cfg(not(unix))]
+fn same_device(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// The blob id git gives `contents`
+fn hash_object(contents: &[u8]) -> io::Result<String> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("blob");
+    fs::write(&path, contents)?;
+    let output = Command::new("git")
+        .args(["hash-object", "--no-filters"])
+        .arg(&path)
+        .output()?;
+    assert!(output.status.success());
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
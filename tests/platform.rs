@@ -0,0 +1,163 @@
+//! Entries record the operating system and architecture a bitstream was
+//! built for, and the version of the tool that built it.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{PublishOptions, RegisterOptions};
+use common::bitcache;
+use std::env;
+use std::fs;
+use std::io;
+
+/// Options publishing a bitstream for the source `name`
+fn inputs(repo: &TestRepo, name: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let bitstream = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&bitstream, format!("{} bitstream", name))?;
+    Ok(PublishOptions::new(source, bitstream, "boards/zedboard"))
+}
+
+#[test]
+fn records_this_machine_unless_told_otherwise() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let here = client.publish(&inputs(&repo, "top")?)?;
+    let elsewhere = client.publish(&PublishOptions {
+        os: Some("windows".to_string()),
+        arch: Some("aarch64".to_string()),
+        build_tool_version: Some("Vivado 2023.2".to_string()),
+        ..inputs(&repo, "uart")?
+    })?;
+
+    let entries = client.list()?;
+    let entry = |md5: &str| {
+        entries
+            .iter()
+            .find(|entry| entry.md5 == md5)
+            .expect("published entry")
+    };
+    assert_eq!(entry(&here.md5).os.as_deref(), Some(env::consts::OS));
+    assert_eq!(entry(&here.md5).arch.as_deref(), Some(env::consts::ARCH));
+    assert_eq!(entry(&here.md5).build_tool_version, None);
+    assert_eq!(entry(&elsewhere.md5).os.as_deref(), Some("windows"));
+    assert_eq!(entry(&elsewhere.md5).arch.as_deref(), Some("aarch64"));
+    assert_eq!(
+        entry(&elsewhere.md5).build_tool_version.as_deref(),
+        Some("Vivado 2023.2")
+    );
+    Ok(())
+}
+
+#[test]
+fn register_records_them_too() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let here = client.register(&RegisterOptions::new(
+        "0cc175b9c0f1b6a831c399e269772661",
+        "top.vhd",
+        "boards/zedboard/top.bit",
+    ))?;
+    assert_eq!(here.entry.os.as_deref(), Some(env::consts::OS));
+    assert_eq!(here.entry.arch.as_deref(), Some(env::consts::ARCH));
+
+    let elsewhere = client.register(&RegisterOptions {
+        os: Some("windows".to_string()),
+        arch: Some("aarch64".to_string()),
+        build_tool_version: Some("Vivado 2023.2".to_string()),
+        ..RegisterOptions::new(
+            "92eb5ffee6ae2fec3ad71c777531578f",
+            "uart.vhd",
+            "boards/zedboard/uart.bit",
+        )
+    })?;
+    assert_eq!(elsewhere.entry.os.as_deref(), Some("windows"));
+    assert_eq!(elsewhere.entry.arch.as_deref(), Some("aarch64"));
+    assert_eq!(
+        elsewhere.entry.build_tool_version.as_deref(),
+        Some("Vivado 2023.2")
+    );
+    Ok(())
+}
+
+#[test]
+fn the_command_line_records_and_lists_them() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    inputs(&repo, "top")?;
+    let output = bitcache(
+        &repo,
+        &[
+            "publish",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+            "--os",
+            "linux",
+            "--arch",
+            "riscv64",
+            "--build-tool-version",
+            "Vivado 2023.2",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = bitcache(&repo, &["list"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let header = stdout.lines().next().unwrap_or_default();
+    assert!(header.contains("PLATFORM"), "{}", stdout);
+    assert!(header.contains("TOOL"), "{}", stdout);
+    assert!(stdout.contains("linux/riscv64"), "{}", stdout);
+    assert!(stdout.contains("Vivado 2023.2"), "{}", stdout);
+
+    let output = bitcache(&repo, &["--format", "csv", "list"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(",linux,riscv64,Vivado 2023.2,"),
+        "{}",
+        stdout
+    );
+    Ok(())
+}
+
+#[test]
+fn the_build_tool_version_is_read_from_the_config() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    inputs(&repo, "top")?;
+    let config = repo.path().join("config/bitcache");
+    fs::create_dir_all(&config)?;
+    fs::write(
+        config.join("config.toml"),
+        "build_tool_version = \"Quartus 23.1\"\n",
+    )?;
+    let output = bitcache(
+        &repo,
+        &[
+            "publish",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let entries = repo.client()?.list()?;
+    assert_eq!(
+        entries[0].build_tool_version.as_deref(),
+        Some("Quartus 23.1")
+    );
+    Ok(())
+}
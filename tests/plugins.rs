@@ -0,0 +1,193 @@
+//! An unknown subcommand runs the `bitcache-<name>` plugin on PATH with the
+//! resolved configuration but no token in its environment, and `--list-plugins` lists the
+//! plugins a built-in command does not shadow. The example plugin in
+//! contrib/plugins publishes through bitcache and logs what it published.
+#![cfg(unix)]
+
+use bitcache::testing::{self, TestRepo};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Prints its arguments and the variables bitcache passes on, then fails
+/// with a status of its own
+const ECHO: &str = "#!/bin/sh
+printf 'arg %s\\n' \"$@\"
+echo \"repo $BITCACHE_REPO\"
+echo \"cache $BITCACHE_CACHE_DIR\"
+echo \"api $BITCACHE_PLUGIN_API\"
+echo \"bin $BITCACHE_BIN\"
+echo \"token ${BITCACHE_TOKEN:-none}\"
+exit 3
+";
+
+fn install(dir: &Path, name: &str, script: &str, mode: u32) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(name);
+    fs::write(&path, script)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+    Ok(path)
+}
+
+/// The plugins directory holding the echo plugin, a plugin a built-in
+/// command shadows and a file that is not executable
+fn plugins(repo: &TestRepo) -> io::Result<PathBuf> {
+    let dir = repo.path().join("plugins");
+    install(&dir, "bitcache-echo", ECHO, 0o755)?;
+    install(&dir, "bitcache-publish", ECHO, 0o755)?;
+    install(&dir, "bitcache-unmarked", ECHO, 0o644)?;
+    Ok(dir)
+}
+
+/// Run bitcache with `dirs` first on PATH and the repository in the
+/// environment, as a plugin's own options come after its name
+fn command(repo: &TestRepo, dirs: &[&Path], args: &[&str]) -> io::Result<Command> {
+    let path = env::join_paths(
+        dirs.iter()
+            .map(|dir| dir.to_path_buf())
+            .chain(env::split_paths(&env::var_os("PATH").unwrap_or_default())),
+    )
+    .expect("PATH");
+    let config = repo.path().join("config");
+    fs::create_dir_all(&config)?;
+    let mut command = Command::new(env!("CARGO_BIN_EXE_bitcache"));
+    command
+        .arg("--cache-dir")
+        .arg(repo.path().join("cache"))
+        .arg("--work-dir")
+        .arg(repo.path())
+        .args(args)
+        .current_dir(repo.path())
+        .env("PATH", path)
+        .env("BITCACHE_REPO", repo.url())
+        .env("XDG_CONFIG_HOME", &config)
+        .envs(testing::identity_env());
+    Ok(command)
+}
+
+fn run(repo: &TestRepo, dirs: &[&Path], args: &[&str]) -> io::Result<Output> {
+    command(repo, dirs, args)?.output()
+}
+
+#[test]
+fn a_plugin_runs_with_its_arguments_and_the_configuration() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let dir = plugins(&repo)?;
+    let output = command(&repo, &[&dir], &["echo", "one", "--two", "three four"])?
+        .env("BITCACHE_TOKEN", "s3cret-token")
+        .output()?;
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "arg one".to_string(),
+            "arg --two".to_string(),
+            "arg three four".to_string(),
+            format!("repo {}", repo.url()),
+            format!("cache {}", repo.path().join("cache").display()),
+            "api 1".to_string(),
+            format!("bin {}", env!("CARGO_BIN_EXE_bitcache")),
+            // The token bitcache was given is not passed on
+            "token none".to_string(),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn only_an_executable_plugin_no_builtin_shadows_runs() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let dir = plugins(&repo)?;
+    let output = run(&repo, &[&dir], &["publish", "--help"])?;
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("arg --help"));
+
+    for name in ["unmarked", "missing"] {
+        let output = run(&repo, &[&dir], &[name])?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{} ran", name);
+        assert!(
+            stderr.contains(&format!("unrecognized subcommand '{}'", name))
+                && stderr.contains("--list-plugins"),
+            "{}: {}",
+            name,
+            stderr
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn list_plugins_lists_what_a_plugin_name_runs() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let dir = plugins(&repo)?;
+    // The first plugin of a name on PATH is the one that runs
+    let earlier = repo.path().join("earlier");
+    let first = install(&earlier, "bitcache-echo", ECHO, 0o755)?;
+
+    let output = run(&repo, &[&earlier, &dir], &["--list-plugins"])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Plugins elsewhere on the PATH of whoever runs the tests are left out
+    let listed: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.contains(&*repo.path().to_string_lossy()))
+        .collect();
+    assert_eq!(listed, [format!("echo  {}", first.display())]);
+
+    let output = run(&repo, &[&earlier, &dir], &["--json", "--list-plugins"])?;
+    let plugins: Value = serde_json::from_slice(&output.stdout).expect("JSON on stdout");
+    let echo = plugins
+        .as_array()
+        .expect("a list")
+        .iter()
+        .find(|plugin| plugin["name"] == "echo")
+        .expect("echo listed");
+    assert_eq!(echo["path"], first.to_string_lossy().as_ref());
+    Ok(())
+}
+
+#[test]
+fn the_example_plugin_publishes_and_logs() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let contrib = Path::new(env!("CARGO_MANIFEST_DIR")).join("contrib/plugins");
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(repo.path().join("top.bit"), b"top bitstream")?;
+    let log = repo.path().join("published.log");
+
+    let args = [
+        "--source",
+        "top.vhd",
+        "--bitstream",
+        "top.bit",
+        "--path",
+        "bits",
+    ];
+    let mut all = vec!["publish-log"];
+    all.extend(args);
+    let output = command(&repo, &[&contrib], &all)?
+        .env("BITCACHE_PUBLISH_LOG", &log)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let tree = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.url())
+        .args(["ls-tree", "--name-only", "main", "bits/"])
+        .output()?;
+    assert_eq!(String::from_utf8_lossy(&tree.stdout).trim(), "bits/top.bit");
+    let logged = fs::read_to_string(&log)?;
+    let fields: Vec<_> = logged.trim_end().split('\t').collect();
+    assert_eq!(fields[1..], [repo.url(), &args.join(" ")]);
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! The observer of a context or client sees each phase of an operation start
+//! and finish in order, the bytes it processes and the git commands it runs,
+//! and a client reports only what it runs to its observer.
+
+use bitcache::progress::{Phase, Recorded, RecordingObserver};
+use bitcache::testing::TestRepo;
+use bitcache::{Context, GetOptions, PublishOptions};
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+/// The phases in `events` as they started, checking that each finished
+/// before the next one started
+fn phases(events: &[Recorded]) -> Vec<Phase> {
+    let mut started = Vec::new();
+    let mut open: Option<Phase> = None;
+    for event in events {
+        match event {
+            Recorded::PhaseStarted(phase) => {
+                assert_eq!(open, None, "{:?} started inside {:?}", phase, open);
+                open = Some(*phase);
+                started.push(*phase);
+            }
+            Recorded::PhaseFinished(phase) => {
+                assert_eq!(open, Some(*phase), "{:?} finished unstarted", phase);
+                open = None;
+            }
+            Recorded::Bytes { phase, .. } => {
+                assert_eq!(open, Some(*phase), "bytes outside {:?}", phase);
+            }
+            _ => {}
+        }
+    }
+    assert_eq!(open, None, "left unfinished");
+    started
+}
+
+/// Whether `wanted` appears in `all` in order, not necessarily adjacent
+fn in_order<T: PartialEq>(all: &[T], wanted: &[T]) -> bool {
+    let mut all = all.iter();
+    wanted.iter().all(|want| all.any(|item| item == want))
+}
+
+/// The last byte count reported for `phase`
+fn bytes(events: &[Recorded], of: Phase) -> Option<(u64, Option<u64>)> {
+    events.iter().rev().find_map(|event| match event {
+        Recorded::Bytes { phase, done, total } if *phase == of => Some((*done, *total)),
+        _ => None,
+    })
+}
+
+fn git_commands(events: &[Recorded]) -> Vec<&str> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Recorded::GitCommand(subcommand) => Some(subcommand.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn a_publish_reports_its_phases_in_order() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, vec![7u8; 4096])?;
+    let recorder = Arc::new(RecordingObserver::new());
+    let ctx = Context {
+        observer: Some(recorder.clone()),
+        ..repo.context()?
+    };
+    bitcache::publish(
+        &repo.remote(),
+        &PublishOptions::new(&source, &bitstream, "bits"),
+        &ctx,
+    )?;
+
+    let events = recorder.events();
+    let phases = phases(&events);
+    assert!(
+        in_order(
+            &phases,
+            &[
+                Phase::Hashing,
+                Phase::Cloning,
+                Phase::Staging,
+                Phase::Committing,
+                Phase::Pushing,
+            ]
+        ),
+        "{:?}",
+        phases
+    );
+    // The bitstream is hashed last, and to the end
+    assert_eq!(bytes(&events, Phase::Hashing), Some((4096, Some(4096))));
+    let commands = git_commands(&events);
+    assert!(
+        in_order(&commands, &["clone", "add", "commit", "push"]),
+        "{:?}",
+        commands
+    );
+    // Each git command is announced before the phase it runs in
+    let push = events
+        .iter()
+        .position(|event| *event == Recorded::GitCommand("push".to_string()));
+    let pushing = events
+        .iter()
+        .position(|event| *event == Recorded::PhaseStarted(Phase::Pushing));
+    assert!(push < pushing, "{:?}", events);
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, Recorded::Status(line) if line.starts_with("MD5: "))));
+    Ok(())
+}
+
+#[test]
+fn a_client_reports_to_its_observer_only() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, b"top bitstream")?;
+    let published = repo
+        .client()?
+        .publish(&PublishOptions::new(&source, &bitstream, "bits"))?;
+
+    let recorder = Arc::new(RecordingObserver::new());
+    let client = repo.builder()?.observer(recorder.clone()).build()?;
+    let opts = GetOptions {
+        output: Some(repo.path().join("out.bit")),
+        ..GetOptions::new(&published.md5)
+    };
+    client.get(&opts)?.expect("published");
+
+    let events = recorder.events();
+    let phases = phases(&events);
+    assert!(
+        in_order(&phases, &[Phase::Cloning, Phase::Copying, Phase::Hashing]),
+        "{:?}",
+        phases
+    );
+    assert_eq!(bytes(&events, Phase::Copying), Some((13, Some(13))));
+    assert!(!phases.contains(&Phase::Pushing));
+    // The publish by another client went unrecorded
+    assert!(events.iter().all(|event| !matches!(
+        event,
+        Recorded::GitCommand(subcommand) if subcommand == "push"
+    )));
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Entries record who published them, the version of bitcache that did and
+//! the commit of the work tree their source was in, and the commit adding
+//! them carries the same as trailers.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{PublishOptions, RegisterOptions};
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// Run git in `dir`, failing the test when it fails, and return its output
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .expect("git runs");
+    assert!(
+        output.status.success(),
+        "git {:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// A git work tree in `repo` holding a committed source, with its own user,
+/// returning the directory and the commit
+fn work_tree(repo: &TestRepo) -> (std::path::PathBuf, String) {
+    let dir = repo.path().join("rtl");
+    fs::create_dir(&dir).expect("create the work tree");
+    git(&dir, &["init", "--quiet"]);
+    git(&dir, &["config", "user.name", "Ada Designer"]);
+    git(&dir, &["config", "user.email", "ada@example.com"]);
+    fs::write(dir.join("top.vhd"), "entity top is end;\n").expect("write the source");
+    fs::write(dir.join("top.bit"), "top bitstream").expect("write the bitstream");
+    git(&dir, &["add", "top.vhd"]);
+    git(&dir, &["commit", "--quiet", "-m", "Add top"]);
+    let commit = git(&dir, &["rev-parse", "HEAD"]);
+    (dir, commit)
+}
+
+/// Message of the commit at the head of `repo`
+fn head_message(repo: &TestRepo) -> String {
+    git(
+        repo.path(),
+        &["--git-dir", repo.url(), "log", "-1", "--format=%B", "main"],
+    )
+}
+
+#[test]
+fn records_the_git_user_and_commit_of_the_source() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let (dir, commit) = work_tree(&repo);
+    let client = repo.client()?;
+    client.publish(&PublishOptions::new(
+        dir.join("top.vhd"),
+        dir.join("top.bit"),
+        "boards/zedboard",
+    ))?;
+
+    let entries = client.list()?;
+    assert_eq!(
+        entries[0].published_by.as_deref(),
+        Some("Ada Designer <ada@example.com>")
+    );
+    assert_eq!(entries[0].source_commit.as_deref(), Some(commit.as_str()));
+    assert_eq!(
+        entries[0].tool_version.as_deref(),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+
+    let message = head_message(&repo);
+    assert!(
+        message.contains("\n\nPublished-by: Ada Designer <ada@example.com>\n"),
+        "{}",
+        message
+    );
+    assert!(
+        message.contains(&format!("Source-commit: {}", commit)),
+        "{}",
+        message
+    );
+    assert!(
+        message.ends_with(&format!("Bitcache-version: {}", env!("CARGO_PKG_VERSION"))),
+        "{}",
+        message
+    );
+    Ok(())
+}
+
+#[test]
+fn the_options_override_what_git_says() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let (dir, _) = work_tree(&repo);
+    let client = repo.client()?;
+    client.publish(&PublishOptions {
+        author: Some("CI <ci@example.com>".to_string()),
+        source_commit: Some("0123abc".to_string()),
+        ..PublishOptions::new(dir.join("top.vhd"), dir.join("top.bit"), "boards/zedboard")
+    })?;
+    let entry = &client.list()?[0];
+    assert_eq!(entry.published_by.as_deref(), Some("CI <ci@example.com>"));
+    assert_eq!(entry.source_commit.as_deref(), Some("0123abc"));
+
+    // Without detection, a source in a work tree records no commit
+    fs::write(dir.join("uart.vhd"), "entity uart is end;\n")?;
+    fs::write(dir.join("uart.bit"), "uart bitstream")?;
+    let published = client.publish(&PublishOptions {
+        detect_source_commit: false,
+        ..PublishOptions::new(
+            dir.join("uart.vhd"),
+            dir.join("uart.bit"),
+            "boards/zedboard",
+        )
+    })?;
+    let entries = client.list()?;
+    let uart = entries
+        .iter()
+        .find(|entry| entry.md5 == published.md5)
+        .expect("uart entry");
+    assert_eq!(uart.source_commit, None);
+    assert!(!head_message(&repo).contains("Source-commit:"));
+
+    // register records who registered it
+    let registered = client.register(&RegisterOptions {
+        author: Some("Ada Designer".to_string()),
+        ..RegisterOptions::new(MISSING_MD5, "spi.vhd", "boards/zedboard/top.bit")
+    })?;
+    assert_eq!(
+        registered.entry.published_by.as_deref(),
+        Some("Ada Designer")
+    );
+    assert_eq!(
+        registered.entry.tool_version.as_deref(),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+    Ok(())
+}
+
+#[test]
+fn the_command_line_records_and_shows_them() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let (dir, _) = work_tree(&repo);
+    let source = dir.join("top.vhd");
+    let bitstream = dir.join("top.bit");
+    let output = bitcache(
+        &repo,
+        &[
+            "publish",
+            "--source",
+            &source.to_string_lossy(),
+            "--bitstream",
+            &bitstream.to_string_lossy(),
+            "--path",
+            "boards/zedboard",
+            "--author",
+            "CI <ci@example.com>",
+            "--no-source-commit",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let md5 = bitcache::compute_md5(&source)?;
+
+    let output = bitcache(&repo, &["info", "--md5", &md5])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Published by: CI <ci@example.com>"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(&format!("Bitcache version: {}", env!("CARGO_PKG_VERSION"))),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("Source commit:"), "{}", stdout);
+
+    let output = bitcache(&repo, &["--format", "csv", "list"])?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!(
+            ",CI <ci@example.com>,{},,",
+            env!("CARGO_PKG_VERSION")
+        )),
+        "{}",
+        stdout
+    );
+
+    let output = bitcache(
+        &repo,
+        &[
+            "publish",
+            "--source",
+            &source.to_string_lossy(),
+            "--bitstream",
+            &bitstream.to_string_lossy(),
+            "--path",
+            "boards/zedboard",
+            "--source-commit",
+            "0123abc",
+            "--no-source-commit",
+        ],
+    )?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--no-source-commit"));
+    Ok(())
+}
@@ -0,0 +1,212 @@
+//! `prune` removes the entries outside a retention policy, or that a filter
+//! picks, to the trash or for good, and finds the bitstreams no entry refers
+//! to.
+
+use bitcache::filter::Filter;
+use bitcache::testing::{self, TestRepo};
+use bitcache::{BitcacheError, MetadataEntry, PruneOptions};
+use chrono::{SecondsFormat, Utc};
+use std::fs;
+use std::io;
+use std::process::Command;
+use std::time::Duration;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A repository with four builds of `top.vhd`, from 2020 to 2023, one of
+/// `other.vhd` from 2020, and one of `new.vhd` published just now
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    for year in 2020..=2023 {
+        repo.seed(
+            MetadataEntry::new(
+                format!("{:032x}", year),
+                format!("top/{}.bit", year),
+                "top.vhd",
+                format!("{}-01-01T00:00:00Z", year),
+            ),
+            format!("top {}", year).as_bytes(),
+        )?;
+    }
+    repo.seed(
+        MetadataEntry::new(
+            format!("{:032x}", 1),
+            "other/other.bit",
+            "other.vhd",
+            "2020-01-01T00:00:00Z",
+        ),
+        b"other",
+    )?;
+    let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    repo.seed(
+        MetadataEntry::new(format!("{:032x}", 2), "new/new.bit", "new.vhd", now),
+        b"new",
+    )?;
+    Ok(repo)
+}
+
+/// The sources of the repository's entries, sorted
+fn sources(repo: &TestRepo) -> io::Result<Vec<String>> {
+    let mut entries = repo.client()?.list()?;
+    entries.sort_by(|a, b| a.binary_path.cmp(&b.binary_path));
+    Ok(entries.into_iter().map(|entry| entry.binary_path).collect())
+}
+
+/// Commit a file no entry refers to next to the entries of `top.vhd`
+fn orphan(repo: &TestRepo) -> io::Result<()> {
+    let clone = repo.path().join("orphan");
+    let git = |args: &[&str]| -> io::Result<()> {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(&clone)
+            .envs(testing::identity_env())
+            .status()?;
+        assert!(status.success(), "git {:?}", args);
+        Ok(())
+    };
+    fs::create_dir_all(&clone)?;
+    git(&["clone", "--quiet", repo.url(), "."])?;
+    fs::write(clone.join("top/stray.bit"), b"stray bitstream")?;
+    git(&["add", "top/stray.bit"])?;
+    git(&["commit", "--quiet", "-m", "Add a stray bitstream"])?;
+    git(&["push", "--quiet", "origin", "HEAD:main"])?;
+    fs::remove_dir_all(&clone)
+}
+
+#[test]
+fn keeps_the_newest_of_each_source() -> io::Result<()> {
+    let repo = seeded()?;
+    let client = repo.client()?;
+    let pruned = client.prune(&PruneOptions {
+        keep_latest: Some(2),
+        ..PruneOptions::new()
+    })?;
+    assert!(pruned.committed);
+    let paths: Vec<_> = pruned.entries.iter().map(|e| &e.binary_path).collect();
+    // Oldest first
+    assert_eq!(paths, ["top/2020.bit", "top/2021.bit"]);
+    assert_eq!(pruned.entry_bytes, 2 * "top 2020".len() as u64);
+    assert_eq!(
+        sources(&repo)?,
+        [
+            "new/new.bit",
+            "other/other.bit",
+            "top/2022.bit",
+            "top/2023.bit"
+        ]
+    );
+    // Without --hard they can be brought back
+    assert_eq!(client.list_trash()?.len(), 2);
+    Ok(())
+}
+
+#[test]
+fn an_age_and_a_count_must_both_allow_it() -> io::Result<()> {
+    let repo = seeded()?;
+    let pruned = repo.client()?.prune(&PruneOptions {
+        older_than: Some(30 * DAY),
+        keep_latest: Some(1),
+        ..PruneOptions::new()
+    })?;
+    assert_eq!(pruned.entries.len(), 3);
+    // Each source keeps its newest entry, however old
+    assert_eq!(
+        sources(&repo)?,
+        ["new/new.bit", "other/other.bit", "top/2023.bit"]
+    );
+
+    let pruned = repo.client()?.prune(&PruneOptions {
+        older_than: Some(30 * DAY),
+        ..PruneOptions::new()
+    })?;
+    assert_eq!(pruned.entries.len(), 2);
+    assert_eq!(sources(&repo)?, ["new/new.bit"]);
+    Ok(())
+}
+
+#[test]
+fn a_filter_narrows_what_is_pruned() -> io::Result<()> {
+    let repo = seeded()?;
+    let filter = |expression: &str| Some(Filter::parse(expression).expect("valid filter"));
+    let pruned = repo.client()?.prune(&PruneOptions {
+        older_than: Some(30 * DAY),
+        filter: filter("source = top.vhd"),
+        ..PruneOptions::new()
+    })?;
+    assert_eq!(pruned.entries.len(), 4);
+    assert_eq!(sources(&repo)?, ["new/new.bit", "other/other.bit"]);
+
+    // Alone it prunes whatever it picks
+    let pruned = repo.client()?.prune(&PruneOptions {
+        filter: filter("path ~ 'new/*'"),
+        ..PruneOptions::new()
+    })?;
+    assert_eq!(pruned.entries.len(), 1);
+    assert_eq!(sources(&repo)?, ["other/other.bit"]);
+    Ok(())
+}
+
+#[test]
+fn a_dry_run_changes_nothing() -> io::Result<()> {
+    let repo = seeded()?;
+    orphan(&repo)?;
+    let pruned = repo.client()?.prune(&PruneOptions {
+        older_than: Some(DAY),
+        orphans: true,
+        dry_run: true,
+        ..PruneOptions::new()
+    })?;
+    assert!(!pruned.committed);
+    assert_eq!(pruned.entries.len(), 5);
+    assert_eq!(pruned.orphans, ["top/stray.bit"]);
+    assert_eq!(pruned.orphan_bytes, "stray bitstream".len() as u64);
+    assert_eq!(sources(&repo)?.len(), 6);
+    Ok(())
+}
+
+#[test]
+fn removes_orphans_and_hard_pruned_bitstreams() -> io::Result<()> {
+    let repo = seeded()?;
+    orphan(&repo)?;
+    let client = repo.client()?;
+    let pruned = client.prune(&PruneOptions {
+        keep_latest: Some(3),
+        orphans: true,
+        hard: true,
+        ..PruneOptions::new()
+    })?;
+    assert!(pruned.committed);
+    assert_eq!(pruned.entries.len(), 1);
+    assert_eq!(pruned.orphans, ["top/stray.bit"]);
+    assert!(client.list_trash()?.is_empty());
+
+    let tree = Command::new("git")
+        .args([
+            "--git-dir",
+            repo.url(),
+            "ls-tree",
+            "-r",
+            "--name-only",
+            "main",
+        ])
+        .output()?;
+    let tree = String::from_utf8_lossy(&tree.stdout);
+    assert!(!tree.contains("top/2020.bit"), "{}", tree);
+    assert!(!tree.contains("top/stray.bit"), "{}", tree);
+    assert!(tree.contains("top/2021.bit"), "{}", tree);
+    Ok(())
+}
+
+#[test]
+fn refuses_to_prune_without_a_policy() -> io::Result<()> {
+    let repo = seeded()?;
+    let error = repo
+        .client()?
+        .prune(&PruneOptions::new())
+        .expect_err("pruned without a policy");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::InvalidArgument { flag, .. }) => assert_eq!(*flag, "--older-than"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! publish-batch publishes every bitstream of a batch in one commit, or none
+//! of them.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{MetadataEntry, PublishOptions};
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+const SEEDED_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(
+        MetadataEntry::new(SEEDED_MD5, "old/old.bit", "old.vhd", "2024-01-01T00:00:00Z"),
+        b"old bitstream",
+    )?;
+    Ok(repo)
+}
+
+/// Write a source and a bitstream named after `name` and the options to
+/// publish them under `path`
+fn inputs(repo: &TestRepo, name: &str, path: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let bitstream = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&bitstream, format!("{} bitstream", name))?;
+    Ok(PublishOptions::new(source, bitstream, path))
+}
+
+fn commit_count(repo: &TestRepo) -> io::Result<usize> {
+    let output = Command::new("git")
+        .args(["--git-dir", repo.url(), "rev-list", "--count", "main"])
+        .output()?;
+    assert!(output.status.success());
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .unwrap())
+}
+
+fn md5s(repo: &TestRepo) -> io::Result<Vec<String>> {
+    let mut md5s: Vec<_> = repo.client()?.list()?.into_iter().map(|e| e.md5).collect();
+    md5s.sort();
+    Ok(md5s)
+}
+
+#[test]
+fn publishes_the_batch_in_one_commit() -> io::Result<()> {
+    let repo = seeded()?;
+    let before = commit_count(&repo)?;
+    let batch = [
+        inputs(&repo, "zed", "boards/zedboard")?,
+        inputs(&repo, "arty", "boards/arty")?,
+    ];
+    let published = repo.client()?.publish_batch(&batch)?;
+    assert_eq!(published.len(), 2);
+    assert_eq!(published[0].binary_path, "boards/zedboard/zed.bit");
+    assert_eq!(published[1].binary_path, "boards/arty/arty.bit");
+    assert_eq!(commit_count(&repo)?, before + 1);
+
+    let mut expected: Vec<_> = published.iter().map(|p| p.md5.clone()).collect();
+    expected.push(SEEDED_MD5.to_string());
+    expected.sort();
+    assert_eq!(md5s(&repo)?, expected);
+    Ok(())
+}
+
+#[test]
+fn a_missing_input_fails_the_whole_batch() -> io::Result<()> {
+    let repo = seeded()?;
+    let before = commit_count(&repo)?;
+    let mut missing = inputs(&repo, "missing", "boards/missing")?;
+    missing.bitstream = repo.path().join("nowhere.bit");
+    let batch = [inputs(&repo, "zed", "boards/zedboard")?, missing];
+    assert!(repo.client()?.publish_batch(&batch).is_err());
+    assert_eq!(commit_count(&repo)?, before);
+    assert_eq!(md5s(&repo)?, [SEEDED_MD5]);
+    Ok(())
+}
+
+#[test]
+fn refuses_duplicate_sources_and_paths() -> io::Result<()> {
+    let repo = seeded()?;
+    let client = repo.client()?;
+    let zed = inputs(&repo, "zed", "boards/zedboard")?;
+
+    let same_source = PublishOptions {
+        bitstream: inputs(&repo, "arty", "boards/arty")?.bitstream,
+        rename_in_repo: Some("zed.bit".into()),
+        ..inputs(&repo, "zed", "boards/arty")?
+    };
+    let error = client
+        .publish_batch(&[zed.clone(), same_source])
+        .expect_err("published one source and name twice");
+    assert!(
+        error
+            .to_string()
+            .contains("one bitstream per source and name"),
+        "{}",
+        error
+    );
+
+    let mut same_path = inputs(&repo, "other", "boards/zedboard")?;
+    same_path.rename_in_repo = Some("zed.bit".into());
+    let error = client
+        .publish_batch(&[zed, same_path])
+        .expect_err("published two bitstreams to one path");
+    assert!(
+        error.to_string().contains("the batch puts both"),
+        "{}",
+        error
+    );
+    assert_eq!(md5s(&repo)?, [SEEDED_MD5]);
+    Ok(())
+}
+
+#[test]
+fn copies_in_parallel_as_one_at_a_time() -> io::Result<()> {
+    let mut trees = Vec::new();
+    for jobs in [1, 4] {
+        let repo = seeded()?;
+        let mut batch = Vec::new();
+        for name in ["zed", "arty", "kc705", "vc707", "zcu102"] {
+            batch.push(inputs(&repo, name, &format!("boards/{}", name))?);
+        }
+        // A second source with the same bitstream at the same path, copied once
+        fs::write(repo.path().join("zed2.vhd"), "entity zed2 is end;\n")?;
+        batch.push(PublishOptions {
+            rename_in_repo: Some("zed.bit".into()),
+            ..PublishOptions::new(
+                repo.path().join("zed2.vhd"),
+                repo.path().join("zed.bit"),
+                "boards/zed",
+            )
+        });
+        let published = repo.builder()?.jobs(jobs).build()?.publish_batch(&batch)?;
+        assert_eq!(published.len(), 6);
+        for (opts, published) in batch.iter().zip(&published) {
+            assert_eq!(published.size, fs::metadata(&opts.bitstream)?.len());
+        }
+
+        let output = Command::new("git")
+            .args(["--git-dir", repo.url(), "ls-tree", "-r", "main"])
+            .output()?;
+        assert!(output.status.success());
+        // The bitstreams, leaving out the metadata file and its timestamps
+        let tree: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| line.ends_with(".bit"))
+            .map(str::to_string)
+            .collect();
+        assert_eq!(tree.len(), 6, "{:?}", tree);
+        trees.push(tree);
+    }
+    // The same files with the same contents either way
+    assert_eq!(trees[0], trees[1]);
+    Ok(())
+}
+
+#[test]
+fn the_command_line_takes_jobs() -> io::Result<()> {
+    let repo = seeded()?;
+    inputs(&repo, "zed", "boards/zedboard")?;
+    inputs(&repo, "arty", "boards/arty")?;
+    fs::write(
+        repo.path().join("bitstreams.toml"),
+        "[[entry]]\nsource = \"zed.vhd\"\nbitstream = \"zed.bit\"\npath = \"boards/zedboard\"\n\n\
+         [[entry]]\nsource = \"arty.vhd\"\nbitstream = \"arty.bit\"\npath = \"boards/arty\"\n",
+    )?;
+    let output = bitcache(
+        &repo,
+        &["publish-batch", "--manifest", "bitstreams.toml", "-j", "2"],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(md5s(&repo)?.len(), 3);
+
+    let output = bitcache(
+        &repo,
+        &[
+            "publish-batch",
+            "--manifest",
+            "bitstreams.toml",
+            "--jobs",
+            "many",
+        ],
+    )?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--jobs"));
+    Ok(())
+}
@@ -0,0 +1,85 @@
+//! `--quiet`, or `BITCACHE_LOG=error`, leaves errors, printed data and the
+//! line summing up what a command did, and drops everything else.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use std::fs;
+use std::io;
+use std::process::Output;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// [`common::bitcache`] with `envs` set as well
+fn bitcache(repo: &TestRepo, args: &[&str], envs: &[(&str, &str)]) -> io::Result<Output> {
+    common::command(repo, args)?
+        .envs(envs.iter().copied())
+        .output()
+}
+
+/// Publish a source and bitstream with `args` added, returning the run
+fn publish(repo: &TestRepo, args: &[&str], envs: &[(&str, &str)]) -> io::Result<Output> {
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(repo.path().join("top.bit"), b"top bitstream")?;
+    let mut all = vec![
+        "publish",
+        "--source",
+        "top.vhd",
+        "--bitstream",
+        "top.bit",
+        "--path",
+        "boards/zedboard",
+    ];
+    all.extend_from_slice(args);
+    bitcache(repo, &all, envs)
+}
+
+#[test]
+fn keeps_only_the_result_line() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let output = publish(&repo, &["--quiet"], &[])?;
+    assert!(output.status.success());
+    let md5 = bitcache::compute_md5(&repo.path().join("top.vhd"))?;
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!("Successfully published bitstream with MD5: {}\n", md5)
+    );
+    assert!(output.stderr.is_empty());
+
+    // What a command is asked to print is still printed
+    let output = bitcache(&repo, &["list", "--quiet"], &[])?;
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&md5));
+    Ok(())
+}
+
+#[test]
+fn keeps_errors() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    publish(&repo, &[], &[])?;
+    let output = bitcache(&repo, &["get", "--md5", MISSING_MD5, "-q"], &[])?;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Error: "), "{}", stderr);
+    assert!(stderr.contains(MISSING_MD5), "{}", stderr);
+    Ok(())
+}
+
+#[test]
+fn log_levels_stand_for_the_flags() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let output = publish(&repo, &[], &[("BITCACHE_LOG", "error")])?;
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout).lines().count(), 1);
+
+    // --verbose on the command line wins over the environment
+    let repo = TestRepo::new()?;
+    let output = publish(&repo, &["--verbose"], &[("BITCACHE_LOG", "error")])?;
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).lines().count() > 1);
+
+    let output = bitcache(&repo, &["list"], &[("BITCACHE_LOG", "loud")])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("loud"));
+    Ok(())
+}
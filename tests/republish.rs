@@ -0,0 +1,160 @@
+//! Publishing a bitstream the entry already stores commits nothing unless
+//! forced, publishing another one is refused unless forced, and
+//! `fail_if_exists` refuses any entry the hash already has. A dry run fails
+//! the same way.
+
+mod common;
+
+use bitcache::backend::Backend;
+use bitcache::testing::{MemoryBackend, TestRepo};
+use bitcache::{BitcacheError, PublishAction, PublishOptions};
+use std::fs;
+use std::io;
+use std::process::Command;
+
+/// Options publishing `bitstream` for the source `name`
+fn inputs(repo: &TestRepo, name: &str, bitstream: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let binary = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&binary, bitstream)?;
+    Ok(PublishOptions::new(source, binary, "boards/zedboard"))
+}
+
+/// Subjects of the commits on the repository's branch, newest first
+fn subjects(repo: &TestRepo) -> io::Result<Vec<String>> {
+    let output = Command::new("git")
+        .args(["--git-dir", repo.url(), "log", "--format=%s", "main"])
+        .output()?;
+    assert!(output.status.success());
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+#[test]
+fn publishing_the_same_bitstream_again_commits_nothing() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let opts = inputs(&repo, "top", "bitstream")?;
+    assert_eq!(client.publish(&opts)?.action, PublishAction::Create);
+    let commits = subjects(&repo)?.len();
+
+    let again = client.publish(&opts)?;
+    assert_eq!(again.action, PublishAction::Unchanged);
+    assert!(again.commit_message.is_empty());
+    assert_eq!(subjects(&repo)?.len(), commits);
+    let plan = bitcache::explain(&repo.remote(), &opts, client.context())?;
+    assert_eq!(plan.action, PublishAction::Unchanged);
+    assert!(plan.unchanged);
+
+    let forced = PublishOptions {
+        force: true,
+        ..opts.clone()
+    };
+    assert_eq!(client.publish(&forced)?.action, PublishAction::Overwrite);
+    assert_eq!(subjects(&repo)?.len(), commits + 1);
+
+    // Another bitstream replaces the entry only when forced
+    let rebuilt = inputs(&repo, "top", "rebuilt bitstream")?;
+    let error = client.publish(&rebuilt).expect_err("replaced the entry");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::AlreadyPublished { forcible, .. }) => assert!(forcible),
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(error.to_string().contains("pass --force"), "{}", error);
+    assert_eq!(subjects(&repo)?.len(), commits + 1);
+    let rebuilt = PublishOptions {
+        force: true,
+        ..rebuilt
+    };
+    assert_eq!(client.publish(&rebuilt)?.action, PublishAction::Overwrite);
+    assert_eq!(subjects(&repo)?.len(), commits + 2);
+    Ok(())
+}
+
+#[test]
+fn fail_if_exists_refuses_any_entry() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let opts = PublishOptions {
+        fail_if_exists: true,
+        ..inputs(&repo, "top", "bitstream")?
+    };
+    client.publish(&opts)?;
+    let error = client.publish(&opts).expect_err("published twice");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::AlreadyPublished { entry, forcible }) => {
+            assert_eq!(entry.binary_path, "boards/zedboard/top.bit");
+            assert!(!forcible);
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    // --force can't be combined with it, so the message doesn't offer it
+    assert!(!error.to_string().contains("--force"), "{}", error);
+
+    let backend = MemoryBackend::new();
+    backend.publish(&opts)?;
+    assert!(backend.publish(&opts).is_err());
+    Ok(())
+}
+
+#[test]
+fn a_dry_run_fails_like_the_publish_it_previews() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    inputs(&repo, "top", "bitstream")?;
+    let publish = ["publish", "--source", "top.vhd", "--bitstream", "top.bit"];
+    let publish = [&publish[..], &["--path", "boards/zedboard"]].concat();
+    assert!(common::bitcache(&repo, &publish)?.status.success());
+    let commits = subjects(&repo)?.len();
+
+    inputs(&repo, "top", "rebuilt bitstream")?;
+    for flags in [&[][..], &["--fail-if-exists"]] {
+        let real = common::bitcache(&repo, &[&publish[..], flags].concat())?;
+        let dry = common::bitcache(&repo, &[&publish[..], flags, &["--dry-run"]].concat())?;
+        assert!(!real.status.success());
+        assert_eq!(dry.status.code(), real.status.code());
+        let refused = String::from_utf8_lossy(&real.stderr);
+        let refused = refused.lines().last().expect("an error");
+        let previewed = String::from_utf8_lossy(&dry.stderr);
+        assert!(previewed.contains(refused), "{}", previewed);
+        let printed = String::from_utf8_lossy(&dry.stdout);
+        assert!(!printed.contains("would publish"), "{}", printed);
+    }
+
+    let dry = common::bitcache(&repo, &[&publish[..], &["--force", "--dry-run"]].concat())?;
+    assert!(dry.status.success());
+    let printed = String::from_utf8_lossy(&dry.stdout);
+    assert!(
+        printed.contains("Replaces the existing entry"),
+        "{}",
+        printed
+    );
+    assert_eq!(subjects(&repo)?.len(), commits);
+    Ok(())
+}
+
+#[test]
+fn a_batch_leaves_published_entries_out_of_its_commit() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let top = inputs(&repo, "top", "top bitstream")?;
+    let uart = inputs(&repo, "uart", "uart bitstream")?;
+    client.publish(&top)?;
+
+    let published = client.publish_batch(&[top.clone(), uart.clone()])?;
+    let actions: Vec<_> = published.iter().map(|published| published.action).collect();
+    assert_eq!(actions, [PublishAction::Unchanged, PublishAction::Create]);
+    let subject = subjects(&repo)?.remove(0);
+    assert_eq!(subject, "Add 1 bitstream");
+
+    // Nothing left to add, nothing committed
+    let commits = subjects(&repo)?.len();
+    let published = client.publish_batch(&[top, uart])?;
+    assert!(published
+        .iter()
+        .all(|published| published.action == PublishAction::Unchanged));
+    assert_eq!(subjects(&repo)?.len(), commits);
+    Ok(())
+}
@@ -0,0 +1,294 @@
+//! A published metadata file and the `--json` output of `get`, `list` and
+//! `verify` validate against what `bitcache schema` prints for them, with no
+//! field the schema doesn't declare.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{MetadataEntry, METADATA_FILE};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::process::{Command, Output};
+
+const MISMATCHED_MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+const DEPRECATED_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+
+/// stdout of a run of the bitcache binary, which must be one JSON document
+fn json(repo: &TestRepo, args: &[&str]) -> io::Result<Value> {
+    let output = common::bitcache(repo, &[&["--json"], args].concat())?;
+    Ok(parsed(&output))
+}
+
+fn parsed(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap_or_else(|e| {
+        panic!(
+            "stdout is not JSON ({}):\n{}{}",
+            e,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        )
+    })
+}
+
+/// Check `instance` against the subset of JSON Schema that schema.rs uses.
+/// An object property that is not declared fails unless the schema allows
+/// additional properties, so that a field added to a type but not to its
+/// schema is caught.
+fn validate(root: &Value, schema: &Value, instance: &Value, at: &str) -> Result<(), String> {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = reference
+            .strip_prefix("#/$defs/")
+            .ok_or_else(|| format!("{}: unsupported $ref {}", at, reference))?;
+        return validate(root, &root["$defs"][name], instance, at);
+    }
+    if let Some(options) = schema.get("oneOf").and_then(Value::as_array) {
+        let matching = options
+            .iter()
+            .filter(|option| validate(root, option, instance, at).is_ok())
+            .count();
+        if matching != 1 {
+            return Err(format!("{}: matches {} of oneOf", at, matching));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if instance != constant {
+            return Err(format!("{}: {} is not {}", at, instance, constant));
+        }
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(instance) {
+            return Err(format!("{}: {} is not one of {:?}", at, instance, values));
+        }
+    }
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            types => types.as_str().into_iter().collect(),
+        };
+        let is = |kind: &str| match kind {
+            "object" => instance.is_object(),
+            "array" => instance.is_array(),
+            "string" => instance.is_string(),
+            "integer" => instance.is_u64() || instance.is_i64(),
+            "number" => instance.is_number(),
+            "boolean" => instance.is_boolean(),
+            "null" => instance.is_null(),
+            _ => false,
+        };
+        if !types.iter().any(|kind| is(kind)) {
+            return Err(format!("{}: {} is not {:?}", at, instance, types));
+        }
+    }
+    if let (Some(minimum), Some(number)) = (
+        schema.get("minimum").and_then(Value::as_f64),
+        instance.as_f64(),
+    ) {
+        if number < minimum {
+            return Err(format!("{}: {} is below {}", at, number, minimum));
+        }
+    }
+    if let Some(array) = instance.as_array() {
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+            if (array.len() as u64) < min {
+                return Err(format!("{}: fewer than {} items", at, min));
+            }
+        }
+        if let Some(items) = schema.get("items") {
+            for (i, item) in array.iter().enumerate() {
+                validate(root, items, item, &format!("{}[{}]", at, i))?;
+            }
+        }
+    }
+    if let Some(object) = instance.as_object() {
+        for required in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !object.contains_key(required) {
+                return Err(format!("{}: missing {}", at, required));
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let additional = schema.get("additionalProperties");
+        // A schema that only combines others, e.g. with oneOf, describes no
+        // properties of its own
+        let describes = properties.is_some() || additional.is_some();
+        for (name, value) in object {
+            let at = format!("{}.{}", at, name);
+            match (properties.and_then(|p| p.get(name)), additional) {
+                (Some(property), _) => validate(root, property, value, &at)?,
+                (None, Some(Value::Bool(true))) => {}
+                (None, Some(additional)) if additional.is_object() => {
+                    validate(root, additional, value, &at)?
+                }
+                (None, _) if describes => return Err(format!("{}: not in the schema", at)),
+                (None, _) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What `bitcache schema <document>` prints
+fn schema(document: &str) -> io::Result<Value> {
+    let output = Command::new(env!("CARGO_BIN_EXE_bitcache"))
+        .args(["schema", document])
+        .output()?;
+    Ok(parsed(&output))
+}
+
+fn assert_valid(document: &str, instance: &Value) -> io::Result<()> {
+    let schema = schema(document)?;
+    if let Err(problem) = validate(&schema, &schema, instance, document) {
+        panic!("{}\n{:#}", problem, instance);
+    }
+    Ok(())
+}
+
+/// The metadata file as committed to the repository
+fn metadata_file(repo: &TestRepo) -> io::Result<Value> {
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.url())
+        .args(["show", &format!("main:{}", METADATA_FILE)])
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(parsed(&output))
+}
+
+/// A repository with a published entry using every optional field the
+/// command line can set, one whose bitstream no longer matches its
+/// `binary_md5` and one deprecated in favour of a hash without an entry
+fn populated() -> io::Result<(TestRepo, String)> {
+    let repo = TestRepo::new()?;
+    let mut mismatched = MetadataEntry::new(
+        MISMATCHED_MD5,
+        "bits/old.bit",
+        "old.vhd",
+        "2024-01-01T00:00:00Z",
+    );
+    mismatched.binary_md5 = Some("ffffffffffffffffffffffffffffffff".to_string());
+    repo.seed(mismatched, b"changed since")?;
+    let mut deprecated = MetadataEntry::new(
+        DEPRECATED_MD5,
+        "bits/bad.bit",
+        "bad.vhd",
+        "2024-01-01T00:00:00Z",
+    );
+    deprecated.deprecated = true;
+    deprecated.deprecation_reason = Some("timing violation".to_string());
+    deprecated.replacement = Some("ffffffffffffffffffffffffffffffff".to_string());
+    repo.seed(deprecated, b"bad bitstream")?;
+
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    fs::write(repo.path().join("top.bit"), b"top bitstream")?;
+    let output = common::bitcache(
+        &repo,
+        &[
+            "publish",
+            "--source",
+            "top.vhd",
+            "--bitstream",
+            "top.bit",
+            "--path",
+            "boards/zedboard",
+            "--variant",
+            "debug",
+            "--tag",
+            "board=zedboard",
+            "--compress",
+            "gzip",
+            "--build-tool-version",
+            "Vivado 2023.2",
+            "--author",
+            "Jane Doe <jane@example.com>",
+            "--source-commit",
+            "44587f5",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let md5 = bitcache::compute_md5(&repo.path().join("top.vhd"))?;
+    Ok((repo, md5))
+}
+
+#[test]
+fn the_metadata_file_matches_its_schema() -> io::Result<()> {
+    let (repo, _) = populated()?;
+    let metadata = metadata_file(&repo)?;
+    let entry = &metadata["entries"]
+        .as_object()
+        .expect("entries")
+        .values()
+        .flat_map(|entries| entries.as_array().expect("entries of a hash"))
+        .find(|entry| entry["variant"] == "debug")
+        .expect("published entry")
+        .clone();
+    // Every field publish records is there to be checked
+    for field in [
+        "size",
+        "binary_md5",
+        "compression",
+        "original_size",
+        "tags",
+        "os",
+        "arch",
+        "build_tool_version",
+        "published_by",
+        "tool_version",
+        "source_commit",
+    ] {
+        assert!(entry.get(field).is_some(), "{} not recorded", field);
+    }
+    assert_valid("metadata", &metadata)
+}
+
+#[test]
+fn get_list_and_verify_output_match_their_schemas() -> io::Result<()> {
+    let (repo, md5) = populated()?;
+    let output = repo.path().join("out.bit");
+    let retrieved = json(
+        &repo,
+        &[
+            "get",
+            "--md5",
+            &md5,
+            "--variant",
+            "debug",
+            "--output",
+            &output.to_string_lossy(),
+        ],
+    )?;
+    assert_eq!(retrieved["from_cache"], false);
+    assert_valid("get", &retrieved)?;
+
+    let listed = json(&repo, &["list"])?;
+    assert_eq!(listed.as_array().map(Vec::len), Some(3));
+    assert_valid("list", &listed)?;
+
+    let report = json(&repo, &["verify"])?;
+    assert_eq!(report["failures"][0]["problem"]["kind"], "mismatch");
+    assert_eq!(report["dangling"][0]["md5"], DEPRECATED_MD5);
+    assert_valid("verify", &report)
+}
+
+#[test]
+fn an_undeclared_field_fails_validation() -> io::Result<()> {
+    let (repo, _) = populated()?;
+    let mut listed = json(&repo, &["list"])?;
+    listed[0]["publisher"] = Value::from("Jane Doe");
+    let schema = schema("list")?;
+    assert!(validate(&schema, &schema, &listed, "list").is_err());
+    Ok(())
+}
@@ -0,0 +1,175 @@
+//! `search` finds entries by a glob over their source file name, by tag and
+//! by filter expression, and exits 2 when none match; the library's
+//! [`bitcache::search`] picks them alike.
+
+use bitcache::testing::TestRepo;
+use bitcache::{MetadataEntry, SearchOptions};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::process::{Command, Output};
+
+/// A repository with builds of `top_arty.vhd`, `top_zed.vhd` and
+/// `uart.vhd`, the last two tagged for the zedboard
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    for (i, (source, board)) in [
+        ("top_arty.vhd", None),
+        ("top_zed.vhd", Some("zedboard")),
+        ("uart.vhd", Some("zedboard")),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let mut entry = MetadataEntry::new(
+            format!("{:032x}", i + 1),
+            format!("boards/{}.bit", source.trim_end_matches(".vhd")),
+            source,
+            "2024-05-03T10:02:51Z",
+        );
+        if let Some(board) = board {
+            entry.tags.insert("board".to_string(), board.to_string());
+        }
+        repo.seed(entry, source.as_bytes())?;
+    }
+    Ok(repo)
+}
+
+/// Run `search` against `repo`, away from any config file of the user
+/// running the tests
+fn search(repo: &TestRepo, args: &[&str]) -> io::Result<Output> {
+    let config = repo.path().join("config");
+    fs::create_dir_all(&config)?;
+    Command::new(env!("CARGO_BIN_EXE_bitcache"))
+        .args(args)
+        .args(["--repo", repo.url()])
+        .arg("--cache-dir")
+        .arg(repo.path().join("cache"))
+        .arg("--work-dir")
+        .arg(repo.path())
+        .env("XDG_CONFIG_HOME", &config)
+        .output()
+}
+
+/// The source files of the matches `search --json` printed
+fn sources(output: &Output) -> Vec<String> {
+    let entries: Vec<Value> = serde_json::from_slice(&output.stdout).expect("JSON on stdout");
+    entries
+        .iter()
+        .map(|entry| {
+            entry["source_file"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn finds_by_glob_and_tag_together() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = search(&repo, &["--json", "search", "--source-pattern", "top_*"])?;
+    assert!(output.status.success());
+    let mut found = sources(&output);
+    found.sort();
+    assert_eq!(found, ["top_arty.vhd", "top_zed.vhd"]);
+
+    let output = search(
+        &repo,
+        &[
+            "--json",
+            "search",
+            "--source-pattern",
+            "top_*",
+            "--tag",
+            "board=zedboard",
+        ],
+    )?;
+    assert_eq!(sources(&output), ["top_zed.vhd"]);
+
+    // A filter expression narrows them the same way
+    let output = search(
+        &repo,
+        &[
+            "--json",
+            "search",
+            "--source-pattern",
+            "top_*",
+            "--filter",
+            "not tags.board = zedboard",
+        ],
+    )?;
+    assert_eq!(sources(&output), ["top_arty.vhd"]);
+    Ok(())
+}
+
+#[test]
+fn prints_a_block_per_match() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = search(&repo, &["search", "--source-pattern", "uart.vhd"])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with(&format!("{:032x}\n", 3)), "{}", stdout);
+    assert!(stdout.contains("  Source file: uart.vhd"), "{}", stdout);
+    assert!(stdout.contains("  Path: boards/uart.bit"), "{}", stdout);
+    assert!(stdout.contains("  Tags: board=zedboard"), "{}", stdout);
+    Ok(())
+}
+
+#[test]
+fn exits_2_when_nothing_matches() -> io::Result<()> {
+    let repo = seeded()?;
+    let output = search(&repo, &["search", "--tag", "board=arty"])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+
+    let output = search(&repo, &["--json", "search", "--source-pattern", "*.v"])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(sources(&output).is_empty());
+
+    // A query is required
+    let output = search(&repo, &["search"])?;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--source-pattern"));
+    Ok(())
+}
+
+#[test]
+fn the_library_picks_the_same_entries() -> io::Result<()> {
+    let repo = seeded()?;
+    let client = repo.client()?;
+    let found = |opts: &SearchOptions| -> io::Result<Vec<String>> {
+        Ok(client
+            .search(opts)?
+            .into_iter()
+            .map(|entry| entry.source_file)
+            .collect())
+    };
+    let top = SearchOptions {
+        source_pattern: Some("top_*".to_string()),
+        ..SearchOptions::default()
+    };
+    assert_eq!(found(&top)?, ["top_arty.vhd", "top_zed.vhd"]);
+
+    let zedboard = SearchOptions {
+        tags: [("board".to_string(), "zedboard".to_string())].into(),
+        ..top.clone()
+    };
+    assert_eq!(found(&zedboard)?, ["top_zed.vhd"]);
+    let not_zedboard = SearchOptions {
+        filter: Some("not tags.board = zedboard".parse().expect("filter")),
+        ..top
+    };
+    assert_eq!(found(&not_zedboard)?, ["top_arty.vhd"]);
+
+    // Every entry by default, and only those published after `since`
+    assert_eq!(found(&SearchOptions::default())?.len(), 3);
+    for (since, count) in [("2024-05-03T10:00:00Z", 3), ("2024-05-03T10:02:51Z", 0)] {
+        let opts = SearchOptions {
+            since: Some(chrono::DateTime::parse_from_rfc3339(since).expect("time")),
+            ..SearchOptions::default()
+        };
+        assert_eq!(found(&opts)?.len(), count, "since {}", since);
+    }
+    Ok(())
+}
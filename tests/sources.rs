@@ -0,0 +1,190 @@
+//! A source can be several files or directories, hashed together under
+//! their sorted relative paths so every checkout gives the same hash, which
+//! `get --source` finds an entry by when no hash is given.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, GetOptions, HashAlgo, PublishOptions, SourceWalk};
+use common::bitcache;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Write a small project under `dir`: two source files, one in a
+/// subdirectory, and a `.git` directory
+fn project(dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir.join("cores"))?;
+    fs::create_dir_all(dir.join(".git"))?;
+    fs::write(dir.join("top.vhd"), "entity top is end;\n")?;
+    fs::write(dir.join("cores/uart.vhd"), "entity uart is end;\n")?;
+    fs::write(dir.join(".git/HEAD"), "ref: refs/heads/main\n")?;
+    Ok(dir.to_path_buf())
+}
+
+fn hash(paths: &[&Path], walk: SourceWalk) -> io::Result<String> {
+    let paths: Vec<PathBuf> = paths.iter().map(|path| path.to_path_buf()).collect();
+    bitcache::compute_source_hash(&paths, walk, HashAlgo::Md5)
+}
+
+#[test]
+fn a_directory_hashes_the_same_wherever_it_is() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let here = project(&tmp.path().join("here"))?;
+    let there = project(&tmp.path().join("elsewhere/rtl"))?;
+    let walk = SourceWalk::default();
+    assert_eq!(hash(&[&here], walk)?, hash(&[&there], walk)?);
+
+    // Any change to a file, or to which files there are, changes it
+    let before = hash(&[&here], walk)?;
+    fs::write(here.join("cores/uart.vhd"), "entity uart2 is end;\n")?;
+    assert_ne!(hash(&[&here], walk)?, before);
+    let edited = hash(&[&here], walk)?;
+    fs::rename(here.join("cores/uart.vhd"), here.join("cores/uart2.vhd"))?;
+    assert_ne!(hash(&[&here], walk)?, edited);
+    Ok(())
+}
+
+#[test]
+fn skips_hidden_files_unless_asked() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let dir = project(&tmp.path().join("rtl"))?;
+    let walk = SourceWalk::default();
+    let hidden = SourceWalk {
+        include_hidden: true,
+        ..walk
+    };
+    let before = hash(&[&dir], walk)?;
+    let with_hidden = hash(&[&dir], hidden)?;
+    assert_ne!(before, with_hidden);
+    fs::write(dir.join(".git/HEAD"), "ref: refs/heads/other\n")?;
+    assert_eq!(hash(&[&dir], walk)?, before);
+    assert_ne!(hash(&[&dir], hidden)?, with_hidden);
+    Ok(())
+}
+
+#[test]
+fn one_plain_file_keeps_its_own_hash() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let dir = project(&tmp.path().join("rtl"))?;
+    let top = dir.join("top.vhd");
+    assert_eq!(
+        hash(&[&top], SourceWalk::default())?,
+        bitcache::compute_md5(&top)?
+    );
+    Ok(())
+}
+
+#[test]
+fn refuses_two_files_of_one_name() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let a = project(&tmp.path().join("a"))?;
+    let b = project(&tmp.path().join("b"))?;
+    let error = hash(
+        &[&a.join("top.vhd"), &b.join("top.vhd")],
+        SourceWalk::default(),
+    )
+    .expect_err("hashed two files named top.vhd");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("top.vhd"), "{}", error);
+    Ok(())
+}
+
+#[test]
+fn publishes_and_checks_a_source_of_several() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let rtl = project(&repo.path().join("rtl"))?;
+    let constraints = repo.path().join("top.xdc");
+    fs::write(
+        &constraints,
+        "set_property PACKAGE_PIN Y9 [get_ports clk]\n",
+    )?;
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&bitstream, b"top bitstream")?;
+    let opts = PublishOptions {
+        extra_sources: vec![constraints.clone()],
+        ..PublishOptions::new(&rtl, &bitstream, "boards/zedboard")
+    };
+    let client = repo.client()?;
+    let published = client.publish(&opts)?;
+    assert_eq!(
+        published.md5,
+        hash(&[&rtl, &constraints], SourceWalk::default())?
+    );
+
+    let entry = client.list()?.pop().expect("published entry");
+    assert_eq!(entry.source_file, "rtl, top.xdc");
+    assert_eq!(entry.source_files, ["cores/uart.vhd", "top.vhd", "top.xdc"]);
+
+    let get = |extra_sources: Vec<PathBuf>| GetOptions {
+        source: Some(rtl.clone()),
+        extra_sources,
+        output: Some(repo.path().join("out.bit")),
+        force: true,
+        ..GetOptions::new(&published.md5)
+    };
+    assert!(client.get(&get(vec![constraints.clone()]))?.is_some());
+    // Leaving out a file gives another hash, which get refuses
+    let error = client
+        .get(&get(Vec::new()))
+        .expect_err("checked a partial source");
+    assert!(matches!(
+        BitcacheError::of(&error),
+        Some(BitcacheError::SourceMismatch { .. })
+    ));
+    Ok(())
+}
+
+#[test]
+fn the_command_line_gets_by_the_hash_of_the_source() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let rtl = project(&repo.path().join("rtl"))?;
+    fs::write(
+        repo.path().join("top.xdc"),
+        "set_property IOSTANDARD LVCMOS33\n",
+    )?;
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&bitstream, b"top bitstream")?;
+    let opts = PublishOptions {
+        extra_sources: vec![repo.path().join("top.xdc")],
+        ..PublishOptions::new(&rtl, &bitstream, "boards/zedboard")
+    };
+    let published = repo.client()?.publish(&opts)?;
+
+    let args = ["get", "--source", "rtl", "--source", "top.xdc"];
+    let output = bitcache(&repo, &[&args[..], &["--output", "found.bit"]].concat())?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(fs::read(repo.path().join("found.bit"))?, b"top bitstream");
+
+    // A source that is not the entry's names both hashes
+    let partial = hash(&[&rtl], SourceWalk::default())?;
+    let output = bitcache(
+        &repo,
+        &[
+            "get",
+            "--md5",
+            &published.md5,
+            "--source",
+            "rtl",
+            "--output",
+            "partial.bit",
+        ],
+    )?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains(&published.md5) && stderr.contains(&partial),
+        "{}",
+        stderr
+    );
+    assert!(!repo.path().join("partial.bit").exists());
+
+    // Neither a hash nor a source is an error
+    let output = bitcache(&repo, &["get", "--output", "none.bit"])?;
+    assert!(!output.status.success());
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! `get` clones sparsely and checks out only the directory of the bitstream
+//! it retrieves, in a one-off clone or a cached one; a command that needs
+//! the whole repository widens a sparse cached clone first.
+
+use bitcache::testing::TestRepo;
+use bitcache::{Context, GetOptions, MetadataEntry, Remote, VerifyOptions, METADATA_FILE};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const TOP_MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+const OTHER_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const ROOT_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    let entry = |md5, binary_path, source| {
+        MetadataEntry::new(md5, binary_path, source, "2024-01-01T00:00:00Z")
+    };
+    repo.seed(
+        entry(TOP_MD5, "bits/top/top.bit", "top.vhd"),
+        b"top bitstream",
+    )?;
+    repo.seed(
+        entry(OTHER_MD5, "bits/other/other.bit", "other.vhd"),
+        b"other bitstream",
+    )?;
+    repo.seed(entry(ROOT_MD5, "root.bit", "root.vhd"), b"root bitstream")?;
+    Ok(repo)
+}
+
+/// A context that keeps its clones in `work_dir`, and has no local cache
+fn keeping(work_dir: &Path) -> Context {
+    Context {
+        work_dir: Some(work_dir.to_path_buf()),
+        keep_temp: true,
+        cache_dir: None,
+        ..Context::default()
+    }
+}
+
+/// The only clone left in `work_dir`
+fn kept_clone(work_dir: &Path) -> io::Result<PathBuf> {
+    let mut clones = fs::read_dir(work_dir)?
+        .map(|dir| dir.map(|dir| dir.path().join("repo")))
+        .collect::<io::Result<Vec<_>>>()?;
+    assert_eq!(
+        clones.len(),
+        1,
+        "expected a single clone in {}",
+        work_dir.display()
+    );
+    Ok(clones.remove(0))
+}
+
+/// Get `md5` through a `file://` URL, which git clones like a remote rather
+/// than copying the repository, and return the bitstream and the clone it
+/// came from
+fn get(repo: &TestRepo, md5: &str) -> io::Result<(Vec<u8>, PathBuf)> {
+    let work_dir = repo.path().join(format!("work-{}", md5));
+    let output = repo.path().join(format!("{}.bit", md5));
+    let remote = Remote::new(format!("file://{}", repo.url()));
+    let mut opts = GetOptions::new(md5);
+    opts.output = Some(output.clone());
+    let retrieved = bitcache::get(&remote, &opts, &keeping(&work_dir))?;
+    assert!(retrieved.is_some(), "no entry for {}", md5);
+    Ok((fs::read(output)?, kept_clone(&work_dir)?))
+}
+
+#[test]
+fn checks_out_only_the_bitstreams_directory() -> io::Result<()> {
+    let repo = seeded()?;
+    let (contents, clone) = get(&repo, TOP_MD5)?;
+    assert_eq!(contents, b"top bitstream");
+    assert!(clone.join(METADATA_FILE).is_file());
+    assert!(clone.join("bits/top/top.bit").is_file());
+    assert!(!clone.join("bits/other").exists());
+    Ok(())
+}
+
+#[test]
+fn top_level_bitstream_needs_no_directory() -> io::Result<()> {
+    let repo = seeded()?;
+    let (contents, clone) = get(&repo, ROOT_MD5)?;
+    assert_eq!(contents, b"root bitstream");
+    assert!(!clone.join("bits").exists());
+    Ok(())
+}
+
+#[test]
+fn pooled_clone_serves_every_directory() -> io::Result<()> {
+    let repo = seeded()?;
+    let client = repo.client()?;
+    let output = repo.path().join("other.bit");
+    let mut opts = GetOptions::new(OTHER_MD5);
+    opts.output = Some(output.clone());
+    assert!(client.get(&opts)?.is_some());
+    assert_eq!(fs::read(&output)?, b"other bitstream");
+
+    // The same clone serves an entry in another directory
+    let mut opts = GetOptions::new(TOP_MD5);
+    opts.output = Some(repo.path().join("top.bit"));
+    assert!(client.get(&opts)?.is_some());
+    Ok(())
+}
+
+#[test]
+fn a_cached_clone_is_widened_when_needed() -> io::Result<()> {
+    let repo = seeded()?;
+    let remote = Remote::new(format!("file://{}", repo.url()));
+    let ctx = Context {
+        work_dir: Some(repo.path().join("work")),
+        cache_dir: Some(repo.path().join("cache")),
+        clone_cache: true,
+        ..Context::default()
+    };
+    let mut opts = GetOptions::new(TOP_MD5);
+    opts.output = Some(repo.path().join("top.bit"));
+    opts.use_local_cache = false;
+    assert!(bitcache::get(&remote, &opts, &ctx)?.is_some());
+    let clone = ctx.clone_dir(&remote)?;
+    assert!(clone.join("bits/top/top.bit").is_file());
+    assert!(!clone.join("bits/other").exists());
+
+    // list needs no bitstream, and leaves the clone as it is
+    assert_eq!(bitcache::list(&remote, &ctx)?.len(), 3);
+    assert!(!clone.join("bits/other").exists());
+
+    // verify reads every bitstream
+    let report = bitcache::verify(&remote, &VerifyOptions::new(), &ctx)?;
+    assert!(report.failures.is_empty(), "{:?}", report.failures);
+    assert!(clone.join("bits/other/other.bit").is_file());
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! Publish stages only the bitstream and the metadata it writes, so a file
+//! that turns up in the clone while it publishes is never pushed.
+
+use bitcache::progress::{Event, ProgressObserver};
+use bitcache::testing::TestRepo;
+use bitcache::{Context, PublishOptions, Remote};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Arc;
+
+/// Writes junk into the clone once publish has placed the bitstream
+struct Planter {
+    clone: PathBuf,
+}
+
+impl ProgressObserver for Planter {
+    fn event(&self, event: &Event) {
+        if let Event::Status("Updating metadata...") = event {
+            fs::write(self.clone.join("junk.txt"), "not a bitstream").unwrap();
+            fs::create_dir_all(self.clone.join("bits/.scratch")).unwrap();
+            fs::write(self.clone.join("bits/.scratch/partial"), "half").unwrap();
+        }
+    }
+}
+
+#[test]
+fn a_file_planted_in_the_clone_is_not_pushed() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let remote = Remote::new(format!("file://{}", repo.url()));
+    let mut ctx = Context {
+        cache_dir: Some(repo.path().join("cache")),
+        ..repo.context()?
+    };
+    ctx.observer = Some(Arc::new(Planter {
+        clone: ctx.clone_dir(&remote)?,
+    }));
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("top.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, b"top bitstream")?;
+    bitcache::publish(
+        &remote,
+        &PublishOptions::new(&source, &bitstream, "bits"),
+        &ctx,
+    )?;
+
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.url())
+        .args(["ls-tree", "-r", "--name-only", "main"])
+        .output()?;
+    let mut pushed: Vec<_> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    pushed.sort();
+    assert_eq!(
+        pushed,
+        [".gitattributes", "bitcache_metadata.json", "bits/top.bit"]
+    );
+    // The junk was there to be picked up
+    assert!(ctx.clone_dir(&remote)?.join("junk.txt").is_file());
+    Ok(())
+}
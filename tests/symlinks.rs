@@ -0,0 +1,117 @@
+//! Publish follows a symlinked `--source` or `--bitstream` to its target,
+//! keeping the link's name, and with `follow_symlinks` off refuses it. A
+//! dangling link, or a link to something other than a file, fails with an
+//! error that says so.
+#![cfg(unix)]
+
+use bitcache::testing::TestRepo;
+use bitcache::PublishOptions;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A source and a bitstream under `real/`, linked to from the top directory
+/// under other names
+fn linked() -> io::Result<(TestRepo, PathBuf, PathBuf)> {
+    let repo = TestRepo::new()?;
+    let real = repo.path().join("real");
+    fs::create_dir(&real)?;
+    fs::write(real.join("design.vhd"), "entity design is end;\n")?;
+    fs::write(real.join("design.bit"), b"design bitstream")?;
+    let (source, bitstream) = (repo.path().join("top.vhd"), repo.path().join("top.bit"));
+    symlink(real.join("design.vhd"), &source)?;
+    symlink(real.join("design.bit"), &bitstream)?;
+    Ok((repo, source, bitstream))
+}
+
+fn publish(repo: &TestRepo, opts: &PublishOptions) -> io::Result<bitcache::Published> {
+    bitcache::publish(&repo.remote(), opts, &repo.context()?)
+}
+
+/// The mode and contents git has for `path` on main, if it has it
+fn committed(repo: &TestRepo, path: &str) -> io::Result<Option<(String, Vec<u8>)>> {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("--git-dir")
+            .arg(repo.url())
+            .args(args)
+            .output()
+    };
+    let listed = git(&["ls-tree", "main", "--", path])?;
+    let listed = String::from_utf8_lossy(&listed.stdout);
+    let Some(mode) = listed.split_whitespace().next() else {
+        return Ok(None);
+    };
+    let contents = git(&["show", &format!("main:{}", path)])?;
+    assert!(contents.status.success());
+    Ok(Some((mode.to_string(), contents.stdout)))
+}
+
+#[test]
+fn links_are_followed_under_their_own_names() -> io::Result<()> {
+    let (repo, source, bitstream) = linked()?;
+    let published = publish(&repo, &PublishOptions::new(&source, &bitstream, "bits"))?;
+
+    assert_eq!(published.binary_path, "bits/top.bit");
+    assert_eq!(
+        published.md5,
+        bitcache::compute_md5(&repo.path().join("real/design.vhd"))?
+    );
+    // The target's contents, as a regular file rather than a link
+    assert_eq!(
+        committed(&repo, "bits/top.bit")?,
+        Some(("100644".to_string(), b"design bitstream".to_vec()))
+    );
+    let entries = repo.client()?.list()?;
+    assert_eq!(entries[0].source_file, "top.vhd");
+    Ok(())
+}
+
+#[test]
+fn links_are_refused_without_following() -> io::Result<()> {
+    let (repo, source, bitstream) = linked()?;
+    let plain = repo.path().join("real/design.bit");
+    for (source, bitstream, flag) in [
+        (&source, &plain, "--source"),
+        (
+            &repo.path().join("real/design.vhd"),
+            &bitstream,
+            "--bitstream",
+        ),
+    ] {
+        let mut opts = PublishOptions::new(source, bitstream, "bits");
+        opts.follow_symlinks = false;
+        let error = publish(&repo, &opts).expect_err("published through a link");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", error);
+        let message = error.to_string();
+        assert!(message.starts_with(flag), "{}", message);
+        assert!(message.contains("--no-follow-symlinks"), "{}", message);
+    }
+    assert_eq!(committed(&repo, "bits/design.bit")?, None);
+    Ok(())
+}
+
+#[test]
+fn a_dangling_link_or_a_link_to_a_directory_fails() -> io::Result<()> {
+    let (repo, source, _) = linked()?;
+    let dangling = repo.path().join("gone.bit");
+    symlink(repo.path().join("real/missing.bit"), &dangling)?;
+    let error = publish(&repo, &PublishOptions::new(&source, &dangling, "bits"))
+        .expect_err("published a dangling link");
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    assert!(error.to_string().contains("dangling symlink"), "{}", error);
+
+    let directory = repo.path().join("dir.bit");
+    symlink(repo.path().join("real"), &directory)?;
+    let error = publish(&repo, &PublishOptions::new(&source, &directory, "bits"))
+        .expect_err("published a directory");
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(
+        error.to_string().contains("not a regular file"),
+        "{}",
+        error
+    );
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! `sync` copies the entries one repository has and another lacks, with
+//! their bitstreams, in one commit, and leaves conflicting entries alone
+//! unless told to overwrite them.
+
+use bitcache::testing::{self, TestRepo};
+use bitcache::{Context, GetOptions, MetadataEntry, SyncOptions};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+const FIRST_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const SECOND_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// A source repository with two entries, and an empty destination
+fn repos() -> io::Result<(TestRepo, TestRepo)> {
+    let from = TestRepo::new()?;
+    for (md5, path) in [
+        (FIRST_MD5, "boards/first.bit"),
+        (SECOND_MD5, "boards/second.bit"),
+    ] {
+        from.seed(
+            MetadataEntry::new(md5, path, "top.vhd", "2024-01-01T00:00:00Z"),
+            path.as_bytes(),
+        )?;
+    }
+    Ok((from, TestRepo::new()?))
+}
+
+/// A context whose clones stay inside the test's directory
+fn context(repo: &TestRepo) -> Context {
+    Context {
+        work_dir: Some(repo.path().join("work")),
+        cache_dir: None,
+        committer: Some(testing::committer()),
+        ..Context::default()
+    }
+}
+
+/// Number of commits on the head of `repo`
+fn commits(repo: &TestRepo) -> io::Result<usize> {
+    let output = Command::new("git")
+        .args(["--git-dir", repo.url(), "rev-list", "--count", "main"])
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .unwrap_or(0))
+}
+
+/// What `get` saves from `repo` for `md5`
+fn get(repo: &TestRepo, md5: &str) -> io::Result<String> {
+    let output = repo.path().join(format!("{}.bit", md5));
+    let opts = GetOptions {
+        output: Some(output.clone()),
+        ..GetOptions::new(md5)
+    };
+    repo.client()?.get(&opts)?.expect("synced entry");
+    fs::read_to_string(output)
+}
+
+#[test]
+fn copies_what_the_destination_lacks_in_one_commit() -> io::Result<()> {
+    let (from, to) = repos()?;
+    to.seed(
+        MetadataEntry::new(
+            FIRST_MD5,
+            "boards/first.bit",
+            "top.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"boards/first.bit",
+    )?;
+    let before = commits(&to)?;
+
+    let ctx = context(&to);
+    let synced = bitcache::sync(&from.remote(), &to.remote(), &SyncOptions::new(), &ctx)?;
+    assert!(synced.committed);
+    assert_eq!(synced.unchanged, 1);
+    let added: Vec<_> = synced.added.iter().map(|entry| &entry.md5).collect();
+    assert_eq!(added, [SECOND_MD5]);
+    assert_eq!(commits(&to)?, before + 1);
+    assert_eq!(get(&to, SECOND_MD5)?, "boards/second.bit");
+
+    // Nothing is left to sync, so nothing is committed
+    let again = bitcache::sync(&from.remote(), &to.remote(), &SyncOptions::new(), &ctx)?;
+    assert!(!again.committed);
+    assert_eq!(again.unchanged, 2);
+    assert_eq!(commits(&to)?, before + 1);
+    Ok(())
+}
+
+#[test]
+fn skips_conflicts_unless_overwriting() -> io::Result<()> {
+    let (from, to) = repos()?;
+    to.seed(
+        MetadataEntry::new(
+            FIRST_MD5,
+            "old/first.bit",
+            "top.vhd",
+            "2023-01-01T00:00:00Z",
+        ),
+        b"old bitstream",
+    )?;
+    let ctx = context(&to);
+    let opts = SyncOptions {
+        md5s: vec![FIRST_MD5.to_string()],
+        ..SyncOptions::new()
+    };
+    let synced = bitcache::sync(&from.remote(), &to.remote(), &opts, &ctx)?;
+    assert!(synced.added.is_empty());
+    assert_eq!(synced.skipped.len(), 1);
+    assert!(
+        synced.skipped[0].reason.contains("old/first.bit"),
+        "{}",
+        synced.skipped[0].reason
+    );
+    assert!(!synced.committed);
+
+    let overwrite = SyncOptions {
+        overwrite: true,
+        ..opts
+    };
+    let synced = bitcache::sync(&from.remote(), &to.remote(), &overwrite, &ctx)?;
+    assert_eq!(synced.replaced.len(), 1);
+    assert_eq!(get(&to, FIRST_MD5)?, "boards/first.bit");
+    // --md5 left the other entry out
+    assert_eq!(to.client()?.list()?.len(), 1);
+    Ok(())
+}
+
+#[test]
+fn a_dry_run_changes_nothing() -> io::Result<()> {
+    let (from, to) = repos()?;
+    let config = to.path().join("config");
+    fs::create_dir_all(&config)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_bitcache"))
+        .args(["--json", "sync", "--from", from.url(), "--to", to.url()])
+        .args(["--md5", SECOND_MD5, "--md5", MISSING_MD5, "--dry-run"])
+        .arg("--work-dir")
+        .arg(to.path())
+        .env("XDG_CONFIG_HOME", &config)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let synced: Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(synced["added"][0]["md5"], SECOND_MD5);
+    assert_eq!(synced["not_found"][0], MISSING_MD5);
+    assert_eq!(synced["committed"], false);
+    assert_eq!(commits(&to)?, 0);
+    Ok(())
+}
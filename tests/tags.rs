@@ -0,0 +1,208 @@
+//! `publish --tag` and `register --tag` record key-value tags in an entry, and
+//! `list --filter-tag` and `get --filter-tag` select entries by them.
+
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, GetOptions, PublishOptions, RegisterOptions};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+/// Options publishing the source `name`.vhd with `tags`
+fn inputs(repo: &TestRepo, name: &str, tags: &[(&str, &str)]) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let bitstream = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&bitstream, format!("{} bitstream", name))?;
+    Ok(PublishOptions {
+        tags: tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        ..PublishOptions::new(source, bitstream, format!("boards/{}", name))
+    })
+}
+
+/// The source files `list --json` prints with `args`
+fn listed(repo: &TestRepo, args: &[&str]) -> io::Result<Vec<String>> {
+    let config = repo.path().join("config");
+    fs::create_dir_all(&config)?;
+    let output = Command::new(env!("CARGO_BIN_EXE_bitcache"))
+        .args(["--json", "list", "--repo", repo.url()])
+        .args(args)
+        .arg("--cache-dir")
+        .arg(repo.path().join("cache"))
+        .arg("--work-dir")
+        .arg(repo.path())
+        .env("XDG_CONFIG_HOME", &config)
+        .output()?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let entries: Vec<Value> = serde_json::from_slice(&output.stdout)?;
+    let mut sources: Vec<String> = entries
+        .iter()
+        .map(|entry| {
+            entry["source_file"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect();
+    sources.sort();
+    Ok(sources)
+}
+
+#[test]
+fn records_tags_and_filters_by_them() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    client.publish(&inputs(
+        &repo,
+        "arty",
+        &[("target_board", "arty-a7"), ("build_host", "ci-runner-03")],
+    )?)?;
+    client.publish(&inputs(
+        &repo,
+        "vc709",
+        &[("target_board", "xilinx-vc709")],
+    )?)?;
+    client.publish(&inputs(&repo, "plain", &[])?)?;
+
+    let arty = client
+        .list()?
+        .into_iter()
+        .find(|entry| entry.source_file == "arty.vhd")
+        .expect("published entry");
+    let expected: BTreeMap<_, _> = [("build_host", "ci-runner-03"), ("target_board", "arty-a7")]
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .into();
+    assert_eq!(arty.tags, expected);
+
+    assert_eq!(
+        listed(&repo, &["--filter-tag", "target_board=arty-a7"])?,
+        ["arty.vhd"]
+    );
+    // Every tag given must match
+    assert!(listed(
+        &repo,
+        &[
+            "--filter-tag",
+            "target_board=xilinx-vc709",
+            "--filter-tag",
+            "build_host=ci-runner-03"
+        ]
+    )?
+    .is_empty());
+    assert_eq!(
+        listed(&repo, &["--where", "target_board=xilinx-vc709"])?,
+        ["vc709.vhd"]
+    );
+    assert_eq!(listed(&repo, &[])?.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn refuses_a_key_that_is_not_one() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let opts = inputs(&repo, "arty", &[("", "arty-a7")])?;
+    let error = repo.client()?.publish(&opts).expect_err("published");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::InvalidArgument { flag, .. }) => assert_eq!(*flag, "--tag"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn register_records_tags_too() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let tags: BTreeMap<_, _> = [("target_board", "arty-a7")]
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .into();
+    let registered = client.register(&RegisterOptions {
+        tags: tags.clone(),
+        ..RegisterOptions::new(
+            "ffffffffffffffffffffffffffffffff",
+            "arty.vhd",
+            "boards/arty/arty.bit",
+        )
+    })?;
+    assert_eq!(registered.entry.tags, tags);
+    assert_eq!(client.list()?[0].tags, tags);
+    assert_eq!(
+        listed(&repo, &["--filter-tag", "target_board=arty-a7"])?,
+        ["arty.vhd"]
+    );
+
+    let error = client
+        .register(&RegisterOptions {
+            tags: [("a=b".to_string(), "c".to_string())].into(),
+            ..RegisterOptions::new(
+                "eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee",
+                "arty.vhd",
+                "boards/arty/arty.bit",
+            )
+        })
+        .expect_err("registered a key holding '='");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::InvalidArgument { flag, .. }) => assert_eq!(*flag, "--tag"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn get_picks_a_bitstream_by_its_tags() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let source = repo.path().join("arty.vhd");
+    fs::write(&source, "entity arty is end;\n")?;
+    let mut md5 = String::new();
+    for (file, kind) in [("arty.bit", "bitstream"), ("arty.mcs", "flash")] {
+        let bitstream = repo.path().join(file);
+        fs::write(&bitstream, format!("{} image", kind))?;
+        let opts = PublishOptions {
+            tags: [("board", "arty-a7"), ("kind", kind)]
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .into(),
+            ..PublishOptions::new(&source, bitstream, "boards/arty")
+        };
+        md5 = client.publish(&opts)?.md5;
+    }
+
+    let get = |tags: &[(&str, &str)], name: Option<&str>| {
+        let opts = GetOptions {
+            variant: name.map(String::from),
+            tags: tags
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            output: Some(repo.path().join("out").join("")),
+            force: true,
+            ..GetOptions::new(&md5)
+        };
+        client.get(&opts)
+    };
+    let flash = get(&[("kind", "flash")], None)?.expect("tagged entry");
+    assert_eq!(flash.entry.name(), "arty.mcs");
+    assert_eq!(fs::read_to_string(flash.path)?, "flash image");
+
+    // Both are tagged so; the name picks one
+    let error = get(&[("board", "arty-a7")], None).expect_err("picked one of two");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::AmbiguousVariant { variants, .. }) => {
+            assert_eq!(variants, &["arty.bit", "arty.mcs"])
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    let bit = get(&[("board", "arty-a7")], Some("arty.bit"))?.expect("tagged entry");
+    assert_eq!(fs::read_to_string(bit.path)?, "bitstream image");
+
+    assert!(get(&[("kind", "jtag")], None)?.is_none());
+    Ok(())
+}
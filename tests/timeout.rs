@@ -0,0 +1,123 @@
+//! A transfer running past `--timeout` is killed and fails with
+//! [`BitcacheError::Timeout`], a remote refusing the connection fails with
+//! [`BitcacheError::Unreachable`], and both exit with status 4.
+
+mod common;
+
+use bitcache::{BitcacheError, Context, Remote};
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::process::Output;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A `git://` URL whose server accepts connections and never answers
+fn silent_remote() -> io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("git://{}/bitstreams.git", listener.local_addr()?);
+    thread::spawn(move || {
+        let mut held: Vec<TcpStream> = Vec::new();
+        for stream in listener.incoming().flatten() {
+            held.push(stream);
+        }
+    });
+    Ok(url)
+}
+
+/// A `git://` URL on a port nothing listens on
+fn refusing_remote() -> io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    drop(listener);
+    Ok(format!("git://{}/bitstreams.git", addr))
+}
+
+/// A context for `work`, failing at the first network error
+fn context(work: &Path) -> Context {
+    Context {
+        work_dir: Some(work.to_path_buf()),
+        cache_dir: None,
+        max_retries: 0,
+        ..Context::default()
+    }
+}
+
+/// Run the bitcache binary in `dir`, failing at the first network error
+fn bitcache(dir: &Path, args: &[&str]) -> io::Result<Output> {
+    common::command_in(dir, args)?
+        .args(["--max-retries", "0"])
+        .output()
+}
+
+#[test]
+fn kills_a_transfer_past_the_timeout() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let remote = Remote::new(silent_remote()?);
+    let ctx = Context {
+        timeout: Some(Duration::from_secs(1)),
+        ..context(tmp.path())
+    };
+    let started = Instant::now();
+    let error = bitcache::list(&remote, &ctx).expect_err("listed a silent remote");
+    assert!(started.elapsed() < Duration::from_secs(30));
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::Timeout { elapsed, .. }) => {
+            assert!(*elapsed >= Duration::from_secs(1))
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    assert!(error.to_string().contains("raise --timeout"), "{}", error);
+    Ok(())
+}
+
+#[test]
+fn a_refused_connection_is_unreachable() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let remote = Remote::new(refusing_remote()?);
+    let error = bitcache::list(&remote, &context(tmp.path())).expect_err("listed a closed port");
+    match BitcacheError::of(&error) {
+        Some(error @ BitcacheError::Unreachable { .. }) => assert_eq!(error.exit_code(), 4),
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(
+        error.to_string().starts_with("Repository unreachable"),
+        "{}",
+        error
+    );
+    Ok(())
+}
+
+#[test]
+fn the_command_line_exits_with_4() -> io::Result<()> {
+    let tmp = tempfile::tempdir()?;
+    let silent = silent_remote()?;
+    let output = bitcache(tmp.path(), &["list", "--repo", &silent, "--timeout", "1"])?;
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--timeout"));
+
+    let refusing = refusing_remote()?;
+    let output = bitcache(tmp.path(), &["list", "--repo", &refusing])?;
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Repository unreachable"));
+
+    // status names them in its table
+    let output = bitcache(
+        tmp.path(),
+        &[
+            "status",
+            "--repo",
+            &silent,
+            "--repo",
+            &refusing,
+            "--timeout",
+            "1",
+        ],
+    )?;
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("timed out"), "{}", stdout);
+    assert!(stdout.contains("unreachable"), "{}", stdout);
+    Ok(())
+}
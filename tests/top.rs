@@ -0,0 +1,65 @@
+//! `top` ranks entries by size or age, and shows who published each one.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{PublishOptions, TopKey, TopOptions};
+use std::fs;
+use std::io;
+
+/// Publish a bitstream of `size` bytes for the source `name`, as `author`
+fn publish(repo: &TestRepo, name: &str, size: usize, author: &str) -> io::Result<()> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let bitstream = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&bitstream, vec![b'1'; size])?;
+    repo.client()?.publish(&PublishOptions {
+        author: Some(author.to_string()),
+        ..PublishOptions::new(source, bitstream, "boards/zedboard")
+    })?;
+    Ok(())
+}
+
+#[test]
+fn ranks_by_size_and_age() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    publish(&repo, "small", 10, "Jane Doe <jane@example.com>")?;
+    publish(&repo, "large", 1000, "John Roe <john@example.com>")?;
+    let client = repo.client()?;
+
+    let by_size = client.top(&TopOptions::new())?;
+    let paths: Vec<_> = by_size
+        .iter()
+        .map(|r| r.entry.binary_path.as_str())
+        .collect();
+    assert_eq!(
+        paths,
+        ["boards/zedboard/large.bit", "boards/zedboard/small.bit"]
+    );
+    assert_eq!(by_size[0].size, Some(1000));
+
+    let by_age = client.top(&TopOptions {
+        by: TopKey::Age,
+        limit: Some(1),
+        ..TopOptions::new()
+    })?;
+    assert_eq!(by_age.len(), 1);
+    assert_eq!(by_age[0].entry.binary_path, "boards/zedboard/large.bit");
+    Ok(())
+}
+
+#[test]
+fn shows_who_published_each_entry() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    publish(&repo, "top", 10, "Jane Doe <jane@example.com>")?;
+    let output = common::bitcache(&repo, &["top"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let table = String::from_utf8_lossy(&output.stdout);
+    assert!(table.contains("PUBLISHER"), "{}", table);
+    assert!(table.contains("Jane Doe <jane@example.com>"), "{}", table);
+    Ok(())
+}
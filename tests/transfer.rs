@@ -0,0 +1,150 @@
+//! `export` reads a repository's metadata and `import` writes it into
+//! another, merging with or replacing the entries it has.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{ImportMode, ImportOptions, MetadataEntry};
+use common::bitcache;
+use std::io;
+
+const FIRST_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const SECOND_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+
+/// An entry for `md5` stored at `path`
+fn entry(md5: &str, path: &str, timestamp: &str) -> MetadataEntry {
+    MetadataEntry::new(md5, path, "top.vhd", timestamp)
+}
+
+/// The hashes and paths of the entries `repo` has
+fn entries(repo: &TestRepo) -> io::Result<Vec<(String, String)>> {
+    let mut entries: Vec<_> = repo
+        .client()?
+        .list()?
+        .into_iter()
+        .map(|entry| (entry.md5, entry.binary_path))
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+/// A source with two bitstreams of a first hash, and a destination with the same hash
+/// stored elsewhere under the first name and a second entry of its own
+fn repos() -> io::Result<(TestRepo, TestRepo)> {
+    let source = TestRepo::new()?;
+    source.seed(
+        entry(FIRST_MD5, "boards/first.bit", "2024-01-01T00:00:00Z"),
+        b"first",
+    )?;
+    source.seed(
+        entry(FIRST_MD5, "boards/first.mcs", "2024-01-01T00:00:00Z"),
+        b"first flash",
+    )?;
+    let destination = TestRepo::new()?;
+    destination.seed(
+        entry(FIRST_MD5, "old/first.bit", "2023-01-01T00:00:00Z"),
+        b"old first",
+    )?;
+    destination.seed(
+        entry(SECOND_MD5, "boards/second.bit", "2024-02-01T00:00:00Z"),
+        b"second",
+    )?;
+    Ok((source, destination))
+}
+
+#[test]
+fn merges_keeping_conflicts_unless_overwriting() -> io::Result<()> {
+    let (source, destination) = repos()?;
+    let metadata = source.client()?.export()?;
+    assert_eq!(metadata.len(), 2);
+
+    // Entries are matched by hash and name, so the second name is new
+    let imported = destination
+        .client()?
+        .import(&ImportOptions::new(metadata.clone()))?;
+    assert_eq!(imported.added.len(), 1);
+    assert_eq!(imported.added[0].name(), "first.mcs");
+    assert_eq!(imported.skipped.len(), 1);
+    assert_eq!(
+        entries(&destination)?,
+        [
+            (FIRST_MD5.to_string(), "boards/first.mcs".to_string()),
+            (FIRST_MD5.to_string(), "old/first.bit".to_string()),
+            (SECOND_MD5.to_string(), "boards/second.bit".to_string()),
+        ]
+    );
+
+    let opts = ImportOptions {
+        overwrite: true,
+        ..ImportOptions::new(metadata)
+    };
+    let imported = destination.client()?.import(&opts)?;
+    assert_eq!(imported.replaced.len(), 1);
+    assert_eq!(imported.unchanged, 1);
+    // The bitstream was not copied along
+    assert_eq!(imported.missing_binaries, 1);
+    assert_eq!(
+        entries(&destination)?,
+        [
+            (FIRST_MD5.to_string(), "boards/first.bit".to_string()),
+            (FIRST_MD5.to_string(), "boards/first.mcs".to_string()),
+            (SECOND_MD5.to_string(), "boards/second.bit".to_string()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn replace_drops_the_other_entries() -> io::Result<()> {
+    let (source, destination) = repos()?;
+    let opts = ImportOptions {
+        mode: ImportMode::Replace,
+        ..ImportOptions::new(source.client()?.export()?)
+    };
+    let imported = destination.client()?.import(&opts)?;
+    assert_eq!(imported.removed.len(), 1);
+    assert_eq!(imported.removed[0].md5, SECOND_MD5);
+    assert_eq!(
+        entries(&destination)?,
+        [
+            (FIRST_MD5.to_string(), "boards/first.bit".to_string()),
+            (FIRST_MD5.to_string(), "boards/first.mcs".to_string()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn export_writes_a_file_import_reads() -> io::Result<()> {
+    let (source, destination) = repos()?;
+    let file = source.path().join("bitstreams.json");
+    let file_arg = file.to_str().unwrap();
+    let output = bitcache(&source, &["export", "-o", file_arg])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // An existing file is only replaced when asked to
+    assert!(!bitcache(&source, &["export", "-o", file_arg])?
+        .status
+        .success());
+    assert!(bitcache(&source, &["export", "-o", file_arg, "--force"])?
+        .status
+        .success());
+
+    let output = bitcache(&destination, &["import", "--input", file_arg, "--replace"])?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        entries(&destination)?,
+        [
+            (FIRST_MD5.to_string(), "boards/first.bit".to_string()),
+            (FIRST_MD5.to_string(), "boards/first.mcs".to_string()),
+        ]
+    );
+    Ok(())
+}
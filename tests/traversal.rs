@@ -0,0 +1,180 @@
+//! An entry whose `binary_path` would leave the clone, as a hand-edited or
+//! hostile metadata file can hold, is refused before anything is read or
+//! written through it, and so is a path through a symbolic link the
+//! repository holds.
+
+use bitcache::testing::TestRepo;
+use bitcache::{BitcacheError, GetOptions, PublishOptions, METADATA_FILE, METADATA_SCHEMA_VERSION};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+const MD5: &str = "d3699e851d7f4fde53ee37c037408af7";
+
+/// Run git in `dir`, failing the test when it fails
+fn git(dir: &Path, args: &[&str]) {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .expect("git runs");
+    assert!(
+        output.status.success(),
+        "git {:?}: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Commit and push whatever `change` writes into a clone of `repo`
+fn push_change(repo: &TestRepo, change: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let clone = repo.path().join("hostile");
+    if clone.exists() {
+        fs::remove_dir_all(&clone)?;
+    }
+    git(
+        repo.path(),
+        &["clone", "--quiet", repo.url(), &clone.to_string_lossy()],
+    );
+    git(&clone, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+    change(&clone)?;
+    git(&clone, &["add", "-A"]);
+    git(
+        &clone,
+        &[
+            "-c",
+            "user.name=bitcache",
+            "-c",
+            "user.email=bitcache@localhost",
+            "commit",
+            "--quiet",
+            "-m",
+            "Hostile change",
+        ],
+    );
+    git(&clone, &["push", "--quiet", "origin", "HEAD:main"]);
+    fs::remove_dir_all(&clone)
+}
+
+/// Commit metadata holding one entry at `binary_path`
+fn hostile(repo: &TestRepo, binary_path: &str) -> io::Result<()> {
+    push_change(repo, |clone| {
+        let metadata = json!({
+            "schema_version": METADATA_SCHEMA_VERSION,
+            "entries": {
+                MD5: [{
+                    "md5": MD5,
+                    "binary_path": binary_path,
+                    "source_file": "top.vhd",
+                    "timestamp": "2024-01-01T00:00:00Z",
+                }],
+            },
+        });
+        fs::write(clone.join(METADATA_FILE), serde_json::to_vec(&metadata)?)
+    })
+}
+
+#[test]
+fn get_refuses_a_path_leaving_the_clone() -> io::Result<()> {
+    for binary_path in [
+        "../../secret.bit",
+        "%2e%2e/%2e%2e/secret.bit",
+        "/etc/passwd",
+    ] {
+        let repo = TestRepo::new()?;
+        hostile(&repo, binary_path)?;
+        let output = repo.path().join("out");
+        let error = repo
+            .client()?
+            .get(&GetOptions {
+                output: Some(output.clone()),
+                ..GetOptions::new(MD5)
+            })
+            .expect_err("followed an unsafe path");
+        match BitcacheError::of(&error) {
+            Some(BitcacheError::UnsafeMetadataPath {
+                binary_path: refused,
+                ..
+            }) => assert_eq!(refused, binary_path),
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(!output.exists(), "{}", binary_path);
+    }
+    Ok(())
+}
+
+#[test]
+fn listing_refuses_it_too() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    hostile(&repo, "boards/../../secret.bit")?;
+    let error = repo.client()?.list().expect_err("listed an unsafe path");
+    assert!(
+        error.to_string().contains("Refusing unsafe binary_path"),
+        "{}",
+        error
+    );
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn publish_refuses_a_path_through_a_committed_symlink() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let victim = repo.path().join("victim");
+    fs::create_dir(&victim)?;
+    push_change(&repo, |clone| {
+        std::os::unix::fs::symlink(&victim, clone.join("evil"))
+    })?;
+    let source = repo.path().join("top.vhd");
+    let bitstream = repo.path().join("q.bit");
+    fs::write(&source, "entity top is end;\n")?;
+    fs::write(&bitstream, b"q bitstream")?;
+
+    for path in ["evil", "evil/deeper"] {
+        let error = repo
+            .client()?
+            .publish(&PublishOptions::new(&source, &bitstream, path))
+            .expect_err("published through a symlink");
+        match BitcacheError::of(&error) {
+            Some(BitcacheError::InvalidPath { reason, .. }) => {
+                assert_eq!(reason, "'evil' is a symbolic link in the repository")
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(fs::read_dir(&victim)?.count(), 0, "{}", path);
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn get_refuses_a_committed_symlink_to_a_file_outside() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let secret = repo.path().join("secret");
+    fs::write(&secret, "not for the caller")?;
+    hostile(&repo, "b/o.bit")?;
+    push_change(&repo, |clone| {
+        fs::create_dir(clone.join("b"))?;
+        std::os::unix::fs::symlink(&secret, clone.join("b/o.bit"))
+    })?;
+
+    let output = repo.path().join("out");
+    let error = repo
+        .client()?
+        .get(&GetOptions {
+            output: Some(output.clone()),
+            ..GetOptions::new(MD5)
+        })
+        .expect_err("followed a symlink");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::UnsafeMetadataPath { reason, .. }) => {
+            assert_eq!(reason, "'b/o.bit' is a symbolic link in the repository")
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(!output.exists());
+    Ok(())
+}
@@ -0,0 +1,203 @@
+//! `update` replaces the bitstream of an existing entry in place, keeping
+//! its hash and path, and refuses entries that don't exist or whose path
+//! another entry shares. `register` points at it for an entry it would
+//! replace. A get from a local artifact cache filled before an update made
+//! elsewhere serves the update.
+
+mod common;
+
+use bitcache::testing::TestRepo;
+use bitcache::{
+    BitcacheError, GetOptions, PublishAction, PublishOptions, RegisterOptions, UpdateOptions,
+};
+use common::bitcache;
+use std::fs;
+use std::io;
+
+const MISSING_MD5: &str = "ffffffffffffffffffffffffffffffff";
+
+/// Options publishing `bitstream` for the source `name`
+fn inputs(repo: &TestRepo, name: &str, bitstream: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join(format!("{}.vhd", name));
+    let binary = repo.path().join(format!("{}.bit", name));
+    fs::write(&source, format!("entity {} is end;\n", name))?;
+    fs::write(&binary, bitstream)?;
+    Ok(PublishOptions::new(source, binary, "boards/zedboard"))
+}
+
+#[test]
+fn replaces_the_bitstream_and_adds_tags() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let opts = PublishOptions {
+        tags: [("board".to_string(), "zedboard".to_string())].into(),
+        ..inputs(&repo, "top", "broken bitstream")?
+    };
+    let published = client.publish(&opts)?;
+
+    let fixed = repo.path().join("fixed.bit");
+    fs::write(&fixed, "fixed bitstream")?;
+    let updated = client.update(&UpdateOptions {
+        tags: [("fix".to_string(), "timing".to_string())].into(),
+        ..UpdateOptions::new(&published.md5, &fixed)
+    })?;
+    assert_eq!(updated.md5, published.md5);
+    assert_eq!(updated.binary_path, published.binary_path);
+    assert_eq!(updated.action, PublishAction::Overwrite);
+    assert!(updated
+        .commit_message
+        .starts_with("Update bitstream for source MD5"));
+
+    let entries = client.list()?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].source_file, "top.vhd");
+    assert_eq!(entries[0].tags["board"], "zedboard");
+    assert_eq!(entries[0].tags["fix"], "timing");
+    assert_eq!(
+        entries[0].binary_md5.as_deref(),
+        Some(bitcache::compute_md5(&fixed)?.as_str())
+    );
+    let output = repo.path().join("out.bit");
+    client
+        .get(&GetOptions {
+            output: Some(output.clone()),
+            ..GetOptions::new(&published.md5)
+        })?
+        .expect("updated entry");
+    assert_eq!(fs::read(output)?, b"fixed bitstream");
+    Ok(())
+}
+
+#[test]
+fn a_cached_bitstream_is_not_served_after_an_update_elsewhere() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let published = repo
+        .client()?
+        .publish(&inputs(&repo, "top", "broken bitstream")?)?;
+    let cached = repo
+        .builder()?
+        .cache_dir(Some(repo.path().join("cache")))
+        .build()?;
+    let output = repo.path().join("out.bit");
+    let opts = GetOptions {
+        output: Some(output.clone()),
+        force: true,
+        ..GetOptions::new(&published.md5)
+    };
+    assert!(!cached.get(&opts)?.expect("published entry").from_cache);
+    assert!(cached.get(&opts)?.expect("cached entry").from_cache);
+    assert_eq!(fs::read(&output)?, b"broken bitstream");
+
+    // Updated by a client whose cache is not the one holding the bitstream
+    let fixed = repo.path().join("fixed.bit");
+    fs::write(&fixed, "fixed bitstream")?;
+    repo.client()?
+        .update(&UpdateOptions::new(&published.md5, &fixed))?;
+
+    let retrieved = cached.get(&opts)?.expect("updated entry");
+    assert!(!retrieved.from_cache);
+    assert_eq!(fs::read(&output)?, b"fixed bitstream");
+    assert_eq!(
+        retrieved.entry.binary_md5.as_deref(),
+        Some(bitcache::compute_md5(&fixed)?.as_str())
+    );
+    // And what is cached now is the update
+    assert!(cached.get(&opts)?.expect("cached update").from_cache);
+    assert_eq!(fs::read(&output)?, b"fixed bitstream");
+    Ok(())
+}
+
+#[test]
+fn refuses_a_missing_entry_or_a_shared_path() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let top = client.publish(&inputs(&repo, "top", "shared bitstream")?)?;
+    let fixed = repo.path().join("fixed.bit");
+    fs::write(&fixed, "fixed bitstream")?;
+
+    let error = client
+        .update(&UpdateOptions::new(MISSING_MD5, &fixed))
+        .expect_err("updated a missing entry");
+    assert!(matches!(
+        BitcacheError::of(&error),
+        Some(BitcacheError::NotFound { .. })
+    ));
+
+    // A second source stored at the same path, with the same bitstream
+    client.publish(&PublishOptions {
+        rename_in_repo: Some("top.bit".into()),
+        ..inputs(&repo, "uart", "shared bitstream")?
+    })?;
+    let error = client
+        .update(&UpdateOptions::new(&top.md5, &fixed))
+        .expect_err("updated a shared path");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::PathTaken { path, .. }) => {
+            assert_eq!(path, "boards/zedboard/top.bit")
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn register_points_at_update_to_replace_a_bitstream() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let published = client.publish(&inputs(&repo, "top", "top bitstream")?)?;
+
+    // Registering what is there leaves it alone
+    let registered = client.register(&RegisterOptions::new(
+        &published.md5,
+        "top.vhd",
+        &published.binary_path,
+    ))?;
+    assert!(registered.unchanged);
+
+    let error = client
+        .register(&RegisterOptions::new(
+            &published.md5,
+            "top.vhd",
+            "boards/arty/top.bit",
+        ))
+        .expect_err("registered over an entry");
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+    assert!(error.to_string().contains("run update"), "{}", error);
+    Ok(())
+}
+
+#[test]
+fn the_command_line_updates_an_entry() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let published = repo
+        .client()?
+        .publish(&inputs(&repo, "top", "broken bitstream")?)?;
+    fs::write(repo.path().join("fixed.bit"), "fixed bitstream")?;
+    let output = bitcache(
+        &repo,
+        &[
+            "update",
+            "--md5",
+            &published.md5,
+            "--bitstream",
+            "fixed.bit",
+            "--tag",
+            "fix=timing",
+        ],
+    )?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let entries = repo.client()?.list()?;
+    assert_eq!(entries[0].tags["fix"], "timing");
+
+    let output = bitcache(
+        &repo,
+        &["update", "--md5", MISSING_MD5, "--bitstream", "fixed.bit"],
+    )?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(MISSING_MD5));
+    Ok(())
+}
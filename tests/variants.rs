@@ -0,0 +1,431 @@
+//! Several bitstreams can be published for one source, told apart by their
+//! variant: the one given to publish, or else the file name they are stored
+//! under. The metadata keeps every source's variants as a list.
+
+mod common;
+
+use bitcache::testing::{self, TestRepo};
+use bitcache::{
+    BitcacheError, DeleteOptions, DeprecateOptions, GetOptions, LockManifest, LockOptions,
+    LockedGetOptions, PublishOptions, RegisterOptions, RestoreOptions,
+};
+use common::bitcache;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::process::Command;
+
+/// Options publishing the bitstream `file`, holding `contents`, for the
+/// one source of the test repository
+fn inputs(repo: &TestRepo, file: &str, contents: &str) -> io::Result<PublishOptions> {
+    let source = repo.path().join("arty.vhd");
+    let bitstream = repo.path().join(file);
+    fs::write(&source, "entity arty is end;\n")?;
+    fs::write(&bitstream, contents)?;
+    Ok(PublishOptions::new(source, bitstream, "boards/arty"))
+}
+
+/// Options publishing `contents` for the source as `variant`
+fn board(repo: &TestRepo, variant: &str, contents: &str) -> io::Result<PublishOptions> {
+    Ok(PublishOptions {
+        variant: Some(variant.to_string()),
+        rename_in_repo: Some(format!("arty_{}.bit", variant).into()),
+        ..inputs(repo, "arty.bit", contents)?
+    })
+}
+
+/// The metadata file at the head of `repo`
+fn metadata_file(repo: &TestRepo) -> io::Result<Value> {
+    let output = Command::new("git")
+        .args([
+            "--git-dir",
+            repo.url(),
+            "show",
+            "main:bitcache_metadata.json",
+        ])
+        .output()?;
+    assert!(output.status.success());
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Get `name` of `md5` into `dir`, returning what was saved
+fn get(repo: &TestRepo, md5: &str, name: Option<&str>, dir: &str) -> io::Result<String> {
+    let dir = repo.path().join(dir);
+    fs::create_dir_all(&dir)?;
+    let opts = GetOptions {
+        variant: name.map(String::from),
+        output: Some(dir),
+        ..GetOptions::new(md5)
+    };
+    let retrieved = repo.client()?.get(&opts)?.expect("published entry");
+    fs::read_to_string(retrieved.path)
+}
+
+#[test]
+fn keeps_each_name_and_gets_it_back() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let bit = client.publish(&inputs(&repo, "arty.bit", "bitstream")?)?;
+    assert_eq!(bit.name, "arty.bit");
+    let flash = client.publish(&PublishOptions {
+        variant: Some("flash".to_string()),
+        ..inputs(&repo, "arty.mcs", "flash image")?
+    })?;
+    assert_eq!(flash.md5, bit.md5);
+    assert_eq!(flash.name, "flash");
+
+    let mut entries = client.list()?;
+    entries.sort_by(|a, b| a.binary_path.cmp(&b.binary_path));
+    let names: Vec<_> = entries.iter().map(|entry| entry.name()).collect();
+    assert_eq!(names, ["arty.bit", "flash"]);
+    assert_eq!(entries[0].variant, None);
+
+    assert_eq!(get(&repo, &bit.md5, Some("arty.bit"), "bit")?, "bitstream");
+    assert_eq!(get(&repo, &bit.md5, Some("flash"), "flash")?, "flash image");
+    let missing = GetOptions {
+        variant: Some("jtag".to_string()),
+        ..GetOptions::new(&bit.md5)
+    };
+    assert!(client.get(&missing)?.is_none());
+    Ok(())
+}
+
+#[test]
+fn refuses_to_guess_among_several() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let bit = client.publish(&inputs(&repo, "arty.bit", "bitstream")?)?;
+    // The only bitstream of a source needs no name
+    assert_eq!(get(&repo, &bit.md5, None, "only")?, "bitstream");
+
+    client.publish(&inputs(&repo, "arty.mcs", "flash image")?)?;
+    let opts = GetOptions {
+        use_local_cache: false,
+        ..GetOptions::new(&bit.md5)
+    };
+    let error = client.get(&opts).expect_err("picked one of two");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::AmbiguousVariant { variants, .. }) => {
+            assert_eq!(variants, &["arty.bit", "arty.mcs"])
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+    assert!(
+        error.to_string().contains("arty.bit, arty.mcs"),
+        "{}",
+        error
+    );
+    Ok(())
+}
+
+#[test]
+fn a_warm_cache_refuses_to_guess_as_well() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let bit = repo
+        .client()?
+        .publish(&inputs(&repo, "arty.bit", "bitstream")?)?;
+    let cached = repo
+        .builder()?
+        .cache_dir(Some(repo.path().join("cache")))
+        .build()?;
+    let opts = GetOptions {
+        output: Some(repo.path().join("out.bit")),
+        force: true,
+        ..GetOptions::new(&bit.md5)
+    };
+    assert!(!cached.get(&opts)?.expect("published").from_cache);
+    assert!(cached.get(&opts)?.expect("cached").from_cache);
+
+    // A second bitstream published elsewhere makes the name necessary,
+    // whether the first one is cached or not
+    repo.client()?
+        .publish(&inputs(&repo, "arty.mcs", "flash image")?)?;
+    let error = cached.get(&opts).expect_err("served the cached one");
+    assert!(
+        matches!(
+            BitcacheError::of(&error),
+            Some(BitcacheError::AmbiguousVariant { .. })
+        ),
+        "{}",
+        error
+    );
+    let named = GetOptions {
+        variant: Some("arty.bit".to_string()),
+        ..opts
+    };
+    assert_eq!(
+        cached.get(&named)?.expect("named").entry.binary_path,
+        bit.binary_path
+    );
+    Ok(())
+}
+
+#[test]
+fn publishing_a_name_again_replaces_only_it() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let flash = || -> io::Result<PublishOptions> {
+        Ok(PublishOptions {
+            variant: Some("flash".to_string()),
+            ..inputs(&repo, "arty.mcs", "flash image")?
+        })
+    };
+    client.publish(&inputs(&repo, "arty.bit", "bitstream")?)?;
+    client.publish(&flash()?)?;
+    let plan = bitcache::explain(&repo.remote(), &flash()?, client.context())?;
+    assert_eq!(plan.name, "flash");
+    assert!(plan.entry_exists);
+
+    let rebuilt = PublishOptions {
+        rename_in_repo: Some("arty_v2.mcs".into()),
+        ..flash()?
+    };
+    // Only with --force
+    assert!(client.publish(&rebuilt).is_err());
+    let published = client.publish(&PublishOptions {
+        force: true,
+        ..rebuilt
+    })?;
+    assert_eq!(client.list()?.len(), 2);
+    assert_eq!(
+        get(&repo, &published.md5, Some("flash"), "flash")?,
+        "flash image"
+    );
+    let entry = client
+        .list()?
+        .into_iter()
+        .find(|entry| entry.name() == "flash")
+        .expect("published entry");
+    assert_eq!(entry.binary_path, "boards/arty/arty_v2.mcs");
+    Ok(())
+}
+
+#[test]
+fn refuses_a_name_that_is_not_one() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let opts = PublishOptions {
+        variant: Some("../flash".to_string()),
+        ..inputs(&repo, "arty.mcs", "flash image")?
+    };
+    let error = repo.client()?.publish(&opts).expect_err("published");
+    match BitcacheError::of(&error) {
+        Some(BitcacheError::InvalidArgument { flag, .. }) => assert_eq!(*flag, "--variant"),
+        other => panic!("unexpected error {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn delete_deprecate_and_restore_pick_a_variant() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let vc709 = client.publish(&board(&repo, "vc709", "vc709 bitstream")?)?;
+    client.publish(&board(&repo, "kcu105", "kcu105 bitstream")?)?;
+    let md5 = vc709.md5;
+
+    let error = client
+        .delete(&DeleteOptions::new(&md5))
+        .expect_err("deleted one of two");
+    assert!(matches!(
+        BitcacheError::of(&error),
+        Some(BitcacheError::AmbiguousVariant { .. })
+    ));
+
+    let deprecated = client
+        .deprecate(&DeprecateOptions {
+            variant: Some("vc709".to_string()),
+            ..DeprecateOptions::new(&md5, "timing violation")
+        })?
+        .expect("deprecated");
+    assert_eq!(deprecated.name(), "vc709");
+    let deleted = client
+        .delete(&DeleteOptions {
+            variant: Some("kcu105".to_string()),
+            ..DeleteOptions::new(&md5)
+        })?
+        .expect("deleted");
+    assert_eq!(deleted.entry.name(), "kcu105");
+    let left = client.list()?;
+    assert_eq!(left.len(), 1);
+    assert_eq!(left[0].name(), "vc709");
+    assert!(left[0].deprecated);
+
+    // Deleting the other one too leaves two in the trash to pick from
+    client.delete(&DeleteOptions::new(&md5))?.expect("deleted");
+    let error = client
+        .restore(&RestoreOptions::new(&md5))
+        .expect_err("restored one of two");
+    assert!(matches!(
+        BitcacheError::of(&error),
+        Some(BitcacheError::AmbiguousVariant { .. })
+    ));
+    let restored = client
+        .restore(&RestoreOptions {
+            variant: Some("kcu105".to_string()),
+            ..RestoreOptions::new(&md5)
+        })?
+        .expect("restored");
+    assert_eq!(restored.name(), "kcu105");
+    assert_eq!(get(&repo, &md5, None, "restored")?, "kcu105 bitstream");
+    Ok(())
+}
+
+#[test]
+fn writes_every_source_as_a_list() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    // Written by a version before 3, which stored a single entry as itself
+    repo.seed(
+        bitcache::MetadataEntry::new(
+            "0cc175b9c0f1b6a831c399e269772661",
+            "old/old.bit",
+            "old.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"old bitstream",
+    )?;
+    let clone = repo.path().join("edit");
+    let git = |args: &[&str]| -> io::Result<()> {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(&clone)
+            .envs(testing::identity_env())
+            .status()?;
+        assert!(status.success(), "git {:?}", args);
+        Ok(())
+    };
+    fs::create_dir_all(&clone)?;
+    git(&["clone", "--quiet", repo.url(), "."])?;
+    let mut old = metadata_file(&repo)?;
+    old["schema_version"] = 2.into();
+    let entries = old["entries"].as_object_mut().expect("entries");
+    for variants in entries.values_mut() {
+        *variants = variants[0].take();
+    }
+    fs::write(clone.join("bitcache_metadata.json"), old.to_string())?;
+    git(&["commit", "--quiet", "-am", "Write version 2"])?;
+    git(&["push", "--quiet", "origin", "HEAD:main"])?;
+
+    let client = repo.client()?;
+    assert_eq!(client.list()?[0].source_file, "old.vhd");
+    let published = client.publish(&inputs(&repo, "arty.bit", "bitstream")?)?;
+    let metadata = metadata_file(&repo)?;
+    assert_eq!(metadata["schema_version"], 4);
+    for md5 in ["0cc175b9c0f1b6a831c399e269772661", published.md5.as_str()] {
+        let variants = metadata["entries"][md5].as_array().expect("a list");
+        assert_eq!(variants.len(), 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn the_command_line_takes_variant_and_name() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    fs::write(repo.path().join("top.vhd"), "entity top is end;\n")?;
+    for board in ["vc709", "kcu105"] {
+        fs::write(repo.path().join("top.bit"), format!("{} bitstream", board))?;
+        let output = bitcache(
+            &repo,
+            &[
+                "publish",
+                "--source",
+                "top.vhd",
+                "--bitstream",
+                "top.bit",
+                "--path",
+                &format!("boards/{}", board),
+                "--variant",
+                board,
+            ],
+        )?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let output = bitcache(&repo, &["list"])?;
+    assert!(String::from_utf8_lossy(&output.stdout).contains("VARIANT"));
+    let output = bitcache(&repo, &["get-by-source", "--source", "top.vhd"])?;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("kcu105, vc709"));
+
+    for (flag, board) in [("--variant", "kcu105"), ("--name", "vc709")] {
+        let saved = format!("{}.bit", board);
+        let output = bitcache(
+            &repo,
+            &[
+                "get-by-source",
+                "--source",
+                "top.vhd",
+                flag,
+                board,
+                "--output",
+                &saved,
+            ],
+        )?;
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            fs::read_to_string(repo.path().join(saved))?,
+            format!("{} bitstream", board)
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn a_lock_pins_one_variant() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let client = repo.client()?;
+    let vc709 = client.publish(&board(&repo, "vc709", "vc709 bitstream")?)?;
+    client.publish(&board(&repo, "kcu105", "kcu105 bitstream")?)?;
+
+    let manifest = repo.path().join("bitcache.toml");
+    fs::write(
+        &manifest,
+        format!(
+            "[[artifact]]\nname = \"board\"\nmd5 = \"{}\"\nvariant = \"kcu105\"\n",
+            vc709.md5
+        ),
+    )?;
+    let manifest = LockManifest::load(&manifest)?;
+    let lock = client.lock(&LockOptions::new(manifest))?;
+    assert_eq!(lock.artifacts[0].variant.as_deref(), Some("kcu105"));
+    assert_eq!(lock.artifacts[0].path, "boards/arty/arty_kcu105.bit");
+
+    let output = repo.path().join("locked");
+    client.get_locked(&lock, &LockedGetOptions::new(&output))?;
+    assert_eq!(
+        fs::read_to_string(output.join("board/arty_kcu105.bit"))?,
+        "kcu105 bitstream"
+    );
+    Ok(())
+}
+
+#[test]
+fn register_records_a_variant() -> io::Result<()> {
+    let repo = TestRepo::new()?;
+    let md5 = "0cc175b9c0f1b6a831c399e269772661";
+    repo.seed(
+        bitcache::MetadataEntry::new(
+            md5,
+            "boards/vc709/top.bit",
+            "top.vhd",
+            "2024-01-01T00:00:00Z",
+        ),
+        b"vc709 bitstream",
+    )?;
+    let client = repo.client()?;
+    let registered = client.register(&RegisterOptions {
+        variant: Some("vc709".to_string()),
+        ..RegisterOptions::new(md5, "top.vhd", "boards/vc709/top.bit")
+    })?;
+    assert_eq!(registered.entry.variant.as_deref(), Some("vc709"));
+    // A second variant of the hash, next to the seeded one
+    assert_eq!(client.find(md5)?.len(), 2);
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! verify checks each bitstream against the binary_md5 recorded for it.
+
+use bitcache::testing::TestRepo;
+use bitcache::{MetadataEntry, Problem, VerifyOptions};
+use std::io;
+
+const GOOD_MD5: &str = "0cc175b9c0f1b6a831c399e269772661";
+const BAD_MD5: &str = "92eb5ffee6ae2fec3ad71c777531578f";
+const OLD_MD5: &str = "4a8a08f09d37b73795649038408b5f33";
+
+fn entry(md5: &str, binary_path: &str, contents: &[u8]) -> MetadataEntry {
+    let mut entry = MetadataEntry::new(md5, binary_path, "top.vhd", "2024-01-01T00:00:00Z");
+    entry.binary_md5 = Some(format!("{:x}", md5::compute(contents)));
+    entry
+}
+
+/// A repository with an intact entry, one whose bitstream changed after it
+/// was published and one from before binary_md5 was recorded
+fn seeded() -> io::Result<TestRepo> {
+    let repo = TestRepo::new()?;
+    repo.seed(entry(GOOD_MD5, "good/top.bit", b"good"), b"good")?;
+    repo.seed(entry(BAD_MD5, "bad/top.bit", b"as published"), b"edited")?;
+    let mut old = entry(OLD_MD5, "old/top.bit", b"old");
+    old.binary_md5 = None;
+    repo.seed(old, b"old")?;
+    Ok(repo)
+}
+
+#[test]
+fn reports_changed_bitstreams() -> io::Result<()> {
+    let repo = seeded()?;
+    let report = repo.client()?.verify(&VerifyOptions::new())?;
+    assert_eq!(report.checked, 3);
+    assert_eq!(report.unverifiable, 1);
+    assert!(!report.fixed);
+    assert_eq!(report.failures.len(), 1);
+    let failure = &report.failures[0];
+    assert_eq!(failure.entry.md5, BAD_MD5);
+    assert_eq!(
+        failure.problem,
+        Problem::Mismatch {
+            expected: format!("{:x}", md5::compute(b"as published")),
+            actual: format!("{:x}", md5::compute(b"edited")),
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn fix_removes_failed_entries() -> io::Result<()> {
+    let repo = seeded()?;
+    let client = repo.client()?;
+    let report = client.verify(&VerifyOptions { fix: true })?;
+    assert!(report.fixed);
+
+    let mut left: Vec<_> = client.list()?.into_iter().map(|e| e.md5).collect();
+    left.sort();
+    assert_eq!(left, [GOOD_MD5, OLD_MD5]);
+    assert!(client.verify(&VerifyOptions::new())?.failures.is_empty());
+    Ok(())
+}
+
+#[test]
+fn reports_replacements_without_an_entry() -> io::Result<()> {
+    let repo = seeded()?;
+    let mut deprecated = entry(OLD_MD5, "old/top.bit", b"old");
+    deprecated.deprecated = true;
+    deprecated.replacement = Some("ffffffffffffffffffffffffffffffff".to_string());
+    repo.seed(deprecated, b"old")?;
+
+    let report = repo.client()?.verify(&VerifyOptions::new())?;
+    let dangling: Vec<_> = report.dangling.iter().map(|e| e.md5.as_str()).collect();
+    assert_eq!(dangling, [OLD_MD5]);
+    Ok(())
+}
@@ -0,0 +1,40 @@
+//! `bug-report` prints the build and environment for an issue, and like any
+//! command ends quietly when whoever reads its output has gone.
+
+use std::io;
+use std::process::{Command, Stdio};
+
+fn bug_report() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_bitcache"));
+    command.arg("bug-report");
+    command
+}
+
+#[test]
+fn bug_report_prints_the_build_and_environment() -> io::Result<()> {
+    let output = bug_report().output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("bitcache {}", env!("CARGO_PKG_VERSION")))
+            && stdout.contains("git: "),
+        "{}",
+        stdout
+    );
+    Ok(())
+}
+
+#[test]
+fn a_closed_stdout_is_no_crash() -> io::Result<()> {
+    let mut child = bug_report()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // As `bitcache bug-report | head -0` would
+    drop(child.stdout.take());
+    let output = child.wait_with_output()?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{:?}: {}", output.status, stderr);
+    assert!(stderr.is_empty(), "{}", stderr);
+    Ok(())
+}